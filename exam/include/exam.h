@@ -0,0 +1,93 @@
+#ifndef EXAM_H
+#define EXAM_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Success
+#define EXAM_OK 0
+
+// A required pointer argument was null
+#define EXAM_ERR_NULL -1
+
+// The output buffer is smaller than `exam_grid_rgb_len`
+#define EXAM_ERR_BUFFER_TOO_SMALL -2
+
+// The colormap name is not valid UTF-8 or not one of the known colormaps
+#define EXAM_ERR_COLORMAP -3
+
+// The renderer failed unexpectedly, e.g. on a grid it can't handle
+#define EXAM_ERR_INTERNAL -4
+
+// The RGB image of the grid has more bytes than a `size_t` holds
+#define EXAM_ERR_TOO_LARGE -5
+
+// Opaque handle to a parsed elevation grid
+typedef struct ExamGrid ExamGrid;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Parses the contents of an .asc file held in `data[0..len]`.
+// Returns null if the buffer cannot be parsed or the parser fails. Release the grid with `exam_grid_free`.
+//
+// # Safety
+// `data` must point to `len` readable bytes.
+struct ExamGrid *exam_grid_from_buffer(const uint8_t *data,
+                                       size_t len);
+
+// Releases a grid returned by `exam_grid_from_buffer`, null is ignored
+//
+// # Safety
+// `grid` must be null or a pointer from `exam_grid_from_buffer` that was not freed yet.
+void exam_grid_free(struct ExamGrid *grid);
+
+// Number of columns, 0 for a null grid
+//
+// # Safety
+// `grid` must be null or a valid grid.
+size_t exam_grid_ncols(const struct ExamGrid *grid);
+
+// Number of rows, 0 for a null grid
+//
+// # Safety
+// `grid` must be null or a valid grid.
+size_t exam_grid_nrows(const struct ExamGrid *grid);
+
+// Cell size from the header, 0 for a null grid
+//
+// # Safety
+// `grid` must be null or a valid grid.
+double exam_grid_cellsize(const struct ExamGrid *grid);
+
+// Bytes needed for an RGB image of the grid (`ncols * nrows * 3`), 0 for a null grid or one too large to address
+//
+// # Safety
+// `grid` must be null or a valid grid.
+size_t exam_grid_rgb_len(const struct ExamGrid *grid);
+
+// Renders the colored hillshade into `out` as row-major RGB, north row first.
+// `colormap` may be null for viridis.
+//
+// # Safety
+// `grid` must be a valid grid, `out` must point to `out_len` writable bytes and
+// `colormap` must be null or a NUL-terminated string.
+int32_t exam_render_hillshade(const struct ExamGrid *grid,
+                              double azimuth,
+                              double altitude,
+                              double z_factor,
+                              const char *colormap,
+                              uint8_t *out,
+                              size_t out_len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* EXAM_H */
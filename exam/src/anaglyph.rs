@@ -0,0 +1,88 @@
+#[cfg(feature = "fs")]
+use std::io;
+#[cfg(feature = "fs")]
+use image::DynamicImage;
+use colorgrad::Gradient;
+use image::{GrayImage, Luma, Rgb, RgbImage};
+
+use crate::grid::Grid;
+#[cfg(feature = "fs")]
+use crate::output::{save_image, OutputFormat, OutputTarget};
+#[cfg(feature = "fs")]
+use crate::render::color_range;
+use crate::render::{render_shaded, ShadeParams};
+
+/// Default maximum parallax: 2% of the image width, but at least 2 pixels
+pub fn default_parallax(ncols: usize) -> f32 {
+    (ncols as f32 / 50.0).max(2.0)
+}
+
+/// Shifts every pixel horizontally by `direction * parallax * normalized elevation`.
+/// Higher cells are drawn last so they occlude lower ones, holes are filled from the left neighbor.
+fn shifted_view(shade: &GrayImage, grid: &Grid, range: (f32, f32), parallax: f32, direction: f32) -> GrayImage {
+    let (w, h) = shade.dimensions();
+    let (min, max) = range;
+    let mut view = GrayImage::new(w, h);
+    let mut depth = vec![f32::MIN; (w * h) as usize];
+    let mut filled = vec![false; (w * h) as usize];
+
+    for (y, row) in grid.data.iter().enumerate() {
+        for (x, &val) in row.iter().enumerate() {
+            let norm = if !grid.is_valid(x, y) { 0.0 } else { ((val - min) / (max - min)).clamp(0.0, 1.0) };
+            let tx = (x as f32 + direction * parallax * norm).round();
+            if tx < 0.0 || tx >= w as f32 { continue; }
+            let i = y * w as usize + tx as usize;
+            if norm >= depth[i] {
+                depth[i] = norm;
+                filled[i] = true;
+                view.put_pixel(tx as u32, y as u32, *shade.get_pixel(x as u32, y as u32));
+            }
+        }
+    }
+
+    for y in 0..h {
+        let mut last = Luma([0]);
+        for x in 0..w {
+            let i = (y * w + x) as usize;
+            if filled[i] {
+                last = *view.get_pixel(x, y);
+            } else {
+                view.put_pixel(x, y, last);
+            }
+        }
+    }
+    view
+}
+
+/// Renders a red/cyan anaglyph of the hillshade lit by `target.shade` and saves it as product "anaglyph"
+#[cfg(feature = "fs")]
+pub fn save_anaglyph_image(grid: &Grid, parallax: f32, grad: &Gradient, format: &OutputFormat, target: &OutputTarget) -> io::Result<()> {
+    let range = color_range(grid, target.elevation_range.as_ref());
+    let img = render_anaglyph(grid, range, grad, &target.shade, parallax);
+    save_image(DynamicImage::ImageRgb8(img), target, "anaglyph", format)
+}
+
+/// Renders a red/cyan anaglyph of the colored hillshade, whose luminance is all that is kept.
+/// `parallax` is the horizontal offset in pixels between both eyes at the top of `range`.
+pub fn render_anaglyph(grid: &Grid, range: (f32, f32), grad: &Gradient, params: &ShadeParams, parallax: f32) -> RgbImage {
+    let (ncols, nrows) = (grid.ncols, grid.nrows);
+    let color = render_shaded(grid, range, grad, params);
+
+    // Anaglyphs only carry luminance well, the colors would cause retinal rivalry between both eyes
+    let mut shade = GrayImage::new(ncols as u32, nrows as u32);
+    for (x, y, p) in color.enumerate_pixels() {
+        let lum = 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32;
+        shade.put_pixel(x, y, Luma([lum.round().clamp(0.0, 255.0) as u8]));
+    }
+
+    let left = shifted_view(&shade, grid, range, parallax / 2.0, 1.0);
+    let right = shifted_view(&shade, grid, range, parallax / 2.0, -1.0);
+
+    let mut img = RgbImage::new(ncols as u32, nrows as u32);
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        let l = left.get_pixel(x, y)[0];
+        let r = right.get_pixel(x, y)[0];
+        *pixel = Rgb([l, r, r]);
+    }
+    img
+}
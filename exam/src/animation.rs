@@ -0,0 +1,89 @@
+use std::fs::File;
+use std::io;
+use image::codecs::gif::{GifEncoder, Repeat};
+use colorgrad::Gradient;
+use image::{Delay, DynamicImage, Frame};
+
+use crate::grid::Grid;
+use crate::output::OutputTarget;
+use crate::render::{self, Backend, ShadeParams};
+
+/// Which sun angle is swept from frame to frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SweepAxis {
+    Azimuth,
+    Altitude,
+}
+
+impl SweepAxis {
+    pub fn parse(name: &str) -> Option<SweepAxis> {
+        match name.to_lowercase().as_str() {
+            "azimuth" => Some(SweepAxis::Azimuth),
+            "altitude" => Some(SweepAxis::Altitude),
+            _ => None,
+        }
+    }
+}
+
+/// Settings for the hillshade sweep animation
+#[derive(Clone, Copy, Debug)]
+pub struct SweepSettings {
+    pub axis: SweepAxis,
+    pub step: f64,       // Degrees between two frames
+    pub frame_ms: u32,   // Display time of a single frame
+}
+
+/// Returns the (azimuth, altitude) pair of every frame in the sweep, the angle not swept stays that of `shade`
+fn sweep_angles(settings: &SweepSettings, shade: &ShadeParams) -> Vec<(f64, f64)> {
+    let step = settings.step.abs().max(1.0);
+    let mut angles = Vec::new();
+    match settings.axis {
+        SweepAxis::Azimuth => {
+            let mut az = 0.0;
+            while az < 360.0 {
+                angles.push((az, shade.altitude));
+                az += step;
+            }
+        }
+        SweepAxis::Altitude => {
+            // Rise from a grazing sun up to almost zenith and back down again so the GIF loops smoothly
+            let mut alt = 5.0;
+            while alt <= 85.0 {
+                angles.push((shade.azimuth, alt));
+                alt += step;
+            }
+            let rising = angles.len();
+            for i in (1..rising.saturating_sub(1)).rev() {
+                angles.push(angles[i]);
+            }
+        }
+    }
+    angles
+}
+
+/// Renders one hillshade per sun position, otherwise shaded like `target.shade`, and saves them as a looping GIF,
+/// product "hillshade_<axis>"
+pub fn save_hillshade_sweep_gif(grid: &Grid, settings: &SweepSettings, grad: &Gradient, backend: &Backend, target: &OutputTarget) -> io::Result<()> {
+    let name = match settings.axis {
+        SweepAxis::Azimuth => "hillshade_azimuth",
+        SweepAxis::Altitude => "hillshade_altitude",
+    };
+    let file = File::create(target.path(name, "gif")?)?;
+
+    let mut encoder = GifEncoder::new_with_speed(file, 10);
+    encoder.set_repeat(Repeat::Infinite).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+    let delay = Delay::from_numer_denom_ms(settings.frame_ms, 1);
+    let range = render::color_range(grid, target.elevation_range.as_ref());
+    let frames = sweep_angles(settings, &target.shade).into_iter().map(|(azimuth, altitude)| {
+        let params = ShadeParams { azimuth, altitude, ..target.shade };
+        let mut img = backend.render_shaded(grid, range, grad, &params);
+        if let Some(pinned) = &target.elevation_range {
+            pinned.paint_outside(&mut img, grid);
+        }
+        Frame::from_parts(DynamicImage::ImageRgb8(img).into_rgba8(), 0, 0, delay)
+    });
+
+    encoder.encode_frames(frames).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    Ok(())
+}
@@ -0,0 +1,349 @@
+#[cfg(feature = "fs")]
+use std::fs::{self, File};
+use std::io::{self, BufRead, Write};
+#[cfg(feature = "fs")]
+use std::path::Path;
+#[cfg(feature = "fs")]
+use std::thread;
+
+use crate::grid::{Grid, GridInfo};
+
+/// Data sections from this size on are split across threads by [`load_asc`]
+#[cfg(feature = "fs")]
+pub const PARALLEL_BYTES: usize = 4 << 20;
+
+/// Parses a .asc file into 2D elevation data + metadata
+#[cfg(feature = "fs")]
+pub fn load_asc(path: &Path) -> io::Result<Grid> {
+    load_asc_with_warnings(path).map(|(grid, _)| grid)
+}
+
+/// Like [`load_asc`], also reporting what the parser had to patch over
+#[cfg(feature = "fs")]
+pub fn load_asc_with_warnings(path: &Path) -> io::Result<(Grid, AscWarnings)> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    parse_asc_parallel(&fs::read(path)?, threads)
+}
+
+/// Like [`parse_asc_with_warnings`] on text in memory, with a data section of at least [`PARALLEL_BYTES`] cut into
+/// `threads` chunks at line breaks that are parsed at the same time and joined in order
+#[cfg(feature = "fs")]
+pub fn parse_asc_parallel(bytes: &[u8], threads: usize) -> io::Result<(Grid, AscWarnings)> {
+    let mut header = AscHeader::default();
+    let mut start = 0;
+    while start < bytes.len() {
+        let end = bytes[start..].iter().position(|&b| b == b'\n').map_or(bytes.len(), |i| start + i + 1);
+        if !header.parse_line(text(&bytes[start..end])?) {
+            break;
+        }
+        start = end;
+    }
+    let body = &bytes[start..];
+    let threads = if body.len() < PARALLEL_BYTES { 1 } else { threads.max(1) };
+
+    // Chunk boundaries moved forward to the start of the next line
+    let mut bounds = vec![0];
+    for k in 1..threads {
+        let at = (body.len() * k / threads).max(*bounds.last().unwrap());
+        bounds.push(body[at..].iter().position(|&b| b == b'\n').map_or(body.len(), |i| at + i + 1));
+    }
+    bounds.push(body.len());
+    let parse_chunk = |chunk: &[u8]| -> io::Result<(Vec<Vec<f32>>, AscWarnings)> {
+        let (mut data, mut warnings) = (Vec::new(), AscWarnings::default());
+        for line in text(chunk)?.lines() {
+            parse_row(&header, line, &mut data, &mut warnings);
+        }
+        Ok((data, warnings))
+    };
+    let chunks: Vec<&[u8]> = bounds.windows(2).map(|w| &body[w[0]..w[1]]).collect();
+    let parsed: Vec<_> = match chunks[..] {
+        [chunk] => vec![parse_chunk(chunk)],
+        _ => thread::scope(|scope| {
+            let workers: Vec<_> = chunks.iter().map(|&chunk| scope.spawn(move || parse_chunk(chunk))).collect();
+            workers.into_iter().map(|w| w.join().expect("chunk parser panicked")).collect()
+        }),
+    };
+
+    let (mut data, mut warnings) = (Vec::with_capacity(header.nrows), AscWarnings::default());
+    for chunk in parsed {
+        let (rows, chunk_warnings) = chunk?;
+        data.extend(rows);
+        warnings.skipped_rows += chunk_warnings.skipped_rows;
+        warnings.invalid_values += chunk_warnings.invalid_values;
+    }
+    finish(header, data, warnings)
+}
+
+/// A line of the file as text, failing on invalid UTF-8 like [`BufRead::lines`]
+#[cfg(feature = "fs")]
+fn text(bytes: &[u8]) -> io::Result<&str> {
+    std::str::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Problems in the data section that do not prevent reading the grid
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AscWarnings {
+    pub skipped_rows: usize,   // Data lines whose value count differs from ncols, dropped
+    pub invalid_values: usize, // Values that are not numbers, read as NoData
+}
+
+impl AscWarnings {
+    /// Human readable descriptions, empty if the file was clean
+    pub fn messages(&self) -> Vec<String> {
+        let mut messages = Vec::new();
+        if self.skipped_rows > 0 {
+            messages.push(format!("{} rows with a wrong number of values skipped", self.skipped_rows));
+        }
+        if self.invalid_values > 0 {
+            messages.push(format!("{} unparsable values read as NoData", self.invalid_values));
+        }
+        messages
+    }
+}
+
+/// Writes the grid as an .asc file, georeferenced by its lower left corner
+#[cfg(feature = "fs")]
+pub fn save_asc(grid: &Grid, path: &Path) -> io::Result<()> {
+    let mut file = io::BufWriter::new(File::create(path)?);
+    write_asc(grid, &mut file)?;
+    file.flush()
+}
+
+/// Writes ESRI ASCII grid text: the header followed by one line per row, north row first
+pub fn write_asc<W: Write>(grid: &Grid, w: &mut W) -> io::Result<()> {
+    writeln!(w, "ncols {}", grid.ncols)?;
+    writeln!(w, "nrows {}", grid.nrows)?;
+    writeln!(w, "xllcorner {}", grid.xllcorner)?;
+    writeln!(w, "yllcorner {}", grid.yllcorner)?;
+    writeln!(w, "cellsize {}", grid.cellsize)?;
+    writeln!(w, "NODATA_value {}", grid.nodata)?;
+    for row in &grid.data {
+        let line: Vec<String> = row.iter().map(|v| v.to_string()).collect();
+        writeln!(w, "{}", line.join(" "))?;
+    }
+    Ok(())
+}
+
+/// Parses the contents of a .asc file already held in memory (e.g. a file dropped into a web page)
+pub fn parse_asc_bytes(bytes: &[u8]) -> io::Result<Grid> {
+    parse_asc(bytes)
+}
+
+/// Parses ESRI ASCII grid text from any buffered reader
+pub fn parse_asc<R: BufRead>(reader: R) -> io::Result<Grid> {
+    parse_asc_with_warnings(reader).map(|(grid, _)| grid)
+}
+
+/// Parses ESRI ASCII grid text, counting the rows and values that had to be skipped or replaced.
+/// GRASS ASCII rasters (r.out.ascii) with their `north:`/`south:`/`east:`/`west:`/`rows:`/`cols:` header,
+/// `*` or `null:` cells and a `multiplier:` are read as well.
+pub fn parse_asc_with_warnings<R: BufRead>(reader: R) -> io::Result<(Grid, AscWarnings)> {
+    let mut data = Vec::new();
+    let mut header = AscHeader::default();
+    let mut reading_data = false;
+    let mut warnings = AscWarnings::default();
+
+    for line in reader.lines() {
+        let line = line?;
+        if !reading_data && header.parse_line(&line) {
+            continue;
+        }
+        reading_data |= parse_row(&header, &line, &mut data, &mut warnings);
+    }
+    finish(header, data, warnings)
+}
+
+/// Appends the values of a data line to `data`, or counts it as skipped if their number isn't ncols. False for an
+/// empty line.
+fn parse_row(header: &AscHeader, line: &str, data: &mut Vec<Vec<f32>>, warnings: &mut AscWarnings) -> bool {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.is_empty() { return false; }
+    let row: Vec<f32> = parts.iter().map(|&x| header.value(x).unwrap_or_else(|| {
+        warnings.invalid_values += 1;
+        header.nodata
+    })).collect();
+    if row.len() == header.ncols { data.push(row); } else { warnings.skipped_rows += 1; }
+    true
+}
+
+/// The grid of the parsed rows, if there are as many as the header says
+fn finish(header: AscHeader, data: Vec<Vec<f32>>, warnings: AscWarnings) -> io::Result<(Grid, AscWarnings)> {
+    if data.len() != header.nrows {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Row count mismatch"));
+    }
+    let (cellsize, xll, yll) = header.georeference()?;
+    Ok((Grid { data, ncols: header.ncols, nrows: header.nrows, nodata: header.nodata, cellsize, xllcorner: xll, yllcorner: yll, geographic: None }, warnings))
+}
+
+/// Reads only the `ncols` x `nrows` cells from column `col_off` and row `row_off` (counted from the north) of ESRI or
+/// GRASS ASCII text. Rows above the window are skipped without parsing them and reading stops after its last row,
+/// so a corner of a huge file loads quickly. Unparsable values become NoData.
+pub fn read_asc_window<R: BufRead>(mut reader: R, col_off: usize, row_off: usize, ncols: usize, nrows: usize) -> io::Result<Grid> {
+    let mut header = AscHeader::default();
+    let mut line = String::new();
+    // Header lines up to the first row of values, which stays in `line`
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "No values after the header"));
+        }
+        if !header.parse_line(&line) {
+            break;
+        }
+    }
+    if col_off + ncols > header.ncols || row_off + nrows > header.nrows || ncols == 0 || nrows == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+            "Window of {}x{} cells at column {} and row {} is empty or outside the {}x{} grid", ncols, nrows, col_off, row_off, header.ncols, header.nrows)));
+    }
+
+    // The first row is already read, the rest up to the window is skipped
+    let mut skipped = Vec::new();
+    for _ in 1..row_off {
+        skipped.clear();
+        if reader.read_until(b'\n', &mut skipped)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Row count mismatch"));
+        }
+    }
+    let mut data = Vec::with_capacity(nrows);
+    for r in 0..nrows {
+        if r > 0 || row_off > 0 {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Row count mismatch"));
+            }
+        }
+        let row: Vec<f32> = line.split_whitespace().skip(col_off).take(ncols).map(|x| header.value(x).unwrap_or(header.nodata)).collect();
+        if row.len() < ncols {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Row {} holds fewer than {} values", row_off + r, col_off + ncols)));
+        }
+        data.push(row);
+    }
+
+    let (cellsize, xll, yll) = header.georeference()?;
+    Ok(Grid {
+        data, ncols, nrows, nodata: header.nodata, cellsize,
+        xllcorner: xll + col_off as f64 * cellsize,
+        yllcorner: yll + (header.nrows - row_off - nrows) as f64 * cellsize,
+        geographic: None,
+    })
+}
+
+/// Reads the header of ESRI or GRASS ASCII text, stopping at the first row of values
+pub fn read_asc_info<R: BufRead>(reader: R) -> io::Result<GridInfo> {
+    let mut header = AscHeader::default();
+    for line in reader.lines() {
+        if !header.parse_line(&line?) {
+            break;
+        }
+    }
+    if header.ncols == 0 || header.nrows == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "No ncols and nrows in the header"));
+    }
+    let (cellsize, xllcorner, yllcorner) = header.georeference()?;
+    Ok(GridInfo { ncols: header.ncols, nrows: header.nrows, cellsize, xllcorner, yllcorner, nodata: header.nodata })
+}
+
+/// Header of an ESRI or GRASS ASCII grid, filled line by line
+#[derive(Clone, Copy, Debug)]
+struct AscHeader {
+    ncols: usize,
+    nrows: usize,
+    nodata: f32,
+    cellsize: f64,
+    xll: f64,
+    yll: f64,
+    center_registered: bool, // xllcenter/yllcenter give the center of the lower left cell
+    bounds: [Option<f64>; 4], // North, south, east and west edges of a GRASS header
+    multiplier: f32,         // Factor of the GRASS values
+}
+
+impl Default for AscHeader {
+    fn default() -> Self {
+        AscHeader { ncols: 0, nrows: 0, nodata: -99999.0, cellsize: 1.0, xll: 0.0, yll: 0.0, center_registered: false, bounds: [None; 4], multiplier: 1.0 }
+    }
+}
+
+impl AscHeader {
+    /// Takes a header line, false for a line of values (or anything else the header doesn't know)
+    fn parse_line(&mut self, line: &str) -> bool {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.is_empty() { return true; }
+        let value = |default| parts.get(1).and_then(|v| v.parse().ok()).unwrap_or(default);
+
+        match parts[0].to_lowercase().as_str() {
+            "ncols" => self.ncols = parts.get(1).and_then(|v| v.parse().ok()).unwrap_or(0),
+            "nrows" => self.nrows = parts.get(1).and_then(|v| v.parse().ok()).unwrap_or(0),
+            // A NaN NoData can't be compared against, its cells are read as NaN and mapped to the default
+            "nodata_value" => self.nodata = parts.get(1).and_then(|v| v.parse().ok()).filter(|v: &f32| !v.is_nan()).unwrap_or(-99999.0),
+            "cellsize" => self.cellsize = value(1.0),
+            "xllcorner" => self.xll = value(0.0),
+            "yllcorner" => self.yll = value(0.0),
+            "xllcenter" => { self.xll = value(0.0); self.center_registered = true; }
+            "yllcenter" => { self.yll = value(0.0); self.center_registered = true; }
+            key if key.contains(':') => {
+                let (key, value) = line.split_once(':').map(|(k, v)| (k.trim().to_lowercase(), v.trim())).unwrap();
+                match key.as_str() {
+                    "north" => self.bounds[0] = grass_coordinate(value),
+                    "south" => self.bounds[1] = grass_coordinate(value),
+                    "east" => self.bounds[2] = grass_coordinate(value),
+                    "west" => self.bounds[3] = grass_coordinate(value),
+                    "rows" => self.nrows = value.parse().unwrap_or(0),
+                    "cols" => self.ncols = value.parse().unwrap_or(0),
+                    "null" => self.nodata = value.parse().ok().filter(|v: &f32| !v.is_nan()).unwrap_or(self.nodata),
+                    "multiplier" => self.multiplier = value.parse().unwrap_or(1.0),
+                    _ => {} // type: and anything newer
+                }
+            }
+            first => return first.parse::<f32>().is_err() && first != "*",
+        }
+        true
+    }
+
+    /// Elevation of a value token, None if it isn't a number. NaN cells become NoData.
+    fn value(&self, token: &str) -> Option<f32> {
+        match token.parse::<f32>() {
+            Ok(v) if v == self.nodata || v.is_nan() => Some(self.nodata),
+            Ok(v) => Some(v * self.multiplier),
+            Err(_) if token == "*" => Some(self.nodata), // GRASS null cell
+            Err(_) if is_nan_token(token) => Some(self.nodata),
+            Err(_) => None,
+        }
+    }
+
+    /// Cell size and lower left corner of the grid
+    fn georeference(&self) -> io::Result<(f64, f64, f64)> {
+        if let [Some(north), Some(south), Some(east), Some(west)] = self.bounds {
+            let (ew, ns) = ((east - west) / self.ncols.max(1) as f64, (north - south) / self.nrows.max(1) as f64);
+            if (ew - ns).abs() > ew.abs() * 1e-6 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Cells of {} x {} are not square", ew, ns)));
+            }
+            return Ok((ew, west, south));
+        }
+        if self.center_registered {
+            return Ok((self.cellsize, self.xll - self.cellsize / 2.0, self.yll - self.cellsize / 2.0));
+        }
+        Ok((self.cellsize, self.xll, self.yll))
+    }
+}
+
+/// NaN as printed by the Windows C runtime: `nan(ind)`, `-1.#IND`, `1.#QNAN`
+fn is_nan_token(token: &str) -> bool {
+    let token = token.to_ascii_lowercase();
+    token.contains("nan(") || token.contains("#ind") || token.contains("#qnan")
+}
+
+/// Edge of a GRASS header, a number or degrees like `45:30:00N` in latitude-longitude locations
+fn grass_coordinate(value: &str) -> Option<f64> {
+    if let Ok(v) = value.parse() {
+        return Some(v);
+    }
+    let (dms, hemisphere) = value.split_at(value.char_indices().last()?.0);
+    let sign = match hemisphere.to_ascii_uppercase().as_str() {
+        "N" | "E" => 1.0,
+        "S" | "W" => -1.0,
+        _ => return None,
+    };
+    let degrees = dms.split(':').map(|part| part.parse::<f64>().ok()).enumerate()
+        .try_fold(0.0, |total, (i, part)| Some(total + part? / 60f64.powi(i as i32)))?;
+    Some(sign * degrees)
+}
@@ -0,0 +1,180 @@
+//! Entry points for async services (`async` feature): files are read and written with tokio's file IO, parsing,
+//! rendering and encoding run on its blocking pool, and [`render_files`] reports how far it got as a [`Stream`] of
+//! [`Progress`] events, so a web service embeds the renderer without stalling its runtime. All of them need to be
+//! called inside a tokio runtime.
+
+use std::future::Future;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::thread;
+
+use futures_core::Stream;
+use image::{DynamicImage, GrayImage};
+use tokio::sync::{mpsc, Semaphore};
+
+use crate::asc::parse_asc_parallel;
+use crate::e00::parse_e00;
+use crate::geotiff::parse_grid_bytes;
+use crate::grid::Grid;
+use crate::output::{encode_image, OutputFormat};
+use crate::render::{self, colormap, hillshade_values, render_shaded, ShadeParams};
+use crate::sniff::{sniff, InputFormat};
+
+/// Events buffered for a consumer of [`render_files`] that reads more slowly than the files are rendered
+const PROGRESS_BUFFER: usize = 64;
+
+/// Image made of a grid by [`render`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Render {
+    Grayscale,
+    Colored { colormap: String },                            // Name as in --colormap
+    Hillshade(ShadeParams),
+    ColorHillshade { colormap: String, params: ShadeParams }, // The colors shaded by the hillshade
+}
+
+/// A file for [`render_files`] to render
+#[derive(Clone, Debug)]
+pub struct RenderJob {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub render: Render,
+    pub format: OutputFormat,
+}
+
+/// How far [`render_files`] got, in the order the steps of each file happen
+#[derive(Clone, Debug, PartialEq)]
+pub enum Progress {
+    Started { input: PathBuf },
+    Loaded { input: PathBuf, ncols: usize, nrows: usize },
+    Saved { input: PathBuf, output: PathBuf },
+    Failed { input: PathBuf, error: String },
+    Finished { saved: usize, failed: usize }, // Always the last event
+}
+
+/// Runs CPU-bound work such as a derivative or a custom product on the blocking pool, so the runtime keeps serving
+/// other tasks meanwhile. A panic in `work` comes back as an error.
+pub async fn spawn_render<T, F>(work: F) -> io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    tokio::task::spawn_blocking(work).await.map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
+
+/// Reads a grid file with async IO and parses it on the blocking pool: ESRI and GRASS ASCII, GeoTIFF and E00. Point
+/// clouds, netCDF and HDF5 are read by their libraries from the path, see [`spawn_render`].
+pub async fn load_grid(path: impl AsRef<Path>) -> io::Result<Grid> {
+    let path = path.as_ref();
+    let bytes = tokio::fs::read(path).await?;
+    let format = sniff(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}: {}", path, e)))?;
+    spawn_render(move || parse_grid(&bytes, format)).await?
+}
+
+/// Parses grid bytes of a sniffed format
+fn parse_grid(bytes: &[u8], format: InputFormat) -> io::Result<Grid> {
+    match format {
+        InputFormat::EsriAscii | InputFormat::GrassAscii => {
+            parse_asc_parallel(bytes, thread::available_parallelism().map_or(1, |n| n.get())).map(|(grid, _)| grid)
+        }
+        InputFormat::GeoTiff => parse_grid_bytes(bytes),
+        InputFormat::E00 => parse_e00(std::str::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?),
+        other => Err(io::Error::new(io::ErrorKind::Unsupported, format!("{:?} grids are read from their path, not loaded asynchronously", other))),
+    }
+}
+
+/// Renders `grid` on the blocking pool. The grid is shared, so several images of one grid need no copies.
+pub async fn render(grid: Arc<Grid>, kind: Render) -> io::Result<DynamicImage> {
+    spawn_render(move || render_now(&grid, &kind)).await?
+}
+
+fn render_now(grid: &Grid, kind: &Render) -> io::Result<DynamicImage> {
+    let gradient = |name: &str| colormap(name).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown colormap {:?}", name)));
+    Ok(match kind {
+        Render::Grayscale => DynamicImage::ImageLuma8(render::render_grayscale(grid)),
+        Render::Colored { colormap } => DynamicImage::ImageRgb8(render::render_colored(grid, &gradient(colormap)?)),
+        Render::Hillshade(params) => {
+            let values = hillshade_values(grid, params);
+            DynamicImage::ImageLuma8(GrayImage::from_raw(grid.ncols as u32, grid.nrows as u32, values).expect("one value per cell"))
+        }
+        Render::ColorHillshade { colormap, params } => {
+            DynamicImage::ImageRgb8(render_shaded(grid, render::color_range(grid, None), &gradient(colormap)?, params))
+        }
+    })
+}
+
+/// Encodes `img` on the blocking pool and writes it with async IO
+pub async fn save_image(img: DynamicImage, format: OutputFormat, path: impl AsRef<Path>) -> io::Result<()> {
+    let bytes = spawn_render(move || -> io::Result<Vec<u8>> {
+        let mut buffer = Cursor::new(Vec::new());
+        encode_image(img, &format, &mut buffer)?;
+        Ok(buffer.into_inner())
+    }).await??;
+    tokio::fs::write(path, bytes).await
+}
+
+/// Loads, renders and saves one job
+async fn run_job(job: RenderJob, progress: &mpsc::Sender<Progress>) -> io::Result<()> {
+    let grid = load_grid(&job.input).await?;
+    let _ = progress.send(Progress::Loaded { input: job.input.clone(), ncols: grid.ncols, nrows: grid.nrows }).await;
+    let img = render(Arc::new(grid), job.render).await?;
+    save_image(img, job.format, &job.output).await
+}
+
+/// Renders `jobs`, at most `concurrency` of them at once, in a background task. Its progress comes as a stream that
+/// ends after [`Progress::Finished`]; dropping the stream doesn't stop the jobs already running.
+pub fn render_files(jobs: Vec<RenderJob>, concurrency: usize) -> ProgressStream {
+    let (sender, receiver) = mpsc::channel(PROGRESS_BUFFER);
+    tokio::spawn(async move {
+        let slots = Arc::new(Semaphore::new(concurrency.max(1)));
+        let mut tasks = tokio::task::JoinSet::new();
+        for job in jobs {
+            let Ok(slot) = Arc::clone(&slots).acquire_owned().await else { break };
+            let sender = sender.clone();
+            tasks.spawn(async move {
+                let _slot = slot;
+                let input = job.input.clone();
+                let output = job.output.clone();
+                let _ = sender.send(Progress::Started { input: input.clone() }).await;
+                let event = match run_job(job, &sender).await {
+                    Ok(()) => Progress::Saved { input, output },
+                    Err(e) => Progress::Failed { input, error: e.to_string() },
+                };
+                let saved = matches!(event, Progress::Saved { .. });
+                let _ = sender.send(event).await;
+                saved
+            });
+        }
+        let (mut saved, mut failed) = (0, 0);
+        while let Some(result) = tasks.join_next().await {
+            match result {
+                Ok(true) => saved += 1,
+                _ => failed += 1,
+            }
+        }
+        let _ = sender.send(Progress::Finished { saved, failed }).await;
+    });
+    ProgressStream { receiver }
+}
+
+/// Progress events of [`render_files`]
+pub struct ProgressStream {
+    receiver: mpsc::Receiver<Progress>,
+}
+
+impl ProgressStream {
+    /// The next event, None after the last one; the same as polling the stream
+    pub fn recv(&mut self) -> impl Future<Output = Option<Progress>> + '_ {
+        self.receiver.recv()
+    }
+}
+
+impl Stream for ProgressStream {
+    type Item = Progress;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Progress>> {
+        self.receiver.poll_recv(cx)
+    }
+}
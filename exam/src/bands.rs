@@ -0,0 +1,135 @@
+//! Multi-band Float32 GeoTIFF stacking aligned layers of one grid (elevation, hillshade, derivatives,
+//! validity mask) as channels of a single file, the layout machine learning pipelines read.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, Write};
+
+use tiff::encoder::{colortype, compression, TiffEncoder, TiffKind, TiffValue};
+use tiff::tags::{PhotometricInterpretation, SampleFormat, Tag};
+
+use crate::grid::Grid;
+use crate::output::{needs_bigtiff, write_georeference, OutputTarget, TiffCompression};
+use crate::render::{Backend, ShadeParams};
+use crate::terrain::Derivative;
+
+/// Most bands one file can hold
+pub const MAX_BANDS: usize = 8;
+
+/// One channel of the composite
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Band {
+    Elevation,           // The grid values
+    Hillshade,           // Illumination 0-255 of the hillshade with the sun and mode of the run
+    Derived(Derivative), // Raw values of a terrain derivative
+    Mask,                // 1 on valid cells, 0 on NoData
+}
+
+impl Band {
+    /// Written by --bands without a list
+    pub const DEFAULT: [Band; 4] = [Band::Elevation, Band::Hillshade, Band::Derived(Derivative::Slope), Band::Mask];
+
+    pub fn parse(name: &str) -> Option<Band> {
+        match name.to_lowercase().as_str() {
+            "elevation" => Some(Band::Elevation),
+            "hillshade" => Some(Band::Hillshade),
+            "mask" => Some(Band::Mask),
+            other => Derivative::parse(other).map(Band::Derived),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Band::Elevation => "elevation",
+            Band::Hillshade => "hillshade",
+            Band::Derived(derivative) => derivative.name(),
+            Band::Mask => "mask",
+        }
+    }
+
+    /// Values of the band row-major from the north row, NoData cells keep the grid's NoData except in the mask
+    fn values(&self, grid: &Grid, shade: &ShadeParams, backend: &Backend) -> Vec<f32> {
+        let valid = |i: usize| grid.is_valid(i % grid.ncols, i / grid.ncols);
+        match self {
+            Band::Elevation => grid.data.iter().flatten().copied().collect(),
+            Band::Hillshade => backend.hillshade_values(grid, shade).into_iter().enumerate()
+                .map(|(i, v)| if valid(i) { v as f32 } else { grid.nodata })
+                .collect(),
+            Band::Derived(derivative) => {
+                let values = crate::profiling::stage("derive", || derivative.compute_with(grid, shade.edges));
+                values.data.iter().flatten().map(|&v| if values.is_nodata(v) { grid.nodata } else { v }).collect()
+            }
+            Band::Mask => (0..grid.ncols * grid.nrows).map(|i| valid(i) as u8 as f32).collect(),
+        }
+    }
+}
+
+/// Saves the bands as product "bands" (.tif), see [`write_bands`]
+pub fn save_bands(grid: &Grid, bands: &[Band], shade: &ShadeParams, backend: &Backend, target: &OutputTarget) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(target.path("bands", "tif")?)?);
+    write_bands(grid, bands, shade, backend, &mut file)?;
+    file.flush()
+}
+
+/// Writes the bands pixel-interleaved into a deflate-compressed Float32 GeoTIFF georeferenced like
+/// [`crate::output::save_float_tiff`], a BigTIFF if it may pass 4 GiB. Band names go into the GDAL metadata, so GDAL,
+/// QGIS and rasterio show them.
+pub fn write_bands<W: Write + Seek>(grid: &Grid, bands: &[Band], shade: &ShadeParams, backend: &Backend, out: &mut W) -> io::Result<()> {
+    let layers: Vec<Vec<f32>> = bands.iter().map(|band| band.values(grid, shade, backend)).collect();
+    let mut data = Vec::with_capacity(grid.ncols * grid.nrows * bands.len());
+    for i in 0..grid.ncols * grid.nrows {
+        data.extend(layers.iter().map(|layer| layer[i]));
+    }
+    let names: Vec<&str> = bands.iter().map(Band::name).collect();
+    match bands.len() {
+        1 => encode::<colortype::Gray32Float, W>(grid, &names, &data, out),
+        2 => encode::<Float32<2>, W>(grid, &names, &data, out),
+        3 => encode::<Float32<3>, W>(grid, &names, &data, out),
+        4 => encode::<Float32<4>, W>(grid, &names, &data, out),
+        5 => encode::<Float32<5>, W>(grid, &names, &data, out),
+        6 => encode::<Float32<6>, W>(grid, &names, &data, out),
+        7 => encode::<Float32<7>, W>(grid, &names, &data, out),
+        8 => encode::<Float32<8>, W>(grid, &names, &data, out),
+        n => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("A composite holds 1 to {} bands, got {}", MAX_BANDS, n))),
+    }
+}
+
+/// `N` Float32 samples per pixel, the first read as gray and the rest as unspecified extra samples
+struct Float32<const N: usize>;
+
+impl<const N: usize> colortype::ColorType for Float32<N> {
+    type Inner = f32;
+    const TIFF_VALUE: PhotometricInterpretation = PhotometricInterpretation::BlackIsZero;
+    const BITS_PER_SAMPLE: &'static [u16] = &[32; N];
+    const SAMPLE_FORMAT: &'static [SampleFormat] = &[SampleFormat::IEEEFP; N];
+}
+
+fn encode<C: colortype::ColorType<Inner = f32>, W: Write + Seek>(grid: &Grid, names: &[&str], data: &[f32], out: &mut W) -> io::Result<()>
+where
+    [f32]: TiffValue,
+{
+    let to_io = |e: tiff::TiffError| io::Error::new(io::ErrorKind::Other, e);
+    if needs_bigtiff(data.len() as u64 * 4, TiffCompression::Deflate) {
+        encode_with::<C, _, _>(&mut TiffEncoder::new_big(out).map_err(to_io)?, grid, names, data).map_err(to_io)
+    } else {
+        encode_with::<C, _, _>(&mut TiffEncoder::new(out).map_err(to_io)?, grid, names, data).map_err(to_io)
+    }
+}
+
+fn encode_with<C: colortype::ColorType<Inner = f32>, W: Write + Seek, K: TiffKind>(encoder: &mut TiffEncoder<W, K>, grid: &Grid, names: &[&str], data: &[f32]) -> tiff::TiffResult<()>
+where
+    [f32]: TiffValue,
+{
+    let mut image = encoder.new_image_with_compression::<C, _>(grid.ncols as u32, grid.nrows as u32, compression::Deflate::default())?;
+    let dir = image.encoder();
+    write_georeference(dir, grid)?;
+    dir.write_tag(Tag::GdalNodata, &*grid.nodata.to_string())?;
+    if names.len() > 1 {
+        dir.write_tag(Tag::ExtraSamples, &vec![0u16; names.len() - 1][..])?;
+    }
+    let items: String = names.iter().enumerate()
+        .map(|(i, name)| format!("<Item name=\"DESCRIPTION\" sample=\"{}\" role=\"description\">{}</Item>", i, name))
+        .collect();
+    dir.write_tag(Tag::Unknown(42112), &*format!("<GDALMetadata>{}</GDALMetadata>", items))?;
+
+    image.write_data(data)
+}
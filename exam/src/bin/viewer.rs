@@ -0,0 +1,180 @@
+//! Interactive DEM viewer: `cargo run --features viewer --bin viewer -- <path_to_asc_file>`
+//!
+//! Drag to pan, scroll to zoom, hover to read the elevation under the cursor.
+
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use eframe::egui::{self, Align2, Color32, ColorImage, FontId, Pos2, Rect, Sense, TextureHandle, TextureOptions, Vec2};
+
+use exam::asc::load_asc;
+use exam::grid::Grid;
+use exam::render::{self, ShadeParams, COLORMAPS};
+
+/// Image shown in the central panel
+#[derive(Clone, Copy, PartialEq)]
+enum Product {
+    Grayscale,
+    Colored,
+    Hillshade,
+}
+
+struct Viewer {
+    name: String,
+    grid: Grid,
+    product: Product,
+    colormap: &'static str,
+    params: ShadeParams,
+    texture: Option<TextureHandle>,
+    dirty: bool,       // Parameters changed since the texture was rendered
+    zoom: f32,         // Screen pixels per cell
+    offset: Vec2,      // Pan of the image center relative to the panel center
+    fit: bool,         // Fit the whole grid into the panel on the next frame
+}
+
+impl Viewer {
+    /// Renders the selected product with the library into an egui image
+    fn render(&self) -> ColorImage {
+        let size = [self.grid.ncols, self.grid.nrows];
+        let grad = render::colormap(self.colormap).unwrap_or_else(colorgrad::viridis);
+        match self.product {
+            Product::Grayscale => {
+                let img = render::render_grayscale(&self.grid);
+                ColorImage::from_gray(size, img.as_raw())
+            }
+            Product::Colored => {
+                let img = render::render_colored(&self.grid, &grad);
+                ColorImage::from_rgb(size, img.as_raw())
+            }
+            Product::Hillshade => {
+                let img = render::render_shaded(&self.grid, self.grid.min_max(), &grad, &self.params);
+                ColorImage::from_rgb(size, img.as_raw())
+            }
+        }
+    }
+
+    fn controls(&mut self, ui: &mut egui::Ui) {
+        ui.heading(&self.name);
+        ui.label(format!("{} × {} cells", self.grid.ncols, self.grid.nrows));
+        ui.separator();
+
+        let before = (self.product, self.colormap, self.params);
+
+        ui.label("Product");
+        ui.selectable_value(&mut self.product, Product::Grayscale, "Grayscale");
+        ui.selectable_value(&mut self.product, Product::Colored, "Colored");
+        ui.selectable_value(&mut self.product, Product::Hillshade, "Hillshade");
+        ui.separator();
+
+        egui::ComboBox::from_label("Colormap")
+            .selected_text(self.colormap)
+            .show_ui(ui, |ui| {
+                for &name in COLORMAPS {
+                    ui.selectable_value(&mut self.colormap, name, name);
+                }
+            });
+        ui.add(egui::Slider::new(&mut self.params.azimuth, 0.0..=360.0).text("Azimuth °"));
+        ui.add(egui::Slider::new(&mut self.params.altitude, 0.0..=90.0).text("Altitude °"));
+        ui.add(egui::Slider::new(&mut self.params.z_factor, 0.1..=20.0).logarithmic(true).text("Z-factor"));
+        ui.separator();
+
+        if ui.button("Fit to window").clicked() {
+            self.fit = true;
+        }
+
+        if (self.product, self.colormap, self.params) != before {
+            self.dirty = true;
+        }
+    }
+
+    fn image_view(&mut self, ui: &mut egui::Ui) {
+        let (response, painter) = ui.allocate_painter(ui.available_size(), Sense::click_and_drag());
+        let panel = response.rect;
+        let grid_size = Vec2::new(self.grid.ncols as f32, self.grid.nrows as f32);
+
+        if self.fit {
+            self.zoom = (panel.width() / grid_size.x).min(panel.height() / grid_size.y);
+            self.offset = Vec2::ZERO;
+            self.fit = false;
+        }
+
+        // Zoom around the cursor so the cell under it stays in place
+        if let Some(hover) = response.hover_pos() {
+            let scroll = ui.input(|i| i.smooth_scroll_delta.y);
+            if scroll != 0.0 {
+                let factor = (scroll * 0.002).exp();
+                let anchor = hover - panel.center() - self.offset;
+                self.offset -= anchor * (factor - 1.0);
+                self.zoom *= factor;
+            }
+        }
+        if response.dragged() {
+            self.offset += response.drag_delta();
+        }
+
+        let origin = panel.center() + self.offset - grid_size * self.zoom / 2.0;
+        let rect = Rect::from_min_size(origin, grid_size * self.zoom);
+        if let Some(texture) = &self.texture {
+            let uv = Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0));
+            painter.image(texture.id(), rect, uv, Color32::WHITE);
+        }
+
+        // Pixel inspector
+        if let Some(hover) = response.hover_pos() {
+            let cell = (hover - origin) / self.zoom;
+            if cell.x >= 0.0 && cell.y >= 0.0 && (cell.x as usize) < self.grid.ncols && (cell.y as usize) < self.grid.nrows {
+                let (col, row) = (cell.x as usize, cell.y as usize);
+                let val = self.grid.data[row][col];
+                let text = if self.grid.is_nodata(val) {
+                    format!("col {} row {}: NoData", col, row)
+                } else {
+                    format!("col {} row {}: {:.2}", col, row, val)
+                };
+                let pos = panel.left_top() + Vec2::new(8.0, 8.0);
+                painter.rect_filled(Rect::from_min_size(pos, Vec2::new(260.0, 22.0)), 4.0, Color32::from_black_alpha(180));
+                painter.text(pos + Vec2::new(6.0, 11.0), Align2::LEFT_CENTER, text, FontId::monospace(14.0), Color32::WHITE);
+            }
+        }
+    }
+}
+
+impl eframe::App for Viewer {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::SidePanel::left("controls").show(ctx, |ui| self.controls(ui));
+
+        if self.dirty || self.texture.is_none() {
+            self.texture = Some(ctx.load_texture("dem", self.render(), TextureOptions::NEAREST));
+            self.dirty = false;
+        }
+
+        egui::CentralPanel::default().show(ctx, |ui| self.image_view(ui));
+    }
+}
+
+fn main() -> eframe::Result {
+    let Some(path) = env::args().nth(1).map(PathBuf::from) else {
+        eprintln!("Usage: viewer <path_to_asc_file>");
+        process::exit(2);
+    };
+    let grid = match load_asc(&path) {
+        Ok(grid) => grid,
+        Err(e) => {
+            eprintln!("Failed to read {:?}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let viewer = Viewer {
+        name: path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default(),
+        grid,
+        product: Product::Hillshade,
+        colormap: "viridis",
+        params: ShadeParams::default(),
+        texture: None,
+        dirty: true,
+        zoom: 1.0,
+        offset: Vec2::ZERO,
+        fit: true,
+    };
+    eframe::run_native("exam viewer", eframe::NativeOptions::default(), Box::new(|_cc| Ok(Box::new(viewer))))
+}
@@ -0,0 +1,96 @@
+//! Memory budget of a batch run with several jobs (`--max-memory`): every file reserves an estimate of the memory it
+//! needs before it is loaded, from the size in its header, and waits while the files in flight would exceed the budget.
+
+use std::fs;
+use std::path::Path;
+use std::sync::{Condvar, Mutex};
+
+use crate::grid::read_info;
+use crate::sniff::{sniff_file, InputFormat};
+
+/// Heap per grid cell while a file is rendered: the elevations plus the derived values, shade intensities and image
+/// buffers of a product, as measured by `--profiling` for the hillshade, slope and bands products
+pub const BYTES_PER_CELL: u64 = 48;
+
+/// Reads a size like `512M`, `4G`, `1.5GiB` or a plain count of bytes. K, M, G and T are powers of 1024.
+pub fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let split = text.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("Invalid size {:?}", text))?;
+    let exponent = match unit.trim().to_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        _ => return Err(format!("Unknown unit in size {:?}, expected K, M, G or T", text)),
+    };
+    Ok((number * 1024f64.powi(exponent)) as u64)
+}
+
+/// Columns and rows of a grid file from its header, without reading the values. None for the formats whose size isn't
+/// in a header: netCDF, LAS and the other point or vector formats.
+pub fn grid_dimensions(path: &Path, format: InputFormat) -> Option<(usize, usize)> {
+    match format {
+        InputFormat::EsriAscii | InputFormat::GrassAscii | InputFormat::GeoTiff => read_info(path).ok().map(|info| (info.ncols, info.nrows)),
+        _ => None,
+    }
+}
+
+/// Bytes to reserve for rendering a file of `cells` cells
+pub fn estimate_bytes(cells: usize) -> u64 {
+    cells as u64 * BYTES_PER_CELL
+}
+
+/// Bytes to reserve for rendering the grid file at `path`, from the size in its header or, for the formats without
+/// one, from the file size at 4 bytes per cell
+pub fn estimate_file(path: &Path) -> u64 {
+    let dimensions = sniff_file(path).ok().and_then(|format| grid_dimensions(path, format));
+    match dimensions {
+        Some((ncols, nrows)) => estimate_bytes(ncols * nrows),
+        None => fs::metadata(path).map_or(0, |m| m.len()) / 4 * BYTES_PER_CELL,
+    }
+}
+
+/// Memory shared by the jobs of a run
+pub struct MemoryBudget {
+    limit: u64,
+    reserved: Mutex<u64>, // Bytes held by the files in flight
+    released: Condvar,
+}
+
+/// Memory held by one file, given back when dropped
+pub struct Reservation<'a> {
+    budget: &'a MemoryBudget,
+    bytes: u64,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: u64) -> MemoryBudget {
+        MemoryBudget { limit, reserved: Mutex::new(0), released: Condvar::new() }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Waits until `bytes` fit next to the files in flight and reserves them. A file larger than the whole budget
+    /// waits until it is alone and then runs anyway, rather than never.
+    pub fn reserve(&self, bytes: u64) -> Reservation<'_> {
+        let mut reserved = self.reserved.lock().unwrap_or_else(|e| e.into_inner());
+        while *reserved > 0 && *reserved + bytes > self.limit {
+            reserved = self.released.wait(reserved).unwrap_or_else(|e| e.into_inner());
+        }
+        *reserved += bytes;
+        Reservation { budget: self, bytes }
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        let mut reserved = self.budget.reserved.lock().unwrap_or_else(|e| e.into_inner());
+        *reserved -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
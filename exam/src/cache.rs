@@ -0,0 +1,116 @@
+//! Binary sidecar cache of parsed grids (`<name>.ascbin` next to `<name>.asc`), enabled with `--cache`.
+//!
+//! Layout, all little endian: magic `EXAMGRID`, version u32, ncols u64, nrows u64, nodata f32,
+//! cellsize f64, xllcorner f64, yllcorner f64, skipped rows u64, invalid values u64,
+//! then `ncols * nrows` f32 elevations row by row, north row first.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::asc::{load_asc_with_warnings, AscWarnings};
+use crate::grid::Grid;
+
+const MAGIC: &[u8; 8] = b"EXAMGRID";
+const VERSION: u32 = 1;
+
+/// Extension of the cache files
+pub const CACHE_EXTENSION: &str = "ascbin";
+
+/// Path of the cache belonging to an .asc file
+pub fn cache_path(asc: &Path) -> PathBuf {
+    asc.with_extension(CACHE_EXTENSION)
+}
+
+/// Loads `asc` from its cache if that is at least as new as the file, otherwise parses it and writes the cache.
+/// A cache that cannot be read or written is only reported, the text file is always the fallback.
+pub fn load_cached(asc: &Path) -> io::Result<(Grid, AscWarnings)> {
+    let cache = cache_path(asc);
+    if is_fresh(&cache, asc) {
+        match read_cache(&cache) {
+            Ok(loaded) => return Ok(loaded),
+            Err(e) => eprintln!("Ignoring cache {:?}: {}", cache, e),
+        }
+    }
+    let (grid, warnings) = load_asc_with_warnings(asc)?;
+    if let Err(e) = write_cache(&cache, &grid, &warnings) {
+        eprintln!("Could not write cache {:?}: {}", cache, e);
+    }
+    Ok((grid, warnings))
+}
+
+/// Whether `cache` exists and was modified no earlier than `source`
+fn is_fresh(cache: &Path, source: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    match (modified(cache), modified(source)) {
+        (Some(cached), Some(source)) => cached >= source,
+        _ => false,
+    }
+}
+
+/// Writes the grid to a temporary file first, so an interrupted run never leaves a truncated cache
+pub fn write_cache(path: &Path, grid: &Grid, warnings: &AscWarnings) -> io::Result<()> {
+    let tmp = path.with_extension(format!("{}.tmp", CACHE_EXTENSION));
+    {
+        let mut w = BufWriter::new(File::create(&tmp)?);
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&(grid.ncols as u64).to_le_bytes())?;
+        w.write_all(&(grid.nrows as u64).to_le_bytes())?;
+        w.write_all(&grid.nodata.to_le_bytes())?;
+        w.write_all(&grid.cellsize.to_le_bytes())?;
+        w.write_all(&grid.xllcorner.to_le_bytes())?;
+        w.write_all(&grid.yllcorner.to_le_bytes())?;
+        w.write_all(&(warnings.skipped_rows as u64).to_le_bytes())?;
+        w.write_all(&(warnings.invalid_values as u64).to_le_bytes())?;
+        for row in &grid.data {
+            let bytes: Vec<u8> = row.iter().flat_map(|v| v.to_le_bytes()).collect();
+            w.write_all(&bytes)?;
+        }
+        w.flush()?;
+    }
+    fs::rename(&tmp, path)
+}
+
+/// Reads a cache written by [`write_cache`]
+pub fn read_cache(path: &Path) -> io::Result<(Grid, AscWarnings)> {
+    let mut r = BufReader::new(File::open(path)?);
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+    let mut magic = [0; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not an exam grid cache"));
+    }
+    if u32::from_le_bytes(read_bytes(&mut r)?) != VERSION {
+        return Err(invalid("unsupported cache version"));
+    }
+    let ncols = u64::from_le_bytes(read_bytes(&mut r)?) as usize;
+    let nrows = u64::from_le_bytes(read_bytes(&mut r)?) as usize;
+    let nodata = f32::from_le_bytes(read_bytes(&mut r)?);
+    let cellsize = f64::from_le_bytes(read_bytes(&mut r)?);
+    let xllcorner = f64::from_le_bytes(read_bytes(&mut r)?);
+    let yllcorner = f64::from_le_bytes(read_bytes(&mut r)?);
+    let warnings = AscWarnings {
+        skipped_rows: u64::from_le_bytes(read_bytes(&mut r)?) as usize,
+        invalid_values: u64::from_le_bytes(read_bytes(&mut r)?) as usize,
+    };
+
+    let mut bytes = vec![0; ncols * 4];
+    let mut data = Vec::with_capacity(nrows);
+    for _ in 0..nrows {
+        r.read_exact(&mut bytes)?;
+        data.push(bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect());
+    }
+    if r.read(&mut [0])? != 0 {
+        return Err(invalid("trailing bytes after the grid"));
+    }
+
+    Ok((Grid { data, ncols, nrows, nodata, cellsize, xllcorner, yllcorner, geographic: None }, warnings))
+}
+
+fn read_bytes<const N: usize>(r: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
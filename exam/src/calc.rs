@@ -0,0 +1,406 @@
+//! Raster calculator: cell-wise map algebra over one or more grids, e.g. `(a - b) * 2` or `max(a, 0)`.
+//!
+//! Operators: `+ - * / % ^`, comparisons `< <= > >= == !=` (1 if true, 0 otherwise) and parentheses.
+//! Functions: `abs sqrt exp ln log10 sin cos tan floor ceil round min max clamp(x, lo, hi) if(c, a, b)`
+//! plus the terrain derivatives `slope aspect curvature flow twi spi`, which work on the whole grid of their argument.
+//! NoData in any input makes the cell NoData, as does a result that is not a finite number.
+
+use std::collections::HashMap;
+
+use crate::grid::Grid;
+use crate::terrain::Derivative;
+
+/// Parsed expression, evaluated with [`Expression::evaluate`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expression {
+    root: Node,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Number(f64),
+    Variable(String),
+    Call(String, Vec<Node>), // Operators are calls too, e.g. "+" with two arguments
+}
+
+/// Functions evaluated cell by cell with their number of arguments
+const FUNCTIONS: &[(&str, usize)] = &[
+    ("abs", 1), ("sqrt", 1), ("exp", 1), ("ln", 1), ("log10", 1), ("sin", 1), ("cos", 1), ("tan", 1),
+    ("floor", 1), ("ceil", 1), ("round", 1), ("min", 2), ("max", 2), ("clamp", 3), ("if", 3),
+];
+
+/// Deepest nesting of parentheses, arguments, signs and exponents accepted, so the recursive parser and evaluator
+/// can't overflow the stack
+const MAX_DEPTH: usize = 64;
+
+/// Value of a sub-expression: one number for every cell, or a constant
+enum Value {
+    Scalar(f64),
+    Cells(Vec<f64>), // Row-major, NaN is NoData
+}
+
+impl Value {
+    fn at(&self, i: usize) -> f64 {
+        match self {
+            Value::Scalar(v) => *v,
+            Value::Cells(cells) => cells[i],
+        }
+    }
+}
+
+impl Expression {
+    /// Parses the expression text, reporting the position of the first syntax error.
+    /// Expressions nested deeper than [`MAX_DEPTH`] levels are refused.
+    pub fn parse(text: &str) -> Result<Expression, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, pos: 0, depth: 0 };
+        let root = parser.comparison()?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(format!("Unexpected {:?} in expression", token));
+        }
+        Ok(Expression { root })
+    }
+
+    /// Names of the grids the expression refers to, sorted and without duplicates
+    pub fn variables(&self) -> Vec<String> {
+        fn collect(node: &Node, names: &mut Vec<String>) {
+            match node {
+                Node::Number(_) => {}
+                Node::Variable(name) => names.push(name.clone()),
+                Node::Call(_, args) => args.iter().for_each(|arg| collect(arg, names)),
+            }
+        }
+        let mut names = Vec::new();
+        collect(&self.root, &mut names);
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Evaluates the expression for every cell. All grids must have the same size,
+    /// the result takes the georeferencing and NoData value of the first grid in name order.
+    pub fn evaluate(&self, grids: &HashMap<String, Grid>) -> Result<Grid, String> {
+        let names = self.variables();
+        let first = names.first().ok_or("The expression does not use any grid")?;
+        let template = grids.get(first).ok_or_else(|| format!("No grid given for {:?}", first))?;
+        for name in &names {
+            let grid = grids.get(name).ok_or_else(|| format!("No grid given for {:?}", name))?;
+            if (grid.ncols, grid.nrows) != (template.ncols, template.nrows) {
+                return Err(format!("{:?} is {}x{} cells but {:?} is {}x{}", name, grid.ncols, grid.nrows, first, template.ncols, template.nrows));
+            }
+        }
+
+        let cells = match eval(&self.root, grids, template) {
+            Value::Scalar(v) => vec![v; template.ncols * template.nrows],
+            Value::Cells(cells) => cells,
+        };
+        Ok(from_cells(&cells, template))
+    }
+}
+
+fn eval(node: &Node, grids: &HashMap<String, Grid>, template: &Grid) -> Value {
+    match node {
+        Node::Number(v) => Value::Scalar(*v),
+        Node::Variable(name) => Value::Cells(to_cells(&grids[name])),
+        Node::Call(name, args) => {
+            if let Some(derivative) = Derivative::parse(name) {
+                let input = match eval(&args[0], grids, template) {
+                    Value::Scalar(v) => vec![v; template.ncols * template.nrows],
+                    Value::Cells(cells) => cells,
+                };
+                return Value::Cells(to_cells(&derivative.compute(&from_cells(&input, template))));
+            }
+            let values: Vec<Value> = args.iter().map(|arg| eval(arg, grids, template)).collect();
+            let mut buffer = vec![0.0; values.len()];
+            let mut at = |i: usize| {
+                for (slot, value) in buffer.iter_mut().zip(&values) {
+                    *slot = value.at(i);
+                }
+                apply(name, &buffer)
+            };
+            if values.iter().all(|v| matches!(v, Value::Scalar(_))) {
+                Value::Scalar(at(0))
+            } else {
+                Value::Cells((0..template.ncols * template.nrows).map(at).collect())
+            }
+        }
+    }
+}
+
+/// Applies an operator or cell-wise function, NaN (NoData) in any argument gives NaN
+fn apply(name: &str, args: &[f64]) -> f64 {
+    if args.iter().any(|v| v.is_nan()) {
+        return f64::NAN;
+    }
+    let truth = |b: bool| if b { 1.0 } else { 0.0 };
+    match (name, args) {
+        ("+", [a, b]) => a + b,
+        ("-", [a, b]) => a - b,
+        ("*", [a, b]) => a * b,
+        ("/", [a, b]) => a / b,
+        ("%", [a, b]) => a % b,
+        ("^", [a, b]) => a.powf(*b),
+        ("neg", [a]) => -a,
+        ("<", [a, b]) => truth(a < b),
+        ("<=", [a, b]) => truth(a <= b),
+        (">", [a, b]) => truth(a > b),
+        (">=", [a, b]) => truth(a >= b),
+        ("==", [a, b]) => truth(a == b),
+        ("!=", [a, b]) => truth(a != b),
+        ("abs", [a]) => a.abs(),
+        ("sqrt", [a]) => a.sqrt(),
+        ("exp", [a]) => a.exp(),
+        ("ln", [a]) => a.ln(),
+        ("log10", [a]) => a.log10(),
+        ("sin", [a]) => a.to_radians().sin(),
+        ("cos", [a]) => a.to_radians().cos(),
+        ("tan", [a]) => a.to_radians().tan(),
+        ("floor", [a]) => a.floor(),
+        ("ceil", [a]) => a.ceil(),
+        ("round", [a]) => a.round(),
+        ("min", [a, b]) => a.min(*b),
+        ("max", [a, b]) => a.max(*b),
+        ("clamp", [x, lo, hi]) => x.max(*lo).min(*hi),
+        ("if", [c, a, b]) => if *c != 0.0 { *a } else { *b },
+        _ => unreachable!("checked by the parser"),
+    }
+}
+
+/// Grid values as f64 with NoData turned into NaN
+fn to_cells(grid: &Grid) -> Vec<f64> {
+    grid.data.iter().flatten().map(|&v| if grid.is_nodata(v) { f64::NAN } else { v as f64 }).collect()
+}
+
+/// Grid shaped like `template` from row-major cells, anything not finite becomes NoData
+fn from_cells(cells: &[f64], template: &Grid) -> Grid {
+    let data = cells.chunks(template.ncols.max(1))
+        .map(|row| row.iter().map(|&v| if v.is_finite() { v as f32 } else { template.nodata }).collect())
+        .collect();
+    Grid { data, ..template.clone() }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(&'static str),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    const OPS: [&str; 15] = ["<=", ">=", "==", "!=", "+", "-", "*", "/", "%", "^", "<", ">", "(", ")", ","];
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let start = text.len() - rest.len();
+        if let Some(op) = OPS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else if rest.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+            let end = rest.find(|c: char| !(c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E')).unwrap_or(rest.len());
+            // Exponent signs belong to the number, as in 1e-3
+            let end = if rest[..end].ends_with(['e', 'E']) && rest[end..].starts_with(['+', '-']) {
+                end + 1 + rest[end + 1..].find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len() - end - 1)
+            } else {
+                end
+            };
+            let number = rest[..end].parse().map_err(|_| format!("Invalid number {:?} at position {}", &rest[..end], start))?;
+            tokens.push(Token::Number(number));
+            rest = &rest[end..];
+        } else if rest.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            return Err(format!("Unexpected {:?} at position {}", rest.chars().next().unwrap(), start));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over the precedence levels: comparison < additive < term < unary < power
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize, // Levels of `unary` entered, every nested sub-expression passes through it
+}
+
+impl Parser {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        if self.peek_op() == Some(op) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!("Expected '{}' in expression", op))
+        }
+    }
+
+    fn binary(&mut self, ops: &[&str], next: fn(&mut Parser) -> Result<Node, String>, repeat: bool) -> Result<Node, String> {
+        let mut left = next(self)?;
+        while let Some(op) = self.peek_op().filter(|op| ops.contains(op)) {
+            self.pos += 1;
+            left = Node::Call(op.to_string(), vec![left, next(self)?]);
+            if !repeat {
+                break;
+            }
+        }
+        Ok(left)
+    }
+
+    fn comparison(&mut self) -> Result<Node, String> {
+        self.binary(&["<", "<=", ">", ">=", "==", "!="], Parser::additive, false)
+    }
+
+    fn additive(&mut self) -> Result<Node, String> {
+        self.binary(&["+", "-"], Parser::term, true)
+    }
+
+    fn term(&mut self) -> Result<Node, String> {
+        self.binary(&["*", "/", "%"], Parser::unary, true)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!("Expression nested deeper than {} levels", MAX_DEPTH));
+        }
+        self.depth += 1;
+        let node = if self.peek_op() == Some("-") {
+            self.pos += 1;
+            self.unary().map(|arg| Node::Call("neg".into(), vec![arg]))
+        } else {
+            self.power()
+        };
+        self.depth -= 1;
+        node
+    }
+
+    /// `^` binds tighter than the unary minus on its left and is right associative
+    fn power(&mut self) -> Result<Node, String> {
+        let base = self.primary()?;
+        if self.peek_op() == Some("^") {
+            self.pos += 1;
+            return Ok(Node::Call("^".into(), vec![base, self.unary()?]));
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        let token = self.tokens.get(self.pos).cloned().ok_or("Unexpected end of expression")?;
+        self.pos += 1;
+        match token {
+            Token::Number(v) => Ok(Node::Number(v)),
+            Token::Op("(") => {
+                let inner = self.comparison()?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            Token::Ident(name) if self.peek_op() == Some("(") => {
+                self.pos += 1;
+                let mut args = Vec::new();
+                if self.peek_op() != Some(")") {
+                    args.push(self.comparison()?);
+                    while self.peek_op() == Some(",") {
+                        self.pos += 1;
+                        args.push(self.comparison()?);
+                    }
+                }
+                self.expect(")")?;
+                let arity = if Derivative::parse(&name).is_some() {
+                    1
+                } else {
+                    FUNCTIONS.iter().find(|(f, _)| *f == name).map(|(_, n)| *n).ok_or_else(|| format!("Unknown function {:?}", name))?
+                };
+                if args.len() != arity {
+                    return Err(format!("{} expects {} arguments, got {}", name, arity, args.len()));
+                }
+                Ok(Node::Call(name, args))
+            }
+            Token::Ident(name) => Ok(Node::Variable(name)),
+            Token::Op(op) => Err(format!("Unexpected '{}' in expression", op)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 1x3 grid `a` of 1, NoData and 0
+    fn grids() -> HashMap<String, Grid> {
+        let a = Grid { data: vec![vec![1.0, -9999.0, 0.0]], ncols: 3, nrows: 1, nodata: -9999.0, cellsize: 1.0, xllcorner: 0.0, yllcorner: 0.0, geographic: None };
+        HashMap::from([("a".to_string(), a)])
+    }
+
+    /// Value of an expression without grids
+    fn constant(text: &str) -> f64 {
+        let expression = Expression::parse(text).unwrap();
+        match eval(&expression.root, &HashMap::new(), &grids()["a"]) {
+            Value::Scalar(v) => v,
+            Value::Cells(_) => panic!("{:?} uses a grid", text),
+        }
+    }
+
+    /// The unary minus binds looser than `^`, which is right associative
+    #[test]
+    fn precedence() {
+        assert_eq!(constant("-2^2"), -4.0);
+        assert_eq!(constant("2^3^2"), 512.0);
+        assert_eq!(constant("2^-1"), 0.5);
+        assert_eq!(constant("--2"), 2.0);
+        assert_eq!(constant("1 + 2 * 3 % 4 - 8 / 2"), -1.0);
+        assert_eq!(constant("1 + 2 < 4"), 1.0);
+        assert_eq!(constant("(1 + 2) * 3"), 9.0);
+    }
+
+    /// Exponent signs stay with the number instead of becoming operators
+    #[test]
+    fn exponent_numbers() {
+        assert_eq!(tokenize("1e-3-a").unwrap(), vec![Token::Number(0.001), Token::Op("-"), Token::Ident("a".into())]);
+        assert_eq!(constant("1e-3"), 0.001);
+        assert_eq!(constant("2.5E+2 - 1e3"), -750.0);
+        assert!(Expression::parse("1e").is_err());
+    }
+
+    /// Functions take exactly their number of arguments, derivatives one
+    #[test]
+    fn arity_errors() {
+        assert_eq!(Expression::parse("max(1)").unwrap_err(), "max expects 2 arguments, got 1");
+        assert_eq!(Expression::parse("clamp(a, 0)").unwrap_err(), "clamp expects 3 arguments, got 2");
+        assert_eq!(Expression::parse("abs()").unwrap_err(), "abs expects 1 arguments, got 0");
+        assert_eq!(Expression::parse("slope(a, a)").unwrap_err(), "slope expects 1 arguments, got 2");
+        assert_eq!(Expression::parse("foo(a)").unwrap_err(), "Unknown function \"foo\"");
+        assert!(Expression::parse("if(a > 0, 1, 2)").is_ok());
+    }
+
+    /// NoData in any argument and results that aren't finite come out as NoData
+    #[test]
+    fn nan_propagation() {
+        assert!(apply("+", &[1.0, f64::NAN]).is_nan());
+        assert!(apply("if", &[1.0, 2.0, f64::NAN]).is_nan());
+        let values = |text: &str| Expression::parse(text).unwrap().evaluate(&grids()).unwrap().data[0].clone();
+        assert_eq!(values("max(a, 0) + 1"), vec![2.0, -9999.0, 1.0]);
+        assert_eq!(values("if(a == a, 5, 6)"), vec![5.0, -9999.0, 5.0]);
+        assert_eq!(values("1 / a"), vec![1.0, -9999.0, -9999.0]);
+        assert_eq!(values("sqrt(a - 1)"), vec![0.0, -9999.0, -9999.0]);
+    }
+
+    /// Nesting is refused beyond MAX_DEPTH levels instead of overflowing the stack
+    #[test]
+    fn nesting_depth() {
+        let nested = |n: usize| format!("{}a{}", "(".repeat(n), ")".repeat(n));
+        assert!(Expression::parse(&nested(MAX_DEPTH - 1)).is_ok());
+        let too_deep = format!("Expression nested deeper than {} levels", MAX_DEPTH);
+        assert_eq!(Expression::parse(&nested(MAX_DEPTH)).unwrap_err(), too_deep);
+        assert_eq!(Expression::parse(&nested(100_000)).unwrap_err(), too_deep);
+        assert_eq!(Expression::parse(&format!("{}1", "-".repeat(100_000))).unwrap_err(), too_deep);
+        assert_eq!(Expression::parse(&format!("{}1)", "abs(".repeat(100_000))).unwrap_err(), too_deep);
+        assert_eq!(Expression::parse(&["2"; 100_000].join("^")).unwrap_err(), too_deep);
+    }
+}
@@ -0,0 +1,291 @@
+//! Terrain character at a glance: an aspect rose, how the sloped cells are spread over 16 facing directions, and a
+//! histogram of the slopes. Product "charts" writes both per input as images and as SVG next to the raster products.
+
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io::{self, BufWriter, Write};
+
+#[cfg(feature = "fs")]
+use image::DynamicImage;
+use image::{Rgb, RgbImage};
+
+use crate::font;
+use crate::grid::{EdgeMode, Grid};
+#[cfg(feature = "fs")]
+use crate::output::{save_image, OutputFormat, OutputTarget};
+use crate::terrain;
+
+/// Directions of the rose, the first centered on north
+pub const SECTORS: usize = 16;
+
+/// Slope covered by one bar of the histogram, in degrees
+pub const SLOPE_BIN: f64 = 5.0;
+
+/// Sizes of the charts in pixels
+const ROSE_SIZE: u32 = 480;
+const HISTOGRAM_SIZE: (u32, u32) = (640, 400);
+const MARGIN: f64 = 48.0;
+
+/// Scale of the bitmap font in the images, about the size of the SVG text
+const TEXT_SCALE: u32 = 2;
+
+const BACKGROUND: [u8; 3] = [255, 255, 255];
+const GRID_LINE: [u8; 3] = [215, 215, 215];
+const AXIS: [u8; 3] = [90, 90, 90];
+const FILL: [u8; 3] = [70, 110, 170];
+const TEXT: [u8; 3] = [40, 40, 40];
+
+#[cfg(feature = "fs")]
+fn hex(c: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2])
+}
+
+/// Share of `count` in `total` as a whole percentage
+fn percent(count: usize, total: usize) -> String {
+    format!("{:.0}%", 100.0 * count as f64 / total.max(1) as f64)
+}
+
+/// Facing directions of the valid cells
+#[derive(Clone, Debug, PartialEq)]
+pub struct AspectRose {
+    pub sectors: [usize; SECTORS], // Cells facing each direction, clockwise from north
+    pub flat: usize,               // Cells without a slope, in no sector
+}
+
+impl AspectRose {
+    /// Counts the aspect of every valid cell, computed with `edges` at the border of the grid
+    pub fn of(grid: &Grid, edges: EdgeMode) -> AspectRose {
+        let width = 360.0 / SECTORS as f32;
+        let mut rose = AspectRose { sectors: [0; SECTORS], flat: 0 };
+        for (_, _, aspect) in terrain::aspect(grid, edges).iter_valid() {
+            if aspect < 0.0 {
+                rose.flat += 1;
+            } else {
+                rose.sectors[((aspect + width / 2.0) / width) as usize % SECTORS] += 1;
+            }
+        }
+        rose
+    }
+
+    fn total(&self) -> usize {
+        self.sectors.iter().sum::<usize>() + self.flat
+    }
+
+    /// Center and outer radius of the rose
+    fn frame() -> (f64, f64) {
+        let center = ROSE_SIZE as f64 / 2.0;
+        (center, center - MARGIN)
+    }
+
+    /// Radius of the petal of `sector`; the area of a petal, not its length, grows with its count, and the fullest
+    /// sector reaches the outer ring
+    fn petal(&self, sector: usize) -> f64 {
+        let max = self.sectors.iter().copied().max().unwrap_or(0).max(1);
+        AspectRose::frame().1 * (self.sectors[sector] as f64 / max as f64).sqrt()
+    }
+
+    /// The rose with rings at a quarter, half and three quarters of the fullest sector, compass letters and the share
+    /// of the fullest sector and of the flat cells
+    #[cfg(feature = "fs")]
+    pub fn write_svg<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let (c, r) = AspectRose::frame();
+        let width = 360.0 / SECTORS as f64;
+        let point = |azimuth: f64, radius: f64| {
+            let a = azimuth.to_radians();
+            (c + radius * a.sin(), c - radius * a.cos())
+        };
+        writeln!(out, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{s}" height="{s}" viewBox="0 0 {s} {s}">"#, s = ROSE_SIZE)?;
+        writeln!(out, r#"<rect width="100%" height="100%" fill="{}"/>"#, hex(BACKGROUND))?;
+        writeln!(out, r#"<g fill="none" stroke="{}">"#, hex(GRID_LINE))?;
+        for k in 1..=4 {
+            writeln!(out, r#"<circle cx="{:.1}" cy="{:.1}" r="{:.1}"/>"#, c, c, r * (k as f64 / 4.0).sqrt())?;
+        }
+        for k in 0..8 {
+            let (x, y) = point(k as f64 * 45.0, r);
+            writeln!(out, r#"<line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}"/>"#, c, c, x, y)?;
+        }
+        writeln!(out, r#"</g>"#)?;
+        writeln!(out, r#"<g id="petals" fill="{}" fill-opacity="0.85">"#, hex(FILL))?;
+        for sector in 0..SECTORS {
+            let (radius, azimuth) = (self.petal(sector), sector as f64 * width);
+            let ((x0, y0), (x1, y1)) = (point(azimuth - 0.45 * width, radius), point(azimuth + 0.45 * width, radius));
+            writeln!(out, r#"<path d="M{:.1},{:.1} L{:.1},{:.1} A{:.1},{:.1} 0 0 1 {:.1},{:.1} Z" data-cells="{}"/>"#,
+                c, c, x0, y0, radius, radius, x1, y1, self.sectors[sector])?;
+        }
+        writeln!(out, r#"</g>"#)?;
+        writeln!(out, r#"<g font-family="sans-serif" font-size="16" fill="{}" text-anchor="middle" dominant-baseline="central">"#, hex(TEXT))?;
+        for (letter, azimuth) in [("N", 0.0), ("E", 90.0), ("S", 180.0), ("W", 270.0)] {
+            let (x, y) = point(azimuth, r + MARGIN / 2.0);
+            writeln!(out, r#"<text x="{:.1}" y="{:.1}">{}</text>"#, x, y, letter)?;
+        }
+        writeln!(out, r#"</g>"#)?;
+        let max = self.sectors.iter().copied().max().unwrap_or(0);
+        writeln!(out, r#"<g font-family="sans-serif" font-size="14" fill="{}">"#, hex(TEXT))?;
+        writeln!(out, r#"<text x="12" y="24">Aspect</text>"#)?;
+        writeln!(out, r#"<text x="{}" y="24" text-anchor="end">Outer ring {}</text>"#, ROSE_SIZE - 12, percent(max, self.total()))?;
+        writeln!(out, r#"<text x="12" y="{}">Flat {}</text>"#, ROSE_SIZE - 14, percent(self.flat, self.total()))?;
+        writeln!(out, "</g>\n</svg>")
+    }
+
+    /// The rose of [`AspectRose::write_svg`] as an image
+    pub fn render(&self) -> RgbImage {
+        let (c, r) = AspectRose::frame();
+        let width = 360.0 / SECTORS as f64;
+        let mut img = RgbImage::from_pixel(ROSE_SIZE, ROSE_SIZE, Rgb(BACKGROUND));
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            let (dx, dy) = (x as f64 + 0.5 - c, y as f64 + 0.5 - c);
+            let d = dx.hypot(dy);
+            let azimuth = dx.atan2(-dy).to_degrees().rem_euclid(360.0);
+            let sector = ((azimuth + width / 2.0) / width) as usize % SECTORS;
+            let off_center = (azimuth - sector as f64 * width + 180.0).rem_euclid(360.0) - 180.0;
+            // Distance to the nearest of the eight spokes
+            let spoke = (azimuth.rem_euclid(45.0).min(45.0 - azimuth.rem_euclid(45.0))).to_radians().sin() * d;
+            let ring = (1..=4).any(|k| (d - r * (k as f64 / 4.0).sqrt()).abs() < 0.5);
+            if d <= self.petal(sector) && off_center.abs() <= 0.45 * width {
+                *pixel = Rgb(FILL);
+            } else if ring || (spoke < 0.5 && d <= r) {
+                *pixel = Rgb(GRID_LINE);
+            }
+        }
+        let (h, half) = (font::text_height(TEXT_SCALE) as i64, |text: &str| font::text_width(text, TEXT_SCALE) as i64 / 2);
+        for (letter, azimuth) in [("N", 0.0f64), ("E", 90.0), ("S", 180.0), ("W", 270.0)] {
+            let a = azimuth.to_radians();
+            let (x, y) = (c + (r + MARGIN / 2.0) * a.sin(), c - (r + MARGIN / 2.0) * a.cos());
+            font::draw_text(&mut img, x as i64 - half(letter), y as i64 - h / 2, letter, TEXT_SCALE, Rgb(TEXT));
+        }
+        let max = self.sectors.iter().copied().max().unwrap_or(0);
+        let outer = format!("OUTER RING {}", percent(max, self.total()));
+        font::draw_text(&mut img, 12, 12, "ASPECT", TEXT_SCALE, Rgb(TEXT));
+        font::draw_text(&mut img, ROSE_SIZE as i64 - 12 - 2 * half(&outer), 12, &outer, TEXT_SCALE, Rgb(TEXT));
+        font::draw_text(&mut img, 12, ROSE_SIZE as i64 - 12 - h, &format!("FLAT {}", percent(self.flat, self.total())), TEXT_SCALE, Rgb(TEXT));
+        img
+    }
+}
+
+/// Slopes of the valid cells
+#[derive(Clone, Debug, PartialEq)]
+pub struct SlopeHistogram {
+    pub counts: Vec<usize>, // Cells per SLOPE_BIN degrees from 0 to 90
+    pub mean: f64,          // Mean slope in degrees
+}
+
+impl SlopeHistogram {
+    /// Counts the slope of every valid cell, computed with `edges` at the border of the grid
+    pub fn of(grid: &Grid, edges: EdgeMode) -> SlopeHistogram {
+        let bins = (90.0 / SLOPE_BIN) as usize;
+        let (mut counts, mut sum) = (vec![0; bins], 0.0);
+        for (_, _, slope) in terrain::slope(grid, edges).iter_valid() {
+            counts[((slope as f64 / SLOPE_BIN) as usize).min(bins - 1)] += 1;
+            sum += slope as f64;
+        }
+        let total = counts.iter().sum::<usize>();
+        SlopeHistogram { counts, mean: if total > 0 { sum / total as f64 } else { 0.0 } }
+    }
+
+    /// Left, top, width and height of the plot area
+    fn plot() -> (f64, f64, f64, f64) {
+        let (w, h) = (HISTOGRAM_SIZE.0 as f64, HISTOGRAM_SIZE.1 as f64);
+        (MARGIN + 16.0, MARGIN, w - MARGIN - 32.0, h - 2.0 * MARGIN)
+    }
+
+    /// Left, top, right and bottom of the bar of every bin
+    fn bars(&self) -> impl Iterator<Item = [f64; 4]> + '_ {
+        let (left, top, width, height) = SlopeHistogram::plot();
+        let max = self.counts.iter().copied().max().unwrap_or(0).max(1);
+        let step = width / self.counts.len() as f64;
+        self.counts.iter().enumerate().map(move |(i, &count)| {
+            let x = left + i as f64 * step;
+            [x + 1.0, top + height * (1.0 - count as f64 / max as f64), x + step - 1.0, top + height]
+        })
+    }
+
+    /// The histogram with its axes, the slope every 15° and the share of the fullest bar
+    #[cfg(feature = "fs")]
+    pub fn write_svg<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let (left, top, width, height) = SlopeHistogram::plot();
+        let total = self.counts.iter().sum::<usize>();
+        let max = self.counts.iter().copied().max().unwrap_or(0);
+        writeln!(out, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#, w = HISTOGRAM_SIZE.0, h = HISTOGRAM_SIZE.1)?;
+        writeln!(out, r#"<rect width="100%" height="100%" fill="{}"/>"#, hex(BACKGROUND))?;
+        writeln!(out, r#"<line x1="{l:.1}" y1="{t:.1}" x2="{r:.1}" y2="{t:.1}" stroke="{}"/>"#, hex(GRID_LINE), l = left, t = top, r = left + width)?;
+        writeln!(out, r#"<g id="bars" fill="{}">"#, hex(FILL))?;
+        for ([x0, y0, x1, y1], count) in self.bars().zip(&self.counts) {
+            writeln!(out, r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" data-cells="{}"/>"#, x0, y0, x1 - x0, y1 - y0, count)?;
+        }
+        writeln!(out, r#"</g>"#)?;
+        writeln!(out, r#"<path d="M{l:.1},{t:.1} V{b:.1} H{r:.1}" fill="none" stroke="{}"/>"#, hex(AXIS), l = left, t = top, b = top + height, r = left + width)?;
+        writeln!(out, r#"<g font-family="sans-serif" font-size="14" fill="{}" text-anchor="middle">"#, hex(TEXT))?;
+        for degrees in (0..=90).step_by(15) {
+            writeln!(out, r#"<text x="{:.1}" y="{:.1}">{}°</text>"#, left + width * degrees as f64 / 90.0, top + height + 20.0, degrees)?;
+        }
+        writeln!(out, r#"<text x="{:.1}" y="{:.1}" text-anchor="end" dominant-baseline="central">{}</text>"#, left - 6.0, top, percent(max, total))?;
+        writeln!(out, r#"<text x="{:.1}" y="{:.1}" text-anchor="end">0%</text>"#, left - 6.0, top + height)?;
+        writeln!(out, r#"<text x="12" y="24" text-anchor="start">Slope</text>"#)?;
+        writeln!(out, r#"<text x="{}" y="24" text-anchor="end">Mean {:.1}°</text>"#, HISTOGRAM_SIZE.0 - 12, self.mean)?;
+        writeln!(out, "</g>\n</svg>")
+    }
+
+    /// The histogram of [`SlopeHistogram::write_svg`] as an image
+    pub fn render(&self) -> RgbImage {
+        let (left, top, width, height) = SlopeHistogram::plot();
+        let (w, h) = HISTOGRAM_SIZE;
+        let mut img = RgbImage::from_pixel(w, h, Rgb(BACKGROUND));
+        let mut fill = |[x0, y0, x1, y1]: [f64; 4], color: [u8; 3]| {
+            for y in y0.round().max(0.0) as u32..(y1.round() as u32).min(h) {
+                for x in x0.round().max(0.0) as u32..(x1.round() as u32).min(w) {
+                    img.put_pixel(x, y, Rgb(color));
+                }
+            }
+        };
+        fill([left, top, left + width, top + 1.0], GRID_LINE);
+        for bar in self.bars() {
+            fill(bar, FILL);
+        }
+        fill([left - 1.0, top, left, top + height + 1.0], AXIS);
+        fill([left - 1.0, top + height, left + width, top + height + 1.0], AXIS);
+
+        let total = self.counts.iter().sum::<usize>();
+        let max = self.counts.iter().copied().max().unwrap_or(0);
+        let text_h = font::text_height(TEXT_SCALE) as i64;
+        let text_w = |text: &str| font::text_width(text, TEXT_SCALE) as i64;
+        for degrees in (0..=90).step_by(15) {
+            let text = format!("{}°", degrees);
+            let x = (left + width * degrees as f64 / 90.0) as i64 - text_w(&text) / 2;
+            font::draw_text(&mut img, x, (top + height) as i64 + 8, &text, TEXT_SCALE, Rgb(TEXT));
+        }
+        // The 0% sits above the axis, clear of the slope labels
+        for (text, y) in [(percent(max, total), top as i64 - text_h / 2), ("0%".to_string(), (top + height) as i64 - text_h)] {
+            font::draw_text(&mut img, left as i64 - 6 - text_w(&text), y, &text, TEXT_SCALE, Rgb(TEXT));
+        }
+        let mean = format!("MEAN {:.1}°", self.mean);
+        font::draw_text(&mut img, 12, 12, "SLOPE", TEXT_SCALE, Rgb(TEXT));
+        font::draw_text(&mut img, w as i64 - 12 - text_w(&mean), 12, &mean, TEXT_SCALE, Rgb(TEXT));
+        img
+    }
+}
+
+/// Both charts side by side, for a single image of product "charts"
+pub fn render_charts(grid: &Grid, edges: EdgeMode) -> RgbImage {
+    let (rose, histogram) = (AspectRose::of(grid, edges).render(), SlopeHistogram::of(grid, edges).render());
+    let mut img = RgbImage::from_pixel(rose.width() + histogram.width(), rose.height().max(histogram.height()), Rgb(BACKGROUND));
+    image::imageops::replace(&mut img, &rose, 0, 0);
+    image::imageops::replace(&mut img, &histogram, rose.width() as i64, ((rose.height() - histogram.height().min(rose.height())) / 2) as i64);
+    img
+}
+
+/// Writes the charts as products "aspect_rose" and "slope_histogram", each as an image and as SVG
+#[cfg(feature = "fs")]
+pub fn save_charts(grid: &Grid, edges: EdgeMode, format: &OutputFormat, target: &OutputTarget) -> io::Result<()> {
+    let rose = AspectRose::of(grid, edges);
+    let mut file = BufWriter::new(File::create(target.path("aspect_rose", "svg")?)?);
+    rose.write_svg(&mut file)?;
+    file.flush()?;
+    save_image(DynamicImage::ImageRgb8(rose.render()), target, "aspect_rose", format)?;
+
+    let histogram = SlopeHistogram::of(grid, edges);
+    let mut file = BufWriter::new(File::create(target.path("slope_histogram", "svg")?)?);
+    histogram.write_svg(&mut file)?;
+    file.flush()?;
+    save_image(DynamicImage::ImageRgb8(histogram.render()), target, "slope_histogram", format)
+}
@@ -0,0 +1,64 @@
+//! Rendering of grids too large to render in one piece, such as country-scale mosaics: the grid is cut into bands of
+//! rows that are rendered on all cores, each with a halo of rows above and below for the kernels reaching across its
+//! border, and handed on from the top down so the image can be encoded row by row.
+
+use std::io;
+use std::thread;
+
+use image::RgbImage;
+
+use crate::grid::Grid;
+use crate::render::{ShadeMode, ShadeParams};
+use crate::shading::ShaderKind;
+
+/// Rows of a band unless told otherwise (--chunk-rows)
+pub const DEFAULT_CHUNK_ROWS: usize = 512;
+
+/// Rows past its band the hillshade under `params` reads: one for the 3x3 kernels, the largest smoothing radius plus
+/// one for multi-scale relief. None for the shaders searching the horizon across the whole grid, which can't be cut.
+pub fn halo(params: &ShadeParams) -> Option<usize> {
+    if matches!(params.shader, ShaderKind::SkyView | ShaderKind::Shadows) {
+        return None;
+    }
+    Some(match params.mode {
+        ShadeMode::MultiScale { levels } if levels > 1 => (1 << (levels - 1)) + 1,
+        _ => 1,
+    })
+}
+
+/// Renders `grid` in bands of `chunk_rows` rows, `threads` bands at a time, and passes the RGB bytes of every band to
+/// `write` from the top down. `render` gets each band with up to `halo` rows of its neighbors above and below, and
+/// returns an image of that size; the halo rows are cut off again, so the seams match a rendering in one piece.
+pub fn render_chunked(
+    grid: &Grid,
+    chunk_rows: usize,
+    halo: usize,
+    threads: usize,
+    render: impl Fn(&Grid) -> RgbImage + Sync,
+    mut write: impl FnMut(&[u8]) -> io::Result<()>,
+) -> io::Result<()> {
+    let (rows, render) = (chunk_rows.max(1), &render);
+    let tops: Vec<usize> = (0..grid.nrows).step_by(rows).collect();
+    for batch in tops.chunks(threads.max(1)) {
+        let bands: Vec<Vec<u8>> = thread::scope(|scope| {
+            let workers: Vec<_> = batch.iter().map(|&top| scope.spawn(move || band(grid, top, rows, halo, render))).collect();
+            workers.into_iter().map(|w| w.join().expect("band renderer panicked")).collect()
+        });
+        for band in bands {
+            write(&band)?;
+        }
+    }
+    Ok(())
+}
+
+/// RGB bytes of the `rows` rows from `top`, rendered with their halo
+fn band(grid: &Grid, top: usize, rows: usize, halo: usize, render: &impl Fn(&Grid) -> RgbImage) -> Vec<u8> {
+    let bottom = (top + rows).min(grid.nrows);
+    let (from, to) = (top.saturating_sub(halo), (bottom + halo).min(grid.nrows));
+    let window = grid.window(0, from, grid.ncols, to - from).expect("a band inside the grid");
+    let row_bytes = grid.ncols * 3;
+    let mut bytes = render(&window).into_raw();
+    bytes.truncate((bottom - from) * row_bytes);
+    bytes.drain(..(top - from) * row_bytes);
+    bytes
+}
@@ -0,0 +1,386 @@
+//! Cloud-Optimized GeoTIFF: 256x256 tiles, overviews halving the size down to a single tile, and every IFD ahead of
+//! the tile data (overviews smallest first, full resolution last), so a client reading the first few kilobytes over
+//! an HTTP range request knows where any tile of any zoom is. Files passing 4 GiB are written as BigTIFFs.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use image::DynamicImage;
+
+use crate::grid::Grid;
+use crate::output::TiffCompression;
+
+/// Edge of the square tiles in pixels
+pub const TILE_SIZE: usize = 256;
+
+/// Samples of one image level, band-interleaved
+#[derive(Clone, Debug)]
+enum Samples {
+    U8(Vec<u8>),
+    F32(Vec<f32>),
+}
+
+/// One level of the pyramid
+#[derive(Clone, Debug)]
+struct Level {
+    width: usize,
+    height: usize,
+    bands: usize,
+    samples: Samples,
+}
+
+impl Level {
+    /// Half the size, every pixel the mean of up to 2x2 pixels; samples equal to `nodata` are left out of the mean
+    fn halved(&self, nodata: Option<f32>) -> Level {
+        let (width, height) = (self.width.div_ceil(2), self.height.div_ceil(2));
+        let sources = |x: usize, y: usize, b: usize| {
+            [(2 * x, 2 * y), (2 * x + 1, 2 * y), (2 * x, 2 * y + 1), (2 * x + 1, 2 * y + 1)].into_iter()
+                .filter(|&(sx, sy)| sx < self.width && sy < self.height)
+                .map(move |(sx, sy)| (sy * self.width + sx) * self.bands + b)
+        };
+        let samples = match &self.samples {
+            Samples::U8(data) => Samples::U8((0..height).flat_map(|y| (0..width).flat_map(move |x| (0..self.bands).map(move |b| (x, y, b))))
+                .map(|(x, y, b)| {
+                    let (sum, count) = sources(x, y, b).fold((0u32, 0u32), |(s, n), i| (s + data[i] as u32, n + 1));
+                    ((sum + count / 2) / count) as u8
+                })
+                .collect()),
+            Samples::F32(data) => Samples::F32((0..height).flat_map(|y| (0..width).flat_map(move |x| (0..self.bands).map(move |b| (x, y, b))))
+                .map(|(x, y, b)| {
+                    let (sum, count) = sources(x, y, b).map(|i| data[i]).filter(|&v| Some(v) != nodata && !v.is_nan())
+                        .fold((0.0, 0), |(s, n), v| (s + v as f64, n + 1));
+                    if count == 0 { nodata.unwrap_or(f32::NAN) } else { (sum / count as f64) as f32 }
+                })
+                .collect()),
+        };
+        Level { width, height, bands: self.bands, samples }
+    }
+
+    /// Bytes of the tile at (tx, ty), padded to a full tile with zeros (or `nodata`) beyond the image
+    fn tile(&self, tx: usize, ty: usize, nodata: Option<f32>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for y in ty * TILE_SIZE..(ty + 1) * TILE_SIZE {
+            for x in tx * TILE_SIZE..(tx + 1) * TILE_SIZE {
+                for b in 0..self.bands {
+                    let inside = x < self.width && y < self.height;
+                    let i = (y * self.width + x) * self.bands + b;
+                    match &self.samples {
+                        Samples::U8(data) => bytes.push(if inside { data[i] } else { 0 }),
+                        Samples::F32(data) => bytes.extend((if inside { data[i] } else { nodata.unwrap_or(0.0) }).to_le_bytes()),
+                    }
+                }
+            }
+        }
+        bytes
+    }
+
+    fn tiles_across(&self) -> (usize, usize) {
+        (self.width.div_ceil(TILE_SIZE), self.height.div_ceil(TILE_SIZE))
+    }
+}
+
+/// Value of a TIFF tag
+enum Value {
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Long8(Vec<u64>), // BigTIFF only
+    Double(Vec<f64>),
+    Ascii(String),
+}
+
+impl Value {
+    /// Field type and count of the entry
+    fn kind(&self) -> (u16, u64) {
+        match self {
+            Value::Short(v) => (3, v.len() as u64),
+            Value::Long(v) => (4, v.len() as u64),
+            Value::Long8(v) => (16, v.len() as u64),
+            Value::Double(v) => (12, v.len() as u64),
+            Value::Ascii(s) => (2, s.len() as u64 + 1),
+        }
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        match self {
+            Value::Short(v) => v.iter().flat_map(|x| x.to_le_bytes()).collect(),
+            Value::Long(v) => v.iter().flat_map(|x| x.to_le_bytes()).collect(),
+            Value::Long8(v) => v.iter().flat_map(|x| x.to_le_bytes()).collect(),
+            Value::Double(v) => v.iter().flat_map(|x| x.to_le_bytes()).collect(),
+            Value::Ascii(s) => s.bytes().chain([0]).collect(),
+        }
+    }
+}
+
+/// Sizes of the parts of a classic TIFF or of a BigTIFF
+struct TiffLayout {
+    big: bool,
+    header: usize,
+    count: usize,  // Entry count of an IFD
+    entry: usize,
+    inline: usize, // Values up to this size sit in their entry, also the size of offsets
+}
+
+impl TiffLayout {
+    const CLASSIC: TiffLayout = TiffLayout { big: false, header: 8, count: 2, entry: 12, inline: 4 };
+    const BIG: TiffLayout = TiffLayout { big: true, header: 16, count: 8, entry: 20, inline: 8 };
+
+    /// Bytes of the entry count and the entries of an IFD of `tags` tags
+    fn entries(&self, tags: usize) -> usize {
+        self.count + self.entry * tags
+    }
+}
+
+/// Compresses one tile, returns the compressed bytes and the TIFF compression code
+pub fn compress(data: &[u8], compression: TiffCompression) -> io::Result<(Vec<u8>, u16)> {
+    Ok(match compression {
+        TiffCompression::None => (data.to_vec(), 1),
+        TiffCompression::Lzw => {
+            let encoded = weezl::encode::Encoder::with_tiff_size_switch(weezl::BitOrder::Msb, 8).encode(data)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            (encoded, 5)
+        }
+        TiffCompression::Deflate => {
+            let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data)?;
+            (encoder.finish()?, 8)
+        }
+        TiffCompression::PackBits => (packbits(data), 32773),
+    })
+}
+
+/// PackBits run-length encoding: runs of 2 to 128 equal bytes as (1 - n, byte), anything else as literals of up to 128
+fn packbits(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let run = data[i..].iter().take(128).take_while(|&&b| b == data[i]).count();
+        if run >= 2 {
+            out.extend([(1 - run as i16) as u8, data[i]]);
+            i += run;
+            continue;
+        }
+        // Literal bytes up to the next run of equal bytes
+        let start = i;
+        while i < data.len() && i - start < 128 && (i + 1 >= data.len() || data[i] != data[i + 1]) {
+            i += 1;
+        }
+        let end = i.max(start + 1);
+        out.push((end - start - 1) as u8);
+        out.extend(&data[start..end]);
+        i = end;
+    }
+    out
+}
+
+/// Writes the levels as a COG: first level at full resolution, georeferenced by `grid` when given. `bigtiff` writes a
+/// BigTIFF even when the file would fit a classic TIFF.
+fn write_cog<W: Write>(first: Level, grid: Option<&Grid>, nodata: Option<f32>, compression: TiffCompression, bigtiff: bool, out: &mut W) -> io::Result<()> {
+    let mut levels = vec![first];
+    while levels.last().is_some_and(|l| l.width > TILE_SIZE || l.height > TILE_SIZE) {
+        let next = levels.last().unwrap().halved(nodata);
+        levels.push(next);
+    }
+
+    // Compressed tiles of every level, row by row
+    let mut tiles = Vec::new();
+    let mut code = 1;
+    for level in &levels {
+        let (across, down) = level.tiles_across();
+        let mut compressed = Vec::new();
+        for ty in 0..down {
+            for tx in 0..across {
+                let (bytes, c) = compress(&level.tile(tx, ty, nodata), compression)?;
+                code = c;
+                compressed.push(bytes);
+            }
+        }
+        tiles.push(compressed);
+    }
+
+    // Tags of every level except the tile offsets, which are only known once the IFDs are laid out
+    let float = matches!(levels[0].samples, Samples::F32(_));
+    let bands = levels[0].bands;
+    let mut ifds: Vec<Vec<(u16, Value)>> = levels.iter().zip(&tiles).enumerate().map(|(i, (level, tiles))| {
+        let mut tags = vec![
+            (254, Value::Long(vec![(i > 0) as u32])), // Reduced-resolution image
+            (256, Value::Long(vec![level.width as u32])),
+            (257, Value::Long(vec![level.height as u32])),
+            (258, Value::Short(vec![if float { 32 } else { 8 }; bands])),
+            (259, Value::Short(vec![code])),
+            (262, Value::Short(vec![if bands == 3 { 2 } else { 1 }])), // RGB or black is zero
+            (277, Value::Short(vec![bands as u16])),
+            (284, Value::Short(vec![1])), // Interleaved
+            (322, Value::Short(vec![TILE_SIZE as u16])),
+            (323, Value::Short(vec![TILE_SIZE as u16])),
+            (324, Value::Long(vec![0; tiles.len()])),
+            (325, Value::Long(tiles.iter().map(|t| t.len() as u32).collect())),
+            (339, Value::Short(vec![if float { 3 } else { 1 }; bands])),
+        ];
+        if let (0, Some(grid)) = (i, grid) {
+            let (x0, y0) = grid.origin();
+            tags.push((33550, Value::Double(vec![grid.cellsize, grid.cellsize, 0.0])));
+            tags.push((33922, Value::Double(vec![0.0, 0.0, 0.0, x0, y0, 0.0])));
+            // GeoKey directory: version 1.1.0 with 2 keys, projected model (1024 = 1) and pixels as areas (1025 = 1)
+            tags.push((34735, Value::Short(vec![1, 1, 0, 2, 1024, 0, 1, 1, 1025, 0, 1, 1])));
+        }
+        if let Some(nodata) = nodata {
+            tags.push((42113, Value::Ascii(nodata.to_string())));
+        }
+        tags
+    }).collect();
+
+    // IFDs right after the header, each followed by its values that don't fit into the entry, then the tile data
+    // smallest level first. A file passing 4 GiB becomes a BigTIFF, with 64-bit counts and offsets.
+    let layout = |ifds: &[Vec<(u16, Value)>], format: &TiffLayout| {
+        let mut ifd_offsets = Vec::new();
+        let mut offset = format.header;
+        for tags in ifds {
+            ifd_offsets.push(offset);
+            let values: usize = tags.iter().map(|(_, v)| v.bytes().len()).filter(|&n| n > format.inline).map(|n| n + n % 2).sum();
+            offset += format.entries(tags.len()) + format.inline + values;
+        }
+        let mut tile_offsets = vec![Vec::new(); levels.len()];
+        for (level, tiles) in tiles.iter().enumerate().rev() {
+            for tile in tiles {
+                tile_offsets[level].push(offset as u64);
+                offset += tile.len();
+            }
+        }
+        (ifd_offsets, tile_offsets, offset)
+    };
+    let mut format = TiffLayout::CLASSIC;
+    let (mut ifd_offsets, mut tile_offsets, end) = layout(&ifds, &format);
+    if bigtiff || end > u32::MAX as usize {
+        format = TiffLayout::BIG;
+        for tags in &mut ifds {
+            let offsets = tags.iter_mut().find(|(tag, _)| *tag == 324).unwrap();
+            offsets.1 = Value::Long8(vec![0; offsets.1.kind().1 as usize]);
+        }
+        (ifd_offsets, tile_offsets, _) = layout(&ifds, &format);
+    }
+
+    let word = |v: usize, out: &mut W| if format.big { out.write_all(&(v as u64).to_le_bytes()) } else { out.write_all(&(v as u32).to_le_bytes()) };
+    if format.big {
+        out.write_all(b"II+\0")?;
+        out.write_all(&[8, 0, 0, 0])?; // Offsets of 8 bytes
+    } else {
+        out.write_all(b"II*\0")?;
+    }
+    word(format.header, out)?;
+    for (i, mut tags) in ifds.into_iter().enumerate() {
+        let offsets = std::mem::take(&mut tile_offsets[i]);
+        tags.iter_mut().find(|(tag, _)| *tag == 324).unwrap().1 = if format.big { Value::Long8(offsets) } else { Value::Long(offsets.into_iter().map(|o| o as u32).collect()) };
+        let mut values_offset = ifd_offsets[i] + format.entries(tags.len()) + format.inline;
+        let mut values = Vec::new();
+        if format.big {
+            out.write_all(&(tags.len() as u64).to_le_bytes())?;
+        } else {
+            out.write_all(&(tags.len() as u16).to_le_bytes())?;
+        }
+        for (tag, value) in &tags {
+            let (kind, count) = value.kind();
+            let mut bytes = value.bytes();
+            out.write_all(&tag.to_le_bytes())?;
+            out.write_all(&kind.to_le_bytes())?;
+            word(count as usize, out)?;
+            if bytes.len() <= format.inline {
+                bytes.resize(format.inline, 0);
+                out.write_all(&bytes)?;
+            } else {
+                word(values_offset, out)?;
+                if bytes.len() % 2 == 1 {
+                    bytes.push(0); // Values start on a word boundary
+                }
+                values_offset += bytes.len();
+                values.extend(bytes);
+            }
+        }
+        word(ifd_offsets.get(i + 1).copied().unwrap_or(0), out)?;
+        out.write_all(&values)?;
+    }
+    for tiles in tiles.iter().rev() {
+        for tile in tiles {
+            out.write_all(tile)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the grid values as a Float32 COG with deflate-compressed tiles, georeferenced by the ASC header like
+/// [`crate::output::save_float_tiff`]. NoData stays out of the overview means.
+pub fn save_float_cog(grid: &Grid, path: &Path) -> io::Result<()> {
+    let level = Level { width: grid.ncols, height: grid.nrows, bands: 1, samples: Samples::F32(grid.data.iter().flatten().copied().collect()) };
+    let mut file = BufWriter::new(File::create(path)?);
+    write_cog(level, Some(grid), Some(grid.nodata), TiffCompression::Deflate, false, &mut file)?;
+    file.flush()
+}
+
+/// Encodes a grayscale or RGB image as a COG with the selected tile compression
+pub fn write_image_cog<W: Write>(img: &DynamicImage, compression: TiffCompression, out: &mut W) -> io::Result<()> {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let level = match img {
+        DynamicImage::ImageLuma8(gray) => Level { width, height, bands: 1, samples: Samples::U8(gray.as_raw().clone()) },
+        other => Level { width, height, bands: 3, samples: Samples::U8(other.to_rgb8().into_raw()) },
+    };
+    write_cog(level, None, None, compression, false, out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use tiff::decoder::{Decoder, DecodingResult};
+
+    use super::*;
+
+    /// A float level with overviews, forced into the BigTIFF layout, read back by the tiff crate
+    #[test]
+    fn bigtiff_cog_reads_back() {
+        let (width, height) = (TILE_SIZE + 44, TILE_SIZE / 2 + 3);
+        let values: Vec<f32> = (0..width * height).map(|i| (i % 977) as f32 * 0.5 - 100.0).collect();
+        let level = Level { width, height, bands: 1, samples: Samples::F32(values.clone()) };
+        let mut bytes = Vec::new();
+        write_cog(level, None, Some(-9999.0), TiffCompression::Deflate, true, &mut bytes).unwrap();
+        assert_eq!(&bytes[..4], b"II+\0");
+
+        let mut decoder = Decoder::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (width as u32, height as u32));
+        match decoder.read_image().unwrap() {
+            DecodingResult::F32(decoded) => assert_eq!(decoded, values),
+            _ => panic!("expected Float32 samples"),
+        }
+        assert!(decoder.more_images());
+        decoder.next_image().unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (width.div_ceil(2) as u32, height.div_ceil(2) as u32));
+    }
+
+    /// An RGB image with LZW tiles in the BigTIFF layout
+    #[test]
+    fn bigtiff_rgb_cog_reads_back() {
+        let (width, height) = (70, 40);
+        let pixels: Vec<u8> = (0..width * height * 3).map(|i| (i * 7 % 251) as u8).collect();
+        let level = Level { width, height, bands: 3, samples: Samples::U8(pixels.clone()) };
+        let mut bytes = Vec::new();
+        write_cog(level, None, None, TiffCompression::Lzw, true, &mut bytes).unwrap();
+
+        let mut decoder = Decoder::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (width as u32, height as u32));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(decoded) => assert_eq!(decoded, pixels),
+            _ => panic!("expected 8-bit samples"),
+        }
+        assert!(!decoder.more_images());
+    }
+
+    /// Small files stay classic TIFFs unless forced
+    #[test]
+    fn small_cog_is_classic() {
+        let level = Level { width: 10, height: 10, bands: 1, samples: Samples::U8(vec![7; 100]) };
+        let mut bytes = Vec::new();
+        write_cog(level, None, None, TiffCompression::PackBits, false, &mut bytes).unwrap();
+        assert_eq!(&bytes[..4], b"II*\0");
+        let mut decoder = Decoder::new(Cursor::new(bytes)).unwrap();
+        assert!(matches!(decoder.read_image().unwrap(), DecodingResult::U8(decoded) if decoded == vec![7; 100]));
+    }
+}
@@ -0,0 +1,353 @@
+//! Contour lines traced with marching squares and written as SVG for map finishing in Illustrator or Inkscape:
+//! regular and index contours in groups of their own, optionally smoothed into Bézier curves, and elevation labels
+//! along the index contours.
+//!
+//! The drawing is in cell units, (0, 0) the north-west corner of the grid, so it overlays the images of the batch run.
+
+use std::collections::HashMap;
+#[cfg(feature = "fs")]
+use std::io::{self, Write};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+use crate::grid::Grid;
+#[cfg(feature = "fs")]
+use crate::shapefile::{Field, FieldType, ShapeType, Shapefile, Value};
+
+/// Contour levels aimed at when no interval is given
+const DEFAULT_LEVELS: f64 = 20.0;
+
+/// Line widths and label size in pixels of the drawing
+#[cfg(feature = "fs")]
+const STROKE: f64 = 0.6;
+#[cfg(feature = "fs")]
+const INDEX_STROKE: f64 = 1.2;
+#[cfg(feature = "fs")]
+const FONT_SIZE: f64 = 11.0;
+
+/// Labels along an index contour, one per this share of the longer side of the grid
+#[cfg(feature = "fs")]
+const LABEL_SPACING: f64 = 1.0 / 3.0;
+
+/// Which contours are traced and how they are drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ContourSettings {
+    pub interval: Option<f64>, // Elevation between two contours (--interval), a round value giving about 20 when None
+    pub index_every: usize,    // Every n-th contour is an index contour (--index), counted from elevation 0
+    pub smooth: bool,          // Bézier curves through the traced points (--smooth)
+    pub labels: bool,          // Elevations along the index contours (--labels)
+    pub width: u32,            // Width of the drawing in pixels (--width)
+}
+
+impl Default for ContourSettings {
+    fn default() -> Self {
+        ContourSettings { interval: None, index_every: 5, smooth: false, labels: false, width: 1200 }
+    }
+}
+
+/// One contour line in cell units
+#[derive(Clone, Debug, PartialEq)]
+pub struct Contour {
+    pub level: f64,
+    pub index: bool,
+    pub closed: bool, // A ring, its last point joining the first
+    pub points: Vec<[f64; 2]>,
+}
+
+/// The contours of a grid and the size of their drawing
+#[derive(Clone, Debug, PartialEq)]
+pub struct Contours {
+    pub interval: f64,
+    pub width: u32,
+    pub height: u32,
+    pub ncols: usize,
+    pub nrows: usize,
+    pub smooth: bool,
+    pub labels: bool,
+    pub lines: Vec<Contour>,
+}
+
+/// A round interval (1, 2, 2.5 or 5 times a power of ten) giving about `levels` contours over `range`
+pub fn round_interval(range: f64, levels: f64) -> f64 {
+    let raw = (range / levels).max(f64::EPSILON);
+    let power = 10f64.powf(raw.log10().floor());
+    [1.0, 2.0, 2.5, 5.0, 10.0].into_iter().map(|m| m * power).find(|&step| step >= raw).unwrap_or(10.0 * power)
+}
+
+/// Edge of the lattice of cell centers crossed by a contour: horizontal from (x, y) to (x + 1, y) or vertical from
+/// (x, y) to (x, y + 1)
+type Edge = (bool, usize, usize);
+
+/// The contour lines at `level`, through the cell centers; squares with a NoData corner are left out
+pub fn trace(grid: &Grid, level: f64) -> Vec<(Vec<[f64; 2]>, bool)> {
+    let value = |x: usize, y: usize| Some(grid.data[y][x]).filter(|&v| !grid.is_nodata(v)).map(f64::from);
+    let mut crossings: HashMap<Edge, [f64; 2]> = HashMap::new();
+    let mut segments: Vec<[Edge; 2]> = Vec::new();
+    for y in 0..grid.nrows.saturating_sub(1) {
+        for x in 0..grid.ncols.saturating_sub(1) {
+            let (Some(tl), Some(tr), Some(br), Some(bl)) = (value(x, y), value(x + 1, y), value(x + 1, y + 1), value(x, y + 1)) else { continue };
+            // Sides of the square clockwise from the top, with the values at their ends and their start corner
+            let sides = [
+                ((true, x, y), tl, tr, [x, y], [1.0, 0.0]),
+                ((false, x + 1, y), tr, br, [x + 1, y], [0.0, 1.0]),
+                ((true, x, y + 1), bl, br, [x, y + 1], [1.0, 0.0]),
+                ((false, x, y), tl, bl, [x, y], [0.0, 1.0]),
+            ];
+            let crossed: Vec<Edge> = sides.iter().filter(|s| (s.1 >= level) != (s.2 >= level)).map(|&(edge, a, b, [cx, cy], [dx, dy])| {
+                let t = (level - a) / (b - a);
+                crossings.entry(edge).or_insert([cx as f64 + 0.5 + dx * t, cy as f64 + 0.5 + dy * t]);
+                edge
+            }).collect();
+            match crossed[..] {
+                [a, b] => segments.push([a, b]),
+                [top, right, bottom, left] => {
+                    // Saddle: the center decides whether the top left corner joins the bottom right one
+                    let center = (tl + tr + br + bl) / 4.0;
+                    if (center >= level) == (tl >= level) {
+                        segments.extend([[top, right], [bottom, left]]);
+                    } else {
+                        segments.extend([[top, left], [right, bottom]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Joins the segments at their shared edges into lines, open ones from their loose ends first, then the rings
+    let mut at: HashMap<Edge, Vec<usize>> = HashMap::new();
+    for (i, segment) in segments.iter().enumerate() {
+        segment.iter().for_each(|&edge| at.entry(edge).or_default().push(i));
+    }
+    let mut used = vec![false; segments.len()];
+    let loose = |edge: &Edge| at[edge].len() == 1;
+    let starts: Vec<(usize, Edge)> = segments.iter().enumerate()
+        .flat_map(|(i, s)| s.iter().filter(|e| loose(e)).map(move |&e| (i, e)))
+        .chain(segments.iter().enumerate().map(|(i, s)| (i, s[0])))
+        .collect();
+    let mut lines = Vec::new();
+    for (first, start) in starts {
+        if used[first] {
+            continue;
+        }
+        let mut line = vec![crossings[&start]];
+        let (mut segment, mut edge) = (first, start);
+        loop {
+            used[segment] = true;
+            edge = if segments[segment][0] == edge { segments[segment][1] } else { segments[segment][0] };
+            line.push(crossings[&edge]);
+            match at[&edge].iter().find(|&&s| !used[s]) {
+                Some(&next) => segment = next,
+                None => break,
+            }
+        }
+        let closed = edge == start && line.len() > 2;
+        if closed {
+            line.pop();
+        }
+        lines.push((line, closed));
+    }
+    lines
+}
+
+/// Map coordinates of a point in cell units
+pub fn map_point(grid: &Grid, [x, y]: [f64; 2]) -> [f64; 2] {
+    let (x0, y0) = grid.origin();
+    [x0 + x * grid.cellsize, y0 - y * grid.cellsize]
+}
+
+/// The areas at or above `level` as polygons in cell units, each its outer ring and then its holes; NoData and the
+/// outside of the grid count as `floor`, below the level. Rings are closed, their last point repeating the first.
+pub fn polygons(grid: &Grid, level: f64, floor: f32) -> Vec<Vec<Vec<[f64; 2]>>> {
+    // A border below the level around the grid closes every line into a ring
+    let below = if (floor as f64) < level { floor } else { level as f32 - 1.0 };
+    let mut padded = Grid { data: vec![vec![below; grid.ncols + 2]; grid.nrows + 2], ncols: grid.ncols + 2, nrows: grid.nrows + 2,
+        nodata: f32::NAN, cellsize: grid.cellsize, xllcorner: grid.xllcorner, yllcorner: grid.yllcorner, geographic: grid.geographic };
+    for (x, y, val) in grid.iter_cells() {
+        padded.data[y + 1][x + 1] = if grid.is_nodata(val) { below } else { val };
+    }
+    let rings: Vec<Vec<[f64; 2]>> = trace(&padded, level).into_iter()
+        .map(|(mut ring, _)| {
+            ring.iter_mut().for_each(|p| *p = [p[0] - 1.0, p[1] - 1.0]);
+            ring.push(ring[0]);
+            ring
+        })
+        .collect();
+
+    // Rings inside an even number of others are outer rings, the others holes of the innermost ring around them
+    let boxes: Vec<[f64; 4]> = rings.iter().map(|ring| ring.iter().fold([f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY],
+        |b, p| [b[0].min(p[0]), b[1].min(p[1]), b[2].max(p[0]), b[3].max(p[1])])).collect();
+    let inside = |p: [f64; 2], ring: &[[f64; 2]]| ring.windows(2)
+        .filter(|w| (w[0][1] > p[1]) != (w[1][1] > p[1]) && p[0] < w[0][0] + (p[1] - w[0][1]) / (w[1][1] - w[0][1]) * (w[1][0] - w[0][0]))
+        .count() % 2 == 1;
+    let around: Vec<Vec<usize>> = rings.iter().enumerate().map(|(i, ring)| {
+        let p = ring[0];
+        (0..rings.len()).filter(|&j| {
+            let b = boxes[j];
+            j != i && p[0] >= b[0] && p[0] <= b[2] && p[1] >= b[1] && p[1] <= b[3] && inside(p, &rings[j])
+        }).collect()
+    }).collect();
+    let mut polygons: Vec<Vec<Vec<[f64; 2]>>> = Vec::new();
+    let mut outer_of = vec![None; rings.len()];
+    for (i, ring) in rings.iter().enumerate() {
+        if around[i].len().is_multiple_of(2) {
+            outer_of[i] = Some(polygons.len());
+            polygons.push(vec![ring.clone()]);
+        }
+    }
+    for (i, ring) in rings.iter().enumerate() {
+        if around[i].len() % 2 == 1 {
+            let parent = around[i].iter().copied().max_by_key(|&j| around[j].len()).and_then(|j| outer_of[j]);
+            if let Some(parent) = parent {
+                polygons[parent].push(ring.clone());
+            }
+        }
+    }
+    polygons
+}
+
+/// Twice the signed area of a closed ring, positive when counterclockwise with y up
+pub fn ring_area(ring: &[[f64; 2]]) -> f64 {
+    ring.windows(2).map(|w| w[0][0] * w[1][1] - w[1][0] * w[0][1]).sum()
+}
+
+impl Contours {
+    /// Traces every contour between the lowest and the highest cell
+    pub fn new(grid: &Grid, settings: &ContourSettings) -> Result<Contours, String> {
+        if settings.interval.is_some_and(|i| i.is_nan() || i <= 0.0) || settings.index_every == 0 || settings.width < 16 {
+            return Err("Contours need an interval above 0, index contours every one or more lines and a width of 16 pixels".into());
+        }
+        let (min, max) = grid.min_max();
+        let (min, max) = (min as f64, max as f64);
+        let interval = settings.interval.unwrap_or_else(|| round_interval(max - min, DEFAULT_LEVELS));
+        if (max - min) / interval > 10_000.0 {
+            return Err(format!("An interval of {} makes over 10000 contours between {} and {}", interval, min, max));
+        }
+        let mut lines = Vec::new();
+        let mut step = (min / interval).ceil() as i64;
+        while step as f64 * interval <= max {
+            let level = step as f64 * interval;
+            let index = step.rem_euclid(settings.index_every as i64) == 0;
+            lines.extend(trace(grid, level).into_iter().map(|(points, closed)| Contour { level, index, closed, points }));
+            step += 1;
+        }
+        let height = (settings.width as f64 * grid.nrows as f64 / grid.ncols.max(1) as f64).round().max(1.0) as u32;
+        Ok(Contours { interval, width: settings.width, height, ncols: grid.ncols, nrows: grid.nrows, smooth: settings.smooth, labels: settings.labels, lines })
+    }
+
+    /// Path data of a line, through its points or along Catmull-Rom curves as cubic Béziers
+    #[cfg(feature = "fs")]
+    fn path(&self, contour: &Contour) -> String {
+        let p = &contour.points;
+        let n = p.len();
+        let mut d = format!("M{:.2},{:.2}", p[0][0], p[0][1]);
+        if !self.smooth || n < 3 {
+            p[1..].iter().for_each(|[x, y]| d += &format!(" L{:.2},{:.2}", x, y));
+        } else {
+            // Neighbors wrap around rings and repeat the ends of open lines
+            let at = |i: isize| if contour.closed { p[i.rem_euclid(n as isize) as usize] } else { p[i.clamp(0, n as isize - 1) as usize] };
+            let segments = if contour.closed { n } else { n - 1 };
+            for i in 0..segments as isize {
+                let (p0, p1, p2, p3) = (at(i - 1), at(i), at(i + 1), at(i + 2));
+                let c1 = [p1[0] + (p2[0] - p0[0]) / 6.0, p1[1] + (p2[1] - p0[1]) / 6.0];
+                let c2 = [p2[0] - (p3[0] - p1[0]) / 6.0, p2[1] - (p3[1] - p1[1]) / 6.0];
+                d += &format!(" C{:.2},{:.2} {:.2},{:.2} {:.2},{:.2}", c1[0], c1[1], c2[0], c2[1], p2[0], p2[1]);
+            }
+        }
+        if contour.closed {
+            d += " Z";
+        }
+        d
+    }
+
+    /// Places of the labels of a line and their angles in degrees, kept upright, evenly along lines long enough
+    #[cfg(feature = "fs")]
+    fn label_places(&self, contour: &Contour, spacing: f64, label_length: f64) -> Vec<([f64; 2], f64)> {
+        let mut points = contour.points.clone();
+        if contour.closed {
+            points.push(points[0]);
+        }
+        let length: f64 = points.windows(2).map(|w| (w[1][0] - w[0][0]).hypot(w[1][1] - w[0][1])).sum();
+        if length < 2.0 * label_length {
+            return Vec::new();
+        }
+        let count = (length / spacing).floor().max(1.0);
+        let mut targets = (0..count as usize).map(|i| length * (i as f64 + 0.5) / count).peekable();
+        let mut places = Vec::new();
+        let mut walked = 0.0;
+        for w in points.windows(2) {
+            let (dx, dy) = (w[1][0] - w[0][0], w[1][1] - w[0][1]);
+            let len = dx.hypot(dy);
+            while let Some(&target) = targets.peek().filter(|&&t| t <= walked + len) {
+                let t = (target - walked) / len.max(f64::EPSILON);
+                let mut angle = dy.atan2(dx).to_degrees();
+                if angle > 90.0 {
+                    angle -= 180.0;
+                } else if angle < -90.0 {
+                    angle += 180.0;
+                }
+                places.push(([w[0][0] + dx * t, w[0][1] + dy * t], angle));
+                targets.next();
+            }
+            walked += len;
+        }
+        places
+    }
+
+    /// The drawing as SVG: groups "contours", "index-contours" and "labels", the labels with a white halo
+    #[cfg(feature = "fs")]
+    pub fn write_svg<W: Write>(&self, mut out: W) -> io::Result<()> {
+        // Cell units per pixel of the drawing
+        let unit = self.ncols as f64 / self.width as f64;
+        writeln!(out, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}">"#, self.width, self.height, self.ncols, self.nrows)?;
+        writeln!(out, "<!-- Contours every {} elevation units -->", self.interval)?;
+        for (id, index, stroke) in [("contours", false, STROKE), ("index-contours", true, INDEX_STROKE)] {
+            writeln!(out, r##"<g id="{}" fill="none" stroke="#8a5a2b" stroke-width="{:.3}" stroke-linejoin="round">"##, id, stroke * unit)?;
+            for contour in self.lines.iter().filter(|c| c.index == index) {
+                writeln!(out, r#"<path d="{}" data-elevation="{}"/>"#, self.path(contour), elevation_text(contour.level))?;
+            }
+            writeln!(out, "</g>")?;
+        }
+        if self.labels {
+            let font = FONT_SIZE * unit;
+            writeln!(out, r##"<g id="labels" font-family="sans-serif" font-size="{:.3}" fill="#8a5a2b" stroke="#ffffff" stroke-width="{:.3}" paint-order="stroke" text-anchor="middle" dominant-baseline="central">"##,
+                font, font / 3.0)?;
+            let spacing = self.ncols.max(self.nrows) as f64 * LABEL_SPACING;
+            for contour in self.lines.iter().filter(|c| c.index) {
+                let text = elevation_text(contour.level);
+                for ([x, y], angle) in self.label_places(contour, spacing, text.len() as f64 * font * 0.6) {
+                    writeln!(out, r#"<text x="{:.2}" y="{:.2}" transform="rotate({:.1} {:.2} {:.2})">{}</text>"#, x, y, angle, x, y, text)?;
+                }
+            }
+            writeln!(out, "</g>")?;
+        }
+        writeln!(out, "</svg>")
+    }
+
+    /// Writes the lines in the map coordinates of `grid` as a shapefile of PolyLines with their elevation (ELEV) and
+    /// 1 on index contours (INDEX), rings closed by repeating their first point
+    #[cfg(feature = "fs")]
+    pub fn save_shapefile(&self, grid: &Grid, path: &Path) -> io::Result<()> {
+        let fields = vec![
+            Field { name: "ELEV", kind: FieldType::Number { width: 16, decimals: 3 } },
+            Field { name: "INDEX", kind: FieldType::Number { width: 1, decimals: 0 } },
+        ];
+        let mut shapefile = Shapefile::new(ShapeType::PolyLine, fields);
+        for contour in &self.lines {
+            let ring = contour.closed.then_some(contour.points[0]);
+            let points = contour.points.iter().chain(&ring).map(|&p| {
+                let [x, y] = map_point(grid, p);
+                [x, y, contour.level]
+            }).collect();
+            shapefile.add(vec![points], vec![Value::Number(contour.level), Value::Number(contour.index as u8 as f64)]);
+        }
+        shapefile.save(path)
+    }
+}
+
+/// An elevation without trailing zeros, "1250" or "12.5"
+#[cfg(feature = "fs")]
+fn elevation_text(level: f64) -> String {
+    let text = format!("{:.3}", level);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
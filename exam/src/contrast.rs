@@ -0,0 +1,143 @@
+//! Contrast stretches of the grayscale product beyond the linear min/max mapping: global histogram
+//! equalization and CLAHE, which spread the gray levels where most cells are, e.g. over flat floodplains.
+
+use image::{GrayImage, Luma};
+
+use crate::grid::Grid;
+use crate::render::render_grayscale_range;
+
+/// Elevation classes of the histograms, finer than the 256 gray levels so low relief is not flattened before equalizing
+const BINS: usize = 1024;
+
+/// Tiles along each axis of CLAHE
+pub const CLAHE_TILES: usize = 8;
+
+/// Maximum height of a CLAHE histogram bin as a multiple of the mean bin height, limits the noise amplification
+pub const CLAHE_CLIP: f64 = 3.0;
+
+/// How elevations become gray values (--stretch)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "fs", derive(serde::Serialize), serde(rename_all = "lowercase"))]
+pub enum Stretch {
+    #[default]
+    Linear,   // Min to max as black to white
+    Equalize, // Every gray level covers about the same number of cells
+    Clahe,    // Contrast-limited adaptive equalization, per tile and blended between tiles
+}
+
+impl Stretch {
+    pub fn parse(name: &str) -> Option<Stretch> {
+        match name.to_lowercase().as_str() {
+            "linear" => Some(Stretch::Linear),
+            "equalize" => Some(Stretch::Equalize),
+            "clahe" => Some(Stretch::Clahe),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stretch::Linear => "linear",
+            Stretch::Equalize => "equalize",
+            Stretch::Clahe => "clahe",
+        }
+    }
+}
+
+/// Renders the elevation within `range` as gray values using `stretch`, NoData is black
+pub fn render_grayscale_stretched(grid: &Grid, range: (f32, f32), stretch: Stretch) -> GrayImage {
+    match stretch {
+        Stretch::Linear => render_grayscale_range(grid, range),
+        Stretch::Equalize => equalize(grid, range),
+        Stretch::Clahe => clahe(grid, range),
+    }
+}
+
+/// Histogram bin of every cell, None on NoData
+fn bins(grid: &Grid, (min, max): (f32, f32)) -> Vec<Vec<Option<usize>>> {
+    grid.data.iter()
+        .map(|row| row.iter()
+            .map(|&val| (!grid.is_nodata(val)).then(|| (((val - min) / (max - min)).clamp(0.0, 1.0) * (BINS - 1) as f32) as usize))
+            .collect())
+        .collect()
+}
+
+/// Gray value of every bin from the cumulative histogram, the lowest occupied bin maps to 0
+fn lookup(histogram: &[u32]) -> Vec<f32> {
+    let total: u32 = histogram.iter().sum();
+    let first = histogram.iter().copied().find(|&n| n > 0).unwrap_or(0);
+    let mut cumulative = 0;
+    histogram.iter()
+        .map(|&n| {
+            cumulative += n;
+            if total > first { (cumulative.saturating_sub(first)) as f32 / (total - first) as f32 * 255.0 } else { 0.0 }
+        })
+        .collect()
+}
+
+fn equalize(grid: &Grid, range: (f32, f32)) -> GrayImage {
+    let bins = bins(grid, range);
+    let mut histogram = vec![0u32; BINS];
+    for bin in bins.iter().flatten().flatten() {
+        histogram[*bin] += 1;
+    }
+    let lut = lookup(&histogram);
+
+    let mut img = GrayImage::new(grid.ncols as u32, grid.nrows as u32);
+    for (y, row) in bins.iter().enumerate() {
+        for (x, bin) in row.iter().enumerate() {
+            img.put_pixel(x as u32, y as u32, Luma([bin.map_or(0, |b| lut[b].round() as u8)]));
+        }
+    }
+    img
+}
+
+/// Equalizes each of [`CLAHE_TILES`] x [`CLAHE_TILES`] tiles with its histogram clipped at [`CLAHE_CLIP`],
+/// the clipped excess spread over all bins. Cells interpolate bilinearly between the mappings of the four
+/// nearest tile centers, so no tile edges show.
+fn clahe(grid: &Grid, range: (f32, f32)) -> GrayImage {
+    let bins = bins(grid, range);
+    let tiles_x = CLAHE_TILES.min(grid.ncols.max(1));
+    let tiles_y = CLAHE_TILES.min(grid.nrows.max(1));
+    let (tile_w, tile_h) = (grid.ncols.div_ceil(tiles_x), grid.nrows.div_ceil(tiles_y));
+
+    let mut luts = Vec::with_capacity(tiles_x * tiles_y);
+    for ty in 0..tiles_y {
+        for tx in 0..tiles_x {
+            let mut histogram = vec![0u32; BINS];
+            for row in bins.iter().skip(ty * tile_h).take(tile_h) {
+                for bin in row.iter().skip(tx * tile_w).take(tile_w).flatten() {
+                    histogram[*bin] += 1;
+                }
+            }
+            let total: u32 = histogram.iter().sum();
+            let limit = ((CLAHE_CLIP * total as f64 / BINS as f64).ceil() as u32).max(1);
+            let excess: u32 = histogram.iter().map(|&n| n.saturating_sub(limit)).sum();
+            let (share, rest) = (excess / BINS as u32, excess as usize % BINS);
+            for (i, n) in histogram.iter_mut().enumerate() {
+                *n = (*n).min(limit) + share + (i < rest) as u32;
+            }
+            luts.push(lookup(&histogram));
+        }
+    }
+
+    // Position of a cell between tile centers: the lower tile and the weight of the upper one
+    let between = |pos: usize, size: usize, count: usize| {
+        let t = ((pos as f32 + 0.5) / size as f32 - 0.5).clamp(0.0, (count - 1) as f32);
+        let lower = (t as usize).min(count.saturating_sub(2));
+        (lower, (lower + 1).min(count - 1), t - lower as f32)
+    };
+    let mut img = GrayImage::new(grid.ncols as u32, grid.nrows as u32);
+    for (y, row) in bins.iter().enumerate() {
+        let (y0, y1, fy) = between(y, tile_h, tiles_y);
+        for (x, bin) in row.iter().enumerate() {
+            let Some(bin) = *bin else { continue };
+            let (x0, x1, fx) = between(x, tile_w, tiles_x);
+            let at = |tx: usize, ty: usize| luts[ty * tiles_x + tx][bin];
+            let top = at(x0, y0) + (at(x1, y0) - at(x0, y0)) * fx;
+            let bottom = at(x0, y1) + (at(x1, y1) - at(x0, y1)) * fx;
+            img.put_pixel(x as u32, y as u32, Luma([(top + (bottom - top) * fy).round() as u8]));
+        }
+    }
+    img
+}
@@ -0,0 +1,265 @@
+//! Least-cost routes over the terrain: A* across the 8-connected cells with the walking time of a
+//! slope-dependent speed as the cost, e.g. Tobler's hiking function.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+#[cfg(feature = "fs")]
+use std::io::{self, Write};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+use image::{Rgb, RgbImage};
+
+use crate::grid::Grid;
+#[cfg(feature = "fs")]
+use crate::shapefile::{Field, FieldType, ShapeType, Shapefile, Value};
+
+const ROUTE_COLOR: Rgb<u8> = Rgb([220, 20, 60]);
+const START_COLOR: Rgb<u8> = Rgb([30, 200, 60]);
+const END_COLOR: Rgb<u8> = Rgb([250, 250, 250]);
+
+/// Walking speed as a function of the slope (rise over run, uphill positive) of a step (--cost)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CostFunction {
+    #[default]
+    Tobler,        // 6 km/h * e^(-3.5 |slope + 0.05|), fastest slightly downhill
+    ToblerOffPath, // Tobler at 3/5 of the speed, for cross-country travel
+    Flat,          // A constant 5 km/h, the route with the shortest ground distance
+}
+
+impl CostFunction {
+    pub fn parse(name: &str) -> Option<CostFunction> {
+        match name.to_lowercase().as_str() {
+            "tobler" => Some(CostFunction::Tobler),
+            "tobler-offpath" => Some(CostFunction::ToblerOffPath),
+            "flat" => Some(CostFunction::Flat),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CostFunction::Tobler => "tobler",
+            CostFunction::ToblerOffPath => "tobler-offpath",
+            CostFunction::Flat => "flat",
+        }
+    }
+
+    /// Speed in meters per second
+    pub fn speed(&self, slope: f64) -> f64 {
+        let tobler = 6.0 / 3.6 * (-3.5 * (slope + 0.05).abs()).exp();
+        match self {
+            CostFunction::Tobler => tobler,
+            CostFunction::ToblerOffPath => 0.6 * tobler,
+            CostFunction::Flat => 5.0 / 3.6,
+        }
+    }
+
+    /// Highest speed on any slope, which keeps the A* estimate of the remaining time a lower bound
+    fn max_speed(&self) -> f64 {
+        match self {
+            CostFunction::Tobler | CostFunction::ToblerOffPath => self.speed(-0.05),
+            CostFunction::Flat => self.speed(0.0),
+        }
+    }
+}
+
+/// Constraints of the route search
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RouteSettings {
+    pub cost: CostFunction,
+    pub max_slope: Option<f64>, // Steps steeper than this many degrees are impassable (--max-slope)
+}
+
+/// The fastest route between two cells
+#[derive(Clone, Debug)]
+pub struct CostPath {
+    pub cells: Vec<(usize, usize)>, // Column and row of every cell from the start to the end
+    pub seconds: f64,               // Travel time
+    pub length: f64,                // Distance along the surface in meters
+    pub ascent: f64,                // Total climb and descent in meters
+    pub descent: f64,
+}
+
+impl CostPath {
+    /// Map coordinates and elevation of the cell centers along the route
+    pub fn points(&self, grid: &Grid) -> Vec<[f64; 3]> {
+        let (x0, y0) = grid.origin();
+        self.cells.iter()
+            .map(|&(c, r)| [x0 + (c as f64 + 0.5) * grid.cellsize, y0 - (r as f64 + 0.5) * grid.cellsize, grid.data[r][c] as f64])
+            .collect()
+    }
+
+    /// Draws the route over an image of the grid, the start in green and the end in white
+    pub fn draw(&self, img: &mut RgbImage) {
+        let mut dot = |(c, r): (usize, usize), radius: i64, color| {
+            for y in r as i64 - radius..=r as i64 + radius {
+                for x in c as i64 - radius..=c as i64 + radius {
+                    if x >= 0 && y >= 0 && (x as u32) < img.width() && (y as u32) < img.height() {
+                        img.put_pixel(x as u32, y as u32, color);
+                    }
+                }
+            }
+        };
+        for &cell in &self.cells {
+            dot(cell, 1, ROUTE_COLOR);
+        }
+        if let (Some(&first), Some(&last)) = (self.cells.first(), self.cells.last()) {
+            dot(first, 3, START_COLOR);
+            dot(last, 3, END_COLOR);
+        }
+    }
+}
+
+/// Cell of the grid containing map coordinates (x, y), None outside the grid or on NoData
+pub fn cell_at(grid: &Grid, x: f64, y: f64) -> Option<(usize, usize)> {
+    let (x0, y0) = grid.origin();
+    let (col, row) = ((x - x0) / grid.cellsize, (y0 - y) / grid.cellsize);
+    if !(0.0..grid.ncols as f64).contains(&col) || !(0.0..grid.nrows as f64).contains(&row) {
+        return None;
+    }
+    let (col, row) = (col as usize, row as usize);
+    grid.is_valid(col, row).then_some((col, row))
+}
+
+/// Open cell of the A* search, ordered so the heap pops the lowest estimated total time first
+struct Open {
+    estimate: f64,
+    index: usize,
+}
+
+impl PartialEq for Open {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Open {}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+/// Fastest route from the cell at map coordinates `from` to the one at `to`, through valid cells only
+pub fn least_cost_path(grid: &Grid, from: (f64, f64), to: (f64, f64), settings: &RouteSettings) -> Result<CostPath, String> {
+    let start = cell_at(grid, from.0, from.1).ok_or_else(|| format!("Start {},{} is outside the grid or on NoData", from.0, from.1))?;
+    let end = cell_at(grid, to.0, to.1).ok_or_else(|| format!("End {},{} is outside the grid or on NoData", to.0, to.1))?;
+    let (dx, dy) = grid.ground_spacing();
+    let max_rise = settings.max_slope.map(|degrees| degrees.to_radians().tan());
+    let index = |(c, r): (usize, usize)| r * grid.ncols + c;
+    let remaining = |(c, r): (usize, usize)| {
+        let (ex, ey) = ((c as f64 - end.0 as f64) * dx, (r as f64 - end.1 as f64) * dy);
+        ex.hypot(ey) / settings.cost.max_speed()
+    };
+
+    let mut time = vec![f64::INFINITY; grid.ncols * grid.nrows];
+    let mut previous = vec![usize::MAX; grid.ncols * grid.nrows];
+    let mut heap = BinaryHeap::new();
+    time[index(start)] = 0.0;
+    heap.push(Open { estimate: remaining(start), index: index(start) });
+    while let Some(Open { estimate, index: i }) = heap.pop() {
+        let (c, r) = (i % grid.ncols, i / grid.ncols);
+        if (c, r) == end {
+            break;
+        }
+        if estimate > time[i] + remaining((c, r)) {
+            continue; // Already reached faster
+        }
+        let z = grid.data[r][c] as f64;
+        for (oc, or) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+            let (nc, nr) = (c as i64 + oc, r as i64 + or);
+            if nc < 0 || nr < 0 || nc >= grid.ncols as i64 || nr >= grid.nrows as i64 {
+                continue;
+            }
+            let (nc, nr) = (nc as usize, nr as usize);
+            let nz = grid.data[nr][nc];
+            if grid.is_nodata(nz) {
+                continue;
+            }
+            let run = (oc as f64 * dx).hypot(or as f64 * dy);
+            let slope = (nz as f64 - z) / run;
+            if max_rise.is_some_and(|max| slope.abs() > max) {
+                continue;
+            }
+            let t = time[i] + run / settings.cost.speed(slope);
+            let j = index((nc, nr));
+            if t < time[j] {
+                time[j] = t;
+                previous[j] = i;
+                heap.push(Open { estimate: t + remaining((nc, nr)), index: j });
+            }
+        }
+    }
+    if time[index(end)].is_infinite() {
+        return Err("No passable route between the start and the end".into());
+    }
+
+    let mut cells = vec![end];
+    while let Some(&cell) = cells.last().filter(|&&cell| cell != start) {
+        let i = previous[index(cell)];
+        cells.push((i % grid.ncols, i / grid.ncols));
+    }
+    cells.reverse();
+    let (mut length, mut ascent, mut descent) = (0.0, 0.0, 0.0);
+    for pair in cells.windows(2) {
+        let ((c0, r0), (c1, r1)) = (pair[0], pair[1]);
+        let rise = grid.data[r1][c1] as f64 - grid.data[r0][c0] as f64;
+        let run = ((c1 as f64 - c0 as f64) * dx).hypot((r1 as f64 - r0 as f64) * dy);
+        length += run.hypot(rise);
+        if rise > 0.0 { ascent += rise } else { descent -= rise }
+    }
+    Ok(CostPath { cells, seconds: time[index(end)], length, ascent, descent })
+}
+
+/// Travel time as hours and minutes, e.g. "1:05 h"
+pub fn format_duration(seconds: f64) -> String {
+    let minutes = (seconds / 60.0).round() as u64;
+    format!("{}:{:02} h", minutes / 60, minutes % 60)
+}
+
+/// Writes the route as a GeoJSON feature: a 3D line string in the coordinates of the grid
+/// with the length, travel time, climb and cost function as properties
+#[cfg(feature = "fs")]
+pub fn write_geojson<W: Write>(path: &CostPath, grid: &Grid, settings: &RouteSettings, out: W) -> io::Result<()> {
+    let feature = serde_json::json!({
+        "type": "Feature",
+        "geometry": { "type": "LineString", "coordinates": path.points(grid) },
+        "properties": {
+            "cost": settings.cost.name(),
+            "max_slope": settings.max_slope,
+            "length_m": path.length,
+            "travel_time_s": path.seconds,
+            "travel_time": format_duration(path.seconds),
+            "ascent_m": path.ascent,
+            "descent_m": path.descent,
+        },
+    });
+    serde_json::to_writer_pretty(out, &feature).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
+
+/// Writes the route as a shapefile of one PolyLineZ with the properties of [`write_geojson`] as attributes
+#[cfg(feature = "fs")]
+pub fn save_shapefile(path: &CostPath, grid: &Grid, settings: &RouteSettings, target: &Path) -> io::Result<()> {
+    let number = |name, decimals| Field { name, kind: FieldType::Number { width: 16, decimals } };
+    let fields = vec![Field { name: "COST", kind: FieldType::Text { width: 16 } }, number("MAX_SLOPE", 2), number("LENGTH_M", 2),
+        number("TIME_S", 1), Field { name: "TIME", kind: FieldType::Text { width: 16 } }, number("ASCENT_M", 2), number("DESCENT_M", 2)];
+    let mut shapefile = Shapefile::new(ShapeType::PolyLineZ, fields);
+    shapefile.add(vec![path.points(grid)], vec![
+        Value::Text(settings.cost.name().into()),
+        settings.max_slope.map_or(Value::Null, Value::Number),
+        Value::Number(path.length),
+        Value::Number(path.seconds),
+        Value::Text(format_duration(path.seconds)),
+        Value::Number(path.ascent),
+        Value::Number(path.descent),
+    ]);
+    shapefile.save(target)
+}
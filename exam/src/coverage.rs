@@ -0,0 +1,142 @@
+//! Footprints of the tiles of a survey from the bounds in their headers (`exam manifest --coverage`): a bucket index
+//! of the footprints, an overview image of where tiles lie, overlap or are missing, and the footprints and gaps as
+//! GeoJSON, so holes show up before the tiles are mosaicked.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use image::{Rgb, RgbImage};
+
+/// File names of the overview and of the footprints below the output root
+pub const COVERAGE_IMAGE: &str = "coverage.png";
+pub const COVERAGE_GEOJSON: &str = "coverage.geojson";
+
+/// Longer side of the overview in pixels
+pub const COVERAGE_SIZE: u32 = 1024;
+
+/// Tile of a survey: its name and west, south, east and north edge
+#[derive(Clone, Debug)]
+pub struct Footprint {
+    pub name: String,
+    pub bounds: [f64; 4],
+}
+
+/// Footprints sorted into square buckets about the size of a tile, so the tiles at a point are found without
+/// testing all of them
+pub struct TileIndex {
+    footprints: Vec<Footprint>,
+    bucket: f64,                            // Edge of a bucket in map units
+    buckets: HashMap<(i64, i64), Vec<usize>>, // Footprints overlapping each bucket
+}
+
+/// Pixel colors of the overview: gaps, tiles, overlaps and tile edges
+const GAP: Rgb<u8> = Rgb([220, 60, 50]);
+const COVERED: Rgb<u8> = Rgb([120, 190, 110]);
+const OVERLAP: Rgb<u8> = Rgb([60, 110, 170]);
+const EDGE: Rgb<u8> = Rgb([40, 40, 40]);
+
+impl TileIndex {
+    /// Indexes `footprints`, dropping empty or non-finite ones; None if none is left
+    pub fn new(footprints: Vec<Footprint>) -> Option<TileIndex> {
+        let footprints: Vec<Footprint> = footprints.into_iter()
+            .filter(|f| f.bounds.iter().all(|v| v.is_finite()) && f.bounds[2] > f.bounds[0] && f.bounds[3] > f.bounds[1])
+            .collect();
+        let mut sizes: Vec<f64> = footprints.iter().map(|f| (f.bounds[2] - f.bounds[0]).max(f.bounds[3] - f.bounds[1])).collect();
+        sizes.sort_by(f64::total_cmp);
+        let bucket = *sizes.get(sizes.len() / 2)?; // The median tile
+        let mut buckets: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+        for (i, footprint) in footprints.iter().enumerate() {
+            let [west, south, east, north] = footprint.bounds;
+            for bx in (west / bucket).floor() as i64..=(east / bucket).floor() as i64 {
+                for by in (south / bucket).floor() as i64..=(north / bucket).floor() as i64 {
+                    buckets.entry((bx, by)).or_default().push(i);
+                }
+            }
+        }
+        Some(TileIndex { footprints, bucket, buckets })
+    }
+
+    pub fn footprints(&self) -> &[Footprint] {
+        &self.footprints
+    }
+
+    /// Bounds around all footprints
+    pub fn extent(&self) -> [f64; 4] {
+        self.footprints.iter().fold([f64::MAX, f64::MAX, f64::MIN, f64::MIN], |e, f| {
+            [e[0].min(f.bounds[0]), e[1].min(f.bounds[1]), e[2].max(f.bounds[2]), e[3].max(f.bounds[3])]
+        })
+    }
+
+    /// Footprints containing the point, edges included
+    pub fn at(&self, x: f64, y: f64) -> impl Iterator<Item = &Footprint> + '_ {
+        let key = ((x / self.bucket).floor() as i64, (y / self.bucket).floor() as i64);
+        self.buckets.get(&key).into_iter().flatten().map(|&i| &self.footprints[i])
+            .filter(move |f| x >= f.bounds[0] && x <= f.bounds[2] && y >= f.bounds[1] && y <= f.bounds[3])
+    }
+
+    /// Squares of the size of the smallest tile, laid from the west and north edge of the extent, whose center no
+    /// footprint covers: the missing tiles of a regular tiling
+    pub fn gaps(&self) -> Vec<[f64; 4]> {
+        let size = self.footprints.iter().map(|f| (f.bounds[2] - f.bounds[0]).min(f.bounds[3] - f.bounds[1])).fold(f64::MAX, f64::min);
+        let [west, south, east, north] = self.extent();
+        let (cols, rows) = (((east - west) / size - 1e-9).ceil() as usize, ((north - south) / size - 1e-9).ceil() as usize);
+        let mut gaps = Vec::new();
+        for row in 0..rows {
+            let top = north - row as f64 * size;
+            for col in 0..cols {
+                let left = west + col as f64 * size;
+                if self.at(left + size / 2.0, top - size / 2.0).next().is_none() {
+                    gaps.push([left, (top - size).max(south), (left + size).min(east), top]);
+                }
+            }
+        }
+        gaps
+    }
+
+    /// Overview of the extent with `max_size` pixels along its longer side: gaps red, tiles green, overlaps blue and
+    /// tile edges dark. Also returns the share of the extent covered by tiles.
+    pub fn render(&self, max_size: u32) -> (RgbImage, f64) {
+        let [west, south, east, north] = self.extent();
+        let scale = (east - west).max(north - south) / max_size.max(1) as f64; // Map units per pixel
+        let (width, height) = ((((east - west) / scale).round() as u32).max(1), (((north - south) / scale).round() as u32).max(1));
+        let mut covered = 0usize;
+        let mut img = RgbImage::from_fn(width, height, |px, py| {
+            let (x, y) = (west + (px as f64 + 0.5) * scale, north - (py as f64 + 0.5) * scale);
+            match self.at(x, y).count() {
+                0 => GAP,
+                1 => { covered += 1; COVERED }
+                _ => { covered += 1; OVERLAP }
+            }
+        });
+        for footprint in &self.footprints {
+            let [w, s, e, n] = footprint.bounds;
+            let to_px = |x: f64| (((x - west) / scale).round() as i64).clamp(0, width as i64 - 1) as u32;
+            let to_py = |y: f64| (((north - y) / scale).round() as i64).clamp(0, height as i64 - 1) as u32;
+            let (x0, x1, y0, y1) = (to_px(w), to_px(e), to_py(n), to_py(s));
+            for x in x0..=x1 {
+                img.put_pixel(x, y0, EDGE);
+                img.put_pixel(x, y1, EDGE);
+            }
+            for y in y0..=y1 {
+                img.put_pixel(x0, y, EDGE);
+                img.put_pixel(x1, y, EDGE);
+            }
+        }
+        (img, covered as f64 / (width as f64 * height as f64))
+    }
+
+    /// GeoJSON FeatureCollection of the footprints (`"kind":"tile"` with their name) and of `gaps` (`"kind":"gap"`),
+    /// in the coordinates of the headers
+    pub fn write_geojson<W: Write>(&self, gaps: &[[f64; 4]], out: &mut W) -> io::Result<()> {
+        let ring = |[w, s, e, n]: [f64; 4]| format!("[[{w},{s}],[{e},{s}],[{e},{n}],[{w},{n}],[{w},{s}]]");
+        let features = self.footprints.iter()
+            .map(|f| (format!("\"kind\":\"tile\",\"name\":{}", serde_json::Value::from(f.name.as_str())), f.bounds))
+            .chain(gaps.iter().map(|&b| ("\"kind\":\"gap\"".to_string(), b)));
+        write!(out, "{{\"type\":\"FeatureCollection\",\"features\":[")?;
+        for (i, (properties, bounds)) in features.enumerate() {
+            write!(out, "{}\n{{\"type\":\"Feature\",\"properties\":{{{}}},\"geometry\":{{\"type\":\"Polygon\",\"coordinates\":[{}]}}}}",
+                if i == 0 { "" } else { "," }, properties, ring(bounds))?;
+        }
+        writeln!(out, "\n]}}")
+    }
+}
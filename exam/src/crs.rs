@@ -0,0 +1,186 @@
+//! Coordinate reference systems and warping grids between them, enabled with the `reproject` feature.
+//! The transformations run in pure Rust through proj4rs, so the feature also builds for WebAssembly.
+
+#[cfg(feature = "fs")]
+use std::fs;
+#[cfg(feature = "fs")]
+use std::io;
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+use proj4rs::adaptors::transform_xy;
+use proj4rs::Proj;
+
+use crate::grid::{Grid, Interpolation};
+
+/// Mean earth radius in meters, for ground distances
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// A coordinate reference system given as EPSG code or PROJ string
+pub struct Crs {
+    pub definition: String, // PROJ string the system was built from
+    pub epsg: Option<u32>,
+    proj: Proj,
+}
+
+impl Crs {
+    /// Parses `EPSG:<code>` for the codes known to [`Crs::from_epsg`] or a PROJ string like `+proj=utm +zone=32 +datum=WGS84`
+    pub fn parse(text: &str) -> Result<Crs, String> {
+        let text = text.trim();
+        if let Some(code) = text.strip_prefix("EPSG:").or_else(|| text.strip_prefix("epsg:")) {
+            let code = code.parse().map_err(|_| format!("Invalid EPSG code {:?}", code))?;
+            return Crs::from_epsg(code).ok_or_else(|| format!("EPSG:{} is not built in, pass its PROJ string instead", code));
+        }
+        if text.eq_ignore_ascii_case("WGS84") {
+            return Ok(Crs::from_epsg(4326).unwrap());
+        }
+        let proj = Proj::from_proj_string(text).map_err(|e| format!("Invalid PROJ string {:?}: {}", text, e))?;
+        Ok(Crs { definition: text.to_string(), epsg: None, proj })
+    }
+
+    /// Common systems: WGS 84 (4326), ETRS89 (4258), RGF93 (4171), Web Mercator (3857), Lambert-93 (2154)
+    /// and the UTM zones on WGS 84 (326xx north, 327xx south) and ETRS89 (258xx)
+    pub fn from_epsg(code: u32) -> Option<Crs> {
+        let definition = match code {
+            4326 => "+proj=longlat +datum=WGS84 +no_defs".to_string(),
+            4258 | 4171 => "+proj=longlat +ellps=GRS80 +towgs84=0,0,0,0,0,0,0 +no_defs".to_string(),
+            3857 => "+proj=merc +a=6378137 +b=6378137 +lat_ts=0 +lon_0=0 +x_0=0 +y_0=0 +k=1 +units=m +no_defs".to_string(),
+            2154 => "+proj=lcc +lat_0=46.5 +lon_0=3 +lat_1=49 +lat_2=44 +x_0=700000 +y_0=6600000 +ellps=GRS80 +towgs84=0,0,0,0,0,0,0 +units=m +no_defs".to_string(),
+            32601..=32660 => format!("+proj=utm +zone={} +datum=WGS84 +units=m +no_defs", code - 32600),
+            32701..=32760 => format!("+proj=utm +zone={} +south +datum=WGS84 +units=m +no_defs", code - 32700),
+            25828..=25838 => format!("+proj=utm +zone={} +ellps=GRS80 +towgs84=0,0,0,0,0,0,0 +units=m +no_defs", code - 25800),
+            _ => return None,
+        };
+        let proj = Proj::from_proj_string(&definition).ok()?;
+        Some(Crs { definition, epsg: Some(code), proj })
+    }
+
+    /// Recognizes the system of an ESRI .prj (WKT) by its EPSG authority or by the well-known names of [`Crs::from_epsg`]
+    pub fn from_wkt(wkt: &str) -> Option<Crs> {
+        // The authority of the outermost definition comes last: AUTHORITY["EPSG","2154"]] or ID["EPSG",2154]]
+        let authority = ["AUTHORITY[\"EPSG\",\"", "ID[\"EPSG\","].iter()
+            .filter_map(|key| wkt.rfind(key).map(|i| &wkt[i + key.len()..]))
+            .filter_map(|rest| rest.split(|c: char| !c.is_ascii_digit()).next()?.parse().ok())
+            .next();
+        if let Some(crs) = authority.and_then(Crs::from_epsg) {
+            return Some(crs);
+        }
+
+        let name = wkt.to_lowercase().replace(' ', "_");
+        if name.contains("lambert_93") || name.contains("lambert-93") {
+            return Crs::from_epsg(2154);
+        }
+        if name.contains("web_mercator") || name.contains("pseudo-mercator") || name.contains("pseudo_mercator") {
+            return Crs::from_epsg(3857);
+        }
+        if let Some(i) = name.find("utm_zone_") {
+            let rest = &name[i + "utm_zone_".len()..];
+            let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+            let zone: u32 = digits.parse().ok()?;
+            let south = rest[digits.len()..].starts_with('s');
+            let base = if name.contains("etrs") { 25800 } else if south { 32700 } else { 32600 };
+            return Crs::from_epsg(base + zone);
+        }
+        if name.starts_with("geogcs") && name.contains("wgs") && name.contains("84") {
+            return Crs::from_epsg(4326);
+        }
+        None
+    }
+
+    /// Coordinates in degrees instead of a linear unit
+    pub fn is_geographic(&self) -> bool {
+        self.proj.is_latlong()
+    }
+
+    /// Transforms a point into `to`, geographic coordinates are longitude and latitude in degrees
+    pub fn transform(&self, to: &Crs, x: f64, y: f64) -> Option<(f64, f64)> {
+        let (x, y) = if self.is_geographic() { (x.to_radians(), y.to_radians()) } else { (x, y) };
+        let (x, y) = transform_xy(&self.proj, &to.proj, x, y).ok()?;
+        let (x, y) = if to.is_geographic() { (x.to_degrees(), y.to_degrees()) } else { (x, y) };
+        (x.is_finite() && y.is_finite()).then_some((x, y))
+    }
+
+    /// Ground size in meters of a cell of `cellsize` units at (x, y): the geometric mean of its east-west and north-south extent.
+    /// Differs from `cellsize` for degrees and for projections that stretch with the latitude, like Web Mercator.
+    pub fn ground_cell_size(&self, x: f64, y: f64, cellsize: f64) -> Option<f64> {
+        let wgs84 = Crs::from_epsg(4326)?;
+        let center = self.transform(&wgs84, x, y)?;
+        let east = self.transform(&wgs84, x + cellsize, y)?;
+        let north = self.transform(&wgs84, x, y + cellsize)?;
+        Some((haversine(center, east) * haversine(center, north)).sqrt())
+    }
+}
+
+/// Reads the system of an input: the `.prj` next to it, or the GeoKeys of a GeoTIFF
+#[cfg(feature = "fs")]
+pub fn detect(path: &Path) -> io::Result<Option<Crs>> {
+    let prj = path.with_extension("prj");
+    if prj.exists() {
+        return Ok(Crs::from_wkt(&fs::read_to_string(prj)?));
+    }
+    if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("tif") || e.eq_ignore_ascii_case("tiff")) {
+        return Ok(crate::geotiff::epsg_code(&fs::read(path)?).and_then(Crs::from_epsg));
+    }
+    Ok(None)
+}
+
+/// Distance in meters between two (longitude, latitude) points in degrees
+fn haversine((lon1, lat1): (f64, f64), (lon2, lat2): (f64, f64)) -> f64 {
+    let (dlat, dlon) = ((lat2 - lat1).to_radians(), (lon2 - lon1).to_radians());
+    let a = (dlat / 2.0).sin().powi(2) + lat1.to_radians().cos() * lat2.to_radians().cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+/// Resamples `grid` from `src` into `dst`. The output covers the transformed extent of the input,
+/// its cell size is `cellsize` or, if None, the size of a source cell at the center after the transformation.
+/// Every output cell center is transformed back into `src` and sampled there; cells falling outside become NoData.
+pub fn warp(grid: &Grid, src: &Crs, dst: &Crs, cellsize: Option<f64>, method: Interpolation) -> Result<Grid, String> {
+    let failed = || "Grid lies outside the area the coordinate systems can be transformed in".to_string();
+    let (left, top) = grid.origin();
+    let (width, height) = (grid.ncols as f64 * grid.cellsize, grid.nrows as f64 * grid.cellsize);
+
+    // Edges are sampled densely since straight lines curve under most projections
+    const STEPS: usize = 32;
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+    for i in 0..=STEPS {
+        let t = i as f64 / STEPS as f64;
+        for (x, y) in [(left + t * width, top), (left + t * width, top - height), (left, top - t * height), (left + width, top - t * height)] {
+            let (x, y) = src.transform(dst, x, y).ok_or_else(failed)?;
+            (min_x, min_y, max_x, max_y) = (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y));
+        }
+    }
+
+    let cellsize = match cellsize {
+        Some(size) => size,
+        None => {
+            let (cx, cy) = (left + width / 2.0, top - height / 2.0);
+            let center = src.transform(dst, cx, cy).ok_or_else(failed)?;
+            let east = src.transform(dst, cx + grid.cellsize, cy).ok_or_else(failed)?;
+            let north = src.transform(dst, cx, cy + grid.cellsize).ok_or_else(failed)?;
+            let distance = |(x, y): (f64, f64)| (x - center.0).hypot(y - center.1);
+            (distance(east) * distance(north)).sqrt()
+        }
+    };
+    if !cellsize.is_finite() || cellsize <= 0.0 {
+        return Err(format!("Invalid target cell size {}", cellsize));
+    }
+    let ncols = ((max_x - min_x) / cellsize).ceil() as usize;
+    let nrows = ((max_y - min_y) / cellsize).ceil() as usize;
+    if ncols * nrows > 16 * grid.ncols.max(1) * grid.nrows.max(1) {
+        return Err(format!("Warped grid of {}x{} cells is far larger than the input, check the cell size", ncols, nrows));
+    }
+
+    let data = (0..nrows)
+        .map(|row| {
+            let y = max_y - (row as f64 + 0.5) * cellsize;
+            (0..ncols)
+                .map(|col| {
+                    let x = min_x + (col as f64 + 0.5) * cellsize;
+                    dst.transform(src, x, y).and_then(|(sx, sy)| grid.sample(sx, sy, method)).unwrap_or(grid.nodata)
+                })
+                .collect()
+        })
+        .collect();
+
+    Ok(Grid { data, ncols, nrows, nodata: grid.nodata, cellsize, xllcorner: min_x, yllcorner: max_y - nrows as f64 * cellsize, geographic: Some(dst.is_geographic()) })
+}
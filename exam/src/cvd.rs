@@ -0,0 +1,72 @@
+//! Simulation of color vision deficiencies, to check how a palette reads for color-blind readers.
+//! Dichromacies use the full-severity matrices of Machado, Oliveira and Fernandes (2009) in linear RGB.
+
+use image::{DynamicImage, RgbImage};
+
+/// A color vision deficiency to simulate (--simulate-cvd)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cvd {
+    Protanopia,    // No long-wavelength (red) cones
+    Deuteranopia,  // No medium-wavelength (green) cones, the most common
+    Tritanopia,    // No short-wavelength (blue) cones
+    Achromatopsia, // No color vision, luminance only
+}
+
+impl Cvd {
+    pub const ALL: [Cvd; 4] = [Cvd::Protanopia, Cvd::Deuteranopia, Cvd::Tritanopia, Cvd::Achromatopsia];
+
+    pub fn parse(name: &str) -> Option<Cvd> {
+        Cvd::ALL.into_iter().find(|cvd| cvd.name() == name.to_lowercase())
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Cvd::Protanopia => "protanopia",
+            Cvd::Deuteranopia => "deuteranopia",
+            Cvd::Tritanopia => "tritanopia",
+            Cvd::Achromatopsia => "achromatopsia",
+        }
+    }
+
+    fn matrix(&self) -> [[f32; 3]; 3] {
+        match self {
+            Cvd::Protanopia => [[0.152286, 1.052583, -0.204868], [0.114503, 0.786281, 0.099216], [-0.003882, -0.048116, 1.051998]],
+            Cvd::Deuteranopia => [[0.367322, 0.860646, -0.227968], [0.280085, 0.672501, 0.047413], [-0.011820, 0.042940, 0.968881]],
+            Cvd::Tritanopia => [[1.255528, -0.076749, -0.178779], [-0.078411, 0.930809, 0.147602], [0.004733, 0.691367, 0.303900]],
+            // Rec. 709 luminance in every channel
+            Cvd::Achromatopsia => [[0.2126, 0.7152, 0.0722]; 3],
+        }
+    }
+
+    /// The image as seen with this deficiency, grayscale images are returned unchanged
+    pub fn simulate(&self, img: &DynamicImage) -> DynamicImage {
+        let rgb = match img {
+            DynamicImage::ImageLuma8(_) | DynamicImage::ImageLumaA8(_) => return img.clone(),
+            DynamicImage::ImageRgb8(rgb) => rgb.clone(),
+            other => other.to_rgb8(),
+        };
+        DynamicImage::ImageRgb8(self.simulate_rgb(&rgb))
+    }
+
+    pub fn simulate_rgb(&self, img: &RgbImage) -> RgbImage {
+        let linear: Vec<f32> = (0..=255u8).map(|v| to_linear(v as f32 / 255.0)).collect();
+        let m = self.matrix();
+        let mut out = img.clone();
+        for pixel in out.pixels_mut() {
+            let [r, g, b] = pixel.0.map(|c| linear[c as usize]);
+            for (c, row) in pixel.0.iter_mut().zip(m) {
+                *c = (from_linear((row[0] * r + row[1] * g + row[2] * b).clamp(0.0, 1.0)) * 255.0).round() as u8;
+            }
+        }
+        out
+    }
+}
+
+/// sRGB transfer function, encoded to linear light
+fn to_linear(v: f32) -> f32 {
+    if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+
+fn from_linear(v: f32) -> f32 {
+    if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 }
+}
@@ -0,0 +1,100 @@
+//! Inputs delivered twice under different names (`--find-duplicates`, `--skip-duplicates`): files of equal size are
+//! hashed, and files with equal hashes compared byte by byte, so only exact copies are reported.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+/// What the batch run does with a copy of an earlier input
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DuplicateMode {
+    Report, // Print and report it, and render it anyway
+    Skip,   // Print and report it, and leave it out
+}
+
+/// An input with the same content as one earlier in the listing
+#[derive(Clone, Debug, Serialize)]
+pub struct Duplicate {
+    pub input: PathBuf,
+    pub original: PathBuf,
+    pub checksum: String, // CRC-32 and FNV-1a 64 of the content, in hex
+    pub skipped: bool,
+}
+
+/// CRC-32 and 64-bit FNV-1a of the content of a file as 24 hex digits, read in blocks
+pub fn checksum(path: &Path) -> io::Result<String> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut crc = crc32fast::Hasher::new();
+    let mut fnv: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut block = vec![0; 1 << 16];
+    loop {
+        let n = reader.read(&mut block)?;
+        if n == 0 {
+            break;
+        }
+        crc.update(&block[..n]);
+        for &byte in &block[..n] {
+            fnv = (fnv ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    Ok(format!("{:08x}{:016x}", crc.finalize(), fnv))
+}
+
+/// Whether two files hold the same bytes
+fn same_content(a: &Path, b: &Path) -> io::Result<bool> {
+    let (mut a, mut b) = (BufReader::new(File::open(a)?), BufReader::new(File::open(b)?));
+    let (mut block_a, mut block_b) = (vec![0; 1 << 16], vec![0; 1 << 16]);
+    loop {
+        let n = a.read(&mut block_a)?;
+        if n == 0 {
+            return Ok(b.read(&mut block_b[..1])? == 0);
+        }
+        match b.read_exact(&mut block_b[..n]) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            result => result?,
+        }
+        if block_a[..n] != block_b[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+/// The copies among `paths`, each with the first path of the same content as its original. Only files sharing their
+/// size with another are read.
+pub fn find_duplicates(paths: &[PathBuf]) -> io::Result<Vec<Duplicate>> {
+    let mut by_size: HashMap<u64, Vec<&PathBuf>> = HashMap::new();
+    for path in paths {
+        by_size.entry(fs::metadata(path)?.len()).or_default().push(path);
+    }
+    let mut duplicates = Vec::new();
+    for path in paths {
+        let group = &by_size[&fs::metadata(path)?.len()];
+        if group.len() < 2 || group[0] != path {
+            continue;
+        }
+        // Files of one size, each compared with the originals of its hash
+        let mut originals: HashMap<String, Vec<&PathBuf>> = HashMap::new();
+        for &candidate in group {
+            let sum = checksum(candidate)?;
+            let known = originals.entry(sum.clone()).or_default();
+            let mut original = None;
+            for &earlier in known.iter() {
+                if same_content(earlier, candidate)? {
+                    original = Some(earlier);
+                    break;
+                }
+            }
+            match original {
+                Some(original) => duplicates.push(Duplicate { input: candidate.clone(), original: original.clone(), checksum: sum, skipped: false }),
+                None => known.push(candidate),
+            }
+        }
+    }
+    // In the order of the listing
+    let position: HashMap<&PathBuf, usize> = paths.iter().enumerate().map(|(i, p)| (p, i)).collect();
+    duplicates.sort_by_key(|d| position[&d.input]);
+    Ok(duplicates)
+}
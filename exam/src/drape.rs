@@ -0,0 +1,110 @@
+//! Draping a co-registered RGB image, e.g. an orthophoto, over the hillshade in place of the color gradient.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use colorgrad::Gradient;
+use image::{DynamicImage, Rgb, RgbImage};
+
+use crate::geotiff;
+use crate::grid::Grid;
+use crate::output::{save_image, OutputFormat, OutputTarget};
+use crate::render::{apply_aerial_perspective, apply_slope_saturation, blend_shade, color_range, shade_color, Backend};
+
+/// An RGB image placed on the map by a world file or GeoTIFF tags
+pub struct Drape {
+    pub image: RgbImage,
+    pub x0: f64, // Map coordinates of the upper left corner of the upper left pixel
+    pub y0: f64,
+    pub dx: f64, // Pixel width and height in map units
+    pub dy: f64,
+}
+
+impl Drape {
+    /// Loads a PNG, JPEG or TIFF image, georeferenced by a world file next to it (`.pgw`, `.jgw`, `.tfw`, `<ext>w`
+    /// or `.wld`) or else by the GeoTIFF tags. The image must be in the coordinate system of the rendered grid.
+    pub fn load(path: &Path) -> io::Result<Drape> {
+        let bytes = fs::read(path).map_err(|e| io::Error::new(e.kind(), format!("{:?}: {}", path, e)))?;
+        let image = image::load_from_memory(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}: {}", path, e)))?.to_rgb8();
+
+        let transform = match world_file(path) {
+            Some(world) => Some(parse_world_file(&fs::read_to_string(&world)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}: {}", world, e)))?),
+            None if geotiff::is_tiff(&bytes) => geotiff::model_transform(&bytes),
+            None => None,
+        };
+        let (x0, y0, dx, dy) = transform.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+            format!("{:?} has no world file and no GeoTIFF georeferencing", path)))?;
+        Ok(Drape { image, x0, y0, dx, dy })
+    }
+
+    /// Color at map coordinates, bilinear between pixel centers, None outside the image
+    pub fn sample(&self, x: f64, y: f64) -> Option<Rgb<u8>> {
+        let (width, height) = (self.image.width(), self.image.height());
+        let (col, row) = ((x - self.x0) / self.dx, (self.y0 - y) / self.dy);
+        if !(0.0..width as f64).contains(&col) || !(0.0..height as f64).contains(&row) {
+            return None;
+        }
+        let fc = (col - 0.5).clamp(0.0, (width - 1) as f64);
+        let fr = (row - 0.5).clamp(0.0, (height - 1) as f64);
+        let (c0, r0) = (fc as u32, fr as u32);
+        let (c1, r1) = ((c0 + 1).min(width - 1), (r0 + 1).min(height - 1));
+        let (tx, ty) = (fc - c0 as f64, fr - r0 as f64);
+        let px = |c, r| self.image.get_pixel(c, r).0.map(|v| v as f64);
+        let (a, b, c, d) = (px(c0, r0), px(c1, r0), px(c0, r1), px(c1, r1));
+        Some(Rgb(std::array::from_fn(|i| {
+            let top = a[i] + (b[i] - a[i]) * tx;
+            let bottom = c[i] + (d[i] - c[i]) * tx;
+            (top + (bottom - top) * ty).round() as u8
+        })))
+    }
+}
+
+/// World file of an image: `<name>.<e>w` from the first and last letter of the extension, `<name>.<ext>w` or `<name>.wld`
+fn world_file(path: &Path) -> Option<PathBuf> {
+    let ext = path.extension()?.to_string_lossy().to_lowercase();
+    let short = format!("{}{}w", ext.chars().next()?, ext.chars().last()?);
+    [short, format!("{}w", ext), "wld".to_string()].into_iter()
+        .map(|e| path.with_extension(e))
+        .find(|p| p.exists())
+}
+
+/// The six lines of a world file (pixel width, two rotations, negative pixel height, then the
+/// center of the upper left pixel) as upper left corner and pixel size
+fn parse_world_file(text: &str) -> Result<(f64, f64, f64, f64), String> {
+    let values: Vec<f64> = text.split_whitespace().map(|v| v.parse().map_err(|_| format!("Invalid number {:?}", v))).collect::<Result<_, _>>()?;
+    let [a, d, b, e, c, f] = values[..] else {
+        return Err(format!("A world file has 6 values, found {}", values.len()));
+    };
+    if d != 0.0 || b != 0.0 {
+        return Err("Rotated world files are not supported".into());
+    }
+    if a <= 0.0 || e >= 0.0 {
+        return Err("Expected a positive pixel width and a negative pixel height".into());
+    }
+    Ok((c - a / 2.0, f - e / 2.0, a, -e))
+}
+
+/// Shades the drape colors with the hillshade values like [`blend_shade`] shades the gradient.
+/// Cells the image does not cover keep the gradient colors normalized to `range`.
+pub fn render_draped(grid: &Grid, range: (f32, f32), grad: &Gradient, drape: &Drape, shade: &[u8]) -> RgbImage {
+    let mut img = blend_shade(grid, range, grad, shade);
+    let (x0, y0) = grid.origin();
+    for (x, y, _) in grid.iter_valid() {
+        let (cx, cy) = (x0 + (x as f64 + 0.5) * grid.cellsize, y0 - (y as f64 + 0.5) * grid.cellsize);
+        if let Some(color) = drape.sample(cx, cy) {
+            img.put_pixel(x as u32, y as u32, shade_color(color, shade[y * grid.ncols + x]));
+        }
+    }
+    img
+}
+
+/// Renders the draped hillshade with the sun of the target, including its slope saturation and aerial perspective, as
+/// product "hillside"
+pub fn save_draped_image(grid: &Grid, drape: &Drape, grad: &Gradient, backend: &Backend, format: &OutputFormat, target: &OutputTarget) -> io::Result<()> {
+    let range = color_range(grid, target.elevation_range.as_ref());
+    let mut img = render_draped(grid, range, grad, drape, &backend.hillshade_values(grid, &target.shade));
+    apply_slope_saturation(&mut img, grid, &target.shade);
+    apply_aerial_perspective(&mut img, grid, range, target.shade.aerial_perspective);
+    save_image(DynamicImage::ImageRgb8(img), target, "hillside", format)
+}
@@ -0,0 +1,91 @@
+//! Grids of Arc/Info export files (.e00): the GRD section holds the size, NoData, cell size and bounds,
+//! followed by the values row by row from the north, each row starting on a new line.
+//! Only uncompressed exports are read, compressed ones (`EXP  1`) have to be expanded with e00conv or avcimport first.
+
+#[cfg(feature = "fs")]
+use std::fs;
+use std::io;
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+use crate::grid::Grid;
+
+/// Loads the grid of an .e00 file
+#[cfg(feature = "fs")]
+pub fn load_e00(path: &Path) -> io::Result<Grid> {
+    parse_e00(&String::from_utf8_lossy(&fs::read(path)?))
+}
+
+/// Whether the text looks like an Arc/Info export
+pub fn is_e00(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"EXP ")
+}
+
+/// Parses the first GRD section of an uncompressed export
+pub fn parse_e00(text: &str) -> io::Result<Grid> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("E00: {}", msg));
+    let mut lines = text.lines();
+    let first = lines.next().unwrap_or_default();
+    if !first.starts_with("EXP ") {
+        return Err(invalid("missing the EXP line"));
+    }
+    if first.get(4..6).is_some_and(|level| level.trim() != "0") {
+        return Err(invalid("compressed exports are not supported, expand it with e00conv or avcimport"));
+    }
+    lines.find(|line| line.starts_with("GRD ")).ok_or_else(|| invalid("no GRD section, the export holds no grid"))?;
+
+    // Size, value type (1 integer, 2 float) and NoData, then the cell sizes, the lower left and the upper right corner
+    let header: Vec<f64> = lines.by_ref().take(4).flat_map(numbers).collect();
+    if header.len() < 10 {
+        return Err(invalid("truncated GRD header"));
+    }
+    let (ncols, nrows, nodata) = (header[0] as usize, header[1] as usize, header[3]);
+    let (dx, dy, xll, yll) = (header[4], header[5], header[6], header[7]);
+    if ncols == 0 || nrows == 0 {
+        return Err(invalid("empty grid"));
+    }
+    if dx <= 0.0 || (dx - dy).abs() > dx * 1e-6 {
+        return Err(invalid(&format!("cells of {} x {} are not square", dx, dy)));
+    }
+
+    let mut values = lines.take_while(|line| !line.starts_with("EOG")).flat_map(numbers);
+    let mut data = Vec::with_capacity(nrows);
+    for _ in 0..nrows {
+        // Single precision values round NoData to 7 digits, e.g. -0.2147484E+10
+        let row: Vec<f32> = values.by_ref().take(ncols).map(|v| if (v - nodata).abs() <= nodata.abs() * 1e-6 { nodata as f32 } else { v as f32 }).collect();
+        if row.len() < ncols {
+            return Err(invalid("Row count mismatch"));
+        }
+        data.push(row);
+    }
+    Ok(Grid { data, ncols, nrows, nodata: nodata as f32, cellsize: dx, xllcorner: xll, yllcorner: yll, geographic: None })
+}
+
+/// Numbers of a line, also those written without a space in between like `2-0.21474836470000E+10`,
+/// which end after the two digits of their exponent
+fn numbers(line: &str) -> Vec<f64> {
+    let mut numbers = Vec::new();
+    for token in line.split_whitespace() {
+        let mut start = 0;
+        let bytes = token.as_bytes();
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] == b'E' {
+                i = (i + 4).min(bytes.len()); // E, sign and two digits
+                numbers.extend(token[start..i].parse::<f64>().ok());
+                start = i;
+                continue;
+            }
+            // A sign after a digit starts the next number
+            if i > start && bytes[i] == b'-' && bytes[i - 1].is_ascii_digit() {
+                numbers.extend(token[start..i].parse::<f64>().ok());
+                start = i;
+            }
+            i += 1;
+        }
+        if start < bytes.len() {
+            numbers.extend(token[start..].parse::<f64>().ok());
+        }
+    }
+    numbers
+}
@@ -0,0 +1,186 @@
+//! Edge matching of tiled surveys (`exam edges`): the elevations on both sides of every seam between two adjacent
+//! tiles, found by their georeferencing, are compared, so datum or offset errors between deliveries are caught before
+//! they show up as cliffs in the mosaic. Written as `edges.json` and the seams as lines in `edges.geojson`.
+
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use crate::grid::Grid;
+
+/// File names of the report and of the seams below the output root
+pub const EDGES_FILE: &str = "edges.json";
+pub const EDGES_GEOJSON: &str = "edges.geojson";
+
+/// Mean difference above which a seam is flagged when no --tolerance is given, in elevation units
+pub const DEFAULT_TOLERANCE: f64 = 0.5;
+
+/// The two outermost rows and columns of a tile, all that edge matching keeps of it
+#[derive(Clone, Debug)]
+pub struct TileEdges {
+    pub name: String,
+    pub bounds: [f64; 4], // West, south, east and north edge
+    pub cellsize: f64,
+    north: [Vec<f32>; 2], // Outermost row or column first, each from west to east or north to south, NoData as NaN
+    south: [Vec<f32>; 2],
+    west: [Vec<f32>; 2],
+    east: [Vec<f32>; 2],
+}
+
+impl TileEdges {
+    /// Copies the edges of `grid`, which needs at least two rows and columns
+    pub fn of(name: &str, grid: &Grid) -> Option<TileEdges> {
+        if grid.ncols < 2 || grid.nrows < 2 {
+            return None;
+        }
+        let (last_col, last_row) = (grid.ncols - 1, grid.nrows - 1);
+        let valid = |val: f32| if grid.is_nodata(val) { f32::NAN } else { val };
+        let row = |y: usize| grid.data[y].iter().map(|&val| valid(val)).collect::<Vec<f32>>();
+        let column = |x: usize| grid.data.iter().map(|row| valid(row[x])).collect::<Vec<f32>>();
+        Some(TileEdges {
+            name: name.to_string(),
+            bounds: [grid.xllcorner, grid.yllcorner, grid.xllcorner + grid.ncols as f64 * grid.cellsize, grid.yllcorner + grid.nrows as f64 * grid.cellsize],
+            cellsize: grid.cellsize,
+            north: [row(0), row(1)],
+            south: [row(last_row), row(last_row - 1)],
+            west: [column(0), column(1)],
+            east: [column(last_col), column(last_col - 1)],
+        })
+    }
+}
+
+/// Where the second tile of a seam lies
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    East,
+    South,
+}
+
+/// The comparison along the seam of two adjacent tiles
+#[derive(Clone, Debug, Serialize)]
+pub struct Seam {
+    pub tiles: [String; 2],       // West and east, or north and south tile
+    pub direction: Direction,     // Of the second tile from the first
+    pub shared_cells: bool,       // The tiles repeat a row or column of cells, compared directly instead of across the seam
+    pub line: [[f64; 2]; 2],      // Ends of the compared stretch in map coordinates
+    pub samples: usize,           // Cells valid on both sides
+    pub mean: f64,                // Mean difference, second tile minus first
+    pub rms: f64,
+    pub max: f64,                 // Largest difference either way
+    pub worst: [f64; 2],          // Where it is
+    pub flagged: bool,            // The mean exceeds the tolerance
+}
+
+/// Every seam of a set of tiles
+#[derive(Clone, Debug, Serialize)]
+pub struct EdgeReport {
+    pub tolerance: f64,
+    pub tiles: usize,
+    pub flagged: usize,
+    pub seams: Vec<Seam>,
+}
+
+impl EdgeReport {
+    /// Finds the tiles that touch or repeat one row or column of cells along a whole or partial edge, and compares
+    /// each such pair. Tiles of different cell sizes or overlapping by more than a cell are not compared.
+    pub fn check(tiles: &[TileEdges], tolerance: f64) -> EdgeReport {
+        let mut seams = Vec::new();
+        for a in tiles {
+            for b in tiles {
+                let cellsize = a.cellsize;
+                if std::ptr::eq(a, b) || (a.cellsize - b.cellsize).abs() > 0.01 * cellsize {
+                    continue;
+                }
+                let ([aw, as_, ae, an], [bw, bs, be, bn]) = (a.bounds, b.bounds);
+                let near = |gap: f64| gap.abs() < 0.25 * cellsize;
+                // East: b starts where a ends, or one column before
+                if an.min(bn) - as_.max(bs) > 0.5 * cellsize && (near(bw - ae) || near(bw - ae + cellsize)) {
+                    seams.extend(compare(a, b, Direction::East, near(bw - ae + cellsize), tolerance));
+                }
+                if ae.min(be) - aw.max(bw) > 0.5 * cellsize && (near(as_ - bn) || near(as_ - bn + cellsize)) {
+                    seams.extend(compare(a, b, Direction::South, near(as_ - bn + cellsize), tolerance));
+                }
+            }
+        }
+        let flagged = seams.iter().filter(|seam| seam.flagged).count();
+        EdgeReport { tolerance, tiles: tiles.len(), flagged, seams }
+    }
+
+    /// GeoJSON FeatureCollection of the seams as lines with their tiles, mean, max and whether they are flagged
+    pub fn write_geojson<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "{{\"type\":\"FeatureCollection\",\"features\":[")?;
+        for (i, seam) in self.seams.iter().enumerate() {
+            let [[x0, y0], [x1, y1]] = seam.line;
+            write!(out, "{}\n{{\"type\":\"Feature\",\"properties\":{{\"tiles\":{},\"mean\":{},\"max\":{},\"flagged\":{}}},\"geometry\":{{\"type\":\"LineString\",\"coordinates\":[[{},{}],[{},{}]]}}}}",
+                if i == 0 { "" } else { "," }, serde_json::json!(seam.tiles), seam.mean, seam.max, seam.flagged, x0, y0, x1, y1)?;
+        }
+        writeln!(out, "\n]}}")
+    }
+}
+
+/// Compares the edge of `a` facing `b` with the edge of `b` facing it. Across a seam between touching tiles the step
+/// from one outermost cell to the other is measured against the slope on either side, so only the jump remains.
+fn compare(a: &TileEdges, b: &TileEdges, direction: Direction, shared_cells: bool, tolerance: f64) -> Option<Seam> {
+    let cellsize = a.cellsize;
+    let ((a_side, b_side), seam) = match direction {
+        Direction::East => ((&a.east, &b.west), if shared_cells { a.bounds[2] - cellsize / 2.0 } else { a.bounds[2] }),
+        Direction::South => ((&a.south, &b.north), if shared_cells { a.bounds[1] + cellsize / 2.0 } else { a.bounds[1] }),
+    };
+    // Map coordinate of cell `i` along a side, and the cell of b at a coordinate
+    let along = |tile: &TileEdges, i: usize| match direction {
+        Direction::East => tile.bounds[3] - (i as f64 + 0.5) * cellsize,
+        Direction::South => tile.bounds[0] + (i as f64 + 0.5) * cellsize,
+    };
+    let index = |tile: &TileEdges, v: f64| {
+        let i = match direction {
+            Direction::East => (tile.bounds[3] - v) / cellsize - 0.5,
+            Direction::South => (v - tile.bounds[0]) / cellsize - 0.5,
+        };
+        Some(i.round()).filter(|i| *i >= 0.0).map(|i| i as usize)
+    };
+    let point = |v: f64| match direction {
+        Direction::East => [seam, v],
+        Direction::South => [v, seam],
+    };
+
+    let (mut samples, mut sum, mut squares, mut max, mut worst) = (0, 0.0, 0.0, 0.0f64, [0.0; 2]);
+    let (mut first, mut last) = (None, None);
+    for (i, (&a0, &a1)) in a_side[0].iter().zip(&a_side[1]).enumerate() {
+        let v = along(a, i);
+        let Some(j) = index(b, v).filter(|&j| j < b_side[0].len()) else { continue };
+        let (b0, b1) = (b_side[0][j], b_side[1][j]);
+        if a0.is_nan() || b0.is_nan() {
+            continue;
+        }
+        let difference = if shared_cells {
+            (b0 - a0) as f64
+        } else if !a1.is_nan() && !b1.is_nan() {
+            (b0 - a0) as f64 - ((a0 - a1) + (b1 - b0)) as f64 / 2.0
+        } else {
+            continue;
+        };
+        samples += 1;
+        sum += difference;
+        squares += difference * difference;
+        if difference.abs() > max.abs() || samples == 1 {
+            (max, worst) = (difference, point(v));
+        }
+        first = first.or(Some(v));
+        last = Some(v);
+    }
+    let (first, last) = (first?, last?);
+    let mean = sum / samples as f64;
+    Some(Seam {
+        tiles: [a.name.clone(), b.name.clone()],
+        direction,
+        shared_cells,
+        line: [point(first), point(last)],
+        samples,
+        mean,
+        rms: (squares / samples as f64).sqrt(),
+        max,
+        worst,
+        flagged: mean.abs() > tolerance,
+    })
+}
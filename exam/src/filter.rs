@@ -0,0 +1,147 @@
+//! Selection of the input files of a batch run by name, size and age (--include, --exclude, --min-size, --max-size,
+//! --newer-than), so a subset of a large archive can be rendered without moving files around.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Conditions an input file has to meet, all of them; the default lets every file through
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputFilter {
+    pub include: Vec<String>,            // Glob patterns of which the name has to match one, any name if empty
+    pub exclude: Vec<String>,            // Glob patterns the name must not match
+    pub min_size: Option<u64>,           // Smallest file size in bytes
+    pub max_size: Option<u64>,           // Largest file size in bytes
+    pub newer_than: Option<SystemTime>,  // Files modified before are left out
+}
+
+impl InputFilter {
+    /// Whether any condition is set
+    pub fn is_active(&self) -> bool {
+        *self != InputFilter::default()
+    }
+
+    /// Whether the file at `path`, at `relative` below the input folder, passes. Patterns with a `/` are matched
+    /// against the relative path, the others against the file name.
+    pub fn matches(&self, path: &Path, relative: &Path) -> io::Result<bool> {
+        let name = relative.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        let hit = |pattern: &String| glob_match(pattern, if pattern.contains('/') { &relative } else { &name });
+        if (!self.include.is_empty() && !self.include.iter().any(hit)) || self.exclude.iter().any(hit) {
+            return Ok(false);
+        }
+        if self.min_size.is_none() && self.max_size.is_none() && self.newer_than.is_none() {
+            return Ok(true);
+        }
+        let metadata = fs::metadata(path)?;
+        let size = metadata.len();
+        Ok(self.min_size.is_none_or(|min| size >= min)
+            && self.max_size.is_none_or(|max| size <= max)
+            && self.newer_than.is_none_or(|time| metadata.modified().is_ok_and(|modified| modified >= time)))
+    }
+}
+
+/// Shell-style match of a whole name: `*` any run of characters, `?` one character, `[abc]`, `[a-z]` and `[!abc]` one
+/// of a set. `*` and `?` don't cross a `/`.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None; // Pattern position after the last `*` and the name position it took
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+                continue;
+            }
+            Some('?') if name[n] != '/' => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            Some('[') => {
+                if let Some((matched, end)) = match_class(&pattern[p..], name[n]) {
+                    if matched {
+                        p += end;
+                        n += 1;
+                        continue;
+                    }
+                } else if name[n] == '[' {
+                    p += 1;
+                    n += 1;
+                    continue;
+                }
+            }
+            Some(&c) if c == name[n] => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            _ => {}
+        }
+        // Let the last `*` take one more character, unless that would cross a `/`
+        match star {
+            Some((after, taken)) if name[taken] != '/' => {
+                star = Some((after, taken + 1));
+                p = after;
+                n = taken + 1;
+            }
+            _ => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Whether `c` is in the set at the start of `pattern` ("[...]") and the length of the set, None if it isn't closed
+fn match_class(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let negated = matches!(pattern.get(1), Some('!' | '^'));
+    let start = if negated { 2 } else { 1 };
+    // A `]` right after the opening bracket is part of the set
+    let end = start + 1 + pattern.get(start + 1..)?.iter().position(|&c| c == ']')?;
+    let set = &pattern[start..end];
+    let mut found = false;
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == '-' {
+            found |= (set[i]..=set[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            found |= set[i] == c;
+            i += 1;
+        }
+    }
+    Some((found != negated, end + 1))
+}
+
+/// Reads a point in time: a UTC date `2024-05-01`, a date and time `2024-05-01T12:00:00` (optionally ending in Z), or an
+/// age before now as a number of days, hours or minutes: `7d`, `12h`, `30m`
+pub fn parse_time(text: &str) -> Result<SystemTime, String> {
+    let text = text.trim();
+    let invalid = || format!("Invalid time {:?}, expected YYYY-MM-DD, YYYY-MM-DDTHH:MM:SS or an age like 7d, 12h or 30m", text);
+    if let Some(unit) = text.chars().last().filter(|c| matches!(c, 'd' | 'h' | 'm')) {
+        let count: f64 = text[..text.len() - 1].parse().map_err(|_| invalid())?;
+        let seconds = count * match unit { 'd' => 86_400.0, 'h' => 3_600.0, _ => 60.0 };
+        return SystemTime::now().checked_sub(Duration::from_secs_f64(seconds.max(0.0))).ok_or_else(invalid);
+    }
+    let (date, time) = text.trim_end_matches('Z').split_once('T').unwrap_or((text, "00:00:00"));
+    let numbers = |part: &str, sep: char| part.split(sep).map(|v| v.parse::<i64>().ok()).collect::<Option<Vec<_>>>();
+    let (Some(&[year, month, day]), Some(&[hour, minute, second])) = (numbers(date, '-').as_deref(), numbers(time, ':').as_deref()) else {
+        return Err(invalid());
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return Err(invalid());
+    }
+    let seconds = days_from_civil(year, month, day) * 86_400 + hour * 3_600 + minute * 60 + second;
+    u64::try_from(seconds).map(|s| UNIX_EPOCH + Duration::from_secs(s)).map_err(|_| invalid())
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
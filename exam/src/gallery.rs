@@ -0,0 +1,116 @@
+//! `index.html` contact sheet of a batch run: every written image grouped by input, with its elevation stats.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::report::{Report, Status};
+
+/// File name of the gallery below the output root
+pub const GALLERY_FILE: &str = "index.html";
+
+/// Extensions browsers display inline, other outputs are listed as links
+const WEB_IMAGES: [&str; 6] = ["png", "jpg", "jpeg", "gif", "webp", "avif"];
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;background:#fafafa}\
+section{margin-bottom:2.5em}h2{margin-bottom:.2em}.stats{color:#555}.error{color:#b00}.warning{color:#a60}\
+.images{display:flex;flex-wrap:wrap;gap:12px}figure{margin:0;width:256px}\
+figure img{width:256px;height:192px;object-fit:contain;background:#222;border:1px solid #ccc}\
+figcaption{font-size:.85em;overflow-wrap:anywhere}";
+
+/// Writes the gallery of `report` into `root`, links are relative so the output folder can be moved as a whole
+pub fn write_gallery(report: &Report, root: &Path) -> io::Result<PathBuf> {
+    let mut html = String::new();
+    html += "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>exam gallery</title>";
+    html += &format!("<style>{}</style></head><body>\n", STYLE);
+    html += &format!("<h1>exam gallery</h1><p class=\"stats\">{} processed, {} failed, {} skipped, {:.1} s</p>\n",
+        report.processed, report.failed, report.skipped, report.seconds);
+
+    for input in &report.inputs {
+        let name = input.input.file_name().map_or_else(|| input.input.to_string_lossy(), |n| n.to_string_lossy());
+        html += &format!("<section id=\"{}\"><h2>{}</h2>\n", escape(&name), escape(&name));
+        if let Some(s) = &input.stats {
+            let nodata = 100.0 * s.nodata_cells as f64 / (s.ncols * s.nrows).max(1) as f64;
+            html += &format!("<p class=\"stats\">{} x {} cells of {}, elevation {} to {} (mean {:.2}), {:.1}% NoData</p>\n",
+                s.ncols, s.nrows, s.cellsize, s.min, s.max, s.mean, nodata);
+        }
+        if input.status == Status::Failed {
+            html += &format!("<p class=\"error\">Failed: {}</p>\n", escape(input.error.as_deref().unwrap_or_default()));
+        }
+        for warning in &input.warnings {
+            html += &format!("<p class=\"warning\">{}</p>\n", escape(warning));
+        }
+
+        let (images, others): (Vec<&PathBuf>, Vec<&PathBuf>) = input.outputs.iter()
+            .partition(|path| path.extension().is_some_and(|e| WEB_IMAGES.contains(&e.to_string_lossy().to_lowercase().as_str())));
+        // Thumbnails stand in for their images, overviews are linked below them
+        let (previews, images): (Vec<&PathBuf>, Vec<&PathBuf>) = images.into_iter().partition(|path| preview_of(path).is_some());
+        html += "<div class=\"images\">\n";
+        for path in images {
+            let (href, label) = (link(path, root), label(path, root));
+            let mut src = href.clone();
+            let mut overviews = Vec::new();
+            for preview in &previews {
+                match preview_of(preview) {
+                    Some((kind, image)) if image == *path && kind == "thumb" => src = link(preview, root),
+                    Some((kind, image)) if image == *path => overviews.push(format!("<a href=\"{}\">/{}</a>", link(preview, root), kind.trim_start_matches("ovr"))),
+                    _ => {}
+                }
+            }
+            html += &format!("<figure><a href=\"{}\"><img src=\"{}\" loading=\"lazy\" alt=\"{}\"></a><figcaption>{} {}</figcaption></figure>\n",
+                href, src, label, label, overviews.join(" "));
+        }
+        html += "</div>\n";
+        if !others.is_empty() {
+            let links: Vec<String> = others.iter().map(|path| format!("<a href=\"{}\">{}</a>", link(path, root), label(path, root))).collect();
+            html += &format!("<p>Other files: {}</p>\n", links.join(", "));
+        }
+        html += "</section>\n";
+    }
+    html += "</body></html>\n";
+
+    let path = root.join(GALLERY_FILE);
+    fs::write(&path, html)?;
+    Ok(path)
+}
+
+/// Kind ("thumb" or "ovr<factor>") and image path of a preview written by [`crate::output::Previews`].
+/// The product name comes before the suffix in any output pattern, so removing it gives the image's file name.
+fn preview_of(path: &Path) -> Option<(String, PathBuf)> {
+    let name = path.file_name()?.to_string_lossy();
+    let (start, suffix) = name.match_indices("_thumb").map(|(i, _)| (i, "_thumb".to_string())).last().or_else(|| {
+        name.match_indices("_ovr").filter_map(|(i, _)| {
+            let digits: String = name[i + 4..].chars().take_while(char::is_ascii_digit).collect();
+            (!digits.is_empty()).then(|| (i, format!("_ovr{}", digits)))
+        }).last()
+    })?;
+    let image = format!("{}{}", &name[..start], &name[start + suffix.len()..]);
+    Some((suffix[1..].to_string(), path.with_file_name(image)))
+}
+
+/// Path below the root, as written in the gallery
+fn relative<'a>(path: &'a Path, root: &Path) -> &'a Path {
+    path.strip_prefix(root).unwrap_or(path)
+}
+
+fn label(path: &Path, root: &Path) -> String {
+    escape(&relative(path, root).to_string_lossy())
+}
+
+/// Relative URL of an output, with the characters that would end or break it percent-encoded
+fn link(path: &Path, root: &Path) -> String {
+    relative(path, root).components()
+        .map(|c| c.as_os_str().to_string_lossy()
+            .chars()
+            .map(|c| match c {
+                '%' | ' ' | '#' | '?' | '"' | '<' | '>' | '&' => format!("%{:02X}", c as u32),
+                c => c.to_string(),
+            })
+            .collect::<String>())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
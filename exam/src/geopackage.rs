@@ -0,0 +1,294 @@
+//! OGC GeoPackages (`.gpkg`, `sqlite` feature): the rendered images as PNG tile pyramids and the vector products such
+//! as contours as feature tables, delivered together in one SQLite file that QGIS, ArcGIS and GDAL open directly.
+
+use std::io::{self, Cursor};
+use std::path::Path;
+
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+use rusqlite::{params, Connection};
+
+use crate::grid::Grid;
+
+/// Edge of a tile in pixels
+pub const TILE_SIZE: u32 = 256;
+
+/// "GPKG" and version 1.3.0, as the GeoPackage specification wants them in the SQLite header
+const APPLICATION_ID: i32 = 0x4750_4B47;
+const USER_VERSION: i32 = 10300;
+
+const WGS84_WKT: &str = "GEOGCS[\"WGS 84\",DATUM[\"WGS_1984\",SPHEROID[\"WGS 84\",6378137,298.257223563,AUTHORITY[\"EPSG\",\"7030\"]],\
+AUTHORITY[\"EPSG\",\"6326\"]],PRIMEM[\"Greenwich\",0,AUTHORITY[\"EPSG\",\"8901\"]],UNIT[\"degree\",0.0174532925199433,\
+AUTHORITY[\"EPSG\",\"9122\"]],AUTHORITY[\"EPSG\",\"4326\"]]";
+
+const SCHEMA: &str = "
+CREATE TABLE gpkg_spatial_ref_sys (
+    srs_name TEXT NOT NULL,
+    srs_id INTEGER PRIMARY KEY,
+    organization TEXT NOT NULL,
+    organization_coordsys_id INTEGER NOT NULL,
+    definition TEXT NOT NULL,
+    description TEXT
+);
+CREATE TABLE gpkg_contents (
+    table_name TEXT NOT NULL PRIMARY KEY,
+    data_type TEXT NOT NULL,           -- tiles or features
+    identifier TEXT UNIQUE,
+    description TEXT DEFAULT '',
+    last_change DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now')),
+    min_x DOUBLE, min_y DOUBLE, max_x DOUBLE, max_y DOUBLE,
+    srs_id INTEGER REFERENCES gpkg_spatial_ref_sys(srs_id)
+);
+CREATE TABLE gpkg_geometry_columns (
+    table_name TEXT NOT NULL PRIMARY KEY REFERENCES gpkg_contents(table_name),
+    column_name TEXT NOT NULL,
+    geometry_type_name TEXT NOT NULL,
+    srs_id INTEGER NOT NULL REFERENCES gpkg_spatial_ref_sys(srs_id),
+    z TINYINT NOT NULL,
+    m TINYINT NOT NULL
+);
+CREATE TABLE gpkg_tile_matrix_set (
+    table_name TEXT NOT NULL PRIMARY KEY REFERENCES gpkg_contents(table_name),
+    srs_id INTEGER NOT NULL REFERENCES gpkg_spatial_ref_sys(srs_id),
+    min_x DOUBLE NOT NULL, min_y DOUBLE NOT NULL, max_x DOUBLE NOT NULL, max_y DOUBLE NOT NULL
+);
+CREATE TABLE gpkg_tile_matrix (
+    table_name TEXT NOT NULL REFERENCES gpkg_contents(table_name),
+    zoom_level INTEGER NOT NULL,
+    matrix_width INTEGER NOT NULL,
+    matrix_height INTEGER NOT NULL,
+    tile_width INTEGER NOT NULL,
+    tile_height INTEGER NOT NULL,
+    pixel_x_size DOUBLE NOT NULL,
+    pixel_y_size DOUBLE NOT NULL,
+    PRIMARY KEY (table_name, zoom_level)
+);
+";
+
+/// Geometry of a feature in map coordinates
+#[derive(Clone, Debug, PartialEq)]
+pub enum Geometry {
+    LineString(Vec<[f64; 2]>),
+    MultiPolygon(Vec<Vec<Vec<[f64; 2]>>>), // Polygons of an outer ring and its holes, rings closed
+}
+
+impl Geometry {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Geometry::LineString(_) => "LINESTRING",
+            Geometry::MultiPolygon(_) => "MULTIPOLYGON",
+        }
+    }
+
+    /// GeoPackage binary: the "GP" header with the srs and the xy envelope, then little-endian WKB
+    fn encode(&self, srs_id: i32) -> Vec<u8> {
+        let points: Vec<&[f64; 2]> = match self {
+            Geometry::LineString(points) => points.iter().collect(),
+            Geometry::MultiPolygon(polygons) => polygons.iter().flatten().flatten().collect(),
+        };
+        let envelope = points.iter().fold([f64::INFINITY, f64::NEG_INFINITY, f64::INFINITY, f64::NEG_INFINITY],
+            |e, p| [e[0].min(p[0]), e[1].max(p[0]), e[2].min(p[1]), e[3].max(p[1])]);
+        let mut bytes = vec![b'G', b'P', 0, 0b0000_0011]; // Version 0, little-endian with an [x min, x max, y min, y max] envelope
+        bytes.extend(srs_id.to_le_bytes());
+        envelope.iter().for_each(|v| bytes.extend(v.to_le_bytes()));
+        let ring = |bytes: &mut Vec<u8>, points: &[[f64; 2]]| {
+            bytes.extend((points.len() as u32).to_le_bytes());
+            points.iter().for_each(|p| bytes.extend(p[0].to_le_bytes().into_iter().chain(p[1].to_le_bytes())));
+        };
+        match self {
+            Geometry::LineString(points) => {
+                bytes.extend([1, 2, 0, 0, 0]);
+                ring(&mut bytes, points);
+            }
+            Geometry::MultiPolygon(polygons) => {
+                bytes.extend([1, 6, 0, 0, 0]);
+                bytes.extend((polygons.len() as u32).to_le_bytes());
+                for polygon in polygons {
+                    bytes.extend([1, 3, 0, 0, 0]);
+                    bytes.extend((polygon.len() as u32).to_le_bytes());
+                    polygon.iter().for_each(|r| ring(&mut bytes, r));
+                }
+            }
+        }
+        bytes
+    }
+}
+
+/// A column of a feature table and its values
+#[derive(Clone, Debug, PartialEq)]
+pub enum Column {
+    Real(&'static str, Vec<f64>),
+    Integer(&'static str, Vec<i64>),
+    Text(&'static str, Vec<String>),
+}
+
+fn sql_error(e: rusqlite::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+/// Table names are quoted into SQL, so they are kept to letters, digits and `_`
+fn table_name(name: &str) -> String {
+    let name: String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' }).collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) { format!("t_{}", name) } else { name }
+}
+
+/// A GeoPackage being written
+pub struct GeoPackage {
+    conn: Connection,
+    srs_id: i32,
+}
+
+impl GeoPackage {
+    /// Creates `path`, replacing an existing file. With an EPSG code the contents are declared in that system,
+    /// otherwise in the undefined Cartesian one (srs_id -1).
+    pub fn create(path: &Path, epsg: Option<u32>) -> io::Result<GeoPackage> {
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        let conn = Connection::open(path).map_err(sql_error)?;
+        conn.pragma_update(None, "application_id", APPLICATION_ID).map_err(sql_error)?;
+        conn.pragma_update(None, "user_version", USER_VERSION).map_err(sql_error)?;
+        conn.execute_batch(SCHEMA).map_err(sql_error)?;
+        let srs = "INSERT INTO gpkg_spatial_ref_sys (srs_name, srs_id, organization, organization_coordsys_id, definition) VALUES (?1, ?2, ?3, ?4, ?5)";
+        conn.execute(srs, params!["Undefined Cartesian SRS", -1, "NONE", -1, "undefined"]).map_err(sql_error)?;
+        conn.execute(srs, params!["Undefined geographic SRS", 0, "NONE", 0, "undefined"]).map_err(sql_error)?;
+        conn.execute(srs, params!["WGS 84 geodetic", 4326, "EPSG", 4326, WGS84_WKT]).map_err(sql_error)?;
+        let srs_id = match epsg {
+            Some(4326) => 4326,
+            Some(code) => {
+                conn.execute(srs, params![format!("EPSG:{}", code), code, "EPSG", code, "undefined"]).map_err(sql_error)?;
+                code as i32
+            }
+            None => -1,
+        };
+        Ok(GeoPackage { conn, srs_id })
+    }
+
+    /// Adds `img`, rendered from `grid` one pixel per cell, as the tile table `name`: the deepest zoom at the cell
+    /// size, each zoom above at twice the pixel size, up to zoom 0 of one tile. NoData and the area beyond the grid
+    /// are transparent, and tiles with nothing in them left out. Returns the number of tiles written.
+    pub fn add_tiles(&mut self, name: &str, img: &DynamicImage, grid: &Grid) -> io::Result<usize> {
+        let table = table_name(name);
+        let mut level = img.to_rgba8();
+        for (x, y, pixel) in level.enumerate_pixels_mut() {
+            if !grid.is_valid(x as usize, y as usize) {
+                *pixel = Rgba([0, 0, 0, 0]);
+            }
+        }
+        let mut zooms = 0;
+        while (TILE_SIZE << zooms) < grid.ncols.max(grid.nrows) as u32 {
+            zooms += 1;
+        }
+        // The matrix set is the square zoom 0 covers, from the north-west corner of the grid
+        let (x0, y0) = grid.origin();
+        let extent = (TILE_SIZE << zooms) as f64 * grid.cellsize;
+        let (left, bottom, right, top) = (x0, grid.yllcorner, x0 + grid.ncols as f64 * grid.cellsize, y0);
+
+        let tx = self.conn.transaction().map_err(sql_error)?;
+        tx.execute_batch(&format!("CREATE TABLE \"{0}\" (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            zoom_level INTEGER NOT NULL,
+            tile_column INTEGER NOT NULL,
+            tile_row INTEGER NOT NULL,
+            tile_data BLOB NOT NULL,
+            UNIQUE (zoom_level, tile_column, tile_row));", table)).map_err(sql_error)?;
+        tx.execute("INSERT INTO gpkg_contents (table_name, data_type, identifier, min_x, min_y, max_x, max_y, srs_id) VALUES (?1, 'tiles', ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![table, name, left, bottom, right, top, self.srs_id]).map_err(sql_error)?;
+        tx.execute("INSERT INTO gpkg_tile_matrix_set VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![table, self.srs_id, x0, y0 - extent, x0 + extent, y0]).map_err(sql_error)?;
+        let insert = format!("INSERT INTO \"{}\" (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4)", table);
+        let mut tiles = 0;
+        for zoom in (0..=zooms).rev() {
+            let side = 1u32 << zoom;
+            let pixel = grid.cellsize * (1u32 << (zooms - zoom)) as f64;
+            tx.execute("INSERT INTO gpkg_tile_matrix VALUES (?1, ?2, ?3, ?3, ?4, ?4, ?5, ?5)", params![table, zoom, side, TILE_SIZE, pixel])
+                .map_err(sql_error)?;
+            for row in 0..level.height().div_ceil(TILE_SIZE) {
+                for col in 0..level.width().div_ceil(TILE_SIZE) {
+                    if let Some(png) = encode_tile(&level, col, row)? {
+                        tx.execute(&insert, params![zoom, col, row, png]).map_err(sql_error)?;
+                        tiles += 1;
+                    }
+                }
+            }
+            level = half_size(&level);
+        }
+        tx.commit().map_err(sql_error)?;
+        Ok(tiles)
+    }
+
+    /// Adds the feature table `name` of `geometries`, each with its value in every column
+    pub fn add_features(&mut self, name: &str, geometries: &[Geometry], columns: &[Column]) -> io::Result<()> {
+        let table = table_name(name);
+        let type_name = geometries.first().map_or("GEOMETRY", Geometry::type_name);
+        let points = geometries.iter().flat_map(|g| match g {
+            Geometry::LineString(points) => points.clone(),
+            Geometry::MultiPolygon(polygons) => polygons.iter().flatten().flatten().copied().collect(),
+        });
+        let b = points.fold([f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY],
+            |b, p| [b[0].min(p[0]), b[1].min(p[1]), b[2].max(p[0]), b[3].max(p[1])]);
+        let bounds = b.iter().all(|v| v.is_finite()).then_some(b);
+        let definitions: Vec<String> = columns.iter().map(|column| match column {
+            Column::Real(name, _) => format!(", \"{}\" REAL", table_name(name)),
+            Column::Integer(name, _) => format!(", \"{}\" INTEGER", table_name(name)),
+            Column::Text(name, _) => format!(", \"{}\" TEXT", table_name(name)),
+        }).collect();
+
+        let tx = self.conn.transaction().map_err(sql_error)?;
+        tx.execute_batch(&format!("CREATE TABLE \"{}\" (fid INTEGER PRIMARY KEY AUTOINCREMENT, geom {}{});", table, type_name, definitions.concat()))
+            .map_err(sql_error)?;
+        tx.execute("INSERT INTO gpkg_contents (table_name, data_type, identifier, min_x, min_y, max_x, max_y, srs_id) VALUES (?1, 'features', ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![table, name, bounds.map(|b| b[0]), bounds.map(|b| b[1]), bounds.map(|b| b[2]), bounds.map(|b| b[3]), self.srs_id]).map_err(sql_error)?;
+        tx.execute("INSERT INTO gpkg_geometry_columns VALUES (?1, 'geom', ?2, ?3, 0, 0)", params![table, type_name, self.srs_id]).map_err(sql_error)?;
+        {
+            let placeholders: String = (0..columns.len()).map(|i| format!(", ?{}", i + 2)).collect();
+            let names: String = columns.iter().map(|c| match c {
+                Column::Real(name, _) | Column::Integer(name, _) | Column::Text(name, _) => format!(", \"{}\"", table_name(name)),
+            }).collect();
+            let mut insert = tx.prepare(&format!("INSERT INTO \"{}\" (geom{}) VALUES (?1{})", table, names, placeholders)).map_err(sql_error)?;
+            for (i, geometry) in geometries.iter().enumerate() {
+                let mut values: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(geometry.encode(self.srs_id))];
+                for column in columns {
+                    values.push(match column {
+                        Column::Real(_, v) => Box::new(v.get(i).copied()),
+                        Column::Integer(_, v) => Box::new(v.get(i).copied()),
+                        Column::Text(_, v) => Box::new(v.get(i).cloned()),
+                    });
+                }
+                insert.execute(rusqlite::params_from_iter(values.iter().map(|v| v.as_ref()))).map_err(sql_error)?;
+            }
+        }
+        tx.commit().map_err(sql_error)
+    }
+}
+
+/// The tile at `col`, `row` of a level as PNG, transparent past its edges; None when nothing in it is opaque
+fn encode_tile(level: &RgbaImage, col: u32, row: u32) -> io::Result<Option<Vec<u8>>> {
+    let mut tile = RgbaImage::new(TILE_SIZE, TILE_SIZE);
+    image::imageops::replace(&mut tile, level, -((col * TILE_SIZE) as i64), -((row * TILE_SIZE) as i64));
+    if tile.pixels().all(|p| p.0[3] == 0) {
+        return Ok(None);
+    }
+    let mut png = Cursor::new(Vec::new());
+    DynamicImage::ImageRgba8(tile).write_to(&mut png, ImageFormat::Png).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    Ok(Some(png.into_inner()))
+}
+
+/// The level above: every pixel the mean of the 2x2 pixels below it, weighted by their opacity
+fn half_size(level: &RgbaImage) -> RgbaImage {
+    RgbaImage::from_fn(level.width().div_ceil(2), level.height().div_ceil(2), |px, py| {
+        let (mut sum, mut alpha) = ([0u32; 3], 0u32);
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            if let Some(&Rgba([r, g, b, a])) = level.get_pixel_checked(2 * px + dx, 2 * py + dy) {
+                for (s, c) in sum.iter_mut().zip([r, g, b]) {
+                    *s += c as u32 * a as u32;
+                }
+                alpha += a as u32;
+            }
+        }
+        if alpha == 0 {
+            return Rgba([0, 0, 0, 0]);
+        }
+        let [r, g, b] = sum.map(|s| ((s + alpha / 2) / alpha) as u8);
+        Rgba([r, g, b, ((alpha + 2) / 4) as u8])
+    })
+}
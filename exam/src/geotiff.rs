@@ -0,0 +1,206 @@
+//! Reading single-band GeoTIFF elevation models, e.g. the Float32 files written by `--raw tiff`.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, Cursor, Read, Seek};
+use std::path::Path;
+
+use tiff::decoder::{Decoder, DecodingResult, Limits};
+use tiff::tags::Tag;
+use tiff::ColorType;
+
+use crate::asc::parse_asc_bytes;
+use crate::e00;
+use crate::grid::{Elevation, Grid, GridInfo};
+use crate::sniff::{sniff, InputFormat, SNIFF_LEN};
+
+/// Whether `bytes` start with a little or big endian TIFF (or BigTIFF) signature
+pub fn is_tiff(bytes: &[u8]) -> bool {
+    matches!(bytes.get(..4), Some(b"II*\0" | b"MM\0*" | b"II+\0" | b"MM\0+"))
+}
+
+/// Parses a GeoTIFF, an Arc/Info export or the text of an ESRI or GRASS ASCII grid, recognized by the content
+pub fn parse_grid_bytes(bytes: &[u8]) -> io::Result<Grid> {
+    match sniff(&bytes[..bytes.len().min(SNIFF_LEN)]).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))? {
+        InputFormat::GeoTiff => parse_geotiff(bytes),
+        InputFormat::E00 => e00::parse_e00(&String::from_utf8_lossy(bytes)),
+        InputFormat::EsriAscii | InputFormat::GrassAscii => parse_asc_bytes(bytes),
+        format => Err(io::Error::new(io::ErrorKind::Unsupported, format!("{} has to be read from a file path", format.name()))),
+    }
+}
+
+/// Loads a GeoTIFF from disk
+pub fn load_geotiff(path: &Path) -> io::Result<Grid> {
+    parse_geotiff(&fs::read(path)?)
+}
+
+/// Decodes the first image of a single-band TIFF into a grid.
+/// The georeferencing comes from ModelPixelScale and ModelTiepoint, NoData from the GDAL_NODATA tag;
+/// without them the cell size is 1, the origin 0 and NoData -99999 as in an .asc file without those lines.
+pub fn parse_geotiff(bytes: &[u8]) -> io::Result<Grid> {
+    parse_geotiff_as(bytes)
+}
+
+/// Loads a GeoTIFF from disk keeping values of type `T`, e.g. `load_geotiff_as::<i16>` for an Int16 SRTM tile
+pub fn load_geotiff_as<T: Elevation>(path: &Path) -> io::Result<Grid<T>> {
+    parse_geotiff_as(&fs::read(path)?)
+}
+
+/// Like [`parse_geotiff`] with values of type `T`, failing if a sample has no exact equivalent in `T`.
+/// NoData defaults to [`Elevation::DEFAULT_NODATA`].
+pub fn parse_geotiff_as<T: Elevation>(bytes: &[u8]) -> io::Result<Grid<T>> {
+    let mut decoder = Decoder::new(Cursor::new(bytes)).map_err(to_io)?.with_limits(Limits::unlimited());
+    let header = read_header(&mut decoder)?;
+    let (ncols, nrows) = (header.ncols, header.nrows);
+
+    let mut values = samples::<T>(decoder.read_image().map_err(to_io)?)?;
+    if values.len() < ncols * nrows {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "TIFF holds fewer values than its size"));
+    }
+    let nodata = header.nodata();
+    values.iter_mut().filter(|v| v.is_nan()).for_each(|v| *v = nodata);
+    let data = values.chunks_exact(ncols.max(1)).take(nrows).map(|row| row.to_vec()).collect();
+
+    Ok(Grid { data, ncols, nrows, nodata, cellsize: header.cellsize, xllcorner: header.x0, yllcorner: header.y0 - nrows as f64 * header.cellsize, geographic: None })
+}
+
+/// Reads only the `ncols` x `nrows` cells from column `col_off` and row `row_off` (counted from the north) of a
+/// GeoTIFF file, decoding just the strips or tiles the window touches
+pub fn read_geotiff_window(path: &Path, col_off: usize, row_off: usize, ncols: usize, nrows: usize) -> io::Result<Grid> {
+    let mut decoder = Decoder::new(BufReader::new(File::open(path)?)).map_err(to_io)?.with_limits(Limits::unlimited());
+    let header = read_header(&mut decoder)?;
+    if col_off + ncols > header.ncols || row_off + nrows > header.nrows || ncols == 0 || nrows == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+            "Window of {}x{} cells at column {} and row {} is empty or outside the {}x{} grid", ncols, nrows, col_off, row_off, header.ncols, header.nrows)));
+    }
+
+    // Strips span the whole width, so they are chunks of one column
+    let (chunk_width, chunk_height) = decoder.chunk_dimensions();
+    let (chunk_width, chunk_height) = (chunk_width as usize, chunk_height as usize);
+    let across = header.ncols.div_ceil(chunk_width);
+    let nodata = header.nodata::<f32>();
+    let mut data = vec![vec![nodata; ncols]; nrows];
+    for chunk_row in row_off / chunk_height..=(row_off + nrows - 1) / chunk_height {
+        for chunk_col in col_off / chunk_width..=(col_off + ncols - 1) / chunk_width {
+            let index = (chunk_row * across + chunk_col) as u32;
+            let (width, height) = decoder.chunk_data_dimensions(index);
+            let values = samples::<f32>(decoder.read_chunk(index).map_err(to_io)?)?;
+            let (top, left) = (chunk_row * chunk_height, chunk_col * chunk_width);
+            for r in top.max(row_off)..(top + height as usize).min(row_off + nrows) {
+                for c in left.max(col_off)..(left + width as usize).min(col_off + ncols) {
+                    let val = values[(r - top) * width as usize + c - left];
+                    data[r - row_off][c - col_off] = if val.is_nan() { nodata } else { val };
+                }
+            }
+        }
+    }
+
+    Ok(Grid {
+        data, ncols, nrows, nodata, cellsize: header.cellsize,
+        xllcorner: header.x0 + col_off as f64 * header.cellsize,
+        yllcorner: header.y0 - (row_off + nrows) as f64 * header.cellsize,
+        geographic: None,
+    })
+}
+
+/// Reads the size, georeferencing and NoData tags of a GeoTIFF file without decoding its samples
+pub fn read_geotiff_info(path: &Path) -> io::Result<GridInfo> {
+    let mut decoder = Decoder::new(BufReader::new(File::open(path)?)).map_err(to_io)?;
+    let header = read_header(&mut decoder)?;
+    Ok(GridInfo {
+        ncols: header.ncols, nrows: header.nrows, cellsize: header.cellsize, xllcorner: header.x0,
+        yllcorner: header.y0 - header.nrows as f64 * header.cellsize, nodata: header.nodata(),
+    })
+}
+
+/// Size, upper left corner, cell size and NoData of the first image
+struct TiffHeader {
+    ncols: usize,
+    nrows: usize,
+    x0: f64,
+    y0: f64,
+    cellsize: f64,
+    nodata: Option<String>, // GDAL_NODATA text
+}
+
+impl TiffHeader {
+    /// NoData as a value of type `T`, read like the samples
+    fn nodata<T: Elevation>(&self) -> T {
+        let text = self.nodata.as_deref().map(|s| s.trim_matches(char::from(0)).trim());
+        text.and_then(|s| s.parse().ok().or_else(|| s.parse().ok().and_then(T::from_f64)))
+            .filter(|v: &T| !v.is_nan()) // GDAL writes "nan" for float rasters, their NaN cells are mapped to the default
+            .unwrap_or(T::DEFAULT_NODATA)
+    }
+}
+
+fn to_io(e: tiff::TiffError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Checks the image is a single band and reads its georeferencing tags
+fn read_header<R: Read + Seek>(decoder: &mut Decoder<R>) -> io::Result<TiffHeader> {
+    if !matches!(decoder.colortype().map_err(to_io)?, ColorType::Gray(_)) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "only single-band GeoTIFFs hold elevations"));
+    }
+    let (width, height) = decoder.dimensions().map_err(to_io)?;
+    let (ncols, nrows) = (width as usize, height as usize);
+
+    let scale = decoder.find_tag(Tag::ModelPixelScaleTag).map_err(to_io)?.map(|v| v.into_f64_vec()).transpose().map_err(to_io)?;
+    let tiepoint = decoder.find_tag(Tag::ModelTiepointTag).map_err(to_io)?.map(|v| v.into_f64_vec()).transpose().map_err(to_io)?;
+    let nodata = decoder.find_tag(Tag::GdalNodata).map_err(to_io)?.map(|v| v.into_string()).transpose().map_err(to_io)?;
+
+    let cellsize = scale.as_ref().and_then(|s| s.first().copied()).unwrap_or(1.0);
+    // Tie point (i, j, k, x, y, z): raster position (i, j) sits at map position (x, y)
+    let (x0, y0) = match tiepoint.as_deref() {
+        Some([i, j, _, x, y, ..]) => (x - i * cellsize, y + j * cellsize),
+        _ => (0.0, nrows as f64 * cellsize),
+    };
+    Ok(TiffHeader { ncols, nrows, x0, y0, cellsize, nodata })
+}
+
+/// Samples as values of type `T`, moved without a copy when they already are
+fn samples<T: Elevation>(result: DecodingResult) -> io::Result<Vec<T>> {
+    let exact = |v: f64| T::from_f64(v).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("The sample {} does not fit in {}", v, std::any::type_name::<T>())));
+    match result {
+        DecodingResult::U8(v) => v.into_iter().map(|x| exact(x as f64)).collect(),
+        DecodingResult::U16(v) => cast_or(v, |x| exact(x as f64)),
+        DecodingResult::U32(v) => v.into_iter().map(|x| exact(x as f64)).collect(),
+        DecodingResult::U64(v) => v.into_iter().map(|x| exact(x as f64)).collect(),
+        DecodingResult::I8(v) => v.into_iter().map(|x| exact(x as f64)).collect(),
+        DecodingResult::I16(v) => cast_or(v, |x| exact(x as f64)),
+        DecodingResult::I32(v) => cast_or(v, |x| exact(x as f64)),
+        DecodingResult::I64(v) => v.into_iter().map(|x| exact(x as f64)).collect(),
+        DecodingResult::F32(v) => cast_or(v, |x| exact(x as f64)),
+        DecodingResult::F64(v) => cast_or(v, exact),
+    }
+}
+
+/// `values` itself if `S` is `T`, otherwise every value converted by `convert`
+fn cast_or<S: 'static, T: 'static>(values: Vec<S>, convert: impl Fn(S) -> io::Result<T>) -> io::Result<Vec<T>> {
+    let mut values = Some(values);
+    if let Some(same) = (&mut values as &mut dyn std::any::Any).downcast_mut::<Option<Vec<T>>>() {
+        return Ok(same.take().unwrap_or_default());
+    }
+    values.into_iter().flatten().map(convert).collect()
+}
+
+/// EPSG code of a GeoTIFF from its GeoKeyDirectory: ProjectedCSTypeGeoKey (3072), else GeographicTypeGeoKey (2048)
+pub fn epsg_code(bytes: &[u8]) -> Option<u32> {
+    let mut decoder = Decoder::new(Cursor::new(bytes)).ok()?;
+    let keys = decoder.find_tag(Tag::GeoKeyDirectoryTag).ok()??.into_u16_vec().ok()?;
+    // Header of 4 values, then (key, location, count, value) entries; location 0 means the value is inline
+    let key = |id: u16| keys.get(4..)?.chunks_exact(4).find(|k| k[0] == id && k[1] == 0).map(|k| k[3] as u32);
+    // 32767 is "user-defined", not an EPSG code
+    [3072, 2048].into_iter().filter_map(key).find(|&code| code != 32767)
+}
+
+/// Upper left corner and pixel width and height (x0, y0, dx, dy) in map units from ModelPixelScale and ModelTiepoint,
+/// None for a TIFF without them
+pub fn model_transform(bytes: &[u8]) -> Option<(f64, f64, f64, f64)> {
+    let mut decoder = Decoder::new(Cursor::new(bytes)).ok()?;
+    let scale = decoder.find_tag(Tag::ModelPixelScaleTag).ok()??.into_f64_vec().ok()?;
+    let tiepoint = decoder.find_tag(Tag::ModelTiepointTag).ok()??.into_f64_vec().ok()?;
+    match (&scale[..], &tiepoint[..]) {
+        ([dx, dy, ..], [i, j, _, x, y, ..]) => Some((x - i * dx, y + j * dy, *dx, *dy)),
+        _ => None,
+    }
+}
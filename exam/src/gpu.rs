@@ -0,0 +1,345 @@
+//! Hillshade on the GPU with wgpu compute shaders (`--gpu`, needs the `gpu` feature): Horn's hillshade, and the
+//! horizon angles behind the `shadows` and `sky-view` shaders.
+
+use std::io;
+use std::sync::mpsc;
+
+use wgpu::util::DeviceExt;
+
+use crate::grid::{EdgeMode, Grid};
+use crate::horizon::Lattice;
+use crate::render::ShadeParams;
+use crate::shading::{ShaderKind, Shadows, SkyView};
+
+const WORKGROUP_SIZE: u32 = 16; // Must match @workgroup_size in hillshade.wgsl and of `resample` in horizon.wgsl
+const SWEEP_SIZE: u32 = 64;     // Must match @workgroup_size of `sweep` in horizon.wgsl
+
+/// Uniform block of hillshade.wgsl
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Uniforms {
+    ncols: u32,
+    nrows: u32,
+    cell_width: f32,
+    cell_height: f32,
+    z_factor: f32,
+    azimuth: f32,
+    altitude: f32,
+    nodata: f32,
+    edges: u32,   // 0 replicate, 1 mirror, 2 NoData, see [`EdgeMode`]
+    shadows: u32, // 1 reads the horizon angles toward the sun
+    ambient: f32, // Light left in a cast shadow
+    pad: u32,
+}
+
+/// Uniform block of horizon.wgsl, the [`Lattice`] of one azimuth
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct HorizonUniforms {
+    ncols: u32,
+    nrows: u32,
+    samples: u32,
+    lines: u32,
+    east: f32,
+    north: f32,
+    dx: f32,
+    dy: f32,
+    step: f32,
+    u_min: f32,
+    v_min: f32,
+    nodata: f32,
+    accumulate: u32, // 1 sums the sky-view factor's blocked share instead of writing the angle
+    pad: [u32; 3],
+}
+
+/// GPU device with the compiled hillshade and horizon pipelines, created once and reused for every grid and frame
+pub struct GpuHillshader {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    sweep: wgpu::ComputePipeline,    // Horizons of the lattice, one invocation per line
+    resample: wgpu::ComputePipeline, // Horizons of the cells from the lattice
+    adapter: String,                 // Name and backend of the adapter, for logging
+}
+
+fn gpu_error<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e.to_string())
+}
+
+impl GpuHillshader {
+    /// Picks the default adapter and compiles the shaders, fails if no GPU is available
+    pub fn new() -> io::Result<GpuHillshader> {
+        let instance = wgpu::Instance::default();
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            ..Default::default()
+        }))
+        .map_err(gpu_error)?;
+        let info = adapter.get_info();
+        let adapter_name = format!("{} ({:?})", info.name, info.backend);
+
+        // Ask for everything the adapter offers so large rasters fit into a single storage buffer
+        let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+            label: Some("exam"),
+            required_limits: adapter.limits(),
+            ..Default::default()
+        }))
+        .map_err(gpu_error)?;
+
+        let hillshade = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("hillshade"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("hillshade.wgsl").into()),
+        });
+        let horizon = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("horizon"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("horizon.wgsl").into()),
+        });
+        let compile = |module: &wgpu::ShaderModule, entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: None,
+                module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        let pipeline = compile(&hillshade, "main");
+        let sweep = compile(&horizon, "sweep");
+        let resample = compile(&horizon, "resample");
+
+        Ok(GpuHillshader { device, queue, pipeline, sweep, resample, adapter: adapter_name })
+    }
+
+    /// Name and backend of the adapter in use
+    pub fn adapter_name(&self) -> &str {
+        &self.adapter
+    }
+
+    /// Whether the shader of `params` runs on the GPU; the others, and every shader under a fill light, stay on the CPU
+    pub fn supports(params: &ShadeParams) -> bool {
+        matches!(params.shader, ShaderKind::Horn | ShaderKind::Shadows | ShaderKind::SkyView) && params.fill.is_none()
+    }
+
+    /// Same result as [`crate::render::hillshade_values`] for the shaders of [`GpuHillshader::supports`]
+    pub fn hillshade_values(&self, grid: &Grid, params: &ShadeParams) -> io::Result<Vec<u8>> {
+        self.check_size(grid)?;
+        if grid.ncols * grid.nrows == 0 {
+            return Ok(Vec::new());
+        }
+        let (elevation, nodata) = self.elevation(grid);
+        match params.shader {
+            ShaderKind::Horn => self.shade(grid, params, &elevation, nodata, None),
+            ShaderKind::Shadows => {
+                let horizon = self.horizons(grid, &elevation, nodata, &[params.azimuth], false)?;
+                self.shade(grid, params, &elevation, nodata, Some((&horizon, Shadows::default().ambient)))
+            }
+            ShaderKind::SkyView => {
+                let directions = SkyView::default().directions.max(1);
+                let azimuths: Vec<f64> = (0..directions).map(|k| 360.0 * k as f64 / directions as f64).collect();
+                let blocked = self.read_back(&self.horizons(grid, &elevation, nodata, &azimuths, true)?)?;
+                Ok(grid.iter_cells().zip(bytemuck::cast_slice::<u8, f32>(&blocked))
+                    .map(|((x, y, _), &b)| if grid.is_valid(x, y) { (255.0 * (1.0 - b as f64 / directions as f64)) as u8 } else { 0 })
+                    .collect())
+            }
+            other => Err(gpu_error(format!("the {} shader has no GPU version", other.name()))),
+        }
+    }
+
+    /// Fails for grids past the buffer or dispatch limits of the device
+    fn check_size(&self, grid: &Grid) -> io::Result<()> {
+        let cells = grid.ncols * grid.nrows;
+        let size = (cells * 4) as u64;
+        let limits = self.device.limits();
+        if size > limits.max_storage_buffer_binding_size as u64 || size > limits.max_buffer_size {
+            return Err(gpu_error(format!("{} cells exceed the GPU buffer limit", cells)));
+        }
+        let groups = (grid.ncols as u32).div_ceil(WORKGROUP_SIZE).max((grid.nrows as u32).div_ceil(WORKGROUP_SIZE));
+        if groups > limits.max_compute_workgroups_per_dimension {
+            return Err(gpu_error(format!("{} × {} cells exceed the GPU dispatch limit", grid.ncols, grid.nrows)));
+        }
+        Ok(())
+    }
+
+    /// The elevations uploaded, NoData and NaN both as the returned marker the shaders compare with
+    fn elevation(&self, grid: &Grid) -> (wgpu::Buffer, f32) {
+        let nodata = if grid.nodata.is_nan() { f32::MIN } else { grid.nodata };
+        let elevation: Vec<f32> = grid.data.iter().flatten().map(|&v| if grid.is_nodata(v) { nodata } else { v }).collect();
+        let buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("elevation"),
+            contents: bytemuck::cast_slice(&elevation),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        (buffer, nodata)
+    }
+
+    /// Runs hillshade.wgsl, with the horizon angles toward the sun and the light left in their shadows if given
+    fn shade(&self, grid: &Grid, params: &ShadeParams, elevation: &wgpu::Buffer, nodata: f32, shadows: Option<(&wgpu::Buffer, f64)>) -> io::Result<Vec<u8>> {
+        let uniforms = Uniforms {
+            ncols: grid.ncols as u32,
+            nrows: grid.nrows as u32,
+            cell_width: params.cell_size.0 as f32,
+            cell_height: params.cell_size.1 as f32,
+            z_factor: params.z_factor as f32,
+            azimuth: params.azimuth as f32,
+            altitude: params.altitude as f32,
+            nodata,
+            edges: match params.edges {
+                EdgeMode::Replicate => 0,
+                EdgeMode::Mirror => 1,
+                EdgeMode::NoData => 2,
+            },
+            shadows: shadows.is_some() as u32,
+            ambient: shadows.map_or(1.0, |(_, ambient)| ambient as f32),
+            pad: 0,
+        };
+        let uniform_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("params"),
+            contents: bytemuck::bytes_of(&uniforms),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let shade_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("shade"),
+            size: (grid.ncols * grid.nrows * 4) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        // The binding has to be filled even when the shader doesn't read it
+        let placeholder;
+        let horizon = match shadows {
+            Some((horizon, _)) => horizon,
+            None => {
+                placeholder = self.device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("no horizon"),
+                    size: 4,
+                    usage: wgpu::BufferUsages::STORAGE,
+                    mapped_at_creation: false,
+                });
+                &placeholder
+            }
+        };
+
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("hillshade"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: uniform_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: elevation.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: shade_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 3, resource: horizon.as_entire_binding() },
+            ],
+        });
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("hillshade") });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("hillshade"), timestamp_writes: None });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(uniforms.ncols.div_ceil(WORKGROUP_SIZE), uniforms.nrows.div_ceil(WORKGROUP_SIZE), 1);
+        }
+        self.queue.submit([encoder.finish()]);
+
+        let shade = self.read_back(&shade_buffer)?;
+        Ok(bytemuck::cast_slice::<u8, u32>(&shade).iter().map(|&v| v as u8).collect())
+    }
+
+    /// Runs horizon.wgsl toward every azimuth into one buffer: the angles of the last one, or with `accumulate` the sum
+    /// over all of them of the sine of the horizon above the horizontal
+    fn horizons(&self, grid: &Grid, elevation: &wgpu::Buffer, nodata: f32, azimuths: &[f64], accumulate: bool) -> io::Result<wgpu::Buffer> {
+        let storage = |label: &str, size: u64| self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        // Zeroed on creation, which the sums start from
+        let horizon = storage("horizon", (grid.ncols * grid.nrows * 4) as u64);
+
+        let limits = self.device.limits();
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("horizon") });
+        for &azimuth in azimuths {
+            let geometry = Lattice::new(grid, azimuth);
+            // The hull of every line needs room for all of its points
+            let hull_size = (geometry.samples * geometry.lines * 8) as u64;
+            if hull_size > limits.max_storage_buffer_binding_size as u64 || hull_size > limits.max_buffer_size {
+                return Err(gpu_error(format!("the horizon lattice of {} × {} cells exceeds the GPU buffer limit", grid.ncols, grid.nrows)));
+            }
+            let uniforms = HorizonUniforms {
+                ncols: grid.ncols as u32,
+                nrows: grid.nrows as u32,
+                samples: geometry.samples as u32,
+                lines: geometry.lines as u32,
+                east: geometry.east as f32,
+                north: geometry.north as f32,
+                dx: geometry.dx as f32,
+                dy: geometry.dy as f32,
+                step: geometry.step as f32,
+                u_min: geometry.u_min as f32,
+                v_min: geometry.v_min as f32,
+                nodata,
+                accumulate: accumulate as u32,
+                pad: [0; 3],
+            };
+            let uniform_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("horizon params"),
+                contents: bytemuck::bytes_of(&uniforms),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            let lattice = storage("lattice", hull_size / 2);
+            let hull = storage("hull", hull_size);
+
+            let sweep = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("sweep"),
+                layout: &self.sweep.get_bind_group_layout(0),
+                entries: &[
+                    wgpu::BindGroupEntry { binding: 0, resource: uniform_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 1, resource: elevation.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 3, resource: lattice.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 4, resource: hull.as_entire_binding() },
+                ],
+            });
+            let resample = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("resample"),
+                layout: &self.resample.get_bind_group_layout(0),
+                entries: &[
+                    wgpu::BindGroupEntry { binding: 0, resource: uniform_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 1, resource: elevation.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 2, resource: horizon.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 3, resource: lattice.as_entire_binding() },
+                ],
+            });
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("horizon"), timestamp_writes: None });
+            pass.set_pipeline(&self.sweep);
+            pass.set_bind_group(0, &sweep, &[]);
+            pass.dispatch_workgroups(uniforms.lines.div_ceil(SWEEP_SIZE), 1, 1);
+            pass.set_pipeline(&self.resample);
+            pass.set_bind_group(0, &resample, &[]);
+            pass.dispatch_workgroups(uniforms.ncols.div_ceil(WORKGROUP_SIZE), uniforms.nrows.div_ceil(WORKGROUP_SIZE), 1);
+        }
+        self.queue.submit([encoder.finish()]);
+        Ok(horizon)
+    }
+
+    /// Copies `buffer` back to memory once the work queued on it is done
+    fn read_back(&self, buffer: &wgpu::Buffer) -> io::Result<Vec<u8>> {
+        let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback"),
+            size: buffer.size(),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("readback") });
+        encoder.copy_buffer_to_buffer(buffer, 0, &readback, 0, buffer.size());
+        self.queue.submit([encoder.finish()]);
+
+        let (sender, receiver) = mpsc::channel();
+        readback.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::PollType::wait_indefinitely()).map_err(gpu_error)?;
+        receiver.recv().map_err(gpu_error)?.map_err(gpu_error)?;
+
+        let bytes = readback.slice(..).get_mapped_range().to_vec();
+        readback.unmap();
+        Ok(bytes)
+    }
+}
@@ -0,0 +1,574 @@
+#[cfg(feature = "fs")]
+use std::io;
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+/// How [`Grid::sample`] derives a value between cell centers
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Interpolation {
+    Nearest,  // Value of the cell containing the point
+    #[default]
+    Bilinear, // Weighted mean of the four surrounding cell centers
+}
+
+impl Interpolation {
+    pub fn parse(name: &str) -> Option<Interpolation> {
+        match name.to_lowercase().as_str() {
+            "nearest" => Some(Interpolation::Nearest),
+            "bilinear" => Some(Interpolation::Bilinear),
+            _ => None,
+        }
+    }
+}
+
+/// How [`Grid::downsampled`] reduces a block of cells to one
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "fs", derive(serde::Serialize), serde(rename_all = "lowercase"))]
+pub enum Downsample {
+    Decimate, // The cell at the center of the block, every Nth cell
+    #[default]
+    Average,  // Mean of the valid cells of the block
+}
+
+impl Downsample {
+    pub fn parse(name: &str) -> Option<Downsample> {
+        match name.to_lowercase().as_str() {
+            "decimate" | "nearest" => Some(Downsample::Decimate),
+            "average" | "mean" => Some(Downsample::Average),
+            _ => None,
+        }
+    }
+
+    /// Name as accepted by [`Downsample::parse`]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Downsample::Decimate => "decimate",
+            Downsample::Average => "average",
+        }
+    }
+}
+
+/// Coarser copy of every input rendered instead of it, to iterate on the parameters of huge grids (--preview)
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "fs", derive(serde::Serialize))]
+pub struct Preview {
+    pub factor: usize,       // Cells per side of a block that becomes one cell
+    pub sampling: Downsample,
+}
+
+impl Preview {
+    pub fn apply(&self, grid: Grid) -> Grid {
+        if self.factor <= 1 { grid } else { grid.downsampled(self.factor, self.sampling) }
+    }
+
+    /// As recorded in PNG text chunks, e.g. "factor=8 sampling=average"
+    pub fn describe(&self) -> String {
+        format!("factor={} sampling={}", self.factor, self.sampling.name())
+    }
+}
+
+/// How the 3x3 kernels (hillshade, slope, aspect, curvature) fill the neighbors of border cells that lie outside the grid
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "fs", derive(serde::Serialize), serde(rename_all = "lowercase"))]
+pub enum EdgeMode {
+    Replicate, // The center value, the border cells lose the slope across the edge
+    #[default]
+    Mirror,    // Reflected through the center (2·center − opposite neighbor), continuing the slope past the edge
+    NoData,    // Border cells are not computed and become NoData
+}
+
+impl EdgeMode {
+    pub fn parse(name: &str) -> Option<EdgeMode> {
+        match name.to_lowercase().as_str() {
+            "replicate" => Some(EdgeMode::Replicate),
+            "mirror" => Some(EdgeMode::Mirror),
+            "nodata" => Some(EdgeMode::NoData),
+            _ => None,
+        }
+    }
+
+    /// Name as accepted by [`EdgeMode::parse`]
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgeMode::Replicate => "replicate",
+            EdgeMode::Mirror => "mirror",
+            EdgeMode::NoData => "nodata",
+        }
+    }
+}
+
+/// Value type of a [`Grid`]: f32 by default, i16 or u16 store integer DEMs like SRTM in half the memory
+pub trait Elevation: Copy + PartialEq + PartialOrd + std::fmt::Debug + std::str::FromStr + Send + Sync + 'static {
+    /// NoData of a file that doesn't name one
+    const DEFAULT_NODATA: Self;
+
+    fn to_f32(self) -> f32;
+
+    /// Whether the value is not a number, which always counts as NoData
+    fn is_nan(self) -> bool {
+        false
+    }
+
+    /// The value equal to `v`, None if the type can't hold it (a fraction or out of range for integers)
+    fn from_f64(v: f64) -> Option<Self>;
+}
+
+impl Elevation for f32 {
+    const DEFAULT_NODATA: f32 = -99999.0;
+    fn to_f32(self) -> f32 { self }
+    fn is_nan(self) -> bool { self.is_nan() }
+    fn from_f64(v: f64) -> Option<f32> { Some(v as f32) }
+}
+
+impl Elevation for f64 {
+    const DEFAULT_NODATA: f64 = -99999.0;
+    fn to_f32(self) -> f32 { self as f32 }
+    fn is_nan(self) -> bool { self.is_nan() }
+    fn from_f64(v: f64) -> Option<f64> { Some(v) }
+}
+
+impl Elevation for i16 {
+    const DEFAULT_NODATA: i16 = i16::MIN; // The void value of SRTM tiles
+    fn to_f32(self) -> f32 { self as f32 }
+    fn from_f64(v: f64) -> Option<i16> { (v.fract() == 0.0 && (i16::MIN as f64..=i16::MAX as f64).contains(&v)).then_some(v as i16) }
+}
+
+impl Elevation for u16 {
+    const DEFAULT_NODATA: u16 = u16::MAX;
+    fn to_f32(self) -> f32 { self as f32 }
+    fn from_f64(v: f64) -> Option<u16> { (v.fract() == 0.0 && (0.0..=u16::MAX as f64).contains(&v)).then_some(v as u16) }
+}
+
+impl Elevation for i32 {
+    const DEFAULT_NODATA: i32 = -99999;
+    fn to_f32(self) -> f32 { self as f32 }
+    fn from_f64(v: f64) -> Option<i32> { (v.fract() == 0.0 && (i32::MIN as f64..=i32::MAX as f64).contains(&v)).then_some(v as i32) }
+}
+
+/// Elevation raster together with the metadata of its ESRI ASCII header, f32 values unless another [`Elevation`] type is given
+#[derive(Clone, Debug)]
+pub struct Grid<T = f32> {
+    pub data: Vec<Vec<T>>, // Rows from north to south, each `ncols` long
+    pub ncols: usize,
+    pub nrows: usize,
+    pub nodata: T,
+    pub cellsize: f64,
+    pub xllcorner: f64,          // Map coordinates of the lower left corner of the grid
+    pub yllcorner: f64,
+    pub geographic: Option<bool>, // Whether the cells are in degrees, guessed by [`Grid::looks_geographic`] when None
+}
+
+/// One bit per cell of a grid, set on the valid ones, see [`Grid::mask`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mask {
+    bits: Vec<u64>,
+    ncols: usize,
+    nrows: usize,
+}
+
+impl Mask {
+    /// Whether the cell at column `x` and row `y` is valid
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        let i = y * self.ncols + x;
+        self.bits[i / 64] >> (i % 64) & 1 == 1
+    }
+
+    /// Columns and rows of the grid
+    pub fn size(&self) -> (usize, usize) {
+        (self.ncols, self.nrows)
+    }
+
+    /// Number of valid cells
+    pub fn count(&self) -> usize {
+        self.bits.iter().map(|b| b.count_ones() as usize).sum()
+    }
+}
+
+/// Grid of 16-bit integer elevations, half the size of a [`Grid`] of f32
+pub type GridI16 = Grid<i16>;
+
+impl Grid {
+    /// Elevation at map coordinates (x, y), None outside the grid or on NoData.
+    /// Bilinear interpolation falls back to the nearest cell when one of the four neighbors is NoData.
+    pub fn sample(&self, x: f64, y: f64, method: Interpolation) -> Option<f32> {
+        let (x0, y0) = self.origin();
+        // Position in cells from the upper left corner
+        let (col, row) = ((x - x0) / self.cellsize, (y0 - y) / self.cellsize);
+        if !(0.0..self.ncols as f64).contains(&col) || !(0.0..self.nrows as f64).contains(&row) {
+            return None;
+        }
+        let valid = |c: usize, r: usize| Some(self.data[r][c]).filter(|&v| !self.is_nodata(v));
+        let nearest = valid(col as usize, row as usize);
+        if method == Interpolation::Nearest {
+            return nearest;
+        }
+
+        // Cell centers sit at half cells, points in the outer half cell are clamped to the border row or column
+        let fc = (col - 0.5).clamp(0.0, (self.ncols - 1) as f64);
+        let fr = (row - 0.5).clamp(0.0, (self.nrows - 1) as f64);
+        let (c0, r0) = (fc as usize, fr as usize);
+        let (c1, r1) = ((c0 + 1).min(self.ncols - 1), (r0 + 1).min(self.nrows - 1));
+        let (tx, ty) = ((fc - c0 as f64) as f32, (fr - r0 as f64) as f32);
+        match (valid(c0, r0), valid(c1, r0), valid(c0, r1), valid(c1, r1)) {
+            (Some(a), Some(b), Some(c), Some(d)) => {
+                let top = a + (b - a) * tx;
+                let bottom = c + (d - c) * tx;
+                Some(top + (bottom - top) * ty)
+            }
+            _ => nearest,
+        }
+    }
+
+    /// Box-filtered copy: every valid cell becomes the mean of the valid cells within `radius` cells, NoData stays NoData
+    pub fn smoothed(&self, radius: usize) -> Grid {
+        // Summed-area tables of the valid values and their count, one row and column larger than the grid
+        let (w, h) = (self.ncols + 1, self.nrows + 1);
+        let mut sum = vec![0.0f64; w * h];
+        let mut count = vec![0u32; w * h];
+        let mask = self.mask();
+        for (y, row) in self.data.iter().enumerate() {
+            for (x, &val) in row.iter().enumerate() {
+                let valid = mask.get(x, y);
+                let i = (y + 1) * w + x + 1;
+                sum[i] = if valid { val as f64 } else { 0.0 } + sum[i - 1] + sum[i - w] - sum[i - w - 1];
+                count[i] = valid as u32 + count[i - 1] + count[i - w] - count[i - w - 1];
+            }
+        }
+
+        let mut smoothed = self.clone();
+        for (y, row) in smoothed.data.iter_mut().enumerate() {
+            let (y0, y1) = (y.saturating_sub(radius), (y + radius + 1).min(self.nrows));
+            for (x, val) in row.iter_mut().enumerate() {
+                if !mask.get(x, y) {
+                    continue;
+                }
+                let (x0, x1) = (x.saturating_sub(radius), (x + radius + 1).min(self.ncols));
+                let total = sum[y1 * w + x1] - sum[y0 * w + x1] - sum[y1 * w + x0] + sum[y0 * w + x0];
+                let n = count[y1 * w + x1] + count[y0 * w + x0] - count[y0 * w + x1] - count[y1 * w + x0];
+                *val = (total / n as f64) as f32;
+            }
+        }
+        smoothed
+    }
+
+    /// Copy with `factor` times the cell size: every block of `factor` x `factor` cells becomes one, NoData if none of
+    /// them is valid. Blocks at the east and south edge may be cut short; the north-west corner stays in place.
+    pub fn downsampled(&self, factor: usize, method: Downsample) -> Grid {
+        if factor <= 1 {
+            return self.clone();
+        }
+        let (ncols, nrows) = (self.ncols.div_ceil(factor), self.nrows.div_ceil(factor));
+        let data = (0..nrows).map(|by| (0..ncols).map(|bx| {
+            let (x0, y0) = (bx * factor, by * factor);
+            let (x1, y1) = ((x0 + factor).min(self.ncols), (y0 + factor).min(self.nrows));
+            match method {
+                Downsample::Decimate => self.data[(y0 + y1) / 2][(x0 + x1) / 2],
+                Downsample::Average => {
+                    let (sum, n) = self.data[y0..y1].iter().flat_map(|row| &row[x0..x1])
+                        .filter(|&&v| !self.is_nodata(v))
+                        .fold((0.0f64, 0u32), |(sum, n), &v| (sum + v as f64, n + 1));
+                    if n == 0 { self.nodata } else { (sum / n as f64) as f32 }
+                }
+            }
+        }).collect()).collect();
+        let cellsize = self.cellsize * factor as f64;
+        Grid {
+            data,
+            ncols,
+            nrows,
+            nodata: self.nodata,
+            cellsize,
+            xllcorner: self.xllcorner,
+            yllcorner: self.yllcorner + self.nrows as f64 * self.cellsize - nrows as f64 * cellsize,
+            geographic: self.geographic,
+        }
+    }
+
+    /// Applies `f` to the `kernel_size` x `kernel_size` neighborhood (row-major, north row first, None outside the
+    /// grid or on NoData) of every valid cell; NoData stays NoData. Even sizes are rounded up to center the kernel.
+    pub fn map_neighborhood(&self, kernel_size: usize, f: impl Fn(&[Option<f32>]) -> f32) -> Grid {
+        let size = kernel_size | 1;
+        let radius = (size / 2) as isize;
+        let mut neighborhood = vec![None; size * size];
+        let mut data = self.data.clone();
+        for (x, y, _) in self.iter_valid() {
+            for (i, v) in neighborhood.iter_mut().enumerate() {
+                *v = self.get(x as isize + (i % size) as isize - radius, y as isize + (i / size) as isize - radius);
+            }
+            data[y][x] = f(&neighborhood);
+        }
+        Grid { data, ..self.clone() }
+    }
+
+    /// Gaussian-smoothed copy with a standard deviation of `sigma` cells: every valid cell becomes the weighted mean of
+    /// the valid cells within 3 sigma, NoData stays NoData
+    pub fn gaussian_smoothed(&self, sigma: f64) -> Grid {
+        let radius = (3.0 * sigma).ceil() as isize;
+        if radius < 1 {
+            return self.clone();
+        }
+        let kernel: Vec<f64> = (-radius..=radius).map(|i| (-((i * i) as f64) / (2.0 * sigma * sigma)).exp()).collect();
+
+        // Separable: weighted sums and weights of the valid cells along the rows, then down the columns
+        let mut rows = vec![(0.0f64, 0.0f64); self.ncols * self.nrows];
+        for (x, y, _) in self.iter_cells() {
+            let (sum, weight) = &mut rows[y * self.ncols + x];
+            for (k, w) in kernel.iter().enumerate() {
+                if let Some(v) = self.get(x as isize + k as isize - radius, y as isize) {
+                    *sum += w * v as f64;
+                    *weight += w;
+                }
+            }
+        }
+        let mut smoothed = self.clone();
+        for (x, y, _) in self.iter_valid() {
+            let (mut sum, mut weight) = (0.0, 0.0);
+            for (k, w) in kernel.iter().enumerate() {
+                let ny = y as isize + k as isize - radius;
+                if (0..self.nrows as isize).contains(&ny) {
+                    let (s, n) = rows[ny as usize * self.ncols + x];
+                    sum += w * s;
+                    weight += w * n;
+                }
+            }
+            smoothed.data[y][x] = (sum / weight) as f32;
+        }
+        smoothed
+    }
+
+    /// Starts a [`crate::pipeline::Pipeline`] of deferred operations on the grid
+    pub fn lazy(self) -> crate::pipeline::Pipeline {
+        crate::pipeline::Pipeline::new(self)
+    }
+
+    /// Copy holding the values as `T`, e.g. `grid.convert::<i16>()` for a DEM of whole meters.
+    /// None if a value or NoData has no exact equivalent, so nothing is rounded away.
+    pub fn convert<T: Elevation>(&self) -> Option<Grid<T>> {
+        let data = self.data.iter()
+            .map(|row| row.iter().map(|&v| T::from_f64(v as f64)).collect::<Option<Vec<T>>>())
+            .collect::<Option<Vec<_>>>()?;
+        Some(Grid { data, ncols: self.ncols, nrows: self.nrows, nodata: T::from_f64(self.nodata as f64)?, cellsize: self.cellsize, xllcorner: self.xllcorner, yllcorner: self.yllcorner, geographic: self.geographic })
+    }
+}
+
+impl<T: Elevation> Grid<T> {
+    /// Minimum and maximum valid elevation, ignoring NoData
+    pub fn min_max(&self) -> (f32, f32) {
+        self.iter_valid().fold((f32::MAX, f32::MIN), |(min, max), (_, _, val)| (min.min(val.to_f32()), max.max(val.to_f32())))
+    }
+
+    /// Map coordinates of the upper left corner, the origin of row 0
+    pub fn origin(&self) -> (f64, f64) {
+        (self.xllcorner, self.yllcorner + self.nrows as f64 * self.cellsize)
+    }
+
+    /// Whether the header looks like longitude/latitude in degrees: cells smaller than a degree
+    /// and the whole extent within ±180° (up to 360° for 0–360 grids) and ±90°
+    pub fn looks_geographic(&self) -> bool {
+        let (left, top) = self.origin();
+        let right = left + self.ncols as f64 * self.cellsize;
+        self.cellsize < 1.0 && left >= -180.0 && right <= 360.0 && self.yllcorner >= -90.0 && top <= 90.0
+    }
+
+    /// Whether the cells are in degrees: as set by --geographic or --projected, otherwise [`Grid::looks_geographic`]
+    pub fn is_geographic(&self) -> bool {
+        self.geographic.unwrap_or_else(|| self.looks_geographic())
+    }
+
+    /// Latitude of the grid center, for grids in degrees
+    pub fn center_latitude(&self) -> f64 {
+        self.yllcorner + self.nrows as f64 * self.cellsize / 2.0
+    }
+
+    /// East-west and north-south cell spacing in the units of the elevations (meters) for slopes and other gradients.
+    /// Grids in degrees are converted at their central latitude, where a degree of longitude shrinks with its cosine.
+    pub fn ground_spacing(&self) -> (f64, f64) {
+        if !self.is_geographic() {
+            return (self.cellsize, self.cellsize);
+        }
+        let (east, north) = meters_per_degree(self.center_latitude());
+        (self.cellsize * east, self.cellsize * north)
+    }
+
+    /// Single cell size in meters, the geometric mean of [`Grid::ground_spacing`]
+    pub fn ground_cellsize(&self) -> f64 {
+        let (dx, dy) = self.ground_spacing();
+        (dx * dy).sqrt()
+    }
+
+    /// Whether `val` is the NoData value or NaN
+    pub fn is_nodata(&self, val: T) -> bool {
+        is_nodata(val, self.nodata)
+    }
+
+    /// Whether the cell at column `x` and row `y` holds an elevation
+    pub fn is_valid(&self, x: usize, y: usize) -> bool {
+        !self.is_nodata(self.data[y][x])
+    }
+
+    /// Bitset of the valid cells, for passes that look at the same cells many times
+    pub fn mask(&self) -> Mask {
+        let mut mask = Mask { bits: vec![0; (self.ncols * self.nrows).div_ceil(64)], ncols: self.ncols, nrows: self.nrows };
+        for (x, y, _) in self.iter_valid() {
+            let i = y * self.ncols + x;
+            mask.bits[i / 64] |= 1 << (i % 64);
+        }
+        mask
+    }
+
+    /// Every cell as (column, row, value), row by row from the north, NoData included
+    pub fn iter_cells(&self) -> impl Iterator<Item = (usize, usize, T)> + '_ {
+        self.data.iter().enumerate().flat_map(|(y, row)| row.iter().enumerate().map(move |(x, &val)| (x, y, val)))
+    }
+
+    /// The cells of [`Grid::iter_cells`] that are not NoData
+    pub fn iter_valid(&self) -> impl Iterator<Item = (usize, usize, T)> + '_ {
+        self.iter_cells().filter(|&(_, _, val)| !self.is_nodata(val))
+    }
+
+    /// The values of the valid cells, row by row, to be changed in place
+    pub fn valid_mut(&mut self) -> impl Iterator<Item = &mut T> + '_ {
+        let nodata = self.nodata;
+        self.data.iter_mut().flatten().filter(move |val| !is_nodata(**val, nodata))
+    }
+
+    /// Value of the cell at column `x` and row `y`, None outside the grid or on NoData
+    pub fn get(&self, x: isize, y: isize) -> Option<T> {
+        if x < 0 || y < 0 || x as usize >= self.ncols || y as usize >= self.nrows {
+            return None;
+        }
+        Some(self.data[y as usize][x as usize]).filter(|&v| !self.is_nodata(v))
+    }
+
+    /// 3x3 neighborhood `[a, b, c, d, e, f, g, h, i]` (north row first) around column `x` and row `y`,
+    /// None for neighbors outside the grid or on NoData
+    pub fn window_3x3(&self, x: usize, y: usize) -> [Option<T>; 9] {
+        let mut w = [None; 9];
+        for (i, v) in w.iter_mut().enumerate() {
+            *v = self.get(x as isize + (i % 3) as isize - 1, y as isize + (i / 3) as isize - 1);
+        }
+        w
+    }
+
+    /// [`Grid::window_3x3`] as f64 with the neighbors outside the grid filled in as `edges` says, None for cells that
+    /// aren't computed: NoData ones and, with [`EdgeMode::NoData`], the border. Neighbors on NoData inside the grid stay None.
+    pub fn edge_window(&self, x: usize, y: usize, edges: EdgeMode) -> Option<[Option<f64>; 9]> {
+        let center = self.get(x as isize, y as isize)?.to_f32() as f64;
+        let border = x == 0 || y == 0 || x + 1 == self.ncols || y + 1 == self.nrows;
+        if border && edges == EdgeMode::NoData {
+            return None;
+        }
+        let mut w = self.window_3x3(x, y).map(|v| v.map(|v| v.to_f32() as f64));
+        if border {
+            let inside = w;
+            for i in 0..9 {
+                let (nx, ny) = (x as isize + (i % 3) as isize - 1, y as isize + (i / 3) as isize - 1);
+                if nx >= 0 && ny >= 0 && (nx as usize) < self.ncols && (ny as usize) < self.nrows {
+                    continue;
+                }
+                // Opposite neighbor, outside or NoData as well on grids a single cell wide
+                w[i] = Some(match (edges, inside[8 - i]) {
+                    (EdgeMode::Mirror, Some(opposite)) => 2.0 * center - opposite,
+                    _ => center,
+                });
+            }
+        }
+        Some(w)
+    }
+
+    /// The `ncols` x `nrows` cells from column `col_off` and row `row_off` (counted from the north),
+    /// None if the window is empty or leaves the grid
+    pub fn window(&self, col_off: usize, row_off: usize, ncols: usize, nrows: usize) -> Option<Grid<T>> {
+        if ncols == 0 || nrows == 0 || col_off + ncols > self.ncols || row_off + nrows > self.nrows {
+            return None;
+        }
+        Some(Grid {
+            data: self.data[row_off..row_off + nrows].iter().map(|row| row[col_off..col_off + ncols].to_vec()).collect(),
+            ncols,
+            nrows,
+            nodata: self.nodata,
+            cellsize: self.cellsize,
+            xllcorner: self.xllcorner + col_off as f64 * self.cellsize,
+            yllcorner: self.yllcorner + (self.nrows - row_off - nrows) as f64 * self.cellsize,
+            geographic: self.geographic,
+        })
+    }
+
+    /// Copy with f32 values for the operations only defined on those
+    pub fn to_f32(&self) -> Grid {
+        Grid {
+            data: self.data.iter().map(|row| row.iter().map(|v| v.to_f32()).collect()).collect(),
+            ncols: self.ncols,
+            nrows: self.nrows,
+            nodata: self.nodata.to_f32(),
+            cellsize: self.cellsize,
+            xllcorner: self.xllcorner,
+            yllcorner: self.yllcorner,
+            geographic: self.geographic,
+        }
+    }
+}
+
+/// Whether `val` is `nodata` or NaN, for [`Grid::is_nodata`] where the grid is borrowed otherwise
+fn is_nodata<T: Elevation>(val: T, nodata: T) -> bool {
+    val == nodata || val.is_nan()
+}
+
+/// Size, georeference and NoData of a grid file from its header, without the values
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GridInfo {
+    pub ncols: usize,
+    pub nrows: usize,
+    pub cellsize: f64,
+    pub xllcorner: f64,
+    pub yllcorner: f64,
+    pub nodata: f32,
+}
+
+impl GridInfo {
+    /// West, south, east and north edge in map coordinates
+    pub fn bounds(&self) -> [f64; 4] {
+        [self.xllcorner, self.yllcorner, self.xllcorner + self.ncols as f64 * self.cellsize, self.yllcorner + self.nrows as f64 * self.cellsize]
+    }
+}
+
+/// Reads the [`GridInfo`] of a grid file. ESRI and GRASS ASCII files are read up to their first row of values and
+/// GeoTIFFs only for their tags; E00 files are loaded whole.
+#[cfg(feature = "fs")]
+pub fn read_info(path: &Path) -> io::Result<GridInfo> {
+    use crate::sniff::{sniff_file, InputFormat};
+    match sniff_file(path)? {
+        InputFormat::EsriAscii | InputFormat::GrassAscii => crate::asc::read_asc_info(io::BufReader::new(std::fs::File::open(path)?)),
+        InputFormat::GeoTiff => crate::geotiff::read_geotiff_info(path),
+        InputFormat::E00 => {
+            let grid = crate::e00::load_e00(path)?;
+            Ok(GridInfo { ncols: grid.ncols, nrows: grid.nrows, cellsize: grid.cellsize, xllcorner: grid.xllcorner, yllcorner: grid.yllcorner, nodata: grid.nodata })
+        }
+        format => Err(io::Error::new(io::ErrorKind::Unsupported, format!("The size of {} is only known after loading it", format.name()))),
+    }
+}
+
+/// Reads only a window of a grid file: `ncols` x `nrows` cells from column `col_off` and row `row_off` (counted from
+/// the north). ESRI and GRASS ASCII files are parsed up to the last row of the window and GeoTIFFs decode only the
+/// strips or tiles it touches; E00 files are loaded whole and cropped.
+#[cfg(feature = "fs")]
+pub fn read_window(path: &Path, col_off: usize, row_off: usize, ncols: usize, nrows: usize) -> io::Result<Grid> {
+    use crate::sniff::{sniff_file, InputFormat};
+    match sniff_file(path)? {
+        InputFormat::EsriAscii | InputFormat::GrassAscii => {
+            crate::asc::read_asc_window(io::BufReader::new(std::fs::File::open(path)?), col_off, row_off, ncols, nrows)
+        }
+        InputFormat::GeoTiff => crate::geotiff::read_geotiff_window(path, col_off, row_off, ncols, nrows),
+        InputFormat::E00 => crate::e00::load_e00(path)?.window(col_off, row_off, ncols, nrows)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "The window is empty or leaves the grid")),
+        format => Err(io::Error::new(io::ErrorKind::Unsupported, format!("Windows of {} are not supported", format.name()))),
+    }
+}
+
+/// Length in meters of one degree of longitude and of latitude at `latitude` on the WGS 84 ellipsoid
+pub fn meters_per_degree(latitude: f64) -> (f64, f64) {
+    let phi = latitude.to_radians();
+    let east = 111_412.84 * phi.cos() - 93.5 * (3.0 * phi).cos() + 0.118 * (5.0 * phi).cos();
+    let north = 111_132.92 - 559.82 * (2.0 * phi).cos() + 1.175 * (4.0 * phi).cos() - 0.0023 * (6.0 * phi).cos();
+    (east, north)
+}
@@ -0,0 +1,276 @@
+//! 16-bit heightmaps for game engines and renderers: the grid resampled to the sizes Unity and Unreal accept, or
+//! kept at its size for POV-Ray `height_field` and Blender displacement, with the scales that restore the real relief
+//! on import and, for the renderers, a scene or script using them.
+
+#[cfg(feature = "fs")]
+use std::io::{self, BufWriter, Seek, Write};
+#[cfg(feature = "fs")]
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "fs")]
+use tiff::encoder::{colortype, compression, TiffEncoder};
+
+use crate::grid::{Grid, Interpolation};
+#[cfg(feature = "fs")]
+use crate::output::Conflict;
+
+/// Square sizes Unity terrains take, 2^n + 1
+pub const UNITY_SIZES: &[usize] = &[33, 65, 129, 257, 513, 1025, 2049, 4097];
+
+/// Landscape sizes Epic recommends for Unreal, whole numbers of components
+pub const UNREAL_SIZES: &[usize] = &[127, 253, 505, 1009, 2017, 4033, 8129];
+
+/// Program a heightmap is made for (--preset)
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "fs", derive(serde::Serialize), serde(rename_all = "lowercase"))]
+pub enum Preset {
+    Unity,   // RAW, 16-bit little-endian, square 2^n + 1
+    Unreal,  // 16-bit grayscale PNG of a recommended landscape size
+    Povray,  // 16-bit PGM for a height_field, plus a .pov scene
+    Blender, // 16-bit grayscale TIFF for a Displace modifier, plus a Python script building the plane
+}
+
+impl Preset {
+    pub fn parse(name: &str) -> Option<Preset> {
+        match name.to_lowercase().as_str() {
+            "unity" => Some(Preset::Unity),
+            "unreal" => Some(Preset::Unreal),
+            "povray" | "pov-ray" => Some(Preset::Povray),
+            "blender" => Some(Preset::Blender),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Preset::Unity => "unity",
+            Preset::Unreal => "unreal",
+            Preset::Povray => "povray",
+            Preset::Blender => "blender",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Preset::Unity => "raw",
+            Preset::Unreal => "png",
+            Preset::Povray => "pgm",
+            Preset::Blender => "tif",
+        }
+    }
+
+    /// Sizes a game engine takes, None when any size goes
+    fn sizes(&self) -> Option<&'static [usize]> {
+        match self {
+            Preset::Unity => Some(UNITY_SIZES),
+            Preset::Unreal => Some(UNREAL_SIZES),
+            Preset::Povray | Preset::Blender => None,
+        }
+    }
+
+    /// Smallest accepted size holding `cells` samples, the largest one if none does
+    pub fn size_for(&self, cells: usize) -> usize {
+        match self.sizes() {
+            Some(sizes) => sizes.iter().copied().find(|&s| s >= cells).unwrap_or(sizes[sizes.len() - 1]),
+            None => cells,
+        }
+    }
+
+    /// Whether the program takes `size` samples along the longer side
+    pub fn accepts(&self, size: usize) -> bool {
+        self.sizes().map_or(size >= 2, |sizes| sizes.contains(&size))
+    }
+}
+
+/// A quantized heightmap and how it maps back to the terrain
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "fs", derive(serde::Serialize))]
+pub struct Heightmap {
+    #[cfg_attr(feature = "fs", serde(skip))]
+    pub samples: Vec<u16>,     // Row by row, north first; 0 is `min_elevation`, 65535 `max_elevation`
+    pub preset: Preset,
+    pub width: usize,          // Samples along x and y, equal for the game engines
+    pub height: usize,
+    pub source_cols: usize,    // Samples covered by the grid, the rest is padding at the lowest elevation
+    pub source_rows: usize,
+    pub spacing: f64,          // Ground distance between samples in meters
+    pub min_elevation: f64,
+    pub max_elevation: f64,
+    pub terrain_size: [f64; 3], // Width, height and length in meters (Unity Terrain size, POV-Ray and Blender scale)
+    pub unreal_scale: [f64; 3], // Landscape X, Y and Z scale in Unreal units
+    pub unreal_z_offset: f64,   // Landscape location Z in Unreal units that puts the samples at their elevation
+}
+
+impl Heightmap {
+    /// Resamples the grid so its longer side spans `size` samples (bilinear, NoData as the lowest elevation),
+    /// pads the shorter side with the lowest elevation for the square game engine formats and stretches the
+    /// elevations over the 16-bit range
+    pub fn new(grid: &Grid, preset: Preset, size: usize) -> Result<Heightmap, String> {
+        if size < 2 {
+            return Err("A heightmap needs at least 2 samples per side".into());
+        }
+        let (min, max) = grid.min_max();
+        if min > max {
+            return Err("The grid has no valid cells".into());
+        }
+        let (min, max) = (min as f64, max as f64);
+        // Samples run from the first to the last cell center of the longer side
+        let longest = grid.ncols.max(grid.nrows);
+        let step = (longest - 1).max(1) as f64 / (size - 1) as f64;
+        let covered = |cells: usize| (((cells - 1) as f64 / step).floor() as usize + 1).min(size);
+        let (source_cols, source_rows) = (covered(grid.ncols), covered(grid.nrows));
+        let (width, height) = if preset.sizes().is_some() { (size, size) } else { (source_cols, source_rows) };
+        let (x0, y0) = grid.origin();
+        let range = (max - min).max(1e-9);
+        let mut samples = vec![0u16; width * height];
+        for row in 0..source_rows {
+            for col in 0..source_cols {
+                let x = x0 + (col as f64 * step + 0.5) * grid.cellsize;
+                let y = y0 - (row as f64 * step + 0.5) * grid.cellsize;
+                let z = grid.sample(x, y, Interpolation::Bilinear).map_or(min, |z| z as f64);
+                samples[row * width + col] = ((z - min) / range * 65535.0).round().clamp(0.0, 65535.0) as u16;
+            }
+        }
+        let spacing = grid.ground_cellsize() * step;
+        Ok(Heightmap {
+            samples,
+            preset,
+            width,
+            height,
+            source_cols,
+            source_rows,
+            spacing,
+            min_elevation: min,
+            max_elevation: max,
+            terrain_size: [spacing * (width - 1) as f64, max - min, spacing * (height - 1) as f64],
+            // Unreal spans 512 m between the lowest and highest sample at a Z scale of 100, 1 unit = 1 cm
+            unreal_scale: [spacing * 100.0, spacing * 100.0, (max - min) * 100.0 / 512.0],
+            unreal_z_offset: (min + max) / 2.0 * 100.0,
+        })
+    }
+
+    /// Unity RAW: 16-bit little-endian, south row first as the terrain reads it without flipping
+    #[cfg(feature = "fs")]
+    pub fn write_raw<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for row in self.samples.chunks(self.width).rev() {
+            for &v in row {
+                out.write_all(&v.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Unreal: 16-bit grayscale PNG, north row at the top
+    #[cfg(feature = "fs")]
+    pub fn write_png<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(out, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Sixteen);
+        let data: Vec<u8> = self.samples.iter().flat_map(|v| v.to_be_bytes()).collect();
+        encoder.write_header().and_then(|mut writer| writer.write_image_data(&data))
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+
+    /// Binary PGM (P5) with a maximum of 65535, two big-endian bytes per sample, north row at the top
+    #[cfg(feature = "fs")]
+    pub fn write_pgm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "P5\n{} {}\n65535\n", self.width, self.height)?;
+        for &v in &self.samples {
+            out.write_all(&v.to_be_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// 16-bit grayscale TIFF, north row at the top
+    #[cfg(feature = "fs")]
+    pub fn write_tiff<W: Write + Seek>(&self, out: &mut W) -> io::Result<()> {
+        let to_io = |e: tiff::TiffError| io::Error::new(io::ErrorKind::Other, e);
+        TiffEncoder::new(out).map_err(to_io)?
+            .write_image_with_compression::<colortype::Gray16, _>(self.width as u32, self.height as u32, compression::Deflate::default(), &self.samples)
+            .map_err(to_io)
+    }
+
+    /// POV-Ray scene with the height field scaled to meters, north along +z, lit from the northwest
+    pub fn pov_scene(&self, image: &str) -> String {
+        let [x, y, z] = self.terrain_size;
+        let span = x.max(z);
+        format!(
+            "// {image}: {width}x{height} samples {spacing:.3} m apart, elevations {min:.2} to {max:.2} m\n\
+            #include \"colors.inc\"\n\n\
+            camera {{ location <{cx:.1}, {cy:.1}, {cz:.1}> look_at <0, {ly:.1}, 0> angle 50 }}\n\
+            light_source {{ <{lx:.1}, {lh:.1}, {lz:.1}> color White }}\n\
+            background {{ color rgb <0.7, 0.8, 1.0> }}\n\n\
+            height_field {{\n    pgm \"{image}\"\n    smooth\n\
+            \x20   // Rows run along +z in POV-Ray, mirrored so north points to +z\n\
+            \x20   scale <1, 1, -1> translate <-0.5, 0, 0.5>\n\
+            \x20   scale <{x:.3}, {y:.3}, {z:.3}>\n\
+            \x20   translate <0, {min:.3}, 0>\n\
+            \x20   pigment {{ color rgb <0.75, 0.72, 0.65> }}\n}}\n",
+            image = image, width = self.width, height = self.height, spacing = self.spacing,
+            min = self.min_elevation, max = self.max_elevation, x = x, y = y, z = z,
+            cx = 0.0, cy = self.max_elevation + span * 0.6, cz = -span * 0.9, ly = self.min_elevation,
+            lx = -span, lh = self.max_elevation + span, lz = span)
+    }
+
+    /// Blender Python script adding a plane of one vertex per sample, displaced by the image to the real relief
+    pub fn blender_script(&self, image: &str) -> String {
+        let [x, y, z] = self.terrain_size;
+        format!(
+            "# {image}: {width}x{height} samples {spacing:.3} m apart, elevations {min:.2} to {max:.2} m\n\
+            # Run in the Scripting workspace of Blender 3.0 or later, with the image next to the .blend file\n\
+            import bpy\n\n\
+            bpy.ops.mesh.primitive_grid_add(x_subdivisions={cuts_x}, y_subdivisions={cuts_y}, size=1, location=(0, 0, {min:.3}))\n\
+            plane = bpy.context.active_object\n\
+            plane.name = \"Terrain\"\n\
+            plane.scale = ({x:.3}, {z:.3}, 1)\n\
+            bpy.ops.object.transform_apply(scale=True)\n\n\
+            texture = bpy.data.textures.new(\"Terrain\", type=\"IMAGE\")\n\
+            texture.image = bpy.data.images.load(bpy.path.abspath(\"//{image}\"))\n\
+            texture.image.colorspace_settings.name = \"Non-Color\"\n\
+            texture.extension = \"EXTEND\"\n\n\
+            displace = plane.modifiers.new(\"Displace\", \"DISPLACE\")\n\
+            displace.texture = texture\n\
+            displace.texture_coords = \"UV\"\n\
+            displace.mid_level = 0.0\n\
+            displace.strength = {y:.3}  # Meters between black and white\n",
+            image = image, width = self.width, height = self.height, spacing = self.spacing,
+            min = self.min_elevation, max = self.max_elevation, x = x, y = y, z = z,
+            cuts_x = self.width - 1, cuts_y = self.height - 1)
+    }
+
+    /// Writes the heightmap to `path` in the format of its preset and the scales next to it as `<path>.json`,
+    /// for POV-Ray and Blender also a scene as `<path>.pov` or a script as `<path>.py`. Existing files are handled as
+    /// `conflict` says, the path the heightmap was written to is returned.
+    #[cfg(feature = "fs")]
+    pub fn save(&self, path: &Path, conflict: Conflict) -> io::Result<PathBuf> {
+        let (file, path) = conflict.create(path)?;
+        let mut file = BufWriter::new(file);
+        match self.preset {
+            Preset::Unity => self.write_raw(&mut file)?,
+            Preset::Unreal => self.write_png(&mut file)?,
+            Preset::Povray => self.write_pgm(&mut file)?,
+            Preset::Blender => self.write_tiff(&mut file)?,
+        }
+        file.flush()?;
+        let sibling = |ext: &str| {
+            let mut name = path.as_os_str().to_owned();
+            name.push(ext);
+            conflict.create(Path::new(&name)).map(|(file, _)| BufWriter::new(file))
+        };
+        let image = path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+        let scene = match self.preset {
+            Preset::Povray => Some((".pov", self.pov_scene(&image))),
+            Preset::Blender => Some((".py", self.blender_script(&image))),
+            Preset::Unity | Preset::Unreal => None,
+        };
+        if let Some((ext, text)) = scene {
+            let mut file = sibling(ext)?;
+            file.write_all(text.as_bytes())?;
+            file.flush()?;
+        }
+        let mut file = sibling(".json")?;
+        serde_json::to_writer_pretty(&mut file, self).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        file.flush()?;
+        Ok(path)
+    }
+}
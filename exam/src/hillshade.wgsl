@@ -0,0 +1,119 @@
+// Horn's hillshade, one invocation per cell. Mirrors `render::hillshade_values` in the standard mode, and the
+// `shadows` shader with the horizon angles of horizon.wgsl.
+
+struct Params {
+    ncols: u32,
+    nrows: u32,
+    cell_width: f32,  // East-west and north-south cell spacing in elevation units
+    cell_height: f32,
+    z_factor: f32,
+    azimuth: f32,   // Degrees clockwise from north
+    altitude: f32,  // Degrees above the horizon
+    nodata: f32,
+    edges: u32,     // Border cells: 0 replicate, 1 mirror, 2 NoData like `grid::EdgeMode`
+    shadows: u32,   // 1 darkens the cells whose horizon rises above the sun to `ambient`
+    ambient: f32,
+    pad: u32,
+}
+
+const PI: f32 = 3.14159265358979;
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> elevation: array<f32>;
+@group(0) @binding(2) var<storage, read_write> shade: array<u32>;
+@group(0) @binding(3) var<storage, read> horizon: array<f32>; // Degrees toward the sun, a placeholder without shadows
+
+fn inside(x: i32, y: i32) -> bool {
+    return x >= 0 && y >= 0 && x < i32(params.ncols) && y < i32(params.nrows);
+}
+
+// Elevation of the neighbor (dx, dy) of cell (x, y), filled in like `Grid::edge_window` on the CPU outside the grid
+fn elevation_at(x: i32, y: i32, dx: i32, dy: i32) -> f32 {
+    if (inside(x + dx, y + dy)) {
+        return elevation[u32(y + dy) * params.ncols + u32(x + dx)];
+    }
+    let center = elevation[u32(y) * params.ncols + u32(x)];
+    if (params.edges == 1u && inside(x - dx, y - dy)) {
+        let opposite = elevation[u32(y - dy) * params.ncols + u32(x - dx)];
+        if (opposite != params.nodata) {
+            return 2.0 * center - opposite;
+        }
+    }
+    return center;
+}
+
+// Difference across one row or column of the window like `terrain::weighted_difference`:
+// (difference, 1) or (0, 0) if it has to be left out
+fn difference(low: f32, middle: f32, high: f32, spacing: f32) -> vec2<f32> {
+    let has_low = low != params.nodata;
+    let has_high = high != params.nodata;
+    if (has_low && has_high) {
+        return vec2<f32>((high - low) / (2.0 * spacing), 1.0);
+    }
+    if (middle != params.nodata && has_high) {
+        return vec2<f32>((high - middle) / spacing, 1.0);
+    }
+    if (middle != params.nodata && has_low) {
+        return vec2<f32>((middle - low) / spacing, 1.0);
+    }
+    return vec2<f32>(0.0, 0.0);
+}
+
+// 1-2-1 weighted mean of three differences, NoData neighbors are left out like in `terrain::horn_gradient`
+fn weighted(first: vec2<f32>, second: vec2<f32>, third: vec2<f32>) -> f32 {
+    let weights = first.y + 2.0 * second.y + third.y;
+    if (weights == 0.0) {
+        return 0.0;
+    }
+    return (first.x + 2.0 * second.x + third.x) / weights;
+}
+
+@compute @workgroup_size(16, 16)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.ncols || id.y >= params.nrows) {
+        return;
+    }
+    let i = id.y * params.ncols + id.x;
+    if (elevation[i] == params.nodata) {
+        shade[i] = 0u;
+        return;
+    }
+
+    let x = i32(id.x);
+    let y = i32(id.y);
+    if (params.edges == 2u && (id.x == 0u || id.y == 0u || id.x + 1u == params.ncols || id.y + 1u == params.nrows)) {
+        shade[i] = 0u;
+        return;
+    }
+    var w: array<f32, 9>;
+    for (var k = 0; k < 9; k++) {
+        w[k] = elevation_at(x, y, k % 3 - 1, k / 3 - 1);
+    }
+    let sx = params.cell_width;
+    let sy = params.cell_height;
+    let dzdx = weighted(difference(w[0], w[1], w[2], sx), difference(w[3], w[4], w[5], sx), difference(w[6], w[7], w[8], sx)) * params.z_factor;
+    let dzdy = weighted(difference(w[0], w[3], w[6], sy), difference(w[1], w[4], w[7], sy), difference(w[2], w[5], w[8], sy)) * params.z_factor;
+
+    let slope = atan(sqrt(dzdx * dzdx + dzdy * dzdy));
+    var aspect: f32;
+    if (dzdx != 0.0) {
+        aspect = atan(dzdy / dzdx);
+        if (dzdx < 0.0) {
+            aspect += PI;
+        } else if (dzdy < 0.0) {
+            aspect += 2.0 * PI;
+        }
+    } else if (dzdy > 0.0) {
+        aspect = PI / 2.0;
+    } else {
+        aspect = 3.0 * PI / 2.0;
+    }
+
+    let az = radians(params.azimuth);
+    let alt = radians(params.altitude);
+    var value = clamp(255.0 * (sin(alt) * sin(slope) + cos(alt) * cos(slope) * cos(az - aspect)), 0.0, 255.0);
+    if (params.shadows == 1u && horizon[i] > params.altitude) {
+        value *= params.ambient;
+    }
+    shade[i] = u32(value);
+}
@@ -0,0 +1,139 @@
+//! Commands and a webhook run when a file or the whole batch is finished (`--on-success`, `--on-failure`,
+//! `--webhook`), to plug the batch run into notification and orchestration systems. Both get the outcome as JSON:
+//! a command on its standard input and in `EXAM_*` environment variables, the webhook as the body of a POST.
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::report::{InputReport, Report, Status};
+
+/// How long the webhook may take to connect, send and answer
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What to run on completion; all unset by default
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Hooks {
+    pub on_success: Option<String>, // Shell command run for every file rendered completely and a batch without failures
+    pub on_failure: Option<String>, // Shell command run for every failed file and a batch with failures
+    pub webhook: Option<String>,    // http:// or https:// URL every event is posted to
+}
+
+/// Outcome of one file or of the batch, the payload of every hook
+#[derive(Debug, Serialize)]
+pub struct HookEvent {
+    pub event: &'static str,     // "file" or "batch"
+    pub status: Status,
+    pub input: Option<PathBuf>,  // The file, None for the batch
+    pub outputs: Vec<PathBuf>,   // Files written for the file
+    pub error: Option<String>,
+    pub processed: usize,        // Counts of the batch, 0 for a file
+    pub failed: usize,
+    pub skipped: usize,
+    pub seconds: f64,            // Time of the steps of the file or wall time of the batch
+    pub report: Option<PathBuf>, // report.json of the batch
+}
+
+impl HookEvent {
+    pub fn file(record: &InputReport) -> HookEvent {
+        HookEvent {
+            event: "file", status: record.status, input: Some(record.input.clone()), outputs: record.outputs.clone(), error: record.error.clone(),
+            processed: 0, failed: 0, skipped: 0, seconds: record.timings.values().fold(0.0, |sum, s| sum + s), report: None,
+        }
+    }
+
+    /// The batch of `report`, saved at `path`
+    pub fn batch(report: &Report, path: &Path) -> HookEvent {
+        HookEvent {
+            event: "batch", status: if report.failed > 0 { Status::Failed } else { Status::Ok }, input: None, outputs: Vec::new(), error: None,
+            processed: report.processed, failed: report.failed, skipped: report.skipped, seconds: report.seconds, report: Some(path.to_path_buf()),
+        }
+    }
+}
+
+impl Hooks {
+    pub fn is_active(&self) -> bool {
+        *self != Hooks::default()
+    }
+
+    /// Runs the command for the status of `event` and posts it to the webhook. A hook that fails is a warning,
+    /// returned instead of ending the run.
+    pub fn fire(&self, event: &HookEvent) -> Vec<String> {
+        let payload = match serde_json::to_string(event) {
+            Ok(payload) => payload,
+            Err(e) => return vec![format!("Hook payload: {}", e)],
+        };
+        let command = if event.status == Status::Ok { &self.on_success } else { &self.on_failure };
+        let mut warnings = Vec::new();
+        if let Some(command) = command {
+            if let Err(e) = run_command(command, event, &payload) {
+                warnings.push(format!("Hook {:?} failed: {}", command, e));
+            }
+        }
+        if let Some(url) = &self.webhook {
+            if let Err(e) = post_json(url, &payload) {
+                warnings.push(format!("Webhook {} failed: {}", url, e));
+            }
+        }
+        warnings
+    }
+}
+
+/// Runs `command` through the shell with the payload on its standard input and the main fields in `EXAM_EVENT`,
+/// `EXAM_STATUS`, `EXAM_INPUT`, `EXAM_ERROR` and `EXAM_REPORT`, failing if it exits with an error
+fn run_command(command: &str, event: &HookEvent, payload: &str) -> io::Result<()> {
+    let mut shell = if cfg!(windows) { Command::new("cmd") } else { Command::new("sh") };
+    shell.arg(if cfg!(windows) { "/C" } else { "-c" }).arg(command)
+        .env("EXAM_EVENT", event.event)
+        .env("EXAM_STATUS", if event.status == Status::Ok { "ok" } else { "failed" })
+        .env("EXAM_INPUT", event.input.as_deref().unwrap_or(Path::new("")))
+        .env("EXAM_ERROR", event.error.as_deref().unwrap_or(""))
+        .env("EXAM_REPORT", event.report.as_deref().unwrap_or(Path::new("")))
+        .stdin(Stdio::piped());
+    let mut child = shell.spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A command that doesn't read its input closes the pipe early, which is fine
+        let _ = stdin.write_all(payload.as_bytes());
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::new(io::ErrorKind::Other, format!("exited with {}", status)));
+    }
+    Ok(())
+}
+
+/// POSTs `payload` as JSON to `url`: http:// directly, https:// through `curl` as TLS needs a library
+fn post_json(url: &str, payload: &str) -> io::Result<()> {
+    if url.starts_with("https://") {
+        let mut child = Command::new("curl")
+            .args(["-fsS", "-m", &WEBHOOK_TIMEOUT.as_secs().to_string(), "-X", "POST", "-H", "Content-Type: application/json", "--data-binary", "@-", url])
+            .stdin(Stdio::piped()).stdout(Stdio::null()).spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("https:// webhooks need curl: {}", e)))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(payload.as_bytes())?;
+        }
+        let status = child.wait()?;
+        return if status.success() { Ok(()) } else { Err(io::Error::new(io::ErrorKind::Other, format!("curl exited with {}", status))) };
+    }
+
+    let rest = url.strip_prefix("http://").ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "expected an http:// or https:// URL"))?;
+    let (host, path) = rest.split_once('/').map_or((rest, "/".to_string()), |(host, path)| (host, format!("/{}", path)));
+    let address = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+    let mut stream = TcpStream::connect(&address)?;
+    stream.set_read_timeout(Some(WEBHOOK_TIMEOUT))?;
+    stream.set_write_timeout(Some(WEBHOOK_TIMEOUT))?;
+    write!(stream, "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path, host, payload.len(), payload)?;
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer)?;
+    // Status line "HTTP/1.1 200 OK"
+    match answer.split_whitespace().nth(1).and_then(|code| code.parse::<u16>().ok()) {
+        Some(code) if (200..300).contains(&code) => Ok(()),
+        Some(code) => Err(io::Error::new(io::ErrorKind::Other, format!("answered {}", code))),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, "no HTTP answer")),
+    }
+}
@@ -0,0 +1,106 @@
+//! Horizon angles toward one azimuth for every cell, for solar exposure and visibility work.
+//! The grid is resampled onto a lattice rotated to the azimuth, and a sweep runs along each lattice line from
+//! the far end back, keeping the upper convex hull of the profile already passed, on which the horizon of the
+//! next sample is a tangent point. Every sample is pushed and popped at most once, so a line costs linear time
+//! instead of a ray march per cell. The cells then interpolate the horizons of the surrounding samples.
+
+use crate::grid::{Grid, Interpolation};
+use crate::progress::{Cancelled, Tracker};
+
+/// Elevation angle in degrees of the highest terrain seen from each cell toward `azimuth` (degrees clockwise from north).
+/// Cells with no terrain of the grid in that direction get 0, as if the land beyond the edge were level with them;
+/// NoData cells stay NoData but don't block the view across them.
+pub fn horizon_angles(grid: &Grid, azimuth: f64) -> Grid {
+    horizon_angles_tracked(grid, azimuth, &Tracker::none()).expect("an unwatched tracker is never cancelled")
+}
+
+/// The lattice rotated to an azimuth that the horizon is swept along, on the ground geometry of a grid
+pub struct Lattice {
+    pub east: f64,      // Direction of the azimuth
+    pub north: f64,
+    pub dx: f64,        // Ground spacing of the columns and rows, see [`Grid::ground_spacing`]
+    pub dy: f64,
+    pub step: f64,      // Ground spacing of the lattice
+    pub u_min: f64,     // First lattice point along and across the azimuth
+    pub v_min: f64,
+    pub samples: usize, // Lattice points per line
+    pub lines: usize,
+}
+
+impl Lattice {
+    pub fn new(grid: &Grid, azimuth: f64) -> Lattice {
+        let (dx, dy) = grid.ground_spacing();
+        let (east, north) = (azimuth.to_radians().sin(), azimuth.to_radians().cos());
+        let step = grid.ground_cellsize();
+        let mut lattice = Lattice { east, north, dx, dy, step, u_min: 0.0, v_min: 0.0, samples: 0, lines: 0 };
+        let corners = [(0.0, 0.0), ((grid.ncols - 1) as f64 * dx, 0.0), (0.0, -((grid.nrows - 1) as f64) * dy),
+            ((grid.ncols - 1) as f64 * dx, -((grid.nrows - 1) as f64) * dy)].map(|(a, b)| lattice.turn(a, b));
+        lattice.u_min = corners.iter().map(|c| c.0).fold(f64::MAX, f64::min);
+        lattice.v_min = corners.iter().map(|c| c.1).fold(f64::MAX, f64::min);
+        // One more lattice point than steps fit, so the lattice covers every cell center on both ends
+        lattice.samples = ((corners.iter().map(|c| c.0).fold(f64::MIN, f64::max) - lattice.u_min) / step + 1e-9).floor() as usize + 2;
+        lattice.lines = ((corners.iter().map(|c| c.1).fold(f64::MIN, f64::max) - lattice.v_min) / step + 1e-9).floor() as usize + 2;
+        lattice
+    }
+
+    /// Meters east and north of the center of the upper left cell turned into the distance along the azimuth `u`
+    /// and the offset across it `v`. The turn is its own inverse.
+    pub fn turn(&self, a: f64, b: f64) -> (f64, f64) {
+        (a * self.east + b * self.north, a * self.north - b * self.east)
+    }
+}
+
+/// [`horizon_angles`] reporting the lattice lines swept to `tracker` and stopping when it is cancelled
+pub fn horizon_angles_tracked(grid: &Grid, azimuth: f64, tracker: &Tracker) -> Result<Grid, Cancelled> {
+    let geometry = Lattice::new(grid, azimuth);
+    let Lattice { dx, dy, step, u_min, v_min, samples, lines, .. } = geometry;
+    let turn = |a: f64, b: f64| geometry.turn(a, b);
+    let (x0, y0) = grid.origin();
+
+    // Horizon of every lattice point, NaN where the lattice is off the grid or on NoData
+    let mut lattice = vec![f32::NAN; samples * lines];
+    let mut hull: Vec<(f64, f64)> = Vec::new(); // (distance along, elevation), farthest at the bottom
+    for line in 0..lines {
+        tracker.advance(line as f32 / lines as f32)?;
+        hull.clear();
+        let v = v_min + line as f64 * step;
+        for sample in (0..samples).rev() {
+            let u = u_min + sample as f64 * step;
+            let (a, b) = turn(u, v);
+            let Some(height) = grid.sample(x0 + a / dx * grid.cellsize + grid.cellsize / 2.0, y0 + b / dy * grid.cellsize - grid.cellsize / 2.0, Interpolation::Bilinear) else {
+                continue;
+            };
+            let height = height as f64;
+            // Gradient from this sample to a point of the profile ahead of it
+            let gradient = |&(d, h): &(f64, f64)| (h - height) / (d - u);
+            // A hull point below the line to the one behind it is hidden from here and from every sample after
+            while hull.len() >= 2 && gradient(&hull[hull.len() - 1]) <= gradient(&hull[hull.len() - 2]) {
+                hull.pop();
+            }
+            lattice[line * samples + sample] = hull.last().map_or(0.0, |top| gradient(top).atan().to_degrees() as f32);
+            hull.push((u, height));
+        }
+    }
+
+    // Bilinear between the valid lattice points around each cell
+    let data = (0..grid.nrows).map(|y| (0..grid.ncols).map(|x| {
+        if !grid.is_valid(x, y) {
+            return grid.nodata;
+        }
+        let (u, v) = turn(x as f64 * dx, -(y as f64) * dy);
+        let (fs, fl) = ((u - u_min) / step, (v - v_min) / step);
+        let (s0, l0) = (fs.floor() as usize, fl.floor() as usize);
+        let (ts, tl) = (fs - s0 as f64, fl - l0 as f64);
+        let (mut sum, mut weights) = (0.0, 0.0);
+        for (s, l, w) in [(s0, l0, (1.0 - ts) * (1.0 - tl)), (s0 + 1, l0, ts * (1.0 - tl)), (s0, l0 + 1, (1.0 - ts) * tl), (s0 + 1, l0 + 1, ts * tl)] {
+            let angle = if s < samples && l < lines { lattice[l * samples + s] } else { f32::NAN };
+            if !angle.is_nan() && w > 0.0 {
+                sum += angle as f64 * w;
+                weights += w;
+            }
+        }
+        if weights > 0.0 { (sum / weights) as f32 } else { 0.0 }
+    }).collect()).collect();
+    tracker.advance(1.0)?;
+    Ok(Grid { data, ..grid.clone() })
+}
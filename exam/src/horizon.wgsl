@@ -0,0 +1,148 @@
+// Horizon angle toward one azimuth, the sweep of `horizon::horizon_angles` in two passes: `sweep` runs one invocation
+// per line of the lattice rotated to the azimuth, keeping the upper convex hull of the profile passed, and
+// `resample` interpolates the horizon of every cell from the lattice points around it.
+
+struct Params {
+    ncols: u32,
+    nrows: u32,
+    samples: u32,     // Lattice points per line
+    lines: u32,
+    east: f32,        // Direction of the azimuth
+    north: f32,
+    dx: f32,          // Ground spacing of the columns and rows
+    dy: f32,
+    step: f32,        // Ground spacing of the lattice
+    u_min: f32,       // Lattice origin along and across the azimuth, from the center of the upper left cell
+    v_min: f32,
+    nodata: f32,
+    accumulate: u32,  // 0 writes the angle in degrees, 1 adds the sine of the part above the horizontal for the sky-view factor
+    pad0: u32,
+    pad1: u32,
+    pad2: u32,
+}
+
+const NONE: f32 = -1000.0; // Lattice point off the grid or on NoData; angles stay within ±90
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> elevation: array<f32>;
+@group(0) @binding(2) var<storage, read_write> horizon: array<f32>;
+@group(0) @binding(3) var<storage, read_write> lattice: array<f32>;
+@group(0) @binding(4) var<storage, read_write> hull: array<vec2<f32>>; // (distance along, elevation) per line, farthest first
+
+fn at(c: u32, r: u32) -> f32 {
+    return elevation[r * params.ncols + c];
+}
+
+// Meters east and north of the center of the upper left cell to meters along and across the azimuth, and back
+fn turn(a: f32, b: f32) -> vec2<f32> {
+    return vec2<f32>(a * params.east + b * params.north, a * params.north - b * params.east);
+}
+
+// Bilinear between cell centers like `Grid::sample`, the nearest cell where a corner is NoData, NONE off the grid
+fn height_at(col: f32, row: f32) -> f32 {
+    if (col < 0.0 || row < 0.0 || col >= f32(params.ncols) || row >= f32(params.nrows)) {
+        return NONE;
+    }
+    let nearest = at(u32(col), u32(row));
+    let fc = clamp(col - 0.5, 0.0, f32(params.ncols - 1u));
+    let fr = clamp(row - 0.5, 0.0, f32(params.nrows - 1u));
+    let c0 = u32(fc);
+    let r0 = u32(fr);
+    let c1 = min(c0 + 1u, params.ncols - 1u);
+    let r1 = min(r0 + 1u, params.nrows - 1u);
+    let a = at(c0, r0);
+    let b = at(c1, r0);
+    let c = at(c0, r1);
+    let d = at(c1, r1);
+    if (a == params.nodata || b == params.nodata || c == params.nodata || d == params.nodata) {
+        return select(nearest, NONE, nearest == params.nodata);
+    }
+    let top = a + (b - a) * (fc - f32(c0));
+    let bottom = c + (d - c) * (fc - f32(c0));
+    return top + (bottom - top) * (fr - f32(r0));
+}
+
+@compute @workgroup_size(64)
+fn sweep(@builtin(global_invocation_id) id: vec3<u32>) {
+    let line = id.x;
+    if (line >= params.lines) {
+        return;
+    }
+    let v = params.v_min + f32(line) * params.step;
+    let base = line * params.samples;
+    var top = 0u; // Points on the hull
+    for (var sample_index = params.samples; sample_index > 0u; sample_index--) {
+        let s = sample_index - 1u;
+        let u = params.u_min + f32(s) * params.step;
+        let ab = turn(u, v);
+        let height = height_at(ab.x / params.dx + 0.5, -ab.y / params.dy + 0.5);
+        if (height == NONE) {
+            lattice[base + s] = NONE;
+            continue;
+        }
+        // A hull point below the line to the one behind it is hidden from here and from every sample after
+        while (top >= 2u) {
+            let last = hull[base + top - 1u];
+            let before = hull[base + top - 2u];
+            if ((last.y - height) / (last.x - u) > (before.y - height) / (before.x - u)) {
+                break;
+            }
+            top--;
+        }
+        var angle = 0.0;
+        if (top > 0u) {
+            let last = hull[base + top - 1u];
+            angle = degrees(atan((last.y - height) / (last.x - u)));
+        }
+        lattice[base + s] = angle;
+        hull[base + top] = vec2<f32>(u, height);
+        top++;
+    }
+}
+
+@compute @workgroup_size(16, 16)
+fn resample(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.ncols || id.y >= params.nrows) {
+        return;
+    }
+    let i = id.y * params.ncols + id.x;
+    if (elevation[i] == params.nodata) {
+        if (params.accumulate == 0u) {
+            horizon[i] = params.nodata;
+        }
+        return;
+    }
+
+    // Bilinear between the valid lattice points around the cell
+    let uv = turn(f32(id.x) * params.dx, -f32(id.y) * params.dy);
+    let fs = (uv.x - params.u_min) / params.step;
+    let fl = (uv.y - params.v_min) / params.step;
+    let s0 = u32(max(floor(fs), 0.0));
+    let l0 = u32(max(floor(fl), 0.0));
+    let ts = fs - f32(s0);
+    let tl = fl - f32(l0);
+    var sum = 0.0;
+    var weights = 0.0;
+    for (var k = 0u; k < 4u; k++) {
+        let s = s0 + (k & 1u);
+        let l = l0 + (k >> 1u);
+        let w = select(1.0 - ts, ts, (k & 1u) == 1u) * select(1.0 - tl, tl, (k >> 1u) == 1u);
+        if (s < params.samples && l < params.lines && w > 0.0) {
+            let angle = lattice[l * params.samples + s];
+            if (angle != NONE) {
+                sum += angle * w;
+                weights += w;
+            }
+        }
+    }
+    var angle = 0.0;
+    if (weights > 0.0) {
+        angle = sum / weights;
+    }
+
+    if (params.accumulate == 1u) {
+        horizon[i] += sin(radians(max(angle, 0.0)));
+    } else {
+        horizon[i] = angle;
+    }
+}
@@ -0,0 +1,231 @@
+//! Minimal HTTP/1.1 server behind `exam serve` and the `--metrics` endpoint: one request per connection with its body
+//! sized by Content-Length, answered by a handler on one of a fixed number of worker threads. Enough for a handful of
+//! routes without pulling in a web framework.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// How long a client may pause while sending its request
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Longest request line or header accepted
+const MAX_LINE: usize = 16 * 1024;
+
+/// Most headers accepted in one request
+const MAX_HEADERS: usize = 100;
+
+/// A parsed request
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,                 // Percent-decoded, without the query string
+    pub query: Vec<(String, String)>, // Decoded parameters in their order, "" for a parameter without value
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Value of the first query parameter `name`
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.query.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+}
+
+/// An answer to a request
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: Vec<u8>) -> Response {
+        Response { status, content_type: content_type.to_string(), headers: Vec::new(), body }
+    }
+
+    /// Plain text, with a line break added
+    pub fn text(status: u16, message: &str) -> Response {
+        Response::new(status, "text/plain; charset=utf-8", format!("{}\n", message).into_bytes())
+    }
+
+    pub fn json<T: Serialize>(status: u16, value: &T) -> Response {
+        match serde_json::to_vec_pretty(value) {
+            Ok(body) => Response::new(status, "application/json", body),
+            Err(e) => Response::text(500, &e.to_string()),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        // Line breaks would end the header early
+        self.headers.push((name.to_string(), value.replace(['\r', '\n'], " ")));
+        self
+    }
+}
+
+/// Running server, its workers answer until the process ends
+pub struct Server {
+    pub address: SocketAddr, // Bound address, with the port the system picked for port 0
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Server {
+    /// Blocks for as long as the server runs
+    pub fn wait(self) {
+        for worker in self.workers {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        _ if status >= 500 => "Internal Server Error",
+        _ => "",
+    }
+}
+
+/// Decodes %XX escapes and, in query strings, + as space; invalid escapes stay as they are
+pub fn percent_decode(text: &str, plus_as_space: bool) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok()).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', _) if plus_as_space => out.push(b' '),
+            (byte, _) => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = Vec::new();
+    reader.by_ref().take(MAX_LINE as u64).read_until(b'\n', &mut line)?;
+    if !line.ends_with(b"\n") {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "request line or header too long or cut off"));
+    }
+    Ok(String::from_utf8_lossy(&line).trim_end().to_string())
+}
+
+/// Reads one request from `stream`. Bodies above `max_body` bytes are refused with an InvalidInput error, a
+/// malformed request or one with more than [`MAX_HEADERS`] headers is an InvalidData error. Clients sending `Expect: 100-continue` are told to go on.
+pub fn read_request(stream: &mut TcpStream, max_body: u64) -> io::Result<Request> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let line = read_line(&mut reader)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("malformed request line {:?}", line))),
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query.split('&').filter(|pair| !pair.is_empty()).map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        (percent_decode(key, true), percent_decode(value, true))
+    }).collect();
+
+    let (mut length, mut expect_continue) = (0, false);
+    for count in 0.. {
+        let header = read_line(&mut reader)?;
+        if header.is_empty() {
+            break;
+        }
+        if count == MAX_HEADERS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("more than {} headers", MAX_HEADERS)));
+        }
+        let (name, value) = header.split_once(':').unwrap_or((&header, ""));
+        match (name.trim().to_lowercase().as_str(), value.trim()) {
+            ("content-length", value) => length = value.parse()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("invalid Content-Length {:?}", value)))?,
+            ("transfer-encoding", value) if !value.eq_ignore_ascii_case("identity") => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "chunked bodies aren't supported, send a Content-Length"));
+            }
+            ("expect", value) => expect_continue = value.eq_ignore_ascii_case("100-continue"),
+            _ => {}
+        }
+    }
+    if length > max_body {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("body of {} bytes, at most {} accepted", length, max_body)));
+    }
+    if expect_continue && length > 0 {
+        write!(stream, "HTTP/1.1 100 Continue\r\n\r\n")?;
+    }
+    let mut body = Vec::new();
+    reader.take(length).read_to_end(&mut body)?;
+    if body.len() as u64 != length {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "body shorter than its Content-Length"));
+    }
+    Ok(Request { method, path: percent_decode(path, false), query, body })
+}
+
+pub fn write_response<W: Write>(out: &mut W, response: &Response) -> io::Result<()> {
+    write!(out, "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status, reason(response.status), response.content_type, response.body.len())?;
+    for (name, value) in &response.headers {
+        write!(out, "{}: {}\r\n", name, value)?;
+    }
+    write!(out, "\r\n")?;
+    out.write_all(&response.body)?;
+    out.flush()
+}
+
+/// Answers one connection, a client that hangs up only loses its own answer. A handler that panics answers 500 and
+/// the worker goes on, so bad requests can't empty the pool.
+fn answer<H: Fn(Request) -> Response>(mut stream: TcpStream, max_body: u64, handler: &H) {
+    if stream.set_read_timeout(Some(READ_TIMEOUT)).is_err() {
+        return;
+    }
+    let response = match read_request(&mut stream, max_body) {
+        Ok(request) => panic::catch_unwind(AssertUnwindSafe(|| handler(request))).unwrap_or_else(|payload| {
+            let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown cause".into());
+            Response::text(500, &format!("Handler panicked: {}", message))
+        }),
+        Err(e) if e.kind() == io::ErrorKind::InvalidInput => Response::text(413, &e.to_string()),
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => Response::text(400, &e.to_string()),
+        Err(_) => return,
+    };
+    let _ = write_response(&mut stream, &response);
+}
+
+/// Listens on `address` and answers every request with `handler` on `workers` threads, accepting bodies of up to
+/// `max_body` bytes
+pub fn listen<H>(address: &str, workers: usize, max_body: u64, handler: H) -> io::Result<Server>
+where
+    H: Fn(Request) -> Response + Send + Sync + 'static,
+{
+    let address = address.to_socket_addrs()?.next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} resolves to no address", address)))?;
+    let listener = Arc::new(TcpListener::bind(address)?);
+    let handler = Arc::new(handler);
+    let workers = (0..workers.max(1)).map(|_| {
+        let (listener, handler) = (Arc::clone(&listener), Arc::clone(&handler));
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                answer(stream, max_body, &*handler);
+            }
+        })
+    }).collect();
+    Ok(Server { address: listener.local_addr()?, workers })
+}
@@ -0,0 +1,134 @@
+//! SQLite database of the inputs of a batch run (`--job-db`), so several runs, on one machine or on several sharing
+//! the input folder and the database over a network file system, work through one batch together. Every input is a
+//! row that a run claims atomically before rendering it and then marks as done or failed with its timings and the
+//! checksums of its outputs. A claim holds for a lease, after which a crashed run's inputs are taken up again.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, TransactionBehavior};
+
+use crate::dedup::checksum;
+use crate::report::{InputReport, Status};
+
+/// How long a claim holds unless --job-lease is given: longer than any single file takes to render
+pub const DEFAULT_LEASE: Duration = Duration::from_secs(3600);
+
+/// How long a statement waits for another run holding the database
+const BUSY_TIMEOUT: Duration = Duration::from_secs(60);
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS jobs (
+    input TEXT PRIMARY KEY,            -- Path below the input folder, with / between folders
+    state TEXT NOT NULL,               -- pending, running, done or failed
+    worker TEXT,                       -- host:pid of the run that claimed it last
+    attempts INTEGER NOT NULL DEFAULT 0,
+    claimed REAL,                      -- Unix times in seconds
+    lease_until REAL,
+    finished REAL,
+    seconds REAL,                      -- Time of all steps
+    timings TEXT,                      -- JSON object of the seconds per step
+    error TEXT
+);
+CREATE TABLE IF NOT EXISTS outputs (
+    input TEXT NOT NULL,
+    path TEXT NOT NULL,                -- Below the output root
+    checksum TEXT NOT NULL,            -- See dedup::checksum
+    PRIMARY KEY (input, path)
+);";
+
+/// Open job database, shared by the workers of a run
+pub struct JobDb {
+    connection: Mutex<Connection>,
+    worker: String,
+    lease: Duration,
+}
+
+fn to_io(e: rusqlite::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+fn now() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64())
+}
+
+/// Key of an input: its path below the input folder with / between folders, the same on every machine
+pub fn job_key(input_dir: &Path, path: &Path) -> String {
+    path.strip_prefix(input_dir).unwrap_or(path).to_string_lossy().replace('\\', "/")
+}
+
+impl JobDb {
+    /// Opens or creates the database at `path`. Claims hold for `lease`.
+    pub fn open(path: &Path, lease: Duration) -> io::Result<JobDb> {
+        let connection = Connection::open(path).map_err(to_io)?;
+        connection.busy_timeout(BUSY_TIMEOUT).map_err(to_io)?;
+        connection.execute_batch(SCHEMA).map_err(to_io)?;
+        let host = env::var("HOSTNAME").or_else(|_| env::var("COMPUTERNAME")).unwrap_or_else(|_| "localhost".into());
+        Ok(JobDb { connection: Mutex::new(connection), worker: format!("{}:{}", host, process::id()), lease })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Adds the inputs not in the database yet as pending, returning how many were new. With `retry_failed` the
+    /// failed ones are pending again.
+    pub fn register(&self, keys: &[String], retry_failed: bool) -> io::Result<usize> {
+        let mut connection = self.lock();
+        let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate).map_err(to_io)?;
+        let mut added = 0;
+        for key in keys {
+            added += transaction.execute("INSERT OR IGNORE INTO jobs (input, state) VALUES (?1, 'pending')", params![key]).map_err(to_io)?;
+        }
+        if retry_failed {
+            transaction.execute("UPDATE jobs SET state = 'pending' WHERE state = 'failed'", params![]).map_err(to_io)?;
+        }
+        transaction.commit().map_err(to_io)?;
+        Ok(added)
+    }
+
+    /// Claims the input for this run if it is pending or its claim by another run has run out. False if another run
+    /// holds it or it is finished.
+    pub fn claim(&self, key: &str) -> io::Result<bool> {
+        let now = now();
+        let changed = self.lock().execute(
+            "UPDATE jobs SET state = 'running', worker = ?1, attempts = attempts + 1, claimed = ?2, lease_until = ?3
+             WHERE input = ?4 AND (state = 'pending' OR (state = 'running' AND lease_until < ?2))",
+            params![self.worker, now, now + self.lease.as_secs_f64(), key]).map_err(to_io)?;
+        Ok(changed == 1)
+    }
+
+    /// Records the outcome of a claimed input with the checksums of its outputs, found below `output_root`
+    pub fn finish(&self, key: &str, record: &InputReport, output_root: &Path) -> io::Result<()> {
+        let sums: Vec<(String, String)> = record.outputs.iter()
+            .filter_map(|path| Some((path.strip_prefix(output_root).unwrap_or(path).to_string_lossy().replace('\\', "/"), checksum(path).ok()?)))
+            .collect();
+        let state = if record.status == Status::Ok { "done" } else { "failed" };
+        let timings = serde_json::to_string(&record.timings).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let seconds = record.timings.values().fold(0.0, |sum, s| sum + s);
+
+        let mut connection = self.lock();
+        let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate).map_err(to_io)?;
+        transaction.execute(
+            "UPDATE jobs SET state = ?1, finished = ?2, seconds = ?3, timings = ?4, error = ?5, lease_until = NULL WHERE input = ?6",
+            params![state, now(), seconds, timings, record.error, key]).map_err(to_io)?;
+        transaction.execute("DELETE FROM outputs WHERE input = ?1", params![key]).map_err(to_io)?;
+        for (path, sum) in &sums {
+            transaction.execute("INSERT INTO outputs (input, path, checksum) VALUES (?1, ?2, ?3)", params![key, path, sum]).map_err(to_io)?;
+        }
+        transaction.commit().map_err(to_io)
+    }
+
+    /// Number of inputs in every state
+    pub fn counts(&self) -> io::Result<BTreeMap<String, usize>> {
+        let connection = self.lock();
+        let mut statement = connection.prepare("SELECT state, COUNT(*) FROM jobs GROUP BY state").map_err(to_io)?;
+        let rows = statement.query_map(params![], |row| Ok((row.get(0)?, row.get(1)?))).map_err(to_io)?;
+        rows.collect::<Result<_, _>>().map_err(to_io)
+    }
+}
@@ -0,0 +1,62 @@
+//! Record of the inputs a batch run has finished, so `--resume` can skip them after a crash.
+
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// File name of the journal below the output root
+pub const JOURNAL_FILE: &str = "journal.txt";
+
+/// One line per finished input, appended and flushed as soon as the input is done
+pub struct Journal {
+    done: HashSet<PathBuf>, // Canonical paths of the finished inputs
+    file: File,
+}
+
+/// Canonical form of an input path so entries match however the input directory was spelled
+pub fn input_key(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Canonical paths of the inputs the journal in `dir` lists as finished, without opening it for writing
+pub fn finished(dir: &Path) -> io::Result<HashSet<PathBuf>> {
+    let path = dir.join(JOURNAL_FILE);
+    let mut done = HashSet::new();
+    if path.exists() {
+        for line in BufReader::new(File::open(&path)?).lines() {
+            let line = line?;
+            if !line.is_empty() {
+                done.insert(PathBuf::from(line));
+            }
+        }
+    }
+    Ok(done)
+}
+
+impl Journal {
+    /// Opens the journal in `dir`, keeping the earlier entries if `keep` is set and starting empty otherwise
+    pub fn open(dir: &Path, keep: bool) -> io::Result<Journal> {
+        let path = dir.join(JOURNAL_FILE);
+        let done = if keep { finished(dir)? } else { HashSet::new() };
+        let file = OpenOptions::new().create(true).append(true).truncate(false).open(&path)?;
+        if !keep {
+            file.set_len(0)?;
+        }
+        Ok(Journal { done, file })
+    }
+
+    /// Whether `input` was finished by an earlier run
+    pub fn is_done(&self, input: &Path) -> bool {
+        self.done.contains(&input_key(input))
+    }
+
+    /// Marks `input` as finished
+    pub fn record(&mut self, input: &Path) -> io::Result<()> {
+        let key = input_key(input);
+        writeln!(self.file, "{}", key.display())?;
+        self.file.sync_data()?;
+        self.done.insert(key);
+        Ok(())
+    }
+}
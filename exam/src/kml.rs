@@ -0,0 +1,208 @@
+//! KMZ export: a rendered image as a Google Earth GroundOverlay, optionally split into a
+//! super-overlay whose tiles are loaded by region as the view zooms in.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use image::imageops::{self, FilterType};
+use image::DynamicImage;
+
+use crate::grid::Grid;
+use crate::output::{write_image_to, ImageFormat, OutputFormat, OutputTarget};
+
+/// Pixels a tile must cover on screen before it is drawn, and before its children are loaded
+const MIN_LOD_PIXELS: u32 = 128;
+
+/// Geographic bounds of an overlay in degrees
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LatLonBox {
+    pub north: f64,
+    pub south: f64,
+    pub east: f64,
+    pub west: f64,
+}
+
+impl LatLonBox {
+    /// Bounds of a grid in longitude/latitude degrees, None for projected grids
+    pub fn of(grid: &Grid) -> Option<LatLonBox> {
+        if !grid.is_geographic() {
+            return None;
+        }
+        let (west, north) = grid.origin();
+        Some(LatLonBox { north, south: grid.yllcorner, east: west + grid.ncols as f64 * grid.cellsize, west })
+    }
+
+    /// Part of the box covering the pixels `x0..x1`, `y0..y1` of an image `width` x `height` pixels large
+    fn crop(&self, (x0, y0, x1, y1): (u32, u32, u32, u32), width: u32, height: u32) -> LatLonBox {
+        let lon = |x: u32| self.west + (self.east - self.west) * x as f64 / width as f64;
+        let lat = |y: u32| self.north - (self.north - self.south) * y as f64 / height as f64;
+        LatLonBox { north: lat(y0), south: lat(y1), east: lon(x1), west: lon(x0) }
+    }
+
+    fn kml(&self, tag: &str) -> String {
+        format!("<{tag}><north>{}</north><south>{}</south><east>{}</east><west>{}</west></{tag}>", self.north, self.south, self.east, self.west)
+    }
+}
+
+/// Saves `img` as product "overlay" (.kmz), see [`write_kmz`]
+pub fn save_kmz(img: &DynamicImage, bounds: &LatLonBox, format: &OutputFormat, tile: Option<u32>, target: &OutputTarget) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(target.path("overlay", "kmz")?)?);
+    write_kmz(img, bounds, &target.stem, format, tile, &mut file)?;
+    file.flush()
+}
+
+/// Writes a KMZ holding `doc.kml` and the image as a GroundOverlay stretched over `bounds`.
+/// With `tile`, images larger than `tile` pixels become a quadtree of tiles at most that size, each level
+/// doubling the resolution, linked through regions so Google Earth only loads what is in view.
+/// Images are PNG unless `format` asks for JPEG, the formats Google Earth reads.
+pub fn write_kmz<W: Write>(img: &DynamicImage, bounds: &LatLonBox, name: &str, format: &OutputFormat, tile: Option<u32>, out: W) -> io::Result<()> {
+    let format = match format.format {
+        ImageFormat::Jpeg => *format,
+        _ => OutputFormat::default(),
+    };
+    let ext = format.format.extension();
+    let mut zip = Zip::new(out);
+
+    let levels = match tile {
+        Some(tile) => {
+            let tile = tile.max(MIN_LOD_PIXELS);
+            let size = img.width().max(img.height()) as f64;
+            (size / tile as f64).log2().ceil().max(0.0) as u32
+        }
+        None => 0,
+    };
+    if tile.is_none() || levels == 0 {
+        zip.add("doc.kml", document(name, &overlay(&format!("overlay.{}", ext), bounds, 0)).as_bytes())?;
+        zip.add(&format!("overlay.{}", ext), &encode(img.clone(), &format)?)?;
+        return zip.finish();
+    }
+
+    let tile = tile.unwrap().max(MIN_LOD_PIXELS);
+    let link = |href: &str, bounds: &LatLonBox, min_lod_pixels: u32| {
+        format!("<NetworkLink>{}<Link><href>{}</href><viewRefreshMode>onRegion</viewRefreshMode></Link></NetworkLink>", region(bounds, min_lod_pixels), href)
+    };
+    zip.add("doc.kml", document(name, &link("tiles/0_0_0.kml", bounds, 0)).as_bytes())?;
+
+    let (width, height) = (img.width(), img.height());
+    for level in 0..=levels {
+        let count = 1 << level;
+        for y in 0..count {
+            for x in 0..count {
+                let pixels = |i: u32, n: u32, size: u32| (size as u64 * i as u64 / n as u64) as u32;
+                let area = (pixels(x, count, width), pixels(y, count, height), pixels(x + 1, count, width), pixels(y + 1, count, height));
+                if area.2 <= area.0 || area.3 <= area.1 {
+                    continue;
+                }
+                let tile_bounds = bounds.crop(area, width, height);
+                let part = img.crop_imm(area.0, area.1, area.2 - area.0, area.3 - area.1);
+                let part = if part.width() > tile || part.height() > tile {
+                    DynamicImage::from(imageops::resize(&part.to_rgba8(), tile.min(part.width()), tile.min(part.height()), FilterType::Triangle))
+                } else {
+                    part
+                };
+                let file = format!("{}_{}_{}", level, x, y);
+
+                // Tiles lie next to each other in tiles/, so children are linked by name without the directory
+                let mut body = region(&tile_bounds, if level == 0 { 0 } else { MIN_LOD_PIXELS });
+                body += &overlay(&format!("{}.{}", file, ext), &tile_bounds, level);
+                if level < levels {
+                    for (cx, cy) in [(2 * x, 2 * y), (2 * x + 1, 2 * y), (2 * x, 2 * y + 1), (2 * x + 1, 2 * y + 1)] {
+                        let child = (pixels(cx, 2 * count, width), pixels(cy, 2 * count, height), pixels(cx + 1, 2 * count, width), pixels(cy + 1, 2 * count, height));
+                        if child.2 > child.0 && child.3 > child.1 {
+                            body += &link(&format!("{}_{}_{}.kml", level + 1, cx, cy), &bounds.crop(child, width, height), MIN_LOD_PIXELS);
+                        }
+                    }
+                }
+                zip.add(&format!("tiles/{}.kml", file), document(&file, &body).as_bytes())?;
+                zip.add(&format!("tiles/{}.{}", file, ext), &encode(part, &format)?)?;
+            }
+        }
+    }
+    zip.finish()
+}
+
+fn document(name: &str, body: &str) -> String {
+    format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<kml xmlns=\"http://www.opengis.net/kml/2.2\"><Document><name>{}</name>{}</Document></kml>\n",
+        escape(name), body)
+}
+
+fn overlay(href: &str, bounds: &LatLonBox, draw_order: u32) -> String {
+    format!("<GroundOverlay><drawOrder>{}</drawOrder><Icon><href>{}</href></Icon>{}</GroundOverlay>", draw_order, href, bounds.kml("LatLonBox"))
+}
+
+fn region(bounds: &LatLonBox, min_lod_pixels: u32) -> String {
+    format!("<Region>{}<Lod><minLodPixels>{}</minLodPixels><maxLodPixels>-1</maxLodPixels></Lod></Region>", bounds.kml("LatLonAltBox"), min_lod_pixels)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn encode(img: DynamicImage, format: &OutputFormat) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    write_image_to(img, format, &mut bytes)?;
+    Ok(bytes)
+}
+
+/// Minimal ZIP writer storing entries uncompressed, the images inside are compressed already
+struct Zip<W: Write> {
+    out: W,
+    offset: u32,
+    entries: Vec<(String, u32, u32, u32)>, // Name, CRC-32, size and offset of the local header
+}
+
+impl<W: Write> Zip<W> {
+    fn new(out: W) -> Self {
+        Zip { out, offset: 0, entries: Vec::new() }
+    }
+
+    fn add(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let crc = crc32fast::hash(data);
+        let size = u32::try_from(data.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "KMZ entry larger than 4 GB"))?;
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend(0x04034b50u32.to_le_bytes());
+        header.extend(20u16.to_le_bytes()); // Version needed, 2.0
+        header.extend([0u8; 8]); // Flags, method (stored), time and date
+        header.extend(crc.to_le_bytes());
+        header.extend(size.to_le_bytes());
+        header.extend(size.to_le_bytes());
+        header.extend((name.len() as u16).to_le_bytes());
+        header.extend(0u16.to_le_bytes());
+        header.extend(name.as_bytes());
+        self.out.write_all(&header)?;
+        self.out.write_all(data)?;
+
+        self.entries.push((name.to_string(), crc, size, self.offset));
+        self.offset = self.offset.checked_add(header.len() as u32 + size)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "KMZ larger than 4 GB"))?;
+        Ok(())
+    }
+
+    /// Writes the central directory
+    fn finish(mut self) -> io::Result<()> {
+        let start = self.offset;
+        let mut directory = Vec::new();
+        for (name, crc, size, offset) in &self.entries {
+            directory.extend(0x02014b50u32.to_le_bytes());
+            directory.extend(20u16.to_le_bytes()); // Version made by
+            directory.extend(20u16.to_le_bytes()); // Version needed
+            directory.extend([0u8; 8]);
+            directory.extend(crc.to_le_bytes());
+            directory.extend(size.to_le_bytes());
+            directory.extend(size.to_le_bytes());
+            directory.extend((name.len() as u16).to_le_bytes());
+            directory.extend([0u8; 12]); // Extra and comment length, disk, attributes
+            directory.extend(offset.to_le_bytes());
+            directory.extend(name.as_bytes());
+        }
+        let count = self.entries.len() as u16;
+        directory.extend(0x06054b50u32.to_le_bytes());
+        directory.extend([0u8; 4]); // Disk numbers
+        directory.extend(count.to_le_bytes());
+        directory.extend(count.to_le_bytes());
+        directory.extend((directory.len() as u32 - 12).to_le_bytes());
+        directory.extend(start.to_le_bytes());
+        directory.extend(0u16.to_le_bytes());
+        self.out.write_all(&directory)
+    }
+}
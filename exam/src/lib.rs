@@ -0,0 +1,122 @@
+//! Rendering of ESRI ASCII grid (.asc) elevation models: grayscale, color-mapped and hillshaded images,
+//! plus the animation, stereo and perspective products built on top of them.
+//!
+//! Everything touching the file system sits behind the default `fs` feature, so the library also
+//! builds for `wasm32-unknown-unknown` with `--no-default-features --features wasm`.
+//! The `ffi` feature exports a C API for embedding the renderer into other applications,
+//! the `gpu` feature adds a wgpu compute-shader hillshade, `reproject` warps grids between coordinate systems,
+//! `async` adds entry points for services running on tokio and `script` styles cells by rhai scripts.
+
+pub mod anaglyph;
+#[cfg(feature = "fs")]
+pub mod animation;
+pub mod asc;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+#[cfg(feature = "fs")]
+pub mod bands;
+#[cfg(feature = "fs")]
+pub mod budget;
+#[cfg(feature = "fs")]
+pub mod cache;
+pub mod calc;
+pub mod charts;
+#[cfg(feature = "fs")]
+pub mod chunked;
+#[cfg(feature = "fs")]
+pub mod cog;
+pub mod contour;
+pub mod contrast;
+pub mod costpath;
+#[cfg(feature = "fs")]
+pub mod coverage;
+#[cfg(feature = "reproject")]
+pub mod crs;
+pub mod cvd;
+#[cfg(feature = "fs")]
+pub mod dedup;
+#[cfg(feature = "fs")]
+pub mod drape;
+pub mod e00;
+#[cfg(feature = "fs")]
+pub mod edges;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "fs")]
+pub mod filter;
+pub mod font;
+#[cfg(feature = "fs")]
+pub mod gallery;
+#[cfg(feature = "sqlite")]
+pub mod geopackage;
+#[cfg(feature = "fs")]
+pub mod geotiff;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+pub mod grid;
+pub mod heightmap;
+#[cfg(feature = "fs")]
+pub mod hooks;
+pub mod horizon;
+#[cfg(feature = "fs")]
+pub mod http;
+#[cfg(feature = "fs")]
+pub mod journal;
+#[cfg(feature = "sqlite")]
+pub mod jobdb;
+#[cfg(feature = "fs")]
+pub mod kml;
+pub mod lidar;
+#[cfg(feature = "fs")]
+pub mod manifest;
+#[cfg(feature = "fs")]
+pub mod metrics;
+pub mod mosaic;
+pub mod nc;
+#[cfg(feature = "fs")]
+pub mod output;
+pub mod perspective;
+pub mod pipeline;
+#[cfg(feature = "fs")]
+pub mod plugin;
+#[cfg(feature = "fs")]
+pub mod pmtiles;
+#[cfg(feature = "fs")]
+pub mod profile;
+#[cfg(feature = "fs")]
+pub mod profiling;
+pub mod progress;
+pub mod qc;
+#[cfg(feature = "fs")]
+pub mod quantized_mesh;
+pub mod reclass;
+pub mod render;
+#[cfg(feature = "fs")]
+pub mod report;
+pub mod ridgeline;
+pub mod ruggedness;
+#[cfg(feature = "script")]
+pub mod script;
+pub mod shading;
+#[cfg(feature = "fs")]
+pub mod shapefile;
+#[cfg(feature = "fs")]
+pub mod sniff;
+pub mod spot;
+pub mod tanaka;
+pub mod terrain;
+pub mod terraces;
+#[cfg(feature = "fs")]
+pub mod timeseries;
+pub mod tin;
+#[cfg(feature = "fs")]
+pub mod verify;
+pub mod vertical;
+pub mod viewshed;
+pub mod voxel;
+#[cfg(feature = "fs")]
+pub mod walk;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "fs")]
+pub mod xyz;
@@ -0,0 +1,153 @@
+//! Airborne LiDAR point clouds gridded into elevation models: a terrain model (DTM) from the mean of the ground points
+//! in each cell or a surface model (DSM) from the highest return, with small gaps filled by inverse distance weighting.
+//! LAS and LAZ files are read with the `las` crate behind the `lidar` feature.
+
+#[cfg(feature = "lidar")]
+use std::io;
+#[cfg(feature = "lidar")]
+use std::path::Path;
+
+use crate::grid::Grid;
+
+/// ASPRS classification of ground points
+pub const GROUND: u8 = 2;
+
+/// ASPRS classes of low and high noise, never part of a surface model
+const NOISE: [u8; 2] = [7, 18];
+
+/// NoData of the gridded cells, as in an .asc file without a nodata_value line
+const NODATA: f32 = -99999.0;
+
+/// Which elevation model the points become
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Surface {
+    #[default]
+    Dtm, // Bare earth, mean of the ground points in a cell
+    Dsm, // Buildings and canopy included, highest point in a cell
+}
+
+impl Surface {
+    pub fn parse(name: &str) -> Option<Surface> {
+        match name.to_lowercase().as_str() {
+            "dtm" => Some(Surface::Dtm),
+            "dsm" => Some(Surface::Dsm),
+            _ => None,
+        }
+    }
+}
+
+/// How points are binned into cells
+#[derive(Clone, Debug, PartialEq)]
+pub struct LidarSettings {
+    pub resolution: Option<f64>, // Cell size in map units, about one point per cell if None (--resolution)
+    pub surface: Surface,        // Model to build (--surface)
+    pub classes: Option<Vec<u8>>, // Classes kept, ground for a DTM and everything but noise for a DSM if None (--classes)
+    pub fill: usize,             // Empty cells within this many cells of a binned one are interpolated (--fill)
+}
+
+impl Default for LidarSettings {
+    fn default() -> Self {
+        LidarSettings { resolution: None, surface: Surface::default(), classes: None, fill: 3 }
+    }
+}
+
+impl LidarSettings {
+    /// Whether a point of `class` goes into the grid
+    pub fn keeps(&self, class: u8) -> bool {
+        match (&self.classes, self.surface) {
+            (Some(classes), _) => classes.contains(&class),
+            (None, Surface::Dtm) => class == GROUND,
+            (None, Surface::Dsm) => !NOISE.contains(&class),
+        }
+    }
+}
+
+/// Bins `(x, y, z)` points into a grid aligned to multiples of the resolution, covering all of them.
+/// The points have to be filtered by class already, see [`LidarSettings::keeps`].
+pub fn grid_points(points: &[(f64, f64, f64)], settings: &LidarSettings) -> Result<Grid, String> {
+    if points.is_empty() {
+        return Err("No points of the selected classes, try --classes or --surface dsm".into());
+    }
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+    for &(x, y, _) in points {
+        (min_x, min_y, max_x, max_y) = (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y));
+    }
+    let cellsize = match settings.resolution {
+        Some(resolution) => resolution,
+        // Spacing of the points if they were spread evenly, at least a centimeter for tiny or degenerate clouds
+        None => nice_size((((max_x - min_x) * (max_y - min_y)) / points.len() as f64).sqrt().max(0.01)),
+    };
+    if cellsize.is_nan() || cellsize <= 0.0 {
+        return Err("The resolution has to be above 0".into());
+    }
+    let (xll, yll) = ((min_x / cellsize).floor() * cellsize, (min_y / cellsize).floor() * cellsize);
+    let ncols = ((max_x - xll) / cellsize).floor() as usize + 1;
+    let nrows = ((max_y - yll) / cellsize).floor() as usize + 1;
+    if ncols.saturating_mul(nrows) > 1 << 30 {
+        return Err(format!("{}x{} cells are too many, raise --resolution", ncols, nrows));
+    }
+
+    // Sum and count for the mean of a DTM, the running maximum for a DSM
+    let mut sums = vec![0.0; ncols * nrows];
+    let mut counts = vec![0u32; ncols * nrows];
+    for &(x, y, z) in points {
+        let c = (((x - xll) / cellsize) as usize).min(ncols - 1);
+        let r = (nrows - 1).saturating_sub(((y - yll) / cellsize) as usize);
+        let i = r * ncols + c;
+        match settings.surface {
+            Surface::Dtm => sums[i] += z,
+            Surface::Dsm => sums[i] = if counts[i] == 0 { z } else { sums[i].max(z) },
+        }
+        counts[i] += 1;
+    }
+    let binned: Vec<Option<f64>> = sums.iter().zip(&counts).map(|(&sum, &count)| match (count, settings.surface) {
+        (0, _) => None,
+        (_, Surface::Dtm) => Some(sum / count as f64),
+        (_, Surface::Dsm) => Some(sum),
+    }).collect();
+
+    let radius = settings.fill as isize;
+    let data = (0..nrows).map(|r| (0..ncols).map(|c| {
+        if let Some(z) = binned[r * ncols + c] {
+            return z as f32;
+        }
+        // Inverse squared distance to the binned cells around, only those within the radius
+        let (mut sum, mut weights) = (0.0, 0.0);
+        for dr in -radius..=radius {
+            for dc in -radius..=radius {
+                let (rr, cc) = (r as isize + dr, c as isize + dc);
+                let d2 = (dr * dr + dc * dc) as f64;
+                if rr < 0 || cc < 0 || rr >= nrows as isize || cc >= ncols as isize || d2 > (radius * radius) as f64 {
+                    continue;
+                }
+                if let Some(z) = binned[rr as usize * ncols + cc as usize] {
+                    sum += z / d2;
+                    weights += 1.0 / d2;
+                }
+            }
+        }
+        if weights > 0.0 { (sum / weights) as f32 } else { NODATA }
+    }).collect()).collect();
+
+    Ok(Grid { data, ncols, nrows, nodata: NODATA, cellsize, xllcorner: xll, yllcorner: yll, geographic: None })
+}
+
+/// Nearest of 1, 2 or 5 times a power of ten, so estimated cells line up with round coordinates
+fn nice_size(size: f64) -> f64 {
+    let power = 10f64.powf(size.log10().floor());
+    [1.0, 2.0, 5.0, 10.0].iter().map(|m| m * power).min_by(|a, b| (a / size).ln().abs().total_cmp(&(b / size).ln().abs())).unwrap()
+}
+
+/// Reads the points of a LAS or LAZ file and grids those of the kept classes
+#[cfg(feature = "lidar")]
+pub fn load_las(path: &Path, settings: &LidarSettings) -> io::Result<Grid> {
+    let mut reader = las::Reader::from_path(path).map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}: {}", path, e)))?;
+    let mut points = Vec::new();
+    for point in reader.points() {
+        let point = point.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}: {}", path, e)))?;
+        if settings.keeps(u8::from(point.classification)) {
+            points.push((point.x, point.y, point.z));
+        }
+    }
+    grid_points(&points, settings).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}: {}", path, e)))
+}
@@ -1,215 +1,2804 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::env;
 use std::fs::{self, File};
-use std::io::{self, BufRead};
+use std::io::{self, BufWriter, Cursor, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
-use image::{GrayImage, Luma, Rgb, RgbImage};
-use colorgrad;
+use std::process::{self, ExitCode};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
 
-fn main() -> io::Result<()> {
-    let input_dir = "./dataset";       // Directory containing .asc files change this as you like :D
-    let output_root = "./output";      // Root output folder for all processed images same for this you can change it
+use colorgrad::Gradient;
+use image::{DynamicImage, GrayImage, RgbaImage};
+use exam::animation::{self, SweepAxis, SweepSettings};
+use exam::perspective::{self, Camera, PerspectiveSettings};
+use exam::plugin::{self, Inputs, Registry};
+use exam::pmtiles::{self, ZoomRange};
+use exam::asc::load_asc_with_warnings;
+use exam::bands::{self, Band, MAX_BANDS};
+use exam::asc::write_asc;
+use exam::cache;
+use exam::calc::Expression;
+use exam::cvd::Cvd;
+use exam::drape::{self, Drape};
+use exam::e00;
+use exam::sniff::{self, InputFormat};
+use exam::contour::{ContourSettings, Contours};
+use exam::chunked;
+use exam::contrast::{self, Stretch};
+use exam::costpath::{self, CostFunction, RouteSettings};
+#[cfg(feature = "reproject")]
+use exam::crs::{self, Crs};
+use exam::reclass::Reclass;
+use exam::ridgeline::{RidgelineSettings, Ridgelines};
+use exam::{gallery, geotiff};
+use exam::grid::{self, Downsample, EdgeMode, Grid, Interpolation, Preview};
+use exam::heightmap::{Heightmap, Preset};
+use exam::profile::{self, DEFAULT_CONFIG};
+use exam::profiling::{self, FileProfile, ProfileReport, TrackingAllocator, PROFILE_FILE};
+use exam::output::{encode_image, encode_image_with, save_grid, save_rgb_rows, Conflict, ImageFormat, OutputFormat, OutputTarget, PngCompression, PngPalette, Previews, Provenance, RawFormat, TiffCompression};
+use exam::render::{self, save_color_hillshade_image, save_colored_image, save_grayscale_image, Backend, ElevationRange, FillLight, NodataStyles, ShadeMode, ShadeParams, Tone, COLORMAPS, CVD_SAFE_COLORMAPS};
+use exam::shading::ShaderKind;
+use exam::spot::{SpotHeights, SpotKind};
+use exam::journal::{self, Journal};
+use exam::kml::{self, LatLonBox};
+use exam::lidar::{LidarSettings, Surface};
+use exam::mosaic;
+use exam::qc::{self, QcSettings};
+use exam::quantized_mesh;
+use exam::report::{self, ElevationStats, InputReport, Report, Status, REPORT_FILE, SUMMARY_CSV};
+use exam::terrain::Derivative;
+use exam::tin::Tin;
+use exam::vertical::Vertical;
+use exam::viewshed::{self, Observer, ViewshedSettings, DEFAULT_OBSERVER_HEIGHT};
+use exam::voxel::{VoxelSettings, Voxels, WORLD_HEIGHT};
+use exam::xyz::{self, XyzFormat};
+use exam::budget::{self, MemoryBudget};
+use exam::filter::{self, InputFilter};
+use exam::walk::{self, WalkOptions};
+use exam::dedup::{self, Duplicate, DuplicateMode};
+use exam::hooks::{HookEvent, Hooks};
+use exam::http::{self, Request, Response};
+use exam::metrics::{self, Metrics};
+#[cfg(feature = "script")]
+use exam::script::{self, StyleScript};
+#[cfg(feature = "sqlite")]
+use exam::jobdb::{self, JobDb};
+use exam::coverage::{Footprint, TileIndex, COVERAGE_GEOJSON, COVERAGE_IMAGE, COVERAGE_SIZE};
+use exam::manifest::{Manifest, ManifestEntry, MANIFEST_CSV, MANIFEST_FILE};
+use exam::edges::{self, EdgeReport, TileEdges, EDGES_FILE, EDGES_GEOJSON};
+use exam::{anaglyph, charts, timeseries, verify};
 
+// Counts the heap in use so --profiling can report the peak of every stage
+#[global_allocator]
+static ALLOCATOR: TrackingAllocator = TrackingAllocator;
+
+/// Entry point of a subcommand, called with the arguments after its name
+type Command = fn(&[String]) -> io::Result<()>;
+
+fn main() -> io::Result<ExitCode> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    // Subcommands working on a single file, anything else is a batch run
+    let command: Option<Command> = match args.first().map(String::as_str) {
+        Some("render") => Some(render_command),
+        Some("query") => Some(query_command),
+        Some("calc") => Some(calc_command),
+        Some("reclass") => Some(reclass_command),
+        Some("warp") => Some(warp_command),
+        Some("mosaic") => Some(mosaic_command),
+        Some("cost-path") => Some(cost_path_command),
+        Some("viewshed") => Some(viewshed_command),
+        Some("tin") => Some(tin_command),
+        Some("terrain-tiles") => Some(terrain_tiles_command),
+        Some("pmtiles") => Some(pmtiles_command),
+        Some("heightmap") => Some(heightmap_command),
+        Some("voxels") => Some(voxels_command),
+        Some("ridgeline") => Some(ridgeline_command),
+        Some("contours") => Some(contours_command),
+        Some("gpkg") => Some(gpkg_command),
+        Some("convert") => Some(convert_command),
+        Some("lidar") => Some(lidar_command),
+        Some("verify") => Some(verify_command),
+        Some("manifest") => Some(manifest_command),
+        Some("edges") => Some(edges_command),
+        Some("serve") => Some(serve_command),
+        _ => None,
+    };
+    if let Some(command) = command {
+        command(&args[1..])?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let options = parse_args(args)?;  // Optional modes, e.g. `cargo run -- --animate azimuth --step 10`
+    let output_root = &options.output_root; // Root output folder for all processed images, ./output unless --output is given
+
+    if options.dry_run {
+        return plan_batch(&options);
+    }
     fs::create_dir_all(output_root)?;  // Ensure the root output folder exists
 
-    // Iterate over all .asc files in dataset
-    for entry in fs::read_dir(input_dir)? {
-        let entry = entry?;
-        let path = entry.path();
+    if !options.time_series.is_empty() {
+        let target = target_for(&options, "time_series");
+        println!("Rendering time series of {} DEMs → Saving to {:?}", options.time_series.len(), output_root.join(&target.pattern));
+        timeseries::save_time_series(&options.time_series, &options.colormap, &target, options.frame_ms, &options.format)?;
+        println!("Time series rendered, have a wonderful day :D !");
+        return Ok(ExitCode::SUCCESS);
+    }
 
-        if path.extension().map(|e| e == "asc").unwrap_or(false) {
-            let stem = path.file_stem().unwrap().to_string_lossy();
-            let output_dir: PathBuf = [output_root, &stem].iter().collect();
+    let report = run_batch(&options)?;
+    if report.failed > 0 {
+        eprintln!("{} of {} files failed, see {:?}", report.failed, report.inputs.len(), output_root.join(REPORT_FILE));
+        return Ok(ExitCode::FAILURE);
+    }
+
+    println!("All files processed successfully!, have a wonderful day :D !");
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Where the outputs of the grid file `stem` go, with the output settings of `options`
+fn target_for(options: &Options, stem: &str) -> OutputTarget {
+    OutputTarget {
+        root: options.output_root.clone(),
+        pattern: options.out_pattern.clone(),
+        stem: stem.to_string(),
+        shade: options.shade,
+        elevation_range: options.elevation_range,
+        tone: options.tone,
+        simulate_cvd: options.simulate_cvd,
+        previews: options.previews.clone(),
+        provenance: None,
+        sidecars: options.sidecars,
+        nodata_style: options.nodata_style.clone(),
+        nodata_mask: None,
+        spot_heights: None,
+        conflict: options.conflict,
+        written: RefCell::default(),
+    }
+}
 
-            println!("Processing {:?} → Saving to {:?}", path.file_name().unwrap(), output_dir);
+/// Renders every grid of the input folder into the output folder, then writes the report and the gallery
+fn run_batch(options: &Options) -> io::Result<Report> {
+    let output_root = &options.output_root;
+    let backend = select_backend(options.gpu);
 
-            fs::create_dir_all(&output_dir)?; // Create per-file output directory
+    let mut report = Report::new();
+    let report_path = output_root.join(REPORT_FILE);
+    let metrics = Arc::new(Metrics::new());
+    if let Some(address) = &options.metrics {
+        println!("Metrics on http://{}/metrics", metrics.serve(address)?);
+    }
+    // Runs sharing a job database share the journal too, none of them starts it over, and the database decides
+    // which inputs are done
+    let journal = Journal::open(output_root, options.resume || options.retry_failed || options.job_db.is_some())?;
+    let (pending, skipped, duplicates) = batch_inputs(options, |path| options.job_db.is_none() && journal.is_done(path))?;
+    report.skipped = skipped;
+    report.duplicates = duplicates;
+    metrics.add_inputs(pending.len() + skipped);
+    metrics.add_skipped(skipped);
+    // With --job-db the inputs are shared with other runs: each is rendered by the run that claims it first
+    #[cfg(feature = "sqlite")]
+    let jobs = match &options.job_db {
+        Some(path) => {
+            let jobs = JobDb::open(path, options.job_lease.unwrap_or(jobdb::DEFAULT_LEASE))?;
+            let keys: Vec<String> = pending.iter().map(|path| jobdb::job_key(&options.input_dir, path)).collect();
+            let added = jobs.register(&keys, options.retry_failed)?;
+            println!("Job database {:?}: {} of {} inputs new", path, added, keys.len());
+            Some(jobs)
+        }
+        None => None,
+    };
+    let claimed_elsewhere = AtomicUsize::new(0);
 
-            // Load and process the .asc file
-            match load_asc(&path) {
-                Ok((data, ncols, nrows, nodata_value)) => {
-                    save_grayscale_image(&data, ncols, nrows, nodata_value, &output_dir)?;
-                    save_colored_image(&data, ncols, nrows, nodata_value, &output_dir)?;
-                    save_color_hillshade_image(&data, ncols, nrows, nodata_value, &output_dir)?;
+    // --jobs workers take the files in turn; with --max-memory each file first reserves its estimated memory and
+    // waits while the files in flight hold too much of the budget
+    let budget = options.max_memory.map(MemoryBudget::new);
+    let next = AtomicUsize::new(0);
+    let journal = Mutex::new(journal);
+    let finished = Mutex::new(Vec::new()); // Index into pending, report and profile of every file
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..options.jobs.min(pending.len())).map(|_| scope.spawn(|| -> io::Result<()> {
+            while let Some(path) = pending.get(next.fetch_add(1, Ordering::Relaxed)) {
+                #[cfg(feature = "sqlite")]
+                if let Some(jobs) = &jobs {
+                    if !jobs.claim(&jobdb::job_key(&options.input_dir, path))? {
+                        claimed_elsewhere.fetch_add(1, Ordering::Relaxed);
+                        metrics.add_skipped(1);
+                        continue;
+                    }
+                }
+                let _reservation = budget.as_ref().map(|budget| {
+                    let bytes = estimated_bytes(path, options);
+                    if bytes > budget.limit() {
+                        eprintln!("Warning: {:?} needs about {} MiB, more than --max-memory, and runs alone", path, bytes >> 20);
+                    }
+                    budget.reserve(bytes)
+                });
+                let (mut record, profile) = process_input(path, options, &backend);
+                if options.hooks.is_active() {
+                    record.warnings.extend(options.hooks.fire(&HookEvent::file(&record)));
                 }
-                Err(e) => eprintln!("Failed to read {:?}: {}", path, e),
+                metrics.record(&record);
+                if record.status == Status::Ok {
+                    journal.lock().unwrap_or_else(|e| e.into_inner()).record(path)?;
+                }
+                #[cfg(feature = "sqlite")]
+                if let Some(jobs) = &jobs {
+                    jobs.finish(&jobdb::job_key(&options.input_dir, path), &record, &options.output_root)?;
+                }
+                finished.lock().unwrap_or_else(|e| e.into_inner()).push((record, profile));
             }
+            Ok(())
+        })).collect();
+        workers.into_iter().try_for_each(|worker| worker.join().unwrap_or_else(|payload| Err(panic_error(payload))))
+    })?;
+
+    let claimed_elsewhere = claimed_elsewhere.into_inner();
+    if claimed_elsewhere > 0 {
+        println!("Skipped {} inputs the job database lists as done or claimed by another run", claimed_elsewhere);
+        report.skipped += claimed_elsewhere;
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(jobs) = &jobs {
+        let counts: Vec<String> = jobs.counts()?.iter().map(|(state, count)| format!("{} {}", count, state)).collect();
+        println!("Job database: {}", counts.join(", "));
+    }
+
+    let mut profile = ProfileReport::default();
+    let mut finished = finished.into_inner().unwrap_or_else(|e| e.into_inner());
+    let order: HashMap<&PathBuf, usize> = pending.iter().enumerate().map(|(i, path)| (path, i)).collect();
+    finished.sort_by_key(|(record, _)| order.get(&record.input).copied());
+    for (record, file) in finished {
+        report.push(record);
+        if let Some(file) = file {
+            profile.push(file);
         }
     }
 
-    println!("All files processed successfully!, have a wonderful day :D !");
+    report.save(&report_path)?;
+    report.save_summary(&output_root.join(SUMMARY_CSV))?;
+    if options.profiling {
+        profile.save(&output_root.join(PROFILE_FILE))?;
+        println!("Time per stage, profile in {:?}:", output_root.join(PROFILE_FILE));
+        for line in profile.summary() {
+            println!("  {}", line);
+        }
+    }
+    let gallery = gallery::write_gallery(&report, output_root)?;
+    println!("Gallery of the run: {:?}", gallery);
+    if options.hooks.is_active() {
+        for warning in options.hooks.fire(&HookEvent::batch(&report, &report_path)) {
+            eprintln!("Warning: {}", warning);
+        }
+    }
+    Ok(report)
+}
+
+/// The grids of the input folder to render in the order of [`walk::walk_files`], how many of them `is_done` skips as
+/// finished by an earlier run (--resume) and, with --find-duplicates or --skip-duplicates, the copies among them:
+/// files whose content is a known format, whatever their name, and files named like a grid that turn out not to be
+/// one, which fail with the reason. --include, --exclude and the other filters and --retry-failed, which keeps the
+/// failures of the last report unless a --job-db keeps them, narrow them down.
+fn batch_inputs(options: &Options, is_done: impl Fn(&Path) -> bool) -> io::Result<(Vec<PathBuf>, usize, Vec<Duplicate>)> {
+    let retry: Option<HashSet<PathBuf>> = if options.retry_failed && options.job_db.is_none() {
+        Some(report::failed_inputs(&options.output_root.join(REPORT_FILE))?.iter().map(|input| journal::input_key(input)).collect())
+    } else {
+        None
+    };
+    let mut candidates = Vec::new();
+    for path in walk::walk_files(&options.input_dir, &options.walk)? {
+        let relative = path.strip_prefix(&options.input_dir).unwrap_or(&path);
+        if path.is_file() && options.filter.matches(&path, relative)?
+            && (sniff::sniff_file(&path).is_ok() || InputFormat::from_extension(&path).is_some()) {
+            candidates.push(path);
+        }
+    }
+    // Copies are found among all inputs before any is skipped, so the original stays the same from run to run
+    let mut duplicates = match options.duplicates {
+        Some(_) => dedup::find_duplicates(&candidates)?,
+        None => Vec::new(),
+    };
+    let relative = |path: &Path| path.strip_prefix(&options.input_dir).unwrap_or(path).to_path_buf();
+    for duplicate in &mut duplicates {
+        duplicate.skipped = options.duplicates == Some(DuplicateMode::Skip);
+        println!("{:?} has the same content as {:?}{}", relative(&duplicate.input), relative(&duplicate.original),
+            if duplicate.skipped { ", skipping it" } else { "" });
+    }
+    let (mut inputs, mut skipped) = (Vec::new(), 0);
+    for path in candidates {
+        if duplicates.iter().any(|d| d.skipped && d.input == path)
+            || retry.as_ref().is_some_and(|failed| !failed.contains(&journal::input_key(&path))) {
+            continue;
+        }
+        if is_done(&path) {
+            println!("Skipping {:?}, finished by an earlier run", relative(&path));
+            skipped += 1;
+            continue;
+        }
+        inputs.push(path);
+    }
+    Ok((inputs, skipped, duplicates))
+}
+
+/// Time per cell of a step assumed by --dry-run without a report of an earlier run, about a product of a release build
+const DEFAULT_SECONDS_PER_CELL: f64 = 1e-6;
+
+/// --dry-run: lists what a batch run would read and write, with the size of every grid from its header, the files
+/// and bytes of its products and an estimate of the time, without rendering or writing anything. Fails if a header
+/// can't be read.
+fn plan_batch(options: &Options) -> io::Result<ExitCode> {
+    if !options.time_series.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "--dry-run plans batch runs, not --time-series"));
+    }
+    let finished = if options.resume || options.retry_failed { journal::finished(&options.output_root)? } else { HashSet::new() };
+    let (inputs, skipped, _) = batch_inputs(options, |path| finished.contains(&journal::input_key(path)))?;
+    // Rates measured by the last run into the same output folder if there is one
+    let report_path = options.output_root.join(REPORT_FILE);
+    let rates = report::step_rates(&report_path).unwrap_or_default();
+    let rate = |step: &str| rates.get(step).copied().unwrap_or(DEFAULT_SECONDS_PER_CELL);
+
+    println!("Plan for {} inputs in {:?} → {:?}{}:", inputs.len(), options.input_dir, options.output_root,
+        if skipped > 0 { format!(", {} finished by an earlier run", skipped) } else { String::new() });
+    let (mut files, mut bytes, mut seconds, mut invalid, mut unknown) = (0, 0, 0.0, 0, 0);
+    for path in &inputs {
+        let name = path.strip_prefix(&options.input_dir).unwrap_or(path).to_string_lossy();
+        let format = match sniff::sniff_file(path) {
+            Ok(format) => format,
+            Err(e) => {
+                println!("  {:<40} invalid: {}", name, e);
+                invalid += 1;
+                continue;
+            }
+        };
+        let size = match options.window {
+            Some([_, _, ncols, nrows]) => Some((ncols, nrows)),
+            None => budget::grid_dimensions(path, format),
+        };
+        let Some((ncols, nrows)) = size else {
+            if matches!(format, InputFormat::EsriAscii | InputFormat::GrassAscii | InputFormat::GeoTiff) {
+                println!("  {:<40} invalid: no grid size in the header of {}", name, format.name());
+                invalid += 1;
+            } else {
+                println!("  {:<40} size of {} known only after loading, not estimated", name, format.name());
+                unknown += 1;
+            }
+            continue;
+        };
+        let (ncols, nrows) = match options.preview {
+            Some(preview) => (ncols.div_ceil(preview.factor), nrows.div_ceil(preview.factor)),
+            None => (ncols, nrows),
+        };
+        let cells = (ncols * nrows) as u64;
+        let outputs: Vec<(usize, u64)> = options.products.iter().map(|product| planned_outputs(product, options, cells)).collect();
+        let file_count: usize = outputs.iter().map(|(n, _)| n).sum::<usize>() + options.animation.is_some() as usize;
+        let file_bytes: u64 = outputs.iter().map(|(_, b)| b).sum();
+        let file_seconds = cells as f64 * (rate("load") + options.products.iter().map(|p| rate(p.name())).sum::<f64>());
+        println!("  {:<40} {:>6} x {:<6} {:>3} files, up to {:>8.1} MiB, ~{:.1} s", name, ncols, nrows,
+            file_count, file_bytes as f64 / (1024.0 * 1024.0), file_seconds);
+        files += file_count;
+        bytes += file_bytes;
+        seconds += file_seconds;
+    }
+    let jobs = options.jobs.min(inputs.len()).max(1);
+    println!("Total: {} files, up to {:.1} MiB uncompressed, about {:.1} s with {} job{} ({})", files, bytes as f64 / (1024.0 * 1024.0),
+        seconds / jobs as f64, jobs, if jobs == 1 { "" } else { "s" },
+        if rates.is_empty() { "assumed rates, no earlier report".to_string() } else { format!("rates of {:?}", report_path) });
+    if unknown > 0 {
+        println!("{} inputs of unknown size are left out of the total", unknown);
+    }
+    if invalid > 0 {
+        eprintln!("{} of {} inputs can't be read", invalid, inputs.len());
+        return Ok(ExitCode::FAILURE);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Number of files and their uncompressed bytes, an upper bound for PNG and TIFF, that a product writes for a grid of
+/// `cells` cells, without previews
+fn planned_outputs(product: &Product, options: &Options, cells: u64) -> (usize, u64) {
+    let raw = options.raw_formats.len();
+    let raw_bytes = 4 * cells * raw as u64; // One f32 per cell in each raw format
+    match product {
+        Product::Grayscale => (1, cells),
+        Product::Colored | Product::Hillshade | Product::Anaglyph | Product::Kmz => (1, 3 * cells),
+        Product::Shade => (2 + raw, 3 * cells + raw_bytes),
+        Product::Plugin(_) => (1 + raw, 3 * cells + raw_bytes), // An image and raw values, like the derivatives
+        Product::Perspective => {
+            let view = 3 * options.perspective.width as u64 * options.perspective.height as u64;
+            let frames = options.perspective.flyover_frames;
+            (1 + (frames > 0) as usize, view * (1 + frames as u64))
+        }
+        Product::Bands => (1, 4 * cells * options.bands.len() as u64),
+        Product::Qc => (2, 3 * cells),
+        Product::Charts => (4, 3 * (480 * 480 + 640 * 400)), // Two images of a fixed size and their SVGs
+        Product::Styled => (1, 4 * cells),
+    }
+}
+
+/// Renders one grid file into its output folder, returning its report and, with --profiling, its stages
+fn process_input(path: &Path, options: &Options, backend: &Backend) -> (InputReport, Option<FileProfile>) {
+    // Files in subfolders keep their folder below the output root, so equal names in two folders don't collide
+    let relative = path.strip_prefix(&options.input_dir).unwrap_or(path);
+    let stem = relative.with_extension("").to_string_lossy().replace('\\', "/");
+    let mut target = target_for(options, &stem); // Directories are created as files are written
+
+    println!("Processing {:?} → Saving to {:?}", relative, options.output_root.join(target.pattern.replace("{stem}", &stem)));
+
+    let mut record = InputReport::new(path);
+    if options.profiling {
+        profiling::begin(path);
+    }
+    // A panic in one file is reported like any other failure instead of ending the run
+    let result = panic::catch_unwind(AssertUnwindSafe(|| process_file(path, options, backend, &mut target, &mut record)))
+        .unwrap_or_else(|payload| Err(panic_error(payload)));
+    if let Err(e) = result {
+        eprintln!("Failed to process {:?}: {}", path, e);
+        record.fail(&e);
+    }
+    record.outputs = target.written.take();
+    (record, profiling::end())
+}
+
+/// Memory to reserve for an input under --max-memory: the --window or the size in its header, for formats without
+/// one the size of the file
+fn estimated_bytes(path: &Path, options: &Options) -> u64 {
+    match options.window {
+        Some([_, _, ncols, nrows]) => budget::estimate_bytes(ncols * nrows),
+        None => budget::estimate_file(path),
+    }
+}
+
+/// Loads one grid file and renders the selected products, recording stats, warnings and timings
+fn process_file(path: &Path, options: &Options, backend: &Backend, target: &mut OutputTarget, record: &mut InputReport) -> io::Result<()> {
+    let format = sniff::sniff_file(path)?;
+    if !format.matches_extension(path) {
+        let warning = format!("Read as {} despite its name", format.name());
+        eprintln!("Warning: {:?}: {}", path, warning);
+        record.warnings.push(warning);
+    }
+    let (mut grid, warnings) = record.time("load", || profiling::stage("parse", || match format {
+        _ if options.window.is_some() => read_grid_with(&path.to_string_lossy(), &options.lidar, options.variable.as_deref(), options.window)
+            .map(|grid| (grid, Default::default())),
+        InputFormat::EsriAscii | InputFormat::GrassAscii if options.cache => cache::load_cached(path),
+        InputFormat::EsriAscii | InputFormat::GrassAscii => load_asc_with_warnings(path),
+        _ => load_file(path, format, &options.lidar, options.variable.as_deref()).map(|grid| (grid, Default::default())),
+    }))?;
+    options.vertical.apply(&mut grid);
+    grid.geographic = options.geographic.or(grid.geographic);
+    if let Some(preview) = options.preview {
+        grid = preview.apply(grid);
+    }
+    record.stats = Some(ElevationStats::of(&grid));
+    for warning in warnings.messages() {
+        eprintln!("Warning: {:?}: {}", path, warning);
+        record.warnings.push(warning);
+    }
+    let (grid, shade) = record.time("warp", || profiling::stage("warp", || warp_grid(grid, Some(path), options)))?;
+    target.shade = shade;
+    target.nodata_mask = Some(grid.mask());
+    if let Some(peaks) = options.spot_heights {
+        let spots = SpotHeights::of(&grid, peaks);
+        record.peaks = spots.spots.iter().filter(|spot| spot.kind == SpotKind::Peak).copied().collect();
+        target.spot_heights = Some(spots);
+    }
+    let range = render::color_range(&grid, options.elevation_range.as_ref());
+    let mut provenance = Provenance::new(path, &grid, range, &options.colormap_name, options.stretch, options.tone, &target.shade);
+    provenance.vertical = Some(options.vertical).filter(|vertical| !vertical.is_identity());
+    provenance.preview = options.preview;
+    target.provenance = Some(provenance);
+    if let Some(warning) = out_of_range_warning(&grid, options) {
+        eprintln!("Warning: {:?}: {}", path, warning);
+        record.warnings.push(warning);
+    }
+    let target = &*target;
+    if grid.is_geographic() {
+        let (dx, dy) = grid.ground_spacing();
+        println!("{:?} is in degrees, gradients and the hillshade use {:.1} x {:.1} m cells at latitude {:.2}", path.file_name().unwrap(), dx, dy, grid.center_latitude());
+    }
+
+    let mut product_warnings = Vec::new();
+    let inputs = Inputs::new(&grid, &target.shade); // Layers shared by the plugins
+    for product in &options.products {
+        let result = record.time(product.name(), || profiling::stage("render", || match product {
+            Product::Grayscale => save_grayscale_image(&grid, options.stretch, &options.format, target),
+            Product::Colored => save_colored_image(&grid, &options.colormap, &options.format, target),
+            Product::Hillshade => match &options.drape {
+                Some(drape) => drape::save_draped_image(&grid, drape, &options.colormap, backend, &options.format, target),
+                None => save_color_hillshade_image(&grid, &options.colormap, backend, &options.format, target),
+            },
+            Product::Shade => render::save_shade_layers(&grid, backend, &options.raw_formats, &options.format, target),
+            Product::Plugin(plugin) => plugin::save_product(plugin.as_ref(), &inputs, &options.raw_formats, &options.format, target),
+            Product::Anaglyph => {
+                let parallax = options.parallax.unwrap_or_else(|| anaglyph::default_parallax(grid.ncols));
+                anaglyph::save_anaglyph_image(&grid, parallax, &options.colormap, &options.format, target)
+            }
+            Product::Perspective => perspective::save_perspective_images(&grid, &options.perspective, &options.colormap, &options.format, target),
+            Product::Kmz => {
+                let (img, bounds) = overlay_image(&grid, product, Some(path), options, target.shade, backend)?;
+                kml::save_kmz(&img, &bounds, &options.format, options.kmz_tile, target)
+            }
+            Product::Bands => bands::save_bands(&grid, &options.bands, &target.shade, backend, target),
+            Product::Qc => qc::save_qc(&grid, &options.qc, &options.format, target).map(|report| product_warnings = report.summary()),
+            Product::Charts => charts::save_charts(&grid, target.shade.edges, &options.format, target),
+            Product::Styled => save_styled(&grid, options, target).map(|warning| product_warnings.extend(warning)),
+        }));
+        match result {
+            // --skip-existing stops a product at its first file that exists
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && options.conflict == Conflict::Skip => {
+                println!("Skipping {} of {:?}: {}", product.name(), path.file_name().unwrap(), e);
+            }
+            result => result?,
+        }
+    }
+    for warning in product_warnings {
+        eprintln!("Warning: {:?}: {}", path, warning);
+        record.warnings.push(warning);
+    }
+    if let Some(settings) = &options.animation {
+        record.time("animation", || profiling::stage("render", || animation::save_hillshade_sweep_gif(&grid, settings, &options.colormap, backend, target)))?;
+    }
     Ok(())
 }
 
-/// Parses a .asc file into 2D elevation data + metadata
-fn load_asc(path: &Path) -> io::Result<(Vec<Vec<f32>>, usize, usize, f32)> {
-    let file = File::open(path)?;
-    let reader = io::BufReader::new(file);
+/// `exam render <file.asc|file.tif|-> [--product <name>] [--out <file|->] [flags]`: a single image of a single grid.
+/// `-` reads the grid from stdin and writes the image to stdout, so the tool fits into shell pipelines.
+fn render_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam render <file.asc|file.tif|-> [--product <name>] [--out <file|->] [--image-format ...] [--azimuth ...] [other flags of the batch run]";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
+
+    let (input, rest) = args.split_first().ok_or_else(|| invalid("Missing input file".into()))?;
+    let mut product = Product::Hillshade;
+    let mut out = String::from("-");
+    let mut flags = Vec::new();
+    let mut rest = rest.iter().cloned();
+    while let Some(arg) = rest.next() {
+        let mut value = || rest.next().ok_or_else(|| invalid(format!("Missing value for {}", arg)));
+        match arg.as_str() {
+            "--product" => {
+                let name = value()?;
+                product = Product::parse(&name).ok_or_else(|| invalid(format!("Unknown product {:?}", name)))?;
+            }
+            "--out" | "-o" => out = value()?,
+            _ => flags.push(arg),
+        }
+    }
+    let mut options = parse_args(flags)?;
+    // The extension of the output file picks the image format unless it is unknown
+    if let Some(format) = Path::new(&out).extension().and_then(|e| ImageFormat::parse(&e.to_string_lossy())) {
+        options.format.format = format;
+    }
+
+    let kmz = product == Product::Kmz || out.to_lowercase().ends_with(".kmz");
+    let (bytes, warnings) = render_encoded(input, &product, kmz, &options, &select_backend(options.gpu))?;
+    for warning in warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    if out == "-" {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&bytes)?;
+        stdout.flush()
+    } else {
+        fs::write(&out, bytes)
+    }
+}
+
+/// Renders `product` of the grid `input` (a path or - for standard input) into the bytes `exam render` writes: the
+/// image in the format of `options`, the Google Earth overlay if `kmz` is set or the composite GeoTIFF of
+/// `--product bands`, encoded in memory since TIFF encoding seeks. Also returns the warnings about the grid.
+fn render_encoded(input: &str, product: &Product, kmz: bool, options: &Options, backend: &Backend) -> io::Result<(Vec<u8>, Vec<String>)> {
+    if *product == Product::Styled && options.style_script.is_none() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "The styled product needs a --style-script"));
+    }
+    let input_path = Some(Path::new(input)).filter(|_| input != "-");
+    let (grid, shade) = warp_grid(read_input(input, options)?, input_path, options)?;
+    let mut buffer = Cursor::new(Vec::new());
+
+    if kmz {
+        let (img, bounds) = overlay_image(&grid, product, input_path, options, shade, backend)?;
+        let name = input_path.and_then(Path::file_stem).map_or("exam".into(), |stem| stem.to_string_lossy());
+        kml::write_kmz(&img, &bounds, &name, &options.format, options.kmz_tile, &mut buffer)?;
+        return Ok((buffer.into_inner(), Vec::new()));
+    }
+    let warnings: Vec<String> = out_of_range_warning(&grid, options).into_iter().collect();
+    if *product == Product::Bands {
+        bands::write_bands(&grid, &options.bands, &shade, backend, &mut buffer)?;
+        return Ok((buffer.into_inner(), warnings));
+    }
+    let mut img = render_product(&grid, product, options, &shade, backend);
+    if let Some(peaks) = options.spot_heights {
+        SpotHeights::of(&grid, peaks).apply(&mut img);
+    }
+    if let Some(cvd) = options.simulate_cvd {
+        img = cvd.simulate(&img);
+    }
+    let range = render::color_range(&grid, options.elevation_range.as_ref());
+    let mut provenance = Provenance::new(Path::new(input), &grid, range, &options.colormap_name, options.stretch, options.tone, &shade);
+    provenance.vertical = Some(options.vertical).filter(|vertical| !vertical.is_identity());
+    provenance.preview = options.preview;
+    let metadata = provenance.text_chunks();
+    encode_image_with(img, &options.format, &metadata, &mut buffer)?;
+    Ok((buffer.into_inner(), warnings))
+}
+
+/// `exam query <file|-> --points <points.csv> | --at <x,y> ... [--method nearest|bilinear] [--out <file.csv|->]`:
+/// elevations at map coordinates as CSV `x,y,elevation`, the elevation is empty outside the grid or on NoData
+fn query_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam query <file.asc|file.tif|-> [--points <points.csv>] [--at <x,y>] [--method nearest|bilinear] [--out <file.csv|->]";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
+
+    let (input, rest) = args.split_first().ok_or_else(|| invalid("Missing input file".into()))?;
+    let mut points = Vec::new();
+    let mut method = Interpolation::default();
+    let mut out = String::from("-");
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        let mut value = || rest.next().cloned().ok_or_else(|| invalid(format!("Missing value for {}", arg)));
+        match arg.as_str() {
+            "--points" => points.extend(read_points(Path::new(&value()?))?),
+            "--at" => match parse_numbers(&value()?)[..] {
+                [x, y] => points.push((x, y)),
+                _ => return Err(invalid("--at expects <x,y>".into())),
+            },
+            "--method" => {
+                let name = value()?;
+                method = Interpolation::parse(&name).ok_or_else(|| invalid(format!("Unknown interpolation {:?}", name)))?;
+            }
+            "--out" | "-o" => out = value()?,
+            _ => return Err(invalid(format!("Unknown argument {:?}", arg))),
+        }
+    }
+    if points.is_empty() {
+        return Err(invalid("No points given, use --points or --at".into()));
+    }
+
+    let grid = read_grid(input)?;
+    let mut w: Box<dyn Write> = if out == "-" { Box::new(io::stdout().lock()) } else { Box::new(BufWriter::new(File::create(&out)?)) };
+    writeln!(w, "x,y,elevation")?;
+    for (x, y) in points {
+        match grid.sample(x, y, method) {
+            Some(z) => writeln!(w, "{},{},{}", x, y, z)?,
+            None => writeln!(w, "{},{},", x, y)?,
+        }
+    }
+    w.flush()
+}
+
+/// `exam calc "<expression>" --a <file> --b <file> ... [--out <file.asc|file.tif|file.npy|->]`:
+/// cell-wise map algebra, each `--<name> <file>` binds a grid to a variable of the expression
+fn calc_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam calc \"<expression>\" --<name> <file.asc|file.tif|-> ... [--out <file.asc|file.tif|file.npy|->] \
+        [--overwrite|--skip-existing|--suffix-on-conflict]\n\
+        e.g. exam calc \"max(a - b, 0)\" --a 2020.asc --b 2015.asc --out gain.tif";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
+
+    let (text, rest) = args.split_first().ok_or_else(|| invalid("Missing expression".into()))?;
+    let expression = Expression::parse(text).map_err(invalid)?;
+    let mut inputs = HashMap::new();
+    let mut out = String::from("-");
+    let mut conflict = Conflict::default();
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        if let Some(policy) = Conflict::from_flag(arg) {
+            conflict = policy;
+            continue;
+        }
+        let value = rest.next().cloned().ok_or_else(|| invalid(format!("Missing value for {}", arg)))?;
+        match arg.as_str() {
+            "--out" | "-o" => out = value,
+            _ => match arg.strip_prefix("--") {
+                Some(name) if expression.variables().iter().any(|v| v == name) => {
+                    inputs.insert(name.to_string(), read_grid(&value)?);
+                }
+                _ => return Err(invalid(format!("Unknown argument {:?}, the expression uses {:?}", arg, expression.variables()))),
+            },
+        }
+    }
+
+    let result = expression.evaluate(&inputs).map_err(invalid)?;
+    write_grid(&result, &out, false, conflict)
+}
+
+/// `exam reclass <file|-> "<rules>" [--out <file.asc|file.tif|file.npy|->] [--preview <image>]`:
+/// integer classes from rules like `<0 => -1; 0..1000 => 1; >1000 => 2`
+fn reclass_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam reclass <file.asc|file.tif|-> \"<condition> => <class>; ...\" [--out <file.asc|file.tif|file.npy|->] [--preview <image>] \
+        [--overwrite|--skip-existing|--suffix-on-conflict]\n\
+        conditions: <x, <=x, >x, >=x, a..b (a included, b excluded), x, * (everything else)";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
 
-    let mut data = Vec::new();
-    let mut ncols = 0;
-    let mut nrows = 0;
-    let mut nodata_value = -99999.0;
-    let mut reading_data = false;
+    let [input, rules, rest @ ..] = args else {
+        return Err(invalid("Missing input file or rules".into()));
+    };
+    let reclass = Reclass::parse(rules).map_err(invalid)?;
+    let mut out = String::from("-");
+    let mut preview = None;
+    let mut conflict = Conflict::default();
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        if let Some(policy) = Conflict::from_flag(arg) {
+            conflict = policy;
+            continue;
+        }
+        let value = rest.next().cloned().ok_or_else(|| invalid(format!("Missing value for {}", arg)))?;
+        match arg.as_str() {
+            "--out" | "-o" => out = value,
+            "--preview" => preview = Some(value),
+            _ => return Err(invalid(format!("Unknown argument {:?}", arg))),
+        }
+    }
+
+    let classes = reclass.apply(&read_grid(input)?);
+    if let Some(preview) = preview {
+        // A color per class, the smallest palette PNG unless there are more than 256 classes
+        let mut format = OutputFormat { png_palette: PngPalette::Auto, ..OutputFormat::default() };
+        if let Some(image_format) = Path::new(&preview).extension().and_then(|e| ImageFormat::parse(&e.to_string_lossy())) {
+            format.format = image_format;
+        }
+        let mut file = BufWriter::new(conflict.create(Path::new(&preview))?.0);
+        encode_image(DynamicImage::ImageRgb8(reclass.render(&classes)), &format, &mut file)?;
+        file.flush()?;
+    }
+    write_grid(&classes, &out, false, conflict)
+}
+
+/// `exam warp <file|-> --t_srs <crs> [--s_srs <crs>] [--t_res <size>] [--resampling nearest|bilinear] [--cog] [--out <file.asc|file.tif|file.npy|->]`:
+/// reprojects a grid, the source system comes from --s_srs, an .prj sidecar or the GeoTIFF keys
+fn warp_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam warp <file.asc|file.tif|-> --t_srs <EPSG:code|proj string> [--s_srs <EPSG:code|proj string>] [--t_res <cell size>] \
+        [--resampling nearest|bilinear] [--cog] [--out <file.asc|file.tif|file.npy|->]";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
+
+    let (input, rest) = args.split_first().ok_or_else(|| invalid("Missing input file".into()))?;
+    let mut out = String::from("-");
+    let mut flags = Vec::new();
+    let mut rest = rest.iter().cloned();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--out" | "-o" => out = rest.next().ok_or_else(|| invalid(format!("Missing value for {}", arg)))?,
+            _ => flags.push(arg),
+        }
+    }
+    let options = parse_args(flags)?;
+    if options.t_srs.is_none() {
+        return Err(invalid("Missing --t_srs".into()));
+    }
+
+    let (grid, _) = warp_grid(read_input(input, &options)?, Some(Path::new(input)).filter(|_| input != "-"), &options)?;
+    write_grid(&grid, &out, options.format.cog, options.conflict)
+}
 
-    for line in reader.lines() {
-        let line = line?;
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.is_empty() { continue; }
+/// Flags of the batch run styling the image of `exam mosaic --image`, each followed by its value
+const MOSAIC_STYLE_FLAGS: [&str; 17] = [
+    "--colormap", "--azimuth", "--altitude", "--z-factor", "--shading", "--shader", "--edges", "--fill-azimuth", "--fill-altitude",
+    "--fill-weight", "--aerial-perspective", "--slope-saturation", "--gamma", "--brightness", "--contrast", "--elevation-range", "--tiff-compression",
+];
 
-        match parts[0].to_lowercase().as_str() {
-            "ncols" => ncols = parts[1].parse().unwrap_or(0),
-            "nrows" => nrows = parts[1].parse().unwrap_or(0),
-            "nodata_value" => nodata_value = parts[1].parse().unwrap_or(-99999.0),
-            _ if reading_data || parts[0].parse::<f32>().is_ok() => {
-                reading_data = true;
-                let row: Vec<f32> = parts.iter().map(|&x| x.parse().unwrap_or(nodata_value)).collect();
-                if row.len() == ncols { data.push(row); }
+/// `exam mosaic <a.asc|a.tif> <b.asc|b.tif> ... [--feather <cells>] [--cellsize <size>] [--method nearest|bilinear] [--cog] [--out <file|->]
+/// [--image <file.png|file.tif>] [--product hillshade|colored|grayscale|shade] [--chunk-rows <rows>] [--jobs <n>]`: one grid covering all
+/// inputs, later inputs on top with their edges feathered over the earlier ones, and optionally its image rendered in bands on all cores
+fn mosaic_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam mosaic <file.asc|file.tif> <file.asc|file.tif> ... [--feather <cells>] [--cellsize <size>] [--method nearest|bilinear] \
+        [--cog] [--out <file.asc|file.tif|file.npy|->] [--image <file.png|file.tif>] [--product hillshade|colored|grayscale|shade] [--chunk-rows <rows>] [--jobs <n>] \
+        [--overwrite|--skip-existing|--suffix-on-conflict] [--colormap, --azimuth, --z-factor, ... of the batch run]";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
+
+    let mut grids = Vec::new();
+    let mut feather = 0.0;
+    let mut cellsize = None;
+    let mut method = Interpolation::default();
+    let mut cog = false;
+    let mut conflict = Conflict::default();
+    let mut out = None;
+    let mut image = None;
+    let mut product = Product::Hillshade;
+    let mut chunk_rows = chunked::DEFAULT_CHUNK_ROWS;
+    let mut jobs = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut flags = Vec::new();
+    let mut names = Vec::new();
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        if !arg.starts_with("--") {
+            grids.push(read_grid(arg)?);
+            names.push(Path::new(arg).file_name().map_or(arg.clone(), |n| n.to_string_lossy().into_owned()));
+            continue;
+        }
+        if arg == "--cog" {
+            cog = true;
+            continue;
+        }
+        if let Some(policy) = Conflict::from_flag(arg) {
+            conflict = policy;
+            continue;
+        }
+        let value = rest.next().cloned().ok_or_else(|| invalid(format!("Missing value for {}", arg)))?;
+        match arg.as_str() {
+            "--feather" => match value.parse() {
+                Ok(cells) if cells >= 0.0 => feather = cells,
+                _ => return Err(invalid("--feather expects a width in cells".into())),
+            },
+            "--cellsize" => cellsize = Some(value.parse().map_err(|_| invalid("--cellsize expects a cell size".into()))?),
+            "--method" => method = Interpolation::parse(&value).ok_or_else(|| invalid(format!("Unknown interpolation {:?}", value)))?,
+            "--out" | "-o" => out = Some(value),
+            "--image" => image = Some(value),
+            "--product" => {
+                product = Product::parse(&value).filter(|p| matches!(p, Product::Hillshade | Product::Colored | Product::Grayscale | Product::Shade))
+                    .ok_or_else(|| invalid(format!("{:?} can't be rendered in bands, use hillshade, colored, grayscale or shade", value)))?;
             }
-            _ => {}
+            "--chunk-rows" => chunk_rows = value.parse().ok().filter(|&n: &usize| n > 0).ok_or_else(|| invalid("--chunk-rows expects a number of rows above 0".into()))?,
+            "--jobs" | "-j" => jobs = value.parse().ok().filter(|&n: &usize| n > 0).ok_or_else(|| invalid("--jobs expects a number of bands above 0".into()))?,
+            flag if MOSAIC_STYLE_FLAGS.contains(&flag) => flags.extend([arg.clone(), value]),
+            _ => return Err(invalid(format!("Unknown argument {:?}", arg))),
         }
     }
+    if grids.len() < 2 {
+        return Err(invalid("A mosaic needs at least two inputs".into()));
+    }
 
-    if data.len() != nrows {
-        return Err(io::Error::new(io::ErrorKind::InvalidData, "Row count mismatch"));
+    let grid = mosaic::mosaic(&grids, cellsize, feather, method).map_err(invalid)?;
+    drop(grids);
+    if let Some(image) = &image {
+        let mut options = parse_args(flags)?;
+        options.conflict = conflict;
+        options.format.format = match Path::new(image).extension().and_then(|e| ImageFormat::parse(&e.to_string_lossy())) {
+            Some(format @ (ImageFormat::Png | ImageFormat::Tiff)) => format,
+            _ => return Err(invalid(format!("{:?} is neither a PNG nor a TIFF", image))),
+        };
+        render_mosaic_image(&grid, &names.join(", "), &product, &mut options, chunk_rows, jobs, Path::new(image))?;
+    }
+    match out {
+        Some(out) => write_grid(&grid, &out, cog, conflict),
+        None if image.is_none() => write_grid(&grid, "-", cog, conflict),
+        None => Ok(()),
     }
+}
 
-    Ok((data, ncols, nrows, nodata_value))
+/// Renders `product` of a mosaic in bands of `chunk_rows` rows, `jobs` at a time, straight into the image file. The
+/// colors are normalized to the elevations of the whole mosaic so the bands match; shaders that search the horizon
+/// across the whole grid render it in one band. The PNG text chunks name the `sources`.
+fn render_mosaic_image(grid: &Grid, sources: &str, product: &Product, options: &mut Options, chunk_rows: usize, jobs: usize, path: &Path) -> io::Result<()> {
+    if options.elevation_range.is_none() {
+        let (min, max) = grid.min_max();
+        options.elevation_range = Some(ElevationRange { min, max, mark_outside: false });
+    }
+    options.shade = grid_shade(grid, options);
+    let (chunk_rows, halo) = match chunked::halo(&options.shade) {
+        Some(halo) => (chunk_rows, halo),
+        None => {
+            eprintln!("Warning: the {} shader looks across the whole grid, rendering the mosaic in one band", options.shade.shader.name());
+            (grid.nrows, 0)
+        }
+    };
+    let backend = select_backend(options.gpu);
+    let range = render::color_range(grid, options.elevation_range.as_ref());
+    let mut provenance = Provenance::new(path, grid, range, &options.colormap_name, options.stretch, options.tone, &options.shade);
+    provenance.source = format!("mosaic of {}", sources);
+    let options = &*options;
+    let path = options.conflict.claim(path)?;
+    save_rgb_rows(grid, &path, &options.format, &provenance.text_chunks(), |write| {
+        chunked::render_chunked(grid, chunk_rows, halo, jobs, |band| render_product(band, product, options, &options.shade, &backend).to_rgb8(), write)
+    })
 }
 
-/// Finds min and max elevation values, ignoring NoData
-fn find_min_max(data: &[Vec<f32>], nodata: f32) -> (f32, f32) {
-    let mut min = f32::MAX;
-    let mut max = f32::MIN;
-    for row in data {
-        for &val in row {
-            if val != nodata {
-                min = min.min(val);
-                max = max.max(val);
+/// `exam cost-path <file|-> --from <x,y> --to <x,y> [--cost tobler|tobler-offpath|flat] [--max-slope <degrees>] [--out <route.geojson|route.shp|->]
+/// [--image <route.png>] [flags of the batch run]`: the fastest walking route, as GeoJSON and optionally drawn on the hillshade
+fn cost_path_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam cost-path <file.asc|file.tif|-> --from <x,y> --to <x,y> [--cost tobler|tobler-offpath|flat] [--max-slope <degrees>] \
+        [--out <route.geojson|route.shp|->] [--image <route.png>] [other flags of the batch run]";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
+
+    let (input, rest) = args.split_first().ok_or_else(|| invalid("Missing input file".into()))?;
+    let (mut from, mut to) = (None, None);
+    let mut settings = RouteSettings::default();
+    let mut out = String::from("-");
+    let mut image = None;
+    let mut flags = Vec::new();
+    let mut rest = rest.iter().cloned();
+    while let Some(arg) = rest.next() {
+        let mut value = || rest.next().ok_or_else(|| invalid(format!("Missing value for {}", arg)));
+        let point = |text: String| match parse_numbers(&text)[..] {
+            [x, y] => Ok((x, y)),
+            _ => Err(invalid(format!("{} expects <x,y>", arg))),
+        };
+        match arg.as_str() {
+            "--from" => from = Some(point(value()?)?),
+            "--to" => to = Some(point(value()?)?),
+            "--cost" => {
+                let name = value()?;
+                settings.cost = CostFunction::parse(&name).ok_or_else(|| invalid(format!("Unknown cost function {:?}", name)))?;
             }
+            "--max-slope" => match value()?.parse() {
+                Ok(degrees) if degrees > 0.0 && degrees < 90.0 => settings.max_slope = Some(degrees),
+                _ => return Err(invalid("--max-slope expects degrees between 0 and 90".into())),
+            },
+            "--out" | "-o" => out = value()?,
+            "--image" => image = Some(value()?),
+            _ => flags.push(arg),
         }
     }
-    (min, max)
+    let (Some(from), Some(to)) = (from, to) else {
+        return Err(invalid("A route needs --from and --to".into()));
+    };
+    let mut options = parse_args(flags)?;
+
+    let input_path = Some(Path::new(input)).filter(|_| input != "-");
+    let (grid, shade) = warp_grid(read_input(input, &options)?, input_path, &options)?;
+    let route = costpath::least_cost_path(&grid, from, to, &settings).map_err(invalid)?;
+    eprintln!("Route of {:.2} km, {}, {:.0} m up and {:.0} m down", route.length / 1000.0, costpath::format_duration(route.seconds), route.ascent, route.descent);
+
+    if let Some(image) = image {
+        let backend = select_backend(options.gpu);
+        let mut img = render_product(&grid, &Product::Hillshade, &options, &shade, &backend).to_rgb8();
+        route.draw(&mut img);
+        if let Some(format) = Path::new(&image).extension().and_then(|e| ImageFormat::parse(&e.to_string_lossy())) {
+            options.format.format = format;
+        }
+        let mut file = BufWriter::new(File::create(&image)?);
+        encode_image(DynamicImage::ImageRgb8(img), &options.format, &mut file)?;
+        file.flush()?;
+    }
+    if out.to_lowercase().ends_with(".shp") {
+        return costpath::save_shapefile(&route, &grid, &settings, Path::new(&out));
+    }
+    let mut w: Box<dyn Write> = if out == "-" { Box::new(io::stdout().lock()) } else { Box::new(BufWriter::new(File::create(&out)?)) };
+    costpath::write_geojson(&route, &grid, &settings, &mut w)?;
+    writeln!(w)?;
+    w.flush()
 }
 
-/// Generates and saves grayscale elevation image to <output_dir>/grayscale.png
-fn save_grayscale_image(data: &[Vec<f32>], ncols: usize, nrows: usize, nodata: f32, output_dir: &Path) -> io::Result<()> {
-    let (min, max) = find_min_max(data, nodata);
-    let mut img = GrayImage::new(ncols as u32, nrows as u32);
+/// `exam viewshed <file|-> --observers <sites.csv> | --at <x,y[,height]> ... [--height <m>] [--target-height <m>] [--radius <m>] [--out-dir <dir>]`:
+/// what every observer sees, and how many of them see each cell
+fn viewshed_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam viewshed <file.asc|file.tif|-> --observers <sites.csv> | --at <x,y[,height]> ... [--height <m>] [--target-height <m>] \
+        [--radius <m>] [--out-dir <dir>] [--overwrite|--skip-existing|--suffix-on-conflict]";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
 
-    for (y, row) in data.iter().enumerate() {
-        for (x, &val) in row.iter().enumerate() {
-            let pixel = if val == nodata {
-                0
-            } else {
-                ((val - min) / (max - min) * 255.0).clamp(0.0, 255.0) as u8
-            };
-            img.put_pixel(x as u32, y as u32, Luma([pixel]));
+    let (input, rest) = args.split_first().ok_or_else(|| invalid("Missing input file".into()))?;
+    let mut sites: Vec<Site> = Vec::new();
+    let mut height = DEFAULT_OBSERVER_HEIGHT;
+    let mut settings = ViewshedSettings::default();
+    let mut out_dir = PathBuf::from("viewshed");
+    let mut conflict = Conflict::default();
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        if let Some(policy) = Conflict::from_flag(arg) {
+            conflict = policy;
+            continue;
+        }
+        let mut value = || rest.next().cloned().ok_or_else(|| invalid(format!("Missing value for {}", arg)));
+        let mut meters = |name: &str| match value()?.parse() {
+            Ok(v) if v >= 0.0 => Ok(v),
+            _ => Err(invalid(format!("{} expects meters of at least 0", name))),
+        };
+        match arg.as_str() {
+            "--observers" => sites.extend(read_observers(Path::new(&value()?))?),
+            "--at" => match parse_numbers(&value()?)[..] {
+                [x, y] => sites.push(((sites.len() + 1).to_string(), x, y, None)),
+                [x, y, h] => sites.push(((sites.len() + 1).to_string(), x, y, Some(h))),
+                _ => return Err(invalid("--at expects <x,y> or <x,y,height>".into())),
+            },
+            "--height" => height = meters("--height")?,
+            "--target-height" => settings.target_height = meters("--target-height")?,
+            "--radius" => settings.radius = Some(meters("--radius")?).filter(|&r| r > 0.0),
+            "--out-dir" | "-o" => out_dir = PathBuf::from(value()?),
+            _ => return Err(invalid(format!("Unknown argument {:?}", arg))),
         }
     }
+    if sites.is_empty() {
+        return Err(invalid("No observers given, use --observers or --at".into()));
+    }
+    let observers: Vec<Observer> = sites.into_iter()
+        .map(|(name, x, y, h)| Observer { name, x, y, height: h.unwrap_or(height) })
+        .collect();
 
-    let output_path = output_dir.join("grayscale.png");
-    img.save(output_path).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let grid = read_grid(input)?;
+    let stats = viewshed::save_viewsheds(&grid, &observers, &settings, &out_dir, conflict)?;
+    for observer in &stats.observers {
+        println!("{}: {:.1}% visible, {} cells seen by no other observer", observer.name,
+            100.0 * observer.visible_cells as f64 / stats.cells.max(1) as f64, observer.unique_cells);
+    }
+    println!("{:.1}% of {} cells covered, results in {:?}", 100.0 * stats.covered_fraction, stats.cells, out_dir);
     Ok(())
 }
 
-/// Generates and saves color-mapped elevation image to <output_dir>/colored.png
-fn save_colored_image(data: &[Vec<f32>], ncols: usize, nrows: usize, nodata: f32, output_dir: &Path) -> io::Result<()> {
-    let (min, max) = find_min_max(data, nodata);
-    let grad = colorgrad::viridis();
-    let mut img = RgbImage::new(ncols as u32, nrows as u32);
+/// `exam tin <file|-> [--max-error <m>] [--max-vertices <n>] [--out <mesh.obj|mesh.geojson|->]`:
+/// the grid as a Delaunay TIN refined until no cell is further than --max-error from it
+fn tin_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam tin <file.asc|file.tif|-> [--max-error <m>] [--max-vertices <n>] [--out <mesh.obj|mesh.geojson|->]";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
 
-    for (y, row) in data.iter().enumerate() {
-        for (x, &val) in row.iter().enumerate() {
-            let rgb = if val == nodata {
-                Rgb([0, 0, 0])
-            } else {
-                let norm = (val - min) / (max - min);
-                let (r, g, b, _) = grad.at(norm.clamp(0.0, 1.0) as f64).rgba();
-                Rgb([(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8])
-            };
-            img.put_pixel(x as u32, y as u32, rgb);
+    let (input, rest) = args.split_first().ok_or_else(|| invalid("Missing input file".into()))?;
+    let mut max_error = 0.5;
+    let mut max_vertices = None;
+    let mut out = String::from("-");
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        let value = rest.next().cloned().ok_or_else(|| invalid(format!("Missing value for {}", arg)))?;
+        match arg.as_str() {
+            "--max-error" => match value.parse() {
+                Ok(error) if error >= 0.0 => max_error = error,
+                _ => return Err(invalid("--max-error expects an elevation difference of at least 0".into())),
+            },
+            "--max-vertices" => match value.parse() {
+                Ok(n) if n >= 4 => max_vertices = Some(n),
+                _ => return Err(invalid("--max-vertices expects a count of at least 4".into())),
+            },
+            "--out" | "-o" => out = value,
+            _ => return Err(invalid(format!("Unknown argument {:?}", arg))),
+        }
+    }
+
+    let grid = read_grid(input)?;
+    let tin = Tin::greedy(&grid, max_error, max_vertices).map_err(invalid)?;
+    eprintln!("{} vertices and {} triangles for {} cells", tin.vertices.len(), tin.triangles.len(), grid.ncols * grid.nrows);
+    let mut w: Box<dyn Write> = if out == "-" { Box::new(io::stdout().lock()) } else { Box::new(BufWriter::new(File::create(&out)?)) };
+    if out.to_lowercase().ends_with(".geojson") || out.to_lowercase().ends_with(".json") {
+        tin.write_geojson(&mut w)?;
+    } else {
+        tin.write_obj(&mut w)?;
+    }
+    w.flush()
+}
+
+/// `exam terrain-tiles <file|-> [--out-dir <dir>] [--max-level <n>] [other flags]`: Cesium quantized-mesh tiles
+/// and layer.json of the grid warped to WGS 84
+fn terrain_tiles_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam terrain-tiles <file.asc|file.tif|-> [--out-dir <dir>] [--max-level <n>] [other flags of the batch run]";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
+
+    let (input, rest) = args.split_first().ok_or_else(|| invalid("Missing input file".into()))?;
+    let mut out_dir = PathBuf::from("terrain");
+    let mut max_level = None;
+    let mut flags = Vec::new();
+    let mut rest = rest.iter().cloned();
+    while let Some(arg) = rest.next() {
+        let mut value = || rest.next().ok_or_else(|| invalid(format!("Missing value for {}", arg)));
+        match arg.as_str() {
+            "--out-dir" | "-o" => out_dir = PathBuf::from(value()?),
+            "--max-level" => match value()?.parse() {
+                Ok(level) if level <= quantized_mesh::MAX_LEVEL => max_level = Some(level),
+                _ => return Err(invalid(format!("--max-level expects a level from 0 to {}", quantized_mesh::MAX_LEVEL))),
+            },
+            _ => flags.push(arg),
         }
     }
+    let options = parse_args(flags)?;
 
-    let output_path = output_dir.join("colored.png");
-    img.save(output_path).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let input_path = Some(Path::new(input)).filter(|_| input != "-");
+    let (grid, shade) = warp_grid(read_input(input, &options)?, input_path, &options)?;
+    let (grid, _) = geographic_grid(&grid, input_path, &options, shade)?;
+    let max_level = max_level.unwrap_or_else(|| quantized_mesh::native_level(grid.cellsize));
+    let count = quantized_mesh::save_terrain_tiles(&grid, max_level, &out_dir, options.conflict)?;
+    println!("{} tiles of levels 0 to {} in {:?}", count, max_level, out_dir);
     Ok(())
 }
 
-/// Generates and saves hillshaded color image to <output_dir>/hillside.png
-fn save_color_hillshade_image(data: &[Vec<f32>], ncols: usize, nrows: usize, nodata: f32, output_dir: &Path) -> io::Result<()> {
-    let (min, max) = find_min_max(data, nodata);
-    let grad = colorgrad::viridis();
-    let mut img = RgbImage::new(ncols as u32, nrows as u32);
+/// `exam pmtiles <file|-> [--product <name>] [--min-zoom <z>] [--max-zoom <z>] [--out <map.pmtiles>] [other flags]`:
+/// a product rendered in Web Mercator as an XYZ tile pyramid in one PMTiles archive
+fn pmtiles_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam pmtiles <file.asc|file.tif|-> [--product <name>] [--min-zoom <z>] [--max-zoom <z>] [--out <map.pmtiles>] [other flags of the batch run]";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
 
-    let elev_f64: Vec<Vec<f64>> = data.iter().map(|row| row.iter().map(|&x| x as f64).collect()).collect();
-    let cell_size = 30.0;
-    let z_factor = 1.0;
-    let azimuth = 315.0;
-    let altitude = 45.0;
+    let (input, rest) = args.split_first().ok_or_else(|| invalid("Missing input file".into()))?;
+    let mut product = Product::Hillshade;
+    let (mut min_zoom, mut max_zoom) = (None, None);
+    let mut out = PathBuf::from("map.pmtiles");
+    let mut flags = Vec::new();
+    let mut rest = rest.iter().cloned();
+    while let Some(arg) = rest.next() {
+        let mut value = || rest.next().ok_or_else(|| invalid(format!("Missing value for {}", arg)));
+        let zoom = |text: String| match text.parse() {
+            Ok(z) if z <= pmtiles::MAX_ZOOM => Ok(z),
+            _ => Err(invalid(format!("{} expects a zoom from 0 to {}", arg, pmtiles::MAX_ZOOM))),
+        };
+        match arg.as_str() {
+            "--product" => {
+                let name = value()?;
+                product = match Product::parse(&name) {
+                    Some(p @ (Product::Grayscale | Product::Colored | Product::Hillshade | Product::Shade | Product::Plugin(_) | Product::Qc)) => p,
+                    _ => return Err(invalid(format!("{:?} is not a map product, use grayscale, colored, hillshade, shade, qc, a derivative, ruggedness, terraces or tanaka", name))),
+                };
+            }
+            "--min-zoom" => min_zoom = Some(zoom(value()?)?),
+            "--max-zoom" => max_zoom = Some(zoom(value()?)?),
+            "--out" | "-o" => out = PathBuf::from(value()?),
+            _ => flags.push(arg),
+        }
+    }
+    let options = parse_args(flags)?;
 
-    for y in 0..nrows {
-        for x in 0..ncols {
-            let val = data[y][x];
-            let base_color = if val == nodata {
-                Rgb([0, 0, 0])
-            } else {
-                let norm = (val - min) / (max - min);
-                let (r, g, b, _) = grad.at(norm.clamp(0.0, 1.0) as f64).rgba();
-                Rgb([(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8])
-            };
+    let input_path = Some(Path::new(input)).filter(|_| input != "-");
+    let (grid, shade) = warp_grid(read_input(input, &options)?, input_path, &options)?;
+    let (grid, shade) = web_mercator_grid(&grid, input_path, &options, shade)?;
+    let native = ZoomRange::native(&grid);
+    let max = max_zoom.unwrap_or(native.max);
+    let zooms = ZoomRange { min: min_zoom.unwrap_or(native.min.min(max)), max };
+    let img = render_product(&grid, &product, &options, &shade, &select_backend(options.gpu));
+    let name = Path::new(input).file_stem().map_or("exam".into(), |stem| stem.to_string_lossy().into_owned());
+    let stats = pmtiles::save_pmtiles(&img, &grid, zooms, &name, &out).map_err(|e| invalid(e.to_string()))?;
+    println!("{} tiles ({} distinct) of zooms {} to {}, {} bytes in {:?}", stats.tiles, stats.unique, zooms.min, zooms.max, stats.bytes, out);
+    Ok(())
+}
 
-            let shade = if val == nodata {
-                0
-            } else {
-                calculate_hillshade(&elev_f64, x, y, cell_size, z_factor, azimuth, altitude, nodata as f64)
-            };
+/// `exam heightmap <file|-> --preset unity|unreal|povray|blender [--size <samples>] [--out <file>]`: a 16-bit heightmap
+/// resampled to a size the program accepts, with its scales in `<file>.json` and a scene or script for the renderers
+fn heightmap_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam heightmap <file.asc|file.tif|-> --preset unity|unreal|povray|blender [--size <samples>] [--out <heightmap.raw|.png|.pgm|.tif>] \
+        [--overwrite|--skip-existing|--suffix-on-conflict]";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
+
+    let (input, rest) = args.split_first().ok_or_else(|| invalid("Missing input file".into()))?;
+    let mut preset = None;
+    let mut size = None;
+    let mut out = None;
+    let mut conflict = Conflict::default();
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        if let Some(policy) = Conflict::from_flag(arg) {
+            conflict = policy;
+            continue;
+        }
+        let value = rest.next().cloned().ok_or_else(|| invalid(format!("Missing value for {}", arg)))?;
+        match arg.as_str() {
+            "--preset" => preset = Some(Preset::parse(&value).ok_or_else(|| invalid(format!("Unknown preset {:?}", value)))?),
+            "--size" => size = Some(value.parse::<usize>().map_err(|_| invalid("--size expects a number of samples".into()))?),
+            "--out" | "-o" => out = Some(PathBuf::from(value)),
+            _ => return Err(invalid(format!("Unknown argument {:?}", arg))),
+        }
+    }
+    let preset = preset.ok_or_else(|| invalid("Missing --preset".into()))?;
+    if let Some(size) = size.filter(|&size| !preset.accepts(size)) {
+        return Err(invalid(format!("{} takes no heightmap of {} samples", preset.name(), size)));
+    }
+
+    let grid = read_grid(input)?;
+    let size = size.unwrap_or_else(|| preset.size_for(grid.ncols.max(grid.nrows)));
+    let heightmap = Heightmap::new(&grid, preset, size).map_err(invalid)?;
+    let stem = Path::new(input).file_stem().filter(|_| input != "-").map_or("heightmap".into(), |stem| stem.to_string_lossy().into_owned());
+    let out = out.unwrap_or_else(|| PathBuf::from(format!("{}.{}", stem, preset.extension())));
+    let out = heightmap.save(&out, conflict)?;
+    println!("{}x{} samples ({}x{} from the grid), {:.2} m apart, elevations {:.2} to {:.2} m in {:?}", heightmap.width, heightmap.height,
+        heightmap.source_cols, heightmap.source_rows, heightmap.spacing, heightmap.min_elevation, heightmap.max_elevation, out);
+    match preset {
+        Preset::Unity => println!("Unity terrain size: width {:.2}, height {:.2}, length {:.2}",
+            heightmap.terrain_size[0], heightmap.terrain_size[1], heightmap.terrain_size[2]),
+        Preset::Povray | Preset::Blender => println!("Scale: {:.2} x {:.2} m, {:.2} m of relief, scene next to the image",
+            heightmap.terrain_size[0], heightmap.terrain_size[2], heightmap.terrain_size[1]),
+        Preset::Unreal => println!("Unreal landscape scale: X {:.3}, Y {:.3}, Z {:.3}, location Z {:.1}",
+            heightmap.unreal_scale[0], heightmap.unreal_scale[1], heightmap.unreal_scale[2], heightmap.unreal_z_offset),
+    }
+    Ok(())
+}
+
+/// `exam voxels <file|-> [--block-size <m>] [--step <cells>] [--sea-level <m>] [--out <world.schem|world.json>]`:
+/// the terrain as block columns for sandbox games
+fn voxels_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam voxels <file.asc|file.tif|-> [--block-size <m>] [--step <cells>] [--sea-level <m>] [--out <world.schem|world.json|->]";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
+
+    let (input, rest) = args.split_first().ok_or_else(|| invalid("Missing input file".into()))?;
+    let mut settings = VoxelSettings::default();
+    let mut out = String::from("world.schem");
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        let value = rest.next().cloned().ok_or_else(|| invalid(format!("Missing value for {}", arg)))?;
+        match arg.as_str() {
+            "--block-size" => match value.parse() {
+                Ok(size) if size > 0.0 => settings.block_size = size,
+                _ => return Err(invalid("--block-size expects an elevation step above 0".into())),
+            },
+            "--step" => match value.parse() {
+                Ok(step) if step > 0 => settings.step = step,
+                _ => return Err(invalid("--step expects a number of cells".into())),
+            },
+            "--sea-level" => settings.sea_level = Some(value.parse().map_err(|_| invalid("--sea-level expects an elevation".into()))?),
+            "--out" | "-o" => out = value,
+            _ => return Err(invalid(format!("Unknown argument {:?}", arg))),
+        }
+    }
+
+    let voxels = Voxels::new(&read_grid(input)?, &settings).map_err(invalid)?;
+    eprintln!("{}x{} columns of up to {} blocks, {:.2} m wide", voxels.width, voxels.length, voxels.height, voxels.column_size);
+    if voxels.height > WORLD_HEIGHT {
+        eprintln!("Warning: {} blocks exceed the {} block build height, raise --block-size", voxels.height, WORLD_HEIGHT);
+    }
+    let mut w: Box<dyn Write> = if out == "-" { Box::new(io::stdout().lock()) } else { Box::new(BufWriter::new(File::create(&out)?)) };
+    if out.to_lowercase().ends_with(".json") {
+        voxels.write_json(&mut w)?;
+    } else {
+        voxels.write_schematic(&mut w)?;
+    }
+    w.flush()
+}
+
+/// `exam ridgeline <file|-> [--step <rows>] [--exaggeration <gaps>] [--no-occlusion] [--width <pixels>] [--out <plot.svg|plot.png|->]`:
+/// stacked elevation profiles in the style of a joy plot
+fn ridgeline_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam ridgeline <file.asc|file.tif|-> [--step <rows>] [--exaggeration <gaps>] [--no-occlusion] [--width <pixels>] [--out <plot.svg|plot.png|->]";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
+
+    let (input, rest) = args.split_first().ok_or_else(|| invalid("Missing input file".into()))?;
+    let mut settings = RidgelineSettings::default();
+    let mut out = None;
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        if arg == "--no-occlusion" {
+            settings.occlusion = false;
+            continue;
+        }
+        let value = rest.next().cloned().ok_or_else(|| invalid(format!("Missing value for {}", arg)))?;
+        match arg.as_str() {
+            "--step" => match value.parse() {
+                Ok(step) if step > 0 => settings.step = Some(step),
+                _ => return Err(invalid("--step expects a number of rows".into())),
+            },
+            "--exaggeration" => match value.parse() {
+                Ok(e) if e >= 0.0 => settings.exaggeration = e,
+                _ => return Err(invalid("--exaggeration expects a number of gaps of at least 0".into())),
+            },
+            "--width" => match value.parse() {
+                Ok(w) if w >= 16 => settings.width = w,
+                _ => return Err(invalid("--width expects at least 16 pixels".into())),
+            },
+            "--out" | "-o" => out = Some(value),
+            _ => return Err(invalid(format!("Unknown argument {:?}", arg))),
+        }
+    }
+
+    let ridgelines = Ridgelines::new(&read_grid(input)?, &settings).map_err(invalid)?;
+    let stem = Path::new(input).file_stem().filter(|_| input != "-").map_or("ridgeline".into(), |stem| stem.to_string_lossy().into_owned());
+    let out = out.unwrap_or_else(|| format!("{}_ridgeline.svg", stem));
+    eprintln!("{} profiles, {}x{} pixels", ridgelines.profiles.len(), ridgelines.width, ridgelines.height);
+    if out == "-" || out.to_lowercase().ends_with(".svg") {
+        let mut w: Box<dyn Write> = if out == "-" { Box::new(io::stdout().lock()) } else { Box::new(BufWriter::new(File::create(&out)?)) };
+        ridgelines.write_svg(&mut w)?;
+        return w.flush();
+    }
+    ridgelines.render().save(&out).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
 
-            let factor = shade as f32 / 255.0;
-            let shaded = Rgb([
-                (base_color[0] as f32 * (1.0 - factor)) as u8,
-                (base_color[1] as f32 * (1.0 - factor)) as u8,
-                (base_color[2] as f32 * (1.0 - factor)) as u8,
-            ]);
+/// `exam contours <file|-> [--interval <elevation>] [--index <n>] [--smooth] [--labels] [--width <pixels>] [--out <contours.svg|contours.shp|->]`:
+/// contour lines as SVG for map finishing
+fn contours_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam contours <file.asc|file.tif|-> [--interval <elevation>] [--index <n>] [--smooth] [--labels] [--width <pixels>] [--out <contours.svg|contours.shp|->]";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
 
-            img.put_pixel(x as u32, y as u32, shaded);
+    let (input, rest) = args.split_first().ok_or_else(|| invalid("Missing input file".into()))?;
+    let mut settings = ContourSettings::default();
+    let mut out = None;
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--smooth" => settings.smooth = true,
+            "--labels" => settings.labels = true,
+            _ => {
+                let value = rest.next().cloned().ok_or_else(|| invalid(format!("Missing value for {}", arg)))?;
+                match arg.as_str() {
+                    "--interval" => match value.parse() {
+                        Ok(i) if i > 0.0 => settings.interval = Some(i),
+                        _ => return Err(invalid("--interval expects an elevation above 0".into())),
+                    },
+                    "--index" => match value.parse() {
+                        Ok(n) if n > 0 => settings.index_every = n,
+                        _ => return Err(invalid("--index expects a number of contours".into())),
+                    },
+                    "--width" => match value.parse() {
+                        Ok(w) if w >= 16 => settings.width = w,
+                        _ => return Err(invalid("--width expects at least 16 pixels".into())),
+                    },
+                    "--out" | "-o" => out = Some(value),
+                    _ => return Err(invalid(format!("Unknown argument {:?}", arg))),
+                }
+            }
         }
     }
 
-    let output_path = output_dir.join("hillside.png");
-    img.save(output_path).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let grid = read_grid(input)?;
+    let contours = Contours::new(&grid, &settings).map_err(invalid)?;
+    let stem = Path::new(input).file_stem().filter(|_| input != "-").map_or("contours".into(), |stem| stem.to_string_lossy().into_owned());
+    let out = out.unwrap_or_else(|| format!("{}_contours.svg", stem));
+    let index = contours.lines.iter().filter(|c| c.index).count();
+    eprintln!("{} contour lines every {}, {} of them index contours", contours.lines.len(), contours.interval, index);
+    if out.to_lowercase().ends_with(".shp") {
+        return contours.save_shapefile(&grid, Path::new(&out));
+    }
+    let mut w: Box<dyn Write> = if out == "-" { Box::new(io::stdout().lock()) } else { Box::new(BufWriter::new(File::create(&out)?)) };
+    contours.write_svg(&mut w)?;
+    w.flush()
+}
+
+/// `exam gpkg <file|-> [--products <names>] [--contours <interval>] [--index <n>] [--epsg <code>] [--out <file.gpkg>]
+/// [flags of the batch run]`: rendered images as tile pyramids and contours as features in one GeoPackage
+fn gpkg_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam gpkg <file.asc|file.tif|-> [--products hillshade,colored,...] [--contours <interval>] [--index <n>] [--epsg <code>] \
+        [--out <file.gpkg>] [other flags of the batch run]";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
+
+    let (input, rest) = args.split_first().ok_or_else(|| invalid("Missing input file".into()))?;
+    let mut products = vec![Product::Hillshade];
+    let mut contours: Option<ContourSettings> = None;
+    let mut index_every = None;
+    let mut epsg = None;
+    let mut out = None;
+    let mut flags = Vec::new();
+    let mut rest = rest.iter().cloned();
+    while let Some(arg) = rest.next() {
+        let mut value = || rest.next().ok_or_else(|| invalid(format!("Missing value for {}", arg)));
+        match arg.as_str() {
+            "--products" => {
+                products = value()?.split(',').filter(|name| !name.is_empty()).map(|name| match Product::parse(name) {
+                    Some(p @ (Product::Grayscale | Product::Colored | Product::Hillshade | Product::Shade | Product::Plugin(_) | Product::Qc)) => Ok(p),
+                    _ => Err(invalid(format!("{:?} is not a map product, use grayscale, colored, hillshade, shade, qc, a derivative, ruggedness, terraces or tanaka", name))),
+                }).collect::<io::Result<_>>()?;
+            }
+            "--contours" => match value()?.parse() {
+                Ok(interval) if interval > 0.0 => contours = Some(ContourSettings { interval: Some(interval), ..ContourSettings::default() }),
+                _ => return Err(invalid("--contours expects an interval above 0".into())),
+            },
+            "--index" => match value()?.parse() {
+                Ok(n) if n > 0 => index_every = Some(n),
+                _ => return Err(invalid("--index expects a number of contours".into())),
+            },
+            "--epsg" => epsg = Some(value()?.parse::<u32>().map_err(|_| invalid("--epsg expects an EPSG code".into()))?),
+            "--out" | "-o" => out = Some(PathBuf::from(value()?)),
+            _ => flags.push(arg),
+        }
+    }
+    let options = parse_args(flags)?;
+    if let (Some(settings), Some(n)) = (contours.as_mut(), index_every) {
+        settings.index_every = n;
+    }
+    // The system the grid ends up in, when given as an EPSG code
+    let epsg = epsg.or_else(|| options.t_srs.as_ref().or(options.s_srs.as_ref())
+        .and_then(|srs| srs.to_uppercase().strip_prefix("EPSG:").and_then(|code| code.parse().ok())));
+
+    let input_path = Some(Path::new(input)).filter(|_| input != "-");
+    let (grid, shade) = warp_grid(read_input(input, &options)?, input_path, &options)?;
+    let stem = input_path.and_then(Path::file_stem).map_or("exam".into(), |stem| stem.to_string_lossy().into_owned());
+    let out = out.unwrap_or_else(|| PathBuf::from(format!("{}.gpkg", stem)));
+    let backend = select_backend(options.gpu);
+    let images: Vec<(String, DynamicImage)> = products.iter()
+        .map(|product| (product.name().to_string(), render_product(&grid, product, &options, &shade, &backend)))
+        .collect();
+    let contours = contours.map(|settings| Contours::new(&grid, &settings).map_err(invalid)).transpose()?;
+    write_geopackage(&out, epsg, &grid, &images, contours.as_ref())?;
+    println!("{} tile tables{} in {:?}", images.len(), contours.map_or(String::new(), |c| format!(" and {} contour lines", c.lines.len())), out);
     Ok(())
 }
 
-/// Computes hillshade value at a given cell using Horn’s method
-fn calculate_hillshade(elevation: &[Vec<f64>], x: usize, y: usize, cell_size: f64, zf: f64, az: f64, alt: f64, nodata: f64) -> u8 {
-    let get = |dx: isize, dy: isize| -> f64 {
-        let nx = x as isize + dx;
-        let ny = y as isize + dy;
-        if nx >= 0 && ny >= 0 && (nx as usize) < elevation[0].len() && (ny as usize) < elevation.len() {
-            elevation[ny as usize][nx as usize]
-        } else {
-            nodata
+#[cfg(feature = "sqlite")]
+fn write_geopackage(out: &Path, epsg: Option<u32>, grid: &Grid, images: &[(String, DynamicImage)], contours: Option<&Contours>) -> io::Result<()> {
+    use exam::geopackage::{Column, GeoPackage, Geometry};
+    let mut gpkg = GeoPackage::create(out, epsg)?;
+    for (name, img) in images {
+        gpkg.add_tiles(name, img, grid)?;
+    }
+    if let Some(contours) = contours {
+        let lines: Vec<Geometry> = contours.lines.iter().map(|contour| {
+            let ring = contour.closed.then_some(contour.points[0]);
+            Geometry::LineString(contour.points.iter().chain(&ring).map(|&p| exam::contour::map_point(grid, p)).collect())
+        }).collect();
+        gpkg.add_features("contours", &lines, &[
+            Column::Real("elevation", contours.lines.iter().map(|c| c.level).collect()),
+            Column::Integer("index_contour", contours.lines.iter().map(|c| c.index as i64).collect()),
+        ])?;
+    }
+    Ok(())
+}
+
+/// Without the `sqlite` feature no GeoPackage can be written
+#[cfg(not(feature = "sqlite"))]
+fn write_geopackage(_out: &Path, _epsg: Option<u32>, _grid: &Grid, _images: &[(String, DynamicImage)], _contours: Option<&Contours>) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "GeoPackages need the `sqlite` feature"))
+}
+
+fn convert_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam convert <file.asc|file.tif|-> --to <csv|parquet> [--out <file|->]";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
+
+    let (input, rest) = args.split_first().ok_or_else(|| invalid("Missing input file".into()))?;
+    let mut format = None;
+    let mut out = None;
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        let value = rest.next().cloned().ok_or_else(|| invalid(format!("Missing value for {}", arg)))?;
+        match arg.as_str() {
+            "--to" => format = Some(XyzFormat::parse(&value).ok_or_else(|| invalid(format!("Unknown table format {:?}, expected csv or parquet", value)))?),
+            "--out" | "-o" => out = Some(value),
+            _ => return Err(invalid(format!("Unknown argument {:?}", arg))),
+        }
+    }
+    let format = format.ok_or_else(|| invalid("Missing --to".into()))?;
+    // Next to the input by default, standard output for standard input
+    let out = out.unwrap_or_else(|| match input.as_str() {
+        "-" => "-".into(),
+        path => Path::new(path).with_extension(format.extension()).to_string_lossy().into_owned(),
+    });
+
+    let grid = read_grid(input)?;
+    let mut w: Box<dyn Write> = if out == "-" { Box::new(io::stdout().lock()) } else { Box::new(BufWriter::new(File::create(&out)?)) };
+    let rows = xyz::write_xyz(&grid, format, &mut w)?;
+    w.flush()?;
+    eprintln!("{} rows of x, y, z", rows);
+    Ok(())
+}
+
+/// `exam lidar <points.las|points.laz> [--out <file.asc|file.tif|file.npy|->] [flags]`: grids a point cloud into a DTM
+/// or DSM with --resolution, --surface, --classes and --fill
+fn lidar_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam lidar <points.las|points.laz> [--resolution <cell size>] [--surface dtm|dsm] [--classes <2,9|all>] [--fill <cells>] \
+        [--cog] [--out <file.asc|file.tif|file.npy|->]";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
+
+    let (input, rest) = args.split_first().ok_or_else(|| invalid("Missing input file".into()))?;
+    let mut out = String::from("-");
+    let mut flags = Vec::new();
+    let mut rest = rest.iter().cloned();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--out" | "-o" => out = rest.next().ok_or_else(|| invalid(format!("Missing value for {}", arg)))?,
+            _ => flags.push(arg),
+        }
+    }
+    let options = parse_args(flags)?;
+
+    let grid = load_point_cloud(Path::new(input), &options.lidar)?;
+    eprintln!("{}x{} cells of {} m", grid.ncols, grid.nrows, grid.cellsize);
+    write_grid(&grid, &out, options.format.cog, options.conflict)
+}
+
+/// `exam verify --reference <dir> [--pixel-tolerance <0-255>] [--value-tolerance <delta>] [flags of the batch run]`:
+/// renders the input folder again and compares every output with the same file below the reference folder, a run of an
+/// earlier version with the same flags. Without --output the fresh outputs go to a temporary folder, kept if they differ.
+fn verify_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam verify --reference <dir> [--pixel-tolerance <0-255>] [--value-tolerance <delta>] [--input <dir>] [--output <dir>] [other flags of the batch run]";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
+
+    let mut reference = None;
+    let mut tolerance = verify::Tolerance::default();
+    let mut flags = Vec::new();
+    let mut rest = args.iter().cloned();
+    while let Some(arg) = rest.next() {
+        let mut value = || rest.next().ok_or_else(|| invalid(format!("Missing value for {}", arg)));
+        match arg.as_str() {
+            "--reference" => reference = Some(PathBuf::from(value()?)),
+            "--pixel-tolerance" => tolerance.pixel = value()?.parse().map_err(|_| invalid("--pixel-tolerance expects a channel difference from 0 to 255".into()))?,
+            "--value-tolerance" => tolerance.value = value()?.parse().ok().filter(|t: &f64| *t >= 0.0)
+                .ok_or_else(|| invalid("--value-tolerance expects a difference of at least 0".into()))?,
+            _ => flags.push(arg),
+        }
+    }
+    let reference = reference.ok_or_else(|| invalid("Missing --reference".into()))?;
+    let temporary = !flags.iter().any(|f| f == "--output");
+    if temporary {
+        flags.extend(["--output".to_string(), env::temp_dir().join(format!("exam-verify-{}", process::id())).to_string_lossy().into_owned()]);
+    }
+    let options = parse_args(flags)?;
+    fs::create_dir_all(&options.output_root)?;
+
+    let report = run_batch(&options)?;
+    if report.failed > 0 {
+        eprintln!("{} of {} files failed to render, see {:?}", report.failed, report.inputs.len(), options.output_root.join(REPORT_FILE));
+    }
+    let verification = verify::compare_outputs(&reference, &options.output_root, tolerance)?;
+    verification.save(&options.output_root.join(verify::VERIFY_FILE))?;
+    for file in &verification.files {
+        match &file.outcome {
+            verify::Outcome::Same | verify::Outcome::Present => {}
+            verify::Outcome::Extra => println!("New      {:?}", file.path),
+            verify::Outcome::Missing => println!("Missing  {:?}", file.path),
+            verify::Outcome::Pixels { differing, pixels, max_delta } => {
+                println!("Changed  {:?}: {} of {} pixels, by up to {}", file.path, differing, pixels, max_delta)
+            }
+            verify::Outcome::Values { differing, cells, max_delta, nodata_changed } => {
+                println!("Changed  {:?}: {} of {} cells, by up to {}, {} gaining or losing NoData", file.path, differing, cells, max_delta, nodata_changed)
+            }
+            verify::Outcome::Size { reference, actual } => {
+                println!("Resized  {:?}: {}x{} instead of {}x{}", file.path, actual.0, actual.1, reference.0, reference.1)
+            }
+            verify::Outcome::Unreadable { error } => println!("Unreadable {:?}: {}", file.path, error),
         }
+    }
+
+    let regressions = verification.regressions().count();
+    if regressions > 0 || report.failed > 0 {
+        return Err(io::Error::new(io::ErrorKind::Other, format!(
+            "{} of {} outputs differ from {:?}, details in {:?}", regressions, verification.files.len(), reference, options.output_root.join(verify::VERIFY_FILE))));
+    }
+    println!("All {} outputs match {:?}", verification.files.len(), reference);
+    if temporary {
+        fs::remove_dir_all(&options.output_root)?;
+    }
+    Ok(())
+}
+
+/// `exam manifest [--coverage] [--input <dir>] [--output <dir>] [--include <glob>] [--recursive] [other flags of the batch run]`:
+/// lists the inputs a batch run would read with the format, file size, grid size, cell size, bounds and NoData from
+/// their headers, in manifest.json and manifest.csv below the output root. --coverage adds an overview of the tile
+/// footprints and the gaps between them as coverage.png and coverage.geojson.
+fn manifest_command(args: &[String]) -> io::Result<()> {
+    let coverage = args.iter().any(|arg| arg == "--coverage");
+    let options = parse_args(args.iter().filter(|arg| *arg != "--coverage").cloned().collect())?;
+    let (inputs, _, _) = batch_inputs(&options, |_| false)?;
+    let mut manifest = Manifest::new(&options.input_dir);
+    for path in &inputs {
+        let entry = ManifestEntry::read(path, path.strip_prefix(&options.input_dir).unwrap_or(path));
+        match (&entry.error, entry.ncols.zip(entry.nrows)) {
+            (Some(error), _) => println!("  {:<40} invalid: {}", entry.input.to_string_lossy(), error),
+            (None, Some((ncols, nrows))) => println!("  {:<40} {:>6} x {:<6} cells of {}", entry.input.to_string_lossy(), ncols, nrows, entry.cellsize.unwrap_or(1.0)),
+            (None, None) => println!("  {:<40} {}, size known only after loading", entry.input.to_string_lossy(), entry.format.unwrap_or("unknown")),
+        }
+        manifest.entries.push(entry);
+    }
+    fs::create_dir_all(&options.output_root)?;
+    manifest.save(&options.output_root)?;
+    let bytes: u64 = manifest.entries.iter().map(|e| e.file_bytes).sum();
+    println!("{} inputs, {:.1} MiB, listed in {:?} and {:?}", manifest.entries.len(), bytes as f64 / (1024.0 * 1024.0),
+        options.output_root.join(MANIFEST_FILE), options.output_root.join(MANIFEST_CSV));
+    if !coverage {
+        return Ok(());
+    }
+
+    let footprints = manifest.entries.iter()
+        .filter_map(|e| Some(Footprint { name: e.input.to_string_lossy().replace('\\', "/"), bounds: e.bounds? }))
+        .collect();
+    let index = TileIndex::new(footprints).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No input has bounds in its header to map"))?;
+    let gaps = index.gaps();
+    let (image, share) = index.render(COVERAGE_SIZE);
+    image.save(options.output_root.join(COVERAGE_IMAGE)).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let mut w = BufWriter::new(File::create(options.output_root.join(COVERAGE_GEOJSON))?);
+    index.write_geojson(&gaps, &mut w)?;
+    w.flush()?;
+    println!("{} tiles cover {:.1} % of their extent, {} tile-sized gaps → {:?} and {:?}", index.footprints().len(), 100.0 * share, gaps.len(),
+        options.output_root.join(COVERAGE_IMAGE), options.output_root.join(COVERAGE_GEOJSON));
+    Ok(())
+}
+
+/// `exam edges [--tolerance <elevation>] [--input <dir>] [--output <dir>] [other flags of the batch run]`: compares the
+/// elevations along the seams of adjacent input tiles and lists them in edges.json and edges.geojson below the output
+/// root, flagging the ones whose mean difference exceeds the tolerance
+fn edges_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam edges [--tolerance <elevation>] [--input <dir>] [--output <dir>] [--include <glob>] [--recursive] [other flags of the batch run]";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
+
+    let mut tolerance = edges::DEFAULT_TOLERANCE;
+    let mut flags = Vec::new();
+    let mut rest = args.iter().cloned();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--tolerance" => match rest.next().map(|value| value.parse::<f64>()) {
+                Some(Ok(value)) if value >= 0.0 => tolerance = value,
+                _ => return Err(invalid("--tolerance expects an elevation difference of at least 0".into())),
+            },
+            _ => flags.push(arg),
+        }
+    }
+    let options = parse_args(flags)?;
+    let (inputs, _, _) = batch_inputs(&options, |_| false)?;
+    let mut tiles = Vec::new();
+    for path in &inputs {
+        let name = path.strip_prefix(&options.input_dir).unwrap_or(path).to_string_lossy().replace('\\', "/");
+        match read_input(&path.to_string_lossy(), &options) {
+            Ok(grid) => tiles.extend(TileEdges::of(&name, &grid).or_else(|| {
+                eprintln!("Warning: {} has less than two rows or columns and is not compared", name);
+                None
+            })),
+            Err(e) => eprintln!("Warning: {}: {}", name, e),
+        }
+    }
+
+    let report = EdgeReport::check(&tiles, tolerance);
+    fs::create_dir_all(&options.output_root)?;
+    let writer = BufWriter::new(File::create(options.output_root.join(EDGES_FILE))?);
+    serde_json::to_writer_pretty(writer, &report).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let mut w = BufWriter::new(File::create(options.output_root.join(EDGES_GEOJSON))?);
+    report.write_geojson(&mut w)?;
+    w.flush()?;
+    for seam in report.seams.iter().filter(|seam| seam.flagged) {
+        println!("  {} | {}: {:+.3} on average over {} cells, up to {:+.3} at {:.2},{:.2}",
+            seam.tiles[0], seam.tiles[1], seam.mean, seam.samples, seam.max, seam.worst[0], seam.worst[1]);
+    }
+    println!("{} seams between {} tiles, {} off by more than {} → {:?} and {:?}", report.seams.len(), report.tiles, report.flagged, tolerance,
+        options.output_root.join(EDGES_FILE), options.output_root.join(EDGES_GEOJSON));
+    Ok(())
+}
+
+/// Flags of the batch run a request to `exam serve` may set: those reading the DEM and styling the image it answers.
+/// All others, e.g. those reading or writing files of the server or running commands, can only be given when it starts.
+const REQUEST_FLAGS: [&str; 54] = [
+    "colormap", "stretch", "gamma", "brightness", "contrast", "elevation-range", "out-of-range", "nodata-style", "simulate-cvd",
+    "azimuth", "altitude", "fill-azimuth", "fill-altitude", "fill-weight", "z-factor", "shading", "shader", "edges",
+    "aerial-perspective", "slope-saturation", "geographic", "projected", "z-units", "z-offset",
+    "image-format", "quality", "png-compression", "png-palette", "tiff-compression", "cog", "kmz-tile",
+    "bands", "parallax", "camera", "target", "fov", "exaggeration", "view-size", "qc-sigma", "qc-void", "spot-heights", "peaks",
+    "preview", "preview-sampling", "s_srs", "t_srs", "t_res", "resampling", "variable", "window", "resolution", "surface", "classes", "fill",
+];
+
+/// Total size of the uploads `exam serve` keeps by default (--max-stored), the oldest are removed beyond it
+const DEFAULT_MAX_STORED: u64 = 10 << 30;
+
+/// What the workers of `exam serve` share
+struct ServeState {
+    defaults: Vec<String>,                         // Batch flags given at start, before the ones of every request
+    data_dir: Option<PathBuf>,                     // Folder of the files requests name with input= (--data)
+    upload_dir: PathBuf,                           // Uploaded DEMs, named by their checksum (--uploads)
+    max_stored: u64,                               // Total size of the uploads kept, the oldest are removed beyond it (--max-stored)
+    trimming: Mutex<()>,                           // Held while the oldest uploads are removed
+    backend: Backend,
+    metrics: Metrics,
+    stats: Mutex<HashMap<String, ElevationStats>>, // Stats of the DEMs asked for before, by id
+    partial: AtomicUsize,                          // Numbers uploads while they are written
+}
+
+impl ServeState {
+    /// A file of the --data folder, named by a relative path that stays inside it
+    fn data_file(&self, name: &str) -> io::Result<PathBuf> {
+        let data_dir = self.data_dir.as_ref().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "The server has no --data folder, upload the DEM instead"))?;
+        if name.is_empty() || !Path::new(name).components().all(|c| matches!(c, std::path::Component::Normal(_))) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} isn't a path inside the --data folder", name)));
+        }
+        let path = data_dir.join(name);
+        if !path.is_file() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("No file {:?} in the --data folder", name)));
+        }
+        Ok(path)
+    }
+
+    /// Stores an uploaded DEM under its checksum, which is its id, after checking it is an elevation grid
+    fn store_upload(&self, body: &[u8]) -> io::Result<(PathBuf, String)> {
+        let partial = self.upload_dir.join(format!(".upload-{}", self.partial.fetch_add(1, Ordering::Relaxed)));
+        fs::write(&partial, body)?;
+        let id = match sniff::sniff_file(&partial).and_then(|_| dedup::checksum(&partial)) {
+            Ok(id) => id,
+            Err(e) => {
+                let _ = fs::remove_file(&partial);
+                return Err(e);
+            }
+        };
+        // An upload of the same content replaces the stored one by itself
+        let path = self.upload_dir.join(&id);
+        fs::rename(&partial, &path)?;
+        self.trim_uploads(&path)?;
+        Ok((path, id))
+    }
+
+    /// Removes the oldest uploads until the rest take at most --max-stored bytes, never `keep`, the one just stored
+    fn trim_uploads(&self, keep: &Path) -> io::Result<()> {
+        let _trimming = self.trimming.lock().unwrap_or_else(|e| e.into_inner());
+        let mut uploads = Vec::new();
+        for entry in fs::read_dir(&self.upload_dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_file() && entry.file_name().to_str().is_some_and(is_upload_id) {
+                uploads.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+        let mut stored: u64 = uploads.iter().map(|(_, len, _)| len).sum();
+        uploads.sort();
+        for (_, len, path) in uploads {
+            if stored <= self.max_stored {
+                break;
+            }
+            if path == keep {
+                continue;
+            }
+            fs::remove_file(&path)?;
+            stored -= len;
+            if let Some(id) = path.file_name().and_then(|name| name.to_str()) {
+                self.stats.lock().unwrap_or_else(|e| e.into_inner()).remove(id);
+            }
+        }
+        Ok(())
+    }
+
+    /// The DEM of an id: an upload or a file of the --data folder
+    fn dem(&self, id: &str) -> io::Result<PathBuf> {
+        let upload = self.upload_dir.join(id);
+        if is_upload_id(id) && upload.is_file() {
+            return Ok(upload);
+        }
+        self.data_file(id)
+    }
+}
+
+/// Whether `name` is the id of an upload, the checksum it is stored under
+fn is_upload_id(name: &str) -> bool {
+    name.len() == 24 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Status of a request that failed with `e`, with the first line of its message
+fn error_response(e: io::Error) -> Response {
+    let status = match e.kind() {
+        io::ErrorKind::InvalidInput => 400,
+        io::ErrorKind::NotFound => 404,
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof | io::ErrorKind::Unsupported => 422,
+        _ => 500,
     };
+    Response::text(status, e.to_string().lines().next().unwrap_or(""))
+}
 
-    let dzdx = ((get(1, -1) + 2.0 * get(1, 0) + get(1, 1)) -
-                (get(-1, -1) + 2.0 * get(-1, 0) + get(-1, 1))) / (8.0 * cell_size) * zf;
-    let dzdy = ((get(-1, 1) + 2.0 * get(0, 1) + get(1, 1)) -
-                (get(-1, -1) + 2.0 * get(0, -1) + get(1, -1))) / (8.0 * cell_size) * zf;
+/// `POST /render?product=<name>&<flag>=<value>...`: renders the DEM sent as the body, or the file input=<path> of
+/// the --data folder, with the batch flags of the query on top of those of the server, and answers the image. The id
+/// of the DEM for /stats is in the X-Exam-Id header.
+fn serve_render(state: &ServeState, request: &Request) -> io::Result<Response> {
+    let bad = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    let mut product = Product::Hillshade;
+    let mut input = None;
+    let mut flags = state.defaults.clone();
+    for (key, value) in &request.query {
+        match key.as_str() {
+            "product" => product = Product::parse(value).ok_or_else(|| bad(format!("Unknown product {:?}", value)))?,
+            "input" => input = Some(value.as_str()),
+            key if !REQUEST_FLAGS.contains(&key) => return Err(bad(format!("--{} can't be set by a request, only when the server starts", key))),
+            key => {
+                flags.push(format!("--{}", key));
+                if !value.is_empty() {
+                    flags.push(value.clone());
+                }
+            }
+        }
+    }
+    let options = parse_args(flags)?;
+    let (path, id) = match (input, request.body.is_empty()) {
+        (Some(name), true) => (state.data_file(name)?, name.to_string()),
+        (None, false) => state.store_upload(&request.body)?,
+        (Some(_), false) => return Err(bad("Send the DEM as the body or name a file of the --data folder with input=, not both".into())),
+        (None, true) => return Err(bad("No DEM: send one as the body or name a file of the --data folder with input=".into())),
+    };
 
-    let slope = (dzdx.powi(2) + dzdy.powi(2)).sqrt().atan();
-    let aspect = if dzdx != 0.0 {
-        let mut a = (dzdy / dzdx).atan();
-        if dzdx < 0.0 { a += std::f64::consts::PI; }
-        else if dzdy < 0.0 { a += 2.0 * std::f64::consts::PI; }
-        a
-    } else {
-        if dzdy > 0.0 { std::f64::consts::FRAC_PI_2 } else { 3.0 * std::f64::consts::FRAC_PI_2 }
+    let mut record = InputReport::new(&path);
+    let rendered = record.time(product.name(), || render_encoded(&path.to_string_lossy(), &product, product == Product::Kmz, &options, &state.backend));
+    if let Err(e) = &rendered {
+        record.fail(e);
+    }
+    state.metrics.record(&record);
+    let (bytes, warnings) = rendered?;
+    let content_type = match product {
+        Product::Kmz => "application/vnd.google-earth.kmz",
+        Product::Bands => "image/tiff",
+        _ => options.format.format.mime_type(),
+    };
+    let mut response = Response::new(200, content_type, bytes).with_header("X-Exam-Id", &id);
+    for warning in warnings {
+        response = response.with_header("X-Exam-Warning", &warning);
+    }
+    Ok(response)
+}
+
+/// `GET /stats/<id>`: size and elevation statistics of an uploaded DEM or a file of the --data folder as JSON
+fn serve_stats(state: &ServeState, id: &str) -> io::Result<Response> {
+    if let Some(stats) = state.stats.lock().unwrap_or_else(|e| e.into_inner()).get(id) {
+        return Ok(Response::json(200, stats));
+    }
+    let path = state.dem(id)?;
+    let options = parse_args(state.defaults.clone())?;
+    let stats = ElevationStats::of(&read_input(&path.to_string_lossy(), &options)?);
+    state.stats.lock().unwrap_or_else(|e| e.into_inner()).insert(id.to_string(), stats);
+    Ok(Response::json(200, &stats))
+}
+
+/// Answers one request to `exam serve` and counts it
+fn serve_request(state: &ServeState, request: Request) -> Response {
+    let (route, response) = match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/render") => ("/render", serve_render(state, &request).unwrap_or_else(error_response)),
+        ("GET", path) if path.starts_with("/stats/") => ("/stats", serve_stats(state, &path["/stats/".len()..]).unwrap_or_else(error_response)),
+        ("GET", "/metrics") => ("/metrics", Response::new(200, metrics::CONTENT_TYPE, state.metrics.render().into_bytes())),
+        ("GET", "/health") => ("/health", Response::text(200, "ok")),
+        (_, "/render" | "/metrics" | "/health") => ("other", Response::text(405, "Method not allowed")),
+        (_, path) if path.starts_with("/stats/") => ("other", Response::text(405, "Method not allowed")),
+        _ => ("other", Response::text(404, "No such endpoint, see POST /render, GET /stats/<id>, GET /metrics and GET /health")),
+    };
+    state.metrics.request(route, response.status);
+    response
+}
+
+/// `exam serve [--listen <host:port>] [--data <dir>] [--uploads <dir>] [--max-upload <size>] [--max-stored <size>] [--jobs <n>] [batch flags]`:
+/// renders DEMs on request over HTTP, with the batch flags as the defaults of every request
+fn serve_command(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: exam serve [--listen <host:port>] [--data <dir>] [--uploads <dir>] [--max-upload <size, e.g. 512M>] \
+        [--max-stored <size, e.g. 20G>] [--jobs <n>] [flags of the batch run]";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
+
+    let mut listen = String::from("127.0.0.1:8080");
+    let mut data_dir = None;
+    let mut upload_dir = env::temp_dir().join("exam-uploads");
+    let mut max_upload = 1 << 30;
+    let mut max_stored = DEFAULT_MAX_STORED;
+    let mut jobs = 4;
+    let mut defaults = Vec::new();
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        let mut value = || rest.next().cloned().ok_or_else(|| invalid(format!("Missing value for {}", arg)));
+        match arg.as_str() {
+            "--listen" => listen = value()?,
+            "--data" => data_dir = Some(PathBuf::from(value()?)),
+            "--uploads" => upload_dir = PathBuf::from(value()?),
+            "--max-upload" => max_upload = budget::parse_size(&value()?).map_err(invalid)?,
+            "--max-stored" => max_stored = budget::parse_size(&value()?).map_err(invalid)?,
+            "--jobs" | "-j" => jobs = value()?.parse().ok().filter(|&n: &usize| n > 0).ok_or_else(|| invalid("--jobs expects a number of requests above 0".into()))?,
+            _ => defaults.push(arg.clone()),
+        }
+    }
+    // The defaults must parse on their own, so a request only fails for its own flags
+    let options = parse_args(defaults.clone())?;
+    fs::create_dir_all(&upload_dir)?;
+
+    let state = Arc::new(ServeState {
+        defaults,
+        data_dir,
+        upload_dir,
+        max_stored,
+        trimming: Mutex::new(()),
+        backend: select_backend(options.gpu),
+        metrics: Metrics::new(),
+        stats: Mutex::new(HashMap::new()),
+        partial: AtomicUsize::new(0),
+    });
+    let server = {
+        let state = Arc::clone(&state);
+        http::listen(&listen, jobs, max_upload, move |request| serve_request(&state, request))?
+    };
+    println!("Serving on http://{}: POST /render, GET /stats/<id>, GET /metrics, GET /health", server.address);
+    server.wait();
+    Ok(())
+}
+
+/// Reprojects a grid into --t_srs when given, from --s_srs or the system detected next to `path`.
+/// Also returns the hillshade sun with its cell size scaled like the ground size of the cells,
+/// so a warp to a coarser --t_res does not exaggerate the relief.
+#[cfg(feature = "reproject")]
+fn warp_grid(grid: Grid, path: Option<&Path>, options: &Options) -> io::Result<(Grid, ShadeParams)> {
+    let shade = grid_shade(&grid, options);
+    let Some(t_srs) = &options.t_srs else {
+        return Ok((grid, shade));
     };
+    let src = source_crs(path, options)?;
+    let dst = Crs::parse(t_srs).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    warp_to(&grid, &src, &dst, options.t_res, options.resampling, shade)
+}
+
+/// Without the `reproject` feature parse_args rejects --t_srs, so grids pass through unchanged
+#[cfg(not(feature = "reproject"))]
+fn warp_grid(grid: Grid, _path: Option<&Path>, options: &Options) -> io::Result<(Grid, ShadeParams)> {
+    let shade = grid_shade(&grid, options);
+    Ok((grid, shade))
+}
 
-    let az_rad = az.to_radians();
-    let alt_rad = alt.to_radians();
-    let shade = 255.0 * ((alt_rad.sin() * slope.sin()) + (alt_rad.cos() * slope.cos() * (az_rad - aspect).cos()));
+/// The hillshade settings of the flags for `grid`, with the ground spacing in meters as the cell size if it is in degrees
+fn grid_shade(grid: &Grid, options: &Options) -> ShadeParams {
+    let mut shade = options.shade;
+    if grid.is_geographic() {
+        shade.cell_size = grid.ground_spacing();
+    }
+    shade
+}
+
+/// The grid in longitude/latitude for Google Earth overlays and terrain tiles, warped to WGS 84 unless it already is.
+/// A grid warped by --t_srs is in that system, anything else in the system of its input.
+#[cfg(feature = "reproject")]
+fn geographic_grid(grid: &Grid, path: Option<&Path>, options: &Options, shade: ShadeParams) -> io::Result<(Grid, ShadeParams)> {
+    if grid.is_geographic() {
+        return Ok((grid.clone(), shade));
+    }
+    let src = match &options.t_srs {
+        Some(t_srs) => Crs::parse(t_srs).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+        None => source_crs(path, options)?,
+    };
+    warp_to(grid, &src, &Crs::from_epsg(4326).unwrap(), None, options.resampling, shade)
+}
+
+/// Without the `reproject` feature only grids already in degrees can become overlays or terrain tiles
+#[cfg(not(feature = "reproject"))]
+fn geographic_grid(grid: &Grid, _path: Option<&Path>, _options: &Options, shade: ShadeParams) -> io::Result<(Grid, ShadeParams)> {
+    if !grid.is_geographic() {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "KMZ or terrain tiles of a projected grid need the `reproject` feature to warp it to WGS 84"));
+    }
+    Ok((grid.clone(), shade))
+}
+
+/// The grid in Web Mercator (EPSG:3857) for web map tiles, from --t_srs when given, otherwise the system of its input
+#[cfg(feature = "reproject")]
+fn web_mercator_grid(grid: &Grid, path: Option<&Path>, options: &Options, shade: ShadeParams) -> io::Result<(Grid, ShadeParams)> {
+    let src = match &options.t_srs {
+        Some(t_srs) => Crs::parse(t_srs).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+        None => source_crs(path, options)?,
+    };
+    warp_to(grid, &src, &Crs::from_epsg(3857).unwrap(), None, options.resampling, shade)
+}
+
+/// Without the `reproject` feature grids can't be warped to Web Mercator
+#[cfg(not(feature = "reproject"))]
+fn web_mercator_grid(_grid: &Grid, _path: Option<&Path>, _options: &Options, _shade: ShadeParams) -> io::Result<(Grid, ShadeParams)> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Web map tiles need the `reproject` feature to warp the grid to Web Mercator"))
+}
+
+/// System of an input: --s_srs, otherwise the .prj sidecar or GeoTIFF keys of `path`
+#[cfg(feature = "reproject")]
+fn source_crs(path: Option<&Path>, options: &Options) -> io::Result<Crs> {
+    let to_io = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    match (&options.s_srs, path) {
+        (Some(s_srs), _) => Crs::parse(s_srs).map_err(to_io),
+        (None, Some(path)) => crs::detect(path)?.ok_or_else(|| to_io(format!("No known coordinate system in a .prj or GeoTIFF keys of {:?}, pass --s_srs", path))),
+        (None, None) => Err(to_io("Reading stdin needs --s_srs".into())),
+    }
+}
+
+/// Warps `grid` and scales the cell size of `shade` by the change in ground size of the cells
+#[cfg(feature = "reproject")]
+fn warp_to(grid: &Grid, src: &Crs, dst: &Crs, cellsize: Option<f64>, method: Interpolation, shade: ShadeParams) -> io::Result<(Grid, ShadeParams)> {
+    let warped = crs::warp(grid, src, dst, cellsize, method).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let center = |g: &Grid| (g.xllcorner + g.ncols as f64 * g.cellsize / 2.0, g.yllcorner + g.nrows as f64 * g.cellsize / 2.0);
+    let ((sx, sy), (dx, dy)) = (center(grid), center(&warped));
+    let mut shade = shade;
+    if let (Some(before), Some(after)) = (src.ground_cell_size(sx, sy, grid.cellsize), dst.ground_cell_size(dx, dy, warped.cellsize)) {
+        shade.cell_size = (shade.cell_size.0 * after / before, shade.cell_size.1 * after / before);
+    }
+    Ok((warped, shade))
+}
+
+/// Renders `product` of the grid in longitude/latitude together with its bounds, for KMZ overlays
+fn overlay_image(grid: &Grid, product: &Product, path: Option<&Path>, options: &Options, shade: ShadeParams, backend: &Backend) -> io::Result<(DynamicImage, LatLonBox)> {
+    let (grid, shade) = geographic_grid(grid, path, options, shade)?;
+    let bounds = LatLonBox::of(&grid).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Warped grid is not in degrees"))?;
+    Ok((render_product(&grid, product, options, &shade, backend), bounds))
+}
+
+/// Writes a grid of values to `out`, `-` is .asc text on stdout, otherwise the extension picks .asc, .tif or .npy and
+/// an existing file is handled as `conflict` says. With `cog` a .tif is written as a Cloud-Optimized GeoTIFF.
+fn write_grid(grid: &Grid, out: &str, cog: bool, conflict: Conflict) -> io::Result<()> {
+    if out == "-" {
+        let mut stdout = io::stdout().lock();
+        write_asc(grid, &mut stdout)?;
+        return stdout.flush();
+    }
+    save_grid(grid, &conflict.claim(Path::new(out))?, cog)
+}
+
+/// Reads the x and y from the first two columns of a CSV file (comma, semicolon, tab or space separated).
+/// Lines not starting with two numbers, like a header, are skipped.
+fn read_points(path: &Path) -> io::Result<Vec<(f64, f64)>> {
+    let text = fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("{:?}: {}", path, e)))?;
+    Ok(text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split(|c: char| c == ',' || c == ';' || c.is_whitespace()).filter(|f| !f.is_empty());
+            Some((fields.next()?.parse().ok()?, fields.next()?.parse().ok()?))
+        })
+        .collect())
+}
+
+/// Name, x, y and height of an observer, None takes the --height of the viewshed
+type Site = (String, f64, f64, Option<f64>);
+
+/// Reads observers `x,y[,height[,name]]` from a CSV file like [`read_points`], numbered from 1 when unnamed
+fn read_observers(path: &Path) -> io::Result<Vec<Site>> {
+    let text = fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("{:?}: {}", path, e)))?;
+    let rows = text.lines().filter_map(|line| {
+        let mut fields = line.split([',', ';', '\t']).map(str::trim).filter(|f| !f.is_empty());
+        let (x, y) = (fields.next()?.parse().ok()?, fields.next()?.parse().ok()?);
+        let height = fields.next().map(str::parse::<f64>);
+        let name = fields.next().map(str::to_string);
+        Some((x, y, height, name))
+    });
+    rows.enumerate()
+        .map(|(i, (x, y, height, name))| {
+            let height = height.transpose()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}: invalid observer height on data row {}", path, i + 1)))?;
+            Ok((name.unwrap_or_else(|| (i + 1).to_string()), x, y, height))
+        })
+        .collect()
+}
+
+/// Reads a grid in any format recognized by its content, `-` reads stdin; point clouds and netCDF/HDF5 files with the
+/// default settings
+fn read_grid(input: &str) -> io::Result<Grid> {
+    read_grid_with(input, &LidarSettings::default(), None, None)
+}
+
+/// Like [`read_grid`], with point clouds gridded by the --resolution, --surface, --classes and --fill flags,
+/// netCDF/HDF5 files read from their --variable, only the --window when given, the elevations converted by
+/// --z-units and shifted by --z-offset, in degrees or not as --geographic or --projected say, and downsampled by --preview
+fn read_input(input: &str, options: &Options) -> io::Result<Grid> {
+    let mut grid = read_grid_with(input, &options.lidar, options.variable.as_deref(), options.window)?;
+    options.vertical.apply(&mut grid);
+    grid.geographic = options.geographic.or(grid.geographic);
+    Ok(match options.preview {
+        Some(preview) => preview.apply(grid),
+        None => grid,
+    })
+}
+
+fn read_grid_with(input: &str, lidar: &LidarSettings, variable: Option<&str>, window: Option<[usize; 4]>) -> io::Result<Grid> {
+    let outside = || io::Error::new(io::ErrorKind::InvalidInput, "The --window is empty or leaves the grid");
+    if input == "-" {
+        let mut bytes = Vec::new();
+        io::stdin().lock().read_to_end(&mut bytes)?;
+        let grid = geotiff::parse_grid_bytes(&bytes)?;
+        return match window {
+            Some([col, row, ncols, nrows]) => grid.window(col, row, ncols, nrows).ok_or_else(outside),
+            None => Ok(grid),
+        };
+    }
+    let path = Path::new(input);
+    let format = sniff::sniff_file(path).map_err(|e| io::Error::new(e.kind(), format!("{:?}: {}", input, e)))?;
+    match (window, format) {
+        (Some([col, row, ncols, nrows]), InputFormat::EsriAscii | InputFormat::GrassAscii | InputFormat::GeoTiff | InputFormat::E00) => {
+            grid::read_window(path, col, row, ncols, nrows)
+        }
+        (Some([col, row, ncols, nrows]), _) => load_file(path, format, lidar, variable)?.window(col, row, ncols, nrows).ok_or_else(outside),
+        (None, _) => load_file(path, format, lidar, variable),
+    }
+}
+
+/// Loads a file of a sniffed format
+fn load_file(path: &Path, format: InputFormat, lidar: &LidarSettings, variable: Option<&str>) -> io::Result<Grid> {
+    match format {
+        InputFormat::Las => load_point_cloud(path, lidar),
+        InputFormat::NetCdf | InputFormat::Hdf5 => load_netcdf(path, variable),
+        InputFormat::E00 => e00::load_e00(path),
+        InputFormat::GeoTiff => geotiff::load_geotiff(path),
+        InputFormat::EsriAscii | InputFormat::GrassAscii => load_asc_with_warnings(path).map(|(grid, _)| grid),
+    }
+}
+
+#[cfg(feature = "lidar")]
+fn load_point_cloud(path: &Path, settings: &LidarSettings) -> io::Result<Grid> {
+    exam::lidar::load_las(path, settings)
+}
+
+/// Without the `lidar` feature point clouds can't be read
+#[cfg(not(feature = "lidar"))]
+fn load_point_cloud(_path: &Path, _settings: &LidarSettings) -> io::Result<Grid> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "LAS/LAZ point clouds need the `lidar` feature"))
+}
+
+#[cfg(feature = "netcdf")]
+fn load_netcdf(path: &Path, variable: Option<&str>) -> io::Result<Grid> {
+    exam::nc::load_netcdf(path, variable)
+}
+
+/// Without the `netcdf` feature netCDF and HDF5 files can't be read
+#[cfg(not(feature = "netcdf"))]
+fn load_netcdf(_path: &Path, _variable: Option<&str>) -> io::Result<Grid> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "netCDF and HDF5 files need the `netcdf` feature"))
+}
+
+#[cfg(feature = "script")]
+fn save_styled(grid: &Grid, options: &Options, target: &OutputTarget) -> io::Result<Option<String>> {
+    let source = options.style_script.as_deref().expect("parse_args requires a --style-script for the styled product");
+    script::save_styled_image(grid, &StyleScript::compile(source)?, &options.format, target)
+}
+
+/// Without the `script` feature there is no styled product
+#[cfg(not(feature = "script"))]
+fn save_styled(_grid: &Grid, _options: &Options, _target: &OutputTarget) -> io::Result<Option<String>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "The styled product needs the `script` feature"))
+}
+
+/// The styled product in memory and the warning about cells its script failed on
+#[cfg(feature = "script")]
+fn render_styled(grid: &Grid, options: &Options, shade: &ShadeParams) -> (RgbaImage, Option<String>) {
+    let source = options.style_script.as_deref().expect("parse_args requires a --style-script for the styled product");
+    StyleScript::compile(source).expect("parse_args compiles the --style-script").render(grid, shade)
+}
+
+/// Without the `script` feature parse_args refuses --style-script, which the styled product needs
+#[cfg(not(feature = "script"))]
+fn render_styled(_grid: &Grid, _options: &Options, _shade: &ShadeParams) -> (RgbaImage, Option<String>) {
+    unreachable!("--style-script needs the `script` feature")
+}
+
+/// Counts the cells outside --elevation-range, None if there are none or no range is pinned
+fn out_of_range_warning(grid: &Grid, options: &Options) -> Option<String> {
+    let pinned = options.elevation_range.as_ref()?;
+    let (below, above) = render::out_of_range_cells(grid, (pinned.min, pinned.max));
+    (below + above > 0).then(|| format!("{} cells below and {} above the elevation range {}..{} are {}", below, above, pinned.min, pinned.max,
+        if pinned.mark_outside { "marked magenta" } else { "clamped to the end colors" }))
+}
+
+/// Renders the still image of a product in memory, tone adjustment included
+fn render_product(grid: &Grid, product: &Product, options: &Options, shade: &ShadeParams, backend: &Backend) -> DynamicImage {
+    let range = render::color_range(grid, options.elevation_range.as_ref());
+    let mark = |mut img| {
+        if let Some(pinned) = &options.elevation_range {
+            pinned.paint_outside(&mut img, grid);
+        }
+        DynamicImage::ImageRgb8(img)
+    };
+    let mut img = match product {
+        Product::Grayscale => DynamicImage::ImageLuma8(contrast::render_grayscale_stretched(grid, range, options.stretch)),
+        Product::Colored => mark(render::render_colored_range(grid, &options.colormap, range)),
+        // Composites have no image of their own, they are previewed by their hillshade
+        Product::Hillshade | Product::Kmz | Product::Bands => match &options.drape {
+            Some(drape) => {
+                let mut img = drape::render_draped(grid, range, &options.colormap, drape, &backend.hillshade_values(grid, shade));
+                render::apply_slope_saturation(&mut img, grid, shade);
+                render::apply_aerial_perspective(&mut img, grid, range, shade.aerial_perspective);
+                DynamicImage::ImageRgb8(img)
+            }
+            None => mark(backend.render_shaded(grid, range, &options.colormap, shade)),
+        },
+        Product::Shade => DynamicImage::ImageLuma8(GrayImage::from_raw(grid.ncols as u32, grid.nrows as u32, backend.hillshade_values(grid, shade)).expect("one value per cell")),
+        Product::Plugin(plugin) => plugin.render(&Inputs::new(grid, shade)),
+        Product::Anaglyph => {
+            let parallax = options.parallax.unwrap_or_else(|| anaglyph::default_parallax(grid.ncols));
+            DynamicImage::ImageRgb8(anaglyph::render_anaglyph(grid, range, &options.colormap, shade, parallax))
+        }
+        Product::Perspective => DynamicImage::ImageRgb8(perspective::render_perspective(grid, range, &options.colormap, shade, &options.perspective)),
+        Product::Qc => {
+            let report = qc::QcReport::check(grid, &options.qc);
+            for warning in report.summary() {
+                eprintln!("Warning: {}", warning);
+            }
+            return DynamicImage::ImageRgb8(report.overlay(grid)); // A diagnostic, not toned
+        }
+        Product::Charts => return DynamicImage::ImageRgb8(charts::render_charts(grid, shade.edges)),
+        Product::Styled => {
+            let (img, warning) = render_styled(grid, options, shade);
+            if let Some(warning) = warning {
+                eprintln!("Warning: {}", warning);
+            }
+            DynamicImage::ImageRgba8(img)
+        }
+    };
+    options.tone.apply(&mut img);
+    img
+}
+
+/// Turns the payload of a caught panic into an error carrying its message
+fn panic_error(payload: Box<dyn Any + Send>) -> io::Error {
+    let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown cause".into());
+    io::Error::new(io::ErrorKind::Other, format!("panicked: {}", message))
+}
+
+/// Uses the GPU for hillshades if requested and available, the CPU otherwise
+fn select_backend(gpu: bool) -> Backend {
+    if !gpu {
+        return Backend::Cpu;
+    }
+    #[cfg(feature = "gpu")]
+    match exam::gpu::GpuHillshader::new() {
+        Ok(gpu) => {
+            eprintln!("Hillshading on {}", gpu.adapter_name()); // stderr, stdout may carry an image
+            return Backend::Gpu(gpu);
+        }
+        Err(e) => eprintln!("No usable GPU ({}), hillshading on the CPU", e),
+    }
+    #[cfg(not(feature = "gpu"))]
+    eprintln!("Built without the `gpu` feature, hillshading on the CPU");
+    Backend::Cpu
+}
+
+/// Image (or set of images) written per input file
+#[derive(Clone, Debug)]
+enum Product {
+    Grayscale,
+    Colored,
+    Hillshade,
+    Shade, // The plain hillshade layer without the colors
+    Plugin(Arc<dyn plugin::Product>), // A product of the registry: a terrain derivative, ruggedness, ...
+    Anaglyph,
+    Perspective,
+    Kmz,
+    Bands,
+    Qc,
+    Charts, // Aspect rose and slope histogram
+    Styled, // Colored by the --style-script
+}
+
+impl Product {
+    /// Written when --products is not given
+    const DEFAULT: [Product; 3] = [Product::Grayscale, Product::Colored, Product::Hillshade];
+
+    fn parse(name: &str) -> Option<Product> {
+        match name.to_lowercase().as_str() {
+            "grayscale" => Some(Product::Grayscale),
+            "colored" => Some(Product::Colored),
+            "hillshade" | "hillside" => Some(Product::Hillshade),
+            "shade" => Some(Product::Shade),
+            "anaglyph" => Some(Product::Anaglyph),
+            "perspective" => Some(Product::Perspective),
+            "kmz" => Some(Product::Kmz),
+            "bands" => Some(Product::Bands),
+            "qc" => Some(Product::Qc),
+            "charts" => Some(Product::Charts),
+            "styled" => Some(Product::Styled),
+            other => registry().get(other).map(Product::Plugin),
+        }
+    }
+
+    /// Name of the product in the timings of the report
+    fn name(&self) -> &str {
+        match self {
+            Product::Grayscale => "grayscale",
+            Product::Colored => "colored",
+            Product::Hillshade => "hillshade",
+            Product::Shade => "shade",
+            Product::Plugin(plugin) => plugin.name(),
+            Product::Anaglyph => "anaglyph",
+            Product::Perspective => "perspective",
+            Product::Kmz => "kmz",
+            Product::Bands => "bands",
+            Product::Qc => "qc",
+            Product::Charts => "charts",
+            Product::Styled => "styled",
+        }
+    }
+}
+
+/// Products are told apart by name, so a plugin asked for twice is rendered once
+impl PartialEq for Product {
+    fn eq(&self, other: &Product) -> bool {
+        self.name() == other.name()
+    }
+}
+
+/// The products selectable by name besides the enum variants
+fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(Registry::builtin)
+}
+
+/// Optional modes selected on the command line
+struct Options {
+    input_dir: PathBuf,               // Directory scanned for .asc files (--input)
+    output_root: PathBuf,             // Root of all outputs (--output)
+    colormap: Gradient,               // Colors of the colored and hillshade products (--colormap)
+    colormap_name: String,            // Name of the colormap, recorded in the PNG metadata and sidecars
+    stretch: Stretch,                 // Contrast stretch of the grayscale product (--stretch)
+    tone: Tone,                       // Adjustment of every still image (--gamma, --brightness, --contrast)
+    simulate_cvd: Option<Cvd>,        // Color vision deficiency to preview the images with (--simulate-cvd)
+    bands: Vec<Band>,                 // Layers of the multi-band GeoTIFF product (--bands)
+    drape: Option<Drape>,             // Image shaded in place of the colormap in the hillshade product (--drape)
+    #[cfg_attr(not(feature = "script"), allow(dead_code))]
+    style_script: Option<String>,     // Source of the rhai script coloring the styled product (--style-script)
+    qc: QcSettings,                   // Thresholds of the quality-control product (--qc-sigma, --qc-void)
+    animation: Option<SweepSettings>, // Sun sweep GIF per file (--animate)
+    time_series: Vec<PathBuf>,        // Ordered DEM epochs to animate instead of the batch run (--time-series)
+    frame_ms: u32,                    // Display time of one animation frame
+    products: Vec<Product>,           // What to render per file, in order (--products)
+    parallax: Option<f32>,            // Maximum stereo offset in pixels, defaults to 2% of the width
+    perspective: PerspectiveSettings, // Camera of the perspective product
+    gpu: bool,                        // Compute hillshades, shadows and sky-view with wgpu, falls back to the CPU (--gpu)
+    format: OutputFormat,             // Encoding of the still images (--image-format, --quality, ...)
+    raw_formats: Vec<RawFormat>,      // Files holding the raw derivative values (--raw)
+    shade: ShadeParams,               // Sun and mode of the hillshade product (--azimuth, --altitude, --z-factor, --shading, --shader, --edges, ...)
+    out_pattern: String,              // Path of every output below the output root (--out-pattern)
+    resume: bool,                     // Skip the inputs the journal lists as finished (--resume)
+    retry_failed: bool,               // Only process the failures of the last report (--retry-failed)
+    cache: bool,                      // Read and write <name>.ascbin next to each input (--cache)
+    profiling: bool,                  // Write the time and heap peak of every stage to profile.json (--profiling)
+    conflict: Conflict,               // Handling of outputs that already exist (--overwrite, --skip-existing, --suffix-on-conflict)
+    dry_run: bool,                    // Print what the run would do instead of rendering (--dry-run)
+    filter: InputFilter,              // Input files selected by name, size and age (--include, --exclude, --min-size, --max-size, --newer-than)
+    duplicates: Option<DuplicateMode>, // Copies of earlier inputs reported or left out, not looked for if None (--find-duplicates, --skip-duplicates)
+    hooks: Hooks,                     // Commands and webhook run for every file and the batch (--on-success, --on-failure, --webhook)
+    metrics: Option<String>,          // Address the /metrics endpoint listens on while the run lasts (--metrics)
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    job_db: Option<PathBuf>,          // SQLite database of the inputs shared with other runs (--job-db)
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    job_lease: Option<Duration>,      // How long a claim in the job database holds (--job-lease)
+    walk: WalkOptions,                // Subfolders of the input folder searched for inputs (--recursive, --max-depth, --follow-symlinks, --hidden)
+    jobs: usize,                      // Files rendered at once (--jobs)
+    max_memory: Option<u64>,          // Bytes the files rendered at once may hold together, unlimited if None (--max-memory)
+    lidar: LidarSettings,             // Gridding of LAS/LAZ inputs (--resolution, --surface, --classes, --fill)
+    variable: Option<String>,         // Elevation variable of netCDF/HDF5 inputs, guessed if None (--variable)
+    window: Option<[usize; 4]>,       // Column, row, columns and rows of the only part of every input read (--window)
+    #[cfg_attr(not(feature = "reproject"), allow(dead_code))]
+    s_srs: Option<String>,            // Coordinate system of the inputs, detected from .prj or GeoTIFF keys if None (--s_srs)
+    t_srs: Option<String>,            // Coordinate system to warp every grid into before rendering (--t_srs)
+    #[cfg_attr(not(feature = "reproject"), allow(dead_code))]
+    t_res: Option<f64>,               // Cell size after the warp, keeps the source resolution if None (--t_res)
+    #[cfg_attr(not(feature = "reproject"), allow(dead_code))]
+    resampling: Interpolation,        // Sampling of the source grid during the warp (--resampling)
+    kmz_tile: Option<u32>,            // Split KMZ overlays into region-loaded tiles of at most this size (--kmz-tile)
+    previews: Previews,               // Thumbnails and overviews of the still images (--thumbnail, --overviews)
+    sidecars: bool,                   // JSON description next to every still image (--sidecar)
+    spot_heights: Option<usize>,      // Mark the highest and lowest cell and this many prominent peaks (--spot-heights, --peaks)
+    vertical: Vertical,               // Unit conversion and offset of the elevations on loading (--z-units, --z-offset)
+    geographic: Option<bool>,         // Whether the cells are in degrees, guessed from the header if None (--geographic, --projected)
+    preview: Option<Preview>,         // Coarser copy of every grid rendered instead of it (--preview, --preview-sampling)
+    elevation_range: Option<ElevationRange>, // Elevations pinned to the ends of the colors (--elevation-range, --out-of-range)
+    nodata_style: NodataStyles,       // Look of the NoData cells per product (--nodata-style)
+}
+
+/// Reads the command-line flags, preceded by the flags of the --profile from the config file
+fn parse_args(args: Vec<String>) -> io::Result<Options> {
+    let usage = "Usage: exam [--config <exam.toml>] [--profile <name>] [--input <dir>] [--output <dir>] [--colormap <name>] [--stretch linear|equalize|clahe] [--gamma <g>] [--brightness <-1 to 1>] [--contrast <factor>] [--simulate-cvd protanopia|deuteranopia|tritanopia|achromatopsia] [--products grayscale,colored,hillshade,shade,slope,aspect,curvature,flow,twi,spi,horizon[:azimuth],ruggedness,terraces[:levels],tanaka[:interval],charts,anaglyph,perspective,kmz,bands,qc,styled] [--qc] [--qc-sigma <sigmas>] [--qc-void <cells>] [--bands elevation,hillshade,slope,aspect,curvature,flow,twi,spi,horizon[:azimuth],mask] [--drape <image>] [--style-script <style.rhai>] [--animate azimuth|altitude] [--step <degrees>] [--frame-ms <ms>] [--time-series <a.asc> <b.asc> ...] [--anaglyph] [--parallax <pixels>] \
+        [--perspective] [--camera <col,row,height>] [--target <col,row>] [--fov <degrees>] [--exaggeration <factor>] [--view-size <WxH>] [--flyover <frames>] [--gpu] \
+        [--image-format png|tiff|jpeg|webp|avif] [--quality <1-100>] [--png-compression fast|default|best] [--png-palette off|auto|1|2|4|8] [--tiff-compression none|lzw|deflate|packbits] [--cog] \
+        [--derive slope,aspect,curvature,flow,twi,spi,horizon[:azimuth]|all] [--raw tiff,npy|none] \
+        [--azimuth <degrees>] [--altitude <degrees>] [--fill-azimuth <degrees>] [--fill-altitude <degrees>] [--fill-weight <0-1>] [--z-factor <factor>] [--z-units <from>:<to>] [--z-offset <elevation>] [--geographic|--projected] [--shading standard|multiscale[:<levels>]] [--aerial-perspective <0-1>] [--slope-saturation <0-1>] [--shader horn|zevenbergen-thorne|multidirectional|sky-view|shadows] [--edges replicate|mirror|nodata] [--out-pattern <pattern>] [--resume] [--retry-failed] [--cache] [--profiling] [--dry-run] [--include <glob>] [--exclude <glob>] [--min-size <size>] [--max-size <size>] [--newer-than <YYYY-MM-DD[THH:MM:SS]|7d|12h>] [--recursive|--max-depth <n>] [--follow-symlinks] [--hidden] [--find-duplicates|--skip-duplicates] [--on-success <command>] [--on-failure <command>] [--webhook <url>] [--metrics <host:port>] [--job-db <jobs.sqlite>] [--job-lease <seconds>] [--overwrite|--skip-existing|--suffix-on-conflict] [--jobs <n>] [--max-memory <size, e.g. 4G>] \
+        [--resolution <cell size>] [--surface dtm|dsm] [--classes <2,9|all>] [--fill <cells>] [--variable <name>] [--window <col,row,ncols,nrows>] [--preview <n>] [--preview-sampling average|decimate] \
+        [--t_srs <EPSG:code|proj string>] [--s_srs <EPSG:code|proj string>] [--t_res <cell size>] [--resampling nearest|bilinear] [--kmz] [--kmz-tile <pixels>] [--thumbnail <pixels>] [--overviews 2,4,8] [--sidecar] [--spot-heights] [--peaks <n>] \
+        [--elevation-range <min,max>] [--out-of-range clamp|mark] [--nodata-style <black|transparent|checkerboard|infill|#rrggbb>[,<product>=<style>...]]";
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, usage));
+
+    let mut axis = None;
+    let mut step = 10.0;
+    let mut frame_ms = 100;
+    let mut time_series = Vec::new();
+    let mut parallax = None;
+    let mut camera = None;
+    let mut target = None;
+    let mut fov = 60.0;
+    let mut exaggeration = 1.5;
+    let mut view_size = (800, 450);
+    let mut flyover_frames = 0;
+    let mut gpu = false;
+    let mut format = OutputFormat::default();
+    let mut products = None;
+    let mut extra_products = Vec::new(); // Added by --derive, --anaglyph, --perspective, --kmz, --bands and --qc on top of the selection
+    let mut raw_formats = vec![RawFormat::Tiff, RawFormat::Npy];
+    let mut shade = ShadeParams::default();
+    let (mut fill_azimuth, mut fill_altitude, mut fill_weight) = (None, None, None); // --fill-*, a fill light if any is given
+    let mut out_pattern = OutputTarget::DEFAULT_PATTERN.to_string();
+    let mut input_dir = PathBuf::from("./dataset");
+    let mut output_root = PathBuf::from("./output");
+    let mut colormap = colorgrad::viridis();
+    let mut colormap_name = String::from("viridis");
+    let mut stretch = Stretch::default();
+    let mut tone = Tone::default();
+    let mut simulate_cvd = None;
+    let mut bands = Band::DEFAULT.to_vec();
+    let mut drape = None;
+    let mut style_script = None;
+    let mut qc = QcSettings::default();
+    let mut resume = false;
+    let mut retry_failed = false;
+    let mut cache = false;
+    let mut geographic = None;
+    let mut profiling = false;
+    let mut conflict = Conflict::default();
+    let mut dry_run = false;
+    let mut filter = InputFilter::default();
+    let mut duplicates = None;
+    let mut hooks = Hooks::default();
+    let mut metrics = None;
+    let mut job_db = None;
+    let mut job_lease = None;
+    let mut walk = WalkOptions::default();
+    let mut jobs = 1;
+    let mut max_memory = None;
+    let mut lidar = LidarSettings::default();
+    let mut variable = None;
+    let mut window = None;
+    let mut s_srs = None;
+    let mut t_srs = None;
+    let mut t_res = None;
+    let mut resampling = Interpolation::default();
+    let mut kmz_tile = None;
+    let mut previews = Previews::default();
+    let mut sidecars = false;
+    let mut spot_heights = None;
+    let mut vertical = Vertical::default();
+    let mut preview = None;
+    let mut preview_sampling = Downsample::default();
+    let mut elevation_range = None;
+    let mut mark_outside = None;
+    let mut nodata_style = NodataStyles::default();
+
+    // The profile comes first so flags given on the command line override it
+    let mut cli = Vec::new();
+    let mut config = PathBuf::from(DEFAULT_CONFIG);
+    let mut profile_name = None;
+    let mut raw_args = args.into_iter();
+    while let Some(arg) = raw_args.next() {
+        match arg.as_str() {
+            "--config" => config = PathBuf::from(raw_args.next().ok_or_else(|| invalid("Missing value for --config".into()))?),
+            "--profile" => profile_name = Some(raw_args.next().ok_or_else(|| invalid("Missing value for --profile".into()))?),
+            _ => cli.push(arg),
+        }
+    }
+    let mut all_args = match &profile_name {
+        Some(name) => profile::profile_args(&config, name)?,
+        None => Vec::new(),
+    };
+    all_args.extend(cli);
+
+    let mut args = all_args.into_iter().peekable();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| invalid(format!("Missing value for {}", arg)));
+        match arg.as_str() {
+            "--animate" => {
+                let name = value()?;
+                axis = Some(SweepAxis::parse(&name).ok_or_else(|| invalid(format!("Unknown sweep axis {:?}", name)))?);
+            }
+            "--step" => step = value()?.parse().map_err(|_| invalid("--step expects a number of degrees".into()))?,
+            "--frame-ms" => frame_ms = value()?.parse().map_err(|_| invalid("--frame-ms expects milliseconds".into()))?,
+            "--time-series" => {
+                // Every following argument up to the next flag is one epoch, in chronological order
+                while let Some(path) = args.next_if(|a| !a.starts_with("--")) {
+                    time_series.push(PathBuf::from(path));
+                }
+                if time_series.len() < 2 {
+                    return Err(invalid("--time-series expects at least two .asc files".into()));
+                }
+            }
+            "--products" => {
+                let list = value()?;
+                products = Some(list.split(',').map(|name| Product::parse(name.trim()).ok_or_else(|| invalid(format!("Unknown product {:?}", name))))
+                    .collect::<io::Result<Vec<_>>>()?);
+            }
+            "--anaglyph" => extra_products.push(Product::Anaglyph),
+            "--parallax" => parallax = Some(value()?.parse().map_err(|_| invalid("--parallax expects a number of pixels".into()))?),
+            "--perspective" => extra_products.push(Product::Perspective),
+            "--camera" => match parse_numbers(&value()?)[..] {
+                [col, row, height] => camera = Some((col, row, height)),
+                _ => return Err(invalid("--camera expects <col,row,height>".into())),
+            },
+            "--target" => match parse_numbers(&value()?)[..] {
+                [col, row] => target = Some((col, row)),
+                _ => return Err(invalid("--target expects <col,row>".into())),
+            },
+            "--fov" => fov = value()?.parse().map_err(|_| invalid("--fov expects degrees".into()))?,
+            "--exaggeration" => exaggeration = value()?.parse().map_err(|_| invalid("--exaggeration expects a factor".into()))?,
+            "--view-size" => {
+                let size = value()?;
+                view_size = size.split_once('x').and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                    .ok_or_else(|| invalid("--view-size expects <width>x<height>".into()))?;
+            }
+            "--flyover" => {
+                extra_products.push(Product::Perspective);
+                flyover_frames = value()?.parse().map_err(|_| invalid("--flyover expects a number of frames".into()))?;
+            }
+            "--gpu" => gpu = true,
+            "--image-format" => {
+                let name = value()?;
+                format.format = ImageFormat::parse(&name).ok_or_else(|| invalid(format!("Unknown image format {:?}", name)))?;
+                if !format.format.is_available() {
+                    return Err(invalid(format!("{} output needs the `{}` feature", name, format.format.extension())));
+                }
+            }
+            "--quality" => match value()?.parse() {
+                Ok(q @ 1..=100) => format.quality = q,
+                _ => return Err(invalid("--quality expects a number from 1 to 100".into())),
+            },
+            "--png-compression" => {
+                let name = value()?;
+                format.png_compression = PngCompression::parse(&name).ok_or_else(|| invalid(format!("Unknown PNG compression {:?}", name)))?;
+            }
+            "--png-palette" => {
+                let name = value()?;
+                format.png_palette = PngPalette::parse(&name).ok_or_else(|| invalid(format!("--png-palette expects off, auto or a bit depth of 1, 2, 4 or 8, got {:?}", name)))?;
+            }
+            "--tiff-compression" => {
+                let name = value()?;
+                format.tiff_compression = TiffCompression::parse(&name).ok_or_else(|| invalid(format!("Unknown TIFF compression {:?}", name)))?;
+            }
+            "--cog" => format.cog = true,
+            "--derive" => {
+                let list = value()?;
+                let derivatives = if list == "all" {
+                    Derivative::ALL.to_vec()
+                } else {
+                    list.split(',').map(|name| Derivative::parse(name.trim()).ok_or_else(|| invalid(format!("Unknown derivative {:?}", name))))
+                        .collect::<io::Result<_>>()?
+                };
+                extra_products.extend(derivatives.into_iter().map(|derivative| Product::Plugin(Arc::new(derivative))));
+            }
+            "--raw" => {
+                let list = value()?;
+                raw_formats = if list == "none" {
+                    Vec::new()
+                } else {
+                    list.split(',').map(|name| RawFormat::parse(name.trim()).ok_or_else(|| invalid(format!("Unknown raw format {:?}", name))))
+                        .collect::<io::Result<_>>()?
+                };
+            }
+            "--azimuth" => shade.azimuth = value()?.parse().map_err(|_| invalid("--azimuth expects degrees".into()))?,
+            "--altitude" => shade.altitude = value()?.parse().map_err(|_| invalid("--altitude expects degrees".into()))?,
+            "--fill-azimuth" => fill_azimuth = Some(value()?.parse().map_err(|_| invalid("--fill-azimuth expects degrees".into()))?),
+            "--fill-altitude" => fill_altitude = Some(value()?.parse().map_err(|_| invalid("--fill-altitude expects degrees".into()))?),
+            "--fill-weight" => match value()?.parse() {
+                Ok(weight @ 0.0..=1.0) => fill_weight = Some(weight),
+                _ => return Err(invalid("--fill-weight expects a share from 0 to 1".into())),
+            },
+            "--z-factor" => shade.z_factor = value()?.parse().map_err(|_| invalid("--z-factor expects a factor".into()))?,
+            "--z-units" => {
+                let units = value()?;
+                (vertical.from, vertical.to) = Vertical::parse_units(&units)
+                    .ok_or_else(|| invalid(format!("Unknown --z-units {:?}, expected <from>:<to> of m, ft and us-ft, e.g. ft:m", units)))?;
+            }
+            "--preview" => preview = Some(value()?.parse().ok().filter(|&n: &usize| n >= 2)
+                .ok_or_else(|| invalid("--preview expects a number of cells per side of a block, at least 2".into()))?),
+            "--preview-sampling" => {
+                let name = value()?;
+                preview_sampling = Downsample::parse(&name).ok_or_else(|| invalid(format!("Unknown --preview-sampling {:?}, expected average or decimate", name)))?;
+            }
+            "--z-offset" => vertical.offset = value()?.parse().ok().filter(|v: &f64| v.is_finite())
+                .ok_or_else(|| invalid("--z-offset expects an elevation difference".into()))?,
+            "--aerial-perspective" => match value()?.parse() {
+                Ok(strength @ 0.0..=1.0) => shade.aerial_perspective = strength,
+                _ => return Err(invalid("--aerial-perspective expects a strength from 0 to 1".into())),
+            },
+            "--slope-saturation" => match value()?.parse() {
+                Ok(strength @ 0.0..=1.0) => shade.slope_saturation = strength,
+                _ => return Err(invalid("--slope-saturation expects a strength from 0 to 1".into())),
+            },
+            "--shading" => {
+                let name = value()?;
+                shade.mode = ShadeMode::parse(&name).ok_or_else(|| invalid(format!("Unknown shading {:?}, expected standard or multiscale[:<levels 1-8>]", name)))?;
+            }
+            "--shader" => {
+                let name = value()?;
+                shade.shader = ShaderKind::parse(&name)
+                    .ok_or_else(|| invalid(format!("Unknown shader {:?}, expected horn, zevenbergen-thorne, multidirectional, sky-view or shadows", name)))?;
+            }
+            "--edges" => {
+                let name = value()?;
+                shade.edges = EdgeMode::parse(&name).ok_or_else(|| invalid(format!("Unknown edge mode {:?}, expected replicate, mirror or nodata", name)))?;
+            }
+            "--out-pattern" => {
+                out_pattern = value()?;
+                OutputTarget::validate_pattern(&out_pattern).map_err(invalid)?;
+            }
+            "--input" => input_dir = PathBuf::from(value()?),
+            "--output" => output_root = PathBuf::from(value()?),
+            "--colormap" => {
+                let name = value()?;
+                colormap = render::colormap(&name).ok_or_else(|| invalid(format!("Unknown colormap {:?}, expected one of {:?}", name, COLORMAPS)))?;
+                colormap_name = name.to_lowercase();
+            }
+            "--stretch" => {
+                let name = value()?;
+                stretch = Stretch::parse(&name).ok_or_else(|| invalid(format!("Unknown stretch {:?}, expected linear, equalize or clahe", name)))?;
+            }
+            "--gamma" => match value()?.parse() {
+                Ok(gamma) if gamma > 0.0 => tone.gamma = gamma,
+                _ => return Err(invalid("--gamma expects a positive number".into())),
+            },
+            "--brightness" => match value()?.parse() {
+                Ok(brightness @ -1.0..=1.0) => tone.brightness = brightness,
+                _ => return Err(invalid("--brightness expects a number from -1 to 1".into())),
+            },
+            "--contrast" => match value()?.parse() {
+                Ok(contrast) if contrast >= 0.0 => tone.contrast = contrast,
+                _ => return Err(invalid("--contrast expects a factor of at least 0".into())),
+            },
+            "--simulate-cvd" => {
+                let name = value()?;
+                simulate_cvd = Some(Cvd::parse(&name).ok_or_else(|| invalid(format!("Unknown color vision deficiency {:?}", name)))?);
+            }
+            "--bands" => {
+                let list = value()?;
+                bands = list.split(',').map(|name| Band::parse(name.trim()).ok_or_else(|| invalid(format!("Unknown band {:?}", name))))
+                    .collect::<io::Result<_>>()?;
+                if bands.is_empty() || bands.len() > MAX_BANDS {
+                    return Err(invalid(format!("--bands expects 1 to {} bands", MAX_BANDS)));
+                }
+                extra_products.push(Product::Bands);
+            }
+            "--drape" => drape = Some(Drape::load(Path::new(&value()?))?),
+            "--qc" => extra_products.push(Product::Qc),
+            "--style-script" => {
+                let path = PathBuf::from(value()?);
+                style_script = Some(fs::read_to_string(&path).map_err(|e| io::Error::new(e.kind(), format!("{:?}: {}", path, e)))?);
+                extra_products.push(Product::Styled);
+            }
+            "--qc-sigma" => match value()?.parse() {
+                Ok(sigma) if sigma > 0.0 => qc.sigma = sigma,
+                _ => return Err(invalid("--qc-sigma expects a positive number of sigmas".into())),
+            },
+            "--qc-void" => qc.min_void_cells = value()?.parse().map_err(|_| invalid("--qc-void expects a number of cells".into()))?,
+            "--resume" => resume = true,
+            "--retry-failed" => retry_failed = true,
+            "--cache" => cache = true,
+            "--geographic" => geographic = Some(true),
+            "--projected" => geographic = Some(false),
+            "--profiling" => profiling = true,
+            "--dry-run" => dry_run = true,
+            "--include" => filter.include.push(value()?),
+            "--exclude" => filter.exclude.push(value()?),
+            "--min-size" => filter.min_size = Some(budget::parse_size(&value()?).map_err(invalid)?),
+            "--max-size" => filter.max_size = Some(budget::parse_size(&value()?).map_err(invalid)?),
+            "--newer-than" => filter.newer_than = Some(filter::parse_time(&value()?).map_err(invalid)?),
+            "--recursive" | "-r" => walk.max_depth = usize::MAX,
+            "--max-depth" => walk.max_depth = value()?.parse().map_err(|_| invalid("--max-depth expects a number of folder levels".into()))?,
+            "--follow-symlinks" => walk.follow_symlinks = true,
+            "--find-duplicates" => duplicates = Some(DuplicateMode::Report),
+            "--skip-duplicates" => duplicates = Some(DuplicateMode::Skip),
+            "--on-success" => hooks.on_success = Some(value()?),
+            "--on-failure" => hooks.on_failure = Some(value()?),
+            "--webhook" => {
+                let url = value()?;
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    return Err(invalid(format!("--webhook expects an http:// or https:// URL, not {:?}", url)));
+                }
+                hooks.webhook = Some(url);
+            }
+            "--metrics" => metrics = Some(value()?),
+            "--job-db" => job_db = Some(PathBuf::from(value()?)),
+            "--job-lease" => job_lease = Some(value()?.parse().ok().filter(|&s: &f64| s > 0.0).map(Duration::from_secs_f64)
+                .ok_or_else(|| invalid("--job-lease expects a number of seconds above 0".into()))?),
+            "--hidden" => walk.hidden = true,
+            "--overwrite" => conflict = Conflict::Overwrite,
+            "--skip-existing" => conflict = Conflict::Skip,
+            "--suffix-on-conflict" => conflict = Conflict::Suffix,
+            "--jobs" | "-j" => jobs = value()?.parse().ok().filter(|&n: &usize| n > 0).ok_or_else(|| invalid("--jobs expects a number of files above 0".into()))?,
+            "--max-memory" => max_memory = Some(budget::parse_size(&value()?).map_err(invalid)?),
+            "--resolution" => match value()?.parse() {
+                Ok(resolution) if resolution > 0.0 => lidar.resolution = Some(resolution),
+                _ => return Err(invalid("--resolution expects a cell size above 0".into())),
+            },
+            "--surface" => {
+                let name = value()?;
+                lidar.surface = Surface::parse(&name).ok_or_else(|| invalid(format!("Unknown surface {:?}, expected dtm or dsm", name)))?;
+            }
+            "--classes" => {
+                let list = value()?;
+                lidar.classes = Some(match list.as_str() {
+                    "all" => (0..=u8::MAX).collect(),
+                    _ => list.split(',').map(|c| c.trim().parse().ok()).collect::<Option<_>>()
+                        .ok_or_else(|| invalid(format!("--classes expects class numbers like 2,9 or all, got {:?}", list)))?,
+                });
+            }
+            "--variable" => variable = Some(value()?),
+            "--window" => {
+                let list = value()?;
+                window = match list.split(',').map(|v| v.trim().parse().ok()).collect::<Option<Vec<usize>>>().as_deref() {
+                    Some(&[col, row, ncols, nrows]) if ncols > 0 && nrows > 0 => Some([col, row, ncols, nrows]),
+                    _ => return Err(invalid(format!("--window expects <col,row,ncols,nrows> with at least one column and row, got {:?}", list))),
+                };
+            }
+            "--fill" => lidar.fill = value()?.parse().map_err(|_| invalid("--fill expects a number of cells".into()))?,
+            "--s_srs" => s_srs = Some(value()?),
+            "--t_srs" => t_srs = Some(value()?),
+            "--t_res" => t_res = Some(value()?.parse().map_err(|_| invalid("--t_res expects a cell size".into()))?),
+            "--resampling" => {
+                let name = value()?;
+                resampling = Interpolation::parse(&name).ok_or_else(|| invalid(format!("Unknown resampling {:?}", name)))?;
+            }
+            "--kmz" => extra_products.push(Product::Kmz),
+            "--kmz-tile" => kmz_tile = Some(value()?.parse().map_err(|_| invalid("--kmz-tile expects a number of pixels".into()))?),
+            "--sidecar" => sidecars = true,
+            "--spot-heights" => spot_heights = Some(spot_heights.unwrap_or(0)),
+            "--peaks" => spot_heights = Some(value()?.parse().map_err(|_| invalid("--peaks expects a number of peaks".into()))?),
+            "--thumbnail" => previews.thumbnail = Some(value()?.parse().map_err(|_| invalid("--thumbnail expects a size in pixels".into()))?),
+            "--overviews" => {
+                let list = value()?;
+                previews.overviews = list.split(',').map(|f| f.trim().parse().ok().filter(|&f: &u32| f >= 2))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| invalid(format!("--overviews expects reduction factors of at least 2 like 2,4,8, got {:?}", list)))?;
+            }
+            "--elevation-range" => elevation_range = Some(ElevationRange::parse(&value()?).map_err(invalid)?),
+            "--nodata-style" => nodata_style = NodataStyles::parse(&value()?).map_err(invalid)?,
+            "--out-of-range" => match value()?.as_str() {
+                "clamp" => mark_outside = Some(false),
+                "mark" => mark_outside = Some(true),
+                other => return Err(invalid(format!("Unknown out-of-range handling {:?}, expected clamp or mark", other))),
+            },
+            _ => return Err(invalid(format!("Unknown argument {:?}", arg))),
+        }
+    }
+
+    // Systems are checked up front so a typo fails before the first file instead of in every one
+    #[cfg(feature = "reproject")]
+    for srs in s_srs.iter().chain(&t_srs) {
+        Crs::parse(srs).map_err(invalid)?;
+    }
+    #[cfg(not(feature = "reproject"))]
+    if let Some(srs) = s_srs.iter().chain(&t_srs).next() {
+        return Err(invalid(format!("Reprojecting to {:?} needs the `reproject` feature", srs)));
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    if job_db.is_some() {
+        return Err(invalid("--job-db needs the `sqlite` feature".into()));
+    }
+    #[cfg(not(feature = "script"))]
+    if style_script.is_some() {
+        return Err(invalid("--style-script needs the `script` feature".into()));
+    }
+    // Compiled up front so a syntax error fails before the first file
+    #[cfg(feature = "script")]
+    if let Some(source) = &style_script {
+        StyleScript::compile(source).map_err(|e| invalid(e.to_string()))?;
+    }
+
+    if let Some(mark_outside) = mark_outside {
+        let range: &mut ElevationRange = elevation_range.as_mut().ok_or_else(|| invalid("--out-of-range needs an --elevation-range".into()))?;
+        range.mark_outside = mark_outside;
+    }
+    if simulate_cvd.is_some() && !CVD_SAFE_COLORMAPS.contains(&colormap_name.as_str()) {
+        eprintln!("Note: {} is not one of the CVD-safe colormaps {:?}", colormap_name, CVD_SAFE_COLORMAPS);
+    }
+    if fill_azimuth.is_some() || fill_altitude.is_some() || fill_weight.is_some() {
+        let opposite = FillLight::opposite(&shade);
+        shade.fill = Some(FillLight {
+            azimuth: fill_azimuth.unwrap_or(opposite.azimuth),
+            altitude: fill_altitude.unwrap_or(opposite.altitude),
+            weight: fill_weight.unwrap_or(opposite.weight),
+        });
+    }
+    if target.is_some() && camera.is_none() {
+        return Err(invalid("--target needs a --camera position".into()));
+    }
+    let mut products = products.unwrap_or_else(|| Product::DEFAULT.to_vec());
+    for product in extra_products {
+        if !products.contains(&product) {
+            products.push(product);
+        }
+    }
+    if products.contains(&Product::Styled) && style_script.is_none() {
+        return Err(invalid("The styled product needs a --style-script".into()));
+    }
+
+    let perspective = PerspectiveSettings {
+        camera: camera.map(|position| Camera {
+            position,
+            target: target.unwrap_or((position.0, position.1 - 1.0)), // Look north by default
+        }),
+        fov,
+        exaggeration,
+        width: view_size.0,
+        height: view_size.1,
+        flyover_frames,
+        frame_ms,
+    };
+
+    Ok(Options {
+        input_dir,
+        output_root,
+        colormap,
+        colormap_name,
+        stretch,
+        tone,
+        simulate_cvd,
+        bands,
+        drape,
+        style_script,
+        qc,
+        animation: axis.map(|axis| SweepSettings { axis, step, frame_ms }),
+        time_series,
+        frame_ms,
+        products,
+        parallax,
+        perspective,
+        gpu,
+        format,
+        raw_formats,
+        shade,
+        out_pattern,
+        resume,
+        retry_failed,
+        cache,
+        geographic,
+        profiling,
+        conflict,
+        dry_run,
+        filter,
+        duplicates,
+        hooks,
+        metrics,
+        job_db,
+        job_lease,
+        walk,
+        jobs,
+        max_memory,
+        lidar,
+        variable,
+        window,
+        s_srs,
+        t_srs,
+        t_res,
+        resampling,
+        kmz_tile,
+        previews,
+        sidecars,
+        spot_heights,
+        vertical,
+        preview: preview.map(|factor| Preview { factor, sampling: preview_sampling }),
+        elevation_range,
+        nodata_style,
+    })
+}
 
-    shade.clamp(0.0, 255.0) as u8
+/// Splits a comma separated list like "12.5,40" into numbers, an unparsable entry yields an empty list
+fn parse_numbers(list: &str) -> Vec<f64> {
+    list.split(',').map(|v| v.trim().parse()).collect::<Result<_, _>>().unwrap_or_default()
 }
@@ -0,0 +1,100 @@
+//! Inventory of the input folder (`exam manifest`): format, file size, grid size, cell size, bounds and NoData of every
+//! input from its header, written as `manifest.json` and `manifest.csv` into the output root without rendering anything.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::grid::read_info;
+use crate::sniff::sniff_file;
+
+/// File names of the manifest below the output root
+pub const MANIFEST_FILE: &str = "manifest.json";
+pub const MANIFEST_CSV: &str = "manifest.csv";
+
+/// One input file
+#[derive(Debug, Serialize)]
+pub struct ManifestEntry {
+    pub input: PathBuf,                // Below the input folder
+    pub format: Option<&'static str>,  // See [`crate::sniff::InputFormat::key`], None if not recognized
+    pub file_bytes: u64,
+    pub ncols: Option<usize>,          // Grid size and georeference, None for formats without a header holding them
+    pub nrows: Option<usize>,
+    pub cellsize: Option<f64>,
+    pub bounds: Option<[f64; 4]>,      // West, south, east and north edge
+    pub nodata: Option<f32>,
+    pub error: Option<String>,         // Why the file or its header can't be read
+}
+
+impl ManifestEntry {
+    /// Peeks at the header of the file at `path`, listed as `relative`
+    pub fn read(path: &Path, relative: &Path) -> ManifestEntry {
+        let mut entry = ManifestEntry {
+            input: relative.to_path_buf(), format: None, file_bytes: fs::metadata(path).map_or(0, |m| m.len()),
+            ncols: None, nrows: None, cellsize: None, bounds: None, nodata: None, error: None,
+        };
+        match sniff_file(path) {
+            Ok(format) => {
+                entry.format = Some(format.key());
+                match read_info(path) {
+                    Ok(info) => {
+                        entry.ncols = Some(info.ncols);
+                        entry.nrows = Some(info.nrows);
+                        entry.cellsize = Some(info.cellsize);
+                        entry.bounds = Some(info.bounds());
+                        entry.nodata = Some(info.nodata);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Unsupported => {}
+                    Err(e) => entry.error = Some(e.to_string()),
+                }
+            }
+            Err(e) => entry.error = Some(e.to_string()),
+        }
+        entry
+    }
+}
+
+/// Inventory of an input folder
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    pub created: u64, // Unix time in seconds
+    pub input_dir: PathBuf,
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn new(input_dir: &Path) -> Manifest {
+        let created = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        Manifest { created, input_dir: input_dir.to_path_buf(), entries: Vec::new() }
+    }
+
+    /// Writes [`MANIFEST_FILE`] and [`MANIFEST_CSV`] into `dir`
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(dir.join(MANIFEST_FILE))?);
+        serde_json::to_writer_pretty(writer, self).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+        let mut csv = BufWriter::new(File::create(dir.join(MANIFEST_CSV))?);
+        writeln!(csv, "input,format,file_bytes,ncols,nrows,cellsize,west,south,east,north,nodata,error")?;
+        for entry in &self.entries {
+            let field = |value: Option<String>| value.unwrap_or_default();
+            let bounds = entry.bounds.map_or([String::new(), String::new(), String::new(), String::new()], |b| b.map(|v| v.to_string()));
+            writeln!(csv, "{},{},{},{},{},{},{},{},{},{},{},{}",
+                quote(&entry.input.to_string_lossy().replace('\\', "/")), field(entry.format.map(String::from)), entry.file_bytes,
+                field(entry.ncols.map(|v| v.to_string())), field(entry.nrows.map(|v| v.to_string())), field(entry.cellsize.map(|v| v.to_string())),
+                bounds[0], bounds[1], bounds[2], bounds[3], field(entry.nodata.map(|v| v.to_string())), quote(entry.error.as_deref().unwrap_or("")))?;
+        }
+        csv.flush()
+    }
+}
+
+/// A CSV field, in quotes if it holds a comma, quote or line break
+pub fn quote(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
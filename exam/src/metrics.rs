@@ -0,0 +1,161 @@
+//! Counters and render latencies of a running batch or server in the Prometheus text format, served on `/metrics`
+//! (`--metrics`), so operators scrape and alert on a long run like on any other service.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::http::{self, Request, Response};
+use crate::report::{InputReport, Status};
+
+/// Upper bounds in seconds of the latency buckets, from a small tile to a large file
+pub const LATENCY_BUCKETS: [f64; 12] = [0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Content type of the text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Latencies of one step, counted per bucket
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()], // Observations up to each bound, not cumulated
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.buckets[i] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+/// Metrics of the run, updated by its workers and read by the `/metrics` endpoint
+pub struct Metrics {
+    started: f64,                                   // Unix time in seconds
+    inputs: AtomicU64,                              // Inputs found for the batch
+    processed: AtomicU64,                           // Files rendered completely
+    failed: AtomicU64,                              // Files with an error
+    skipped: AtomicU64,                             // Files finished earlier or by another run
+    tiles_served: AtomicU64,                        // Tiles answered by the server
+    requests: Mutex<BTreeMap<(String, u16), u64>>,  // Requests answered by the server per route and status
+    latencies: Mutex<BTreeMap<String, Histogram>>,  // Seconds per step, "file" for whole files and "tile" for tiles
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            started: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64()),
+            inputs: AtomicU64::new(0),
+            processed: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+            tiles_served: AtomicU64::new(0),
+            requests: Mutex::new(BTreeMap::new()),
+            latencies: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn observe(&self, step: &str, seconds: f64) {
+        let mut latencies = self.latencies.lock().unwrap_or_else(|e| e.into_inner());
+        latencies.entry(step.to_string()).or_default().observe(seconds);
+    }
+
+    pub fn add_inputs(&self, count: usize) {
+        self.inputs.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_skipped(&self, count: usize) {
+        self.skipped.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    /// Counts a finished file and the time of each of its steps
+    pub fn record(&self, record: &InputReport) {
+        match record.status {
+            Status::Ok => self.processed.fetch_add(1, Ordering::Relaxed),
+            Status::Failed => self.failed.fetch_add(1, Ordering::Relaxed),
+        };
+        for (step, &seconds) in &record.timings {
+            self.observe(step, seconds);
+        }
+        self.observe("file", record.timings.values().fold(0.0, |sum, s| sum + s));
+    }
+
+    /// Counts a tile answered in `seconds`
+    pub fn tile_served(&self, seconds: f64) {
+        self.tiles_served.fetch_add(1, Ordering::Relaxed);
+        self.observe("tile", seconds);
+    }
+
+    /// Counts a request to `route` answered with `status`
+    pub fn request(&self, route: &str, status: u16) {
+        let mut requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        *requests.entry((route.to_string(), status)).or_default() += 1;
+    }
+
+    /// All metrics in the Prometheus text exposition format 0.0.4
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: f64| {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}");
+        };
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed) as f64;
+        metric("exam_start_time_seconds", "gauge", "Unix time the run started.", self.started);
+        metric("exam_inputs", "gauge", "Inputs found for the batch.", load(&self.inputs));
+        metric("exam_files_processed_total", "counter", "Files rendered completely.", load(&self.processed));
+        metric("exam_files_failed_total", "counter", "Files that failed.", load(&self.failed));
+        metric("exam_files_skipped_total", "counter", "Files finished by an earlier or another run.", load(&self.skipped));
+        metric("exam_tiles_served_total", "counter", "Tiles answered by the server.", load(&self.tiles_served));
+
+        let _ = writeln!(out, "# HELP exam_http_requests_total Requests answered by the server per route and status.");
+        let _ = writeln!(out, "# TYPE exam_http_requests_total counter");
+        for ((route, status), count) in self.requests.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+            let _ = writeln!(out, "exam_http_requests_total{{route=\"{}\",code=\"{}\"}} {}", escape(route), status, count);
+        }
+
+        let _ = writeln!(out, "# HELP exam_render_seconds Seconds per step, of whole files (step=\"file\") and of tiles (step=\"tile\").");
+        let _ = writeln!(out, "# TYPE exam_render_seconds histogram");
+        let latencies = self.latencies.lock().unwrap_or_else(|e| e.into_inner());
+        for (step, histogram) in latencies.iter() {
+            let step = escape(step);
+            let mut cumulated = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                cumulated += count;
+                let _ = writeln!(out, "exam_render_seconds_bucket{{step=\"{}\",le=\"{}\"}} {}", step, bound, cumulated);
+            }
+            let _ = writeln!(out, "exam_render_seconds_bucket{{step=\"{}\",le=\"+Inf\"}} {}", step, histogram.count);
+            let _ = writeln!(out, "exam_render_seconds_sum{{step=\"{}\"}} {}", step, histogram.sum);
+            let _ = writeln!(out, "exam_render_seconds_count{{step=\"{}\"}} {}", step, histogram.count);
+        }
+        out
+    }
+
+    /// Serves `/metrics` on `address` from a background thread for as long as the process runs, returning the
+    /// address bound, which tells the port when `address` asks for port 0
+    pub fn serve(self: &Arc<Self>, address: &str) -> io::Result<SocketAddr> {
+        let metrics = Arc::clone(self);
+        let server = http::listen(address, 1, 0, move |request: Request| match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/metrics") => Response::new(200, CONTENT_TYPE, metrics.render().into_bytes()),
+            ("GET", _) => Response::text(404, "Metrics are at /metrics"),
+            _ => Response::text(405, "Only GET is supported"),
+        })?;
+        Ok(server.address)
+    }
+}
+
+/// Label value with backslashes and quotes escaped
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
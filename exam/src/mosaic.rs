@@ -0,0 +1,81 @@
+//! Mosaicking overlapping grids of one coordinate system into a single grid. Later grids lie on top,
+//! and their values fade in over a band along their edges so neither the elevation nor the shading shows a seam.
+
+use crate::grid::{Grid, Interpolation};
+
+/// Combines `grids` into one covering all of them, with the finest input cell size unless `cellsize` is given.
+/// Every later grid is blended over the earlier ones with a weight rising from 0 at its edge (border or NoData)
+/// to 1 at `feather` output cells inside; 0 gives plain last-one-wins. Cells no grid covers become NoData.
+pub fn mosaic(grids: &[Grid], cellsize: Option<f64>, feather: f64, method: Interpolation) -> Result<Grid, String> {
+    let first = grids.first().ok_or("Nothing to mosaic")?;
+    let cellsize = cellsize.unwrap_or_else(|| grids.iter().map(|g| g.cellsize).fold(f64::MAX, f64::min));
+    if !cellsize.is_finite() || cellsize <= 0.0 {
+        return Err(format!("Invalid cell size {}", cellsize));
+    }
+    let left = grids.iter().map(|g| g.xllcorner).fold(f64::MAX, f64::min);
+    let bottom = grids.iter().map(|g| g.yllcorner).fold(f64::MAX, f64::min);
+    let right = grids.iter().map(|g| g.xllcorner + g.ncols as f64 * g.cellsize).fold(f64::MIN, f64::max);
+    let top = grids.iter().map(|g| g.origin().1).fold(f64::MIN, f64::max);
+    let ncols = ((right - left) / cellsize - 1e-9).ceil().max(1.0) as usize;
+    let nrows = ((top - bottom) / cellsize - 1e-9).ceil().max(1.0) as usize;
+    let input_cells: usize = grids.iter().map(|g| g.ncols * g.nrows).sum();
+    if ncols * nrows > 16 * input_cells.max(1) {
+        return Err(format!("Mosaic of {}x{} cells is far larger than the inputs, are they in the same coordinate system?", ncols, nrows));
+    }
+
+    let nodata = first.nodata;
+    let mut data = vec![vec![nodata; ncols]; nrows];
+    let width = feather * cellsize; // Feather band in map units
+    for grid in grids {
+        let distances = edge_distances(grid);
+        let (gx, gy) = grid.origin();
+        for (row, values) in data.iter_mut().enumerate() {
+            let y = top - (row as f64 + 0.5) * cellsize;
+            for (col, value) in values.iter_mut().enumerate() {
+                let x = left + (col as f64 + 0.5) * cellsize;
+                let Some(v) = grid.sample(x, y, method) else { continue };
+                let weight = if width > 0.0 && !first.is_nodata(*value) {
+                    let (c, r) = (((x - gx) / grid.cellsize) as usize, ((gy - y) / grid.cellsize) as usize);
+                    (distances[r * grid.ncols + c] * grid.cellsize / width).min(1.0) as f32
+                } else {
+                    1.0
+                };
+                *value = if weight >= 1.0 { v } else { *value + (v - *value) * weight };
+            }
+        }
+    }
+    Ok(Grid { data, ncols, nrows, nodata, cellsize, xllcorner: left, yllcorner: top - nrows as f64 * cellsize, geographic: None })
+}
+
+/// Distance in cells from every cell center to the nearest NoData cell or the outside of the grid,
+/// 0.5 on the outermost valid cells, by a two-pass chamfer transform with diagonal steps of √2
+fn edge_distances(grid: &Grid) -> Vec<f64> {
+    const DIAGONAL: f64 = std::f64::consts::SQRT_2;
+    let (w, h) = (grid.ncols, grid.nrows);
+    let mut d: Vec<f64> = grid.data.iter().flatten()
+        .map(|&v| if grid.is_nodata(v) { 0.0 } else { f64::MAX })
+        .collect();
+    // Cells outside the grid count as NoData half a cell beyond the border
+    let at = |d: &[f64], x: isize, y: isize| {
+        if x < 0 || y < 0 || x >= w as isize || y >= h as isize { -0.5 } else { d[y as usize * w + x as usize] }
+    };
+    for y in 0..h as isize {
+        for x in 0..w as isize {
+            let i = y as usize * w + x as usize;
+            let best = [(-1, 0, 1.0), (0, -1, 1.0), (-1, -1, DIAGONAL), (1, -1, DIAGONAL)].iter()
+                .map(|&(dx, dy, step)| at(&d, x + dx, y + dy) + step)
+                .fold(d[i], f64::min);
+            d[i] = best;
+        }
+    }
+    for y in (0..h as isize).rev() {
+        for x in (0..w as isize).rev() {
+            let i = y as usize * w + x as usize;
+            let best = [(1, 0, 1.0), (0, 1, 1.0), (1, 1, DIAGONAL), (-1, 1, DIAGONAL)].iter()
+                .map(|&(dx, dy, step)| at(&d, x + dx, y + dy) + step)
+                .fold(d[i], f64::min);
+            d[i] = best;
+        }
+    }
+    d
+}
@@ -0,0 +1,143 @@
+//! Elevation variables of netCDF files (e.g. GEBCO bathymetry) and HDF5 files, read through libnetcdf with the
+//! `netcdf` feature. The last two dimensions of the variable are the rows and columns, the first slice of any leading
+//! dimension (time, band) is taken; the coordinate variables of the two dimensions give the cell size and origin.
+
+#[cfg(feature = "netcdf")]
+use std::io;
+#[cfg(feature = "netcdf")]
+use std::path::Path;
+
+use crate::grid::Grid;
+
+/// NoData of the grid, as in an .asc file without a nodata_value line
+const NODATA: f32 = -99999.0;
+
+/// Names tried in order when no variable is given, before any other variable of at least two dimensions
+pub const ELEVATION_NAMES: [&str; 8] = ["elevation", "z", "Band1", "height", "altitude", "topo", "bathymetry", "dem"];
+
+/// Packing and missing values of a variable, from its CF attributes
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Encoding {
+    pub fill_value: Option<f64>,     // `_FillValue`, or `missing_value` when there is none
+    pub scale_factor: Option<f64>,   // `scale_factor` of packed values
+    pub add_offset: Option<f64>,     // `add_offset` of packed values
+    pub valid_range: Option<(f64, f64)>, // `valid_min`/`valid_max` or `valid_range`, values outside are NoData
+}
+
+impl Encoding {
+    /// Elevation of a stored value, None for fill values and values outside the valid range
+    fn unpack(&self, value: f64) -> Option<f64> {
+        if value.is_nan() || self.fill_value == Some(value) || self.valid_range.is_some_and(|(lo, hi)| value < lo || value > hi) {
+            return None;
+        }
+        Some(value * self.scale_factor.unwrap_or(1.0) + self.add_offset.unwrap_or(0.0))
+    }
+}
+
+/// Builds a grid from the row-major `values` of a 2-D slice with cell center coordinates `x` along the columns and `y`
+/// along the rows, which may run either way; rows are flipped so the north comes first
+pub fn grid_from_array(values: &[f64], x: &[f64], y: &[f64], encoding: &Encoding) -> Result<Grid, String> {
+    let (ncols, nrows) = (x.len(), y.len());
+    if ncols == 0 || nrows == 0 || values.len() < ncols * nrows {
+        return Err(format!("{} values can't fill {}x{} cells", values.len(), ncols, nrows));
+    }
+    let spacing = |c: &[f64]| if c.len() > 1 { (c[c.len() - 1] - c[0]) / (c.len() - 1) as f64 } else { 1.0 };
+    let (dx, dy) = (spacing(x), spacing(y));
+    if dx == 0.0 || dy == 0.0 || dx.is_nan() || dy.is_nan() {
+        return Err("The coordinates don't advance along the dimensions".into());
+    }
+    // One cell size for both axes, like the .asc header
+    if ((dx.abs() - dy.abs()) / dx.abs()).abs() > 1e-3 {
+        return Err(format!("Cells of {} x {} are not square", dx.abs(), dy.abs()));
+    }
+
+    let data = (0..nrows).map(|r| {
+        let source_row = if dy > 0.0 { nrows - 1 - r } else { r }; // Northernmost row first
+        (0..ncols).map(|c| {
+            let source_col = if dx > 0.0 { c } else { ncols - 1 - c }; // Westernmost column first
+            encoding.unpack(values[source_row * ncols + source_col]).map_or(NODATA, |v| v as f32)
+        }).collect()
+    }).collect();
+
+    let cellsize = dx.abs();
+    let (min_x, min_y) = (x[0].min(x[ncols - 1]), y[0].min(y[nrows - 1]));
+    Ok(Grid { data, ncols, nrows, nodata: NODATA, cellsize, xllcorner: min_x - cellsize / 2.0, yllcorner: min_y - cellsize / 2.0, geographic: None })
+}
+
+/// Reads `variable` of a netCDF or HDF5 file, or the first variable named like an elevation, otherwise the first
+/// one of at least two dimensions
+#[cfg(feature = "netcdf")]
+pub fn load_netcdf(path: &Path, variable: Option<&str>) -> io::Result<Grid> {
+    let error = |e: String| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}: {}", path, e));
+    let file = netcdf::open(path).map_err(|e| error(e.to_string()))?;
+
+    let var = match variable {
+        Some(name) => file.variable(name).ok_or_else(|| error(format!("No variable {:?}", name)))?,
+        None => {
+            let is_grid = |v: &netcdf::Variable| v.dimensions().len() >= 2;
+            ELEVATION_NAMES.iter().filter_map(|name| file.variable(name)).find(is_grid)
+                .or_else(|| file.variables().find(is_grid))
+                .ok_or_else(|| error("No variable of at least two dimensions, pick one with --variable".into()))?
+        }
+    };
+    let dims = var.dimensions();
+    if dims.len() < 2 {
+        return Err(error(format!("{:?} has {} dimension(s), a grid needs two", var.name(), dims.len())));
+    }
+    let (rows, cols) = (&dims[dims.len() - 2], &dims[dims.len() - 1]);
+
+    // Coordinates along a dimension from its coordinate variable, indices when it has none like a GeoTIFF without tags
+    let coordinates = |dim: &netcdf::Dimension| -> io::Result<Vec<f64>> {
+        match file.variable(&dim.name()) {
+            Some(coords) if coords.dimensions().len() == 1 => coords.get_values::<f64, _>(..).map_err(|e| error(e.to_string())),
+            _ => Ok((0..dim.len()).map(|i| i as f64).collect()),
+        }
+    };
+    let (x, y) = (coordinates(cols)?, coordinates(rows)?);
+
+    let number = |name: &str| var.attribute(name).and_then(|a| a.value().ok()).and_then(|v| attribute_number(&v, 0));
+    let valid_range = var.attribute("valid_range").and_then(|a| a.value().ok())
+        .and_then(|v| Some((attribute_number(&v, 0)?, attribute_number(&v, 1)?)))
+        .or_else(|| Some((number("valid_min").unwrap_or(f64::MIN), number("valid_max").unwrap_or(f64::MAX))))
+        .filter(|&range| range != (f64::MIN, f64::MAX));
+    let encoding = Encoding {
+        fill_value: number("_FillValue").or_else(|| number("missing_value")),
+        scale_factor: number("scale_factor"),
+        add_offset: number("add_offset"),
+        valid_range,
+    };
+
+    // Index 0 of every leading dimension, so only the first 2-D slice is read and not every time step or band
+    let extents: Vec<netcdf::Extent> = dims[..dims.len() - 2].iter().map(|_| 0.into()).chain([(..).into(), (..).into()]).collect();
+    let values = var.get_values::<f64, _>(extents).map_err(|e| error(e.to_string()))?;
+    grid_from_array(&values, &x, &y, &encoding).map_err(error)
+}
+
+/// Element `index` of a numeric attribute
+#[cfg(feature = "netcdf")]
+fn attribute_number(value: &netcdf::AttributeValue, index: usize) -> Option<f64> {
+    use netcdf::AttributeValue::*;
+    match value {
+        Uchar(v) if index == 0 => Some(*v as f64),
+        Schar(v) if index == 0 => Some(*v as f64),
+        Ushort(v) if index == 0 => Some(*v as f64),
+        Short(v) if index == 0 => Some(*v as f64),
+        Uint(v) if index == 0 => Some(*v as f64),
+        Int(v) if index == 0 => Some(*v as f64),
+        Ulonglong(v) if index == 0 => Some(*v as f64),
+        Longlong(v) if index == 0 => Some(*v as f64),
+        Float(v) if index == 0 => Some(*v as f64),
+        Double(v) if index == 0 => Some(*v),
+        Uchars(v) => v.get(index).map(|&v| v as f64),
+        Schars(v) => v.get(index).map(|&v| v as f64),
+        Ushorts(v) => v.get(index).map(|&v| v as f64),
+        Shorts(v) => v.get(index).map(|&v| v as f64),
+        Uints(v) => v.get(index).map(|&v| v as f64),
+        Ints(v) => v.get(index).map(|&v| v as f64),
+        Ulonglongs(v) => v.get(index).map(|&v| v as f64),
+        Longlongs(v) => v.get(index).map(|&v| v as f64),
+        Floats(v) => v.get(index).map(|&v| v as f64),
+        Doubles(v) => v.get(index).copied(),
+        _ => None,
+    }
+}
@@ -0,0 +1,1041 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Cursor, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{imageops, DynamicImage, ImageEncoder};
+use tiff::encoder::{colortype, compression, DirectoryEncoder, TiffEncoder, TiffKind};
+use tiff::tags::Tag;
+
+use crate::contrast::Stretch;
+use crate::cvd::Cvd;
+use crate::grid::Grid;
+use crate::grid::Mask;
+use crate::grid::Preview;
+use crate::profiling;
+use crate::render::{ElevationRange, NodataStyle, NodataStyles, ShadeParams, Tone, OUT_OF_RANGE_COLOR};
+use crate::spot::SpotHeights;
+use crate::vertical::Vertical;
+
+/// Container format of the written images
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Tiff,
+    Jpeg,
+    WebP,
+    Avif,
+}
+
+impl ImageFormat {
+    pub fn parse(name: &str) -> Option<ImageFormat> {
+        match name.to_lowercase().as_str() {
+            "png" => Some(ImageFormat::Png),
+            "tif" | "tiff" => Some(ImageFormat::Tiff),
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg),
+            "webp" => Some(ImageFormat::WebP),
+            "avif" => Some(ImageFormat::Avif),
+            _ => None,
+        }
+    }
+
+    /// Whether this build can write the format, AVIF needs the `avif` feature
+    pub fn is_available(&self) -> bool {
+        *self != ImageFormat::Avif || cfg!(feature = "avif")
+    }
+
+    /// File extension without the dot
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Tiff => "tif",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::WebP => "webp",
+            ImageFormat::Avif => "avif",
+        }
+    }
+
+    /// Media type of an encoded image, as sent by `exam serve`
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Tiff => "image/tiff",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::WebP => "image/webp",
+            ImageFormat::Avif => "image/avif",
+        }
+    }
+}
+
+/// zlib effort of the PNG encoder
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PngCompression {
+    Fast,
+    Default,
+    Best,
+}
+
+impl PngCompression {
+    pub fn parse(name: &str) -> Option<PngCompression> {
+        match name.to_lowercase().as_str() {
+            "fast" => Some(PngCompression::Fast),
+            "default" => Some(PngCompression::Default),
+            "best" => Some(PngCompression::Best),
+            _ => None,
+        }
+    }
+}
+
+/// Whether PNGs of few colors are written palette-indexed, a byte or less per pixel instead of three or four
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PngPalette {
+    #[default]
+    Off,
+    Auto,      // The smallest bit depth the colors fit in
+    Depth(u8), // 1, 2, 4 or 8 bits per pixel, for readers that only take some of them
+}
+
+impl PngPalette {
+    /// Parses "off", "auto" or a bit depth of 1, 2, 4 or 8
+    pub fn parse(name: &str) -> Option<PngPalette> {
+        match name.to_lowercase().as_str() {
+            "off" | "none" => Some(PngPalette::Off),
+            "auto" => Some(PngPalette::Auto),
+            depth => depth.parse().ok().filter(|d| matches!(d, 1 | 2 | 4 | 8)).map(PngPalette::Depth),
+        }
+    }
+}
+
+/// Lossless compression of the TIFF strips
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TiffCompression {
+    None,
+    Lzw,
+    Deflate,
+    PackBits,
+}
+
+impl TiffCompression {
+    pub fn parse(name: &str) -> Option<TiffCompression> {
+        match name.to_lowercase().as_str() {
+            "none" => Some(TiffCompression::None),
+            "lzw" => Some(TiffCompression::Lzw),
+            "deflate" => Some(TiffCompression::Deflate),
+            "packbits" => Some(TiffCompression::PackBits),
+            _ => None,
+        }
+    }
+}
+
+/// Format and encoder settings for every still image written by the savers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputFormat {
+    pub format: ImageFormat,
+    pub quality: u8,                       // 1-100 for JPEG, WebP and AVIF
+    pub png_compression: PngCompression,
+    pub png_palette: PngPalette,           // Indexed PNGs for images of up to 256 colors (--png-palette)
+    pub tiff_compression: TiffCompression,
+    pub cog: bool,                         // TIFFs as Cloud-Optimized GeoTIFFs, tiled with overviews (--cog)
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat {
+            format: ImageFormat::Png,
+            quality: 90,
+            png_compression: PngCompression::Fast,
+            png_palette: PngPalette::Off,
+            tiff_compression: TiffCompression::Lzw,
+            cog: false,
+        }
+    }
+}
+
+/// Placeholders accepted in an output pattern
+pub const PATTERN_PLACEHOLDERS: &[&str] = &["stem", "product", "ext", "azimuth", "altitude", "z_factor"];
+
+/// Where the files rendered from one input go: `pattern` below `root` with the placeholders filled in
+#[derive(Clone, Debug)]
+pub struct OutputTarget {
+    pub root: PathBuf,
+    pub pattern: String,     // e.g. "{stem}/{product}_{azimuth}az.png", see [`PATTERN_PLACEHOLDERS`]
+    pub stem: String,        // Input file name without extension
+    pub shade: ShadeParams,  // Sun of the hillshade, for {azimuth}, {altitude} and {z_factor}
+    pub elevation_range: Option<ElevationRange>, // Colors pinned to fixed elevations, each grid's min and max if None
+    pub tone: Tone,          // Gamma, brightness and contrast of every still image
+    pub simulate_cvd: Option<Cvd>, // Also write "<product>_<deficiency>" as seen with this color vision deficiency
+    pub previews: Previews,  // Downsampled copies written next to every still image
+    pub provenance: Option<Provenance>, // How the images were produced, for PNG text chunks and sidecars
+    pub sidecars: bool,      // Write `<image>.json` next to every still image
+    pub nodata_style: NodataStyles, // Look of the NoData cells per product
+    pub nodata_mask: Option<Mask>,  // Valid cells of the grid being rendered, None leaves NoData as rendered
+    pub spot_heights: Option<SpotHeights>, // Marked on every still image of the grid (--spot-heights, --peaks)
+    pub conflict: Conflict,  // What happens to output files that already exist
+    pub written: RefCell<Vec<PathBuf>>, // Every path handed out by `path`, in order, for the run report
+}
+
+/// What happens to an output file that already exists (--overwrite, --skip-existing, --suffix-on-conflict)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Conflict {
+    #[default]
+    Overwrite, // Replace it
+    Skip,      // Keep it: `path` fails with ErrorKind::AlreadyExists before anything is written
+    Suffix,    // Keep it and write "<name>_1.<ext>", "<name>_2.<ext>", ... next to it
+}
+
+impl Conflict {
+    /// The policy named by `--overwrite`, `--skip-existing` or `--suffix-on-conflict`
+    pub fn from_flag(flag: &str) -> Option<Conflict> {
+        match flag {
+            "--overwrite" => Some(Conflict::Overwrite),
+            "--skip-existing" => Some(Conflict::Skip),
+            "--suffix-on-conflict" => Some(Conflict::Suffix),
+            _ => None,
+        }
+    }
+
+    /// Opens `path` for writing, returning the file and the path it was created at. Skip and Suffix create the file
+    /// exclusively, so a file that appears in the meantime is never replaced and two writers never share a path.
+    pub fn create(self, path: &Path) -> io::Result<(File, PathBuf)> {
+        let create_new = |path: &Path| OpenOptions::new().write(true).create_new(true).open(path);
+        match self {
+            Conflict::Overwrite => Ok((File::create(path)?, path.to_path_buf())),
+            Conflict::Skip => match create_new(path) {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{:?} exists, kept by --skip-existing", path)))
+                }
+                file => Ok((file?, path.to_path_buf())),
+            },
+            Conflict::Suffix => {
+                let mut candidate = path.to_path_buf();
+                for n in 1.. {
+                    match create_new(&candidate) {
+                        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => candidate = suffixed(path, n),
+                        file => return Ok((file?, candidate)),
+                    }
+                }
+                unreachable!("a free suffix")
+            }
+        }
+    }
+
+    /// The path [`Conflict::create`] would write to, for writers that open the file themselves. Skip and Suffix
+    /// claim it by creating it empty.
+    pub fn claim(self, path: &Path) -> io::Result<PathBuf> {
+        match self {
+            Conflict::Overwrite => Ok(path.to_path_buf()),
+            Conflict::Skip | Conflict::Suffix => self.create(path).map(|(_, path)| path),
+        }
+    }
+}
+
+/// Downsampled copies of the still images, for galleries and fast previews in external viewers
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Previews {
+    pub thumbnail: Option<u32>, // Longest side of "<product>_thumb", None writes no thumbnail
+    pub overviews: Vec<u32>,    // Reduction factors of the "<product>_ovr<factor>" pyramid levels, e.g. [2, 4, 8]
+}
+
+impl Previews {
+    /// The downsampled images of `img` with their product names
+    pub fn render(&self, img: &DynamicImage, name: &str) -> Vec<(String, DynamicImage)> {
+        let (width, height) = (img.width(), img.height());
+        let mut previews = Vec::new();
+        if let Some(size) = self.thumbnail.filter(|&size| size < width.max(height)) {
+            let scale = size as f64 / width.max(height) as f64;
+            let (w, h) = (((width as f64 * scale).round() as u32).max(1), ((height as f64 * scale).round() as u32).max(1));
+            previews.push((format!("{}_thumb", name), img.resize_exact(w, h, imageops::FilterType::Triangle)));
+        }
+        for &factor in self.overviews.iter().filter(|&&f| f > 1) {
+            let (w, h) = (width.div_ceil(factor), height.div_ceil(factor));
+            previews.push((format!("{}_ovr{}", name, factor), img.resize_exact(w, h, imageops::FilterType::Triangle)));
+        }
+        previews
+    }
+}
+
+impl OutputTarget {
+    /// The classic layout: output/<stem>/<product>.<ext>
+    pub const DEFAULT_PATTERN: &'static str = "{stem}/{product}.{ext}";
+
+    /// Writes every file directly into `dir` as <product>.<ext>
+    pub fn dir(dir: &Path) -> OutputTarget {
+        OutputTarget {
+            root: dir.to_path_buf(),
+            pattern: "{product}.{ext}".into(),
+            stem: String::new(),
+            shade: ShadeParams::default(),
+            elevation_range: None,
+            tone: Tone::default(),
+            simulate_cvd: None,
+            previews: Previews::default(),
+            provenance: None,
+            sidecars: false,
+            nodata_style: NodataStyles::default(),
+            nodata_mask: None,
+            spot_heights: None,
+            conflict: Conflict::default(),
+            written: RefCell::default(),
+        }
+    }
+
+    /// Checks that a pattern only uses known placeholders and contains {product}, so products don't overwrite each other
+    pub fn validate_pattern(pattern: &str) -> Result<(), String> {
+        let mut rest = pattern;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..].find('}').ok_or_else(|| format!("Unclosed '{{' in {:?}", pattern))? + start;
+            let name = &rest[start + 1..end];
+            if !PATTERN_PLACEHOLDERS.contains(&name) {
+                return Err(format!("Unknown placeholder {{{}}} in {:?}, expected one of {:?}", name, pattern, PATTERN_PLACEHOLDERS));
+            }
+            rest = &rest[end + 1..];
+        }
+        if !pattern.contains("{product}") {
+            return Err(format!("{:?} needs a {{product}} placeholder", pattern));
+        }
+        Ok(())
+    }
+
+    /// Path of one output file, creating missing directories.
+    /// Without {ext} in the pattern, its literal extension (or none) is replaced by `ext`,
+    /// so "hillside.png" becomes "hillside.jpg" for JPEG output and "flyover.gif" for animations.
+    /// An existing file is handled as [`OutputTarget::conflict`] says.
+    pub fn path(&self, product: &str, ext: &str) -> io::Result<PathBuf> {
+        let pattern = if self.pattern.contains("{ext}") {
+            self.pattern.clone()
+        } else {
+            match self.pattern.rsplit_once('.') {
+                Some((base, suffix)) if !suffix.is_empty() && suffix.chars().all(|c| c.is_ascii_alphanumeric()) => format!("{}.{{ext}}", base),
+                _ => format!("{}.{{ext}}", self.pattern),
+            }
+        };
+
+        let relative = pattern
+            .replace("{stem}", &self.stem)
+            .replace("{product}", product)
+            .replace("{ext}", ext)
+            .replace("{azimuth}", &self.shade.azimuth.to_string())
+            .replace("{altitude}", &self.shade.altitude.to_string())
+            .replace("{z_factor}", &self.shade.z_factor.to_string());
+        let path = self.root.join(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let path = self.conflict.claim(&path)?;
+        self.written.borrow_mut().push(path.clone());
+        Ok(path)
+    }
+
+    /// Whether the `name` product can be written row by row with [`save_png_rows`]: a PNG without previews, color-blind
+    /// simulation, spot heights or a NoData style that needs the whole image
+    pub fn streams(&self, name: &str, format: &OutputFormat) -> bool {
+        format.format == ImageFormat::Png
+            && self.previews.thumbnail.is_none()
+            && self.previews.overviews.is_empty()
+            && self.simulate_cvd.is_none()
+            && self.spot_heights.is_none()
+            && matches!(self.nodata_style.of(name), NodataStyle::Black | NodataStyle::Color(_) | NodataStyle::Checkerboard)
+    }
+}
+
+/// `path` with "_<n>" added to the file name before the extension
+fn suffixed(path: &Path, n: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match path.extension() {
+        Some(ext) => path.with_file_name(format!("{}_{}.{}", stem, n, ext.to_string_lossy())),
+        None => path.with_file_name(format!("{}_{}", stem, n)),
+    }
+}
+
+/// How the images of an input were produced, stored in PNG text chunks and JSON sidecars
+#[derive(Clone, Debug, Serialize)]
+pub struct Provenance {
+    pub software: String,      // "exam" and the crate version
+    pub source: String,        // Input file name
+    pub created: String,       // UTC time, ISO 8601
+    pub elevation_min: f32,    // Range the colors are normalized to
+    pub elevation_max: f32,
+    pub nodata: f32,           // NoData value of the input
+    pub colormap: String,
+    pub stretch: Stretch,      // Contrast stretch of the grayscale product
+    pub tone: Tone,            // Gamma, brightness and contrast applied after the normalization
+    pub hillshade: ShadeParams,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vertical: Option<Vertical>, // Unit conversion and offset applied on loading, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<Preview>,   // Downsampling of a --preview run
+}
+
+impl Provenance {
+    /// Provenance of images whose colors are normalized to `range`, see [`crate::render::color_range`]
+    pub fn new(source: &Path, grid: &Grid, (elevation_min, elevation_max): (f32, f32), colormap: &str, stretch: Stretch, tone: Tone, shade: &ShadeParams) -> Provenance {
+        Provenance {
+            software: concat!("exam ", env!("CARGO_PKG_VERSION")).to_string(),
+            source: source.file_name().map_or_else(|| source.to_string_lossy(), |n| n.to_string_lossy()).into_owned(),
+            created: utc_timestamp(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())),
+            elevation_min,
+            elevation_max,
+            nodata: grid.nodata,
+            colormap: colormap.to_string(),
+            stretch,
+            tone,
+            hillshade: *shade,
+            vertical: None,
+            preview: None,
+        }
+    }
+
+    /// PNG text chunks (keyword, text), using the registered keywords where one exists
+    pub fn text_chunks(&self) -> Vec<(String, String)> {
+        let shade = &self.hillshade;
+        [
+            ("Software", self.software.clone()),
+            ("Source", self.source.clone()),
+            ("Creation Time", self.created.clone()),
+            ("exam:elevation-range", format!("{} {}", self.elevation_min, self.elevation_max)),
+            ("exam:colormap", self.colormap.clone()),
+            ("exam:stretch", self.stretch.name().to_string()),
+            ("exam:tone", format!("gamma={} brightness={} contrast={}", self.tone.gamma, self.tone.brightness, self.tone.contrast)),
+            ("exam:hillshade", format!("azimuth={} altitude={} z-factor={} shading={} aerial-perspective={} slope-saturation={}{}",
+                shade.azimuth, shade.altitude, shade.z_factor, shade.mode.name(), shade.aerial_perspective, shade.slope_saturation,
+                shade.fill.map(|fill| format!(" fill-azimuth={} fill-altitude={} fill-weight={}", fill.azimuth, fill.altitude, fill.weight)).unwrap_or_default())),
+        ]
+        .into_iter()
+        .chain(self.vertical.map(|vertical| ("exam:vertical", vertical.describe())))
+        .chain(self.preview.map(|preview| ("exam:preview", preview.describe())))
+        .map(|(key, value)| (key.to_string(), value))
+        .collect()
+    }
+}
+
+/// How the pixels of an image relate to the elevations, see the README
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Encoding {
+    Linear,         // Gray value v is elevation_min + v / 255 * (elevation_max - elevation_min)
+    Colormap,       // The index of the nearest palette color takes the place of v
+    ShadedColormap, // Colormap darkened by the hillshade, only the hue is left to match against the palette
+    Other,          // Derivatives, stereo and perspective views and equalized grayscale, not convertible to elevations
+}
+
+impl Encoding {
+    /// Encoding of the still images by product name, grayscale images are only linear with the linear stretch and no tone adjustment
+    pub fn of(product: &str, provenance: &Provenance) -> Encoding {
+        match product {
+            "grayscale" if provenance.stretch == Stretch::Linear && provenance.tone.is_identity() => Encoding::Linear,
+            "colored" => Encoding::Colormap,
+            "hillside" => Encoding::ShadedColormap,
+            _ => Encoding::Other,
+        }
+    }
+}
+
+/// Contents of the `<image>.json` sidecar written with --sidecar
+#[derive(Debug, Serialize)]
+struct Sidecar<'a> {
+    image: String,
+    product: &'a str,
+    #[serde(flatten)]
+    provenance: &'a Provenance,
+    encoding: Encoding,
+    nodata_style: String,                // See [`NodataStyle`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nodata_color: Option<[u8; 3]>,       // Color of NoData cells if they have a single one, black is also the lowest gray value
+    #[serde(skip_serializing_if = "Option::is_none")]
+    palette: Option<Vec<[u8; 3]>>,       // 256 colormap samples from elevation_min to elevation_max, after the tone adjustment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    out_of_range_color: Option<[u8; 3]>, // Color of cells outside the pinned elevation range, if marked
+}
+
+/// Writes `<image>.json` next to an image with everything needed to map its pixels back to elevations
+fn write_sidecar(image: &Path, product: &str, provenance: &Provenance, target: &OutputTarget) -> io::Result<()> {
+    let encoding = Encoding::of(product, provenance);
+    let style = if target.nodata_mask.is_some() { target.nodata_style.of(product) } else { NodataStyle::Black };
+    let lut = provenance.tone.lookup();
+    let toned = |rgb: [u8; 3]| rgb.map(|c| lut[c as usize]);
+    let palette = match encoding {
+        Encoding::Colormap | Encoding::ShadedColormap => crate::render::colormap(&provenance.colormap).map(|grad| {
+            (0..256).map(|i| {
+                let (r, g, b, _) = grad.at(i as f64 / 255.0).rgba();
+                toned([(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8])
+            }).collect()
+        }),
+        _ => None,
+    };
+    let sidecar = Sidecar {
+        image: image.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+        product,
+        provenance,
+        encoding,
+        nodata_style: style.name(),
+        nodata_color: match style {
+            NodataStyle::Black => Some(toned([0, 0, 0])),
+            NodataStyle::Color(color) => Some(color.0),
+            _ => None,
+        },
+        out_of_range_color: target.elevation_range.filter(|r| r.mark_outside && palette.is_some()).map(|_| toned(OUT_OF_RANGE_COLOR.0)),
+        palette,
+    };
+    // Next to the image it describes, whatever name --suffix-on-conflict gave it
+    let mut path = image.as_os_str().to_owned();
+    path.push(".json");
+    let path = PathBuf::from(path);
+    target.written.borrow_mut().push(path.clone());
+    let file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(file, &sidecar).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
+
+/// ISO 8601 UTC time of a Unix timestamp, e.g. 2024-05-01T12:00:00Z
+fn utc_timestamp(secs: u64) -> String {
+    let (days, rest) = ((secs / 86_400) as i64, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, rest / 3600, rest / 60 % 60, rest % 60)
+}
+
+/// Saves `img` as the `name` product of the target, with the extension of the format, followed by its previews
+pub fn save_image(img: DynamicImage, target: &OutputTarget, name: &str, format: &OutputFormat) -> io::Result<()> {
+    profiling::stage("encode", || encode_product(img, target, name, format))
+}
+
+fn encode_product(mut img: DynamicImage, target: &OutputTarget, name: &str, format: &OutputFormat) -> io::Result<()> {
+    if !format.format.is_available() {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, format!("{:?} output is not enabled in this build", format.format)));
+    }
+    target.tone.apply(&mut img);
+    if let Some(mask) = &target.nodata_mask {
+        target.nodata_style.of(name).apply(&mut img, mask);
+    }
+    if let Some(spots) = &target.spot_heights {
+        spots.apply(&mut img);
+    }
+    let chunks = target.provenance.as_ref().map(Provenance::text_chunks).unwrap_or_default();
+    let previews = target.previews.render(&img, name);
+    let simulated = target.simulate_cvd.map(|cvd| (format!("{}_{}", name, cvd.name()), cvd.simulate(&img)));
+    let path = target.path(name, format.format.extension())?;
+    let mut file = BufWriter::new(File::create(&path)?);
+    encode_image_with(img, format, &chunks, &mut file)?;
+    file.flush()?;
+    if let Some(provenance) = target.provenance.as_ref().filter(|_| target.sidecars) {
+        write_sidecar(&path, name, provenance, target)?;
+    }
+
+    for (name, preview) in previews.into_iter().chain(simulated) {
+        let mut file = BufWriter::new(File::create(target.path(&name, format.format.extension())?)?);
+        encode_image_with(preview, format, &chunks, &mut file)?;
+        file.flush()?;
+    }
+    Ok(())
+}
+
+/// Writes `img` to a standard output stream, which cannot seek, by encoding it in memory first
+pub fn write_image_to(img: DynamicImage, format: &OutputFormat, out: &mut impl Write) -> io::Result<()> {
+    let mut buffer = Cursor::new(Vec::new());
+    encode_image(img, format, &mut buffer)?;
+    out.write_all(buffer.get_ref())?;
+    out.flush()
+}
+
+/// Encodes `img` in the selected format and settings
+pub fn encode_image<W: Write + Seek>(img: DynamicImage, format: &OutputFormat, file: &mut W) -> io::Result<()> {
+    encode_image_with(img, format, &[], file)
+}
+
+/// Like [`encode_image`], PNGs also get `metadata` as text chunks: tEXt for Latin-1 text, iTXt otherwise
+pub fn encode_image_with<W: Write + Seek>(img: DynamicImage, format: &OutputFormat, metadata: &[(String, String)], file: &mut W) -> io::Result<()> {
+    if !format.format.is_available() {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, format!("{:?} output is not enabled in this build", format.format)));
+    }
+    let to_io = |e: image::ImageError| io::Error::new(io::ErrorKind::Other, e);
+
+    // The renderers produce grayscale and RGB, with alpha for transparent NoData; formats without alpha drop it
+    let img = match img {
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageRgb8(_) => img,
+        DynamicImage::ImageLumaA8(_) | DynamicImage::ImageRgba8(_) if matches!(format.format, ImageFormat::Png | ImageFormat::WebP | ImageFormat::Avif) => img,
+        DynamicImage::ImageLumaA8(_) | DynamicImage::ImageRgba8(_) if format.format == ImageFormat::Tiff && !format.cog => DynamicImage::ImageRgba8(img.into_rgba8()),
+        DynamicImage::ImageLumaA8(_) => DynamicImage::ImageLuma8(img.into_luma8()),
+        other => DynamicImage::ImageRgb8(other.into_rgb8()),
+    };
+    let (width, height) = (img.width(), img.height());
+
+    match format.format {
+        ImageFormat::Png => match indexed(&img, format.png_palette) {
+            Some(indexed) => write_indexed_png(&mut *file, width, height, indexed, format, metadata)?,
+            None => {
+                let color = match img.color() {
+                    image::ColorType::L8 => png::ColorType::Grayscale,
+                    image::ColorType::La8 => png::ColorType::GrayscaleAlpha,
+                    image::ColorType::Rgba8 => png::ColorType::Rgba,
+                    _ => png::ColorType::Rgb,
+                };
+                png_encoder(&mut *file, width, height, color, format, metadata)?
+                    .write_header().and_then(|mut writer| writer.write_image_data(img.as_bytes())).map_err(png_io)?;
+            }
+        },
+        ImageFormat::Jpeg => {
+            JpegEncoder::new_with_quality(&mut *file, format.quality.clamp(1, 100))
+                .write_image(img.as_bytes(), width, height, img.color())
+                .map_err(to_io)?;
+        }
+        ImageFormat::WebP => {
+            // Lossy WebP needs libwebp, the pure Rust encoder only writes lossless files
+            #[cfg(feature = "webp")]
+            let encoder = WebPEncoder::new_with_quality(&mut *file, image::codecs::webp::WebPQuality::lossy(format.quality.clamp(1, 100)));
+            #[cfg(not(feature = "webp"))]
+            let encoder = WebPEncoder::new_lossless(&mut *file);
+            // WebP has no grayscale mode
+            if img.color().has_alpha() {
+                encoder.write_image(img.into_rgba8().as_raw(), width, height, image::ColorType::Rgba8).map_err(to_io)?;
+            } else {
+                encoder.write_image(img.into_rgb8().as_raw(), width, height, image::ColorType::Rgb8).map_err(to_io)?;
+            }
+        }
+        ImageFormat::Avif => {
+            #[cfg(feature = "avif")]
+            image::codecs::avif::AvifEncoder::new_with_speed_quality(&mut *file, 6, format.quality.clamp(1, 100))
+                .write_image(img.as_bytes(), width, height, img.color())
+                .map_err(to_io)?;
+            #[cfg(not(feature = "avif"))]
+            unreachable!("checked by is_available");
+        }
+        ImageFormat::Tiff if format.cog => crate::cog::write_image_cog(&img, format.tiff_compression, file)?,
+        ImageFormat::Tiff => {
+            let result = if needs_bigtiff(img.as_bytes().len() as u64, format.tiff_compression) {
+                TiffEncoder::new_big(&mut *file).and_then(|mut encoder| write_tiff_image(&mut encoder, &img, format.tiff_compression))
+            } else {
+                TiffEncoder::new(&mut *file).and_then(|mut encoder| write_tiff_image(&mut encoder, &img, format.tiff_compression))
+            };
+            result.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// An image indexed into a palette of RGB triples, with the alpha of the entries if any is transparent
+struct Indexed {
+    palette: Vec<u8>,
+    trns: Option<Vec<u8>>,
+    depth: u8,
+    data: Vec<u8>, // Rows of indices packed at `depth` bits, each starting on a byte, the first pixel in the highest bits
+}
+
+/// `img` indexed as asked by `palette`, None if that is off, the colors don't fit in the depth asked for, or `auto`
+/// would only turn a grayscale image of 8 bits into one of 8-bit indices
+fn indexed(img: &DynamicImage, palette: PngPalette) -> Option<Indexed> {
+    let max_depth = match palette {
+        PngPalette::Off => return None,
+        PngPalette::Auto => 8,
+        PngPalette::Depth(depth) => depth,
+    };
+    let rgba = img.to_rgba8();
+    let mut colors: HashMap<[u8; 4], u8> = HashMap::new();
+    let mut indices = Vec::with_capacity(rgba.len() / 4);
+    for pixel in rgba.pixels() {
+        let index = match colors.get(&pixel.0) {
+            Some(&index) => index,
+            None if colors.len() < 1 << max_depth => {
+                let index = colors.len() as u8;
+                colors.insert(pixel.0, index);
+                index
+            }
+            None => return None,
+        };
+        indices.push(index);
+    }
+    let depth = match (palette, colors.len()) {
+        (PngPalette::Depth(depth), _) => depth,
+        (_, 0..=2) => 1,
+        (_, 3..=4) => 2,
+        (_, 5..=16) => 4,
+        _ => 8,
+    };
+    // Grayscale already takes a byte a pixel
+    if depth == 8 && palette == PngPalette::Auto && matches!(img, DynamicImage::ImageLuma8(_)) {
+        return None;
+    }
+
+    // Sorted, so neighboring pixels of similar colors get similar indices for the PNG filters to work on
+    let mut entries: Vec<([u8; 4], u8)> = colors.into_iter().collect();
+    entries.sort_unstable();
+    let mut rank = [0u8; 256];
+    for (i, &(_, index)) in entries.iter().enumerate() {
+        rank[index as usize] = i as u8;
+    }
+    let entries: Vec<[u8; 4]> = entries.into_iter().map(|(color, _)| color).collect();
+    let trns = entries.iter().any(|c| c[3] < 255).then(|| entries.iter().map(|c| c[3]).collect());
+
+    let (width, depth_bits) = (rgba.width() as usize, depth as usize);
+    let row_bytes = (width * depth_bits).div_ceil(8);
+    let mut data = vec![0u8; row_bytes * rgba.height() as usize];
+    for (row, out) in indices.chunks(width.max(1)).zip(data.chunks_mut(row_bytes.max(1))) {
+        for (x, &index) in row.iter().enumerate() {
+            let bit = x * depth_bits;
+            out[bit / 8] |= rank[index as usize] << (8 - depth_bits - bit % 8);
+        }
+    }
+    Some(Indexed { palette: entries.iter().flat_map(|c| [c[0], c[1], c[2]]).collect(), trns, depth, data })
+}
+
+/// Writes `indexed` as a palette PNG
+fn write_indexed_png<W: Write>(file: W, width: u32, height: u32, indexed: Indexed, format: &OutputFormat, metadata: &[(String, String)]) -> io::Result<()> {
+    let mut encoder = png_encoder(file, width, height, png::ColorType::Indexed, format, metadata)?;
+    encoder.set_depth(match indexed.depth {
+        1 => png::BitDepth::One,
+        2 => png::BitDepth::Two,
+        4 => png::BitDepth::Four,
+        _ => png::BitDepth::Eight,
+    });
+    encoder.set_palette(indexed.palette);
+    if let Some(trns) = indexed.trns {
+        encoder.set_trns(trns);
+    }
+    encoder.write_header().and_then(|mut writer| writer.write_image_data(&indexed.data)).map_err(png_io)
+}
+
+/// Room left in a classic TIFF for its tags and strip tables
+const TIFF_TAG_ALLOWANCE: u64 = 16 * 1024 * 1024;
+
+/// Whether a TIFF of `sample_bytes` uncompressed samples may outgrow the 32-bit offsets of classic TIFF (4 GiB) once
+/// compressed with `compression`, so it has to be written as a BigTIFF. LZW can grow data it fails to compress by
+/// half, the others by a few percent.
+pub fn needs_bigtiff(sample_bytes: u64, compression: TiffCompression) -> bool {
+    let worst = match compression {
+        TiffCompression::Lzw => sample_bytes + sample_bytes / 2,
+        _ => sample_bytes + sample_bytes / 64,
+    };
+    worst + TIFF_TAG_ALLOWANCE > u32::MAX as u64
+}
+
+fn png_io(e: png::EncodingError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+/// PNG encoder of 8-bit `color` pixels with the compression of the format and `metadata` as text chunks
+fn png_encoder<W: Write>(file: W, width: u32, height: u32, color: png::ColorType, format: &OutputFormat, metadata: &[(String, String)]) -> io::Result<png::Encoder<'static, W>> {
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_compression(match format.png_compression {
+        PngCompression::Fast => png::Compression::Fast,
+        PngCompression::Default => png::Compression::Default,
+        PngCompression::Best => png::Compression::Best,
+    });
+    encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive);
+    for (keyword, text) in metadata {
+        if text.chars().all(|c| (c as u32) < 256) {
+            encoder.add_text_chunk(keyword.clone(), text.clone()).map_err(png_io)?;
+        } else {
+            encoder.add_itxt_chunk(keyword.clone(), text.clone()).map_err(png_io)?;
+        }
+    }
+    Ok(encoder)
+}
+
+/// Saves the `name` product of the target as an RGB PNG whose rows `fill_row(y, row)` renders one at a time, so the
+/// whole image is never in memory. The target has to allow it, see [`OutputTarget::streams`]; the tone and NoData
+/// style are applied row by row like [`save_image`] applies them to the whole image.
+pub fn save_png_rows(target: &OutputTarget, name: &str, format: &OutputFormat, width: usize, height: usize, mut fill_row: impl FnMut(usize, &mut [u8])) -> io::Result<()> {
+    let lut = (!target.tone.is_identity()).then(|| target.tone.lookup());
+    let style = target.nodata_style.of(name);
+    let mask = target.nodata_mask.as_ref().filter(|mask| mask.size() == (width, height));
+    let chunks = target.provenance.as_ref().map(Provenance::text_chunks).unwrap_or_default();
+    let path = target.path(name, "png")?;
+    let mut file = BufWriter::new(File::create(&path)?);
+    let mut writer = png_encoder(&mut file, width as u32, height as u32, png::ColorType::Rgb, format, &chunks)?.write_header().map_err(png_io)?;
+    let mut stream = writer.stream_writer().map_err(png_io)?;
+    let mut row = vec![0; width * 3];
+    for y in 0..height {
+        fill_row(y, &mut row);
+        if let Some(lut) = &lut {
+            row.iter_mut().for_each(|c| *c = lut[*c as usize]);
+        }
+        for (x, pixel) in row.chunks_exact_mut(3).enumerate().filter(|&(x, _)| mask.is_some_and(|mask| !mask.get(x, y))) {
+            if let Some(color) = style.pixel(x as u32, y as u32) {
+                pixel.copy_from_slice(&color.0);
+            }
+        }
+        stream.write_all(&row)?;
+    }
+    stream.finish().map_err(png_io)?;
+    drop(writer);
+    file.flush()?;
+    if let Some(provenance) = target.provenance.as_ref().filter(|_| target.sidecars) {
+        write_sidecar(&path, name, provenance, target)?;
+    }
+    Ok(())
+}
+
+/// Writes a grayscale, RGBA or (any other) RGB image as an 8-bit TIFF
+fn write_tiff_image<W: Write + Seek, K: TiffKind>(encoder: &mut TiffEncoder<W, K>, img: &DynamicImage, tiff_compression: TiffCompression) -> tiff::TiffResult<()> {
+    let (width, height) = (img.width(), img.height());
+    match img {
+        DynamicImage::ImageLuma8(gray) => write_tiff::<colortype::Gray8, _, _>(encoder, width, height, gray.as_raw(), tiff_compression),
+        DynamicImage::ImageRgba8(rgba) => write_tiff::<colortype::RGBA8, _, _>(encoder, width, height, rgba.as_raw(), tiff_compression),
+        _ => write_tiff::<colortype::RGB8, _, _>(encoder, width, height, img.as_bytes(), tiff_compression),
+    }
+}
+
+/// Writes one 8-bit TIFF image with the selected strip compression
+fn write_tiff<C: colortype::ColorType<Inner = u8>, W: Write + Seek, K: TiffKind>(
+    encoder: &mut TiffEncoder<W, K>,
+    width: u32,
+    height: u32,
+    data: &[u8],
+    tiff_compression: TiffCompression,
+) -> tiff::TiffResult<()> {
+    match tiff_compression {
+        TiffCompression::None => encoder.write_image_with_compression::<C, _>(width, height, compression::Uncompressed, data),
+        TiffCompression::Lzw => encoder.write_image_with_compression::<C, _>(width, height, compression::Lzw, data),
+        TiffCompression::Deflate => encoder.write_image_with_compression::<C, _>(width, height, compression::Deflate::default(), data),
+        TiffCompression::PackBits => encoder.write_image_with_compression::<C, _>(width, height, compression::Packbits, data),
+    }
+}
+
+/// File format for raw floating point values
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RawFormat {
+    Tiff, // Float32 GeoTIFF, NoData as in the source grid
+    Npy,  // NumPy array of shape (nrows, ncols), NoData as NaN
+}
+
+impl RawFormat {
+    pub fn parse(name: &str) -> Option<RawFormat> {
+        match name.to_lowercase().as_str() {
+            "tif" | "tiff" | "geotiff" => Some(RawFormat::Tiff),
+            "npy" => Some(RawFormat::Npy),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RawFormat::Tiff => "tif",
+            RawFormat::Npy => "npy",
+        }
+    }
+}
+
+/// Writes `values` as "<name>" in each of the `raw` formats, a COG instead of a plain GeoTIFF with --cog
+pub fn save_raw_values(values: &Grid, name: &str, raw: &[RawFormat], format: &OutputFormat, target: &OutputTarget) -> io::Result<()> {
+    profiling::stage("encode", || {
+        for raw_format in raw {
+            let path = target.path(name, raw_format.extension())?;
+            match raw_format {
+                RawFormat::Tiff if format.cog => crate::cog::save_float_cog(values, &path)?,
+                RawFormat::Tiff => save_float_tiff(values, &path)?,
+                RawFormat::Npy => save_npy(values, &path)?,
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Writes the grid as a file of the type its extension names: .tif (a COG if `cog`), .npy, otherwise .asc
+pub fn save_grid(grid: &Grid, path: &Path, cog: bool) -> io::Result<()> {
+    match path.extension().and_then(|e| e.to_str()).map(str::to_lowercase).as_deref() {
+        Some("tif" | "tiff") if cog => crate::cog::save_float_cog(grid, path),
+        Some("tif" | "tiff") => save_float_tiff(grid, path),
+        Some("npy") => save_npy(grid, path),
+        _ => crate::asc::save_asc(grid, path),
+    }
+}
+
+/// Writes the grid values as a deflate-compressed Float32 GeoTIFF, georeferenced by the ASC header, a BigTIFF if it
+/// may pass 4 GiB. The CRS is not known from an .asc file, so only the raster-to-model transformation is stored.
+pub fn save_float_tiff(grid: &Grid, path: &Path) -> io::Result<()> {
+    let to_io = |e: tiff::TiffError| io::Error::new(io::ErrorKind::Other, e);
+    let mut file = BufWriter::new(File::create(path)?);
+    if needs_bigtiff((grid.ncols * grid.nrows * 4) as u64, TiffCompression::Deflate) {
+        write_float_tiff(&mut TiffEncoder::new_big(&mut file).map_err(to_io)?, grid).map_err(to_io)?;
+    } else {
+        write_float_tiff(&mut TiffEncoder::new(&mut file).map_err(to_io)?, grid).map_err(to_io)?;
+    }
+    file.flush()
+}
+
+fn write_float_tiff<W: Write + Seek, K: TiffKind>(encoder: &mut TiffEncoder<W, K>, grid: &Grid) -> tiff::TiffResult<()> {
+    let mut image = encoder.new_image_with_compression::<colortype::Gray32Float, _>(grid.ncols as u32, grid.nrows as u32, compression::Deflate::default())?;
+    let dir = image.encoder();
+    write_georeference(dir, grid)?;
+    dir.write_tag(Tag::GdalNodata, &*grid.nodata.to_string())?;
+
+    let data: Vec<f32> = grid.data.iter().flatten().copied().collect();
+    image.write_data(&data)
+}
+
+/// Tags placing the pixels of a TIFF on the cells of `grid`, without a CRS
+pub fn write_georeference<W: Write + Seek, K: TiffKind>(dir: &mut DirectoryEncoder<W, K>, grid: &Grid) -> tiff::TiffResult<()> {
+    let (x0, y0) = grid.origin();
+    dir.write_tag(Tag::ModelPixelScaleTag, &[grid.cellsize, grid.cellsize, 0.0][..])?;
+    dir.write_tag(Tag::ModelTiepointTag, &[0.0, 0.0, 0.0, x0, y0, 0.0][..])?;
+    // GeoKey directory: version 1.1.0 with 2 keys, projected model (1024 = 1) and pixels as areas (1025 = 1)
+    dir.write_tag(Tag::GeoKeyDirectoryTag, &[1u16, 1, 0, 2, 1024, 0, 1, 1, 1025, 0, 1, 1][..])
+}
+
+/// Rows of a strip in the TIFFs of [`save_rgb_rows`]
+const ROWS_PER_STRIP: usize = 16;
+
+/// Saves an RGB image of one pixel per cell of `grid` whose rows `produce` hands to its writer from the top, any number
+/// of whole rows at a time, so the image is never in memory as a whole: a PNG with `metadata` as text chunks, or a
+/// TIFF of strips georeferenced like [`save_float_tiff`], a BigTIFF if it may pass 4 GiB. Other formats can't be
+/// written this way.
+pub fn save_rgb_rows(
+    grid: &Grid,
+    path: &Path,
+    format: &OutputFormat,
+    metadata: &[(String, String)],
+    produce: impl FnOnce(&mut dyn FnMut(&[u8]) -> io::Result<()>) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    match format.format {
+        ImageFormat::Png => {
+            let mut writer = png_encoder(&mut file, grid.ncols as u32, grid.nrows as u32, png::ColorType::Rgb, format, metadata)?
+                .write_header().map_err(png_io)?;
+            let mut stream = writer.stream_writer().map_err(png_io)?;
+            produce(&mut |rows| stream.write_all(rows))?;
+            stream.finish().map_err(png_io)?;
+        }
+        ImageFormat::Tiff => {
+            let to_io = |e: tiff::TiffError| io::Error::new(io::ErrorKind::Other, e);
+            if needs_bigtiff((grid.ncols * grid.nrows * 3) as u64, format.tiff_compression) {
+                write_tiff_strips(TiffEncoder::new_big(&mut file).map_err(to_io)?, grid, format.tiff_compression, produce)?
+            } else {
+                write_tiff_strips(TiffEncoder::new(&mut file).map_err(to_io)?, grid, format.tiff_compression, produce)?
+            }
+        }
+        other => return Err(io::Error::new(io::ErrorKind::Unsupported, format!("{:?} images can't be written row by row, use PNG or TIFF", other))),
+    }
+    file.flush()
+}
+
+/// The TIFF of [`save_rgb_rows`], a BigTIFF from a BigTIFF `encoder`. The strips are compressed here as they fill up, since the image encoder of the tiff
+/// crate only compresses images handed over whole; the directory is written once all strips are in.
+fn write_tiff_strips<W: Write + Seek, K: TiffKind>(
+    mut encoder: TiffEncoder<W, K>,
+    grid: &Grid,
+    tiff_compression: TiffCompression,
+    produce: impl FnOnce(&mut dyn FnMut(&[u8]) -> io::Result<()>) -> io::Result<()>,
+) -> io::Result<()> {
+    let to_io = |e: tiff::TiffError| io::Error::new(io::ErrorKind::Other, e);
+    let mut dir = encoder.new_directory().map_err(to_io)?;
+
+    // Rows are collected into whole strips, only the last one may be shorter
+    let strip_bytes = ROWS_PER_STRIP * grid.ncols * 3;
+    let mut strip = Vec::with_capacity(strip_bytes);
+    let (mut offsets, mut counts, mut code) = (Vec::new(), Vec::new(), 1);
+    let mut write_strip = |dir: &mut DirectoryEncoder<W, K>, strip: &[u8]| -> io::Result<()> {
+        let (bytes, c) = crate::cog::compress(strip, tiff_compression)?;
+        offsets.push(dir.write_data(&bytes[..]).map_err(to_io)?);
+        counts.push(bytes.len() as u64);
+        code = c;
+        Ok(())
+    };
+    produce(&mut |mut rows| {
+        while !rows.is_empty() {
+            let take = (strip_bytes - strip.len()).min(rows.len());
+            strip.extend_from_slice(&rows[..take]);
+            rows = &rows[take..];
+            if strip.len() == strip_bytes {
+                write_strip(&mut dir, &strip)?;
+                strip.clear();
+            }
+        }
+        Ok(())
+    })?;
+    if !strip.is_empty() {
+        write_strip(&mut dir, &strip)?;
+    }
+
+    let offsets: Vec<K::OffsetType> = offsets.into_iter().map(K::convert_offset).collect::<tiff::TiffResult<_>>().map_err(to_io)?;
+    let counts: Vec<K::OffsetType> = counts.into_iter().map(K::convert_offset).collect::<tiff::TiffResult<_>>().map_err(to_io)?;
+    dir.write_tag(Tag::ImageWidth, grid.ncols as u32).map_err(to_io)?;
+    dir.write_tag(Tag::ImageLength, grid.nrows as u32).map_err(to_io)?;
+    dir.write_tag(Tag::BitsPerSample, &[8u16, 8, 8][..]).map_err(to_io)?;
+    dir.write_tag(Tag::Compression, code).map_err(to_io)?;
+    dir.write_tag(Tag::PhotometricInterpretation, 2u16).map_err(to_io)?; // RGB
+    dir.write_tag(Tag::StripOffsets, K::convert_slice(&offsets)).map_err(to_io)?;
+    dir.write_tag(Tag::SamplesPerPixel, 3u16).map_err(to_io)?;
+    dir.write_tag(Tag::RowsPerStrip, ROWS_PER_STRIP as u32).map_err(to_io)?;
+    dir.write_tag(Tag::StripByteCounts, K::convert_slice(&counts)).map_err(to_io)?;
+    dir.write_tag(Tag::PlanarConfiguration, 1u16).map_err(to_io)?; // Interleaved
+    write_georeference(&mut dir, grid).map_err(to_io)?;
+    dir.finish().map_err(to_io)
+}
+
+/// Writes the grid values as a little-endian float32 NumPy `.npy` file (format version 1.0)
+pub fn save_npy(grid: &Grid, path: &Path) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+
+    let mut header = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}), }}", grid.nrows, grid.ncols);
+    // Magic, version and length take 10 bytes, the header is padded with spaces so the data starts 64-byte aligned
+    let padding = (64 - (10 + header.len() + 1) % 64) % 64;
+    header.push_str(&" ".repeat(padding));
+    header.push('\n');
+
+    file.write_all(b"\x93NUMPY\x01\x00")?;
+    file.write_all(&(header.len() as u16).to_le_bytes())?;
+    file.write_all(header.as_bytes())?;
+    for &v in grid.data.iter().flatten() {
+        let v = if grid.is_nodata(v) { f32::NAN } else { v };
+        file.write_all(&v.to_le_bytes())?;
+    }
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Largest sample size that still fits a classic TIFF: 4 GiB less the tag allowance, less the worst growth
+    #[test]
+    fn needs_bigtiff_boundary() {
+        assert!(!needs_bigtiff(0, TiffCompression::None));
+        for compression in [TiffCompression::None, TiffCompression::Deflate, TiffCompression::PackBits] {
+            assert!(!needs_bigtiff(4_212_371_771, compression));
+            assert!(needs_bigtiff(4_212_371_772, compression));
+        }
+        assert!(!needs_bigtiff(2_852_126_719, TiffCompression::Lzw));
+        assert!(needs_bigtiff(2_852_126_720, TiffCompression::Lzw));
+    }
+
+    /// Anything of 4 GiB or more is a BigTIFF, whatever the compression
+    #[test]
+    fn needs_bigtiff_past_u32() {
+        for compression in [TiffCompression::None, TiffCompression::Lzw, TiffCompression::Deflate, TiffCompression::PackBits] {
+            assert!(needs_bigtiff(u32::MAX as u64, compression));
+            assert!(needs_bigtiff(u32::MAX as u64 + 1, compression));
+        }
+    }
+
+    /// Skip keeps an existing file, Suffix creates the next free name, Overwrite replaces it
+    #[test]
+    fn conflict_create() {
+        let dir = std::env::temp_dir().join(format!("exam-conflict-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("hillside.png");
+        fs::write(&path, "kept").unwrap();
+
+        let err = Conflict::Skip.create(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(Conflict::Suffix.create(&path).unwrap().1, dir.join("hillside_1.png"));
+        assert_eq!(Conflict::Suffix.claim(&path).unwrap(), dir.join("hillside_2.png"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "kept");
+        assert_eq!(Conflict::Overwrite.create(&path).unwrap().1, path);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        assert_eq!(Conflict::Skip.claim(&dir.join("new.png")).unwrap(), dir.join("new.png"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io;
+#[cfg(feature = "fs")]
+use image::codecs::gif::{GifEncoder, Repeat};
+#[cfg(feature = "fs")]
+use image::{Delay, DynamicImage, Frame};
+use colorgrad::Gradient;
+use image::{Rgb, RgbImage};
+
+use crate::grid::Grid;
+#[cfg(feature = "fs")]
+use crate::output::{save_image, OutputFormat, OutputTarget};
+#[cfg(feature = "fs")]
+use crate::render::color_range;
+use crate::render::{render_shaded, ShadeParams};
+
+/// Camera of the perspective view. Horizontal positions are in cells (column, row),
+/// heights are elevations in the units of the DEM.
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub position: (f64, f64, f64),
+    pub target: (f64, f64),
+}
+
+/// Settings for the perspective renderer
+#[derive(Clone, Copy, Debug)]
+pub struct PerspectiveSettings {
+    pub camera: Option<Camera>, // None places the camera south of the center, looking north
+    pub fov: f64,               // Horizontal field of view in degrees
+    pub exaggeration: f64,      // Vertical exaggeration of the terrain
+    pub width: u32,
+    pub height: u32,
+    pub flyover_frames: usize,  // Frames of an orbit around the target, 0 renders no flyover
+    pub frame_ms: u32,
+}
+
+/// Everything the ray marcher needs about the terrain, with heights already in cell units
+struct Scene<'a> {
+    heights: Vec<Vec<f64>>,
+    valid: Vec<Vec<bool>>,
+    texture: &'a RgbImage,
+    cell_size: f64,
+    exaggeration: f64,
+}
+
+impl Scene<'_> {
+    /// Height in cells of the nearest cell, None outside the grid or on NoData
+    fn sample(&self, x: f64, y: f64) -> Option<(f64, Rgb<u8>)> {
+        if x < 0.0 || y < 0.0 { return None; }
+        let (cx, cy) = (x as usize, y as usize);
+        if cy >= self.heights.len() || cx >= self.heights[0].len() || !self.valid[cy][cx] { return None; }
+        Some((self.heights[cy][cx], *self.texture.get_pixel(cx as u32, cy as u32)))
+    }
+
+    /// Converts an elevation to the cell units used for horizontal distances
+    fn to_cells(&self, elevation: f64) -> f64 {
+        elevation * self.exaggeration / self.cell_size
+    }
+}
+
+/// Places the camera south of the grid center, high enough to see the whole tile
+fn default_camera(grid: &Grid, exaggeration: f64) -> Camera {
+    let (_, max) = grid.min_max();
+    let (cx, cy) = (grid.ncols as f64 / 2.0, grid.nrows as f64 / 2.0);
+    let distance = grid.nrows.max(grid.ncols) as f64 * 0.9;
+    // Height above the highest point, in DEM units, so that the view looks down at roughly 30°
+    let height = max as f64 + distance * 0.55 * grid.ground_cellsize() / exaggeration;
+    Camera {
+        position: (cx, cy + distance, height),
+        target: (cx, cy),
+    }
+}
+
+/// Renders the colored hillshade seen from `camera` with a voxel-space style ray marcher:
+/// one ray per screen column, marched front to back while an occlusion buffer keeps hidden terrain out.
+fn render_view(scene: &Scene, camera: &Camera, settings: &PerspectiveSettings) -> RgbImage {
+    let (w, h) = (settings.width, settings.height);
+    let mut img = RgbImage::new(w, h);
+
+    // Sky gradient
+    for y in 0..h {
+        let t = y as f32 / h as f32;
+        let sky = Rgb([(135.0 + 100.0 * t) as u8, (180.0 + 60.0 * t) as u8, 235]);
+        for x in 0..w {
+            img.put_pixel(x, y, sky);
+        }
+    }
+
+    let (px, py, pz) = camera.position;
+    let pz = scene.to_cells(pz);
+    let (dx, dy) = (camera.target.0 - px, camera.target.1 - py);
+    let yaw = dy.atan2(dx);
+    let target_z = scene.sample(camera.target.0, camera.target.1).map(|(z, _)| z).unwrap_or(0.0);
+    let pitch = ((pz - target_z) / dx.hypot(dy).max(1.0)).atan(); // Positive when looking down
+
+    let focal = (w as f64 / 2.0) / (settings.fov.to_radians() / 2.0).tan();
+    let horizon = h as f64 / 2.0 - pitch.tan() * focal;
+    let max_distance = (scene.heights.len().max(scene.heights[0].len()) as f64) * 3.0;
+
+    for col in 0..w {
+        let offset = ((col as f64 - w as f64 / 2.0) / focal).atan();
+        let (sin, cos) = (yaw + offset).sin_cos();
+        let mut occlusion = h as f64; // Lowest screen row not yet covered by closer terrain
+        let mut distance = 1.0;
+        let mut step = 0.25;
+
+        while distance < max_distance && occlusion > 0.0 {
+            if let Some((z, color)) = scene.sample(px + cos * distance, py + sin * distance) {
+                // Distance along the view axis keeps straight lines straight across the screen
+                let depth = distance * offset.cos();
+                let screen_y = horizon + (pz - z) / depth * focal;
+                if screen_y < occlusion {
+                    let top = screen_y.max(0.0) as u32;
+                    for y in top..(occlusion as u32).min(h) {
+                        img.put_pixel(col, y, color);
+                    }
+                    occlusion = screen_y;
+                }
+            }
+            distance += step;
+            step *= 1.005; // Far away terrain needs less detail
+        }
+    }
+    img
+}
+
+fn build_scene<'a>(grid: &Grid, texture: &'a RgbImage, exaggeration: f64) -> Scene<'a> {
+    let mut scene = Scene { heights: Vec::new(), valid: Vec::new(), texture, cell_size: grid.ground_cellsize(), exaggeration };
+    scene.heights = grid.data.iter().map(|row| row.iter().map(|&v| scene.to_cells(v as f64)).collect()).collect();
+    scene.valid = grid.data.iter().map(|row| row.iter().map(|&v| !grid.is_nodata(v)).collect()).collect();
+    scene
+}
+
+/// Renders the single perspective view described by `settings`, textured with the hillshade colored over `range`
+pub fn render_perspective(grid: &Grid, range: (f32, f32), grad: &Gradient, params: &ShadeParams, settings: &PerspectiveSettings) -> RgbImage {
+    let texture = render_shaded(grid, range, grad, params);
+    let scene = build_scene(grid, &texture, settings.exaggeration);
+    let camera = settings.camera.unwrap_or_else(|| default_camera(grid, settings.exaggeration));
+    render_view(&scene, &camera, settings)
+}
+
+/// Saves the "perspective" view and, if requested, an orbiting "flyover" GIF, textured with the hillshade of `target.shade`
+#[cfg(feature = "fs")]
+pub fn save_perspective_images(grid: &Grid, settings: &PerspectiveSettings, grad: &Gradient, format: &OutputFormat, target: &OutputTarget) -> io::Result<()> {
+    let texture = render_shaded(grid, color_range(grid, target.elevation_range.as_ref()), grad, &target.shade);
+    let scene = build_scene(grid, &texture, settings.exaggeration);
+
+    let camera = settings.camera.unwrap_or_else(|| default_camera(grid, settings.exaggeration));
+    let img = render_view(&scene, &camera, settings);
+    save_image(DynamicImage::ImageRgb8(img), target, "perspective", format)?;
+
+    if settings.flyover_frames > 0 {
+        let file = File::create(target.path("flyover", "gif")?)?;
+        let mut encoder = GifEncoder::new_with_speed(file, 10);
+        encoder.set_repeat(Repeat::Infinite).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+        // Orbit around the target keeping the camera's distance and height
+        let (tx, ty) = camera.target;
+        let (px, py, pz) = camera.position;
+        let radius = (px - tx).hypot(py - ty);
+        let start = (px - tx).atan2(py - ty);
+        let delay = Delay::from_numer_denom_ms(settings.frame_ms, 1);
+
+        let frames = (0..settings.flyover_frames).map(|i| {
+            let angle = start + i as f64 / settings.flyover_frames as f64 * std::f64::consts::TAU;
+            let orbit = Camera { position: (tx + radius * angle.sin(), ty + radius * angle.cos(), pz), target: camera.target };
+            let view = render_view(&scene, &orbit, settings);
+            Frame::from_parts(DynamicImage::ImageRgb8(view).into_rgba8(), 0, 0, delay)
+        });
+        encoder.encode_frames(frames).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    }
+    Ok(())
+}
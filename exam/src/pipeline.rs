@@ -0,0 +1,146 @@
+//! Deferred processing chains like `grid.lazy().fill_sinks().smooth(1.5).hillshade(params).save(path)`.
+//! The stages only run when the result is asked for: consecutive per-cell stages are fused into a single pass that
+//! updates the grid in place, and a new grid is only allocated by the stages that read neighboring cells.
+
+#[cfg(feature = "fs")]
+use std::io;
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+use colorgrad::Gradient;
+use image::{GrayImage, Luma, RgbImage};
+
+use crate::grid::Grid;
+use crate::render::{hillshade_values, render_shaded, ShadeParams};
+use crate::terrain::{fill_sinks, Derivative};
+
+/// Step of a [`Pipeline`]
+enum Stage {
+    Cell(Box<dyn Fn(f32) -> f32>),              // Applied to every valid cell on its own
+    Neighborhood(Box<dyn FnOnce(&Grid) -> Grid>), // Reads neighbors, so it needs the finished grid before it
+}
+
+/// Operations recorded on a grid and run by [`Pipeline::collect`] or one of the outputs
+pub struct Pipeline {
+    grid: Grid,
+    stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    pub fn new(grid: Grid) -> Pipeline {
+        Pipeline { grid, stages: Vec::new() }
+    }
+
+    /// Replaces every valid elevation by `f` of it, NoData stays NoData
+    pub fn map(mut self, f: impl Fn(f32) -> f32 + 'static) -> Pipeline {
+        self.stages.push(Stage::Cell(Box::new(f)));
+        self
+    }
+
+    /// Multiplies the elevations, e.g. to convert feet to meters
+    pub fn scale(self, factor: f32) -> Pipeline {
+        self.map(move |v| v * factor)
+    }
+
+    /// Adds `offset` to the elevations
+    pub fn offset(self, offset: f32) -> Pipeline {
+        self.map(move |v| v + offset)
+    }
+
+    /// Limits the elevations to `min..=max`
+    pub fn clamp(self, min: f32, max: f32) -> Pipeline {
+        self.map(move |v| v.clamp(min, max))
+    }
+
+    /// Runs `f` on the grid computed so far, for operations reading more than one cell
+    pub fn then(mut self, f: impl FnOnce(&Grid) -> Grid + 'static) -> Pipeline {
+        self.stages.push(Stage::Neighborhood(Box::new(f)));
+        self
+    }
+
+    /// Fills depressions, see [`crate::terrain::fill_sinks`]
+    pub fn fill_sinks(self) -> Pipeline {
+        self.then(fill_sinks)
+    }
+
+    /// Gaussian smoothing with a standard deviation of `sigma` cells, see [`Grid::gaussian_smoothed`]
+    pub fn smooth(self, sigma: f64) -> Pipeline {
+        self.then(move |grid| grid.gaussian_smoothed(sigma))
+    }
+
+    /// Replaces the elevations by a terrain derivative
+    pub fn derive(self, derivative: Derivative) -> Pipeline {
+        self.then(move |grid| derivative.compute(grid))
+    }
+
+    /// Runs the stages and returns the resulting grid
+    pub fn collect(self) -> Grid {
+        let mut grid = self.grid;
+        let mut fused: Vec<Box<dyn Fn(f32) -> f32>> = Vec::new();
+        for stage in self.stages {
+            match stage {
+                Stage::Cell(f) => fused.push(f),
+                Stage::Neighborhood(f) => {
+                    apply_cells(&mut grid, &fused);
+                    fused.clear();
+                    grid = f(&grid);
+                }
+            }
+        }
+        apply_cells(&mut grid, &fused);
+        grid
+    }
+
+    /// Hillshade of the resulting grid
+    pub fn hillshade(self, params: ShadeParams) -> Shaded {
+        Shaded { pipeline: self, params }
+    }
+
+    /// Runs the stages and writes the grid as .asc, .tif or .npy depending on the extension
+    #[cfg(feature = "fs")]
+    pub fn save(self, path: &Path) -> io::Result<()> {
+        crate::output::save_grid(&self.collect(), path, false)
+    }
+}
+
+/// One pass over the valid cells applying the fused per-cell stages in order
+fn apply_cells(grid: &mut Grid, fused: &[Box<dyn Fn(f32) -> f32>]) {
+    if fused.is_empty() {
+        return;
+    }
+    for val in grid.valid_mut() {
+        *val = fused.iter().fold(*val, |v, f| f(v));
+    }
+}
+
+/// Hillshade at the end of a [`Pipeline`]
+pub struct Shaded {
+    pipeline: Pipeline,
+    params: ShadeParams,
+}
+
+impl Shaded {
+    /// Hillshade values row-major from the north row, see [`hillshade_values`]
+    pub fn values(self) -> Vec<u8> {
+        hillshade_values(&self.pipeline.collect(), &self.params)
+    }
+
+    /// Grayscale hillshade, black on NoData
+    pub fn image(self) -> GrayImage {
+        let grid = self.pipeline.collect();
+        let values = hillshade_values(&grid, &self.params);
+        GrayImage::from_fn(grid.ncols as u32, grid.nrows as u32, |x, y| Luma([values[y as usize * grid.ncols + x as usize]]))
+    }
+
+    /// Gradient colors over the elevation range of the resulting grid, shaded like the "hillside" product
+    pub fn colored(self, grad: &Gradient) -> RgbImage {
+        let grid = self.pipeline.collect();
+        render_shaded(&grid, grid.min_max(), grad, &self.params)
+    }
+
+    /// Writes the grayscale hillshade as an image of the type the extension names
+    #[cfg(feature = "fs")]
+    pub fn save(self, path: &Path) -> io::Result<()> {
+        self.image().save(path).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+}
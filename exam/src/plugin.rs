@@ -0,0 +1,130 @@
+//! Registry of the raster products computed from a grid, such as the terrain derivatives: a product is a
+//! self-contained module implementing [`Product`], and [`Registry::register`] makes it selectable by name from
+//! `--products` like the built-in ones. The layers products compute from ([`Input`]) are computed once per grid
+//! however many products need them.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use image::DynamicImage;
+
+use crate::grid::Grid;
+use crate::output::{save_image, OutputFormat, OutputTarget, RawFormat};
+use crate::profiling;
+use crate::render::{hillshade_intensities, ShadeParams};
+use crate::ruggedness::Ruggedness;
+use crate::terrain::Derivative;
+use crate::tanaka::Tanaka;
+use crate::terraces::Terraces;
+
+/// A layer products compute from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Input {
+    Derivative(Derivative), // Computed with the edges of the shade parameters
+    Shade,                  // Hillshade intensities from 0 to 1, see [`hillshade_intensities`]
+}
+
+/// The grid, its shade parameters and the layers computed from it so far
+pub struct Inputs<'a> {
+    grid: &'a Grid,
+    params: &'a ShadeParams,
+    layers: RefCell<Vec<(Input, Arc<Grid>)>>,
+}
+
+impl<'a> Inputs<'a> {
+    pub fn new(grid: &'a Grid, params: &'a ShadeParams) -> Inputs<'a> {
+        Inputs { grid, params, layers: RefCell::new(Vec::new()) }
+    }
+
+    /// The elevations
+    pub fn grid(&self) -> &'a Grid {
+        self.grid
+    }
+
+    /// Sun, shader and edges of the run
+    pub fn params(&self) -> &'a ShadeParams {
+        self.params
+    }
+
+    /// The layer `input`, computed on the first call
+    pub fn get(&self, input: Input) -> Arc<Grid> {
+        if let Some((_, layer)) = self.layers.borrow().iter().find(|(i, _)| *i == input) {
+            return Arc::clone(layer);
+        }
+        let layer = Arc::new(match input {
+            Input::Derivative(derivative) => derivative.compute_with(self.grid, self.params.edges),
+            Input::Shade => hillshade_intensities(self.grid, self.params),
+        });
+        self.layers.borrow_mut().push((input, Arc::clone(&layer)));
+        layer
+    }
+}
+
+/// A raster product selectable by name
+pub trait Product: fmt::Debug + Send + Sync {
+    /// Name in --products and of the files written
+    fn name(&self) -> &str;
+
+    /// Layers the product computes from, computed before it in the "derive" stage of the profile
+    fn inputs(&self) -> Vec<Input> {
+        Vec::new()
+    }
+
+    /// The image of the product, for the batch run, `exam render`, map tiles and previews
+    fn render(&self, inputs: &Inputs) -> DynamicImage;
+
+    /// Writes the files of the product, by default its image; `raw` are the formats asked for raw values
+    fn save(&self, inputs: &Inputs, _raw: &[RawFormat], format: &OutputFormat, target: &OutputTarget) -> io::Result<()> {
+        save_image(self.render(inputs), target, self.name(), format)
+    }
+}
+
+/// Computes the inputs of `product` and writes it
+pub fn save_product(product: &dyn Product, inputs: &Inputs, raw: &[RawFormat], format: &OutputFormat, target: &OutputTarget) -> io::Result<()> {
+    profiling::stage("derive", || product.inputs().into_iter().for_each(|input| drop(inputs.get(input))));
+    product.save(inputs, raw, format, target)
+}
+
+/// Finds the product of a name, None if the name isn't its
+type Lookup = Box<dyn Fn(&str) -> Option<Arc<dyn Product>> + Send + Sync>;
+
+/// The products selectable by name
+#[derive(Default)]
+pub struct Registry {
+    lookups: Vec<Lookup>, // The latest registered first wins
+}
+
+impl Registry {
+    /// A registry without products
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// The terrain derivatives, the ruggedness index, the terraces and the illuminated contours
+    pub fn builtin() -> Registry {
+        let mut registry = Registry::new();
+        registry.register_with(|name| Derivative::parse(name).map(|d| Arc::new(d) as Arc<dyn Product>));
+        registry.register(Ruggedness);
+        registry.register_with(|name| Terraces::parse(name).map(|t| Arc::new(t) as Arc<dyn Product>));
+        registry.register_with(|name| Tanaka::parse(name).map(|t| Arc::new(t) as Arc<dyn Product>));
+        registry
+    }
+
+    /// Selects `product` by its name, case-insensitively; it replaces a product of the same name registered earlier
+    pub fn register(&mut self, product: impl Product + 'static) {
+        let product: Arc<dyn Product> = Arc::new(product);
+        self.lookups.push(Box::new(move |name| name.eq_ignore_ascii_case(product.name()).then(|| Arc::clone(&product))));
+    }
+
+    /// Adds products selected by a `lookup` of their names, for products with parameters such as "horizon:<azimuth>"
+    pub fn register_with(&mut self, lookup: impl Fn(&str) -> Option<Arc<dyn Product>> + Send + Sync + 'static) {
+        self.lookups.push(Box::new(lookup));
+    }
+
+    /// The product called `name`
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Product>> {
+        self.lookups.iter().rev().find_map(|lookup| lookup(name))
+    }
+}
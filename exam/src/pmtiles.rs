@@ -0,0 +1,292 @@
+//! Web map tiles: a rendered image in Web Mercator (EPSG:3857) cut into the 256 pixel XYZ tile pyramid and stored as
+//! one PMTiles v3 archive, which MapLibre reads with HTTP range requests from static file hosting, no tile server needed.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use flate2::write::GzEncoder;
+use image::{DynamicImage, Rgba, RgbaImage};
+
+use crate::grid::Grid;
+
+/// Edge of a tile in pixels
+pub const TILE_SIZE: u32 = 256;
+
+/// Deepest zoom written when the grid is finer than its tiles (--max-zoom)
+pub const MAX_ZOOM: u8 = 24;
+
+const EARTH_RADIUS: f64 = 6_378_137.0;
+const HEADER_SIZE: usize = 127;
+// The header and root directory must fit in the first 16 KiB a client fetches
+const ROOT_LIMIT: usize = 16_384 - HEADER_SIZE;
+
+/// Zoom levels of the pyramid
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ZoomRange {
+    pub min: u8,
+    pub max: u8,
+}
+
+impl ZoomRange {
+    /// From the zoom at which the whole grid fits into one tile to the one whose pixels are as large as the cells
+    pub fn native(grid: &Grid) -> ZoomRange {
+        let world = 2.0 * PI * EARTH_RADIUS;
+        let max = (world / (TILE_SIZE as f64 * grid.cellsize)).log2().ceil().clamp(0.0, MAX_ZOOM as f64) as u8;
+        let extent = (grid.ncols.max(grid.nrows) as f64 * grid.cellsize).max(1e-9);
+        let min = (world / extent).log2().floor().clamp(0.0, max as f64) as u8;
+        ZoomRange { min, max }
+    }
+}
+
+/// What was written
+#[derive(Clone, Copy, Debug)]
+pub struct PmtilesStats {
+    pub tiles: usize,  // Tiles with at least one pixel of the grid
+    pub unique: usize, // Different tile contents actually stored
+    pub bytes: u64,    // Size of the archive
+}
+
+/// Tiles of one zoom level covering the columns and rows `x0..=x1`, `y0..=y1` as one image
+struct Level {
+    zoom: u8,
+    x0: u32,
+    y0: u32,
+    canvas: RgbaImage,
+}
+
+/// Position of map coordinates (meters of EPSG:3857) in pixels of the whole world at `zoom`
+fn world_pixel(x: f64, y: f64, zoom: u8) -> (f64, f64) {
+    let size = TILE_SIZE as f64 * 2f64.powi(zoom as i32);
+    ((x / (2.0 * PI * EARTH_RADIUS) + 0.5) * size, (0.5 - y / (2.0 * PI * EARTH_RADIUS)) * size)
+}
+
+/// The deepest level: every pixel takes the image pixel under its center, transparent outside the grid and on NoData
+fn deepest_level(img: &RgbaImage, grid: &Grid, zoom: u8) -> Level {
+    let (x0, y0) = grid.origin();
+    let (left, top) = world_pixel(x0, y0, zoom);
+    let (right, bottom) = world_pixel(x0 + grid.ncols as f64 * grid.cellsize, grid.yllcorner, zoom);
+    let last = (1u32 << zoom) - 1;
+    let tile = |p: f64| ((p / TILE_SIZE as f64).floor().max(0.0) as u32).min(last);
+    let (tx0, ty0, tx1, ty1) = (tile(left), tile(top), tile(right - 1e-6), tile(bottom - 1e-6));
+    let pixel = (2.0 * PI * EARTH_RADIUS) / (TILE_SIZE as f64 * 2f64.powi(zoom as i32));
+    let canvas = RgbaImage::from_fn((tx1 - tx0 + 1) * TILE_SIZE, (ty1 - ty0 + 1) * TILE_SIZE, |px, py| {
+        // Pixel center in map coordinates, then in cells of the grid
+        let x = ((tx0 * TILE_SIZE + px) as f64 + 0.5) * pixel - PI * EARTH_RADIUS;
+        let y = PI * EARTH_RADIUS - ((ty0 * TILE_SIZE + py) as f64 + 0.5) * pixel;
+        let (col, row) = ((x - x0) / grid.cellsize, (y0 - y) / grid.cellsize);
+        if col < 0.0 || row < 0.0 || col >= grid.ncols as f64 || row >= grid.nrows as f64 || !grid.is_valid(col as usize, row as usize) {
+            return Rgba([0, 0, 0, 0]);
+        }
+        *img.get_pixel(col as u32, row as u32)
+    });
+    Level { zoom, x0: tx0, y0: ty0, canvas }
+}
+
+/// The level above: every pixel the mean of the 2x2 pixels below it, weighted by their opacity
+fn parent_level(child: &Level) -> Level {
+    let (x0, y0) = (child.x0 / 2, child.y0 / 2);
+    let (x1, y1) = ((child.x0 + child.canvas.width() / TILE_SIZE - 1) / 2, (child.y0 + child.canvas.height() / TILE_SIZE - 1) / 2);
+    // Offset of the child canvas in pixels of the child level seen from the parent canvas
+    let (ox, oy) = ((child.x0 - 2 * x0) * TILE_SIZE, (child.y0 - 2 * y0) * TILE_SIZE);
+    let canvas = RgbaImage::from_fn((x1 - x0 + 1) * TILE_SIZE, (y1 - y0 + 1) * TILE_SIZE, |px, py| {
+        let (mut sum, mut alpha) = ([0u32; 3], 0u32);
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let (cx, cy) = ((2 * px + dx).wrapping_sub(ox), (2 * py + dy).wrapping_sub(oy));
+            if let Some(&Rgba([r, g, b, a])) = child.canvas.get_pixel_checked(cx, cy) {
+                for (s, c) in sum.iter_mut().zip([r, g, b]) {
+                    *s += c as u32 * a as u32;
+                }
+                alpha += a as u32;
+            }
+        }
+        if alpha == 0 {
+            return Rgba([0, 0, 0, 0]);
+        }
+        let [r, g, b] = sum.map(|s| ((s + alpha / 2) / alpha) as u8);
+        Rgba([r, g, b, ((alpha + 2) / 4) as u8])
+    });
+    Level { zoom: child.zoom - 1, x0, y0, canvas }
+}
+
+/// Position of a tile along the PMTiles order: all tiles of shallower zooms first, then the Hilbert curve of its zoom
+fn tile_id(zoom: u8, mut x: u64, mut y: u64) -> u64 {
+    let mut id = ((1u64 << (2 * zoom as u32)) - 1) / 3;
+    let mut s = 1u64 << zoom >> 1;
+    while s > 0 {
+        let (rx, ry) = (((x & s) > 0) as u64, ((y & s) > 0) as u64);
+        id += s * s * ((3 * rx) ^ ry);
+        // Turn the quadrant so the curve continues in it
+        if ry == 0 {
+            if rx == 1 {
+                x = s.wrapping_sub(1).wrapping_sub(x);
+                y = s.wrapping_sub(1).wrapping_sub(y);
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s >>= 1;
+    }
+    id
+}
+
+/// Directory entry: `run_length` consecutive tiles from `tile_id` sharing the data at `offset`, or a leaf directory
+/// when `run_length` is 0
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    tile_id: u64,
+    offset: u64,
+    length: u32,
+    run_length: u32,
+}
+
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn gzip(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Gzipped directory: count, tile id deltas, run lengths, lengths and offsets column by column
+fn directory(entries: &[Entry]) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    varint(&mut out, entries.len() as u64);
+    let mut last = 0;
+    for entry in entries {
+        varint(&mut out, entry.tile_id - last);
+        last = entry.tile_id;
+    }
+    for entry in entries {
+        varint(&mut out, entry.run_length as u64);
+    }
+    for entry in entries {
+        varint(&mut out, entry.length as u64);
+    }
+    for (i, entry) in entries.iter().enumerate() {
+        // 0 marks data right after the previous entry's
+        let follows = i > 0 && entry.offset == entries[i - 1].offset + entries[i - 1].length as u64;
+        varint(&mut out, if follows { 0 } else { entry.offset + 1 });
+    }
+    gzip(&out)
+}
+
+/// Root directory within [`ROOT_LIMIT`], with leaf directories of growing size when all entries don't fit in it
+fn directories(entries: &[Entry]) -> io::Result<(Vec<u8>, Vec<u8>)> {
+    let root = directory(entries)?;
+    if root.len() <= ROOT_LIMIT {
+        return Ok((root, Vec::new()));
+    }
+    let mut leaf_size = 4096;
+    loop {
+        let mut leaves = Vec::new();
+        let mut root_entries = Vec::new();
+        for chunk in entries.chunks(leaf_size) {
+            let leaf = directory(chunk)?;
+            root_entries.push(Entry { tile_id: chunk[0].tile_id, offset: leaves.len() as u64, length: leaf.len() as u32, run_length: 0 });
+            leaves.extend(leaf);
+        }
+        let root = directory(&root_entries)?;
+        if root.len() <= ROOT_LIMIT {
+            return Ok((root, leaves));
+        }
+        leaf_size *= 2;
+    }
+}
+
+/// Encodes a tile as an RGBA PNG, None when it is fully transparent
+fn encode_tile(canvas: &RgbaImage, tx: u32, ty: u32) -> io::Result<Option<Vec<u8>>> {
+    let tile = image::imageops::crop_imm(canvas, tx * TILE_SIZE, ty * TILE_SIZE, TILE_SIZE, TILE_SIZE).to_image();
+    if tile.pixels().all(|p| p.0[3] == 0) {
+        return Ok(None);
+    }
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, TILE_SIZE, TILE_SIZE);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header().and_then(|mut writer| writer.write_image_data(tile.as_raw()))
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    Ok(Some(png))
+}
+
+/// Writes `img`, rendered from `grid` in EPSG:3857, as PNG tiles of the zooms in `zooms` into a PMTiles archive.
+/// Transparent tiles are left out and identical tiles stored once.
+pub fn save_pmtiles(img: &DynamicImage, grid: &Grid, zooms: ZoomRange, name: &str, path: &Path) -> io::Result<PmtilesStats> {
+    if zooms.min > zooms.max || zooms.max > MAX_ZOOM {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Zooms must run from 0 to {}, the smallest first", MAX_ZOOM)));
+    }
+    let img = img.to_rgba8();
+    let mut tiles: Vec<(u64, Vec<u8>)> = Vec::new();
+    let mut level = deepest_level(&img, grid, zooms.max);
+    loop {
+        for ty in 0..level.canvas.height() / TILE_SIZE {
+            for tx in 0..level.canvas.width() / TILE_SIZE {
+                if let Some(png) = encode_tile(&level.canvas, tx, ty)? {
+                    tiles.push((tile_id(level.zoom, (level.x0 + tx) as u64, (level.y0 + ty) as u64), png));
+                }
+            }
+        }
+        if level.zoom == zooms.min {
+            break;
+        }
+        level = parent_level(&level);
+    }
+    tiles.sort_by_key(|(id, _)| *id);
+
+    // Tile data in id order, a repeated content points at its first copy and consecutive repeats share an entry
+    let mut data = Vec::new();
+    let mut stored: HashMap<&[u8], (u64, u32)> = HashMap::new();
+    let mut entries: Vec<Entry> = Vec::new();
+    for (id, png) in &tiles {
+        let (offset, length) = *stored.entry(png.as_slice()).or_insert_with(|| {
+            data.extend(png);
+            ((data.len() - png.len()) as u64, png.len() as u32)
+        });
+        match entries.last_mut() {
+            Some(last) if last.offset == offset && last.tile_id + last.run_length as u64 == *id => last.run_length += 1,
+            _ => entries.push(Entry { tile_id: *id, offset, length, run_length: 1 }),
+        }
+    }
+
+    let (x0, y0) = grid.origin();
+    let lon_lat = |x: f64, y: f64| ((x / EARTH_RADIUS).to_degrees(), (2.0 * (y / EARTH_RADIUS).exp().atan() - PI / 2.0).to_degrees());
+    let (west, north) = lon_lat(x0, y0);
+    let (east, south) = lon_lat(x0 + grid.ncols as f64 * grid.cellsize, grid.yllcorner);
+    let metadata = gzip(format!("{{\"name\":{:?},\"format\":\"png\",\"type\":\"overlay\",\"bounds\":\"{},{},{},{}\"}}", name, west, south, east, north).as_bytes())?;
+    let (root, leaves) = directories(&entries)?;
+
+    // Header, root directory, metadata, leaf directories and tile data
+    let root_offset = HEADER_SIZE as u64;
+    let metadata_offset = root_offset + root.len() as u64;
+    let leaves_offset = metadata_offset + metadata.len() as u64;
+    let data_offset = leaves_offset + leaves.len() as u64;
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    header.extend(b"PMTiles\x03");
+    for value in [root_offset, root.len() as u64, metadata_offset, metadata.len() as u64, leaves_offset, leaves.len() as u64,
+        data_offset, data.len() as u64, tiles.len() as u64, entries.len() as u64, stored.len() as u64] {
+        header.extend(value.to_le_bytes());
+    }
+    // Clustered, gzipped directories, uncompressed PNG tiles and the zooms
+    header.extend([1, 2, 1, 2, zooms.min, zooms.max]);
+    let e7 = |degrees: f64| ((degrees * 1e7).round() as i32).to_le_bytes();
+    for degrees in [west, south, east, north] {
+        header.extend(e7(degrees));
+    }
+    header.push(zooms.min);
+    header.extend(e7((west + east) / 2.0));
+    header.extend(e7((south + north) / 2.0));
+
+    let mut file = BufWriter::new(File::create(path)?);
+    for part in [&header, &root, &metadata, &leaves, &data] {
+        file.write_all(part)?;
+    }
+    file.flush()?;
+    Ok(PmtilesStats { tiles: tiles.len(), unique: stored.len(), bytes: data_offset + data.len() as u64 })
+}
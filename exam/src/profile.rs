@@ -0,0 +1,58 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use toml::{Table, Value};
+
+/// Config file looked up in the working directory when --config is not given
+pub const DEFAULT_CONFIG: &str = "exam.toml";
+
+/// Turns the `[profiles.<name>]` table of a TOML config into command-line arguments.
+///
+/// Keys are the long flags without `--`, e.g. `image-format = "jpeg"` becomes `--image-format jpeg`.
+/// `true` adds a switch like `--anaglyph`, `false` leaves it out, arrays are joined with commas
+/// (except `time-series`, whose entries stay separate paths).
+pub fn profile_args(config: &Path, name: &str) -> io::Result<Vec<String>> {
+    let text = fs::read_to_string(config).map_err(|e| io::Error::new(e.kind(), format!("{:?}: {}", config, e)))?;
+    let table: Table = text.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}: {}", config, e)))?;
+
+    let profiles = table.get("profiles").and_then(Value::as_table);
+    let profile = profiles.and_then(|p| p.get(name)).and_then(Value::as_table).ok_or_else(|| {
+        let known: Vec<&String> = profiles.map(|p| p.keys().collect()).unwrap_or_default();
+        io::Error::new(io::ErrorKind::NotFound, format!("No profile {:?} in {:?}, available: {:?}", name, config, known))
+    })?;
+
+    let mut args = Vec::new();
+    for (key, value) in profile {
+        let flag = format!("--{}", key);
+        match value {
+            Value::Boolean(true) => args.push(flag),
+            Value::Boolean(false) => {}
+            Value::Array(items) if key == "time-series" => {
+                args.push(flag);
+                for item in items {
+                    args.push(scalar(key, item)?);
+                }
+            }
+            Value::Array(items) => {
+                let list = items.iter().map(|item| scalar(key, item)).collect::<io::Result<Vec<_>>>()?;
+                args.push(flag);
+                args.push(list.join(","));
+            }
+            other => {
+                args.push(flag);
+                args.push(scalar(key, other)?);
+            }
+        }
+    }
+    Ok(args)
+}
+
+/// Text of a string or number value as it would be typed on the command line
+fn scalar(key: &str, value: &Value) -> io::Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unsupported value for {:?} in profile: {}", key, value))),
+    }
+}
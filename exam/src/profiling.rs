@@ -0,0 +1,193 @@
+//! Time and heap peak of the processing stages of every file (`--profiling`), written as `profile.json` into the
+//! output root. Stages nest: the time of a stage excludes the stages run inside it, so the stages of a file add up
+//! to its total, while the heap peak of a stage includes them. Heap sizes need [`TrackingAllocator`] installed as the
+//! global allocator, which the `exam` binary does; without it the peaks are left out.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Instant;
+
+use serde::Serialize;
+
+/// File name of the profile below the output root
+pub const PROFILE_FILE: &str = "profile.json";
+
+static HEAP: AtomicUsize = AtomicUsize::new(0); // Bytes allocated right now
+static PEAK: AtomicUsize = AtomicUsize::new(0); // Highest HEAP since the last reset
+static TRACKING: AtomicBool = AtomicBool::new(false);
+
+/// The system allocator counting the bytes in use and their peak
+pub struct TrackingAllocator;
+
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        HEAP.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = System.realloc(ptr, layout, new_size);
+        if !new.is_null() {
+            HEAP.fetch_sub(layout.size(), Ordering::Relaxed);
+            grow(new_size);
+        }
+        new
+    }
+}
+
+fn grow(size: usize) {
+    let now = HEAP.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(now, Ordering::Relaxed);
+    TRACKING.store(true, Ordering::Relaxed);
+}
+
+/// Starts a new peak at the current heap size, returning the peak so far
+fn reset_peak() -> usize {
+    PEAK.swap(HEAP.load(Ordering::Relaxed), Ordering::Relaxed)
+}
+
+/// Bytes in use on the heap, None unless [`TrackingAllocator`] is the global allocator
+pub fn heap_bytes() -> Option<usize> {
+    TRACKING.load(Ordering::Relaxed).then(|| HEAP.load(Ordering::Relaxed))
+}
+
+/// Totals of one stage
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct StageProfile {
+    pub calls: usize,
+    pub seconds: f64,              // Without the stages nested inside
+    pub peak_bytes: Option<usize>, // Highest heap size while the stage ran
+}
+
+impl StageProfile {
+    fn add(&mut self, other: &StageProfile) {
+        self.calls += other.calls;
+        self.seconds += other.seconds;
+        self.peak_bytes = self.peak_bytes.max(other.peak_bytes);
+    }
+}
+
+/// Stages of one input file
+#[derive(Clone, Debug, Serialize)]
+pub struct FileProfile {
+    pub input: PathBuf,
+    pub seconds: f64,
+    pub peak_bytes: Option<usize>,
+    pub stages: BTreeMap<String, StageProfile>,
+}
+
+/// Stage entered and not left yet
+struct Frame {
+    name: String,
+    start: Instant,
+    nested: f64,       // Seconds spent in the stages inside it
+    outer_peak: usize, // Peak before the stage, restored on leaving it
+}
+
+struct Active {
+    input: PathBuf,
+    start: Instant,
+    outer_peak: usize,
+    frames: Vec<Frame>,
+    stages: BTreeMap<String, StageProfile>,
+}
+
+thread_local! {
+    static ACTIVE: RefCell<Option<Active>> = const { RefCell::new(None) };
+}
+
+/// Starts profiling the stages of `input` on this thread
+pub fn begin(input: &Path) {
+    ACTIVE.with(|active| {
+        *active.borrow_mut() = Some(Active { input: input.to_path_buf(), start: Instant::now(), outer_peak: reset_peak(), frames: Vec::new(), stages: BTreeMap::new() });
+    });
+}
+
+/// Ends the profile started by [`begin`], None if there is none. Stages left open by an error are dropped.
+pub fn end() -> Option<FileProfile> {
+    let active = ACTIVE.with(|active| active.borrow_mut().take())?;
+    let peak = PEAK.load(Ordering::Relaxed);
+    PEAK.fetch_max(active.outer_peak, Ordering::Relaxed);
+    Some(FileProfile {
+        input: active.input,
+        seconds: active.start.elapsed().as_secs_f64(),
+        peak_bytes: TRACKING.load(Ordering::Relaxed).then_some(peak),
+        stages: active.stages,
+    })
+}
+
+/// Runs `f` as the stage `name` of the file being profiled, or just runs it if none is
+pub fn stage<T>(name: &str, f: impl FnOnce() -> T) -> T {
+    let entered = ACTIVE.with(|active| match active.borrow_mut().as_mut() {
+        Some(active) => {
+            active.frames.push(Frame { name: name.to_string(), start: Instant::now(), nested: 0.0, outer_peak: reset_peak() });
+            true
+        }
+        None => false,
+    });
+    let result = f();
+    if entered {
+        ACTIVE.with(|active| {
+            let mut active = active.borrow_mut();
+            let Some(active) = active.as_mut() else { return };
+            let Some(frame) = active.frames.pop() else { return };
+            let elapsed = frame.start.elapsed().as_secs_f64();
+            if let Some(parent) = active.frames.last_mut() {
+                parent.nested += elapsed;
+            }
+            let peak = PEAK.load(Ordering::Relaxed);
+            PEAK.fetch_max(frame.outer_peak, Ordering::Relaxed);
+            active.stages.entry(frame.name).or_default().add(&StageProfile {
+                calls: 1,
+                seconds: elapsed - frame.nested,
+                peak_bytes: TRACKING.load(Ordering::Relaxed).then_some(peak),
+            });
+        });
+    }
+    result
+}
+
+/// Profiles of a run with the totals of every stage over all files
+#[derive(Debug, Default, Serialize)]
+pub struct ProfileReport {
+    pub files: Vec<FileProfile>,
+    pub totals: BTreeMap<String, StageProfile>,
+}
+
+impl ProfileReport {
+    pub fn push(&mut self, file: FileProfile) {
+        for (name, stage) in &file.stages {
+            self.totals.entry(name.clone()).or_default().add(stage);
+        }
+        self.files.push(file);
+    }
+
+    /// One line per stage, the slowest first: seconds, share of the profiled time and heap peak
+    pub fn summary(&self) -> Vec<String> {
+        let total: f64 = self.files.iter().map(|f| f.seconds).sum();
+        let mut stages: Vec<_> = self.totals.iter().collect();
+        stages.sort_by(|a, b| b.1.seconds.total_cmp(&a.1.seconds));
+        stages.into_iter().map(|(name, stage)| {
+            let peak = stage.peak_bytes.map_or(String::new(), |b| format!(", peak {:.1} MiB", b as f64 / (1024.0 * 1024.0)));
+            format!("{:<12} {:>9.3} s {:>5.1} %{}", name, stage.seconds, 100.0 * stage.seconds / total.max(1e-9), peak)
+        }).collect()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, self).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+}
@@ -0,0 +1,59 @@
+//! Progress reports and cancellation of long computations such as [`crate::render::render_with_progress`]: the
+//! computation calls its [`Tracker`] between rows and passes, which hands the share done to the callback of the
+//! embedder and stops the computation with [`Cancelled`] once the embedder sets its flag.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A computation stopped by its cancel flag
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Flag of [`Tracker::none`], never set
+static NEVER: AtomicBool = AtomicBool::new(false);
+
+fn ignore(_: f32) {}
+
+/// The callback and cancel flag of an embedder, and the part of the whole work a step of the computation covers
+#[derive(Clone, Copy)]
+pub struct Tracker<'a> {
+    report: &'a dyn Fn(f32),
+    cancel: &'a AtomicBool,
+    start: f32, // Share of the work done before this step
+    span: f32,  // Share of the work this step is
+}
+
+impl<'a> Tracker<'a> {
+    pub fn new(report: &'a dyn Fn(f32), cancel: &'a AtomicBool) -> Tracker<'a> {
+        Tracker { report, cancel, start: 0.0, span: 1.0 }
+    }
+
+    /// A tracker nobody watches, for the entry points without progress
+    pub fn none() -> Tracker<'static> {
+        Tracker::new(&ignore, &NEVER)
+    }
+
+    /// Reports that `done` (0 to 1) of this step is finished, or fails once the computation is to stop
+    pub fn advance(&self, done: f32) -> Result<(), Cancelled> {
+        if self.cancel.load(Ordering::Relaxed) {
+            return Err(Cancelled);
+        }
+        (self.report)(self.start + self.span * done.clamp(0.0, 1.0));
+        Ok(())
+    }
+
+    /// Part `index` of `parts` equal parts of this step, e.g. one pass of several
+    pub fn part(&self, index: usize, parts: usize) -> Tracker<'a> {
+        // Both ends from the whole step, so a part ends exactly where the next one starts
+        let at = |i: usize| self.start + self.span * i as f32 / parts.max(1) as f32;
+        Tracker { start: at(index), span: at(index + 1) - at(index), ..*self }
+    }
+}
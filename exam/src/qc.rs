@@ -0,0 +1,256 @@
+//! Quality control of elevation models: isolated spikes and pits, large NoData voids and striping,
+//! reported as JSON and as an overlay image so bad tiles are caught before they are published.
+
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io::{self, BufWriter};
+
+#[cfg(feature = "fs")]
+use image::DynamicImage;
+use image::{Rgb, RgbImage};
+
+use crate::grid::Grid;
+#[cfg(feature = "fs")]
+use crate::output::{save_image, OutputFormat, OutputTarget};
+
+/// Spikes listed individually in the report, the count covers all of them
+const MAX_LISTED_SPIKES: usize = 1000;
+
+const SPIKE_COLOR: Rgb<u8> = Rgb([230, 30, 30]);
+const VOID_COLOR: Rgb<u8> = Rgb([40, 110, 240]);
+const STRIPE_COLOR: Rgb<u8> = Rgb([250, 200, 0]);
+
+/// Thresholds of the checks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QcSettings {
+    pub sigma: f64,            // A cell is a spike when it departs from its neighbors by more than this many of their standard deviations (--qc-sigma)
+    pub min_void_cells: usize, // Smallest connected NoData region reported as a void (--qc-void)
+}
+
+impl Default for QcSettings {
+    fn default() -> Self {
+        QcSettings { sigma: 6.0, min_void_cells: 100 }
+    }
+}
+
+/// A cell far above or below its valid neighbors
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "fs", derive(serde::Serialize))]
+pub struct Spike {
+    pub col: usize,
+    pub row: usize,
+    pub value: f32,
+    pub expected: f32, // Median of the valid neighbors
+    pub sigmas: f64,   // Departure in standard deviations of the neighbors, negative for pits
+}
+
+/// A connected region of NoData cells
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "fs", derive(serde::Serialize))]
+pub struct Void {
+    pub cells: usize,
+    pub bounds: [usize; 4], // First column, first row, last column, last row
+    pub touches_edge: bool, // Likely the outline of the survey rather than a hole
+}
+
+/// Findings of [`QcReport::check`]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "fs", derive(serde::Serialize))]
+pub struct QcReport {
+    pub residual_sigma: f64,  // Robust spread of the cell-to-neighbors differences, the smallest unit spikes are measured in
+    pub spike_count: usize,
+    pub spikes: Vec<Spike>,   // The strongest first, at most MAX_LISTED_SPIKES
+    pub voids: Vec<Void>,     // Largest first
+    pub stripe_rows: Vec<usize>,
+    pub stripe_cols: Vec<usize>,
+}
+
+impl QcReport {
+    pub fn check(grid: &Grid, settings: &QcSettings) -> QcReport {
+        // Residual of every valid cell against the median of its valid 8 neighbors, which unlike the mean
+        // isn't pulled by a spike next door, so the neighbors of a spike or a stripe aren't flagged with it
+        let mut residuals = vec![None; grid.ncols * grid.nrows];
+        let mut neighbors = Vec::with_capacity(8);
+        for y in 0..grid.nrows {
+            for x in 0..grid.ncols {
+                let v = grid.data[y][x];
+                if grid.is_nodata(v) {
+                    continue;
+                }
+                neighbors.clear();
+                for ny in y.saturating_sub(1)..(y + 2).min(grid.nrows) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(grid.ncols) {
+                        let w = grid.data[ny][nx];
+                        if (nx, ny) != (x, y) && !grid.is_nodata(w) {
+                            neighbors.push(w as f64);
+                        }
+                    }
+                }
+                // Cells with fewer valid neighbors than on a straight border have no reliable expectation
+                if neighbors.len() >= 5 {
+                    neighbors.sort_by(f64::total_cmp);
+                    let n = neighbors.len();
+                    let expected = (neighbors[(n - 1) / 2] + neighbors[n / 2]) / 2.0;
+                    let mean = neighbors.iter().sum::<f64>() / n as f64;
+                    let spread = (neighbors.iter().map(|w| (w - mean).powi(2)).sum::<f64>() / n as f64).sqrt();
+                    residuals[y * grid.ncols + x] = Some((v as f64 - expected, expected as f32, spread));
+                }
+            }
+        }
+        let values: Vec<f64> = residuals.iter().flatten().map(|&(r, _, _)| r).collect();
+        let residual_sigma = robust_sigma(&values).max(1e-6);
+
+        let mut spikes: Vec<Spike> = residuals.iter().enumerate()
+            .filter_map(|(i, r)| {
+                // Measured against the spread of the neighbors, so steep or rough terrain isn't flagged,
+                // but at least against the typical residual, so flat ground doesn't flag centimeters
+                let (residual, expected, spread) = (*r)?;
+                let sigmas = residual / spread.max(residual_sigma);
+                (sigmas.abs() > settings.sigma).then(|| Spike { col: i % grid.ncols, row: i / grid.ncols, value: grid.data[i / grid.ncols][i % grid.ncols], expected, sigmas })
+            })
+            .collect();
+        spikes.sort_by(|a, b| b.sigmas.abs().total_cmp(&a.sigmas.abs()));
+        let spike_count = spikes.len();
+        spikes.truncate(MAX_LISTED_SPIKES);
+
+        // A stripe is a row (column) whose median residual stands out from the medians of all rows (columns).
+        // The outermost lines only see neighbors on one side, on a slope their residuals are biased.
+        let line_medians = |lines: usize, cell: &dyn Fn(usize, usize) -> Option<f64>, length: usize| -> Vec<Option<f64>> {
+            (0..lines).map(|line| {
+                if line == 0 || line + 1 == lines {
+                    return None;
+                }
+                let mut values: Vec<f64> = (0..length).filter_map(|i| cell(line, i)).collect();
+                (values.len() * 2 > length).then(|| {
+                    values.sort_by(f64::total_cmp);
+                    values[values.len() / 2]
+                })
+            }).collect()
+        };
+        let residual = |x: usize, y: usize| residuals[y * grid.ncols + x].map(|(r, _, _)| r);
+        let stripes = |medians: Vec<Option<f64>>| -> Vec<usize> {
+            let spread = robust_sigma(&medians.iter().flatten().copied().collect::<Vec<_>>());
+            // A whole line offset by less than the typical cell residual is within the noise (and perfectly smooth grids have no spread)
+            let limit = (settings.sigma * spread).max(residual_sigma);
+            // The lines next to a stripe see it among their neighbors and stand out too, only the strongest of a run counts
+            let strength = |i: usize| medians.get(i).copied().flatten().map_or(0.0, f64::abs);
+            (0..medians.len())
+                .filter(|&i| strength(i) > limit && strength(i) >= strength(i.wrapping_sub(1)) && strength(i) >= strength(i + 1))
+                .collect()
+        };
+        let stripe_rows = stripes(line_medians(grid.nrows, &|y, x| residual(x, y), grid.ncols));
+        let stripe_cols = stripes(line_medians(grid.ncols, &|x, y| residual(x, y), grid.nrows));
+
+        QcReport { residual_sigma, spike_count, spikes, voids: voids(grid, settings.min_void_cells), stripe_rows, stripe_cols }
+    }
+
+    /// One line per kind of problem found, for the warnings of the run report
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if self.spike_count > 0 {
+            lines.push(format!("QC: {} spikes or pits, the strongest {:.1} sigma at column {}, row {}",
+                self.spike_count, self.spikes[0].sigmas, self.spikes[0].col, self.spikes[0].row));
+        }
+        let holes: Vec<&Void> = self.voids.iter().filter(|v| !v.touches_edge).collect();
+        if !holes.is_empty() {
+            lines.push(format!("QC: {} NoData holes, the largest {} cells", holes.len(), holes[0].cells));
+        }
+        if !self.stripe_rows.is_empty() || !self.stripe_cols.is_empty() {
+            lines.push(format!("QC: striping in {} rows and {} columns", self.stripe_rows.len(), self.stripe_cols.len()));
+        }
+        lines
+    }
+
+    /// Dimmed grayscale elevation with voids in blue, stripes in yellow and spikes (drawn 3x3 to stay visible) in red
+    pub fn overlay(&self, grid: &Grid) -> RgbImage {
+        let gray = crate::render::render_grayscale(grid);
+        let mut img = RgbImage::from_fn(grid.ncols as u32, grid.nrows as u32, |x, y| {
+            let v = 60 + gray.get_pixel(x, y).0[0] as u32 * 120 / 255;
+            Rgb([v as u8; 3])
+        });
+        for (y, row) in grid.data.iter().enumerate() {
+            for (x, &val) in row.iter().enumerate() {
+                if grid.is_nodata(val) && self.voids.iter().any(|v| contains(&v.bounds, x, y)) {
+                    img.put_pixel(x as u32, y as u32, VOID_COLOR);
+                }
+            }
+        }
+        for &row in &self.stripe_rows {
+            (0..grid.ncols).for_each(|x| img.put_pixel(x as u32, row as u32, STRIPE_COLOR));
+        }
+        for &col in &self.stripe_cols {
+            (0..grid.nrows).for_each(|y| img.put_pixel(col as u32, y as u32, STRIPE_COLOR));
+        }
+        for spike in &self.spikes {
+            for y in spike.row.saturating_sub(1)..(spike.row + 2).min(grid.nrows) {
+                for x in spike.col.saturating_sub(1)..(spike.col + 2).min(grid.ncols) {
+                    img.put_pixel(x as u32, y as u32, SPIKE_COLOR);
+                }
+            }
+        }
+        img
+    }
+}
+
+/// Runs the checks, writes the report as `qc.json` and the overlay as product "qc"
+#[cfg(feature = "fs")]
+pub fn save_qc(grid: &Grid, settings: &QcSettings, format: &OutputFormat, target: &OutputTarget) -> io::Result<QcReport> {
+    let report = QcReport::check(grid, settings);
+    let file = BufWriter::new(File::create(target.path("qc", "json")?)?);
+    serde_json::to_writer_pretty(file, &report).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    save_image(DynamicImage::ImageRgb8(report.overlay(grid)), target, "qc", format)?;
+    Ok(report)
+}
+
+fn contains(&[c0, r0, c1, r1]: &[usize; 4], x: usize, y: usize) -> bool {
+    (c0..=c1).contains(&x) && (r0..=r1).contains(&y)
+}
+
+/// 1.4826 times the median absolute deviation, the standard deviation of normal data that outliers don't inflate
+fn robust_sigma(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let median = |v: &mut Vec<f64>| {
+        v.sort_by(f64::total_cmp);
+        v[v.len() / 2]
+    };
+    let center = median(&mut values.to_vec());
+    1.4826 * median(&mut values.iter().map(|v| (v - center).abs()).collect())
+}
+
+/// Connected (4-neighbor) NoData regions of at least `min_cells` cells, largest first
+fn voids(grid: &Grid, min_cells: usize) -> Vec<Void> {
+    let mut seen = vec![false; grid.ncols * grid.nrows];
+    let mut voids = Vec::new();
+    for start in 0..seen.len() {
+        if seen[start] || grid.is_valid(start % grid.ncols, start / grid.ncols) {
+            continue;
+        }
+        seen[start] = true;
+        let mut stack = vec![start];
+        let (mut cells, mut bounds, mut touches_edge) = (0, [usize::MAX, usize::MAX, 0, 0], false);
+        while let Some(i) = stack.pop() {
+            let (x, y) = (i % grid.ncols, i / grid.ncols);
+            cells += 1;
+            bounds = [bounds[0].min(x), bounds[1].min(y), bounds[2].max(x), bounds[3].max(y)];
+            touches_edge |= x == 0 || y == 0 || x + 1 == grid.ncols || y + 1 == grid.nrows;
+            let neighbors = [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)];
+            for (nx, ny) in neighbors {
+                if nx < grid.ncols && ny < grid.nrows {
+                    let j = ny * grid.ncols + nx;
+                    if !seen[j] && !grid.is_valid(nx, ny) {
+                        seen[j] = true;
+                        stack.push(j);
+                    }
+                }
+            }
+        }
+        if cells >= min_cells {
+            voids.push(Void { cells, bounds, touches_edge });
+        }
+    }
+    voids.sort_by_key(|v| std::cmp::Reverse(v.cells));
+    voids
+}
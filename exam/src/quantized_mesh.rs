@@ -0,0 +1,207 @@
+//! Cesium quantized-mesh terrain: a `layer.json` plus `<z>/<x>/<y>.terrain` tiles of the geographic TMS pyramid
+//! (two tiles at level 0), each tile a TIN of its area, so the DEM streams into CesiumJS globes.
+
+use std::f64::consts::PI;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::grid::{Grid, Interpolation};
+use crate::output::Conflict;
+use crate::tin::Tin;
+
+/// Samples across a tile edge the TIN of a tile is built from, the heightmap size Cesium uses
+pub const TILE_SAMPLES: usize = 65;
+
+/// Deepest level written when the grid is finer than its tiles (--max-level)
+pub const MAX_LEVEL: u32 = 20;
+
+const WGS84_A: f64 = 6_378_137.0;
+const WGS84_B: f64 = 6_356_752.314_245_179;
+const FAR_OCCLUSION: f64 = 1e6; // Ellipsoid radii
+
+/// Geometric error of level 0 as estimated by Cesium for a 65 sample heightmap; every level halves it.
+/// Each tile is triangulated to within the error of its level.
+fn geometric_error(level: u32) -> f64 {
+    WGS84_A * 2.0 * PI * 0.25 / (TILE_SAMPLES as f64 * 2.0) / 2f64.powi(level as i32)
+}
+
+/// Level whose samples are about as far apart as the cells of a grid `cellsize` degrees wide
+pub fn native_level(cellsize: f64) -> u32 {
+    let level = (180.0 / ((TILE_SAMPLES - 1) as f64 * cellsize)).log2().ceil();
+    level.clamp(0.0, MAX_LEVEL as f64) as u32
+}
+
+/// Writes the tiles of levels 0 to `max_level` covering the grid (in longitude/latitude degrees) and `layer.json` into `dir`.
+/// Outside the grid and on NoData the terrain lies on the ellipsoid, at height 0. Existing files are handled as `conflict`
+/// says. Returns the number of tiles.
+pub fn save_terrain_tiles(grid: &Grid, max_level: u32, dir: &Path, conflict: Conflict) -> io::Result<usize> {
+    if !grid.is_geographic() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Terrain tiles need a grid in longitude/latitude"));
+    }
+    let (west, north) = grid.origin();
+    let (east, south) = (west + grid.ncols as f64 * grid.cellsize, grid.yllcorner);
+    let mut available = Vec::new();
+    let mut count = 0;
+    for level in 0..=max_level {
+        let size = 180.0 / 2f64.powi(level as i32);
+        // Level 0 is always complete, Cesium loads both of its tiles first
+        let (x0, x1, y0, y1) = if level == 0 {
+            (0, 1, 0, 0)
+        } else {
+            let column = |lon: f64| (((lon + 180.0) / size).floor() as i64).clamp(0, (2 << level) - 1) as u32;
+            let row = |lat: f64| (((lat + 90.0) / size).floor() as i64).clamp(0, (1 << level) - 1) as u32;
+            (column(west), column(east - 1e-9), row(south), row(north - 1e-9))
+        };
+        for x in x0..=x1 {
+            for y in y0..=y1 {
+                let (tile_west, tile_south) = (-180.0 + x as f64 * size, -90.0 + y as f64 * size);
+                let tile = tile_grid(grid, tile_west, tile_south, size);
+                let tin = Tin::greedy(&tile, geometric_error(level), None).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                let path = dir.join(format!("{}/{}/{}.terrain", level, x, y));
+                fs::create_dir_all(path.parent().unwrap())?;
+                let mut file = BufWriter::new(conflict.create(&path)?.0);
+                write_tile(&tin, (tile_west, tile_south, size), &mut file)?;
+                file.flush()?;
+                count += 1;
+            }
+        }
+        available.push(format!("[{{\"startX\":{},\"startY\":{},\"endX\":{},\"endY\":{}}}]", x0, y0, x1, y1));
+    }
+    let layer = format!(
+        "{{\n  \"tilejson\": \"2.1.0\",\n  \"name\": \"exam\",\n  \"version\": \"1.0.0\",\n  \"format\": \"quantized-mesh-1.0\",\n  \
+        \"scheme\": \"tms\",\n  \"tiles\": [\"{{z}}/{{x}}/{{y}}.terrain?v={{version}}\"],\n  \"projection\": \"EPSG:4326\",\n  \
+        \"bounds\": [{}, {}, {}, {}],\n  \"minzoom\": 0,\n  \"maxzoom\": {},\n  \"available\": [\n    {}\n  ]\n}}\n",
+        west, south, east, north, max_level, available.join(",\n    "));
+    conflict.create(&dir.join("layer.json"))?.0.write_all(layer.as_bytes())?;
+    Ok(count)
+}
+
+/// The tile's TILE_SAMPLES x TILE_SAMPLES samples from its west to east and south to north edge, as a grid whose cell
+/// centers are the samples. Samples outside the grid or on NoData are 0.
+fn tile_grid(grid: &Grid, west: f64, south: f64, size: f64) -> Grid {
+    let step = size / (TILE_SAMPLES - 1) as f64;
+    let data = (0..TILE_SAMPLES)
+        .map(|row| (0..TILE_SAMPLES)
+            .map(|col| grid.sample(west + col as f64 * step, south + size - row as f64 * step, Interpolation::Bilinear).unwrap_or(0.0))
+            .collect())
+        .collect();
+    Grid { data, ncols: TILE_SAMPLES, nrows: TILE_SAMPLES, nodata: f32::MIN, cellsize: step,
+        xllcorner: west - step / 2.0, yllcorner: south - step / 2.0, geographic: Some(true) }
+}
+
+/// Earth-centered, earth-fixed coordinates of a WGS 84 position
+fn ecef(lon: f64, lat: f64, height: f64) -> [f64; 3] {
+    let (lon, lat) = (lon.to_radians(), lat.to_radians());
+    let e2 = 1.0 - (WGS84_B * WGS84_B) / (WGS84_A * WGS84_A);
+    let n = WGS84_A / (1.0 - e2 * lat.sin().powi(2)).sqrt();
+    [(n + height) * lat.cos() * lon.cos(), (n + height) * lat.cos() * lon.sin(), (n * (1.0 - e2) + height) * lat.sin()]
+}
+
+/// Point in ellipsoid-scaled coordinates above `center` that, once below the horizon, hides the whole tile
+/// (Cesium's EllipsoidalOccluder)
+fn horizon_occlusion_point(points: &[[f64; 3]], center: [f64; 3]) -> [f64; 3] {
+    let scale = |p: [f64; 3]| [p[0] / WGS84_A, p[1] / WGS84_A, p[2] / WGS84_B];
+    let norm = |p: [f64; 3]| (p[0] * p[0] + p[1] * p[1] + p[2] * p[2]).sqrt();
+    let c = scale(center);
+    let direction = c.map(|v| v / norm(c));
+    let mut magnitude: f64 = 0.0;
+    for &p in points {
+        let s = scale(p);
+        let length = norm(s).max(1.0);
+        let d = s.map(|v| v / norm(s));
+        let cos_alpha = d[0] * direction[0] + d[1] * direction[1] + d[2] * direction[2];
+        let cross = [d[1] * direction[2] - d[2] * direction[1], d[2] * direction[0] - d[0] * direction[2], d[0] * direction[1] - d[1] * direction[0]];
+        let sin_alpha = norm(cross);
+        let cos_beta = 1.0 / length;
+        let sin_beta = (length * length - 1.0).sqrt() * cos_beta;
+        let denominator = cos_alpha * cos_beta - sin_alpha * sin_beta;
+        // A point at or beyond 90 degrees from the center, as on the level 0 tiles, can't be hidden behind any point
+        // on the center's direction; a far point then keeps the tile from ever being culled
+        magnitude = magnitude.max(if denominator > 1e-9 { 1.0 / denominator } else { FAR_OCCLUSION });
+    }
+    direction.map(|v| v * magnitude)
+}
+
+/// The TIN with its vertices renumbered in the order the triangles first use them, as high-water mark encoding
+/// of the indices requires; vertices of no triangle go last
+fn first_use_order(tin: &Tin) -> Tin {
+    let mut renumbered = vec![usize::MAX; tin.vertices.len()];
+    let mut order = Vec::with_capacity(tin.vertices.len());
+    for i in tin.triangles.iter().flatten().copied().chain(0..tin.vertices.len()) {
+        if renumbered[i] == usize::MAX {
+            renumbered[i] = order.len();
+            order.push(i);
+        }
+    }
+    Tin {
+        vertices: order.iter().map(|&i| tin.vertices[i]).collect(),
+        triangles: tin.triangles.iter().map(|t| t.map(|i| renumbered[i])).collect(),
+    }
+}
+
+/// Encodes one tile: header, zigzag-delta vertices, high-water-mark indices and the edge vertex lists
+fn write_tile<W: Write>(tin: &Tin, (west, south, size): (f64, f64, f64), out: &mut W) -> io::Result<()> {
+    let tin = &first_use_order(tin);
+    let (min_height, max_height) = tin.vertices.iter().fold((f64::MAX, f64::MIN), |(lo, hi), v| (lo.min(v[2]), hi.max(v[2])));
+    let quantize = |value: f64, low: f64, span: f64| if span > 0.0 { ((value - low) / span * 32767.0).round().clamp(0.0, 32767.0) as u16 } else { 0 };
+    let u: Vec<u16> = tin.vertices.iter().map(|v| quantize(v[0], west, size)).collect();
+    let v: Vec<u16> = tin.vertices.iter().map(|v| quantize(v[1], south, size)).collect();
+    let h: Vec<u16> = tin.vertices.iter().map(|v| quantize(v[2], min_height, max_height - min_height)).collect();
+
+    // Header: center, height range, bounding sphere and horizon occlusion point
+    let points: Vec<[f64; 3]> = tin.vertices.iter().map(|v| ecef(v[0], v[1], v[2])).collect();
+    let (low, high) = points.iter().fold(([f64::MAX; 3], [f64::MIN; 3]), |(lo, hi), p| {
+        (std::array::from_fn(|i| lo[i].min(p[i])), std::array::from_fn(|i| hi[i].max(p[i])))
+    });
+    let center: [f64; 3] = std::array::from_fn(|i| (low[i] + high[i]) / 2.0);
+    let radius = points.iter().map(|p| ((p[0] - center[0]).powi(2) + (p[1] - center[1]).powi(2) + (p[2] - center[2]).powi(2)).sqrt()).fold(0.0, f64::max);
+    let mut bytes = Vec::new();
+    for value in center {
+        bytes.extend(value.to_le_bytes());
+    }
+    bytes.extend((min_height as f32).to_le_bytes());
+    bytes.extend((max_height as f32).to_le_bytes());
+    // The middle of a level 0 tile's box is near the center of the earth, the occlusion point goes above the middle of the tile
+    let above = ecef(west + size / 2.0, south + size / 2.0, 0.0);
+    for value in center.iter().chain(&[radius]).chain(&horizon_occlusion_point(&points, above)) {
+        bytes.extend(value.to_le_bytes());
+    }
+
+    // Vertices, every coordinate as the zigzag-encoded difference to the previous vertex
+    bytes.extend((tin.vertices.len() as u32).to_le_bytes());
+    for values in [&u, &v, &h] {
+        let mut previous = 0i32;
+        for &value in values.iter() {
+            let delta = value as i32 - previous;
+            bytes.extend((((delta << 1) ^ (delta >> 31)) as u16).to_le_bytes());
+            previous = value as i32;
+        }
+    }
+
+    // Indices with 16 bits up to 65536 vertices, 32 aligned to 4 bytes otherwise
+    let wide = tin.vertices.len() > 65536;
+    let index = |bytes: &mut Vec<u8>, i: u32| if wide { bytes.extend(i.to_le_bytes()) } else { bytes.extend((i as u16).to_le_bytes()) };
+    if wide && bytes.len() % 4 != 0 {
+        bytes.resize(bytes.len() + 4 - bytes.len() % 4, 0);
+    }
+    bytes.extend((tin.triangles.len() as u32).to_le_bytes());
+    let mut highest = 0;
+    for &i in tin.triangles.iter().flatten() {
+        index(&mut bytes, highest - i as u32);
+        if i as u32 == highest {
+            highest += 1;
+        }
+    }
+
+    // Vertices on the west, south, east and north edge, for the skirts hiding cracks between tiles
+    for edge in [u.iter().map(|&x| x == 0).collect::<Vec<_>>(), v.iter().map(|&y| y == 0).collect(),
+        u.iter().map(|&x| x == 32767).collect(), v.iter().map(|&y| y == 32767).collect()] {
+        let on_edge: Vec<u32> = (0..edge.len() as u32).filter(|&i| edge[i as usize]).collect();
+        bytes.extend((on_edge.len() as u32).to_le_bytes());
+        for i in on_edge {
+            index(&mut bytes, i);
+        }
+    }
+    out.write_all(&bytes)
+}
@@ -0,0 +1,131 @@
+//! Reclassification of elevations into integer classes with rules like `<0 => -1; 0..1000 => 1; >1000 => 2`.
+
+use image::{Rgb, RgbImage};
+
+use crate::grid::Grid;
+
+/// NoData value of reclassified grids, for cells that no rule matches
+pub const CLASS_NODATA: f32 = -9999.0;
+
+/// Range of values a rule applies to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Condition {
+    Less(f64),         // <x
+    LessEqual(f64),    // <=x
+    Greater(f64),      // >x
+    GreaterEqual(f64), // >=x
+    Range(f64, f64),   // a..b, including a and excluding b
+    Equal(f64),        // x
+    Any,               // * or else, matches every valid cell
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Option<Condition> {
+        let text = text.trim();
+        let number = |s: &str| s.trim().parse::<f64>().ok();
+        if let Some(v) = text.strip_prefix("<=") {
+            return number(v).map(Condition::LessEqual);
+        }
+        if let Some(v) = text.strip_prefix(">=") {
+            return number(v).map(Condition::GreaterEqual);
+        }
+        if let Some(v) = text.strip_prefix('<') {
+            return number(v).map(Condition::Less);
+        }
+        if let Some(v) = text.strip_prefix('>') {
+            return number(v).map(Condition::Greater);
+        }
+        if text == "*" || text == "else" {
+            return Some(Condition::Any);
+        }
+        if let Some((lo, hi)) = text.split_once("..") {
+            return Some(Condition::Range(number(lo)?, number(hi)?));
+        }
+        number(text).map(Condition::Equal)
+    }
+
+    pub fn matches(&self, v: f64) -> bool {
+        match *self {
+            Condition::Less(x) => v < x,
+            Condition::LessEqual(x) => v <= x,
+            Condition::Greater(x) => v > x,
+            Condition::GreaterEqual(x) => v >= x,
+            Condition::Range(lo, hi) => v >= lo && v < hi,
+            Condition::Equal(x) => v == x,
+            Condition::Any => true,
+        }
+    }
+}
+
+/// Ordered rules, the first one matching a cell decides its class
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reclass {
+    pub rules: Vec<(Condition, i32)>,
+}
+
+impl Reclass {
+    /// Parses `condition => class` rules separated by `;` or new lines
+    pub fn parse(text: &str) -> Result<Reclass, String> {
+        let mut rules = Vec::new();
+        for rule in text.split([';', '\n']).map(str::trim).filter(|r| !r.is_empty()) {
+            let (condition, class) = rule.split_once("=>").ok_or_else(|| format!("Rule {:?} lacks \"=>\"", rule))?;
+            let condition = Condition::parse(condition).ok_or_else(|| format!("Invalid condition {:?}", condition.trim()))?;
+            let class: i32 = class.trim().parse().map_err(|_| format!("Class {:?} is not an integer", class.trim()))?;
+            if class as f32 == CLASS_NODATA {
+                return Err(format!("Class {} is reserved for NoData", class));
+            }
+            rules.push((condition, class));
+        }
+        if rules.is_empty() {
+            return Err("No reclassification rules given".into());
+        }
+        Ok(Reclass { rules })
+    }
+
+    /// Class of a value, None if no rule matches
+    pub fn classify(&self, v: f64) -> Option<i32> {
+        self.rules.iter().find(|(condition, _)| condition.matches(v)).map(|&(_, class)| class)
+    }
+
+    /// Integer class grid with the georeferencing of `grid`; NoData and unmatched cells become [`CLASS_NODATA`]
+    pub fn apply(&self, grid: &Grid) -> Grid {
+        let data = grid.data.iter()
+            .map(|row| row.iter().map(|&v| {
+                if grid.is_nodata(v) { None } else { self.classify(v as f64) }.map_or(CLASS_NODATA, |class| class as f32)
+            }).collect())
+            .collect();
+        Grid { data, nodata: CLASS_NODATA, ..grid.clone() }
+    }
+
+    /// Distinct classes in rule order
+    pub fn classes(&self) -> Vec<i32> {
+        let mut classes = Vec::new();
+        for &(_, class) in &self.rules {
+            if !classes.contains(&class) {
+                classes.push(class);
+            }
+        }
+        classes
+    }
+
+    /// Preview of a grid returned by [`Reclass::apply`], one evenly spaced turbo color per class, NoData is black
+    pub fn render(&self, classes: &Grid) -> RgbImage {
+        let order = self.classes();
+        let grad = colorgrad::turbo();
+        let colors: Vec<Rgb<u8>> = (0..order.len())
+            .map(|i| {
+                let (r, g, b, _) = grad.at((i as f64 + 0.5) / order.len() as f64).rgba();
+                Rgb([(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8])
+            })
+            .collect();
+
+        let mut img = RgbImage::new(classes.ncols as u32, classes.nrows as u32);
+        for (y, row) in classes.data.iter().enumerate() {
+            for (x, &v) in row.iter().enumerate() {
+                let color = order.iter().position(|&class| class as f32 == v).map_or(Rgb([0, 0, 0]), |i| colors[i]);
+                img.put_pixel(x as u32, y as u32, color);
+            }
+        }
+        img
+    }
+}
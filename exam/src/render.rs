@@ -0,0 +1,821 @@
+#[cfg(feature = "fs")]
+use std::io;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::atomic::AtomicBool;
+use image::{DynamicImage, GrayImage, Luma, Rgb, RgbImage, Rgba, RgbaImage};
+#[cfg(feature = "fs")]
+use image::{ImageBuffer, ImageFormat};
+use colorgrad::Gradient;
+
+#[cfg(feature = "fs")]
+use crate::contrast::{render_grayscale_stretched, Stretch};
+use crate::grid::{EdgeMode, Elevation, Grid, Mask};
+#[cfg(feature = "fs")]
+use crate::output::{save_image, save_png_rows, save_raw_values, OutputFormat, OutputTarget, RawFormat};
+use crate::progress::{Cancelled, Tracker};
+use crate::shading::{lit_intensities, lit_intensities_tracked, ShaderKind};
+use crate::terrain::horn_gradient;
+
+/// Names accepted by [`colormap`]
+pub const COLORMAPS: &[&str] = &["viridis", "magma", "inferno", "plasma", "cividis", "turbo", "spectral", "greys", "batlow", "iridescent", "ylorbr"];
+
+/// Colormaps that stay ordered and distinguishable under every color vision deficiency,
+/// lightness rises monotonically so even achromats read the elevation order
+pub const CVD_SAFE_COLORMAPS: &[&str] = &["cividis", "viridis", "batlow", "iridescent", "ylorbr"];
+
+/// Looks up a colorgrad preset or one of the curated terrain palettes by name
+pub fn colormap(name: &str) -> Option<Gradient> {
+    match name.to_lowercase().as_str() {
+        "viridis" => Some(colorgrad::viridis()),
+        "magma" => Some(colorgrad::magma()),
+        "inferno" => Some(colorgrad::inferno()),
+        "plasma" => Some(colorgrad::plasma()),
+        "cividis" => Some(colorgrad::cividis()),
+        "turbo" => Some(colorgrad::turbo()),
+        "spectral" => Some(colorgrad::spectral()),
+        "greys" => Some(colorgrad::greys()),
+        // Crameri's batlow, a perceptually uniform scientific colormap
+        "batlow" => stops(&[0x011959, 0x0E365F, 0x1C5061, 0x3E6A54, 0x687A3C, 0x928533, 0xC49245, 0xEEA077, 0xFCB7B8, 0xFACCFA]),
+        // Paul Tol's iridescent, pale low ground to dark purple high ground
+        "iridescent" => stops(&[0xFEFBE9, 0xF5F3C1, 0xDDECBF, 0xC2E3D2, 0xA8D8DC, 0x8DCBE4, 0x7BBCE7, 0x88A5DD, 0x9B8AC4, 0x9A709E, 0x805770, 0x46353A]),
+        // Paul Tol's YlOrBr, pale lowlands to dark brown mountains
+        "ylorbr" => stops(&[0xFFFFE5, 0xFFF7BC, 0xFEE391, 0xFEC44F, 0xFB9A29, 0xEC7014, 0xCC4C02, 0x993404, 0x662506]),
+        _ => None,
+    }
+}
+
+/// Gradient through evenly spaced 0xRRGGBB colors
+fn stops(colors: &[u32]) -> Option<Gradient> {
+    let colors: Vec<colorgrad::Color> = colors.iter().map(|&c| colorgrad::Color::from_rgba8((c >> 16) as u8, (c >> 8) as u8, c as u8, 255)).collect();
+    colorgrad::CustomGradient::new().colors(&colors).build().ok()
+}
+
+/// Sun position, vertical scaling and method of the hillshade
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "fs", derive(serde::Serialize))]
+pub struct ShadeParams {
+    pub azimuth: f64,  // Degrees clockwise from north
+    pub altitude: f64, // Degrees above the horizon
+    pub z_factor: f64, // Vertical exaggeration
+    pub cell_size: (f64, f64), // East-west and north-south cell spacing in elevation units, see [`SHADE_CELL_SIZE`]
+    pub mode: ShadeMode,
+    pub aerial_perspective: f64, // Strength from 0 (off) to 1 of the haze over low ground, see [`apply_aerial_perspective`]
+    pub edges: EdgeMode,         // Neighbors of the border cells outside the grid
+    pub shader: ShaderKind,      // Shading method, Horn's hillshade unless --shader names another
+    pub fill: Option<FillLight>, // Second light softening the shadows of the sun, none by default
+    pub slope_saturation: f64,   // Strength from 0 (off) to 1 of the graying of steep slopes, see [`apply_slope_saturation`]
+}
+
+impl Default for ShadeParams {
+    fn default() -> Self {
+        ShadeParams { azimuth: 315.0, altitude: 45.0, z_factor: 1.0, cell_size: (SHADE_CELL_SIZE, SHADE_CELL_SIZE), mode: ShadeMode::default(), aerial_perspective: 0.0, edges: EdgeMode::default(), shader: ShaderKind::default(), fill: None, slope_saturation: 0.0 }
+    }
+}
+
+/// A second light shading the relief with the sun, so slopes facing away from the sun aren't black (--fill-weight)
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "fs", derive(serde::Serialize))]
+pub struct FillLight {
+    pub azimuth: f64,  // Degrees clockwise from north
+    pub altitude: f64, // Degrees above the horizon
+    pub weight: f64,   // Share of the fill in the light of a cell from 0 to 1, the sun has the rest
+}
+
+impl FillLight {
+    /// Weight unless --fill-weight gives one
+    pub const DEFAULT_WEIGHT: f64 = 0.3;
+
+    /// The default fill of a sun: opposite it at the same altitude
+    pub fn opposite(params: &ShadeParams) -> FillLight {
+        FillLight { azimuth: (params.azimuth + 180.0).rem_euclid(360.0), altitude: params.altitude, weight: FillLight::DEFAULT_WEIGHT }
+    }
+}
+
+/// How the relief is shaded (--shading)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "fs", derive(serde::Serialize), serde(rename_all = "lowercase"))]
+pub enum ShadeMode {
+    #[default]
+    Standard,                    // Horn's hillshade of the grid as is
+    MultiScale { levels: u32 },  // Mean of the hillshades of `levels` progressively smoothed grids, see [`multiscale_values`]
+}
+
+impl ShadeMode {
+    /// Levels of "multiscale" unless given as "multiscale:<levels>"
+    pub const DEFAULT_LEVELS: u32 = 4;
+
+    /// Parses "standard", "multiscale" or "multiscale:<levels>"
+    pub fn parse(name: &str) -> Option<ShadeMode> {
+        let name = name.to_lowercase();
+        match name.split_once(':') {
+            None if name == "standard" => Some(ShadeMode::Standard),
+            None if name == "multiscale" => Some(ShadeMode::MultiScale { levels: ShadeMode::DEFAULT_LEVELS }),
+            Some(("multiscale", levels)) => levels.parse().ok().filter(|&l| (1..=8).contains(&l)).map(|levels| ShadeMode::MultiScale { levels }),
+            _ => None,
+        }
+    }
+
+    /// Name as accepted by [`ShadeMode::parse`]
+    pub fn name(&self) -> String {
+        match self {
+            ShadeMode::Standard => "standard".into(),
+            ShadeMode::MultiScale { levels } => format!("multiscale:{}", levels),
+        }
+    }
+}
+
+/// Gamma, brightness and contrast applied to the normalized pixel values of a finished image
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "fs", derive(serde::Serialize))]
+pub struct Tone {
+    pub gamma: f32,      // Values v in 0..1 become v^(1/gamma), above 1 lightens the midtones
+    pub brightness: f32, // Added after the gamma, -1 to 1
+    pub contrast: f32,   // Factor around mid-gray after the gamma, 1 keeps the contrast
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone { gamma: 1.0, brightness: 0.0, contrast: 1.0 }
+    }
+}
+
+impl Tone {
+    pub fn is_identity(&self) -> bool {
+        *self == Tone::default()
+    }
+
+    /// The adjusted 8-bit value of every 8-bit value
+    pub fn lookup(&self) -> [u8; 256] {
+        let mut lut = [0u8; 256];
+        for (i, out) in lut.iter_mut().enumerate() {
+            let v = (i as f32 / 255.0).powf(1.0 / self.gamma);
+            *out = (((v - 0.5) * self.contrast + 0.5 + self.brightness).clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+        lut
+    }
+
+    /// Adjusts the color channels of `img` in place, alpha is left alone
+    pub fn apply(&self, img: &mut DynamicImage) {
+        if self.is_identity() {
+            return;
+        }
+        let lut = self.lookup();
+        match img {
+            DynamicImage::ImageLuma8(img) => img.pixels_mut().for_each(|p| p.0[0] = lut[p.0[0] as usize]),
+            DynamicImage::ImageLumaA8(img) => img.pixels_mut().for_each(|p| p.0[0] = lut[p.0[0] as usize]),
+            DynamicImage::ImageRgb8(img) => img.pixels_mut().flat_map(|p| p.0.iter_mut()).for_each(|c| *c = lut[*c as usize]),
+            DynamicImage::ImageRgba8(img) => img.pixels_mut().flat_map(|p| p.0[..3].iter_mut()).for_each(|c| *c = lut[*c as usize]),
+            _ => {}
+        }
+    }
+}
+
+/// Elevations mapped to the ends of the color scale in place of each grid's own min and max (--elevation-range),
+/// so a color stands for the same elevation in every file and run
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ElevationRange {
+    pub min: f32,
+    pub max: f32,
+    pub mark_outside: bool, // Paint cells outside the range in OUT_OF_RANGE_COLOR instead of clamping them to the end colors
+}
+
+/// Color of the cells outside a pinned [`ElevationRange`] with `mark_outside`
+pub const OUT_OF_RANGE_COLOR: Rgb<u8> = Rgb([255, 0, 255]);
+
+impl ElevationRange {
+    /// Parses `min,max`, clamping cells outside of it
+    pub fn parse(text: &str) -> Result<ElevationRange, String> {
+        let (min, max) = text.split_once(',')
+            .and_then(|(min, max)| Some((min.trim().parse::<f32>().ok()?, max.trim().parse::<f32>().ok()?)))
+            .ok_or_else(|| format!("Elevation range expects <min,max>, got {:?}", text))?;
+        if !min.is_finite() || !max.is_finite() || min >= max {
+            return Err(format!("Elevation range {}..{} must have min below max", min, max));
+        }
+        Ok(ElevationRange { min, max, mark_outside: false })
+    }
+
+    /// Paints the cells outside the range in [`OUT_OF_RANGE_COLOR`] if `mark_outside` is set
+    pub fn paint_outside(&self, img: &mut RgbImage, grid: &Grid) {
+        if !self.mark_outside {
+            return;
+        }
+        for (y, row) in grid.data.iter().enumerate() {
+            for (x, &val) in row.iter().enumerate() {
+                if grid.is_valid(x, y) && self.marks(val) {
+                    img.put_pixel(x as u32, y as u32, OUT_OF_RANGE_COLOR);
+                }
+            }
+        }
+    }
+
+    /// Whether a valid cell of elevation `val` is painted by [`ElevationRange::paint_outside`]
+    pub fn marks(&self, val: f32) -> bool {
+        self.mark_outside && (val < self.min || val > self.max)
+    }
+}
+
+/// Products whose pixels are the grid cells, the ones a [`NodataStyle`] applies to
+pub const NODATA_STYLE_PRODUCTS: [&str; 14] = ["grayscale", "colored", "hillside", "slope", "aspect", "curvature", "flow_accumulation", "twi", "spi", "horizon", "ruggedness", "styled", "terraces", "tanaka"];
+
+/// Side in pixels of the squares of [`NodataStyle::Checkerboard`]
+const CHECKER_SIZE: u32 = 8;
+
+/// How NoData cells look in the images (--nodata-style)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NodataStyle {
+    #[default]
+    Black,          // As rendered, black like the lowest gray value
+    Transparent,    // Alpha 0, for formats with an alpha channel (PNG, TIFF, WebP, AVIF), black in the others
+    Color(Rgb<u8>), // A fixed color, e.g. #ff00ff
+    Checkerboard,   // Light and dark gray squares, like image editors show transparency
+    Infill,         // Color of the nearest valid cell, for display only: the values stay NoData
+}
+
+impl NodataStyle {
+    /// Parses "black", "transparent", "checkerboard", "infill" or a color as "#rrggbb"
+    pub fn parse(text: &str) -> Result<NodataStyle, String> {
+        match text.to_lowercase().as_str() {
+            "black" => Ok(NodataStyle::Black),
+            "transparent" | "none" => Ok(NodataStyle::Transparent),
+            "checkerboard" | "checker" => Ok(NodataStyle::Checkerboard),
+            "infill" | "nearest" => Ok(NodataStyle::Infill),
+            hex if hex.len() == 7 && hex.starts_with('#') && hex[1..].chars().all(|c| c.is_ascii_hexdigit()) => {
+                let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or_default();
+                Ok(NodataStyle::Color(Rgb([channel(1), channel(3), channel(5)])))
+            }
+            other => Err(format!("Unknown NoData style {:?}, expected black, transparent, checkerboard, infill or #rrggbb", other)),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            NodataStyle::Black => "black".into(),
+            NodataStyle::Transparent => "transparent".into(),
+            NodataStyle::Color(Rgb([r, g, b])) => format!("#{:02x}{:02x}{:02x}", r, g, b),
+            NodataStyle::Checkerboard => "checkerboard".into(),
+            NodataStyle::Infill => "infill".into(),
+        }
+    }
+
+    /// Opaque color of a NoData pixel at column `x` and row `y`, None for the styles that don't paint one color per pixel
+    pub fn pixel(&self, x: u32, y: u32) -> Option<Rgb<u8>> {
+        match self {
+            NodataStyle::Color(color) => Some(*color),
+            NodataStyle::Checkerboard => {
+                let v = if (x / CHECKER_SIZE + y / CHECKER_SIZE).is_multiple_of(2) { 204 } else { 153 };
+                Some(Rgb([v, v, v]))
+            }
+            NodataStyle::Black | NodataStyle::Transparent | NodataStyle::Infill => None,
+        }
+    }
+
+    /// Restyles the pixels of `img` that are NoData in `mask`; images of another size are left alone
+    pub fn apply(&self, img: &mut DynamicImage, mask: &Mask) {
+        if *self == NodataStyle::Black || (img.width() as usize, img.height() as usize) != mask.size() {
+            return;
+        }
+        // Grayscale images stay grayscale unless the color isn't a gray
+        let gray = matches!(img, DynamicImage::ImageLuma8(_) | DynamicImage::ImageLumaA8(_))
+            && !matches!(self, NodataStyle::Color(Rgb([r, g, b])) if r != g || g != b);
+        let mut rgba = img.to_rgba8();
+        match self {
+            NodataStyle::Black => {}
+            NodataStyle::Transparent => paint(&mut rgba, mask, |_, _| Rgba([0, 0, 0, 0])),
+            NodataStyle::Color(_) | NodataStyle::Checkerboard => paint(&mut rgba, mask, |x, y| {
+                let Rgb([r, g, b]) = self.pixel(x, y).unwrap_or(Rgb([0, 0, 0]));
+                Rgba([r, g, b, 255])
+            }),
+            NodataStyle::Infill => infill(&mut rgba, mask),
+        }
+        *img = match (gray, *self == NodataStyle::Transparent) {
+            (true, true) => DynamicImage::ImageLumaA8(DynamicImage::ImageRgba8(rgba).to_luma_alpha8()),
+            (true, false) => DynamicImage::ImageLuma8(DynamicImage::ImageRgba8(rgba).to_luma8()),
+            (false, true) => DynamicImage::ImageRgba8(rgba),
+            (false, false) => DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba).to_rgb8()),
+        };
+    }
+}
+
+/// Sets every NoData pixel to `color(x, y)`
+fn paint(img: &mut RgbaImage, mask: &Mask, color: impl Fn(u32, u32) -> Rgba<u8>) {
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        if !mask.get(x as usize, y as usize) {
+            *pixel = color(x, y);
+        }
+    }
+}
+
+/// Gives every NoData pixel the color of the nearest valid one, spreading outward from the valid pixels ring by ring
+fn infill(img: &mut RgbaImage, mask: &Mask) {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let mut filled: Vec<bool> = (0..width * height).map(|i| mask.get(i % width, i / width)).collect();
+    let mut queue: VecDeque<usize> = (0..width * height).filter(|&i| filled[i]).collect();
+    while let Some(i) = queue.pop_front() {
+        let (x, y) = (i % width, i / width);
+        let color = *img.get_pixel(x as u32, y as u32);
+        for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1), (-1, -1), (1, -1), (-1, 1), (1, 1)] {
+            let (nx, ny) = (x as isize + dx, y as isize + dy);
+            if nx < 0 || ny < 0 || nx as usize >= width || ny as usize >= height || filled[ny as usize * width + nx as usize] {
+                continue;
+            }
+            let n = ny as usize * width + nx as usize;
+            filled[n] = true;
+            img.put_pixel(nx as u32, ny as u32, color);
+            queue.push_back(n);
+        }
+    }
+}
+
+/// NoData style of each product: one for all, optionally overridden by product (--nodata-style)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NodataStyles {
+    pub default: NodataStyle,
+    pub products: Vec<(String, NodataStyle)>, // Product names from NODATA_STYLE_PRODUCTS
+}
+
+impl NodataStyles {
+    /// Parses a style for every product, product=style pairs, or both, comma separated: "checkerboard,colored=transparent".
+    /// "hillshade" stands for its "hillside" image and "flow" for "flow_accumulation".
+    pub fn parse(text: &str) -> Result<NodataStyles, String> {
+        let mut styles = NodataStyles::default();
+        for item in text.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            match item.split_once('=') {
+                Some((product, style)) => {
+                    let product = match product.trim().to_lowercase().as_str() {
+                        "hillshade" => "hillside".to_string(),
+                        "flow" => "flow_accumulation".to_string(),
+                        name => name.to_string(),
+                    };
+                    if !NODATA_STYLE_PRODUCTS.contains(&product.as_str()) {
+                        return Err(format!("No NoData style for product {:?}, expected one of {:?}", product, NODATA_STYLE_PRODUCTS));
+                    }
+                    styles.products.push((product, NodataStyle::parse(style.trim())?));
+                }
+                None => styles.default = NodataStyle::parse(item)?,
+            }
+        }
+        Ok(styles)
+    }
+
+    /// Style of the `product` image, black for products whose pixels aren't grid cells
+    pub fn of(&self, product: &str) -> NodataStyle {
+        // The plain shade layer looks like the hillshade image
+        let product = if product == "hillshade" { "hillside" } else { product };
+        if !NODATA_STYLE_PRODUCTS.contains(&product) {
+            return NodataStyle::Black;
+        }
+        self.products.iter().rev().find(|(name, _)| name == product).map_or(self.default, |&(_, style)| style)
+    }
+}
+
+/// Range the colors are stretched over: the pinned range if any, the valid min and max of the grid otherwise
+pub fn color_range(grid: &Grid, pinned: Option<&ElevationRange>) -> (f32, f32) {
+    pinned.map_or_else(|| grid.min_max(), |r| (r.min, r.max))
+}
+
+/// Counts the valid cells below and above `range`
+pub fn out_of_range_cells(grid: &Grid, (min, max): (f32, f32)) -> (usize, usize) {
+    grid.iter_valid().fold((0, 0), |(below, above), (_, _, val)| (below + (val < min) as usize, above + (val > max) as usize))
+}
+
+/// Finds min and max elevation values, ignoring NoData
+pub fn find_min_max(data: &[Vec<f32>], nodata: f32) -> (f32, f32) {
+    let mut min = f32::MAX;
+    let mut max = f32::MIN;
+    for row in data {
+        for &val in row {
+            if val != nodata && !val.is_nan() {
+                min = min.min(val);
+                max = max.max(val);
+            }
+        }
+    }
+    (min, max)
+}
+
+/// Generates and saves the grayscale elevation image, contrast-stretched by `stretch`, as product "grayscale"
+#[cfg(feature = "fs")]
+pub fn save_grayscale_image(grid: &Grid, stretch: Stretch, format: &OutputFormat, target: &OutputTarget) -> io::Result<()> {
+    let img = render_grayscale_stretched(grid, color_range(grid, target.elevation_range.as_ref()), stretch);
+    save_image(DynamicImage::ImageLuma8(img), target, "grayscale", format)
+}
+
+/// Renders the elevation stretched between min and max as black to white, NoData is black
+pub fn render_grayscale<T: Elevation>(grid: &Grid<T>) -> GrayImage {
+    render_grayscale_range(grid, grid.min_max())
+}
+
+/// Renders the elevation stretched over `range` as black to white, values outside are clamped, NoData is black
+pub fn render_grayscale_range<T: Elevation>(grid: &Grid<T>, (min, max): (f32, f32)) -> GrayImage {
+    let mut img = GrayImage::new(grid.ncols as u32, grid.nrows as u32);
+
+    for (y, row) in grid.data.iter().enumerate() {
+        for (x, &val) in row.iter().enumerate() {
+            let pixel = if !grid.is_valid(x, y) {
+                0
+            } else {
+                ((val.to_f32() - min) / (max - min) * 255.0).clamp(0.0, 255.0) as u8
+            };
+            img.put_pixel(x as u32, y as u32, Luma([pixel]));
+        }
+    }
+
+    img
+}
+
+/// Generates and saves the color-mapped elevation image as product "colored"
+#[cfg(feature = "fs")]
+pub fn save_colored_image(grid: &Grid, grad: &Gradient, format: &OutputFormat, target: &OutputTarget) -> io::Result<()> {
+    let range = color_range(grid, target.elevation_range.as_ref());
+    if target.streams("colored", format) {
+        return save_png_rows(target, "colored", format, grid.ncols, grid.nrows, |y, row| {
+            for (x, pixel) in row.chunks_exact_mut(3).enumerate() {
+                let val = grid.data[y][x];
+                let color = match &target.elevation_range {
+                    _ if !grid.is_valid(x, y) => Rgb([0, 0, 0]),
+                    Some(pinned) if pinned.marks(val) => OUT_OF_RANGE_COLOR,
+                    _ => gradient_color(grad, val, range),
+                };
+                pixel.copy_from_slice(&color.0);
+            }
+        });
+    }
+    let mut img = render_colored_range(grid, grad, range);
+    if let Some(pinned) = &target.elevation_range {
+        pinned.paint_outside(&mut img, grid);
+    }
+    save_image(DynamicImage::ImageRgb8(img), target, "colored", format)
+}
+
+/// Renders the elevation stretched between min and max through the color gradient, NoData is black
+pub fn render_colored<T: Elevation>(grid: &Grid<T>, grad: &Gradient) -> RgbImage {
+    render_colored_range(grid, grad, grid.min_max())
+}
+
+/// Renders the elevation stretched over `range` through the color gradient, values outside are clamped, NoData is black
+pub fn render_colored_range<T: Elevation>(grid: &Grid<T>, grad: &Gradient, (min, max): (f32, f32)) -> RgbImage {
+    let mut img = RgbImage::new(grid.ncols as u32, grid.nrows as u32);
+
+    for (y, row) in grid.data.iter().enumerate() {
+        for (x, &val) in row.iter().enumerate() {
+            let rgb = if !grid.is_valid(x, y) { Rgb([0, 0, 0]) } else { gradient_color(grad, val.to_f32(), (min, max)) };
+            img.put_pixel(x as u32, y as u32, rgb);
+        }
+    }
+
+    img
+}
+
+/// Color of an elevation stretched over `range` through the gradient, values outside clamped to the end colors
+fn gradient_color(grad: &Gradient, val: f32, (min, max): (f32, f32)) -> Rgb<u8> {
+    let norm = (val - min) / (max - min);
+    let (r, g, b, _) = grad.at(norm.clamp(0.0, 1.0) as f64).rgba();
+    Rgb([(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8])
+}
+
+/// Generates and saves the hillshaded color image, lit by the sun of the target, as product "hillside".
+/// PNGs are streamed row by row when the target allows it, holding only the hillshade values instead of the image.
+#[cfg(feature = "fs")]
+pub fn save_color_hillshade_image(grid: &Grid, grad: &Gradient, backend: &Backend, format: &OutputFormat, target: &OutputTarget) -> io::Result<()> {
+    let range = color_range(grid, target.elevation_range.as_ref());
+    if target.streams("hillside", format) {
+        let shade = backend.hillshade_values(grid, &target.shade);
+        let steepness = steepness(grid, &target.shade);
+        let strength = target.shade.aerial_perspective.clamp(0.0, 1.0) as f32;
+        return save_png_rows(target, "hillside", format, grid.ncols, grid.nrows, |y, row| {
+            for (x, pixel) in row.chunks_exact_mut(3).enumerate() {
+                let val = grid.data[y][x];
+                let color = match &target.elevation_range {
+                    _ if !grid.is_valid(x, y) => Rgb([0, 0, 0]),
+                    Some(pinned) if pinned.marks(val) => OUT_OF_RANGE_COLOR,
+                    _ => {
+                        let mut color = shade_color(gradient_color(grad, val, range), shade[y * grid.ncols + x]);
+                        if let Some(steepness) = &steepness {
+                            gray_slope(&mut color, steepness[y * grid.ncols + x]);
+                        }
+                        haze(&mut color, val, range, strength);
+                        color
+                    }
+                };
+                pixel.copy_from_slice(&color.0);
+            }
+        });
+    }
+    let mut img = backend.render_shaded(grid, range, grad, &target.shade);
+    if let Some(pinned) = &target.elevation_range {
+        pinned.paint_outside(&mut img, grid);
+    }
+    save_image(DynamicImage::ImageRgb8(img), target, "hillside", format)
+}
+
+/// Renders the gradient colors (normalized to `range`) shaded by Horn's hillshade with the given sun and z-factor
+pub fn render_shaded(grid: &Grid, range: (f32, f32), grad: &Gradient, params: &ShadeParams) -> RgbImage {
+    let mut img = blend_shade(grid, range, grad, &hillshade_values(grid, params));
+    apply_slope_saturation(&mut img, grid, params);
+    apply_aerial_perspective(&mut img, grid, range, params.aerial_perspective);
+    img
+}
+
+/// Slope from which [`apply_slope_saturation`] grays a cell fully, in degrees
+const STEEP_SLOPE: f64 = 45.0;
+
+/// Imhof's refinement of the color blend: steep slopes lose saturation and darken toward a neutral rock gray while
+/// flat ground keeps the full palette, so the colors read as elevation on the plains and the relief speaks on the
+/// slopes. The slope is that of the hillshade, by the cell size and z-factor of `params`, and the strength comes from
+/// [`ShadeParams::slope_saturation`], from 0 (unchanged) to 1.
+pub fn apply_slope_saturation(img: &mut RgbImage, grid: &Grid, params: &ShadeParams) {
+    let Some(steepness) = steepness(grid, params) else { return };
+    for (x, y, _) in grid.iter_valid() {
+        gray_slope(img.get_pixel_mut(x as u32, y as u32), steepness[y * grid.ncols + x]);
+    }
+}
+
+/// How much [`apply_slope_saturation`] grays every cell row-major, from 0 (flat) to its strength (steep), None when
+/// it is off
+fn steepness(grid: &Grid, params: &ShadeParams) -> Option<Vec<f32>> {
+    let strength = params.slope_saturation.clamp(0.0, 1.0);
+    if strength == 0.0 {
+        return None;
+    }
+    Some(grid.iter_cells().map(|(x, y, _)| grid.edge_window(x, y, params.edges).map_or(0.0, |w| {
+        let (dzdx, dzdy) = horn_gradient(&w, params.cell_size);
+        let slope = (dzdx.hypot(dzdy) * params.z_factor).atan().to_degrees();
+        (strength * (slope / STEEP_SLOPE).min(1.0)) as f32
+    })).collect())
+}
+
+/// Desaturates a pixel toward its luma and darkens it by `steepness` from 0 to 1
+fn gray_slope(pixel: &mut Rgb<u8>, steepness: f32) {
+    if steepness == 0.0 {
+        return;
+    }
+    let [r, g, b] = pixel.0.map(|c| c as f32);
+    let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+    let (saturation, value) = (1.0 - 0.8 * steepness, 1.0 - 0.3 * steepness);
+    for c in pixel.0.iter_mut() {
+        *c = ((luma + (*c as f32 - luma) * saturation) * value).round().clamp(0.0, 255.0) as u8;
+    }
+}
+
+/// Color the low ground fades into under [`apply_aerial_perspective`], a pale blue-gray
+const HAZE: [f32; 3] = [200.0, 212.0, 228.0];
+
+/// Atmospheric depth cue on a shaded image: cells near the bottom of `range` fade into a pale haze and lose contrast,
+/// cells near the top are lightened, so the eye reads height as nearness. `strength` runs from 0 (unchanged) to 1.
+pub fn apply_aerial_perspective(img: &mut RgbImage, grid: &Grid, (min, max): (f32, f32), strength: f64) {
+    let strength = strength.clamp(0.0, 1.0) as f32;
+    if strength == 0.0 {
+        return;
+    }
+    for (y, row) in grid.data.iter().enumerate() {
+        for (x, &val) in row.iter().enumerate() {
+            if grid.is_valid(x, y) {
+                haze(img.get_pixel_mut(x as u32, y as u32), val, (min, max), strength);
+            }
+        }
+    }
+}
+
+/// The aerial perspective of [`apply_aerial_perspective`] on one pixel of a valid cell
+fn haze(pixel: &mut Rgb<u8>, val: f32, (min, max): (f32, f32), strength: f32) {
+    if strength == 0.0 {
+        return;
+    }
+    let height = ((val - min) / (max - min)).clamp(0.0, 1.0);
+    let haze = 0.6 * strength * (1.0 - height);
+    let light = 0.25 * strength * height;
+    for (c, h) in pixel.0.iter_mut().zip(HAZE) {
+        let hazy = *c as f32 * (1.0 - haze) + h * haze;
+        *c = (hazy + (255.0 - hazy) * light).round() as u8;
+    }
+}
+
+/// Horizontal cell spacing assumed by the hillshade of projected grids. Grids in degrees use their ground spacing in
+/// meters instead, and warps scale [`ShadeParams::cell_size`] like the ground size of the cells.
+pub const SHADE_CELL_SIZE: f64 = 30.0;
+
+/// Computes the hillshade of every cell, row-major from the north row, 0 on NoData, with the shader and in the shading
+/// mode of `params`
+pub fn hillshade_values<T: Elevation>(grid: &Grid<T>, params: &ShadeParams) -> Vec<u8> {
+    match params.mode {
+        ShadeMode::Standard => shader_values(grid, params),
+        // The smoothed levels need fractions
+        ShadeMode::MultiScale { levels } => multiscale_values(&grid.to_f32(), levels, |level| shader_values(level, params)),
+    }
+}
+
+/// 8-bit values of the shader of `params`; Horn's runs on any value type without converting the grid
+fn shader_values<T: Elevation>(grid: &Grid<T>, params: &ShadeParams) -> Vec<u8> {
+    match params.shader {
+        ShaderKind::Horn if params.fill.is_none() => horn_values(grid, params),
+        kind => lit_intensities(kind.shader().as_ref(), &grid.to_f32(), params).into_iter().map(|v| v.map_or(0, |v| (255.0 * v) as u8)).collect(),
+    }
+}
+
+/// Multi-scale relief: the hillshades of the grid box-filtered with radii 0, 2, 4, 8, ... cells, one per level, averaged.
+/// The smoothed levels carry the large landforms, the first keeps the fine detail on top of them.
+/// `shade` computes the hillshade of one level.
+pub fn multiscale_values(grid: &Grid, levels: u32, shade: impl Fn(&Grid) -> Vec<u8>) -> Vec<u8> {
+    let Ok(values) = try_multiscale_values(grid, levels, |_, level| Ok::<_, Infallible>(shade(level)));
+    values
+}
+
+/// [`multiscale_values`] with a `shade` that also gets the number of the level and may fail
+fn try_multiscale_values<E>(grid: &Grid, levels: u32, mut shade: impl FnMut(u32, &Grid) -> Result<Vec<u8>, E>) -> Result<Vec<u8>, E> {
+    let mut total = vec![0u32; grid.ncols * grid.nrows];
+    for level in 0..levels.max(1) {
+        let values = if level == 0 { shade(level, grid)? } else { shade(level, &grid.smoothed(1 << level))? };
+        for (t, v) in total.iter_mut().zip(values) {
+            *t += v as u32;
+        }
+    }
+    Ok(total.into_iter().map(|t| (t / levels.max(1)) as u8).collect())
+}
+
+/// [`hillshade_values`] for embedders showing a progress bar: `progress` gets the share done from 0 to 1 as rows,
+/// horizon sweeps and multi-scale levels finish, and setting `cancel` from another thread stops the computation with
+/// [`Cancelled`] at the next row instead of running it to the end
+pub fn render_with_progress(grid: &Grid, params: &ShadeParams, progress: impl Fn(f32), cancel: &AtomicBool) -> Result<Vec<u8>, Cancelled> {
+    let tracker = Tracker::new(&progress, cancel);
+    let shader = params.shader.shader();
+    let shade = |level: &Grid, tracker: &Tracker| -> Result<Vec<u8>, Cancelled> {
+        Ok(lit_intensities_tracked(shader.as_ref(), level, params, tracker)?.into_iter().map(|v| v.map_or(0, |v| (255.0 * v) as u8)).collect())
+    };
+    match params.mode {
+        ShadeMode::Standard => shade(grid, &tracker),
+        ShadeMode::MultiScale { levels } => {
+            try_multiscale_values(grid, levels, |level, smoothed| shade(smoothed, &tracker.part(level as usize, levels.max(1) as usize)))
+        }
+    }
+}
+
+fn horn_values<T: Elevation>(grid: &Grid<T>, params: &ShadeParams) -> Vec<u8> {
+    horn_intensities(grid, params).map(|v| v.map_or(0, |v| (255.0 * v) as u8)).collect()
+}
+
+/// Horn's [`shade_intensity`] of every cell row-major from the north row, None where it isn't computed
+fn horn_intensities<'a, T: Elevation>(grid: &'a Grid<T>, params: &'a ShadeParams) -> impl Iterator<Item = Option<f64>> + 'a {
+    grid.iter_cells().map(|(x, y, _)| {
+        let w = grid.edge_window(x, y, params.edges)?;
+        // NoData neighbors inside the grid are left out of the gradient
+        let (dzdx, dzdy) = horn_gradient(&w, params.cell_size);
+        Some(shade_intensity(dzdx * params.z_factor, dzdy * params.z_factor, params.azimuth, params.altitude))
+    })
+}
+
+/// The hillshade as a grid of intensities from 0 (facing away from the sun) to 1 (facing it) instead of the 8 bits of
+/// [`hillshade_values`], NoData where those are 0 for a cell that isn't computed. Multi-scale levels are averaged alike.
+pub fn hillshade_intensities(grid: &Grid, params: &ShadeParams) -> Grid {
+    let shader = params.shader.shader();
+    let levels = match params.mode {
+        ShadeMode::Standard => 1,
+        ShadeMode::MultiScale { levels } => levels.max(1),
+    };
+    let mut total = vec![Some(0.0); grid.ncols * grid.nrows];
+    for level in 0..levels {
+        let smoothed;
+        let level_grid = if level == 0 { grid } else { smoothed = grid.smoothed(1 << level); &smoothed };
+        for (t, v) in total.iter_mut().zip(lit_intensities(shader.as_ref(), level_grid, params)) {
+            *t = t.zip(v).map(|(t, v)| t + v);
+        }
+    }
+    let data = total.chunks(grid.ncols.max(1))
+        .map(|row| row.iter().map(|t| t.map_or(grid.nodata, |t| (t / levels as f64) as f32)).collect())
+        .collect();
+    Grid { data, ..grid.clone() }
+}
+
+/// Writes the plain shade layer to composite in other tools: "hillshade" as an 8-bit grayscale image,
+/// "hillshade_16" as a 16-bit grayscale PNG and "hillshade_f32" with the intensities from 0 to 1 in the `raw` formats.
+/// NoData is 0 in the images and the grid's NoData in the raw files.
+#[cfg(feature = "fs")]
+pub fn save_shade_layers(grid: &Grid, backend: &Backend, raw: &[RawFormat], format: &OutputFormat, target: &OutputTarget) -> io::Result<()> {
+    let values = backend.hillshade_values(grid, &target.shade);
+    let img = GrayImage::from_raw(grid.ncols as u32, grid.nrows as u32, values).expect("one value per cell");
+    save_image(DynamicImage::ImageLuma8(img), target, "hillshade", format)?;
+
+    let intensities = hillshade_intensities(grid, &target.shade);
+    let deep = ImageBuffer::from_fn(grid.ncols as u32, grid.nrows as u32, |x, y| {
+        let v = intensities.data[y as usize][x as usize];
+        Luma([if intensities.is_nodata(v) { 0 } else { (v * 65535.0).round() as u16 }])
+    });
+    let path = target.path("hillshade_16", "png")?;
+    crate::profiling::stage("encode", || DynamicImage::ImageLuma16(deep).save_with_format(path, ImageFormat::Png))
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    save_raw_values(&intensities, "hillshade_f32", raw, format, target)
+}
+
+/// Darkens the gradient colors (normalized to `range`) by precomputed hillshade values from [`hillshade_values`]
+pub fn blend_shade(grid: &Grid, range: (f32, f32), grad: &Gradient, shade: &[u8]) -> RgbImage {
+    let (min, max) = range;
+    let mut img = RgbImage::new(grid.ncols as u32, grid.nrows as u32);
+
+    for (y, row) in grid.data.iter().enumerate() {
+        for (x, &val) in row.iter().enumerate() {
+            let base_color = if !grid.is_valid(x, y) { Rgb([0, 0, 0]) } else { gradient_color(grad, val, (min, max)) };
+
+            img.put_pixel(x as u32, y as u32, shade_color(base_color, shade[y * grid.ncols + x]));
+        }
+    }
+
+    img
+}
+
+/// Darkens a base color by a hillshade value from [`hillshade_values`], the blend of [`blend_shade`]
+pub fn shade_color(color: Rgb<u8>, shade: u8) -> Rgb<u8> {
+    let factor = shade as f32 / 255.0;
+    Rgb(color.0.map(|c| (c as f32 * (1.0 - factor)) as u8))
+}
+
+/// Where the hillshade values are computed
+#[derive(Default)]
+pub enum Backend {
+    #[default]
+    Cpu,
+    #[cfg(feature = "gpu")]
+    Gpu(crate::gpu::GpuHillshader),
+}
+
+impl Backend {
+    /// Same as [`hillshade_values`], on the GPU if selected. A failing GPU falls back to the CPU.
+    pub fn hillshade_values(&self, grid: &Grid, params: &ShadeParams) -> Vec<u8> {
+        if let ShadeMode::MultiScale { levels } = params.mode {
+            let single = ShadeParams { mode: ShadeMode::Standard, ..*params };
+            return multiscale_values(grid, levels, |level| self.hillshade_values(level, &single));
+        }
+        match self {
+            Backend::Cpu => hillshade_values(grid, params),
+            // The GPU runs Horn's hillshade, cast shadows and the sky-view factor under a single light
+            #[cfg(feature = "gpu")]
+            Backend::Gpu(_) if !crate::gpu::GpuHillshader::supports(params) => hillshade_values(grid, params),
+            #[cfg(feature = "gpu")]
+            Backend::Gpu(gpu) => gpu.hillshade_values(grid, params).unwrap_or_else(|e| {
+                eprintln!("GPU hillshade failed ({}), falling back to the CPU", e);
+                hillshade_values(grid, params)
+            }),
+        }
+    }
+
+    /// Same as [`render_shaded`] using this backend for the hillshade
+    pub fn render_shaded(&self, grid: &Grid, range: (f32, f32), grad: &Gradient, params: &ShadeParams) -> RgbImage {
+        let mut img = blend_shade(grid, range, grad, &self.hillshade_values(grid, params));
+        apply_slope_saturation(&mut img, grid, params);
+        apply_aerial_perspective(&mut img, grid, range, params.aerial_perspective);
+        img
+    }
+}
+
+/// Computes hillshade value at a given cell using Horn’s method,
+/// neighbors outside the grid or on NoData are left out of the gradient like in [`horn_gradient`]
+pub fn calculate_hillshade(elevation: &[Vec<f64>], x: usize, y: usize, cell_size: f64, zf: f64, az: f64, alt: f64, nodata: f64) -> u8 {
+    let get = |dx: isize, dy: isize| -> Option<f64> {
+        let nx = x as isize + dx;
+        let ny = y as isize + dy;
+        if nx >= 0 && ny >= 0 && (nx as usize) < elevation[0].len() && (ny as usize) < elevation.len() {
+            Some(elevation[ny as usize][nx as usize]).filter(|&v| v != nodata && !v.is_nan())
+        } else {
+            None
+        }
+    };
+    let w: [Option<f64>; 9] = std::array::from_fn(|i| get((i % 3) as isize - 1, (i / 3) as isize - 1));
+    let (dzdx, dzdy) = horn_gradient(&w, (cell_size, cell_size));
+    shade_gradient(dzdx * zf, dzdy * zf, az, alt)
+}
+
+/// Horn's hillshade of the 3x3 window `[a, b, c, d, e, f, g, h, i]` (north row first) around a cell
+pub fn shade_window(w: &[f64; 9], cell_size: f64, zf: f64, az: f64, alt: f64) -> u8 {
+    let [a, b, c, d, _, f, g, h, i] = *w;
+    let dzdx = ((c + 2.0 * f + i) - (a + 2.0 * d + g)) / (8.0 * cell_size) * zf;
+    let dzdy = ((g + 2.0 * h + i) - (a + 2.0 * b + c)) / (8.0 * cell_size) * zf;
+    shade_gradient(dzdx, dzdy, az, alt)
+}
+
+/// Aspect in radians of the gradients as [`shade_intensity`] compares it with the sun azimuth
+pub fn shade_aspect(dzdx: f64, dzdy: f64) -> f64 {
+    if dzdx != 0.0 {
+        let mut a = (dzdy / dzdx).atan();
+        if dzdx < 0.0 { a += std::f64::consts::PI; }
+        else if dzdy < 0.0 { a += 2.0 * std::f64::consts::PI; }
+        a
+    } else {
+        if dzdy > 0.0 { std::f64::consts::FRAC_PI_2 } else { 3.0 * std::f64::consts::FRAC_PI_2 }
+    }
+}
+
+/// Hillshade of a cell with the exaggerated gradients `dzdx` (towards east) and `dzdy` (towards south)
+pub fn shade_gradient(dzdx: f64, dzdy: f64, az: f64, alt: f64) -> u8 {
+    (255.0 * shade_intensity(dzdx, dzdy, az, alt)) as u8
+}
+
+/// [`shade_gradient`] from 0 to 1 before it is quantized
+pub fn shade_intensity(dzdx: f64, dzdy: f64, az: f64, alt: f64) -> f64 {
+    let slope = (dzdx.powi(2) + dzdy.powi(2)).sqrt().atan();
+    let aspect = shade_aspect(dzdx, dzdy);
+
+    let az_rad = az.to_radians();
+    let alt_rad = alt.to_radians();
+    let shade = (alt_rad.sin() * slope.sin()) + (alt_rad.cos() * slope.cos() * (az_rad - aspect).cos());
+
+    shade.clamp(0.0, 1.0)
+}
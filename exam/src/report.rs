@@ -0,0 +1,214 @@
+//! Machine-readable summary of a batch run, written as `report.json` into the output root, with one line per input in
+//! `summary.csv` for comparing whole archives in a spreadsheet.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::dedup::Duplicate;
+use crate::grid::Grid;
+use crate::manifest::quote;
+use crate::spot::{self, SpotHeight};
+
+/// File name of the report below the output root
+pub const REPORT_FILE: &str = "report.json";
+
+/// File name of the table of the inputs below the output root
+pub const SUMMARY_CSV: &str = "summary.csv";
+
+/// Steps in the timings of an input that are not products
+const PREPARATION_STEPS: [&str; 2] = ["load", "warp"];
+
+/// Outcome of the whole run
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub started: u64,             // Unix time in seconds
+    pub seconds: f64,             // Wall time of the run
+    pub processed: usize,         // Inputs that were rendered completely
+    pub failed: usize,            // Inputs with an error
+    pub skipped: usize,           // Inputs finished by an earlier run (--resume)
+    pub duplicates: Vec<Duplicate>, // Copies of earlier inputs (--find-duplicates, --skip-duplicates)
+    pub inputs: Vec<InputReport>, // In processing order
+    #[serde(skip)]
+    clock: Instant,
+}
+
+/// Whether an input was rendered completely
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Failed,
+}
+
+/// What happened to one input file
+#[derive(Debug, Serialize)]
+pub struct InputReport {
+    pub input: PathBuf,
+    pub status: Status,
+    pub outputs: Vec<PathBuf>,              // Files written, also the ones of a product that failed halfway
+    pub stats: Option<ElevationStats>,      // Missing if the file could not be read
+    pub peaks: Vec<SpotHeight>,             // Most prominent peaks, marked on the images (--peaks)
+    pub timings: BTreeMap<String, f64>,     // Seconds per step: "load" and one entry per product
+    pub warnings: Vec<String>,              // Problems that did not stop the rendering
+    pub error: Option<String>,              // First error, which ends the processing of the file
+}
+
+/// Summary of the valid elevations of a grid
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct ElevationStats {
+    pub ncols: usize,
+    pub nrows: usize,
+    pub cellsize: f64,
+    pub min: f32,
+    pub max: f32,
+    pub mean: f64,
+    pub valid_cells: usize,
+    pub nodata_cells: usize,
+    pub highest: Option<SpotHeight>, // Cells of the max and min, the first in row order on ties
+    pub lowest: Option<SpotHeight>,
+}
+
+impl ElevationStats {
+    pub fn of(grid: &Grid) -> ElevationStats {
+        let (mut sum, mut valid_cells) = (0.0, 0);
+        for &v in grid.data.iter().flatten().filter(|&&v| !grid.is_nodata(v)) {
+            sum += v as f64;
+            valid_cells += 1;
+        }
+        let (min, max) = if valid_cells > 0 { grid.min_max() } else { (f32::NAN, f32::NAN) };
+        let extremes = spot::extremes(grid);
+        ElevationStats {
+            ncols: grid.ncols,
+            nrows: grid.nrows,
+            cellsize: grid.cellsize,
+            min,
+            max,
+            mean: if valid_cells > 0 { sum / valid_cells as f64 } else { f64::NAN },
+            valid_cells,
+            nodata_cells: grid.ncols * grid.nrows - valid_cells,
+            highest: extremes.map(|(high, _)| high),
+            lowest: extremes.map(|(_, low)| low),
+        }
+    }
+}
+
+impl InputReport {
+    pub fn new(input: &Path) -> InputReport {
+        InputReport {
+            input: input.to_path_buf(),
+            status: Status::Ok,
+            outputs: Vec::new(),
+            stats: None,
+            peaks: Vec::new(),
+            timings: BTreeMap::new(),
+            warnings: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Runs `step` and records how long it took under `name`
+    pub fn time<T>(&mut self, name: &str, step: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = step();
+        *self.timings.entry(name.to_string()).or_default() += start.elapsed().as_secs_f64();
+        result
+    }
+
+    /// Marks the input as failed with `error`
+    pub fn fail(&mut self, error: &io::Error) {
+        self.status = Status::Failed;
+        self.error = Some(error.to_string());
+    }
+}
+
+impl Report {
+    /// Starts the clock of the run
+    pub fn new() -> Report {
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        Report { started, seconds: 0.0, processed: 0, failed: 0, skipped: 0, duplicates: Vec::new(), inputs: Vec::new(), clock: Instant::now() }
+    }
+
+    pub fn push(&mut self, input: InputReport) {
+        match input.status {
+            Status::Ok => self.processed += 1,
+            Status::Failed => self.failed += 1,
+        }
+        self.inputs.push(input);
+    }
+
+    /// Stops the clock and writes the report as pretty-printed JSON
+    pub fn save(&mut self, path: &Path) -> io::Result<()> {
+        self.seconds = self.clock.elapsed().as_secs_f64();
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, self).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+
+    /// Writes one CSV line per input: its size and elevations, the share of NoData, the products it was rendered to
+    /// (separated by `;`) and the seconds spent on it. Inputs that could not be read leave the grid columns empty.
+    pub fn save_summary(&self, path: &Path) -> io::Result<()> {
+        let mut csv = BufWriter::new(File::create(path)?);
+        writeln!(csv, "input,status,ncols,nrows,cellsize,min,max,mean,nodata_percent,products,seconds,error")?;
+        for input in &self.inputs {
+            let stats = input.stats.map_or(vec![String::new(); 7], |s| {
+                let valid = s.valid_cells > 0;
+                vec![
+                    s.ncols.to_string(),
+                    s.nrows.to_string(),
+                    s.cellsize.to_string(),
+                    if valid { s.min.to_string() } else { String::new() },
+                    if valid { s.max.to_string() } else { String::new() },
+                    if valid { format!("{:.3}", s.mean) } else { String::new() },
+                    format!("{:.2}", 100.0 * s.nodata_cells as f64 / (s.ncols * s.nrows).max(1) as f64),
+                ]
+            });
+            let products: Vec<&str> = input.timings.keys().map(String::as_str).filter(|step| !PREPARATION_STEPS.contains(step)).collect();
+            let status = match input.status {
+                Status::Ok => "ok",
+                Status::Failed => "failed",
+            };
+            writeln!(csv, "{},{},{},{},{:.3},{}",
+                quote(&input.input.to_string_lossy().replace('\\', "/")), status, stats.join(","), quote(&products.join(";")),
+                input.timings.values().fold(0.0, |total, seconds| total + seconds), quote(input.error.as_deref().unwrap_or("")))?;
+        }
+        csv.flush()
+    }
+}
+
+/// Inputs recorded as failed in an earlier report, for `--retry-failed`
+pub fn failed_inputs(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let file = File::open(path).map_err(|e| io::Error::new(e.kind(), format!("{:?}: {}", path, e)))?;
+    let report: serde_json::Value = serde_json::from_reader(io::BufReader::new(file)).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let inputs = report["inputs"].as_array().map(Vec::as_slice).unwrap_or_default();
+    Ok(inputs.iter()
+        .filter(|input| input["status"] == "failed")
+        .filter_map(|input| input["input"].as_str().map(PathBuf::from))
+        .collect())
+}
+
+/// Seconds per grid cell of every step ("load", "warp" and the products) in the report at `path`, over the inputs that
+/// finished, for estimating how long a run takes
+pub fn step_rates(path: &Path) -> io::Result<BTreeMap<String, f64>> {
+    let file = File::open(path).map_err(|e| io::Error::new(e.kind(), format!("{:?}: {}", path, e)))?;
+    let report: serde_json::Value = serde_json::from_reader(io::BufReader::new(file)).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut totals: BTreeMap<String, (f64, f64)> = BTreeMap::new(); // Seconds and cells per step
+    for input in report["inputs"].as_array().map(Vec::as_slice).unwrap_or_default().iter().filter(|input| input["status"] == "ok") {
+        let cells = input["stats"]["ncols"].as_f64().unwrap_or(0.0) * input["stats"]["nrows"].as_f64().unwrap_or(0.0);
+        for (step, seconds) in input["timings"].as_object().into_iter().flatten() {
+            let total = totals.entry(step.clone()).or_default();
+            total.0 += seconds.as_f64().unwrap_or(0.0);
+            total.1 += cells;
+        }
+    }
+    Ok(totals.into_iter().filter(|(_, (_, cells))| *cells > 0.0).map(|(step, (seconds, cells))| (step, seconds / cells)).collect())
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Report::new()
+    }
+}
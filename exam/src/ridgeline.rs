@@ -0,0 +1,149 @@
+//! Ridgeline plots in the style of the *Unknown Pleasures* cover: every few rows of the grid become an elevation
+//! profile, stacked from north (back) to south (front), white lines on black, each profile hiding the ones behind it.
+//! Written as SVG for print and vector editing, or rasterized into an image.
+
+#[cfg(feature = "fs")]
+use std::io::{self, Write};
+
+use image::{Rgb, RgbImage};
+
+use crate::grid::Grid;
+
+/// Profiles drawn when no row step is given
+const DEFAULT_PROFILES: usize = 60;
+
+/// Width of the lines in pixels
+const STROKE: f64 = 1.5;
+
+const BACKGROUND: [u8; 3] = [0, 0, 0];
+const LINE: [u8; 3] = [255, 255, 255];
+
+/// How the profiles are picked and drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RidgelineSettings {
+    pub step: Option<usize>, // Rows between two profiles (--step), about 60 profiles when None
+    pub exaggeration: f64,   // Rise of the highest point above its baseline, in gaps between profiles (--exaggeration)
+    pub occlusion: bool,     // Profiles in front hide the ones behind (off with --no-occlusion)
+    pub width: u32,          // Width of the drawing in pixels (--width)
+}
+
+impl Default for RidgelineSettings {
+    fn default() -> Self {
+        RidgelineSettings { step: None, exaggeration: 5.0, occlusion: true, width: 1200 }
+    }
+}
+
+/// One profile in drawing coordinates, y down
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub row: usize,                  // Row of the grid
+    pub baseline: f64,               // y of the lowest elevation
+    pub points: Vec<Option<[f64; 2]>>, // Left to right, None on NoData
+}
+
+/// The profiles of a grid, back to front
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ridgelines {
+    pub width: u32,
+    pub height: u32,
+    pub occlusion: bool,
+    pub profiles: Vec<Profile>,
+}
+
+impl Ridgelines {
+    /// Samples every `step` rows of `grid` at no more columns than the drawing has pixels. Baselines are as far apart
+    /// as the rows on the map, so the drawing keeps the shape of the grid.
+    pub fn new(grid: &Grid, settings: &RidgelineSettings) -> Result<Ridgelines, String> {
+        if settings.step == Some(0) || settings.exaggeration.is_nan() || settings.exaggeration < 0.0 || settings.width < 16 {
+            return Err("Ridgelines need a step of at least one row, an exaggeration of at least 0 and a width of 16 pixels".into());
+        }
+        if grid.ncols < 2 || grid.nrows == 0 {
+            return Err("Ridgelines need a grid of at least two columns".into());
+        }
+        let step = settings.step.unwrap_or((grid.nrows / DEFAULT_PROFILES).max(1));
+        let margin = settings.width as f64 / 12.0;
+        let inner = settings.width as f64 - 2.0 * margin;
+        let gap = inner * step as f64 / (grid.ncols - 1) as f64;
+        let rise = settings.exaggeration * gap;
+        let rows: Vec<usize> = (0..grid.nrows).step_by(step).collect();
+        let (min, max) = grid.min_max();
+        let span = (max - min).max(f32::EPSILON) as f64;
+        let samples = grid.ncols.min(settings.width as usize);
+
+        let profiles = rows.iter().enumerate().map(|(i, &row)| {
+            let baseline = margin + rise + i as f64 * gap;
+            let points = (0..samples).map(|s| {
+                let col = s * (grid.ncols - 1) / (samples - 1);
+                let val = grid.data[row][col];
+                let x = margin + inner * col as f64 / (grid.ncols - 1) as f64;
+                (!grid.is_nodata(val)).then(|| [x, baseline - rise * (val - min) as f64 / span])
+            }).collect();
+            Profile { row, baseline, points }
+        }).collect();
+        let height = (2.0 * margin + rise + (rows.len() - 1) as f64 * gap).ceil() as u32;
+        Ok(Ridgelines { width: settings.width, height, occlusion: settings.occlusion, profiles })
+    }
+
+    /// Runs of valid points of a profile, the pieces between NoData
+    fn runs(profile: &Profile) -> impl Iterator<Item = Vec<[f64; 2]>> + '_ {
+        profile.points.split(Option::is_none).map(|run| run.iter().flatten().copied().collect::<Vec<_>>()).filter(|run| run.len() > 1)
+    }
+
+    /// The plot as SVG: per profile a black area down to the baseline hiding the ones behind, then the white line
+    #[cfg(feature = "fs")]
+    pub fn write_svg<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let hex = |c: [u8; 3]| format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2]);
+        writeln!(out, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#, w = self.width, h = self.height)?;
+        writeln!(out, r#"<rect width="100%" height="100%" fill="{}"/>"#, hex(BACKGROUND))?;
+        writeln!(out, r#"<g stroke="{}" stroke-width="{}" stroke-linejoin="round" stroke-linecap="round">"#, hex(LINE), STROKE)?;
+        for profile in &self.profiles {
+            for run in Ridgelines::runs(profile) {
+                let line: Vec<String> = run.iter().map(|[x, y]| format!("{:.1},{:.1}", x, y)).collect();
+                if self.occlusion {
+                    let (first, last) = (run[0][0], run[run.len() - 1][0]);
+                    writeln!(out, r#"<path d="M{} L{:.1},{:.1} L{:.1},{:.1} Z" fill="{}" stroke="none"/>"#,
+                        line.join(" L"), last, profile.baseline, first, profile.baseline, hex(BACKGROUND))?;
+                }
+                writeln!(out, r#"<polyline points="{}" fill="none"/>"#, line.join(" "))?;
+            }
+        }
+        writeln!(out, "</g>\n</svg>")
+    }
+
+    /// The plot as an image, the lines antialiased along every pixel column
+    pub fn render(&self) -> RgbImage {
+        let mut img = RgbImage::from_pixel(self.width, self.height, Rgb(BACKGROUND));
+        let half = STROKE / 2.0;
+        for profile in &self.profiles {
+            for run in Ridgelines::runs(profile) {
+                // y of the line at every pixel column the run covers
+                let first = run[0][0].ceil() as u32;
+                let last = (run[run.len() - 1][0].floor() as u32).min(self.width - 1);
+                let line_y = |x: f64| {
+                    let i = run.partition_point(|p| p[0] <= x).clamp(1, run.len() - 1);
+                    let ([x0, y0], [x1, y1]) = (run[i - 1], run[i]);
+                    y0 + (y1 - y0) * ((x - x0) / (x1 - x0)).clamp(0.0, 1.0)
+                };
+                for x in first..=last {
+                    let (left, right) = (line_y(x as f64 - 0.5), line_y(x as f64 + 0.5));
+                    let y = line_y(x as f64);
+                    if self.occlusion {
+                        for py in (y.ceil().max(0.0) as u32)..(profile.baseline.ceil() as u32).min(self.height) {
+                            img.put_pixel(x, py, Rgb(BACKGROUND));
+                        }
+                    }
+                    // The span the line crosses within the column, each pixel lit by its share of it
+                    let (top, bottom) = (left.min(right).min(y) - half, left.max(right).max(y) + half);
+                    for py in (top.floor().max(0.0) as u32)..(bottom.ceil() as u32).min(self.height) {
+                        let cover = ((py + 1) as f64).min(bottom) - (py as f64).max(top);
+                        let pixel = img.get_pixel_mut(x, py);
+                        *pixel = Rgb(std::array::from_fn(|c| {
+                            (pixel[c] as f64 + (LINE[c] as f64 - pixel[c] as f64) * cover.clamp(0.0, 1.0)).round() as u8
+                        }));
+                    }
+                }
+            }
+        }
+        img
+    }
+}
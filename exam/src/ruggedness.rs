@@ -0,0 +1,55 @@
+//! Terrain ruggedness index (Riley et al. 1999), a product of the [`crate::plugin::Registry`]: the elevation
+//! difference between a cell and its eight neighbors, flat plains near 0 and broken mountain terrain in the hundreds.
+
+#[cfg(feature = "fs")]
+use std::io;
+
+#[cfg(feature = "fs")]
+use image::DynamicImage;
+
+use crate::grid::{EdgeMode, Grid};
+#[cfg(feature = "fs")]
+use crate::output::{save_image, save_raw_values, OutputFormat, OutputTarget, RawFormat};
+#[cfg(feature = "fs")]
+use crate::plugin::{Inputs, Product};
+use crate::terrain::map_windows;
+#[cfg(feature = "fs")]
+use crate::terrain::{colorize, percentile};
+
+/// √Σ(zᵢ - z₀)² over the eight neighbors in elevation units; neighbors on NoData are left out
+pub fn ruggedness(grid: &Grid, edges: EdgeMode) -> Grid {
+    map_windows(grid, edges, |w| {
+        let center = w[4].expect("windows are centered on valid cells");
+        w.iter().flatten().map(|z| (z - center).powi(2)).sum::<f64>().sqrt()
+    })
+}
+
+/// The "ruggedness" product: the index colored from smooth (dark) to rugged (bright) up to its 98th percentile,
+/// with the values as "ruggedness_f32" in the raw formats
+#[derive(Clone, Copy, Debug)]
+pub struct Ruggedness;
+
+#[cfg(feature = "fs")]
+impl Ruggedness {
+    fn image(values: &Grid) -> DynamicImage {
+        let limit = percentile(values, 0.98).max(1e-6);
+        DynamicImage::ImageRgb8(colorize(values, &colorgrad::inferno(), |v| v as f64 / limit))
+    }
+}
+
+#[cfg(feature = "fs")]
+impl Product for Ruggedness {
+    fn name(&self) -> &str {
+        "ruggedness"
+    }
+
+    fn render(&self, inputs: &Inputs) -> DynamicImage {
+        Ruggedness::image(&ruggedness(inputs.grid(), inputs.params().edges))
+    }
+
+    fn save(&self, inputs: &Inputs, raw: &[RawFormat], format: &OutputFormat, target: &OutputTarget) -> io::Result<()> {
+        let values = crate::profiling::stage("derive", || ruggedness(inputs.grid(), inputs.params().edges));
+        save_image(Ruggedness::image(&values), target, self.name(), format)?;
+        save_raw_values(&values, "ruggedness_f32", raw, format, target)
+    }
+}
@@ -0,0 +1,153 @@
+//! Custom cartography without recompiling (`script` feature): a [rhai](https://rhai.rs) script gets the elevation,
+//! slope, aspect and shade of a cell and returns its color, e.g. snow above 2500 m and rock on steep slopes.
+//!
+//! The script is the body run for every cell. It sees `elevation`, `slope` (degrees), `aspect` (degrees clockwise
+//! from north, -1 on flat cells), `shade` (0 to 1) and the `min` and `max` elevation of the grid, and its last
+//! expression is the color: `[r, g, b]` or `[r, g, b, a]` with numbers from 0 to 255, a `"#rrggbb"` or
+//! `"#rrggbbaa"` string, or `()` for a transparent cell. NoData cells are transparent without running the script.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::thread;
+
+use image::{DynamicImage, RgbaImage};
+use rhai::{Dynamic, Engine, Scope, AST};
+
+use crate::grid::Grid;
+use crate::output::{save_image, OutputFormat, OutputTarget};
+use crate::render::{hillshade_intensities, ShadeParams};
+use crate::terrain::Derivative;
+
+/// Operations one cell may take, so a script that loops forever fails instead of hanging the batch
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// A compiled styling script
+pub struct StyleScript {
+    engine: Engine,
+    ast: AST,
+}
+
+/// The values a script sees for one cell
+struct Cell {
+    elevation: f64,
+    slope: f64,
+    aspect: f64,
+    shade: f64,
+}
+
+impl StyleScript {
+    /// Compiles the source of a script, failing with the line and column of a syntax error
+    pub fn compile(source: &str) -> io::Result<StyleScript> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let ast = engine.compile(source).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("Style script: {}", e)))?;
+        Ok(StyleScript { engine, ast })
+    }
+
+    /// Reads and compiles a script file
+    pub fn load(path: &Path) -> io::Result<StyleScript> {
+        let source = fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("{:?}: {}", path, e)))?;
+        StyleScript::compile(&source).map_err(|e| io::Error::new(e.kind(), format!("{:?}: {}", path, e)))
+    }
+
+    /// Colors every cell of `grid`, shaded and with slopes by `params`, on all cores. Cells whose script fails stay
+    /// transparent; the warning returned then tells how many did and the first error.
+    pub fn render(&self, grid: &Grid, params: &ShadeParams) -> (RgbaImage, Option<String>) {
+        let slope = Derivative::Slope.compute_with(grid, params.edges);
+        let aspect = Derivative::Aspect.compute_with(grid, params.edges);
+        let shade = hillshade_intensities(grid, params);
+        let (min, max) = grid.min_max();
+
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let band = grid.nrows.div_ceil(threads).max(1);
+        let mut img = RgbaImage::new(grid.ncols as u32, grid.nrows as u32);
+        let mut failures = Vec::new();
+        thread::scope(|s| {
+            let workers: Vec<_> = img.chunks_mut(4 * grid.ncols.max(1) * band).enumerate().map(|(i, pixels)| {
+                let (slope, aspect, shade) = (&slope, &aspect, &shade);
+                s.spawn(move || {
+                    let mut scope = Scope::new();
+                    scope.push_constant("min", min as f64).push_constant("max", max as f64);
+                    let mut failed = 0;
+                    let mut first = None;
+                    for (j, pixel) in pixels.chunks_exact_mut(4).enumerate() {
+                        let (x, y) = (j % grid.ncols, i * band + j / grid.ncols);
+                        let elevation = grid.data[y][x];
+                        if grid.is_nodata(elevation) {
+                            continue;
+                        }
+                        let value = |g: &Grid, default: f64| Some(g.data[y][x]).filter(|&v| !g.is_nodata(v)).map_or(default, f64::from);
+                        let cell = Cell { elevation: elevation as f64, slope: value(slope, 0.0), aspect: value(aspect, -1.0), shade: value(shade, 0.0) };
+                        match self.style(&mut scope, &cell) {
+                            Ok(color) => pixel.copy_from_slice(&color),
+                            Err(e) => {
+                                failed += 1;
+                                first.get_or_insert_with(|| format!("row {}, column {}: {}", y, x, e));
+                            }
+                        }
+                    }
+                    (failed, first)
+                })
+            }).collect();
+            failures = workers.into_iter().map(|w| w.join().expect("a style script worker panicked")).collect();
+        });
+
+        let failed: usize = failures.iter().map(|(n, _)| n).sum();
+        let warning = failures.into_iter().find_map(|(_, first)| first)
+            .map(|first| format!("The style script failed on {} cells, left transparent, first at {}", failed, first));
+        (img, warning)
+    }
+
+    /// Runs the script for one cell
+    fn style(&self, scope: &mut Scope, cell: &Cell) -> Result<[u8; 4], String> {
+        let constants = scope.len();
+        scope.push("elevation", cell.elevation).push("slope", cell.slope).push("aspect", cell.aspect).push("shade", cell.shade);
+        let result = self.engine.eval_ast_with_scope::<Dynamic>(scope, &self.ast);
+        scope.rewind(constants); // Drops the cell and the variables the script declared
+        color(result.map_err(|e| e.to_string())?)
+    }
+}
+
+/// The color a script returned
+fn color(value: Dynamic) -> Result<[u8; 4], String> {
+    if value.is_unit() {
+        return Ok([0; 4]);
+    }
+    if value.is_string() {
+        let text = value.into_string()?;
+        return hex_color(&text).ok_or_else(|| format!("{:?} is not a #rrggbb or #rrggbbaa color", text));
+    }
+    let type_name = value.type_name();
+    let channels = value.try_cast::<rhai::Array>().ok_or_else(|| format!("returned {} instead of a color", type_name))?;
+    if !(3..=4).contains(&channels.len()) {
+        return Err(format!("returned {} channels instead of 3 or 4", channels.len()));
+    }
+    let mut rgba = [255; 4];
+    for (channel, value) in rgba.iter_mut().zip(channels) {
+        let number = value.as_float().or_else(|_| value.as_int().map(|i| i as f64))
+            .map_err(|type_name| format!("returned a {} channel instead of a number", type_name))?;
+        *channel = number.round().clamp(0.0, 255.0) as u8;
+    }
+    Ok(rgba)
+}
+
+/// Parses "#rrggbb" and "#rrggbbaa"
+fn hex_color(text: &str) -> Option<[u8; 4]> {
+    let digits = text.strip_prefix('#')?;
+    if !matches!(digits.len(), 6 | 8) || !digits.is_ascii() {
+        return None;
+    }
+    let mut rgba = [255; 4];
+    for (channel, i) in rgba.iter_mut().zip((0..digits.len()).step_by(2)) {
+        *channel = u8::from_str_radix(&digits[i..i + 2], 16).ok()?;
+    }
+    Some(rgba)
+}
+
+/// Writes the "styled" product, returning the warning about failed cells
+pub fn save_styled_image(grid: &Grid, script: &StyleScript, format: &OutputFormat, target: &OutputTarget) -> io::Result<Option<String>> {
+    let (img, warning) = script.render(grid, &target.shade);
+    save_image(DynamicImage::ImageRgba8(img), target, "styled", format)?;
+    Ok(warning)
+}
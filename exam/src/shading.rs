@@ -0,0 +1,249 @@
+//! Shading methods behind one trait: each turns the terrain into the light a cell receives, from 0 (dark) to 1 (lit),
+//! and the hillshade products, the color blend and the raw shade layers take whichever one [`ShadeParams::shader`]
+//! names. Methods reading only the 3x3 window of a cell implement [`WindowShader`], the others [`Shader`] directly.
+
+use crate::grid::Grid;
+use crate::horizon::horizon_angles_tracked;
+use crate::progress::{Cancelled, Tracker};
+use crate::render::{shade_aspect, shade_intensity, ShadeParams};
+use crate::terrain::horn_gradient;
+
+/// A shading method for whole grids
+pub trait Shader {
+    /// Name as accepted by [`ShaderKind::parse`]
+    fn name(&self) -> &'static str;
+
+    /// Light of every cell from 0 to 1, row-major from the north row, None where it isn't computed
+    fn intensities(&self, grid: &Grid, params: &ShadeParams) -> Vec<Option<f64>>;
+
+    /// [`Shader::intensities`] reporting to `tracker` and stopping when it is cancelled. A method that can't be
+    /// interrupted only reports when it starts and when it is done.
+    fn intensities_tracked(&self, grid: &Grid, params: &ShadeParams, tracker: &Tracker) -> Result<Vec<Option<f64>>, Cancelled> {
+        tracker.advance(0.0)?;
+        let intensities = self.intensities(grid, params);
+        tracker.advance(1.0)?;
+        Ok(intensities)
+    }
+}
+
+/// A shading method that only needs the 3x3 window around a cell, run on every cell by its [`Shader`] implementation
+pub trait WindowShader {
+    fn name(&self) -> &'static str;
+
+    /// Light of the cell from its window `[a, b, c, d, e, f, g, h, i]` (north row first, None on NoData, the neighbors
+    /// past the grid filled in as [`ShadeParams::edges`] says)
+    fn shade(&self, window: &[Option<f64>; 9], params: &ShadeParams) -> f64;
+}
+
+impl<S: WindowShader> Shader for S {
+    fn name(&self) -> &'static str {
+        WindowShader::name(self)
+    }
+
+    fn intensities(&self, grid: &Grid, params: &ShadeParams) -> Vec<Option<f64>> {
+        grid.iter_cells().map(|(x, y, _)| grid.edge_window(x, y, params.edges).map(|w| self.shade(&w, params))).collect()
+    }
+
+    /// Row by row, checking the tracker before each
+    fn intensities_tracked(&self, grid: &Grid, params: &ShadeParams, tracker: &Tracker) -> Result<Vec<Option<f64>>, Cancelled> {
+        let mut intensities = Vec::with_capacity(grid.ncols * grid.nrows);
+        for y in 0..grid.nrows {
+            tracker.advance(y as f32 / grid.nrows as f32)?;
+            intensities.extend((0..grid.ncols).map(|x| grid.edge_window(x, y, params.edges).map(|w| self.shade(&w, params))));
+        }
+        tracker.advance(1.0)?;
+        Ok(intensities)
+    }
+}
+
+/// [`Shader::intensities`] of `shader` lit by the sun of `params` and by its [`crate::render::FillLight`] if it has
+/// one, blended by the weight of the fill
+pub fn lit_intensities(shader: &dyn Shader, grid: &Grid, params: &ShadeParams) -> Vec<Option<f64>> {
+    lit_intensities_tracked(shader, grid, params, &Tracker::none()).expect("an unwatched tracker is never cancelled")
+}
+
+/// [`lit_intensities`] reporting to `tracker`, the sun and the fill light half of the work each
+pub fn lit_intensities_tracked(shader: &dyn Shader, grid: &Grid, params: &ShadeParams, tracker: &Tracker) -> Result<Vec<Option<f64>>, Cancelled> {
+    let Some(fill) = params.fill else {
+        return shader.intensities_tracked(grid, params, tracker);
+    };
+    let sun = shader.intensities_tracked(grid, params, &tracker.part(0, 2))?;
+    let fill_params = ShadeParams { azimuth: fill.azimuth, altitude: fill.altitude, fill: None, ..*params };
+    let filled = shader.intensities_tracked(grid, &fill_params, &tracker.part(1, 2))?;
+    let weight = fill.weight.clamp(0.0, 1.0);
+    Ok(sun.into_iter().zip(filled).map(|(sun, fill)| sun.zip(fill).map(|(sun, fill)| (1.0 - weight) * sun + weight * fill)).collect())
+}
+
+/// The built-in shading methods (--shader)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "fs", derive(serde::Serialize), serde(rename_all = "kebab-case"))]
+pub enum ShaderKind {
+    #[default]
+    Horn,              // Lambertian hillshade of Horn's gradient, see [`Horn`]
+    ZevenbergenThorne, // Lambertian hillshade of the gradient across the four direct neighbors, see [`ZevenbergenThorne`]
+    Multidirectional,  // Four suns blended by aspect, see [`Multidirectional`]
+    SkyView,           // Sky-view factor, see [`SkyView`]
+    Shadows,           // Horn's hillshade with cast shadows, see [`Shadows`]
+}
+
+impl ShaderKind {
+    pub fn parse(name: &str) -> Option<ShaderKind> {
+        match name.to_lowercase().as_str() {
+            "horn" => Some(ShaderKind::Horn),
+            "zevenbergen-thorne" | "zt" => Some(ShaderKind::ZevenbergenThorne),
+            "multidirectional" => Some(ShaderKind::Multidirectional),
+            "sky-view" | "svf" => Some(ShaderKind::SkyView),
+            "shadows" => Some(ShaderKind::Shadows),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.shader().name()
+    }
+
+    /// The method with its default settings
+    pub fn shader(&self) -> Box<dyn Shader> {
+        match self {
+            ShaderKind::Horn => Box::new(Horn),
+            ShaderKind::ZevenbergenThorne => Box::new(ZevenbergenThorne),
+            ShaderKind::Multidirectional => Box::new(Multidirectional),
+            ShaderKind::SkyView => Box::new(SkyView::default()),
+            ShaderKind::Shadows => Box::new(Shadows::default()),
+        }
+    }
+}
+
+/// Exaggerated gradients of a window by Horn's kernel, NoData neighbors left out
+fn horn(window: &[Option<f64>; 9], params: &ShadeParams) -> (f64, f64) {
+    let (dzdx, dzdy) = horn_gradient(window, params.cell_size);
+    (dzdx * params.z_factor, dzdy * params.z_factor)
+}
+
+/// The hillshade of the "hillshade" product: Horn's 3x3 gradient lit by the sun of the params
+pub struct Horn;
+
+impl WindowShader for Horn {
+    fn name(&self) -> &'static str {
+        "horn"
+    }
+
+    fn shade(&self, window: &[Option<f64>; 9], params: &ShadeParams) -> f64 {
+        let (dzdx, dzdy) = horn(window, params);
+        shade_intensity(dzdx, dzdy, params.azimuth, params.altitude)
+    }
+}
+
+/// Zevenbergen & Thorne's gradient from the four direct neighbors only, crisper but noisier than Horn's on rough ground
+pub struct ZevenbergenThorne;
+
+impl WindowShader for ZevenbergenThorne {
+    fn name(&self) -> &'static str {
+        "zevenbergen-thorne"
+    }
+
+    fn shade(&self, window: &[Option<f64>; 9], params: &ShadeParams) -> f64 {
+        // Central difference, one-sided from the center with a NoData neighbor, flat with two
+        let difference = |low: Option<f64>, center: f64, high: Option<f64>, spacing: f64| match (low, high) {
+            (Some(low), Some(high)) => (high - low) / (2.0 * spacing),
+            (None, Some(high)) => (high - center) / spacing,
+            (Some(low), None) => (center - low) / spacing,
+            (None, None) => 0.0,
+        };
+        let center = window[4].unwrap_or_default();
+        let (dx, dy) = params.cell_size;
+        let dzdx = difference(window[3], center, window[5], dx) * params.z_factor;
+        let dzdy = difference(window[1], center, window[7], dy) * params.z_factor;
+        shade_intensity(dzdx, dzdy, params.azimuth, params.altitude)
+    }
+}
+
+/// Multidirectional hillshade like GDAL's: suns at the azimuth of the params, 45° and 90° counterclockwise of it and 45°
+/// clockwise, each weighted by how directly the slope faces it, so slopes running along the main sun
+/// don't fall flat for lack of contrast
+pub struct Multidirectional;
+
+impl WindowShader for Multidirectional {
+    fn name(&self) -> &'static str {
+        "multidirectional"
+    }
+
+    fn shade(&self, window: &[Option<f64>; 9], params: &ShadeParams) -> f64 {
+        let (dzdx, dzdy) = horn(window, params);
+        let aspect = shade_aspect(dzdx, dzdy);
+        let (mut total, mut weights) = (0.0, 0.0);
+        for offset in [-90.0, -45.0, 0.0, 45.0] {
+            let azimuth = params.azimuth + offset;
+            let weight = 1.0 + (azimuth.to_radians() - aspect).cos();
+            total += weight * shade_intensity(dzdx, dzdy, azimuth, params.altitude);
+            weights += weight;
+        }
+        // Four suns within 135° never all face away
+        total / weights
+    }
+}
+
+/// Sky-view factor: the share of the sky hemisphere above a cell that the surrounding terrain leaves open, 1 on
+/// peaks and plains and dark in valleys and pits. Independent of the sun; uses the ground geometry, not the z-factor.
+pub struct SkyView {
+    pub directions: u32, // Azimuths the horizon is traced toward, evenly spaced
+}
+
+impl Default for SkyView {
+    fn default() -> Self {
+        SkyView { directions: 16 }
+    }
+}
+
+impl Shader for SkyView {
+    fn name(&self) -> &'static str {
+        "sky-view"
+    }
+
+    fn intensities(&self, grid: &Grid, params: &ShadeParams) -> Vec<Option<f64>> {
+        self.intensities_tracked(grid, params, &Tracker::none()).expect("an unwatched tracker is never cancelled")
+    }
+
+    /// One part of the progress per direction
+    fn intensities_tracked(&self, grid: &Grid, _params: &ShadeParams, tracker: &Tracker) -> Result<Vec<Option<f64>>, Cancelled> {
+        let directions = self.directions.max(1);
+        let mut blocked = vec![0.0; grid.ncols * grid.nrows];
+        for k in 0..directions {
+            let horizon = horizon_angles_tracked(grid, 360.0 * k as f64 / directions as f64, &tracker.part(k as usize, directions as usize))?;
+            for (b, &angle) in blocked.iter_mut().zip(horizon.data.iter().flatten()) {
+                *b += (angle.max(0.0) as f64).to_radians().sin();
+            }
+        }
+        Ok(grid.iter_cells().zip(blocked).map(|((x, y, _), b)| grid.is_valid(x, y).then(|| 1.0 - b / directions as f64)).collect())
+    }
+}
+
+/// [`Horn`] with the cells whose horizon toward the sun rises above it in the shadow of the terrain around them,
+/// where only `ambient` of the light reaches. The horizon uses the ground geometry, not the z-factor.
+pub struct Shadows {
+    pub ambient: f64, // Share of the light left in a cast shadow, from 0 (black) to 1 (no shadows)
+}
+
+impl Default for Shadows {
+    fn default() -> Self {
+        Shadows { ambient: 0.25 }
+    }
+}
+
+impl Shader for Shadows {
+    fn name(&self) -> &'static str {
+        "shadows"
+    }
+
+    fn intensities(&self, grid: &Grid, params: &ShadeParams) -> Vec<Option<f64>> {
+        self.intensities_tracked(grid, params, &Tracker::none()).expect("an unwatched tracker is never cancelled")
+    }
+
+    /// The horizon as the first half of the progress, the hillshade as the second
+    fn intensities_tracked(&self, grid: &Grid, params: &ShadeParams, tracker: &Tracker) -> Result<Vec<Option<f64>>, Cancelled> {
+        let horizon = horizon_angles_tracked(grid, params.azimuth, &tracker.part(0, 2))?;
+        Ok(Horn.intensities_tracked(grid, params, &tracker.part(1, 2))?.into_iter().zip(horizon.data.iter().flatten())
+            .map(|(light, &angle)| light.map(|light| if angle as f64 > params.altitude { light * self.ambient } else { light }))
+            .collect())
+    }
+}
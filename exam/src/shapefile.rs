@@ -0,0 +1,184 @@
+//! ESRI Shapefiles for the vector products (contours, routes, viewshed outlines) whose consumers want `.shp`
+//! deliverables: the geometry in `.shp` with its index `.shx`, the attributes in a dBASE `.dbf` table and a `.cpg`
+//! declaring its text UTF-8. No `.prj` is written, the grids carry no CRS.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Kinds of geometry, with their shape type codes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShapeType {
+    PolyLine = 3,   // Lines in x and y
+    Polygon = 5,    // Rings, outer ones clockwise and holes counterclockwise
+    PolyLineZ = 13, // Lines with an elevation at every point
+}
+
+/// Column of the attribute table
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FieldType {
+    Number { width: u8, decimals: u8 },
+    Text { width: u8 },
+}
+
+/// A named column, names are cut to the 10 characters dBASE allows
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Field {
+    pub name: &'static str,
+    pub kind: FieldType,
+}
+
+/// The value of a record in a column
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Text(String),
+    Null, // Left empty
+}
+
+/// Geometry of a record, its parts (lines or rings) of points; z is ignored by the 2D types
+type Parts = Vec<Vec<[f64; 3]>>;
+
+/// A shapefile built in memory
+#[derive(Clone, Debug)]
+pub struct Shapefile {
+    shape_type: ShapeType,
+    fields: Vec<Field>,
+    records: Vec<(Parts, Vec<Value>)>,
+}
+
+/// 100-byte header of the .shp and .shx files, `words` the length of the file in 16-bit words
+fn write_header<W: Write>(out: &mut W, shape_type: ShapeType, words: usize, bbox: [f64; 6]) -> io::Result<()> {
+    out.write_all(&9994i32.to_be_bytes())?;
+    out.write_all(&[0; 20])?;
+    out.write_all(&(words as i32).to_be_bytes())?;
+    out.write_all(&1000i32.to_le_bytes())?;
+    out.write_all(&(shape_type as i32).to_le_bytes())?;
+    for value in bbox.into_iter().chain([0.0, 0.0]) {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Bounding box of points as x min, y min, x max, y max, z min, z max
+fn bounds<'a>(points: impl Iterator<Item = &'a [f64; 3]>) -> [f64; 6] {
+    let mut b = [f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY, f64::INFINITY, f64::NEG_INFINITY];
+    for p in points {
+        b = [b[0].min(p[0]), b[1].min(p[1]), b[2].max(p[0]), b[3].max(p[1]), b[4].min(p[2]), b[5].max(p[2])];
+    }
+    if b[0].is_finite() { b } else { [0.0; 6] }
+}
+
+impl Shapefile {
+    pub fn new(shape_type: ShapeType, fields: Vec<Field>) -> Shapefile {
+        Shapefile { shape_type, fields, records: Vec::new() }
+    }
+
+    /// Adds a record of `parts` with one value per field
+    pub fn add(&mut self, parts: Parts, values: Vec<Value>) {
+        self.records.push((parts, values));
+    }
+
+    /// Contents of the shape record of `parts`
+    fn shape(&self, parts: &Parts) -> Vec<u8> {
+        let points: Vec<&[f64; 3]> = parts.iter().flatten().collect();
+        let b = bounds(points.iter().copied());
+        let mut bytes = Vec::new();
+        bytes.extend((self.shape_type as i32).to_le_bytes());
+        [b[0], b[1], b[2], b[3]].iter().for_each(|v| bytes.extend(v.to_le_bytes()));
+        bytes.extend((parts.len() as i32).to_le_bytes());
+        bytes.extend((points.len() as i32).to_le_bytes());
+        let mut start = 0;
+        for part in parts {
+            bytes.extend((start as i32).to_le_bytes());
+            start += part.len();
+        }
+        points.iter().for_each(|p| bytes.extend(p[0].to_le_bytes().into_iter().chain(p[1].to_le_bytes())));
+        if self.shape_type == ShapeType::PolyLineZ {
+            bytes.extend(b[4].to_le_bytes().into_iter().chain(b[5].to_le_bytes()));
+            points.iter().for_each(|p| bytes.extend(p[2].to_le_bytes()));
+        }
+        bytes
+    }
+
+    /// Writes `<path>.shp` with its .shx, .dbf and .cpg next to it
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let shapes: Vec<Vec<u8>> = self.records.iter().map(|(parts, _)| self.shape(parts)).collect();
+        let mut bbox = bounds(self.records.iter().flat_map(|(parts, _)| parts.iter().flatten()));
+        if self.shape_type != ShapeType::PolyLineZ {
+            bbox[4..].fill(0.0);
+        }
+
+        let mut shp = BufWriter::new(File::create(path.with_extension("shp"))?);
+        let mut shx = BufWriter::new(File::create(path.with_extension("shx"))?);
+        write_header(&mut shp, self.shape_type, (100 + shapes.iter().map(|s| 8 + s.len()).sum::<usize>()) / 2, bbox)?;
+        write_header(&mut shx, self.shape_type, (100 + 8 * shapes.len()) / 2, bbox)?;
+        let mut offset = 100;
+        for (i, shape) in shapes.iter().enumerate() {
+            shp.write_all(&(i as i32 + 1).to_be_bytes())?;
+            shp.write_all(&(shape.len() as i32 / 2).to_be_bytes())?;
+            shp.write_all(shape)?;
+            shx.write_all(&(offset as i32 / 2).to_be_bytes())?;
+            shx.write_all(&(shape.len() as i32 / 2).to_be_bytes())?;
+            offset += 8 + shape.len();
+        }
+        shp.flush()?;
+        shx.flush()?;
+
+        let mut dbf = BufWriter::new(File::create(path.with_extension("dbf"))?);
+        self.write_dbf(&mut dbf)?;
+        dbf.flush()?;
+        std::fs::write(path.with_extension("cpg"), "UTF-8")
+    }
+
+    /// The attribute table as dBASE III
+    fn write_dbf<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let width = |field: &Field| match field.kind {
+            FieldType::Number { width, .. } | FieldType::Text { width } => width as usize,
+        };
+        let record_length = 1 + self.fields.iter().map(width).sum::<usize>();
+        let header_length = 32 + 32 * self.fields.len() + 1;
+        out.write_all(&[0x03, 126, 1, 1])?; // Version and the date of the last update, fixed so runs are reproducible
+        out.write_all(&(self.records.len() as u32).to_le_bytes())?;
+        out.write_all(&(header_length as u16).to_le_bytes())?;
+        out.write_all(&(record_length as u16).to_le_bytes())?;
+        out.write_all(&[0; 20])?;
+        for field in &self.fields {
+            let mut name = [0u8; 11];
+            field.name.bytes().take(10).enumerate().for_each(|(i, b)| name[i] = b);
+            out.write_all(&name)?;
+            let (kind, decimals) = match field.kind {
+                FieldType::Number { decimals, .. } => (b'N', decimals),
+                FieldType::Text { .. } => (b'C', 0),
+            };
+            out.write_all(&[kind, 0, 0, 0, 0, width(field) as u8, decimals])?;
+            out.write_all(&[0; 14])?;
+        }
+        out.write_all(&[0x0D])?;
+        for (_, values) in &self.records {
+            out.write_all(b" ")?;
+            for (i, field) in self.fields.iter().enumerate() {
+                let text = match (field.kind, values.get(i)) {
+                    (FieldType::Number { width, decimals }, Some(Value::Number(v))) => format!("{:>w$.d$}", v, w = width as usize, d = decimals as usize),
+                    (_, Some(Value::Text(t))) => t.clone(),
+                    (_, Some(Value::Number(v))) => v.to_string(),
+                    (_, Some(Value::Null) | None) => String::new(),
+                };
+                // Cut on a character boundary and pad to the width, numbers too wide for it stay empty
+                let mut cell: Vec<u8> = Vec::new();
+                for c in text.chars() {
+                    if cell.len() + c.len_utf8() > width(field) {
+                        break;
+                    }
+                    cell.extend(c.to_string().bytes());
+                }
+                if matches!(field.kind, FieldType::Number { .. }) && cell.len() < text.len() {
+                    cell.clear();
+                }
+                cell.resize(width(field), b' ');
+                out.write_all(&cell)?;
+            }
+        }
+        out.write_all(&[0x1A])
+    }
+}
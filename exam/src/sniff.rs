@@ -0,0 +1,129 @@
+//! Input formats recognized by their first bytes instead of the extension, so a `.txt` holding an ESRI grid or a
+//! `.tif` that is really ASCII still reaches the right parser, and images or archives fail with a clear error.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Bytes read from the start of a file to recognize it
+pub const SNIFF_LEN: usize = 512;
+
+/// Keywords of an ESRI ASCII header, any of them may come first
+const ESRI_KEYS: [&str; 8] = ["ncols", "nrows", "xllcorner", "yllcorner", "xllcenter", "yllcenter", "cellsize", "nodata_value"];
+
+/// Keywords of a GRASS ASCII header
+const GRASS_KEYS: [&str; 8] = ["north", "south", "east", "west", "rows", "cols", "null", "type"];
+
+/// Grid formats the loaders understand
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputFormat {
+    EsriAscii,
+    GrassAscii,
+    GeoTiff,
+    E00,
+    Las,    // Also LAZ, the same header with compressed points
+    NetCdf, // Classic and 64-bit offset netCDF, netCDF-4 files are HDF5
+    Hdf5,
+}
+
+impl InputFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            InputFormat::EsriAscii => "an ESRI ASCII grid",
+            InputFormat::GrassAscii => "a GRASS ASCII raster",
+            InputFormat::GeoTiff => "a TIFF",
+            InputFormat::E00 => "an Arc/Info export",
+            InputFormat::Las => "a LAS/LAZ point cloud",
+            InputFormat::NetCdf => "a netCDF file",
+            InputFormat::Hdf5 => "an HDF5 file",
+        }
+    }
+
+    /// Short name for tables and file names
+    pub fn key(&self) -> &'static str {
+        match self {
+            InputFormat::EsriAscii => "esri-ascii",
+            InputFormat::GrassAscii => "grass-ascii",
+            InputFormat::GeoTiff => "geotiff",
+            InputFormat::E00 => "e00",
+            InputFormat::Las => "las",
+            InputFormat::NetCdf => "netcdf",
+            InputFormat::Hdf5 => "hdf5",
+        }
+    }
+
+    /// Format usually stored under the extension of `path`
+    pub fn from_extension(path: &Path) -> Option<InputFormat> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "asc" => Some(InputFormat::EsriAscii),
+            "tif" | "tiff" => Some(InputFormat::GeoTiff),
+            "e00" => Some(InputFormat::E00),
+            "las" | "laz" => Some(InputFormat::Las),
+            "nc" | "nc4" | "cdf" => Some(InputFormat::NetCdf),
+            "h5" | "hdf5" | "he5" => Some(InputFormat::Hdf5),
+            _ => None,
+        }
+    }
+
+    /// Whether the extension of `path` fits the content; GRASS rasters share .asc with ESRI grids and netCDF-4 is HDF5
+    pub fn matches_extension(&self, path: &Path) -> bool {
+        match (InputFormat::from_extension(path), self) {
+            (Some(InputFormat::EsriAscii), InputFormat::GrassAscii) => true,
+            (Some(InputFormat::NetCdf), InputFormat::Hdf5) => true,
+            (extension, _) => extension == Some(*self),
+        }
+    }
+}
+
+/// Recognizes the format from the first bytes of a file, or tells what the file is instead
+pub fn sniff(head: &[u8]) -> Result<InputFormat, String> {
+    if crate::geotiff::is_tiff(head) {
+        return Ok(InputFormat::GeoTiff);
+    }
+    if crate::e00::is_e00(head) {
+        return Ok(InputFormat::E00);
+    }
+    if head.starts_with(b"LASF") {
+        return Ok(InputFormat::Las);
+    }
+    if head.starts_with(b"CDF") && matches!(head.get(3), Some(1 | 2 | 5)) {
+        return Ok(InputFormat::NetCdf);
+    }
+    if head.starts_with(b"\x89HDF\r\n\x1a\n") {
+        return Ok(InputFormat::Hdf5);
+    }
+    let other = [
+        (&b"\x89PNG"[..], "a PNG image, not an elevation grid"),
+        (b"\xff\xd8\xff", "a JPEG image, not an elevation grid"),
+        (b"GIF8", "a GIF image, not an elevation grid"),
+        (b"PK\x03\x04", "a ZIP archive, extract the grid first"),
+        (b"\x1f\x8b", "gzip-compressed, decompress it first"),
+        (b"%PDF", "a PDF document, not an elevation grid"),
+        (b"\x93NUMPY", "a NumPy array, which has no georeferencing; write it as .asc or .tif"),
+        (b"EXAMGRID", "a --cache sidecar, read the .asc file next to it"),
+    ];
+    if let Some((_, what)) = other.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return Err(format!("The file is {}", what));
+    }
+
+    // Text: the first word of the header, after a byte order mark and blank lines
+    let text = String::from_utf8_lossy(head.strip_prefix(b"\xef\xbb\xbf").unwrap_or(head));
+    let first = text.split_whitespace().next().unwrap_or_default().to_lowercase();
+    if ESRI_KEYS.contains(&first.as_str()) {
+        return Ok(InputFormat::EsriAscii);
+    }
+    if first.split_once(':').is_some_and(|(key, _)| GRASS_KEYS.contains(&key)) {
+        return Ok(InputFormat::GrassAscii);
+    }
+    if first.is_empty() {
+        return Err("The file is empty".into());
+    }
+    Err("The file is not a recognized elevation grid (ESRI or GRASS ASCII, GeoTIFF, E00, LAS/LAZ, netCDF or HDF5)".into())
+}
+
+/// Reads the first bytes of `path` and recognizes its format
+pub fn sniff_file(path: &Path) -> io::Result<InputFormat> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    File::open(path)?.take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+    sniff(&head).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
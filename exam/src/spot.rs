@@ -0,0 +1,193 @@
+//! Spot heights: the highest and lowest valid cells of a grid and its most prominent peaks, marked and labeled with
+//! their elevation on the rendered images and listed with their map coordinates in the run report.
+
+use image::{DynamicImage, Rgba, RgbaImage};
+
+use crate::font;
+use crate::grid::Grid;
+
+/// Fill of the markers and labels, each drawn on a white halo
+const HIGHEST_COLOR: [u8; 3] = [200, 30, 30];
+const LOWEST_COLOR: [u8; 3] = [30, 80, 200];
+const PEAK_COLOR: [u8; 3] = [30, 30, 30];
+const HALO: [u8; 3] = [255, 255, 255];
+
+/// What a spot height marks
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "fs", derive(serde::Serialize), serde(rename_all = "lowercase"))]
+pub enum SpotKind {
+    Highest,
+    Lowest,
+    Peak,
+}
+
+/// A marked cell
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "fs", derive(serde::Serialize))]
+pub struct SpotHeight {
+    pub kind: SpotKind,
+    pub col: usize,
+    pub row: usize,              // Counted from the north
+    pub x: f64,                  // Map coordinates of the cell center
+    pub y: f64,
+    pub elevation: f32,
+    pub prominence: Option<f32>, // Height above the lowest col connecting it to higher ground, peaks only
+}
+
+impl SpotHeight {
+    fn new(grid: &Grid, kind: SpotKind, col: usize, row: usize) -> SpotHeight {
+        let (x0, y0) = grid.origin();
+        SpotHeight {
+            kind,
+            col,
+            row,
+            x: x0 + (col as f64 + 0.5) * grid.cellsize,
+            y: y0 - (row as f64 + 0.5) * grid.cellsize,
+            elevation: grid.data[row][col],
+            prominence: None,
+        }
+    }
+}
+
+/// The highest and the lowest valid cell, the first in row order on ties; None if no cell is valid
+pub fn extremes(grid: &Grid) -> Option<(SpotHeight, SpotHeight)> {
+    let mut cells = grid.iter_valid();
+    let first = cells.next()?;
+    let (high, low) = cells.fold((first, first), |(high, low), cell| {
+        (if cell.2 > high.2 { cell } else { high }, if cell.2 < low.2 { cell } else { low })
+    });
+    Some((SpotHeight::new(grid, SpotKind::Highest, high.0, high.1), SpotHeight::new(grid, SpotKind::Lowest, low.0, low.1)))
+}
+
+/// The `n` most prominent peaks besides the highest cell, most prominent first. Prominence is how far a peak rises
+/// above the lowest col (saddle) on the way to higher ground; NoData and the edges of the grid end the way, so peaks
+/// cut off by them count as higher than they may be.
+pub fn peaks(grid: &Grid, n: usize) -> Vec<SpotHeight> {
+    if n == 0 {
+        return Vec::new();
+    }
+    let ncols = grid.ncols;
+    let mut cells: Vec<(f32, usize)> = grid.iter_valid().map(|(x, y, val)| (val, y * ncols + x)).collect();
+    cells.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+
+    // Cells are flooded from the top down; each area that is flooded so far keeps the cell of its peak. Where two
+    // areas meet, the one with the lower peak ends, that peak's prominence measured down to the meeting cell.
+    const DRY: usize = usize::MAX;
+    let mut parent = vec![DRY; ncols * grid.nrows];
+    let mut summit = vec![0; ncols * grid.nrows];
+    let mut found: Vec<(f32, usize)> = Vec::new();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    let elevation = |i: usize| grid.data[i / ncols][i % ncols];
+    for &(val, i) in &cells {
+        parent[i] = i;
+        summit[i] = i;
+        let (x, y) = ((i % ncols) as isize, (i / ncols) as isize);
+        for (dx, dy) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+            let (nx, ny) = (x + dx, y + dy);
+            if nx < 0 || ny < 0 || nx as usize >= ncols || ny as usize >= grid.nrows || parent[ny as usize * ncols + nx as usize] == DRY {
+                continue;
+            }
+            let (a, b) = (root(&mut parent, i), root(&mut parent, ny as usize * ncols + nx as usize));
+            if a == b {
+                continue;
+            }
+            // Equal peaks: the one flooded first, earlier in row order, stays
+            let (keep, end) = if (elevation(summit[a]), usize::MAX - summit[a]) >= (elevation(summit[b]), usize::MAX - summit[b]) { (a, b) } else { (b, a) };
+            if summit[end] != i {
+                found.push((elevation(summit[end]) - val, summit[end]));
+            }
+            parent[end] = keep;
+        }
+    }
+    // The peaks of the areas that never met another one rise above the lowest cell, except for the highest
+    let lowest = cells.last().map_or(0.0, |c| c.0);
+    let highest = cells.first().map(|c| c.1);
+    for &(_, i) in &cells {
+        if parent[i] == i && Some(summit[i]) != highest {
+            found.push((elevation(summit[i]) - lowest, summit[i]));
+        }
+    }
+
+    found.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    found.into_iter().take(n).map(|(prominence, i)| SpotHeight {
+        prominence: Some(prominence),
+        ..SpotHeight::new(grid, SpotKind::Peak, i % ncols, i / ncols)
+    }).collect()
+}
+
+/// Spot heights to mark on the images of a grid
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpotHeights {
+    pub ncols: usize,
+    pub nrows: usize,
+    pub spots: Vec<SpotHeight>, // Highest, lowest, then the peaks
+}
+
+impl SpotHeights {
+    /// The highest and lowest cell of `grid` and its `peaks` most prominent peaks
+    pub fn of(grid: &Grid, peaks: usize) -> SpotHeights {
+        let extremes = extremes(grid).map(|(high, low)| [high, low]).into_iter().flatten();
+        SpotHeights { ncols: grid.ncols, nrows: grid.nrows, spots: extremes.chain(self::peaks(grid, peaks)).collect() }
+    }
+
+    /// Draws a triangle at every spot, pointing up on peaks and down on the lowest cell, labeled with its elevation
+    /// rounded to the unit. Markers grow with the image, labels move left of their marker near the right edge.
+    /// Images of another size than the grid are left as they are.
+    pub fn apply(&self, img: &mut DynamicImage) {
+        if self.spots.is_empty() || (img.width() as usize, img.height() as usize) != (self.ncols, self.nrows) {
+            return;
+        }
+        let alpha = img.color().has_alpha();
+        let mut rgba = img.to_rgba8();
+        let scale = (img.width().max(img.height()) / 500).max(1);
+        // Peaks under the extremes, so their labels stay readable
+        for spot in self.spots.iter().rev() {
+            let color = match spot.kind {
+                SpotKind::Highest => HIGHEST_COLOR,
+                SpotKind::Lowest => LOWEST_COLOR,
+                SpotKind::Peak => PEAK_COLOR,
+            };
+            draw_marker(&mut rgba, spot, scale, color);
+        }
+        *img = if alpha { DynamicImage::ImageRgba8(rgba) } else { DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba).to_rgb8()) };
+    }
+}
+
+/// One marker with its label, in `color` on a halo one `scale` wide
+fn draw_marker(img: &mut RgbaImage, spot: &SpotHeight, scale: u32, [r, g, b]: [u8; 3]) {
+    let (cx, cy) = (spot.col as i64, spot.row as i64);
+    let half = 3 * scale as i64;
+    let up = spot.kind != SpotKind::Lowest;
+    let inside = |x: i64, y: i64, grow: i64| {
+        // Triangle with its base `2 * half` wide, centered on the cell
+        let dy = if up { y - cy + half } else { cy + half - y };
+        dy >= -grow && dy <= 2 * half + grow && 2 * (x - cx).abs() <= dy + 2 * grow
+    };
+    let (w, h) = (img.width() as i64, img.height() as i64);
+    let grow = scale as i64;
+    for y in (cy - half - 2 * grow).max(0)..(cy + half + 2 * grow + 1).min(h) {
+        for x in (cx - half - 2 * grow).max(0)..(cx + half + 2 * grow + 1).min(w) {
+            if inside(x, y, 0) {
+                img.put_pixel(x as u32, y as u32, Rgba([r, g, b, 255]));
+            } else if inside(x, y, grow) {
+                img.put_pixel(x as u32, y as u32, Rgba([HALO[0], HALO[1], HALO[2], 255]));
+            }
+        }
+    }
+
+    let text = format!("{:.0}", spot.elevation);
+    let (tw, th) = (font::text_width(&text, scale) as i64, font::text_height(scale) as i64);
+    let gap = half + 2 * grow;
+    let tx = if cx + gap + tw < w { cx + gap } else { cx - gap - tw };
+    let ty = cy - th / 2;
+    for (dx, dy) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+        font::draw_text(img, tx + dx * grow, ty + dy * grow, &text, scale, Rgba([HALO[0], HALO[1], HALO[2], 255]));
+    }
+    font::draw_text(img, tx, ty, &text, scale, Rgba([r, g, b, 255]));
+}
@@ -0,0 +1,110 @@
+//! Illuminated contours after Kitiro Tanaka, a product of the [`crate::plugin::Registry`]: contour lines on slopes
+//! facing the sun are drawn white and those facing away dark, over a muted hypsometric tint stepped at the contours,
+//! so the relief reads as stacked layers lit from one side.
+
+use image::{Rgb, RgbImage};
+
+use crate::contour;
+use crate::grid::Grid;
+#[cfg(feature = "fs")]
+use crate::plugin::{Inputs, Product};
+#[cfg(feature = "fs")]
+use image::DynamicImage;
+
+/// Tint from the lowest to the highest band, mixed halfway to a neutral gray so the lines stand out
+const TINT: [[f32; 3]; 4] = [[112.0, 150.0, 104.0], [196.0, 190.0, 128.0], [170.0, 130.0, 92.0], [236.0, 232.0, 226.0]];
+const NEUTRAL: [f32; 3] = [150.0, 150.0, 150.0];
+const MUTE: f32 = 0.5;
+
+/// Contours at every multiple of `interval`, or about 20 of them between the lowest and highest cell
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tanaka {
+    pub interval: Option<f64>,
+}
+
+impl Tanaka {
+    /// Contours of "tanaka" between the lowest and the highest cell unless given as "tanaka:<interval>"
+    pub const DEFAULT_CONTOURS: f64 = 20.0;
+
+    /// Parses "tanaka" or "tanaka:<interval>" with an interval above 0 in elevation units
+    pub fn parse(name: &str) -> Option<Tanaka> {
+        let name = name.to_lowercase();
+        let interval = match name.split_once(':') {
+            None if name == "tanaka" => None,
+            Some(("tanaka", interval)) => Some(interval.parse().ok().filter(|i: &f64| *i > 0.0 && i.is_finite())?),
+            _ => return None,
+        };
+        Some(Tanaka { interval })
+    }
+
+    /// The contour interval used on `grid`
+    pub fn interval(&self, grid: &Grid) -> f64 {
+        let (min, max) = grid.min_max();
+        self.interval.unwrap_or_else(|| contour::round_interval((max - min) as f64, Tanaka::DEFAULT_CONTOURS))
+    }
+
+    /// The tinted bands with their contours lit from `azimuth` degrees clockwise from north. Lines are about
+    /// 1/500 of the width of the grid wide, at least a cell, full width where they face the sun or turn away from
+    /// it and thinner where they run along its light. NoData is black.
+    pub fn render(&self, grid: &Grid, azimuth: f64) -> RgbImage {
+        let interval = self.interval(grid);
+        let (min, max) = grid.min_max();
+        let (first, last) = ((min as f64 / interval).floor(), (max as f64 / interval).floor());
+        let bands = (last - first).max(1.0);
+        let width = (grid.ncols as f64 / 500.0).max(1.0);
+        // Toward the sun, rows counted southward
+        let (sx, sy) = (azimuth.to_radians().sin(), -azimuth.to_radians().cos());
+
+        let mut img = RgbImage::new(grid.ncols as u32, grid.nrows as u32);
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            let (xi, yi) = (x as isize, y as isize);
+            let Some(val) = grid.get(xi, yi).map(f64::from) else { continue };
+            let band = (val / interval).floor();
+            let tint = tint(((band - first) / bands) as f32);
+
+            // Central differences, one-sided at the edges and next to NoData
+            let diff = |a: Option<f32>, b: Option<f32>| match (a, b) {
+                (Some(a), Some(b)) => (b - a) as f64 / 2.0,
+                (Some(a), None) => val - a as f64,
+                (None, Some(b)) => b as f64 - val,
+                (None, None) => 0.0,
+            };
+            let (gx, gy) = (diff(grid.get(xi - 1, yi), grid.get(xi + 1, yi)), diff(grid.get(xi, yi - 1), grid.get(xi, yi + 1)));
+            let gradient = gx.hypot(gy);
+            if gradient < f64::EPSILON {
+                *pixel = Rgb(tint.map(|c| c.round() as u8));
+                continue;
+            }
+            // Slopes whose downhill side faces the sun are lit
+            let facing = -(gx * sx + gy * sy) / gradient;
+            let distance = (val - (val / interval).round() * interval).abs() / gradient;
+            let half = width * (0.3 + 0.7 * facing.abs()) / 2.0;
+            let cover = (half + 0.5 - distance).clamp(0.0, 1.0) as f32;
+            let line = (127.5 + 127.5 * facing) as f32;
+            *pixel = Rgb(std::array::from_fn(|i| (tint[i] + (line - tint[i]) * cover).round() as u8));
+        }
+        img
+    }
+}
+
+/// Muted tint at `t` from 0 (lowest band) to 1 (highest)
+fn tint(t: f32) -> [f32; 3] {
+    let pos = t.clamp(0.0, 1.0) * (TINT.len() - 1) as f32;
+    let (i, f) = ((pos as usize).min(TINT.len() - 2), pos - (pos as usize).min(TINT.len() - 2) as f32);
+    std::array::from_fn(|c| {
+        let color = TINT[i][c] + (TINT[i + 1][c] - TINT[i][c]) * f;
+        color + (NEUTRAL[c] - color) * MUTE
+    })
+}
+
+/// The "tanaka" product, lit by the sun of the run
+#[cfg(feature = "fs")]
+impl Product for Tanaka {
+    fn name(&self) -> &str {
+        "tanaka"
+    }
+
+    fn render(&self, inputs: &Inputs) -> DynamicImage {
+        DynamicImage::ImageRgb8(Tanaka::render(self, inputs.grid(), inputs.params().azimuth))
+    }
+}
@@ -0,0 +1,90 @@
+//! Terraced relief for poster-style terrain art, a product of the [`crate::plugin::Registry`]: the elevations are
+//! quantized into stepped layers like stacked sheets of laser-cut card, each a flat tone, and every step casts a soft
+//! shadow away from the sun onto the layer below it.
+
+use image::{Rgb, RgbImage};
+
+use crate::grid::Grid;
+#[cfg(feature = "fs")]
+use crate::plugin::{Inputs, Product};
+#[cfg(feature = "fs")]
+use image::DynamicImage;
+
+/// Darkest (lowest) and lightest (highest) layer, shades of a warm card
+const LOW_CARD: [f32; 3] = [92.0, 78.0, 66.0];
+const HIGH_CARD: [f32; 3] = [246.0, 240.0, 228.0];
+
+/// Darkening right at the foot of a step, fading over the width of the shadow
+const SHADOW_DEPTH: f32 = 0.35;
+
+/// Elevations quantized into `levels` equal steps between the lowest and highest valid cell
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Terraces {
+    pub levels: u32,
+}
+
+impl Terraces {
+    /// Steps of "terraces" unless given as "terraces:<levels>"
+    pub const DEFAULT_LEVELS: u32 = 12;
+
+    /// Parses "terraces" or "terraces:<levels>" with 2 to 64 levels
+    pub fn parse(name: &str) -> Option<Terraces> {
+        let name = name.to_lowercase();
+        let levels = match name.split_once(':') {
+            None if name == "terraces" => Terraces::DEFAULT_LEVELS,
+            Some(("terraces", levels)) => levels.parse().ok().filter(|l| (2..=64).contains(l))?,
+            _ => return None,
+        };
+        Some(Terraces { levels })
+    }
+
+    /// Layer of every cell row-major from 0 (lowest) to `levels - 1`, None on NoData
+    pub fn layers(&self, grid: &Grid) -> Vec<Option<u32>> {
+        let (min, max) = grid.min_max();
+        let span = (max - min).max(f32::EPSILON);
+        let top = self.levels.max(1) - 1;
+        grid.iter_cells()
+            .map(|(_, _, val)| (!grid.is_nodata(val)).then(|| (((val - min) / span * self.levels as f32) as u32).min(top)))
+            .collect()
+    }
+
+    /// The layers in card tones, with the shadows of a sun from `azimuth` degrees clockwise from north. Shadows
+    /// reach about 1/150 of the width of the grid, at least two cells. NoData is black.
+    pub fn render(&self, grid: &Grid, azimuth: f64) -> RgbImage {
+        let layers = self.layers(grid);
+        let top = (self.levels.max(2) - 1) as f32;
+        let reach = (grid.ncols / 150).max(2);
+        // One cell toward the sun, rows counted southward
+        let (dx, dy) = (azimuth.to_radians().sin(), -azimuth.to_radians().cos());
+        let layer = |x: f64, y: f64| {
+            let (x, y) = (x.round(), y.round());
+            let inside = x >= 0.0 && y >= 0.0 && (x as usize) < grid.ncols && (y as usize) < grid.nrows;
+            if inside { layers[y as usize * grid.ncols + x as usize] } else { None }
+        };
+
+        let mut img = RgbImage::new(grid.ncols as u32, grid.nrows as u32);
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            let Some(own) = layers[y as usize * grid.ncols + x as usize] else { continue };
+            // The nearest higher layer toward the sun shades the cell, the more the closer it is
+            let shadow = (1..=reach)
+                .find(|&d| layer(x as f64 + dx * d as f64, y as f64 + dy * d as f64).is_some_and(|l| l > own))
+                .map_or(0.0, |d| 1.0 - (d - 1) as f32 / reach as f32);
+            let t = own as f32 / top;
+            let light = 1.0 - SHADOW_DEPTH * shadow;
+            *pixel = Rgb(std::array::from_fn(|i| ((LOW_CARD[i] + (HIGH_CARD[i] - LOW_CARD[i]) * t) * light).round() as u8));
+        }
+        img
+    }
+}
+
+/// The "terraces" product, lit by the sun of the run
+#[cfg(feature = "fs")]
+impl Product for Terraces {
+    fn name(&self) -> &str {
+        "terraces"
+    }
+
+    fn render(&self, inputs: &Inputs) -> DynamicImage {
+        DynamicImage::ImageRgb8(Terraces::render(self, inputs.grid(), inputs.params().azimuth))
+    }
+}
@@ -0,0 +1,383 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+#[cfg(feature = "fs")]
+use std::io;
+use colorgrad::Gradient;
+#[cfg(feature = "fs")]
+use image::DynamicImage;
+use image::{Rgb, RgbImage};
+
+use crate::grid::{EdgeMode, Grid};
+use crate::horizon::horizon_angles;
+#[cfg(feature = "fs")]
+use crate::output::{save_image, save_raw_values, OutputFormat, OutputTarget, RawFormat};
+#[cfg(feature = "fs")]
+use crate::plugin::{save_product, Input, Inputs, Product};
+
+/// Terrain derivative computed from the elevation, written as a colormapped image and as raw values
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Derivative {
+    Slope,            // Degrees from horizontal
+    Aspect,           // Degrees clockwise from north of the downslope direction, -1 on flat cells
+    Curvature,        // Zevenbergen & Thorne curvature in 1/100 elevation units, positive is convex
+    FlowAccumulation, // Number of upstream cells draining through each cell (D8)
+    Twi,              // Topographic wetness index ln(a / tan β)
+    Spi,              // Stream power index a · tan β
+    Horizon(f64),     // Horizon angle in degrees toward the azimuth, see [`crate::horizon::horizon_angles`]
+}
+
+impl Derivative {
+    /// Azimuth of "horizon" unless given as "horizon:<azimuth>", toward the midday sun north of the tropics
+    pub const DEFAULT_HORIZON_AZIMUTH: f64 = 180.0;
+
+    /// Every derivative of --derive all, the horizon toward [`Derivative::DEFAULT_HORIZON_AZIMUTH`] needs to be asked for
+    pub const ALL: [Derivative; 6] = [Derivative::Slope, Derivative::Aspect, Derivative::Curvature, Derivative::FlowAccumulation, Derivative::Twi, Derivative::Spi];
+
+    pub fn parse(name: &str) -> Option<Derivative> {
+        if let Some(azimuth) = name.to_lowercase().strip_prefix("horizon:") {
+            return azimuth.parse().ok().filter(|a: &f64| a.is_finite()).map(|a| Derivative::Horizon(a.rem_euclid(360.0)));
+        }
+        match name.to_lowercase().as_str() {
+            "slope" => Some(Derivative::Slope),
+            "aspect" => Some(Derivative::Aspect),
+            "curvature" => Some(Derivative::Curvature),
+            "flow" | "flow-accumulation" | "flow_accumulation" => Some(Derivative::FlowAccumulation),
+            "twi" | "wetness" => Some(Derivative::Twi),
+            "spi" | "stream-power" | "stream_power" => Some(Derivative::Spi),
+            "horizon" => Some(Derivative::Horizon(Derivative::DEFAULT_HORIZON_AZIMUTH)),
+            _ => None,
+        }
+    }
+
+    /// Name used for the output files
+    pub fn name(&self) -> &'static str {
+        match self {
+            Derivative::Slope => "slope",
+            Derivative::Aspect => "aspect",
+            Derivative::Curvature => "curvature",
+            Derivative::FlowAccumulation => "flow_accumulation",
+            Derivative::Twi => "twi",
+            Derivative::Spi => "spi",
+            Derivative::Horizon(_) => "horizon",
+        }
+    }
+
+    /// Computes the derivative for every cell, NoData stays NoData
+    pub fn compute(&self, grid: &Grid) -> Grid {
+        self.compute_with(grid, EdgeMode::default())
+    }
+
+    /// [`Derivative::compute`] with the border cells of the 3x3 kernels handled as `edges` says
+    pub fn compute_with(&self, grid: &Grid, edges: EdgeMode) -> Grid {
+        match self {
+            Derivative::Slope => slope(grid, edges),
+            Derivative::Aspect => aspect(grid, edges),
+            Derivative::Curvature => curvature(grid, edges),
+            Derivative::FlowAccumulation => flow_accumulation(grid),
+            Derivative::Twi => wetness_index(grid),
+            Derivative::Spi => stream_power_index(grid),
+            Derivative::Horizon(azimuth) => horizon_angles(grid, *azimuth),
+        }
+    }
+
+    /// Colormapped preview of values returned by [`Derivative::compute`]
+    pub fn render(&self, values: &Grid) -> RgbImage {
+        match self {
+            Derivative::Slope => {
+                let (_, max) = values.min_max();
+                colorize(values, &colorgrad::magma(), |v| v as f64 / max.max(1e-6) as f64)
+            }
+            // Cyclic colormap so north-west and north-east facing slopes look alike
+            Derivative::Aspect => colorize(values, &colorgrad::sinebow(), |v| if v < 0.0 { -1.0 } else { v as f64 / 360.0 }),
+            Derivative::Curvature => {
+                // Curvature is heavy tailed, stretch to the 98th percentile so the bulk stays visible
+                let limit = abs_percentile(values, 0.98).max(1e-6);
+                colorize(values, &colorgrad::rd_bu(), |v| (0.5 + v as f64 / limit / 2.0).clamp(0.0, 1.0))
+            }
+            Derivative::FlowAccumulation => {
+                let (_, max) = values.min_max();
+                let log_max = (max as f64).ln_1p().max(1e-6);
+                colorize(values, &colorgrad::blues(), |v| (v as f64).ln_1p() / log_max)
+            }
+            // Dry ridges yellow to wet valley floors blue, over the 2nd to 98th percentile
+            Derivative::Twi => {
+                let (low, high) = (percentile(values, 0.02), percentile(values, 0.98));
+                colorize(values, &colorgrad::yl_gn_bu(), |v| ((v as f64 - low) / (high - low).max(1e-6)).clamp(0.0, 1.0))
+            }
+            // Spans orders of magnitude like the flow it grows with
+            Derivative::Spi => {
+                let log_max = percentile(values, 0.99).ln_1p().max(1e-6);
+                colorize(values, &colorgrad::yl_or_rd(), |v| (v as f64).ln_1p() / log_max)
+            }
+            // Open sky dark to deep shade bright, cells looking down at their horizon count as open
+            Derivative::Horizon(_) => {
+                let (_, max) = values.min_max();
+                colorize(values, &colorgrad::cividis(), |v| (v as f64 / max.max(1e-6) as f64).max(0.0))
+            }
+        }
+    }
+}
+
+/// Saves the colormapped preview as product "<name>" plus the raw values as "<name>_f32" (.tif / .npy)
+#[cfg(feature = "fs")]
+pub fn save_derivative(grid: &Grid, derivative: Derivative, raw: &[RawFormat], format: &OutputFormat, target: &OutputTarget) -> io::Result<()> {
+    save_product(&derivative, &Inputs::new(grid, &target.shade), raw, format, target)
+}
+
+/// The derivatives as products of the [`crate::plugin::Registry`]
+#[cfg(feature = "fs")]
+impl Product for Derivative {
+    fn name(&self) -> &str {
+        Derivative::name(self)
+    }
+
+    fn inputs(&self) -> Vec<Input> {
+        vec![Input::Derivative(*self)]
+    }
+
+    fn render(&self, inputs: &Inputs) -> DynamicImage {
+        DynamicImage::ImageRgb8(Derivative::render(self, &inputs.get(Input::Derivative(*self))))
+    }
+
+    fn save(&self, inputs: &Inputs, raw: &[RawFormat], format: &OutputFormat, target: &OutputTarget) -> io::Result<()> {
+        let values = inputs.get(Input::Derivative(*self));
+        save_image(DynamicImage::ImageRgb8(Derivative::render(self, &values)), target, self.name(), format)?;
+        save_raw_values(&values, &format!("{}_f32", self.name()), raw, format, target)
+    }
+}
+
+/// Horn's gradients: (dz/dx towards east, dz/dy towards south) of the window `[a, b, c, d, e, f, g, h, i]` (north row
+/// first, None on NoData) for cells `dx` wide and `dy` high. Horn's kernel is the 1-2-1 weighted mean of three central
+/// differences; with NoData neighbors, like GDAL, a difference missing one end becomes one-sided from the middle
+/// cell of its row and one missing both is left out of the mean, so holes don't leak into the slope.
+pub fn horn_gradient(w: &[Option<f64>; 9], (dx, dy): (f64, f64)) -> (f64, f64) {
+    if let [Some(a), Some(b), Some(c), Some(d), _, Some(f), Some(g), Some(h), Some(i)] = *w {
+        let dzdx = ((c + 2.0 * f + i) - (a + 2.0 * d + g)) / (8.0 * dx);
+        let dzdy = ((g + 2.0 * h + i) - (a + 2.0 * b + c)) / (8.0 * dy);
+        return (dzdx, dzdy);
+    }
+    let rows = [(w[0], w[1], w[2]), (w[3], w[4], w[5]), (w[6], w[7], w[8])];
+    let columns = [(w[0], w[3], w[6]), (w[1], w[4], w[7]), (w[2], w[5], w[8])];
+    (weighted_difference(rows, dx), weighted_difference(columns, dy))
+}
+
+/// 1-2-1 weighted mean of the differences `(low, middle, high)` across `spacing`, see [`horn_gradient`]
+fn weighted_difference(lines: [(Option<f64>, Option<f64>, Option<f64>); 3], spacing: f64) -> f64 {
+    let (mut total, mut weights) = (0.0, 0.0);
+    for ((low, middle, high), weight) in lines.into_iter().zip([1.0, 2.0, 1.0]) {
+        let difference = match (low, middle, high) {
+            (Some(low), _, Some(high)) => (high - low) / (2.0 * spacing),
+            (None, Some(middle), Some(high)) => (high - middle) / spacing,
+            (Some(low), Some(middle), None) => (middle - low) / spacing,
+            _ => continue,
+        };
+        total += weight * difference;
+        weights += weight;
+    }
+    if weights > 0.0 { total / weights } else { 0.0 }
+}
+
+/// Applies `f` to the 3x3 window `[a, b, c, d, e, f, g, h, i]` (north row first) of every valid cell,
+/// neighbors outside the grid are filled in as `edges` says and those on NoData are None
+pub fn map_windows(grid: &Grid, edges: EdgeMode, f: impl Fn(&[Option<f64>; 9]) -> f64) -> Grid {
+    let mut data = grid.data.clone();
+    for (x, y, _) in grid.iter_valid() {
+        data[y][x] = match grid.edge_window(x, y, edges) {
+            Some(w) => f(&w) as f32,
+            None => grid.nodata,
+        };
+    }
+    Grid { data, ..grid.clone() }
+}
+
+pub fn slope(grid: &Grid, edges: EdgeMode) -> Grid {
+    let spacing = grid.ground_spacing();
+    map_windows(grid, edges, |w| {
+        let (dzdx, dzdy) = horn_gradient(w, spacing);
+        dzdx.hypot(dzdy).atan().to_degrees()
+    })
+}
+
+pub fn aspect(grid: &Grid, edges: EdgeMode) -> Grid {
+    let spacing = grid.ground_spacing();
+    map_windows(grid, edges, |w| {
+        let (dzdx, dzdy) = horn_gradient(w, spacing);
+        if dzdx == 0.0 && dzdy == 0.0 {
+            return -1.0;
+        }
+        // Downslope direction is against the gradient: east component -dzdx, north component dzdy
+        let azimuth = (-dzdx).atan2(dzdy).to_degrees();
+        if azimuth < 0.0 { azimuth + 360.0 } else { azimuth }
+    })
+}
+
+pub fn curvature(grid: &Grid, edges: EdgeMode) -> Grid {
+    let (dx, dy) = grid.ground_spacing();
+    // A second derivative needs both neighbors, with one on NoData it counts as flat
+    let second = |low: Option<f64>, e: f64, high: Option<f64>, spacing: f64| match (low, high) {
+        (Some(low), Some(high)) => ((low + high) / 2.0 - e) / (spacing * spacing),
+        _ => 0.0,
+    };
+    map_windows(grid, edges, |w| {
+        let e = w[4].unwrap_or_default();
+        -2.0 * (second(w[3], e, w[5], dx) + second(w[1], e, w[7], dy)) * 100.0
+    })
+}
+
+/// D8 flow accumulation: every cell drains into its steepest downhill neighbor,
+/// cells are visited from the highest to the lowest so each one is complete when it is passed on.
+pub fn flow_accumulation(grid: &Grid) -> Grid {
+    let (ncols, nrows) = (grid.ncols, grid.nrows);
+    let valid = |x: usize, y: usize| grid.is_valid(x, y);
+
+    let mut order: Vec<(usize, usize)> = (0..nrows).flat_map(|y| (0..ncols).map(move |x| (x, y))).filter(|&(x, y)| valid(x, y)).collect();
+    order.sort_by(|&(ax, ay), &(bx, by)| grid.data[by][bx].total_cmp(&grid.data[ay][ax]));
+
+    let mut acc = vec![vec![0.0f32; ncols]; nrows];
+    for (x, y) in order {
+        let z = grid.data[y][x];
+        let mut target = None;
+        let mut steepest = 0.0;
+        for (dx, dy) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+            let (nx, ny) = (x as isize + dx, y as isize + dy);
+            if nx < 0 || ny < 0 || nx as usize >= ncols || ny as usize >= nrows || !valid(nx as usize, ny as usize) {
+                continue;
+            }
+            let distance = if dx != 0 && dy != 0 { std::f32::consts::SQRT_2 } else { 1.0 };
+            let drop = (z - grid.data[ny as usize][nx as usize]) / distance;
+            if drop > steepest {
+                steepest = drop;
+                target = Some((nx as usize, ny as usize));
+            }
+        }
+        if let Some((tx, ty)) = target {
+            acc[ty][tx] += acc[y][x] + 1.0;
+        }
+    }
+
+    let data = acc.into_iter().enumerate()
+        .map(|(y, row)| row.into_iter().enumerate().map(|(x, v)| if valid(x, y) { v } else { grid.nodata }).collect())
+        .collect();
+    Grid { data, ..grid.clone() }
+}
+
+/// Priority-flood depression filling (Barnes et al.): cells are flooded inward from the grid edge and NoData, lowest
+/// first, and every cell is raised to the spill level of the cell it was reached from, so each one drains out
+pub fn fill_sinks(grid: &Grid) -> Grid {
+    let (ncols, nrows) = (grid.ncols, grid.nrows);
+    let mut filled = grid.clone();
+    let mut done = vec![false; ncols * nrows];
+    let mut queue = BinaryHeap::new();
+    for (x, y, z) in grid.iter_valid() {
+        let outlet = (0..9).any(|i| grid.get(x as isize + i % 3 - 1, y as isize + i / 3 - 1).is_none());
+        if outlet {
+            queue.push(Reverse(Flooded(z, x, y)));
+            done[y * ncols + x] = true;
+        }
+    }
+    while let Some(Reverse(Flooded(z, x, y))) = queue.pop() {
+        for i in 0..9 {
+            let (nx, ny) = (x as isize + i % 3 - 1, y as isize + i / 3 - 1);
+            if grid.get(nx, ny).is_none() || done[ny as usize * ncols + nx as usize] {
+                continue;
+            }
+            let (nx, ny) = (nx as usize, ny as usize);
+            done[ny * ncols + nx] = true;
+            let level = filled.data[ny][nx].max(z);
+            filled.data[ny][nx] = level;
+            queue.push(Reverse(Flooded(level, nx, ny)));
+        }
+    }
+    filled
+}
+
+/// Cell on the flood front of [`fill_sinks`], ordered by its level
+#[derive(PartialEq)]
+struct Flooded(f32, usize, usize);
+
+impl Eq for Flooded {}
+
+impl PartialOrd for Flooded {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Flooded {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Specific catchment area `a` (upslope area per unit contour width, (flow + 1) · cell size) and `tan β`
+/// of the slope, floored so flat cells give large but finite indices, for every valid cell
+fn catchment_and_gradient(grid: &Grid) -> Vec<Option<(f64, f64)>> {
+    // Gentlest gradient taken into account, a rise of 1 mm per meter
+    const MIN_TAN_SLOPE: f64 = 0.001;
+    let flow = flow_accumulation(grid);
+    let slope = slope(grid, EdgeMode::default());
+    let width = grid.ground_cellsize();
+    flow.data.iter().flatten().zip(slope.data.iter().flatten())
+        .map(|(&f, &s)| (!grid.is_nodata(f)).then(|| ((f as f64 + 1.0) * width, (s as f64).to_radians().tan().max(MIN_TAN_SLOPE))))
+        .collect()
+}
+
+/// Grid of the same shape from one optional value per cell, row-major, None becoming NoData
+fn from_values(grid: &Grid, values: Vec<Option<f64>>) -> Grid {
+    let data = values.chunks(grid.ncols)
+        .map(|row| row.iter().map(|v| v.map_or(grid.nodata, |v| v as f32)).collect())
+        .collect();
+    Grid { data, ..grid.clone() }
+}
+
+/// Topographic wetness index (Beven & Kirkby) ln(a / tan β) from D8 flow and Horn slope, high where water collects
+pub fn wetness_index(grid: &Grid) -> Grid {
+    from_values(grid, catchment_and_gradient(grid).into_iter().map(|v| v.map(|(a, tan)| (a / tan).ln())).collect())
+}
+
+/// Stream power index a · tan β, the erosive power of the flowing water
+pub fn stream_power_index(grid: &Grid) -> Grid {
+    from_values(grid, catchment_and_gradient(grid).into_iter().map(|v| v.map(|(a, tan)| a * tan)).collect())
+}
+
+/// Value below which `fraction` of the valid cells lie
+pub fn percentile(values: &Grid, fraction: f64) -> f64 {
+    let mut valid: Vec<f32> = values.iter_valid().map(|(_, _, v)| v).collect();
+    if valid.is_empty() {
+        return 0.0;
+    }
+    valid.sort_by(f32::total_cmp);
+    valid[((valid.len() - 1) as f64 * fraction) as usize] as f64
+}
+
+/// Absolute value below which `fraction` of the valid cells lie
+fn abs_percentile(values: &Grid, fraction: f64) -> f64 {
+    let mut abs: Vec<f32> = values.iter_valid().map(|(_, _, v)| v.abs()).collect();
+    if abs.is_empty() {
+        return 0.0;
+    }
+    abs.sort_by(f32::total_cmp);
+    abs[((abs.len() - 1) as f64 * fraction) as usize] as f64
+}
+
+/// Maps every valid value through `norm` into the gradient, NoData is black and a negative `norm` is gray
+pub fn colorize(values: &Grid, grad: &Gradient, norm: impl Fn(f32) -> f64) -> RgbImage {
+    let mut img = RgbImage::new(values.ncols as u32, values.nrows as u32);
+    for (y, row) in values.data.iter().enumerate() {
+        for (x, &v) in row.iter().enumerate() {
+            let rgb = if !values.is_valid(x, y) {
+                Rgb([0, 0, 0])
+            } else {
+                let t = norm(v);
+                if t < 0.0 {
+                    Rgb([128, 128, 128])
+                } else {
+                    let (r, g, b, _) = grad.at(t.min(1.0)).rgba();
+                    Rgb([(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8])
+                }
+            };
+            img.put_pixel(x as u32, y as u32, rgb);
+        }
+    }
+    img
+}
@@ -0,0 +1,137 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use colorgrad::Gradient;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, DynamicImage, Frame, Rgb, RgbImage};
+
+use crate::asc::load_asc;
+use crate::font;
+use crate::grid::Grid;
+use crate::output::{save_image, OutputFormat, OutputTarget};
+use crate::render::{color_range, render_shaded};
+
+/// One loaded survey of the time series
+struct Epoch {
+    name: String,
+    grid: Grid,
+}
+
+/// Cell-wise change statistics between two epochs
+struct Change {
+    cells: usize,
+    mean: f64,
+    min: f64,
+    max: f64,
+    rms: f64,
+}
+
+/// Renders an ordered list of DEMs of the same area with a shared normalization into
+/// the products "time_series" (GIF), "difference" (last - first) and "change_summary" (CSV); the frames are colored
+/// with `grad` and shaded like `target.shade`
+pub fn save_time_series(paths: &[PathBuf], grad: &Gradient, target: &OutputTarget, frame_ms: u32, format: &OutputFormat) -> io::Result<()> {
+    if paths.len() < 2 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "A time series needs at least two DEMs"));
+    }
+
+    let mut epochs = Vec::new();
+    let (mut ncols, mut nrows) = (0, 0);
+    for (i, path) in paths.iter().enumerate() {
+        let grid = load_asc(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{:?}: {}", path, e)))?;
+        if i == 0 {
+            ncols = grid.ncols;
+            nrows = grid.nrows;
+        } else if (grid.ncols, grid.nrows) != (ncols, nrows) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:?} is {}x{} but the first epoch is {}x{}", path, grid.ncols, grid.nrows, ncols, nrows),
+            ));
+        }
+        let name = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        epochs.push(Epoch { name, grid });
+    }
+
+    // Shared normalization so the same color means the same elevation in every frame
+    let (min, max) = epochs.iter().map(|e| color_range(&e.grid, target.elevation_range.as_ref()))
+        .fold((f32::MAX, f32::MIN), |(lo, hi), (a, b)| (lo.min(a), hi.max(b)));
+    println!("Time series: {} epochs, shared elevation range {:.2} – {:.2}", epochs.len(), min, max);
+
+    let file = File::create(target.path("time_series", "gif")?)?;
+    let mut encoder = GifEncoder::new_with_speed(file, 10);
+    encoder.set_repeat(Repeat::Infinite).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+    let delay = Delay::from_numer_denom_ms(frame_ms, 1);
+    let total = epochs.len();
+    let frames = epochs.iter().enumerate().map(|(i, epoch)| {
+        let mut img = render_shaded(&epoch.grid, (min, max), grad, &target.shade);
+        if let Some(pinned) = &target.elevation_range {
+            pinned.paint_outside(&mut img, &epoch.grid);
+        }
+        font::draw_label(&mut img, &format!("{}/{} {}", i + 1, total, epoch.name));
+        Frame::from_parts(DynamicImage::ImageRgb8(img).into_rgba8(), 0, 0, delay)
+    });
+    encoder.encode_frames(frames).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+    // Change between consecutive epochs and over the whole series
+    let mut csv = File::create(target.path("change_summary", "csv")?)?;
+    writeln!(csv, "from,to,valid_cells,mean_change,min_change,max_change,rms_change")?;
+    let mut pairs: Vec<(usize, usize)> = (1..total).map(|i| (i - 1, i)).collect();
+    if total > 2 {
+        pairs.push((0, total - 1));
+    }
+    for (a, b) in pairs {
+        let c = change(&epochs[a], &epochs[b]);
+        writeln!(csv, "{},{},{},{:.4},{:.4},{:.4},{:.4}", epochs[a].name, epochs[b].name, c.cells, c.mean, c.min, c.max, c.rms)?;
+    }
+
+    let mut diff_img = render_difference(&epochs[0], &epochs[total - 1], ncols, nrows);
+    font::draw_label(&mut diff_img, &format!("{} - {}", epochs[total - 1].name, epochs[0].name));
+    save_image(DynamicImage::ImageRgb8(diff_img), target, "difference", format)
+}
+
+/// Statistics of `to - from` over cells that are valid in both epochs
+fn change(from: &Epoch, to: &Epoch) -> Change {
+    let mut c = Change { cells: 0, mean: 0.0, min: f64::MAX, max: f64::MIN, rms: 0.0 };
+    for (row_a, row_b) in from.grid.data.iter().zip(&to.grid.data) {
+        for (&a, &b) in row_a.iter().zip(row_b) {
+            if from.grid.is_nodata(a) || to.grid.is_nodata(b) { continue; }
+            let d = (b - a) as f64;
+            c.cells += 1;
+            c.mean += d;
+            c.rms += d * d;
+            c.min = c.min.min(d);
+            c.max = c.max.max(d);
+        }
+    }
+    if c.cells > 0 {
+        c.mean /= c.cells as f64;
+        c.rms = (c.rms / c.cells as f64).sqrt();
+    } else {
+        c.min = 0.0;
+        c.max = 0.0;
+    }
+    c
+}
+
+/// Diverging red (lowering) / blue (rising) image of `to - from`, black where either epoch has no data
+fn render_difference(from: &Epoch, to: &Epoch, ncols: usize, nrows: usize) -> RgbImage {
+    let grad = colorgrad::rd_bu();
+    let c = change(from, to);
+    let limit = c.min.abs().max(c.max.abs()).max(f64::EPSILON);
+    let mut img = RgbImage::new(ncols as u32, nrows as u32);
+
+    for (y, (row_a, row_b)) in from.grid.data.iter().zip(&to.grid.data).enumerate() {
+        for (x, (&a, &b)) in row_a.iter().zip(row_b).enumerate() {
+            let rgb = if !from.grid.is_valid(x, y) || !to.grid.is_valid(x, y) {
+                Rgb([0, 0, 0])
+            } else {
+                let norm = ((b - a) as f64 / limit + 1.0) / 2.0;
+                let (r, g, b, _) = grad.at(norm.clamp(0.0, 1.0)).rgba();
+                Rgb([(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8])
+            };
+            img.put_pixel(x as u32, y as u32, rgb);
+        }
+    }
+    img
+}
@@ -0,0 +1,260 @@
+//! Triangulated irregular networks: the grid approximated by a Delaunay triangulation of few of its cells,
+//! refined by greedy insertion (Garland & Heckbert) until no cell is further than a tolerance from the surface.
+//! Flat areas end up with a handful of large triangles where the regular mesh has two per cell.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::{self, Write};
+
+use crate::grid::Grid;
+
+/// Surface of triangles between grid cell centers
+#[derive(Clone, Debug, Default)]
+pub struct Tin {
+    pub vertices: Vec<[f64; 3]>,    // Map x, y and elevation
+    pub triangles: Vec<[usize; 3]>, // Vertex indices, counter-clockwise seen from above
+}
+
+/// Triangle under construction, in (column, -row) coordinates so counter-clockwise matches the map
+struct Triangle {
+    v: [usize; 3],
+    neighbors: [Option<usize>; 3], // Across the edge from v[i] to v[i + 1]
+    alive: bool,
+}
+
+/// Cell of a triangle furthest from it, ordered so the heap pops the largest error first
+struct Candidate {
+    error: f64,
+    triangle: usize,
+    cell: (usize, usize),
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.error.total_cmp(&other.error)
+    }
+}
+
+/// Incremental Delaunay triangulation of grid cells (Bowyer-Watson), with the elevations to approximate
+struct Builder<'a> {
+    grid: &'a Grid,
+    points: Vec<(f64, f64, f64)>, // Column, -row and elevation of the vertices
+    triangles: Vec<Triangle>,
+}
+
+impl Tin {
+    /// Greedy insertion starting from the four corner cells: the cell furthest from the current surface becomes a vertex
+    /// until every valid cell is within `max_error` elevation units or `max_vertices` are used. NoData cells are not
+    /// approximated, triangles centered on NoData are left out and NoData corners take the nearest valid elevation.
+    pub fn greedy(grid: &Grid, max_error: f64, max_vertices: Option<usize>) -> Result<Tin, String> {
+        if grid.ncols < 2 || grid.nrows < 2 {
+            return Err("A TIN needs a grid of at least 2x2 cells".into());
+        }
+        let mut builder = Builder { grid, points: Vec::new(), triangles: Vec::new() };
+        let (w, h) = (grid.ncols - 1, grid.nrows - 1);
+        for (c, r) in [(0, 0), (w, 0), (w, h), (0, h)] {
+            let z = nearest_valid(grid, c, r).ok_or("The grid has no valid cells")?;
+            builder.points.push((c as f64, -(r as f64), z as f64));
+        }
+        // Corners 0 to 3 clockwise from the upper left, split along the diagonal 0-2
+        builder.triangles.push(Triangle { v: [0, 3, 2], neighbors: [None, None, Some(1)], alive: true });
+        builder.triangles.push(Triangle { v: [0, 2, 1], neighbors: [Some(0), None, None], alive: true });
+
+        let mut heap: BinaryHeap<Candidate> = (0..2).filter_map(|t| builder.candidate(t)).collect();
+        let budget = max_vertices.unwrap_or(usize::MAX).max(4);
+        while let Some(Candidate { error, triangle, cell }) = heap.pop() {
+            if error <= max_error || builder.points.len() >= budget {
+                break;
+            }
+            if !builder.triangles[triangle].alive {
+                continue; // Replaced since, its area has been rescanned
+            }
+            for t in builder.insert(cell, triangle) {
+                heap.extend(builder.candidate(t));
+            }
+        }
+        Ok(builder.finish())
+    }
+
+    /// Wavefront OBJ with one vertex per line (`v x y z`) and 1-based faces
+    pub fn write_obj<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "# TIN of {} vertices and {} triangles", self.vertices.len(), self.triangles.len())?;
+        for [x, y, z] in &self.vertices {
+            writeln!(out, "v {} {} {}", x, y, z)?;
+        }
+        for [a, b, c] in &self.triangles {
+            writeln!(out, "f {} {} {}", a + 1, b + 1, c + 1)?;
+        }
+        Ok(())
+    }
+
+    /// GeoJSON FeatureCollection of one 3D polygon per triangle, in the coordinates of the grid
+    pub fn write_geojson<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "{{\"type\":\"FeatureCollection\",\"features\":[")?;
+        for (i, triangle) in self.triangles.iter().enumerate() {
+            let ring: Vec<String> = [triangle[0], triangle[1], triangle[2], triangle[0]].iter()
+                .map(|&v| format!("[{},{},{}]", self.vertices[v][0], self.vertices[v][1], self.vertices[v][2]))
+                .collect();
+            write!(out, "{}\n{{\"type\":\"Feature\",\"properties\":{{}},\"geometry\":{{\"type\":\"Polygon\",\"coordinates\":[[{}]]}}}}",
+                if i == 0 { "" } else { "," }, ring.join(","))?;
+        }
+        writeln!(out, "\n]}}")
+    }
+}
+
+impl Builder<'_> {
+    fn orient(&self, a: usize, b: usize, c: (f64, f64)) -> f64 {
+        let (pa, pb) = (self.points[a], self.points[b]);
+        (pb.0 - pa.0) * (c.1 - pa.1) - (pb.1 - pa.1) * (c.0 - pa.0)
+    }
+
+    /// Whether `p` lies strictly inside the circumcircle of the counter-clockwise triangle `t`
+    fn in_circumcircle(&self, t: usize, p: (f64, f64)) -> bool {
+        let [a, b, c] = self.triangles[t].v.map(|v| {
+            let q = self.points[v];
+            let (dx, dy) = (q.0 - p.0, q.1 - p.1);
+            (dx, dy, dx * dx + dy * dy)
+        });
+        let det = a.0 * (b.1 * c.2 - b.2 * c.1) - a.1 * (b.0 * c.2 - b.2 * c.0) + a.2 * (b.0 * c.1 - b.1 * c.0);
+        det > 1e-9
+    }
+
+    /// Valid cell inside triangle `t` furthest from its plane, None if it has none
+    fn candidate(&self, t: usize) -> Option<Candidate> {
+        let [a, b, c] = self.triangles[t].v.map(|v| self.points[v]);
+        let area = (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0);
+        if area <= 0.0 {
+            return None;
+        }
+        let (min_c, max_c) = (a.0.min(b.0).min(c.0) as usize, a.0.max(b.0).max(c.0) as usize);
+        let (min_r, max_r) = ((-a.1.max(b.1).max(c.1)) as usize, (-a.1.min(b.1).min(c.1)) as usize);
+        let mut best: Option<Candidate> = None;
+        for r in min_r..=max_r {
+            for col in min_c..=max_c {
+                let z = self.grid.data[r][col];
+                if self.grid.is_nodata(z) {
+                    continue;
+                }
+                let (x, y) = (col as f64, -(r as f64));
+                // Barycentric weights of the cell center, all at least 0 inside the triangle
+                let wa = ((b.0 - x) * (c.1 - y) - (b.1 - y) * (c.0 - x)) / area;
+                let wb = ((c.0 - x) * (a.1 - y) - (c.1 - y) * (a.0 - x)) / area;
+                let wc = 1.0 - wa - wb;
+                if wa < -1e-9 || wb < -1e-9 || wc < -1e-9 {
+                    continue;
+                }
+                let error = (z as f64 - (wa * a.2 + wb * b.2 + wc * c.2)).abs();
+                if best.as_ref().is_none_or(|best| error > best.error) {
+                    best = Some(Candidate { error, triangle: t, cell: (col, r) });
+                }
+            }
+        }
+        best
+    }
+
+    /// Inserts the cell as a vertex inside or on triangle `t`, returns the new triangles
+    fn insert(&mut self, (col, r): (usize, usize), t: usize) -> Vec<usize> {
+        let p = (col as f64, -(r as f64));
+        let index = self.points.len();
+        self.points.push((p.0, p.1, self.grid.data[r][col] as f64));
+
+        // Cavity: the triangles whose circumcircle contains the point, connected to the one containing it
+        let mut cavity = vec![t];
+        let mut stack = vec![t];
+        while let Some(current) = stack.pop() {
+            for neighbor in self.triangles[current].neighbors.into_iter().flatten() {
+                if !cavity.contains(&neighbor) && self.in_circumcircle(neighbor, p) {
+                    cavity.push(neighbor);
+                    stack.push(neighbor);
+                }
+            }
+        }
+
+        // Fan of new triangles from the point to the edges around the cavity; an edge through the point
+        // on the outline of the grid gives no triangle
+        let mut created = Vec::new();
+        let mut starting = HashMap::new(); // Edge vertex `a` of the new triangle [a, b, point]
+        let mut ending = HashMap::new();   // Edge vertex `b` of the new triangle [a, b, point]
+        for &old in &cavity {
+            for i in 0..3 {
+                let outer = self.triangles[old].neighbors[i];
+                if outer.is_some_and(|n| cavity.contains(&n)) {
+                    continue;
+                }
+                let (a, b) = (self.triangles[old].v[i], self.triangles[old].v[(i + 1) % 3]);
+                if self.orient(a, b, p) <= 1e-9 {
+                    continue;
+                }
+                let id = self.triangles.len();
+                self.triangles.push(Triangle { v: [a, b, index], neighbors: [outer, None, None], alive: true });
+                if let Some(outer) = outer {
+                    let back = self.triangles[outer].neighbors.iter().position(|&n| n == Some(old)).unwrap();
+                    self.triangles[outer].neighbors[back] = Some(id);
+                }
+                starting.insert(a, id);
+                ending.insert(b, id);
+                created.push(id);
+            }
+        }
+        for &old in &cavity {
+            self.triangles[old].alive = false;
+        }
+        for &id in &created {
+            let [a, b, _] = self.triangles[id].v;
+            self.triangles[id].neighbors[1] = starting.get(&b).copied(); // Across b -> point
+            self.triangles[id].neighbors[2] = ending.get(&a).copied();   // Across point -> a
+        }
+        created
+    }
+
+    fn finish(self) -> Tin {
+        let grid = self.grid;
+        let (x0, y0) = grid.origin();
+        let vertices = self.points.iter()
+            .map(|&(c, r, z)| [x0 + (c + 0.5) * grid.cellsize, y0 - (-r + 0.5) * grid.cellsize, z])
+            .collect();
+        let triangles = self.triangles.iter()
+            .filter(|t| t.alive)
+            .filter(|t| {
+                let (c, r) = t.v.iter().fold((0.0, 0.0), |(c, r), &v| (c + self.points[v].0 / 3.0, r - self.points[v].1 / 3.0));
+                grid.is_valid(c.round() as usize, r.round() as usize)
+            })
+            .map(|t| t.v)
+            .collect();
+        Tin { vertices, triangles }
+    }
+}
+
+/// Elevation of the valid cell closest to (col, row), searching rings of growing size
+fn nearest_valid(grid: &Grid, col: usize, row: usize) -> Option<f32> {
+    for radius in 0..grid.ncols.max(grid.nrows) {
+        let mut best: Option<(usize, f32)> = None;
+        for r in row.saturating_sub(radius)..(row + radius + 1).min(grid.nrows) {
+            for c in col.saturating_sub(radius)..(col + radius + 1).min(grid.ncols) {
+                let z = grid.data[r][c];
+                let d = c.abs_diff(col).pow(2) + r.abs_diff(row).pow(2);
+                if !grid.is_nodata(z) && best.is_none_or(|(bd, _)| d < bd) {
+                    best = Some((d, z));
+                }
+            }
+        }
+        if let Some((_, z)) = best {
+            return Some(z);
+        }
+    }
+    None
+}
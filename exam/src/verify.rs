@@ -0,0 +1,238 @@
+//! Comparison of a fresh output folder against a reference one from an earlier run or version (`exam verify`):
+//! images pixel by pixel, raw grids (Float32 .tif, .npy, .asc) value by value, written as `verify.json`.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::asc::load_asc;
+use crate::coverage::{COVERAGE_GEOJSON, COVERAGE_IMAGE};
+use crate::gallery::GALLERY_FILE;
+use crate::geotiff::load_geotiff;
+use crate::grid::Grid;
+use crate::journal::JOURNAL_FILE;
+use crate::manifest::{MANIFEST_CSV, MANIFEST_FILE};
+use crate::profiling::PROFILE_FILE;
+use crate::report::REPORT_FILE;
+
+/// File name of the comparison below the output root of the fresh run
+pub const VERIFY_FILE: &str = "verify.json";
+
+/// Differences that still count as equal
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Tolerance {
+    pub pixel: u8,  // Largest change of a color channel
+    pub value: f64, // Largest change of a raw value, in its units
+}
+
+/// How an output compares with its reference
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Outcome {
+    Same,                                                                    // Within the tolerance
+    Present,                                                                 // Not an image or grid, only checked to exist
+    Missing,                                                                 // In the reference only
+    Extra,                                                                   // In the fresh output only, not a regression
+    Pixels { differing: usize, pixels: usize, max_delta: u8 },               // Pixels with a channel beyond the tolerance
+    Values { differing: usize, cells: usize, max_delta: f64, nodata_changed: usize }, // Cells beyond the tolerance or gaining or losing NoData
+    Size { reference: (usize, usize), actual: (usize, usize) },              // Width and height differ
+    Unreadable { error: String },                                           // One of the two files can't be decoded
+}
+
+/// One output file and how it compares
+#[derive(Clone, Debug, Serialize)]
+pub struct FileComparison {
+    pub path: PathBuf, // Relative to both output roots
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+impl FileComparison {
+    /// Whether the file breaks the comparison: anything but equal, present or new
+    pub fn is_regression(&self) -> bool {
+        !matches!(self.outcome, Outcome::Same | Outcome::Present | Outcome::Extra)
+    }
+}
+
+/// Result of [`compare_outputs`]
+#[derive(Debug, Serialize)]
+pub struct Verification {
+    pub reference: PathBuf,
+    pub actual: PathBuf,
+    pub tolerance: Tolerance,
+    pub files: Vec<FileComparison>, // Sorted by path
+}
+
+impl Verification {
+    pub fn regressions(&self) -> impl Iterator<Item = &FileComparison> {
+        self.files.iter().filter(|f| f.is_regression())
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, self).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+}
+
+/// Compares every file below `reference` with the same path below `actual`. The report, journal, gallery, profile
+/// and verification of a run change every time and are left out.
+pub fn compare_outputs(reference: &Path, actual: &Path, tolerance: Tolerance) -> io::Result<Verification> {
+    let (expected, produced) = (output_files(reference)?, output_files(actual)?);
+    let mut files: Vec<FileComparison> = expected.iter().map(|path| {
+        let outcome = if produced.contains(path) {
+            compare_file(&reference.join(path), &actual.join(path), tolerance)
+        } else {
+            Outcome::Missing
+        };
+        FileComparison { path: path.clone(), outcome }
+    }).collect();
+    files.extend(produced.into_iter().filter(|path| !expected.contains(path)).map(|path| FileComparison { path, outcome: Outcome::Extra }));
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(Verification { reference: reference.to_path_buf(), actual: actual.to_path_buf(), tolerance, files })
+}
+
+/// Paths of the files below `root` relative to it, without the bookkeeping files of a run
+fn output_files(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir).map_err(|e| io::Error::new(e.kind(), format!("{:?}: {}", dir, e)))? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if let Ok(relative) = path.strip_prefix(root) {
+                if ![REPORT_FILE, JOURNAL_FILE, GALLERY_FILE, PROFILE_FILE, VERIFY_FILE, MANIFEST_FILE, MANIFEST_CSV, COVERAGE_IMAGE, COVERAGE_GEOJSON].iter().any(|name| relative == Path::new(name)) {
+                    files.push(relative.to_path_buf());
+                }
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// What a file holds, by its extension and for TIFFs by the sample type
+enum Kind {
+    Image,
+    Grid,
+    Other,
+}
+
+fn kind(path: &Path) -> Kind {
+    match path.extension().and_then(|e| e.to_str()).map(str::to_lowercase).as_deref() {
+        Some("png" | "jpg" | "jpeg" | "webp") => Kind::Image,
+        Some("asc" | "npy") => Kind::Grid,
+        Some("tif" | "tiff") if is_float_tiff(path) => Kind::Grid,
+        Some("tif" | "tiff") => Kind::Image,
+        _ => Kind::Other,
+    }
+}
+
+/// Whether the TIFF holds 32 or 64-bit single-band samples, raw values rather than an image
+fn is_float_tiff(path: &Path) -> bool {
+    let Ok(file) = File::open(path) else { return false };
+    tiff::decoder::Decoder::new(io::BufReader::new(file)).and_then(|mut d| d.colortype())
+        .is_ok_and(|c| matches!(c, tiff::ColorType::Gray(32 | 64)))
+}
+
+fn compare_file(reference: &Path, actual: &Path, tolerance: Tolerance) -> Outcome {
+    let unreadable = |e: io::Error| Outcome::Unreadable { error: e.to_string() };
+    match kind(reference) {
+        Kind::Image => match (open_image(reference), open_image(actual)) {
+            (Ok(a), Ok(b)) => compare_images(&a, &b, tolerance.pixel),
+            (Err(e), _) | (_, Err(e)) => unreadable(e),
+        },
+        Kind::Grid => match (load_values(reference), load_values(actual)) {
+            (Ok(a), Ok(b)) => compare_grids(&a, &b, tolerance.value),
+            (Err(e), _) | (_, Err(e)) => unreadable(e),
+        },
+        Kind::Other => Outcome::Present,
+    }
+}
+
+fn open_image(path: &Path) -> io::Result<image::RgbaImage> {
+    image::open(path).map(|img| img.to_rgba8()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}: {}", path, e)))
+}
+
+fn compare_images(reference: &image::RgbaImage, actual: &image::RgbaImage, tolerance: u8) -> Outcome {
+    if reference.dimensions() != actual.dimensions() {
+        let size = |img: &image::RgbaImage| (img.width() as usize, img.height() as usize);
+        return Outcome::Size { reference: size(reference), actual: size(actual) };
+    }
+    let (mut differing, mut max_delta) = (0, 0);
+    for (a, b) in reference.pixels().zip(actual.pixels()) {
+        let delta = a.0.iter().zip(b.0).map(|(&a, b)| a.abs_diff(b)).max().unwrap_or(0);
+        max_delta = max_delta.max(delta);
+        differing += (delta > tolerance) as usize;
+    }
+    if differing == 0 {
+        return Outcome::Same;
+    }
+    Outcome::Pixels { differing, pixels: reference.pixels().len(), max_delta }
+}
+
+/// Raw values of a grid file, NoData and NaN alike invalid
+fn load_values(path: &Path) -> io::Result<Grid> {
+    let grid = match path.extension().and_then(|e| e.to_str()).map(str::to_lowercase).as_deref() {
+        Some("asc") => load_asc(path),
+        Some("npy") => load_npy(path),
+        _ => load_geotiff(path),
+    };
+    grid.map_err(|e| io::Error::new(e.kind(), format!("{:?}: {}", path, e)))
+}
+
+fn compare_grids(reference: &Grid, actual: &Grid, tolerance: f64) -> Outcome {
+    if (reference.ncols, reference.nrows) != (actual.ncols, actual.nrows) {
+        return Outcome::Size { reference: (reference.ncols, reference.nrows), actual: (actual.ncols, actual.nrows) };
+    }
+    let (mut differing, mut nodata_changed, mut max_delta) = (0, 0, 0.0f64);
+    for ((x, y, a), (_, _, b)) in reference.iter_cells().zip(actual.iter_cells()) {
+        match (reference.is_valid(x, y), actual.is_valid(x, y)) {
+            (true, true) => {
+                let delta = (a as f64 - b as f64).abs();
+                max_delta = max_delta.max(delta);
+                differing += (delta > tolerance) as usize;
+            }
+            (false, false) => {}
+            _ => nodata_changed += 1,
+        }
+    }
+    if differing == 0 && nodata_changed == 0 {
+        return Outcome::Same;
+    }
+    Outcome::Values { differing, cells: reference.ncols * reference.nrows, max_delta, nodata_changed }
+}
+
+/// Reads a 2D little-endian float32 array as written by [`crate::output::save_npy`], NaN as NoData
+fn load_npy(path: &Path) -> io::Result<Grid> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+    if !bytes.starts_with(b"\x93NUMPY") || bytes.len() < 10 {
+        return Err(invalid("Not a NumPy array"));
+    }
+    let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+    let header = bytes.get(10..10 + header_len).map(String::from_utf8_lossy).ok_or_else(|| invalid("Truncated header"))?;
+    if !header.contains("'<f4'") || header.contains("'fortran_order': True") {
+        return Err(invalid("Only C-ordered little-endian float32 arrays are compared"));
+    }
+    let shape: Vec<usize> = header.split_once("'shape': (").and_then(|(_, rest)| rest.split_once(')'))
+        .map(|(dims, _)| dims.split(',').filter_map(|d| d.trim().parse().ok()).collect())
+        .unwrap_or_default();
+    let [nrows, ncols] = shape[..] else {
+        return Err(invalid("Not a 2D array"));
+    };
+    let values = &bytes[10 + header_len..];
+    if values.len() < nrows * ncols * 4 {
+        return Err(invalid("Fewer values than the shape holds"));
+    }
+    let nodata = -99999.0;
+    let data = values.chunks_exact(4).take(nrows * ncols)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .map(|v| if v.is_nan() { nodata } else { v })
+        .collect::<Vec<f32>>()
+        .chunks(ncols.max(1)).map(<[f32]>::to_vec).collect();
+    Ok(Grid { data, ncols, nrows, nodata, cellsize: 1.0, xllcorner: 0.0, yllcorner: 0.0, geographic: None })
+}
@@ -0,0 +1,89 @@
+//! Vertical unit conversion and datum offset, applied to the elevations as they are loaded (`--z-units`, `--z-offset`),
+//! so archives mixing feet and meters render with the same hillshade scaling.
+
+use crate::grid::Grid;
+
+/// Unit of the elevations
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "fs", derive(serde::Serialize))]
+pub enum LengthUnit {
+    #[default]
+    #[cfg_attr(feature = "fs", serde(rename = "m"))]
+    Meters,
+    #[cfg_attr(feature = "fs", serde(rename = "ft"))]
+    Feet,         // International foot, 0.3048 m
+    #[cfg_attr(feature = "fs", serde(rename = "us-ft"))]
+    UsSurveyFeet, // 1200/3937 m
+}
+
+impl LengthUnit {
+    /// Parses "m", "ft" or "us-ft", or their names spelled out
+    pub fn parse(name: &str) -> Option<LengthUnit> {
+        match name.trim().to_lowercase().as_str() {
+            "m" | "meter" | "meters" | "metre" | "metres" => Some(LengthUnit::Meters),
+            "ft" | "foot" | "feet" => Some(LengthUnit::Feet),
+            "us-ft" | "us-foot" | "us-feet" | "ftus" => Some(LengthUnit::UsSurveyFeet),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LengthUnit::Meters => "m",
+            LengthUnit::Feet => "ft",
+            LengthUnit::UsSurveyFeet => "us-ft",
+        }
+    }
+
+    /// Length of the unit in meters
+    pub fn meters(&self) -> f64 {
+        match self {
+            LengthUnit::Meters => 1.0,
+            LengthUnit::Feet => 0.3048,
+            LengthUnit::UsSurveyFeet => 1200.0 / 3937.0,
+        }
+    }
+}
+
+/// What happens to every valid elevation on loading: converted from one unit to the other, then shifted
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "fs", derive(serde::Serialize))]
+pub struct Vertical {
+    pub from: LengthUnit, // Unit of the files
+    pub to: LengthUnit,   // Unit of the rendered elevations
+    pub offset: f64,      // Added after the conversion, in the unit `to`, e.g. the separation of two vertical datums
+}
+
+impl Vertical {
+    /// Parses the units of --z-units, "<from>:<to>" like "ft:m"
+    pub fn parse_units(text: &str) -> Option<(LengthUnit, LengthUnit)> {
+        let (from, to) = text.split_once(':')?;
+        Some((LengthUnit::parse(from)?, LengthUnit::parse(to)?))
+    }
+
+    /// Whether the elevations stay as they are
+    pub fn is_identity(&self) -> bool {
+        self.from == self.to && self.offset == 0.0
+    }
+
+    /// Factor from the unit of the files to the unit of the rendering
+    pub fn scale(&self) -> f64 {
+        self.from.meters() / self.to.meters()
+    }
+
+    /// Converts and shifts the valid cells of `grid`, NoData stays NoData
+    pub fn apply(&self, grid: &mut Grid) {
+        if self.is_identity() {
+            return;
+        }
+        let (scale, offset) = (self.scale(), self.offset);
+        for val in grid.valid_mut() {
+            *val = (*val as f64 * scale + offset) as f32;
+        }
+    }
+
+    /// The conversion as recorded in PNG text chunks, e.g. "from=ft to=m offset=-0.3"
+    pub fn describe(&self) -> String {
+        format!("from={} to={} offset={}", self.from.name(), self.to.name(), self.offset)
+    }
+}
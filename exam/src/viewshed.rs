@@ -0,0 +1,250 @@
+//! Viewsheds of one or many observers, each at its own height above the ground, and the coverage count of all of them,
+//! e.g. to compare candidate antenna or lookout sites.
+
+#[cfg(feature = "fs")]
+use std::fs;
+#[cfg(feature = "fs")]
+use std::io::{self, BufWriter, Write};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+use image::{Rgb, RgbImage};
+
+use crate::contour::{self, ring_area};
+use crate::grid::Grid;
+#[cfg(feature = "fs")]
+use crate::output::{encode_image, save_float_tiff, Conflict, OutputFormat};
+#[cfg(feature = "fs")]
+use crate::shapefile::{Field, FieldType, ShapeType, Shapefile, Value};
+
+/// Eye height above the ground of observers that don't give their own (--height), a standing person
+pub const DEFAULT_OBSERVER_HEIGHT: f64 = 1.7;
+
+/// A point the terrain is seen from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Observer {
+    pub name: String,
+    pub x: f64,      // Map coordinates
+    pub y: f64,
+    pub height: f64, // Eye above the ground in elevation units
+}
+
+/// Options shared by all observers
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ViewshedSettings {
+    pub target_height: f64,  // Height above the ground a cell must be seen at, e.g. a receiver mast (--target-height)
+    pub radius: Option<f64>, // Farthest visible distance in meters (--radius)
+}
+
+/// Visibility from one observer: 1 where a point `target_height` above the cell is in sight, 0 where it is hidden, NoData
+/// on NoData and beyond the radius. Rays run from the observer to every cell of the border of the area (R2 algorithm),
+/// a cell is visible when the line to it rises at least as steeply as the line to any cell before it on the ray.
+pub fn viewshed(grid: &Grid, observer: &Observer, settings: &ViewshedSettings) -> Result<Grid, String> {
+    let (x0, y0) = grid.origin();
+    let (col, row) = ((observer.x - x0) / grid.cellsize, (y0 - observer.y) / grid.cellsize);
+    if !(0.0..grid.ncols as f64).contains(&col) || !(0.0..grid.nrows as f64).contains(&row) || !grid.is_valid(col as usize, row as usize) {
+        return Err(format!("Observer {} at {},{} is outside the grid or on NoData", observer.name, observer.x, observer.y));
+    }
+    let (oc, or) = (col as usize, row as usize);
+    let (dx, dy) = grid.ground_spacing();
+    let eye = grid.data[or][oc] as f64 + observer.height;
+    let distance = |c: usize, r: usize| ((c as f64 - oc as f64) * dx).hypot((r as f64 - or as f64) * dy);
+
+    // Border of the area the radius can reach, clipped to the grid
+    let reach = |spacing: f64| settings.radius.map_or(usize::MAX, |radius| (radius / spacing).ceil() as usize);
+    let (left, right) = (oc.saturating_sub(reach(dx)), (oc.saturating_add(reach(dx))).min(grid.ncols - 1));
+    let (top, bottom) = (or.saturating_sub(reach(dy)), (or.saturating_add(reach(dy))).min(grid.nrows - 1));
+    let border = (left..=right).flat_map(|c| [(c, top), (c, bottom)])
+        .chain((top..=bottom).flat_map(|r| [(left, r), (right, r)]));
+
+    let mut visible = vec![vec![false; grid.ncols]; grid.nrows];
+    visible[or][oc] = true;
+    for (ec, er) in border {
+        let (sc, sr) = (ec as f64 - oc as f64, er as f64 - or as f64);
+        let steps = sc.abs().max(sr.abs()) as usize;
+        let mut steepest = f64::NEG_INFINITY;
+        for step in 1..=steps {
+            let t = step as f64 / steps as f64;
+            let (c, r) = ((oc as f64 + sc * t).round() as usize, (or as f64 + sr * t).round() as usize);
+            let d = distance(c, r);
+            if settings.radius.is_some_and(|radius| d > radius) {
+                break;
+            }
+            let z = grid.data[r][c];
+            if grid.is_nodata(z) {
+                continue; // NoData neither blocks nor is seen
+            }
+            if (z as f64 + settings.target_height - eye) / d >= steepest {
+                visible[r][c] = true;
+            }
+            steepest = steepest.max((z as f64 - eye) / d);
+        }
+    }
+
+    let data = visible.iter().enumerate()
+        .map(|(r, row)| row.iter().enumerate().map(|(c, &seen)| {
+            if !grid.is_valid(c, r) || settings.radius.is_some_and(|radius| distance(c, r) > radius) {
+                grid.nodata
+            } else {
+                seen as u8 as f32
+            }
+        }).collect())
+        .collect();
+    Ok(Grid { data, ..grid.clone() })
+}
+
+/// Number of observers seeing each cell, NoData where no observer could (NoData or beyond every radius)
+pub fn coverage(grid: &Grid, viewsheds: &[Grid]) -> Grid {
+    let data = (0..grid.nrows)
+        .map(|r| (0..grid.ncols).map(|c| {
+            let values: Vec<f32> = viewsheds.iter().map(|v| v.data[r][c]).filter(|&v| !grid.is_nodata(v)).collect();
+            if values.is_empty() { grid.nodata } else { values.iter().sum() }
+        }).collect())
+        .collect();
+    Grid { data, ..grid.clone() }
+}
+
+/// Visible share of one observer
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "fs", derive(serde::Serialize))]
+pub struct ObserverStats {
+    pub name: String,
+    pub x: f64,
+    pub y: f64,
+    pub height: f64,
+    pub visible_cells: usize,
+    pub visible_area: f64,    // Square meters
+    pub unique_cells: usize,  // Seen by this observer alone
+}
+
+/// Coverage statistics of a set of observers
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "fs", derive(serde::Serialize))]
+pub struct CoverageStats {
+    pub cells: usize,            // Valid cells within reach of at least one observer
+    pub covered_cells: usize,    // Seen by at least one observer
+    pub covered_fraction: f64,
+    pub by_count: Vec<usize>,    // Cells seen by exactly 0, 1, 2, ... observers
+    pub observers: Vec<ObserverStats>,
+}
+
+impl CoverageStats {
+    pub fn of(grid: &Grid, observers: &[Observer], viewsheds: &[Grid], coverage: &Grid) -> CoverageStats {
+        let cell_area = grid.ground_cellsize().powi(2);
+        let mut by_count = vec![0; observers.len() + 1];
+        for &count in coverage.data.iter().flatten().filter(|&&v| !coverage.is_nodata(v)) {
+            by_count[count as usize] += 1;
+        }
+        let cells: usize = by_count.iter().sum();
+        let covered_cells = cells - by_count[0];
+        let observers = observers.iter().zip(viewsheds)
+            .map(|(observer, view)| {
+                let seen = |r: usize, c: usize| view.data[r][c] == 1.0;
+                let visible_cells = view.data.iter().flatten().filter(|&&v| v == 1.0).count();
+                let unique_cells = (0..grid.nrows).flat_map(|r| (0..grid.ncols).map(move |c| (r, c)))
+                    .filter(|&(r, c)| seen(r, c) && coverage.data[r][c] == 1.0)
+                    .count();
+                ObserverStats { name: observer.name.clone(), x: observer.x, y: observer.y, height: observer.height,
+                    visible_cells, visible_area: visible_cells as f64 * cell_area, unique_cells }
+            })
+            .collect();
+        CoverageStats { cells, covered_cells, covered_fraction: covered_cells as f64 / cells.max(1) as f64, by_count, observers }
+    }
+}
+
+/// Dimmed grayscale elevation with the covered cells tinted from yellow-green (one observer) to deep blue (all of them)
+/// and the observers marked in red
+pub fn coverage_image(grid: &Grid, coverage: &Grid, observers: &[Observer]) -> RgbImage {
+    let gray = crate::render::render_grayscale(grid);
+    let most = coverage.min_max().1.max(1.0);
+    let grad = colorgrad::yl_gn_bu();
+    let mut img = RgbImage::from_fn(grid.ncols as u32, grid.nrows as u32, |x, y| {
+        let shade = 40 + gray.get_pixel(x, y).0[0] as u32 * 120 / 255;
+        let count = coverage.data[y as usize][x as usize];
+        if coverage.is_nodata(count) || count < 1.0 {
+            return Rgb([shade as u8; 3]);
+        }
+        let (r, g, b, _) = grad.at(0.3 + 0.7 * (count / most) as f64).rgba();
+        Rgb([r, g, b].map(|c| ((c * 255.0) as u32 * 3 / 4 + shade / 4) as u8))
+    });
+    let (x0, y0) = grid.origin();
+    for observer in observers {
+        let (c, r) = (((observer.x - x0) / grid.cellsize) as i64, ((y0 - observer.y) / grid.cellsize) as i64);
+        for (px, py) in (-2..=2).flat_map(|d| [(c + d, r), (c, r + d)]) {
+            if px >= 0 && py >= 0 && (px as u32) < img.width() && (py as u32) < img.height() {
+                img.put_pixel(px as u32, py as u32, Rgb([220, 20, 20]));
+            }
+        }
+    }
+    img
+}
+
+/// Visible areas of a viewshed as polygons in map coordinates, each its outer ring (counterclockwise) and its holes
+/// (clockwise), halfway between visible and hidden cell centers
+pub fn outline(grid: &Grid, view: &Grid) -> Vec<Vec<Vec<[f64; 2]>>> {
+    contour::polygons(view, 0.5, 0.0).into_iter()
+        .map(|polygon| polygon.into_iter().enumerate().map(|(i, ring)| {
+            let mut ring: Vec<[f64; 2]> = ring.into_iter().map(|p| contour::map_point(grid, p)).collect();
+            if (ring_area(&ring) > 0.0) != (i == 0) {
+                ring.reverse();
+            }
+            ring
+        }).collect())
+        .collect()
+}
+
+/// Writes the outlines of the viewsheds as `outlines.geojson` (a MultiPolygon per observer) and `outlines.shp`
+#[cfg(feature = "fs")]
+fn save_outlines(grid: &Grid, observers: &[Observer], viewsheds: &[Grid], dir: &Path, conflict: Conflict) -> io::Result<()> {
+    let outlines: Vec<_> = viewsheds.iter().map(|view| outline(grid, view)).collect();
+    let features: Vec<_> = observers.iter().zip(&outlines).map(|(observer, polygons)| serde_json::json!({
+        "type": "Feature",
+        "geometry": { "type": "MultiPolygon", "coordinates": polygons },
+        "properties": { "name": observer.name, "height": observer.height },
+    })).collect();
+    let file = BufWriter::new(conflict.create(&dir.join("outlines.geojson"))?.0);
+    serde_json::to_writer(file, &serde_json::json!({ "type": "FeatureCollection", "features": features }))
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+    let fields = vec![
+        Field { name: "NAME", kind: FieldType::Text { width: 64 } },
+        Field { name: "HEIGHT", kind: FieldType::Number { width: 12, decimals: 2 } },
+    ];
+    let mut shapefile = Shapefile::new(ShapeType::Polygon, fields);
+    for (observer, polygons) in observers.iter().zip(&outlines) {
+        // Shapefiles turn the other way: outer rings clockwise, holes counterclockwise
+        let rings = polygons.iter().flatten().map(|ring| ring.iter().rev().map(|&[x, y]| [x, y, 0.0]).collect()).collect();
+        shapefile.add(rings, vec![Value::Text(observer.name.clone()), Value::Number(observer.height)]);
+    }
+    shapefile.save(&conflict.claim(&dir.join("outlines.shp"))?)
+}
+
+/// Computes every viewshed and writes them into `dir` as `viewshed_<name>.tif` (Float32, 1 visible, 0 hidden) and
+/// their outlines as `outlines.geojson` and `outlines.shp`, the count of observers seeing each cell as `coverage.tif`
+/// and `coverage.png`, and the statistics as `coverage.json`. Existing files are handled as `conflict` says.
+#[cfg(feature = "fs")]
+pub fn save_viewsheds(grid: &Grid, observers: &[Observer], settings: &ViewshedSettings, dir: &Path, conflict: Conflict) -> io::Result<CoverageStats> {
+    fs::create_dir_all(dir)?;
+    let viewsheds = observers.iter()
+        .map(|observer| viewshed(grid, observer, settings).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)))
+        .collect::<io::Result<Vec<_>>>()?;
+    for (observer, view) in observers.iter().zip(&viewsheds) {
+        save_float_tiff(view, &conflict.claim(&dir.join(format!("viewshed_{}.tif", file_name(&observer.name))))?)?;
+    }
+    save_outlines(grid, observers, &viewsheds, dir, conflict)?;
+    let coverage = coverage(grid, &viewsheds);
+    save_float_tiff(&coverage, &conflict.claim(&dir.join("coverage.tif"))?)?;
+    let mut png = BufWriter::new(conflict.create(&dir.join("coverage.png"))?.0);
+    encode_image(image::DynamicImage::ImageRgb8(coverage_image(grid, &coverage, observers)), &OutputFormat::default(), &mut png)?;
+    png.flush()?;
+    let stats = CoverageStats::of(grid, observers, &viewsheds, &coverage);
+    let file = BufWriter::new(conflict.create(&dir.join("coverage.json"))?.0);
+    serde_json::to_writer_pretty(file, &stats).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    Ok(stats)
+}
+
+/// Observer name usable in a file name, anything but letters, digits, `-` and `_` becomes `_`
+#[cfg(feature = "fs")]
+fn file_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect()
+}
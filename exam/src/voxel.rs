@@ -0,0 +1,214 @@
+//! Block terrain for sandbox games and teaching demos: every column of cells becomes a stack of blocks, bedrock at
+//! the bottom, stone, a few layers of dirt and grass on top, sand on the shore and water up to a sea level.
+//! Written as a Sponge schematic (.schem, loaded by WorldEdit) or as plain JSON columns.
+
+#[cfg(feature = "fs")]
+use std::io::{self, Write};
+
+use crate::grid::Grid;
+
+/// Blocks of dirt (or sand) between the surface and the stone
+const SOIL_DEPTH: usize = 3;
+
+/// Build height of a Minecraft world; higher terrain still exports but won't fit
+pub const WORLD_HEIGHT: usize = 384;
+
+/// Kinds of blocks, in the order of the schematic palette
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Block {
+    Air,
+    Bedrock,
+    Stone,
+    Dirt,
+    Grass,
+    Sand,
+    Water,
+}
+
+impl Block {
+    pub const ALL: [Block; 7] = [Block::Air, Block::Bedrock, Block::Stone, Block::Dirt, Block::Grass, Block::Sand, Block::Water];
+
+    /// Minecraft block id
+    pub fn id(&self) -> &'static str {
+        match self {
+            Block::Air => "minecraft:air",
+            Block::Bedrock => "minecraft:bedrock",
+            Block::Stone => "minecraft:stone",
+            Block::Dirt => "minecraft:dirt",
+            Block::Grass => "minecraft:grass_block",
+            Block::Sand => "minecraft:sand",
+            Block::Water => "minecraft:water",
+        }
+    }
+}
+
+/// How the grid is cut into blocks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelSettings {
+    pub block_size: f64,        // Elevation units per block (--block-size)
+    pub step: usize,            // Cells along each side of a block column, averaged (--step)
+    pub sea_level: Option<f64>, // Water fills the columns below this elevation (--sea-level)
+}
+
+impl Default for VoxelSettings {
+    fn default() -> Self {
+        VoxelSettings { block_size: 1.0, step: 1, sea_level: None }
+    }
+}
+
+/// Stack of blocks over one column
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Column {
+    pub ground: usize, // Solid blocks from the bottom, 0 over NoData
+    pub water: usize,  // Top of the water above the ground, at most `ground` when dry
+}
+
+/// The grid as block columns, x to the east and z to the south like Minecraft
+#[derive(Clone, Debug)]
+pub struct Voxels {
+    pub width: usize,  // Columns along x
+    pub length: usize, // Columns along z
+    pub height: usize, // Blocks of the highest column
+    pub columns: Vec<Column>, // Row by row, north first
+    pub base: f64,     // Elevation of the bottom of the lowest block
+    pub sea: Option<usize>, // Blocks up to the sea level
+    pub block_size: f64,
+    pub column_size: f64, // Ground width of a column in meters
+}
+
+impl Voxels {
+    /// Averages `step` x `step` cells into each column and stacks blocks from the lowest elevation (or the sea level
+    /// when lower) up to its mean, rounded to whole blocks and at least the bedrock
+    pub fn new(grid: &Grid, settings: &VoxelSettings) -> Result<Voxels, String> {
+        if settings.block_size.is_nan() || settings.block_size <= 0.0 || settings.step == 0 {
+            return Err("Blocks need a size above 0 and columns at least one cell".into());
+        }
+        let (min, max) = grid.min_max();
+        if min > max {
+            return Err("The grid has no valid cells".into());
+        }
+        let base = settings.sea_level.map_or(min as f64, |sea| sea.min(min as f64));
+        let blocks = |z: f64| ((z - base) / settings.block_size).round() as usize + 1;
+        let sea = settings.sea_level.map(blocks);
+        let (width, length) = (grid.ncols.div_ceil(settings.step), grid.nrows.div_ceil(settings.step));
+        let mut columns = Vec::with_capacity(width * length);
+        for z in 0..length {
+            for x in 0..width {
+                let cells: Vec<f64> = (z * settings.step..((z + 1) * settings.step).min(grid.nrows))
+                    .flat_map(|r| (x * settings.step..((x + 1) * settings.step).min(grid.ncols)).map(move |c| grid.data[r][c]))
+                    .filter(|&v| !grid.is_nodata(v))
+                    .map(|v| v as f64)
+                    .collect();
+                if cells.is_empty() {
+                    columns.push(Column::default());
+                    continue;
+                }
+                let ground = blocks(cells.iter().sum::<f64>() / cells.len() as f64);
+                let water = sea.map_or(ground, |sea| ground.max(sea));
+                columns.push(Column { ground, water });
+            }
+        }
+        let height = columns.iter().map(|c| c.ground.max(c.water)).max().unwrap_or(0);
+        Ok(Voxels { width, length, height, columns, base, sea, block_size: settings.block_size, column_size: grid.ground_cellsize() * settings.step as f64 })
+    }
+
+    /// Block at column (x, z) and `y` blocks up
+    pub fn block(&self, x: usize, y: usize, z: usize) -> Block {
+        let column = self.columns[z * self.width + x];
+        if y >= column.ground {
+            return if y < column.water { Block::Water } else { Block::Air };
+        }
+        // Sea floors and land up to a block above the water are sand, only dry land has grass
+        let shore = self.sea.is_some_and(|sea| column.ground <= sea + 1);
+        match column.ground - 1 - y {
+            _ if y == 0 => Block::Bedrock,
+            0 if shore => Block::Sand,
+            0 => Block::Grass,
+            d if d <= SOIL_DEPTH && shore => Block::Sand,
+            d if d <= SOIL_DEPTH => Block::Dirt,
+            _ => Block::Stone,
+        }
+    }
+
+    /// Sponge schematic version 2: gzipped NBT with the palette and one varint palette index per block,
+    /// x fastest, then z, then y
+    #[cfg(feature = "fs")]
+    pub fn write_schematic<W: Write>(&self, out: W) -> io::Result<()> {
+        if self.width > u16::MAX as usize || self.length > u16::MAX as usize || self.height > u16::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "A schematic holds at most 65535 blocks along each side"));
+        }
+        let mut blocks = Vec::with_capacity(self.width * self.length * self.height);
+        for y in 0..self.height {
+            for z in 0..self.length {
+                for x in 0..self.width {
+                    // Palette indices stay below 128, a single varint byte each
+                    blocks.push(Block::ALL.iter().position(|&b| b == self.block(x, y, z)).unwrap() as u8);
+                }
+            }
+        }
+
+        let mut nbt = Nbt(Vec::new());
+        nbt.tag(10, "Schematic");
+        nbt.tag(3, "Version");
+        nbt.int(2);
+        nbt.tag(3, "DataVersion");
+        nbt.int(3465); // Minecraft 1.20.1
+        for (name, size) in [("Width", self.width), ("Height", self.height), ("Length", self.length)] {
+            nbt.tag(2, name);
+            nbt.0.extend((size as u16).to_be_bytes());
+        }
+        nbt.tag(3, "PaletteMax");
+        nbt.int(Block::ALL.len() as i32);
+        nbt.tag(10, "Palette");
+        for (i, block) in Block::ALL.iter().enumerate() {
+            nbt.tag(3, block.id());
+            nbt.int(i as i32);
+        }
+        nbt.0.push(0); // End of the palette
+        nbt.tag(7, "BlockData");
+        nbt.int(blocks.len() as i32);
+        nbt.0.extend(blocks);
+        nbt.0.push(0); // End of the schematic
+
+        let mut encoder = flate2::write::GzEncoder::new(out, flate2::Compression::default());
+        encoder.write_all(&nbt.0)?;
+        encoder.finish()?.flush()
+    }
+
+    /// JSON with the size, the palette and every column as `[ground, water]` block counts, north row first
+    #[cfg(feature = "fs")]
+    pub fn write_json<W: Write>(&self, out: W) -> io::Result<()> {
+        let json = serde_json::json!({
+            "width": self.width,
+            "length": self.length,
+            "height": self.height,
+            "base_elevation": self.base,
+            "block_size": self.block_size,
+            "sea_level_blocks": self.sea,
+            "column_size_m": self.column_size,
+            "palette": Block::ALL.iter().map(Block::id).collect::<Vec<_>>(),
+            "columns": self.columns.chunks(self.width)
+                .map(|row| row.iter().map(|c| [c.ground, c.water]).collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+        });
+        serde_json::to_writer(out, &json).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+}
+
+/// Big-endian named binary tags, just the kinds a schematic needs
+#[cfg(feature = "fs")]
+struct Nbt(Vec<u8>);
+
+#[cfg(feature = "fs")]
+impl Nbt {
+    /// Type and name of the next tag
+    fn tag(&mut self, kind: u8, name: &str) {
+        self.0.push(kind);
+        self.0.extend((name.len() as u16).to_be_bytes());
+        self.0.extend(name.as_bytes());
+    }
+
+    fn int(&mut self, value: i32) {
+        self.0.extend(value.to_be_bytes());
+    }
+}
@@ -0,0 +1,55 @@
+//! Listing of the input folder of a batch run, optionally with its subfolders (--recursive, --max-depth,
+//! --follow-symlinks, --hidden), always in sorted order so runs and their logs are the same on every machine.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use walkdir::{DirEntry, WalkDir};
+
+/// Folders of operating systems and file managers that never hold inputs, skipped unless --hidden is given
+pub const SYSTEM_DIRS: [&str; 6] = ["System Volume Information", "$RECYCLE.BIN", "lost+found", "__MACOSX", "@eaDir", "#recycle"];
+
+/// How far and into what the input folder is walked
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WalkOptions {
+    pub max_depth: usize,      // Levels of subfolders below the input folder, 0 for its files only
+    pub follow_symlinks: bool, // Descend into linked folders too; linked files are always read
+    pub hidden: bool,          // Also list names starting with '.' and the SYSTEM_DIRS
+}
+
+/// Whether a file or folder is hidden: a dot file or, for folders, one of the [`SYSTEM_DIRS`]
+fn is_hidden(entry: &DirEntry) -> bool {
+    let name = entry.file_name().to_string_lossy();
+    name.starts_with('.') || (entry.file_type().is_dir() && SYSTEM_DIRS.contains(&name.as_ref()))
+}
+
+/// The files below `root`, sorted by name within each folder and the files of a folder before its subfolders.
+/// A folder reached twice through links is walked once.
+pub fn walk_files(root: &Path, options: &WalkOptions) -> io::Result<Vec<PathBuf>> {
+    let mut visited = HashSet::new();
+    let walker = WalkDir::new(root)
+        .max_depth(options.max_depth.saturating_add(1))
+        .follow_links(options.follow_symlinks)
+        .sort_by(|a, b| (a.path().is_dir(), a.file_name()).cmp(&(b.path().is_dir(), b.file_name())))
+        .into_iter()
+        .filter_entry(|entry| {
+            if entry.depth() > 0 && !options.hidden && is_hidden(entry) {
+                return false;
+            }
+            // Links can lead twice into the same folder; those back up the tree come as loop errors
+            !entry.file_type().is_dir() || fs::canonicalize(entry.path()).map_or(true, |path| visited.insert(path))
+        });
+    let mut files = Vec::new();
+    for entry in walker {
+        let entry = match entry {
+            Err(e) if e.loop_ancestor().is_some() => continue,
+            entry => entry?,
+        };
+        if entry.depth() > 0 && entry.path().is_file() {
+            files.push(entry.into_path());
+        }
+    }
+    Ok(files)
+}
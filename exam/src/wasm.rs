@@ -0,0 +1,86 @@
+//! Browser bindings, built with `wasm-pack build --target web -- --no-default-features --features wasm`.
+//! See `www/index.html` for a drag-and-drop demo page.
+
+use image::{DynamicImage, RgbImage};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::Clamped;
+use web_sys::ImageData;
+
+use crate::anaglyph;
+use crate::asc::parse_asc_bytes;
+use crate::grid::Grid;
+use crate::render::{self, ShadeParams};
+
+/// Parsed elevation grid handed to JavaScript
+#[wasm_bindgen(js_name = Grid)]
+pub struct WasmGrid {
+    grid: Grid,
+}
+
+#[wasm_bindgen(js_class = Grid)]
+impl WasmGrid {
+    #[wasm_bindgen(getter)]
+    pub fn ncols(&self) -> usize {
+        self.grid.ncols
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn nrows(&self) -> usize {
+        self.grid.nrows
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn cellsize(&self) -> f64 {
+        self.grid.cellsize
+    }
+
+    /// Elevation at (col, row), undefined for NoData or outside the grid
+    pub fn elevation(&self, col: usize, row: usize) -> Option<f32> {
+        let val = *self.grid.data.get(row)?.get(col)?;
+        (!self.grid.is_nodata(val)).then_some(val)
+    }
+}
+
+/// Parses the bytes of an .asc file, e.g. from `new Uint8Array(await file.arrayBuffer())`
+#[wasm_bindgen(js_name = parseAsc)]
+pub fn parse_asc(bytes: &[u8]) -> Result<WasmGrid, JsError> {
+    let grid = parse_asc_bytes(bytes).map_err(|e| JsError::new(&e.to_string()))?;
+    Ok(WasmGrid { grid })
+}
+
+fn gradient(colormap: Option<String>) -> Result<colorgrad::Gradient, JsError> {
+    let name = colormap.unwrap_or_else(|| "viridis".into());
+    render::colormap(&name).ok_or_else(|| JsError::new(&format!("Unknown colormap {:?}", name)))
+}
+
+fn to_image_data(img: DynamicImage) -> Result<ImageData, JsValue> {
+    let rgba = img.into_rgba8();
+    let (w, h) = rgba.dimensions();
+    ImageData::new_with_u8_clamped_array_and_sh(Clamped(rgba.as_raw()), w, h)
+}
+
+fn rgb_image_data(img: RgbImage) -> Result<ImageData, JsValue> {
+    to_image_data(DynamicImage::ImageRgb8(img))
+}
+
+#[wasm_bindgen(js_name = renderGrayscale)]
+pub fn render_grayscale(grid: &WasmGrid) -> Result<ImageData, JsValue> {
+    to_image_data(DynamicImage::ImageLuma8(render::render_grayscale(&grid.grid)))
+}
+
+#[wasm_bindgen(js_name = renderColored)]
+pub fn render_colored(grid: &WasmGrid, colormap: Option<String>) -> Result<ImageData, JsValue> {
+    rgb_image_data(render::render_colored(&grid.grid, &gradient(colormap)?))
+}
+
+#[wasm_bindgen(js_name = renderHillshade)]
+pub fn render_hillshade(grid: &WasmGrid, azimuth: f64, altitude: f64, z_factor: f64, colormap: Option<String>) -> Result<ImageData, JsValue> {
+    let params = ShadeParams { azimuth, altitude, z_factor, ..ShadeParams::default() };
+    rgb_image_data(render::render_shaded(&grid.grid, grid.grid.min_max(), &gradient(colormap)?, &params))
+}
+
+#[wasm_bindgen(js_name = renderAnaglyph)]
+pub fn render_anaglyph(grid: &WasmGrid, parallax: Option<f32>) -> Result<ImageData, JsValue> {
+    let parallax = parallax.unwrap_or_else(|| anaglyph::default_parallax(grid.grid.ncols));
+    rgb_image_data(anaglyph::render_anaglyph(&grid.grid, grid.grid.min_max(), &colorgrad::viridis(), &ShadeParams::default(), parallax))
+}
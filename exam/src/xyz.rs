@@ -0,0 +1,232 @@
+//! The grid as a table of x, y, z rows, one per valid cell center in map coordinates, for pandas, DuckDB or PostGIS.
+//! CSV text, or Parquet with gzip-compressed columns written directly: x and y as DOUBLE, z as FLOAT.
+
+use std::io::{self, Write};
+
+use flate2::write::GzEncoder;
+
+use crate::grid::Grid;
+
+/// Rows of a Parquet row group, each column of a group is one page
+const ROW_GROUP_ROWS: usize = 1 << 20;
+
+/// Table format written by `exam convert --to`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum XyzFormat {
+    Csv,
+    Parquet,
+}
+
+impl XyzFormat {
+    pub fn parse(name: &str) -> Option<XyzFormat> {
+        match name.to_lowercase().as_str() {
+            "csv" => Some(XyzFormat::Csv),
+            "parquet" => Some(XyzFormat::Parquet),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            XyzFormat::Csv => "csv",
+            XyzFormat::Parquet => "parquet",
+        }
+    }
+}
+
+/// Map coordinates and value of every valid cell center, row by row from the north
+fn points(grid: &Grid) -> impl Iterator<Item = (f64, f64, f32)> + '_ {
+    let (x0, y0) = grid.origin();
+    grid.data.iter().enumerate().flat_map(move |(r, row)| {
+        row.iter().enumerate()
+            .filter(|(_, &z)| !grid.is_nodata(z))
+            .map(move |(c, &z)| (x0 + (c as f64 + 0.5) * grid.cellsize, y0 - (r as f64 + 0.5) * grid.cellsize, z))
+    })
+}
+
+/// Writes the table in `format`, returns the number of rows
+pub fn write_xyz<W: Write>(grid: &Grid, format: XyzFormat, out: &mut W) -> io::Result<usize> {
+    match format {
+        XyzFormat::Csv => write_csv(grid, out),
+        XyzFormat::Parquet => write_parquet(grid, out),
+    }
+}
+
+/// `x,y,z` header and one line per valid cell
+pub fn write_csv<W: Write>(grid: &Grid, out: &mut W) -> io::Result<usize> {
+    writeln!(out, "x,y,z")?;
+    let mut rows = 0;
+    for (x, y, z) in points(grid) {
+        writeln!(out, "{},{},{}", x, y, z)?;
+        rows += 1;
+    }
+    Ok(rows)
+}
+
+/// Parquet file of the required columns x, y (DOUBLE) and z (FLOAT), PLAIN encoded and gzip-compressed
+pub fn write_parquet<W: Write>(grid: &Grid, out: &mut W) -> io::Result<usize> {
+    let columns: [(&str, i32); 3] = [("x", 5), ("y", 5), ("z", 4)]; // Physical types DOUBLE and FLOAT
+    let mut offset = 4u64;
+    out.write_all(b"PAR1")?;
+
+    let points: Vec<(f64, f64, f32)> = points(grid).collect();
+    let mut row_groups = Vec::new();
+    for group in points.chunks(ROW_GROUP_ROWS) {
+        let mut chunks = Vec::new();
+        let mut group_size = 0;
+        for (i, &(name, kind)) in columns.iter().enumerate() {
+            let plain: Vec<u8> = match i {
+                0 => group.iter().flat_map(|p| p.0.to_le_bytes()).collect(),
+                1 => group.iter().flat_map(|p| p.1.to_le_bytes()).collect(),
+                _ => group.iter().flat_map(|p| p.2.to_le_bytes()).collect(),
+            };
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&plain)?;
+            let compressed = encoder.finish()?;
+
+            // PageHeader: data page with its sizes and DataPageHeader (values, PLAIN, RLE levels)
+            let mut header = Thrift::default();
+            header.i32(1, 0);
+            header.i32(2, plain.len() as i32);
+            header.i32(3, compressed.len() as i32);
+            header.begin(5);
+            header.i32(1, group.len() as i32);
+            header.i32(2, 0);
+            header.i32(3, 3);
+            header.i32(4, 3);
+            header.end();
+            header.end();
+
+            let page_offset = offset;
+            out.write_all(&header.0)?;
+            out.write_all(&compressed)?;
+            let (compressed_size, uncompressed_size) = ((header.0.len() + compressed.len()) as i64, (header.0.len() + plain.len()) as i64);
+            offset += compressed_size as u64;
+            group_size += uncompressed_size;
+            chunks.push((name, kind, page_offset, compressed_size, uncompressed_size));
+        }
+        row_groups.push((chunks, group_size, group.len()));
+    }
+
+    // FileMetaData: version, schema, row count, row groups and writer
+    let mut meta = Thrift::default();
+    meta.i32(1, 1);
+    meta.list(2, 12, columns.len() + 1);
+    meta.binary(4, b"schema"); // Root of the schema: name and number of columns
+    meta.i32(5, columns.len() as i32);
+    meta.end();
+    for (name, kind) in columns {
+        meta.i32(1, kind);
+        meta.i32(3, 0); // REQUIRED
+        meta.binary(4, name.as_bytes());
+        meta.end();
+    }
+    meta.i64(3, points.len() as i64);
+    meta.list(4, 12, row_groups.len());
+    for (chunks, group_size, rows) in &row_groups {
+        meta.list(1, 12, chunks.len());
+        for &(name, kind, page_offset, compressed_size, uncompressed_size) in chunks {
+            meta.i64(2, page_offset as i64);
+            // ColumnMetaData: type, encodings, path, GZIP, values, sizes and the offset of the page
+            meta.begin(3);
+            meta.i32(1, kind);
+            meta.list(2, 5, 1);
+            meta.0.push(0); // PLAIN, as a zigzag varint
+            meta.list(3, 8, 1);
+            meta.0.push(name.len() as u8);
+            meta.0.extend(name.as_bytes());
+            meta.i32(4, 2);
+            meta.i64(5, *rows as i64);
+            meta.i64(6, uncompressed_size);
+            meta.i64(7, compressed_size);
+            meta.i64(9, page_offset as i64);
+            meta.end();
+            meta.end();
+        }
+        meta.i64(2, *group_size);
+        meta.i64(3, *rows as i64);
+        meta.end();
+    }
+    meta.binary(6, concat!("exam version ", env!("CARGO_PKG_VERSION")).as_bytes());
+    meta.end();
+
+    out.write_all(&meta.0)?;
+    out.write_all(&(meta.0.len() as u32).to_le_bytes())?;
+    out.write_all(b"PAR1")?;
+    Ok(points.len())
+}
+
+/// Thrift compact protocol, just what the Parquet footer and page headers need. Field ids are written as deltas to
+/// the previous field of the same struct, so every struct keeps its last id on a stack.
+#[derive(Default)]
+struct Thrift(Vec<u8>, Vec<i16>);
+
+impl Thrift {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn field(&mut self, id: i16, kind: u8) {
+        if self.1.is_empty() {
+            self.1.push(0);
+        }
+        let last = self.1.last_mut().unwrap();
+        let delta = id - *last;
+        *last = id;
+        if (1..=15).contains(&delta) {
+            self.0.push((delta as u8) << 4 | kind);
+        } else {
+            self.0.push(kind);
+            self.varint(((id << 1) ^ (id >> 15)) as u16 as u64);
+        }
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, 5);
+        self.varint(((value << 1) ^ (value >> 31)) as u32 as u64);
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, 6);
+        self.varint(((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    fn binary(&mut self, id: i16, value: &[u8]) {
+        self.field(id, 8);
+        self.varint(value.len() as u64);
+        self.0.extend(value);
+    }
+
+    /// Opens a nested struct field, closed by [`Thrift::end`]
+    fn begin(&mut self, id: i16) {
+        self.field(id, 12);
+        self.1.push(0);
+    }
+
+    /// List header of `len` elements of `kind`; struct elements are each closed by [`Thrift::end`]
+    fn list(&mut self, id: i16, kind: u8, len: usize) {
+        self.field(id, 9);
+        if len < 15 {
+            self.0.push((len as u8) << 4 | kind);
+        } else {
+            self.0.push(0xf0 | kind);
+            self.varint(len as u64);
+        }
+        if kind == 12 {
+            // Each element starts its own field ids; pushed in reverse so they pop in order
+            for _ in 0..len {
+                self.1.push(0);
+            }
+        }
+    }
+
+    /// Stop byte of the innermost open struct
+    fn end(&mut self) {
+        self.0.push(0);
+        self.1.pop();
+    }
+}